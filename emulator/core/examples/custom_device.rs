@@ -0,0 +1,202 @@
+//! An example of adding a custom device to a machine, which guest code running on an m68k can control
+//!
+//! The device is a simplified DMX lighting controller.  The guest program selects a channel by writing its
+//! number to the `CHANNEL` register, sets the level of that channel by writing to the `VALUE` register, and
+//! then writes 1 to the `SEND` register to transmit all the levels as one frame.  The device is `Addressable`
+//! so that the cpu can access its registers through the bus, and `Steppable` so that it can take the time
+//! that a real controller would to transmit the frame.
+//!
+//! A watchpoint is also set on the `SEND` register, which stops the simulation when the guest writes to it,
+//! and reports the address of the instruction that made the write.
+//!
+//! Run it with `cargo run -p moa-core --example custom_device`, and it's also run as a test by `cargo test`
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use femtos::{Instant, Duration, Frequency};
+
+use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable, MemoryBlock, Device};
+use moa_m68k::{M68k, M68kType};
+
+const DEV_NAME: &str = "dmx";
+
+/// The address of the device on the m68k's bus
+const DMX_BASE: Address = 0x0010_0000;
+
+#[rustfmt::skip]
+mod reg {
+    use super::Address;
+    pub(super) const CHANNEL: Address   = 0x00;
+    pub(super) const VALUE: Address     = 0x01;
+    pub(super) const SEND: Address      = 0x02;
+    pub(super) const STATUS: Address    = 0x03;
+}
+
+const CHANNELS: usize = 256;
+
+/// The time to send one frame, which is about 44us per channel at 250 kbaud
+const FRAME_TIME_US: u64 = 44 * CHANNELS as u64;
+
+/// The time between steps when there is nothing to send
+const IDLE_TIME_US: u64 = 1_000;
+
+
+struct DmxController {
+    channel: u8,
+    levels: [u8; CHANNELS],
+    send_requested: bool,
+    sending: bool,
+    /// Shared with the code that built the machine, so it can check what was sent after the device has
+    /// been given to the `System`
+    frames: Rc<RefCell<Vec<Vec<u8>>>>,
+}
+
+impl DmxController {
+    fn new(frames: Rc<RefCell<Vec<Vec<u8>>>>) -> Self {
+        Self {
+            channel: 0,
+            levels: [0; CHANNELS],
+            send_requested: false,
+            sending: false,
+            frames,
+        }
+    }
+}
+
+impl Addressable for DmxController {
+    fn size(&self) -> usize {
+        0x04
+    }
+
+    fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        match addr {
+            reg::CHANNEL => data[0] = self.channel,
+            reg::VALUE => data[0] = self.levels[self.channel as usize],
+            reg::STATUS => data[0] = (self.sending || self.send_requested) as u8,
+            _ => data[0] = 0,
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        log::debug!(target: DEV_NAME, "write of {:#04x} to register {}", data[0], addr);
+        match addr {
+            reg::CHANNEL => self.channel = data[0],
+            reg::VALUE => self.levels[self.channel as usize] = data[0],
+            reg::SEND => self.send_requested = data[0] != 0,
+            _ => {},
+        }
+        Ok(())
+    }
+}
+
+impl Steppable for DmxController {
+    fn step(&mut self, _system: &System) -> Result<Duration, Error> {
+        if self.sending {
+            // The frame that was started by the previous step has now been sent
+            self.sending = false;
+            self.frames.borrow_mut().push(self.levels.to_vec());
+        }
+
+        if self.send_requested {
+            self.send_requested = false;
+            self.sending = true;
+            Ok(Duration::from_micros(FRAME_TIME_US))
+        } else {
+            Ok(Duration::from_micros(IDLE_TIME_US))
+        }
+    }
+}
+
+impl Transmutable for DmxController {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+}
+
+
+/// The reset vectors followed by the guest program, which sets channels 0 to 7 to their own channel number,
+/// sends one frame, and then loops forever
+#[rustfmt::skip]
+const PROGRAM: &[(Address, &[u16])] = &[
+    (0x0000, &[0x0000, 0x8000, 0x0000, 0x0400]),    // initial stack pointer and pc
+    (0x0400, &[
+        0x41F9, 0x0010, 0x0000,                     // lea $100000, %a0
+        0x7000,                                     // moveq #0, %d0
+        0x1080,                                     // loop: move.b %d0, (%a0)
+        0x1140, 0x0001,                             // move.b %d0, (1, %a0)
+        0x5200,                                     // addq.b #1, %d0
+        0x0C00, 0x0008,                             // cmpi.b #8, %d0
+        0x66F2,                                     // bne.s loop
+        0x13FC, 0x0001, 0x0010, 0x0002,             // move.b #1, $100002
+        0x60FE,                                     // bra.s .
+    ]),
+];
+
+/// The address of the instruction that writes to the `SEND` register
+const SEND_INSTRUCTION: Address = 0x0416;
+
+fn build_system(frames: Rc<RefCell<Vec<Vec<u8>>>>) -> Result<System, Error> {
+    let mut system = System::default();
+
+    let mut contents = vec![0; 0x1_0000];
+    for (addr, words) in PROGRAM {
+        for (i, word) in words.iter().enumerate() {
+            let offset = *addr as usize + i * 2;
+            contents[offset..offset + 2].copy_from_slice(&word.to_be_bytes());
+        }
+    }
+    system.add_addressable_device(0x0000_0000, Device::new(MemoryBlock::new(contents)))?;
+
+    // `add_peripheral` both maps the device onto the bus and schedules it to be stepped
+    system.add_peripheral(DEV_NAME, DMX_BASE, Device::new(DmxController::new(frames)))?;
+
+    let cpu = M68k::from_type(M68kType::MC68000, Frequency::from_mhz(8));
+    system.add_interruptable_device("cpu", Device::new(cpu))?;
+
+    Ok(system)
+}
+
+fn run() -> Result<(), Error> {
+    let frames = Rc::new(RefCell::new(vec![]));
+    let mut system = build_system(frames.clone())?;
+
+    system.get_bus().add_watcher(DMX_BASE + reg::SEND);
+
+    // Long enough for the program to run and for the device to finish sending the frame
+    let end = system.clock + Duration::from_micros(FRAME_TIME_US + 10 * IDLE_TIME_US);
+    let mut send_pc = None;
+    while system.clock < end {
+        match system.run_until_clock(end) {
+            Ok(()) => {},
+            Err(Error::Breakpoint(info)) => {
+                println!("{}: send register written from PC {:#x?}", DEV_NAME, info.pc);
+                send_pc = info.pc;
+            },
+            Err(err) => return Err(err),
+        }
+    }
+
+    assert_eq!(send_pc, Some(SEND_INSTRUCTION));
+
+    let frames = frames.borrow();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(&frames[0][..9], &[0, 1, 2, 3, 4, 5, 6, 7, 0]);
+    println!("{}: sent {} frame with levels {:?}", DEV_NAME, frames.len(), &frames[0][..8]);
+
+    Ok(())
+}
+
+fn main() -> Result<(), Error> {
+    run()
+}
+
+#[test]
+fn sends_the_levels_written_by_the_guest() {
+    run().unwrap();
+}
@@ -0,0 +1,225 @@
+use std::fs;
+use std::rc::Rc;
+use std::panic;
+use std::sync::Once;
+use std::cell::RefCell;
+use std::io::Write;
+use femtos::Instant;
+
+use crate::{Bus, Error, Address, Addressable, Device};
+
+
+/// The magic number at the start of a memory snapshot file, which is followed by a version byte
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"MOASNAPS";
+pub const SNAPSHOT_VERSION: u8 = 1;
+
+/// Write the contents of a file so that it's either completely replaced or left unchanged, even if the program
+/// crashes part way through, by writing to a temporary file next to it and then renaming it over the original
+pub fn write_atomic(filename: &str, contents: &[u8]) -> Result<(), Error> {
+    let temp = format!("{}.tmp", filename);
+    let result = fs::File::create(&temp)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp, filename));
+    result.map_err(|err| {
+        let _ = fs::remove_file(&temp);
+        Error::new(format!("error writing to {}: {}", filename, err))
+    })
+}
+
+#[derive(Default)]
+struct AutoSaveState {
+    /// The battery-backed memory devices, such as cartridge SRAM, and the file that each one is saved to
+    memories: Vec<(String, Device)>,
+    /// The file to save the contents of all the memory on the bus to, for looking into a crash afterwards
+    snapshot: Option<(String, Rc<RefCell<Bus>>)>,
+}
+
+/// The memory to save when the program exits, so that game saves aren't lost, including when it crashes
+///
+/// The memory is saved when `save` is called by the frontend before it exits normally, and when the program
+/// panics if `save_on_panic` was called.  Devices that are in use when a panic occurs can't be read, so they
+/// are skipped.
+#[derive(Clone, Default)]
+pub struct AutoSave(Rc<RefCell<AutoSaveState>>);
+
+thread_local! {
+    static PANIC_AUTOSAVE: RefCell<Option<AutoSave>> = const { RefCell::new(None) };
+}
+
+static PANIC_HOOK: Once = Once::new();
+
+impl AutoSave {
+    /// Save the contents of the given memory device to a file, after loading its previous contents if the file exists
+    pub fn add_memory(&self, filename: &str, device: Device) -> Result<(), Error> {
+        if let Ok(contents) = fs::read(filename) {
+            write_contents(filename, &device, &contents)?;
+        }
+        self.0.borrow_mut().memories.push((filename.to_string(), device));
+        Ok(())
+    }
+
+    /// Returns the names of the battery-backed memory devices, which are the files they're saved to
+    pub fn memory_names(&self) -> Vec<String> {
+        self.0.borrow().memories.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    /// Returns the contents of the battery-backed memory with the given name, for frontends that can't save it
+    /// to a file, such as in a web browser
+    pub fn read_memory(&self, name: &str) -> Result<Vec<u8>, Error> {
+        let state = self.0.borrow();
+        let (_, device) = state
+            .memories
+            .iter()
+            .find(|(filename, _)| filename == name)
+            .ok_or_else(|| Error::new(format!("autosave: no memory named {}", name)))?;
+        let mut device = device.borrow_mut();
+        let memory = device
+            .as_addressable()
+            .ok_or_else(|| Error::new(format!("autosave: device for {} is not addressable", name)))?;
+        read_all(memory)
+    }
+
+    /// Replace the contents of the battery-backed memory with the given name, such as with a copy that was
+    /// previously returned by `read_memory`
+    pub fn restore_memory(&self, name: &str, contents: &[u8]) -> Result<(), Error> {
+        let state = self.0.borrow();
+        let (_, device) = state
+            .memories
+            .iter()
+            .find(|(filename, _)| filename == name)
+            .ok_or_else(|| Error::new(format!("autosave: no memory named {}", name)))?;
+        write_contents(name, device, contents)
+    }
+
+    /// Save a snapshot of all the memory on the given bus to a file, or stop saving it if `None`
+    pub fn set_snapshot(&self, filename: Option<&str>, bus: Rc<RefCell<Bus>>) {
+        self.0.borrow_mut().snapshot = filename.map(|filename| (filename.to_string(), bus));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let state = self.0.borrow();
+        state.memories.is_empty() && state.snapshot.is_none()
+    }
+
+    /// Also save the memory if the current thread panics
+    pub fn save_on_panic(&self) {
+        PANIC_AUTOSAVE.with(|autosave| *autosave.borrow_mut() = Some(self.clone()));
+        PANIC_HOOK.call_once(|| {
+            let previous = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                previous(info);
+                let _ = PANIC_AUTOSAVE.try_with(|autosave| {
+                    if let Some(autosave) = autosave.try_borrow().ok().as_ref().and_then(|autosave| autosave.as_ref()) {
+                        if let Err(err) = autosave.save() {
+                            eprintln!("{}", err);
+                        }
+                    }
+                });
+            }));
+        });
+    }
+
+    /// Save every memory device and the snapshot, continuing after an error, and returning the first error if any
+    pub fn save(&self) -> Result<(), Error> {
+        let state = self.0.try_borrow().map_err(|_| Error::new("autosave: already saving"))?;
+        let mut result = Ok(());
+        for (filename, device) in state.memories.iter() {
+            let contents = device
+                .try_borrow_mut()
+                .ok()
+                .and_then(|mut device| device.as_addressable().map(|memory| read_all(memory)))
+                .transpose();
+            let saved = match contents {
+                Ok(Some(contents)) => write_atomic(filename, &contents),
+                Ok(None) => Err(Error::new(format!("autosave: unable to read the memory for {}", filename))),
+                Err(err) => Err(err),
+            };
+            result = result.and(saved);
+        }
+
+        if let Some((filename, bus)) = state.snapshot.as_ref() {
+            result = result.and(save_snapshot(filename, bus));
+        }
+        result
+    }
+}
+
+fn write_contents(name: &str, device: &Device, contents: &[u8]) -> Result<(), Error> {
+    let mut device = device.borrow_mut();
+    let memory = device
+        .as_addressable()
+        .ok_or_else(|| Error::new(format!("autosave: device for {} is not addressable", name)))?;
+    let len = contents.len().min(memory.size());
+    memory.write(Instant::START, 0, &contents[..len])
+}
+
+fn read_all(memory: &mut dyn Addressable) -> Result<Vec<u8>, Error> {
+    let mut contents = vec![0; memory.size()];
+    memory.read(Instant::START, 0, &mut contents)?;
+    Ok(contents)
+}
+
+/// Write a snapshot of the memory on the bus to a file
+fn save_snapshot(filename: &str, bus: &Rc<RefCell<Bus>>) -> Result<(), Error> {
+    let bus = bus
+        .try_borrow()
+        .map_err(|_| Error::new(format!("autosave: bus is in use, unable to save {}", filename)))?;
+    write_atomic(filename, &snapshot_contents(&bus)?)
+}
+
+/// Returns the contents of each memory block on the bus, skipping peripherals, since reading their registers can
+/// change their state
+///
+/// The snapshot starts with the magic number and version, and then for each block there is the u64 base address,
+/// the u64 size, and the contents, all little endian
+pub fn snapshot_contents(bus: &Bus) -> Result<Vec<u8>, Error> {
+    let mut snapshot = SNAPSHOT_MAGIC.to_vec();
+    snapshot.push(SNAPSHOT_VERSION);
+    for block in bus.blocks() {
+        let Ok(mut device) = block.dev.try_borrow_mut() else {
+            continue;
+        };
+        let Some(memory) = device.as_addressable().filter(|memory| memory.is_memory()) else {
+            continue;
+        };
+        let contents = read_all(memory)?;
+        snapshot.extend_from_slice(&(block.base as u64).to_le_bytes());
+        snapshot.extend_from_slice(&(contents.len() as u64).to_le_bytes());
+        snapshot.extend_from_slice(&contents);
+    }
+    Ok(snapshot)
+}
+
+/// Write the contents of each block in a snapshot back to the bus, skipping any that haven't changed, such as ROMs
+pub fn restore_snapshot(filename: &str, bus: &mut Bus, clock: Instant) -> Result<(), Error> {
+    let snapshot = fs::read(filename).map_err(|err| Error::new(format!("error reading {}: {}", filename, err)))?;
+    restore_snapshot_contents(filename, &snapshot, bus, clock)
+}
+
+/// Write the contents of each block in a snapshot back to the bus, where `name` is used in error messages
+pub fn restore_snapshot_contents(name: &str, snapshot: &[u8], bus: &mut Bus, clock: Instant) -> Result<(), Error> {
+    let invalid = || Error::new(format!("{} is not a valid memory snapshot", name));
+    let mut rest = snapshot
+        .strip_prefix(&SNAPSHOT_MAGIC[..])
+        .and_then(|rest| rest.strip_prefix(&[SNAPSHOT_VERSION]))
+        .ok_or_else(invalid)?;
+    while !rest.is_empty() {
+        if rest.len() < 16 {
+            return Err(invalid());
+        }
+        let base = u64::from_le_bytes(rest[0..8].try_into().unwrap()) as Address;
+        let size = u64::from_le_bytes(rest[8..16].try_into().unwrap()) as usize;
+        let contents = rest.get(16..16 + size).ok_or_else(invalid)?;
+        rest = &rest[16 + size..];
+
+        let mut current = vec![0; size];
+        bus.read(clock, base, &mut current)?;
+        if current != contents {
+            bus.write(clock, base, contents)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use std::fmt;
+use std::ops::{BitOr, BitOrAssign};
+
+use crate::devices::Transmutable;
+
+
+/// The set of things a device can do, which can be checked without borrowing the device
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Capabilities(u32);
+
+#[rustfmt::skip]
+impl Capabilities {
+    pub const NONE: Self            = Self(0);
+    pub const STEPPABLE: Self       = Self(1 << 0);
+    pub const ADDRESSABLE: Self     = Self(1 << 1);
+    pub const INTERRUPTABLE: Self   = Self(1 << 2);
+    pub const DEBUGGABLE: Self      = Self(1 << 3);
+    pub const INSPECTABLE: Self     = Self(1 << 4);
+    pub const SIGNALABLE: Self      = Self(1 << 5);
+    /// The device's state can be saved and restored, such as for rewinding or running ahead
+    pub const SNAPSHOTABLE: Self    = Self(1 << 6);
+    /// The device sends audio samples to the host
+    pub const AUDIO: Self           = Self(1 << 7);
+    /// The device sends video frames to the host
+    pub const VIDEO: Self           = Self(1 << 8);
+    /// The device is stepped on its own thread, in parallel with the rest of the system
+    pub const THREADED: Self        = Self(1 << 9);
+}
+
+const NAMES: &[(Capabilities, &str)] = &[
+    (Capabilities::STEPPABLE, "steppable"),
+    (Capabilities::ADDRESSABLE, "addressable"),
+    (Capabilities::INTERRUPTABLE, "interruptable"),
+    (Capabilities::DEBUGGABLE, "debuggable"),
+    (Capabilities::INSPECTABLE, "inspectable"),
+    (Capabilities::SIGNALABLE, "signalable"),
+    (Capabilities::SNAPSHOTABLE, "snapshotable"),
+    (Capabilities::AUDIO, "audio"),
+    (Capabilities::VIDEO, "video"),
+    (Capabilities::THREADED, "threaded"),
+];
+
+impl Capabilities {
+    /// Returns the capabilities of the traits that the device returns from its `as_*` methods
+    pub fn probe<T: Transmutable + ?Sized>(device: &mut T) -> Self {
+        let mut capabilities = Self::NONE;
+        if device.as_steppable().is_some() {
+            capabilities |= Self::STEPPABLE;
+        }
+        if device.as_addressable().is_some() {
+            capabilities |= Self::ADDRESSABLE;
+        }
+        if device.as_interruptable().is_some() {
+            capabilities |= Self::INTERRUPTABLE;
+        }
+        if device.as_debuggable().is_some() {
+            capabilities |= Self::DEBUGGABLE;
+        }
+        if device.as_inspectable().is_some() {
+            capabilities |= Self::INSPECTABLE;
+        }
+        if device.as_signalable().is_some() {
+            capabilities |= Self::SIGNALABLE;
+        }
+        if device.as_snapshotable().is_some() {
+            capabilities |= Self::SNAPSHOTABLE;
+        }
+        capabilities
+    }
+
+    /// Returns true if all of the given capabilities are in this set
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the names of the capabilities in this set
+    pub fn names(&self) -> Vec<&'static str> {
+        NAMES
+            .iter()
+            .filter(|(capability, _)| self.contains(*capability))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Capabilities {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.names().join(", "))
+    }
+}
+
+impl fmt::Debug for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Capabilities({})", self.names().join(" | "))
+    }
+}
@@ -0,0 +1,144 @@
+use std::fs;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::collections::BTreeMap;
+
+use crate::{Error, Address};
+
+
+/// The number of times that one address was accessed
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessCounts {
+    pub reads: u64,
+    pub writes: u64,
+    pub executes: u64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    Execute,
+}
+
+impl AccessCounts {
+    pub fn get(&self, kind: AccessKind) -> u64 {
+        match kind {
+            AccessKind::Read => self.reads,
+            AccessKind::Write => self.writes,
+            AccessKind::Execute => self.executes,
+        }
+    }
+}
+
+/// A contiguous range of addresses that were all accessed in the same way, and the total number of accesses
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AccessRange {
+    pub start: Address,
+    pub end: Address,
+    pub count: u64,
+}
+
+/// A record of the number of reads, writes, and instruction executions at each address of a `Bus`
+///
+/// Executions are counted at the address of each instruction that a cpu reports to the bus, and the fetching
+/// of the instructions is also counted as reads.  The heatmap can be saved to a CSV file with one address
+/// per line, giving the address in hex followed by the read, write, and execute counts.
+#[derive(Clone, Default)]
+pub struct Coverage(Rc<RefCell<BTreeMap<Address, AccessCounts>>>);
+
+impl Coverage {
+    pub fn record_read(&self, addr: Address, len: usize) {
+        let mut counts = self.0.borrow_mut();
+        for i in 0..len as Address {
+            counts.entry(addr + i).or_default().reads += 1;
+        }
+    }
+
+    pub fn record_write(&self, addr: Address, len: usize) {
+        let mut counts = self.0.borrow_mut();
+        for i in 0..len as Address {
+            counts.entry(addr + i).or_default().writes += 1;
+        }
+    }
+
+    pub fn record_execute(&self, addr: Address) {
+        self.0.borrow_mut().entry(addr).or_default().executes += 1;
+    }
+
+    pub fn get(&self, addr: Address) -> AccessCounts {
+        self.0.borrow().get(&addr).cloned().unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+
+    /// Returns the ranges of addresses between `start` and `end` that were accessed in the given way.  Instructions
+    /// longer than one byte leave gaps between the executed addresses, so ranges separated by less than `max_gap`
+    /// addresses are joined together
+    pub fn ranges(&self, kind: AccessKind, start: Address, end: Address, max_gap: Address) -> Vec<AccessRange> {
+        let mut ranges: Vec<AccessRange> = vec![];
+        for (addr, counts) in self.0.borrow().range(start..end) {
+            let count = counts.get(kind);
+            if count == 0 {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some(range) if *addr <= range.end + max_gap => {
+                    range.end = *addr + 1;
+                    range.count += count;
+                },
+                _ => ranges.push(AccessRange {
+                    start: *addr,
+                    end: *addr + 1,
+                    count,
+                }),
+            }
+        }
+        ranges
+    }
+
+    /// Returns a description of which parts of each of the given named regions were executed, read, and written
+    pub fn report(&self, regions: &[(String, Address, usize)]) -> String {
+        let mut report = String::new();
+        for (name, base, size) in regions {
+            let end = base + *size as Address;
+            writeln!(report, "{} ({:08x} - {:08x}):", name, base, end - 1).unwrap();
+
+            let mut accessed = false;
+            for (kind, label, max_gap) in
+                [(AccessKind::Execute, "executed", 16), (AccessKind::Read, "read", 1), (AccessKind::Write, "written", 1)]
+            {
+                let ranges = self.ranges(kind, *base, end, max_gap);
+                let total = ranges.iter().map(|range| range.end - range.start).sum::<Address>();
+                if ranges.is_empty() {
+                    continue;
+                }
+                accessed = true;
+                writeln!(report, "  {} {} bytes in {} ranges:", label, total, ranges.len()).unwrap();
+                for range in ranges {
+                    writeln!(report, "    {:08x} - {:08x}  {:>10} times", range.start, range.end - 1, range.count).unwrap();
+                }
+            }
+
+            if !accessed {
+                writeln!(report, "  not accessed").unwrap();
+            }
+        }
+        report
+    }
+
+    pub fn save_heatmap(&self, filename: &str) -> Result<(), Error> {
+        let mut contents = String::from("address,reads,writes,executes\n");
+        for (addr, counts) in self.0.borrow().iter() {
+            writeln!(contents, "{:x},{},{},{}", addr, counts.reads, counts.writes, counts.executes).unwrap();
+        }
+        fs::write(filename, contents).map_err(|err| Error::new(format!("error writing to {}: {}", filename, err)))
+    }
+}
@@ -1,9 +1,10 @@
 use std::rc::Rc;
+use std::any::Any;
 use std::cell::{RefCell, RefMut, BorrowMutError};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use femtos::{Duration, Instant};
 
-use crate::{Error, System};
+use crate::{Error, System, Waker, TimerId, Capabilities};
 
 
 /// A universal memory address used by the Addressable trait
@@ -17,6 +18,24 @@ pub type Address = u64;
 pub trait Steppable {
     fn step(&mut self, system: &System) -> Result<Duration, Error>;
     fn on_error(&mut self, _system: &System) {}
+
+    /// Called when the device is added to a `System`, with a handle the device can use to have itself stepped
+    /// immediately, such as when input arrives, rather than stepping often to check for changes
+    fn set_waker(&mut self, _waker: Waker) {}
+
+    /// Called when a timer that the device scheduled with `Waker::schedule()` expires
+    fn on_timer(&mut self, _system: &System, _timer: TimerId) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// A device that can be stepped with only the time, without access to the rest of the system, such as a sound
+/// chip, so that it can be stepped on its own thread by wrapping it in a `ThreadedDevice`
+///
+/// The device must always return the same interval, because a `ThreadedDevice` schedules each step using the
+/// interval returned by the step before it, and returns an error if the interval changes
+pub trait ParallelSteppable: Send {
+    fn step_at(&mut self, clock: Instant) -> Result<Duration, Error>;
 }
 
 /// A device that can receive an interrupt.  The `interrupt_state_change()` method
@@ -31,6 +50,12 @@ pub trait Addressable {
     fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error>;
     fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error>;
 
+    /// Returns true if reading has no side effects and writing only changes what is read back, such as
+    /// with RAM, so that a write can be undone by writing back the data read before it
+    fn is_memory(&self) -> bool {
+        false
+    }
+
     #[inline]
     fn read_u8(&mut self, clock: Instant, addr: Address) -> Result<u8, Error> {
         let mut data = [0; 1];
@@ -66,6 +91,86 @@ pub trait Addressable {
         Ok(read_leu32(&data))
     }
 
+    #[inline]
+    fn read_beu64(&mut self, clock: Instant, addr: Address) -> Result<u64, Error> {
+        let mut data = [0; 8];
+        self.read(clock, addr, &mut data)?;
+        Ok(read_beu64(&data))
+    }
+
+    #[inline]
+    fn read_leu64(&mut self, clock: Instant, addr: Address) -> Result<u64, Error> {
+        let mut data = [0; 8];
+        self.read(clock, addr, &mut data)?;
+        Ok(read_leu64(&data))
+    }
+
+    #[inline]
+    fn read_i8(&mut self, clock: Instant, addr: Address) -> Result<i8, Error> {
+        Ok(self.read_u8(clock, addr)? as i8)
+    }
+
+    #[inline]
+    fn read_bei16(&mut self, clock: Instant, addr: Address) -> Result<i16, Error> {
+        Ok(self.read_beu16(clock, addr)? as i16)
+    }
+
+    #[inline]
+    fn read_lei16(&mut self, clock: Instant, addr: Address) -> Result<i16, Error> {
+        Ok(self.read_leu16(clock, addr)? as i16)
+    }
+
+    #[inline]
+    fn read_bei32(&mut self, clock: Instant, addr: Address) -> Result<i32, Error> {
+        Ok(self.read_beu32(clock, addr)? as i32)
+    }
+
+    #[inline]
+    fn read_lei32(&mut self, clock: Instant, addr: Address) -> Result<i32, Error> {
+        Ok(self.read_leu32(clock, addr)? as i32)
+    }
+
+    #[inline]
+    fn read_bei64(&mut self, clock: Instant, addr: Address) -> Result<i64, Error> {
+        Ok(self.read_beu64(clock, addr)? as i64)
+    }
+
+    #[inline]
+    fn read_lei64(&mut self, clock: Instant, addr: Address) -> Result<i64, Error> {
+        Ok(self.read_leu64(clock, addr)? as i64)
+    }
+
+    /// Read the data one byte at a time, for accesses that aren't aligned or that cross from one device into
+    /// another, which a device's `read()` might not support
+    fn read_bytewise(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = self.read_u8(clock, addr.wrapping_add(i as Address))?;
+        }
+        Ok(())
+    }
+
+    /// Read a big endian value of 1 to 8 bytes, one byte at a time, and sign extend it if `signed` is true
+    fn read_be_bytewise(&mut self, clock: Instant, addr: Address, size: usize, signed: bool) -> Result<u64, Error> {
+        if size == 0 || size > 8 {
+            return Err(Error::new(format!("invalid read size of {} bytes", size)));
+        }
+        let mut data = [0; 8];
+        self.read_bytewise(clock, addr, &mut data[8 - size..])?;
+        let value = read_beu64(&data);
+        Ok(if signed { sign_extend(value, size) as u64 } else { value })
+    }
+
+    /// Read a little endian value of 1 to 8 bytes, one byte at a time, and sign extend it if `signed` is true
+    fn read_le_bytewise(&mut self, clock: Instant, addr: Address, size: usize, signed: bool) -> Result<u64, Error> {
+        if size == 0 || size > 8 {
+            return Err(Error::new(format!("invalid read size of {} bytes", size)));
+        }
+        let mut data = [0; 8];
+        self.read_bytewise(clock, addr, &mut data[..size])?;
+        let value = read_leu64(&data);
+        Ok(if signed { sign_extend(value, size) as u64 } else { value })
+    }
+
     #[inline]
     fn write_u8(&mut self, clock: Instant, addr: Address, value: u8) -> Result<(), Error> {
         let data = [value];
@@ -99,6 +204,20 @@ pub trait Addressable {
         write_leu32(&mut data, value);
         self.write(clock, addr, &data)
     }
+
+    #[inline]
+    fn write_beu64(&mut self, clock: Instant, addr: Address, value: u64) -> Result<(), Error> {
+        let mut data = [0; 8];
+        write_beu64(&mut data, value);
+        self.write(clock, addr, &data)
+    }
+
+    #[inline]
+    fn write_leu64(&mut self, clock: Instant, addr: Address, value: u64) -> Result<(), Error> {
+        let mut data = [0; 8];
+        write_leu64(&mut data, value);
+        self.write(clock, addr, &data)
+    }
 }
 
 #[inline]
@@ -121,6 +240,29 @@ pub fn read_leu32(data: &[u8]) -> u32 {
     (data[3] as u32) << 24 | (data[2] as u32) << 16 | (data[1] as u32) << 8 | (data[0] as u32)
 }
 
+#[inline]
+pub fn read_beu64(data: &[u8]) -> u64 {
+    (read_beu32(data) as u64) << 32 | (read_beu32(&data[4..]) as u64)
+}
+
+#[inline]
+pub fn read_leu64(data: &[u8]) -> u64 {
+    (read_leu32(&data[4..]) as u64) << 32 | (read_leu32(data) as u64)
+}
+
+/// Sign extend the lowest `size` bytes of the value to 64 bits
+#[inline]
+pub fn sign_extend(value: u64, size: usize) -> i64 {
+    match size {
+        0 => 0,
+        1..=7 => {
+            let shift = 64 - size * 8;
+            ((value << shift) as i64) >> shift
+        },
+        _ => value as i64,
+    }
+}
+
 
 
 #[inline]
@@ -155,15 +297,54 @@ pub fn write_leu32(data: &mut [u8], value: u32) -> &mut [u8] {
     data
 }
 
+#[inline]
+pub fn write_beu64(data: &mut [u8], value: u64) -> &mut [u8] {
+    write_beu32(data, (value >> 32) as u32);
+    write_beu32(&mut data[4..], value as u32);
+    data
+}
+
+#[inline]
+pub fn write_leu64(data: &mut [u8], value: u64) -> &mut [u8] {
+    write_leu32(data, value as u32);
+    write_leu32(&mut data[4..], (value >> 32) as u32);
+    data
+}
+
 
 /// A device (cpu) that can debugged using the built-in debugger
 pub trait Debuggable {
+    /// Stop before running the instruction at the given address.  A breakpoint that's added more than once stays
+    /// until it's been removed as many times, so that a tool can add and remove its own breakpoints without
+    /// removing the ones set by the user
     fn add_breakpoint(&mut self, addr: Address);
     fn remove_breakpoint(&mut self, addr: Address);
 
     fn print_current_step(&mut self, system: &System) -> Result<(), Error>;
     fn print_disassembly(&mut self, system: &System, addr: Address, count: usize);
     fn run_command(&mut self, system: &System, args: &[&str]) -> Result<bool, Error>;
+
+    /// Returns the value of the register with the given lowercase name (eg. "d0" or "hl"), which is used to
+    /// evaluate the conditions of breakpoints, or `None` if there is no such register
+    fn get_register(&mut self, _name: &str) -> Option<u64> {
+        None
+    }
+
+    /// Returns the names of the registers that are compared before and after each step in an instruction trace
+    fn register_names(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Stop before the first instruction of the handler whenever the device takes the given exception or interrupt,
+    /// or stop catching it if `enable` is false.  The exception is a vector number, or a name specific to the device
+    fn catch_exception(&mut self, _exception: &str, _enable: bool) -> Result<(), Error> {
+        Err(Error::new("This device can't catch exceptions"))
+    }
+
+    /// Returns the disassembly and the bytes of the instruction at the given address, if it can be decoded
+    fn disassemble(&mut self, _system: &System, _addr: Address) -> Option<(String, Vec<u8>)> {
+        None
+    }
 }
 
 /// A device (peripheral) that can inspected using the built-in debugger
@@ -171,6 +352,16 @@ pub trait Inspectable {
     fn inspect(&mut self, system: &System, args: &[&str]) -> Result<(), Error>;
 }
 
+/// A device whose state can be saved and restored, such as to rewind the debugger, or to run ahead of the
+/// frontend and then go back
+pub trait Snapshotable {
+    /// Returns a copy of the device's state, which can be given to `restore_state()` to undo the steps taken
+    /// since, or `None` if there's nothing that can change, such as with read-only memory
+    fn save_state(&mut self) -> Option<Box<dyn Any>>;
+
+    fn restore_state(&mut self, state: &dyn Any);
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Signal {
     Reset,
@@ -212,6 +403,22 @@ pub trait Transmutable {
     fn as_signalable(&mut self) -> Option<&mut dyn Signalable> {
         None
     }
+
+    #[inline]
+    fn as_snapshotable(&mut self) -> Option<&mut dyn Snapshotable> {
+        None
+    }
+
+    /// Returns the set of things the device can do, which by default are the traits it returns from the
+    /// `as_*` methods.  Devices that produce audio or video should add those
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities::probe(self)
+    }
+
+    /// Check that the device is configured correctly after the machine has been built, without running it
+    fn self_check(&mut self, _system: &System) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 pub type TransmutableBox = Rc<RefCell<Box<dyn Transmutable>>>;
@@ -222,7 +429,7 @@ pub fn wrap_transmutable<T: Transmutable + 'static>(value: T) -> TransmutableBox
 
 static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct DeviceId(usize);
 
 impl DeviceId {
@@ -240,20 +447,30 @@ impl Default for DeviceId {
 }
 
 #[derive(Clone)]
-pub struct Device(DeviceId, TransmutableBox);
+pub struct Device(DeviceId, TransmutableBox, Capabilities);
 
 impl Device {
-    pub fn new<T>(value: T) -> Self
+    pub fn new<T>(mut value: T) -> Self
     where
         T: Transmutable + 'static,
     {
-        Self(DeviceId::new(), wrap_transmutable(value))
+        let capabilities = value.capabilities();
+        Self(DeviceId::new(), wrap_transmutable(value), capabilities)
     }
 
     pub fn id(&self) -> DeviceId {
         self.0
     }
 
+    /// Returns the capabilities the device had when it was created, without borrowing it
+    pub fn capabilities(&self) -> Capabilities {
+        self.2
+    }
+
+    pub fn has(&self, capabilities: Capabilities) -> bool {
+        self.2.contains(capabilities)
+    }
+
     pub fn borrow_mut(&self) -> RefMut<'_, Box<dyn Transmutable>> {
         self.1.borrow_mut()
     }
@@ -1,16 +1,51 @@
 use std::fmt;
 use moa_host::HostError;
 
+use crate::devices::{Address, DeviceId};
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum EmulatorErrorKind {
     Misc,
     MemoryAlignment,
+    /// The guest program has asked to stop the simulation with the given exit code
+    Exit(i32),
+}
+
+/// The details of a breakpoint or watchpoint that has stopped the simulation
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BreakpointInfo {
+    /// The device that reached the breakpoint.  The `System` will fill this in if the device doesn't
+    pub device: Option<DeviceId>,
+    /// The execution address of the device when the breakpoint was reached
+    pub pc: Option<Address>,
+    /// The index of the breakpoint in the device's list of breakpoints
+    pub id: Option<usize>,
+    /// The memory address that was accessed, if this was caused by a watchpoint
+    pub address: Option<Address>,
+    /// The data that was written to the address, if this was caused by a watchpoint
+    pub value: Option<u64>,
+    pub watchpoint: bool,
+    /// True if this was caused by the device taking an exception or interrupt that the debugger is catching
+    pub exception: bool,
+    pub msg: String,
+}
+
+impl BreakpointInfo {
+    pub fn new<S>(msg: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            msg: msg.into(),
+            ..Default::default()
+        }
+    }
 }
 
 #[derive(Clone, Debug, thiserror::Error)]
 pub enum Error {
     Assertion(String),
-    Breakpoint(String),
+    Breakpoint(BreakpointInfo),
     Emulator(EmulatorErrorKind, String),
     Processor(u32),
     Other(String),
@@ -31,6 +66,10 @@ impl Error {
         Error::Emulator(kind, msg.into())
     }
 
+    pub fn exit(code: i32) -> Error {
+        Error::Emulator(EmulatorErrorKind::Exit(code), format!("guest exited with code {}", code))
+    }
+
     pub fn processor(native: u32) -> Error {
         Error::Processor(native)
     }
@@ -39,7 +78,44 @@ impl Error {
     where
         S: Into<String>,
     {
-        Error::Breakpoint(msg.into())
+        Error::Breakpoint(BreakpointInfo::new(msg))
+    }
+
+    pub fn breakpoint_at(pc: Address, id: Option<usize>) -> Error {
+        Error::Breakpoint(BreakpointInfo {
+            pc: Some(pc),
+            id,
+            ..BreakpointInfo::new(format!("breakpoint reached at {:#010x}", pc))
+        })
+    }
+
+    /// An exception or interrupt that was caught as the device entered its handler, which starts at `pc`
+    pub fn exception_caught<S>(pc: Address, description: S) -> Error
+    where
+        S: Into<String>,
+    {
+        Error::Breakpoint(BreakpointInfo {
+            pc: Some(pc),
+            exception: true,
+            ..BreakpointInfo::new(format!("{} caught, entering handler at {:#010x}", description.into(), pc))
+        })
+    }
+
+    /// A watchpoint at the given memory address, which was written with `value` by the instruction at `pc`,
+    /// if it's known
+    pub fn watchpoint(address: Address, id: Option<usize>, pc: Option<Address>, value: Option<u64>) -> Error {
+        let msg = match pc {
+            Some(pc) => format!("watchpoint reached for {:#010x} from PC {:#010x}", address, pc),
+            None => format!("watchpoint reached for {:#010x}", address),
+        };
+        Error::Breakpoint(BreakpointInfo {
+            address: Some(address),
+            value,
+            pc,
+            id,
+            watchpoint: true,
+            ..BreakpointInfo::new(msg)
+        })
     }
 
     pub fn assertion<S>(msg: S) -> Error
@@ -51,7 +127,8 @@ impl Error {
 
     pub fn msg(&self) -> &str {
         match self {
-            Error::Assertion(msg) | Error::Breakpoint(msg) | Error::Other(msg) | Error::Emulator(_, msg) => msg.as_str(),
+            Error::Assertion(msg) | Error::Other(msg) | Error::Emulator(_, msg) => msg.as_str(),
+            Error::Breakpoint(info) => info.msg.as_str(),
             Error::Processor(_) => "native exception",
         }
     }
@@ -60,7 +137,8 @@ impl Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Error::Assertion(msg) | Error::Breakpoint(msg) | Error::Other(msg) | Error::Emulator(_, msg) => write!(f, "{}", msg),
+            Error::Assertion(msg) | Error::Other(msg) | Error::Emulator(_, msg) => write!(f, "{}", msg),
+            Error::Breakpoint(info) => write!(f, "{}", info.msg),
             Error::Processor(_) => write!(f, "native exception"),
         }
     }
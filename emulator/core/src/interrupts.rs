@@ -1,9 +1,48 @@
+use std::rc::Rc;
+use std::cell::Cell;
+
 use crate::error::Error;
 
+/// The vector number of the first autovector, which is used for interrupts that don't supply their own vector
+const AUTOVECTOR_BASE: u8 = 24;
+
+/// An interrupt output owned by a device, which is connected to an input of an `InterruptController` or an
+/// `InterruptChain`.  The line is level triggered, so it stays asserted until the device clears it
+#[derive(Clone, Debug, Default)]
+pub struct InterruptLine(Rc<Cell<(bool, Option<u8>)>>);
+
+impl InterruptLine {
+    /// Assert or clear the line, for a device that doesn't supply a vector when its interrupt is acknowledged
+    pub fn set(&self, asserted: bool) {
+        self.0.set((asserted, None));
+    }
+
+    /// Assert or clear the line, for a device that supplies the given vector when its interrupt is acknowledged
+    pub fn set_vectored(&self, asserted: bool, vector: u8) {
+        self.0.set((asserted, Some(vector)));
+    }
+
+    pub fn is_asserted(&self) -> bool {
+        self.0.get().0
+    }
+
+    /// Returns the vector supplied by the device, if it supplies one
+    pub fn vector(&self) -> Option<u8> {
+        self.0.get().1
+    }
+}
 
+/// The interrupt inputs of a cpu with prioritized interrupt levels, like the 68000's IPL encoder
+///
+/// Interrupts can either be set directly with a priority and vector number, in which case they stay pending until the
+/// cpu acknowledges them, or devices can be connected with an `InterruptLine` at a given priority.  Connected lines
+/// are level triggered, and when more than one line at the same priority is asserted, the one that was connected first
+/// takes precedence, like a daisy chain.  A line that doesn't supply a vector uses the autovector for its priority.
+/// An interrupt set directly takes precedence over the lines connected at the same priority
 pub struct InterruptController {
     interrupts: Vec<(bool, u8)>,
     highest: u8,
+    lines: Vec<(u8, InterruptLine)>,
 }
 
 impl Default for InterruptController {
@@ -11,10 +50,19 @@ impl Default for InterruptController {
         InterruptController {
             interrupts: vec![(false, 0); 7],
             highest: 0,
+            lines: vec![],
         }
     }
 }
 
+/// The levels of the inputs of an `InterruptController`, which are saved with the state of the system
+#[derive(Clone, Debug)]
+pub(crate) struct InterruptState {
+    interrupts: Vec<(bool, u8)>,
+    highest: u8,
+    lines: Vec<(bool, Option<u8>)>,
+}
+
 impl InterruptController {
     pub fn set(&mut self, state: bool, priority: u8, number: u8) -> Result<(), Error> {
         self.interrupts[priority as usize].0 = state;
@@ -22,23 +70,238 @@ impl InterruptController {
         if state && priority > self.highest {
             self.highest = priority;
         }
+        self.update_highest();
         Ok(())
     }
 
+    /// Connect a new interrupt line to the given priority, and return it to be given to the device that drives it
+    pub fn connect(&mut self, priority: u8) -> InterruptLine {
+        let line = InterruptLine::default();
+        self.connect_line(priority, line.clone());
+        line
+    }
+
+    /// Connect an existing interrupt line to the given priority
+    pub fn connect_line(&mut self, priority: u8, line: InterruptLine) {
+        self.lines.push((priority, line));
+    }
+
     pub fn check(&mut self) -> (bool, u8, u8) {
-        if self.highest > 0 {
+        let mut pending = if self.highest > 0 {
             (true, self.highest, self.interrupts[self.highest as usize].1)
         } else {
             (false, 0, 0)
+        };
+
+        for (priority, line) in self.lines.iter() {
+            if line.is_asserted() && *priority > pending.1 {
+                pending = (true, *priority, line.vector().unwrap_or(AUTOVECTOR_BASE + *priority));
+            }
         }
+        pending
     }
 
+    /// Returns true if an interrupt at the given priority is still waiting to be acknowledged
+    pub fn is_pending(&self, priority: u8) -> bool {
+        self.interrupts[priority as usize].0 || self.lines.iter().any(|(p, line)| *p == priority && line.is_asserted())
+    }
+
+    /// Acknowledge the interrupt at the given priority, returning its vector number.  Interrupts that were set
+    /// directly are cleared, but connected lines stay asserted until the device clears them
     pub fn acknowledge(&mut self, priority: u8) -> Result<u8, Error> {
-        let acknowledge = self.interrupts[priority as usize].1;
-        self.interrupts[priority as usize].0 = false;
+        let (direct, number) = self.interrupts[priority as usize];
+        if direct {
+            self.interrupts[priority as usize].0 = false;
+            self.update_highest();
+            return Ok(number);
+        }
+
+        let line_vector = self
+            .lines
+            .iter()
+            .find(|(p, line)| *p == priority && line.is_asserted())
+            .map(|(_, line)| line.vector().unwrap_or(AUTOVECTOR_BASE + priority));
+        Ok(line_vector.unwrap_or(number))
+    }
+
+    /// Lower the highest priority to the highest interrupt set directly that's still pending
+    fn update_highest(&mut self) {
         while self.highest > 0 && !self.interrupts[self.highest as usize].0 {
             self.highest -= 1;
         }
-        Ok(acknowledge)
+    }
+
+    /// Returns the levels of the inputs, including the connected lines, since the devices that drive the lines don't
+    /// save them
+    pub(crate) fn save_state(&self) -> InterruptState {
+        InterruptState {
+            interrupts: self.interrupts.clone(),
+            highest: self.highest,
+            lines: self.lines.iter().map(|(_, line)| line.0.get()).collect(),
+        }
+    }
+
+    pub(crate) fn restore_state(&mut self, state: &InterruptState) {
+        self.interrupts = state.interrupts.clone();
+        self.highest = state.highest;
+        for ((_, line), level) in self.lines.iter().zip(state.lines.iter()) {
+            line.0.set(*level);
+        }
+    }
+}
+
+/// A daisy chain of interrupt lines into a single interrupt input, like the peripherals of a Z80 in interrupt
+/// mode 2, where the first asserted line in the chain takes precedence and supplies the vector
+#[derive(Clone, Debug, Default)]
+pub struct InterruptChain {
+    lines: Vec<InterruptLine>,
+}
+
+impl InterruptChain {
+    /// Connect a new interrupt line to the end of the chain, which has the lowest priority so far
+    pub fn connect(&mut self) -> InterruptLine {
+        let line = InterruptLine::default();
+        self.lines.push(line.clone());
+        line
+    }
+
+    /// Returns the vector of the highest priority asserted line, or `None` if no lines are asserted.  A line
+    /// that doesn't supply a vector leaves the data bus floating, which reads as 0xFF
+    pub fn check(&self) -> Option<u8> {
+        self.lines
+            .iter()
+            .find(|line| line.is_asserted())
+            .map(|line| line.vector().unwrap_or(0xFF))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connected_line_uses_the_autovector() {
+        let mut controller = InterruptController::default();
+        let line = controller.connect(3);
+        assert_eq!(controller.check(), (false, 0, 0));
+
+        line.set(true);
+        assert_eq!(controller.check(), (true, 3, AUTOVECTOR_BASE + 3));
+        assert!(controller.is_pending(3));
+        assert_eq!(controller.acknowledge(3).unwrap(), AUTOVECTOR_BASE + 3);
+    }
+
+    #[test]
+    fn connected_line_supplies_its_vector() {
+        let mut controller = InterruptController::default();
+        let line = controller.connect(5);
+        line.set_vectored(true, 0x40);
+        assert_eq!(controller.check(), (true, 5, 0x40));
+        assert_eq!(controller.acknowledge(5).unwrap(), 0x40);
+    }
+
+    #[test]
+    fn connected_line_stays_asserted_until_cleared() {
+        let mut controller = InterruptController::default();
+        let line = InterruptLine::default();
+        controller.connect_line(2, line.clone());
+
+        line.set(true);
+        controller.acknowledge(2).unwrap();
+        assert!(controller.is_pending(2));
+        assert_eq!(controller.check(), (true, 2, AUTOVECTOR_BASE + 2));
+
+        line.set(false);
+        assert!(!controller.is_pending(2));
+        assert_eq!(controller.check(), (false, 0, 0));
+    }
+
+    #[test]
+    fn direct_interrupt_is_cleared_when_acknowledged() {
+        let mut controller = InterruptController::default();
+        controller.set(true, 2, 0x50).unwrap();
+        controller.set(true, 4, 0x60).unwrap();
+        assert_eq!(controller.check(), (true, 4, 0x60));
+
+        assert_eq!(controller.acknowledge(4).unwrap(), 0x60);
+        assert_eq!(controller.check(), (true, 2, 0x50));
+        assert_eq!(controller.acknowledge(2).unwrap(), 0x50);
+        assert_eq!(controller.check(), (false, 0, 0));
+    }
+
+    #[test]
+    fn direct_interrupt_that_is_cleared_is_no_longer_pending() {
+        let mut controller = InterruptController::default();
+        controller.set(true, 4, 0x60).unwrap();
+        controller.set(false, 4, 0x60).unwrap();
+        assert!(!controller.is_pending(4));
+        assert_eq!(controller.check(), (false, 0, 0));
+    }
+
+    #[test]
+    fn highest_priority_is_checked_first() {
+        let mut controller = InterruptController::default();
+        let low = controller.connect(2);
+        let high = controller.connect(6);
+        controller.set(true, 4, 0x60).unwrap();
+
+        low.set(true);
+        high.set(true);
+        assert_eq!(controller.check(), (true, 6, AUTOVECTOR_BASE + 6));
+        high.set(false);
+        assert_eq!(controller.check(), (true, 4, 0x60));
+        controller.acknowledge(4).unwrap();
+        assert_eq!(controller.check(), (true, 2, AUTOVECTOR_BASE + 2));
+    }
+
+    #[test]
+    fn direct_interrupt_takes_precedence_over_a_line_at_the_same_priority() {
+        let mut controller = InterruptController::default();
+        let line = controller.connect(4);
+        line.set_vectored(true, 0x40);
+        controller.set(true, 4, 0x60).unwrap();
+
+        assert_eq!(controller.check(), (true, 4, 0x60));
+        assert_eq!(controller.acknowledge(4).unwrap(), 0x60);
+        assert_eq!(controller.check(), (true, 4, 0x40));
+        assert_eq!(controller.acknowledge(4).unwrap(), 0x40);
+    }
+
+    #[test]
+    fn first_line_connected_at_the_same_priority_takes_precedence() {
+        let mut controller = InterruptController::default();
+        let first = controller.connect(3);
+        let second = controller.connect(3);
+        first.set_vectored(true, 0x40);
+        second.set_vectored(true, 0x41);
+
+        assert_eq!(controller.check(), (true, 3, 0x40));
+        assert_eq!(controller.acknowledge(3).unwrap(), 0x40);
+        first.set(false);
+        assert_eq!(controller.check(), (true, 3, 0x41));
+        assert_eq!(controller.acknowledge(3).unwrap(), 0x41);
+    }
+
+    #[test]
+    fn chain_checks_lines_in_the_order_they_were_connected() {
+        let mut chain = InterruptChain::default();
+        let first = chain.connect();
+        let second = chain.connect();
+        assert_eq!(chain.check(), None);
+
+        second.set_vectored(true, 0x12);
+        assert_eq!(chain.check(), Some(0x12));
+        first.set_vectored(true, 0x10);
+        assert_eq!(chain.check(), Some(0x10));
+        first.set(false);
+        assert_eq!(chain.check(), Some(0x12));
+    }
+
+    #[test]
+    fn chain_reads_a_floating_bus_for_a_line_without_a_vector() {
+        let mut chain = InterruptChain::default();
+        let line = chain.connect();
+        line.set(true);
+        assert_eq!(chain.check(), Some(0xFF));
     }
 }
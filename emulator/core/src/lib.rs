@@ -1,21 +1,51 @@
 #[macro_use]
 mod error;
 
+mod autosave;
+mod capabilities;
+mod coverage;
 mod devices;
 mod interrupts;
+pub mod logging;
+mod machine;
 mod memory;
+mod regions;
+mod registers;
+mod rewind;
+pub mod strict;
+mod symbols;
 mod system;
+mod threaded;
+mod trace;
+mod wakeup;
+mod writelog;
 
 pub use crate::devices::{
-    Address, Addressable, Steppable, Interruptable, Debuggable, Inspectable, Signalable, Signal, Transmutable, TransmutableBox,
-    Device,
+    Address, Addressable, Steppable, ParallelSteppable, Interruptable, Debuggable, Inspectable, Signalable, Signal, Snapshotable,
+    Transmutable, TransmutableBox, Device, DeviceId,
 };
 pub use crate::devices::{
-    read_beu16, read_beu32, read_leu16, read_leu32, write_beu16, write_beu32, write_leu16, write_leu32, wrap_transmutable,
+    read_beu16, read_beu32, read_beu64, read_leu16, read_leu32, read_leu64, write_beu16, write_beu32, write_beu64, write_leu16,
+    write_leu32, write_leu64, sign_extend, wrap_transmutable,
 };
-pub use crate::error::Error;
-pub use crate::interrupts::InterruptController;
-pub use crate::memory::{MemoryBlock, AddressTranslator, AddressRepeater, Bus, BusPort, dump_slice, dump_memory};
-pub use crate::system::System;
+pub use crate::capabilities::Capabilities;
+pub use crate::autosave::{AutoSave, write_atomic, SNAPSHOT_MAGIC, SNAPSHOT_VERSION};
+pub use crate::coverage::{Coverage, AccessCounts, AccessKind, AccessRange};
+pub use crate::error::{Error, EmulatorErrorKind, BreakpointInfo};
+pub use crate::interrupts::{InterruptController, InterruptLine, InterruptChain};
+pub use crate::machine::{MachineInfo, VideoStandard};
+pub use crate::memory::{
+    MemoryBlock, AddressTranslator, AddressRepeater, BankedRegion, BankSwitch, BankRegister, Bus, BusPort, AccessContext,
+    WriteObserver, dump_slice, dump_memory,
+};
+pub use crate::regions::{MemoryRegion, RegionColor};
+pub use crate::registers::{Register, RegisterBank, RegisterMapped, Access, Field, read_registers, write_registers};
+pub use crate::strict::StrictMode;
+pub use crate::symbols::SymbolTable;
+pub use crate::system::{System, SystemState, DeviceProfile, ValidationReport, StepPriority, Interleave};
+pub use crate::threaded::ThreadedDevice;
+pub use crate::trace::{TraceFormat, TRACE_MAGIC, TRACE_VERSION};
+pub use crate::wakeup::{Waker, TimerId};
+pub use crate::writelog::{WriteLog, LoggedWrite};
 
 pub use emulator_hal::BusAccess;
@@ -0,0 +1,82 @@
+use std::sync::RwLock;
+use log::{Log, Metadata, Record, LevelFilter, SetLoggerError};
+
+
+struct LogLevels {
+    global: LevelFilter,
+    devices: Vec<(String, LevelFilter)>,
+}
+
+static LEVELS: RwLock<LogLevels> = RwLock::new(LogLevels {
+    global: LevelFilter::Info,
+    devices: Vec::new(),
+});
+
+/// A logger that filters messages using the level set for the device they were logged by, which is the target
+/// of the message, before passing them to the frontend's logger
+struct DeviceLogger {
+    inner: Box<dyn Log>,
+}
+
+impl Log for DeviceLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= log_level(metadata.target()) && self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if self.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Install the given logger, which should accept all levels, with `level` used for devices that don't have
+/// their own level
+pub fn init(logger: Box<dyn Log>, level: LevelFilter) -> Result<(), SetLoggerError> {
+    log::set_boxed_logger(Box::new(DeviceLogger {
+        inner: logger,
+    }))?;
+    set_log_level(level);
+    Ok(())
+}
+
+/// Set the level used for all devices that don't have their own level
+pub fn set_log_level(level: LevelFilter) {
+    let mut levels = LEVELS.write().unwrap();
+    levels.global = level;
+    update_max_level(&levels);
+}
+
+/// Set the level for the messages logged by a device, using the name it logs with (eg. "ym2612") or the path of
+/// a module, or use the global level again if `level` is `None`
+pub fn set_device_log_level(device: &str, level: Option<LevelFilter>) {
+    let mut levels = LEVELS.write().unwrap();
+    levels.devices.retain(|(name, _)| name != device);
+    if let Some(level) = level {
+        levels.devices.push((device.to_string(), level));
+    }
+    update_max_level(&levels);
+}
+
+/// Returns the level that applies to messages with the given target, which is either a device name or the
+/// path of the module that logged the message
+pub fn log_level(target: &str) -> LevelFilter {
+    let levels = LEVELS.read().unwrap();
+    levels
+        .devices
+        .iter()
+        .find(|(name, _)| target == name || target.strip_prefix(name.as_str()).is_some_and(|rest| rest.starts_with("::")))
+        .map(|(_, level)| *level)
+        .unwrap_or(levels.global)
+}
+
+// Messages above the max level are discarded by the log macros before reaching the logger, so it must be
+// raised to the most verbose level of any device
+fn update_max_level(levels: &LogLevels) {
+    let max = levels.devices.iter().map(|(_, level)| *level).fold(levels.global, Ord::max);
+    log::set_max_level(max);
+}
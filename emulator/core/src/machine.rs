@@ -0,0 +1,120 @@
+use std::str::FromStr;
+
+use femtos::{Duration, Frequency};
+
+
+/// The television standard that a machine's video output is made for, which sets the frame rate and the line
+/// timing, and on many machines the master clock, since it's derived from the colour subcarrier
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum VideoStandard {
+    /// 60 frames per second of 262 lines, as used in North America and Japan
+    #[default]
+    Ntsc,
+    /// 50 frames per second of 313 lines, as used in Europe and Australia
+    Pal,
+}
+
+impl VideoStandard {
+    /// Returns the rate of the frames, which are non-interlaced
+    pub fn refresh_rate(self) -> Frequency {
+        match self {
+            VideoStandard::Ntsc => Frequency::from_hz(60),
+            VideoStandard::Pal => Frequency::from_hz(50),
+        }
+    }
+
+    /// Returns the number of lines in each frame, including the lines in vblank
+    pub fn lines_per_frame(self) -> u32 {
+        match self {
+            VideoStandard::Ntsc => 262,
+            VideoStandard::Pal => 313,
+        }
+    }
+
+    /// Returns the time taken to draw each line, including hblank
+    pub fn line_duration(self) -> Duration {
+        match self {
+            VideoStandard::Ntsc => Duration::from_nanos(63_500),
+            VideoStandard::Pal => Duration::from_nanos(64_000),
+        }
+    }
+
+    /// Returns the frequency of the colour subcarrier, which the pixel and cpu clocks are usually multiples or
+    /// fractions of
+    pub fn subcarrier_frequency(self) -> Frequency {
+        match self {
+            VideoStandard::Ntsc => Frequency::from_hz(3_579_545),
+            VideoStandard::Pal => Frequency::from_hz(4_433_619),
+        }
+    }
+}
+
+impl FromStr for VideoStandard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ntsc" => Ok(VideoStandard::Ntsc),
+            "pal" => Ok(VideoStandard::Pal),
+            _ => Err(format!("invalid video standard {:?}, expected ntsc or pal", s)),
+        }
+    }
+}
+
+/// A description of a built machine, which frontends use to size their windows and pace their updates
+#[derive(Clone, Debug, Default)]
+pub struct MachineInfo {
+    pub name: String,
+    /// The size in pixels of the frames that the machine's video output produces, if it has one
+    pub video_size: Option<(u32, u32)>,
+    /// The rate at which the machine produces new frames
+    pub refresh_rate: Option<Frequency>,
+    /// The television standard of the video output, which the refresh rate comes from
+    pub video_standard: Option<VideoStandard>,
+    /// The width divided by the height of the picture on the machine's display, which is different from the
+    /// ratio of the video size if the pixels aren't square
+    pub aspect_ratio: Option<f32>,
+    /// The ROM files that were loaded to build the machine
+    pub required_roms: Vec<String>,
+}
+
+impl MachineInfo {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_video(mut self, width: u32, height: u32, refresh_rate: Frequency) -> Self {
+        self.video_size = Some((width, height));
+        self.refresh_rate = Some(refresh_rate);
+        self
+    }
+
+    /// Set the video standard, and the refresh rate to match it
+    pub fn with_video_standard(mut self, standard: VideoStandard) -> Self {
+        self.video_standard = Some(standard);
+        self.refresh_rate = Some(standard.refresh_rate());
+        self
+    }
+
+    pub fn with_aspect_ratio(mut self, aspect_ratio: f32) -> Self {
+        self.aspect_ratio = Some(aspect_ratio);
+        self
+    }
+
+    pub fn with_rom(mut self, filename: &str) -> Self {
+        self.required_roms.push(filename.to_string());
+        self
+    }
+
+    /// Returns the size of a window that shows the video output at its full height, with the correct aspect ratio
+    pub fn window_size(&self) -> Option<(u32, u32)> {
+        let (width, height) = self.video_size?;
+        match self.aspect_ratio {
+            Some(aspect_ratio) => Some(((height as f32 * aspect_ratio).round() as u32, height)),
+            None => Some((width, height)),
+        }
+    }
+}
@@ -1,19 +1,27 @@
 use std::fs;
+use std::any::Any;
 use std::cmp;
 use std::rc::Rc;
-use std::cell::RefCell;
-use std::fmt::Write;
+use std::cell::{Cell, RefCell};
+use std::fmt::{self, Write};
 use femtos::Instant;
 use emulator_hal::{self, BusAccess, ErrorType};
 
 use crate::error::Error;
-use crate::devices::{Address, Addressable, Transmutable, Device, read_beu16};
+use crate::devices::{Address, Addressable, Snapshotable, Transmutable, Device, DeviceId, read_beu16};
+use crate::writelog::WriteLog;
+use crate::coverage::Coverage;
+use crate::regions::MemoryRegion;
+use crate::strict;
 
 
 /// A contiguous block of `Addressable` memory, backed by a `Vec`
 pub struct MemoryBlock {
     read_only: bool,
     contents: Vec<u8>,
+    /// The contents when the state was last saved, which is shared with the saved state until the next write, so
+    /// that saving the state of memory that hasn't changed doesn't copy it again
+    saved: Option<Rc<[u8]>>,
 }
 
 impl MemoryBlock {
@@ -21,6 +29,7 @@ impl MemoryBlock {
         MemoryBlock {
             read_only: false,
             contents,
+            saved: None,
         }
     }
 
@@ -35,6 +44,7 @@ impl MemoryBlock {
         match fs::read(filename) {
             Ok(contents) => {
                 self.contents[(addr as usize)..(addr as usize) + contents.len()].copy_from_slice(&contents);
+                self.saved = None;
                 Ok(())
             },
             Err(_) => Err(Error::new(format!("Error reading contents of {}", filename))),
@@ -47,6 +57,7 @@ impl MemoryBlock {
 
     pub fn resize(&mut self, new_size: usize) {
         self.contents.resize(new_size, 0);
+        self.saved = None;
     }
 }
 
@@ -69,14 +80,43 @@ impl Addressable for MemoryBlock {
         }
 
         self.contents[(addr as usize)..(addr as usize) + data.len()].copy_from_slice(data);
+        self.saved = None;
         Ok(())
     }
+
+    fn is_memory(&self) -> bool {
+        true
+    }
+}
+
+impl Snapshotable for MemoryBlock {
+    fn save_state(&mut self) -> Option<Box<dyn Any>> {
+        if self.read_only {
+            return None;
+        }
+        let contents = self.saved.get_or_insert_with(|| Rc::from(self.contents.as_slice()));
+        Some(Box::new(contents.clone()))
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        if let Some(contents) = state.downcast_ref::<Rc<[u8]>>() {
+            if self.saved.as_ref().map(|saved| !Rc::ptr_eq(saved, contents)).unwrap_or(true) {
+                self.contents.clear();
+                self.contents.extend_from_slice(contents);
+                self.saved = Some(contents.clone());
+            }
+        }
+    }
 }
 
 impl Transmutable for MemoryBlock {
     fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
         Some(self)
     }
+
+    fn as_snapshotable(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
 }
 
 
@@ -117,6 +157,10 @@ impl Addressable for AddressRepeater {
             .unwrap()
             .write(clock, addr % size, data)
     }
+
+    fn is_memory(&self) -> bool {
+        self.subdevice.borrow_mut().as_addressable().unwrap().is_memory()
+    }
 }
 
 impl Transmutable for AddressRepeater {
@@ -166,6 +210,10 @@ impl Addressable for AddressTranslator {
             .unwrap()
             .write(clock, (self.func)(addr), data)
     }
+
+    fn is_memory(&self) -> bool {
+        self.subdevice.borrow_mut().as_addressable().unwrap().is_memory()
+    }
 }
 
 impl Transmutable for AddressTranslator {
@@ -175,6 +223,270 @@ impl Transmutable for AddressTranslator {
 }
 
 
+/// The bank that's visible in each slot of a `BankedRegion`, which is shared with whatever switches the banks
+#[derive(Clone)]
+pub struct BankSwitch {
+    selected: Rc<[Cell<usize>]>,
+    bank_size: usize,
+    observers: Rc<RefCell<Vec<(Address, Rc<dyn WriteObserver>)>>>,
+}
+
+impl BankSwitch {
+    fn new(slots: usize, bank_size: usize) -> Self {
+        Self {
+            selected: (0..slots).map(Cell::new).collect(),
+            bank_size,
+            observers: Default::default(),
+        }
+    }
+
+    pub fn slots(&self) -> usize {
+        self.selected.len()
+    }
+
+    /// Returns the bank that's visible in the given slot
+    pub fn selected(&self, slot: usize) -> usize {
+        self.selected.get(slot).map(|bank| bank.get()).unwrap_or(0)
+    }
+
+    /// Make the given bank visible in the given slot, which is ignored if there's no such slot
+    pub fn select(&self, slot: usize, bank: usize) {
+        if let Some(selected) = self.selected.get(slot) {
+            if selected.replace(bank) != bank {
+                for (base, observer) in self.observers.borrow().iter() {
+                    observer.on_write(base + (slot * self.bank_size) as Address, self.bank_size);
+                }
+            }
+        }
+    }
+
+    /// Tell the observer whenever a slot shows a different bank, as if the whole slot had been written, where the
+    /// given base is the address that the region is mapped at
+    pub fn observe_selects(&self, base: Address, observer: Rc<dyn WriteObserver>) {
+        self.observers.borrow_mut().push((base, observer));
+    }
+}
+
+/// A window made of equal sized slots, which each show one of a set of banks that can be switched while running
+///
+/// The banks are taken from one or more devices, such as a ROM followed by a RAM, and are numbered in the order
+/// they're added.  Each slot starts out showing the bank with the same number, and bank numbers past the last
+/// bank wrap around, like the unconnected upper bits of a mapper's bank register.  The banks are switched either
+/// by a `BankRegister` on a bus, or by another device holding the region's `BankSwitch`.  For example, a Sega
+/// mapper for the Genesis is 8 slots of 512KB, and a Sega Master System mapper is 3 slots of 16KB
+pub struct BankedRegion {
+    bank_size: usize,
+    banks: Vec<(Device, Address)>,
+    switch: BankSwitch,
+}
+
+impl BankedRegion {
+    pub fn new(slots: usize, bank_size: usize) -> Self {
+        Self {
+            bank_size,
+            banks: vec![],
+            switch: BankSwitch::new(slots, bank_size),
+        }
+    }
+
+    /// Add as many banks as it takes to cover the whole device
+    pub fn with_device(self, device: Device) -> Self {
+        let size = device.borrow_mut().as_addressable().unwrap().size();
+        let count = size.div_ceil(self.bank_size);
+        self.with_banks(device, count.max(1))
+    }
+
+    /// Add the given number of banks, starting from the beginning of the device
+    pub fn with_banks(mut self, device: Device, count: usize) -> Self {
+        for i in 0..count {
+            self.banks.push((device.clone(), (i * self.bank_size) as Address));
+        }
+        self
+    }
+
+    pub fn bank_count(&self) -> usize {
+        self.banks.len()
+    }
+
+    pub fn switch(&self) -> BankSwitch {
+        self.switch.clone()
+    }
+
+    /// Returns a register with one byte for each slot, which selects the bank of that slot when written
+    pub fn register(&self) -> BankRegister {
+        BankRegister {
+            switch: self.switch.clone(),
+            size: self.switch.slots(),
+            readable: true,
+            func: Box::new(|switch: &BankSwitch, addr: Address, data: u8| switch.select(addr as usize, data as usize)),
+        }
+    }
+
+    /// Returns a register of the given size, which calls the closure with the offset and value of each byte written
+    pub fn register_with<F>(&self, size: usize, func: F) -> BankRegister
+    where
+        F: Fn(&BankSwitch, Address, u8) + 'static,
+    {
+        BankRegister {
+            switch: self.switch.clone(),
+            size,
+            readable: false,
+            func: Box::new(func),
+        }
+    }
+
+    /// Returns the device and address within it for the given address, and the number of bytes left in the slot
+    fn translate(&self, addr: Address) -> Result<(Device, Address, usize), Error> {
+        if self.banks.is_empty() {
+            return Err(Error::new(format!("no banks have been added to the banked region accessed at {:x}", addr)));
+        }
+
+        let slot = addr as usize / self.bank_size;
+        let offset = addr as usize % self.bank_size;
+        let bank = self.switch.selected(slot) % self.banks.len();
+        let (device, base) = &self.banks[bank];
+        Ok((device.clone(), base + offset as Address, self.bank_size - offset))
+    }
+}
+
+impl Addressable for BankedRegion {
+    fn size(&self) -> usize {
+        self.switch.slots() * self.bank_size
+    }
+
+    fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        // An access that crosses into the next slot is split between the two banks
+        let mut i = 0;
+        while i < data.len() {
+            let (device, bank_addr, remaining) = self.translate(addr + i as Address)?;
+            let end = cmp::min(i + remaining, data.len());
+            device
+                .borrow_mut()
+                .as_addressable()
+                .unwrap()
+                .read(clock, bank_addr, &mut data[i..end])?;
+            i = end;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        let mut i = 0;
+        while i < data.len() {
+            let (device, bank_addr, remaining) = self.translate(addr + i as Address)?;
+            let end = cmp::min(i + remaining, data.len());
+            device
+                .borrow_mut()
+                .as_addressable()
+                .unwrap()
+                .write(clock, bank_addr, &data[i..end])?;
+            i = end;
+        }
+        Ok(())
+    }
+
+    fn is_memory(&self) -> bool {
+        self.banks
+            .first()
+            .map(|(device, _)| device.borrow_mut().as_addressable().unwrap().is_memory())
+            .unwrap_or(false)
+    }
+}
+
+impl Snapshotable for BankedRegion {
+    fn save_state(&mut self) -> Option<Box<dyn Any>> {
+        // Only the selected banks are saved, since the banks themselves are separate devices
+        let selected: Vec<usize> = (0..self.switch.slots()).map(|slot| self.switch.selected(slot)).collect();
+        Some(Box::new(selected))
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        if let Some(selected) = state.downcast_ref::<Vec<usize>>() {
+            for (slot, bank) in selected.iter().enumerate() {
+                self.switch.select(slot, *bank);
+            }
+        }
+    }
+}
+
+impl Transmutable for BankedRegion {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn as_snapshotable(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
+}
+
+type BankSwitchFn = Box<dyn Fn(&BankSwitch, Address, u8)>;
+
+/// A register that switches the banks of a `BankedRegion` when it's written
+pub struct BankRegister {
+    switch: BankSwitch,
+    size: usize,
+    /// Whether reading returns the bank of the slot with the same number as the offset
+    readable: bool,
+    func: BankSwitchFn,
+}
+
+impl Addressable for BankRegister {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = if self.readable {
+                self.switch.selected(addr as usize + i) as u8
+            } else {
+                0
+            };
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        for (i, byte) in data.iter().enumerate() {
+            (self.func)(&self.switch, addr + i as Address, *byte);
+        }
+        Ok(())
+    }
+}
+
+impl Transmutable for BankRegister {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+}
+
+
+/// What caused an access to a bus, which is shown when a watched address is accessed
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessContext {
+    /// The device being stepped when the access was made, which is set by the `System`
+    pub device: Option<DeviceId>,
+    /// The address of the instruction that made the access, if the device is a cpu that reports it
+    pub pc: Option<Address>,
+}
+
+impl fmt::Display for AccessContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.pc {
+            Some(pc) => write!(f, "from PC {:#x}", pc),
+            None => write!(f, "from an unknown PC"),
+        }
+    }
+}
+
+
+/// Something that needs to know about every write to a bus, such as a cpu's cache of decoded instructions, which
+/// must forget what it has cached from memory that has changed
+pub trait WriteObserver {
+    fn on_write(&self, addr: Address, len: usize);
+}
+
+
 #[derive(Clone)]
 pub struct Block {
     pub base: Address,
@@ -190,7 +502,13 @@ pub struct Bus {
     blocks: Vec<Block>,
     ignore_unmapped: bool,
     watchers: Vec<Address>,
-    watcher_modified: bool,
+    watcher_modified: Option<(usize, Address, AccessContext, u64)>,
+    context: AccessContext,
+    write_logs: Vec<(DeviceId, WriteLog)>,
+    undo_journal: Option<Vec<(Address, Vec<u8>)>>,
+    coverage: Option<Coverage>,
+    regions: Vec<MemoryRegion>,
+    write_observers: Vec<Rc<dyn WriteObserver>>,
 }
 
 impl Bus {
@@ -214,6 +532,7 @@ impl Bus {
             .iter()
             .position(|cur| cur.base > block.base)
             .unwrap_or(self.blocks.len());
+        self.notify_write(base, size);
         self.blocks.insert(i, block);
     }
 
@@ -234,6 +553,7 @@ impl Bus {
     pub fn dump_memory(&mut self, clock: Instant, mut addr: Address, mut count: Address) {
         while count > 0 {
             let mut line = format!("{:#010x}: ", addr);
+            let region = self.region_at(addr).map(|region| region.colored_name());
 
             let to = if count < 16 { count / 2 } else { 8 };
             for _ in 0..to {
@@ -246,10 +566,38 @@ impl Bus {
                 addr += 2;
                 count -= 2;
             }
+            if let Some(region) = region {
+                write!(line, " ; {}", region).unwrap();
+            }
             println!("{}", line);
         }
     }
 
+    /// Tag a range of addresses with a name, replacing any region with the same name
+    pub fn add_region(&mut self, region: MemoryRegion) {
+        self.remove_region(&region.name);
+        self.regions.push(region);
+    }
+
+    /// Remove the region with the given name, returning false if there was no such region
+    pub fn remove_region(&mut self, name: &str) -> bool {
+        let len = self.regions.len();
+        self.regions.retain(|region| region.name != name);
+        self.regions.len() != len
+    }
+
+    pub fn regions(&self) -> &[MemoryRegion] {
+        &self.regions
+    }
+
+    /// Returns the region containing the given address, which is the smallest one if regions overlap
+    pub fn region_at(&self, addr: Address) -> Option<&MemoryRegion> {
+        self.regions
+            .iter()
+            .filter(|region| region.contains(addr))
+            .min_by_key(|region| region.size)
+    }
+
     pub fn add_watcher(&mut self, addr: Address) {
         self.watchers.push(addr);
     }
@@ -261,10 +609,115 @@ impl Bus {
         }
     }
 
+    /// Set the device that the following accesses are made by, and forget the last reported pc
+    pub fn set_access_device(&mut self, device: Option<DeviceId>) {
+        self.context = AccessContext {
+            device,
+            pc: None,
+        };
+    }
+
+    /// Set the address of the instruction that the following accesses are made by
+    pub fn set_access_pc(&mut self, pc: Address) {
+        self.context.pc = Some(pc);
+        if let Some(coverage) = self.coverage.as_ref() {
+            coverage.record_execute(pc);
+        }
+    }
+
+    pub fn access_context(&self) -> AccessContext {
+        self.context
+    }
+
     pub fn check_and_reset_watcher_modified(&mut self) -> bool {
-        let result = self.watcher_modified;
-        self.watcher_modified = false;
-        result
+        self.watcher_modified.take().is_some()
+    }
+
+    /// Returns each pair of blocks whose address ranges overlap
+    pub fn overlapping_blocks(&self) -> Vec<(Block, Block)> {
+        let mut overlaps = vec![];
+        for (i, block) in self.blocks.iter().enumerate() {
+            let end = block.base + block.size as Address;
+            for next in self.blocks[i + 1..].iter().take_while(|next| next.base < end) {
+                overlaps.push((block.clone(), next.clone()));
+            }
+        }
+        overlaps
+    }
+
+    /// Record all writes to the given device in the log, with addresses relative to the start of the device
+    pub fn log_writes(&mut self, device: DeviceId, log: WriteLog) {
+        self.stop_logging_writes(device);
+        self.write_logs.push((device, log));
+    }
+
+    pub fn stop_logging_writes(&mut self, device: DeviceId) {
+        self.write_logs.retain(|(id, _)| *id != device);
+    }
+
+    /// Count the accesses to each address, and the instructions executed at each address, in the given record
+    pub fn record_coverage(&mut self, coverage: Coverage) {
+        self.coverage = Some(coverage);
+    }
+
+    pub fn stop_recording_coverage(&mut self) {
+        self.coverage = None;
+    }
+
+    /// Returns the base address and size of each device on the bus
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// Tell the observer about every write to this bus, and whenever a device is mapped into it
+    pub fn observe_writes(&mut self, observer: Rc<dyn WriteObserver>) {
+        self.write_observers.push(observer);
+    }
+
+    #[inline]
+    fn notify_write(&self, addr: Address, len: usize) {
+        for observer in self.write_observers.iter() {
+            observer.on_write(addr, len);
+        }
+    }
+
+    /// Tell the observers of this bus whenever the switch shows a different bank, for a `BankedRegion` mapped at the
+    /// given address.  Only the observers that have already been added are told
+    pub fn observe_bank_switches(&self, base: Address, switch: &BankSwitch) {
+        for observer in self.write_observers.iter() {
+            switch.observe_selects(base, observer.clone());
+        }
+    }
+
+    /// Record the previous contents of memory before each write, so that the writes can be undone
+    pub fn start_undo_journal(&mut self) {
+        self.undo_journal.get_or_insert_with(Vec::new);
+    }
+
+    pub fn stop_undo_journal(&mut self) {
+        self.undo_journal = None;
+    }
+
+    /// Returns the address and previous contents of each write to memory since this was last called
+    pub fn take_undo_journal(&mut self) -> Vec<(Address, Vec<u8>)> {
+        self.undo_journal.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Write back the previous contents of memory from a journal taken with `take_undo_journal()`, without
+    /// triggering watchers or being logged
+    pub fn undo_writes(&mut self, clock: Instant, journal: &[(Address, Vec<u8>)]) -> Result<(), Error> {
+        for (addr, data) in journal.iter().rev() {
+            self.notify_write(*addr, data.len());
+            let (dev, relative_addr) = self.get_device_at(*addr, data.len())?;
+            dev.borrow_mut().as_addressable().unwrap().write(clock, relative_addr, data)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the index and address of the last watcher that was written to, what wrote to it, and the value
+    /// written, and resets it
+    pub fn take_watcher_modified(&mut self) -> Option<(usize, Address, AccessContext, u64)> {
+        self.watcher_modified.take()
     }
 }
 
@@ -275,6 +728,10 @@ impl Addressable for Bus {
     }
 
     fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        if let Some(coverage) = self.coverage.as_ref() {
+            coverage.record_read(addr, data.len());
+        }
+
         let (dev, relative_addr) = match self.get_device_at(addr, data.len()) {
             Ok(result) => result,
             Err(err) if self.ignore_unmapped => {
@@ -284,13 +741,21 @@ impl Addressable for Bus {
             Err(err) => return Err(err),
         };
         let result = dev.borrow_mut().as_addressable().unwrap().read(clock, relative_addr, data);
+        strict::claim(dev.id());
         result
     }
 
     fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
-        if self.watchers.iter().any(|a| *a == addr) {
-            println!("watch: writing to address {:#06x} with {:?}", addr, data);
-            self.watcher_modified = true;
+        if let Some(index) = self.watchers.iter().position(|a| *a == addr) {
+            let value = data
+                .iter()
+                .fold(String::new(), |output, byte| output + &format!("{:02x}", byte));
+            println!("watch: write of 0x{} to {:#06x} {}", value, addr, self.context);
+            let value = data.iter().take(8).fold(0, |value, byte| (value << 8) | *byte as u64);
+            self.watcher_modified = Some((index, addr, self.context, value));
+        }
+        if let Some(coverage) = self.coverage.as_ref() {
+            coverage.record_write(addr, data.len());
         }
 
         let (dev, relative_addr) = match self.get_device_at(addr, data.len()) {
@@ -301,11 +766,32 @@ impl Addressable for Bus {
             },
             Err(err) => return Err(err),
         };
-        let result = dev.borrow_mut().as_addressable().unwrap().write(clock, relative_addr, data);
+        if let Some((_, log)) = self.write_logs.iter().find(|(id, _)| *id == dev.id()) {
+            log.record(clock, relative_addr, data);
+        }
+        self.notify_write(addr, data.len());
+        let mut device = dev.borrow_mut();
+        let device = device.as_addressable().unwrap();
+        if let Some(journal) = self.undo_journal.as_mut() {
+            if device.is_memory() {
+                let mut previous = vec![0; data.len()];
+                device.read(clock, relative_addr, &mut previous)?;
+                journal.push((addr, previous));
+            }
+        }
+        let result = device.write(clock, relative_addr, data);
+        strict::claim(dev.id());
         result
     }
 }
 
+// A bus can be mapped into another bus, such as to decode only some of the address lines of a range
+impl Transmutable for Bus {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+}
+
 /// An adapter for limiting the access requests of a device (eg. CPU) on a `Bus` to the address
 /// and data widths of the device
 #[derive(Clone)]
@@ -371,6 +857,12 @@ impl Addressable for BusPort {
     }
 }
 
+impl Transmutable for BusPort {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+}
+
 pub fn dump_slice(data: &[u8], mut count: usize) {
     let mut addr = 0;
     while count > 0 {
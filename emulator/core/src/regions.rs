@@ -0,0 +1,81 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::devices::Address;
+
+
+/// A named range of addresses, such as the stack or video memory, which the debugger shows next to the
+/// addresses it prints
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub name: String,
+    pub base: Address,
+    pub size: Address,
+    pub color: Option<RegionColor>,
+}
+
+impl MemoryRegion {
+    pub fn new(name: &str, base: Address, size: Address) -> Self {
+        Self {
+            name: name.to_string(),
+            base,
+            size,
+            color: None,
+        }
+    }
+
+    pub fn with_color(mut self, color: RegionColor) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    pub fn contains(&self, addr: Address) -> bool {
+        addr >= self.base && addr - self.base < self.size
+    }
+
+    /// Returns the name, coloured using terminal escape codes if the region has a colour
+    pub fn colored_name(&self) -> String {
+        match self.color {
+            Some(RegionColor(r, g, b)) => format!("\x1b[38;2;{};{};{}m{}\x1b[0m", r, g, b, self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+impl fmt::Display for MemoryRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:08x}-{:08x})", self.colored_name(), self.base, self.base + self.size.saturating_sub(1))
+    }
+}
+
+/// The colour to show a region in, as red, green, and blue components
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RegionColor(pub u8, pub u8, pub u8);
+
+impl FromStr for RegionColor {
+    type Err = String;
+
+    /// Parse a colour name, or a hex colour of the form `#rrggbb` or `rrggbb`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let color = match s.to_lowercase().as_str() {
+            "red" => RegionColor(0xE0, 0x40, 0x40),
+            "green" => RegionColor(0x40, 0xC0, 0x40),
+            "blue" => RegionColor(0x50, 0x70, 0xF0),
+            "yellow" => RegionColor(0xE0, 0xD0, 0x40),
+            "cyan" => RegionColor(0x40, 0xD0, 0xD0),
+            "magenta" => RegionColor(0xD0, 0x50, 0xD0),
+            "orange" => RegionColor(0xF0, 0x90, 0x30),
+            "gray" | "grey" => RegionColor(0xA0, 0xA0, 0xA0),
+            "white" => RegionColor(0xFF, 0xFF, 0xFF),
+            hex => {
+                let hex = hex.strip_prefix('#').unwrap_or(hex);
+                let value = u32::from_str_radix(hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 6)
+                    .ok_or_else(|| format!("invalid colour: {}", s))?;
+                RegionColor((value >> 16) as u8, (value >> 8) as u8, value as u8)
+            },
+        };
+        Ok(color)
+    }
+}
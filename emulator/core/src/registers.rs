@@ -0,0 +1,265 @@
+//! Declarative tables of memory mapped registers
+//!
+//! A peripheral describes its registers with a static table of [`Register`]s, which give the offset, the
+//! direction, the reset value, the masks of the bits that can be read and written, the names of the bit
+//! fields, and optional hooks for the side effects of accessing the register.  The values of the registers
+//! that are stored are kept in a [`RegisterBank`], and the device's `Addressable` implementation can then
+//! call [`read_registers`] and [`write_registers`] instead of matching on the offsets itself.
+//!
+//! The stored values are kept by offset, so a read-only register and a write-only register at the same
+//! offset share the same storage, unless the read-only one has a read hook that returns its own value.
+
+use std::fmt::Write;
+
+use femtos::Instant;
+
+use crate::{Address, Error, StrictMode, strict};
+
+
+/// Which accesses a register responds to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn readable(self) -> bool {
+        self != Access::Write
+    }
+
+    fn writable(self) -> bool {
+        self != Access::Read
+    }
+}
+
+/// A named group of bits in a register, which is only used to describe the register's value
+#[derive(Copy, Clone, Debug)]
+pub struct Field {
+    pub name: &'static str,
+    pub mask: u8,
+}
+
+impl Field {
+    pub const fn new(name: &'static str, mask: u8) -> Self {
+        Self {
+            name,
+            mask,
+        }
+    }
+}
+
+/// Called when the register is read, with the stored value, which returns the value that is read
+pub type ReadHook<T> = fn(&mut T, Instant, u8) -> u8;
+/// Called when the register is written, with the value written, before the value is stored
+pub type WriteHook<T> = fn(&mut T, Instant, u8);
+
+/// The description of one 8-bit register of a device of type `T`
+pub struct Register<T: 'static> {
+    pub name: &'static str,
+    pub offset: Address,
+    pub access: Access,
+    pub reset: u8,
+    /// The bits of the stored value that are returned by a read, where the others read as 0
+    pub read_mask: u8,
+    /// The bits of the stored value that are changed by a write
+    pub write_mask: u8,
+    pub fields: &'static [Field],
+    pub on_read: Option<ReadHook<T>>,
+    pub on_write: Option<WriteHook<T>>,
+}
+
+impl<T: 'static> Register<T> {
+    pub const fn new(name: &'static str, offset: Address, access: Access) -> Self {
+        Self {
+            name,
+            offset,
+            access,
+            reset: 0,
+            read_mask: 0xFF,
+            write_mask: 0xFF,
+            fields: &[],
+            on_read: None,
+            on_write: None,
+        }
+    }
+
+    pub const fn with_reset(mut self, value: u8) -> Self {
+        self.reset = value;
+        self
+    }
+
+    pub const fn with_read_mask(mut self, mask: u8) -> Self {
+        self.read_mask = mask;
+        self
+    }
+
+    pub const fn with_write_mask(mut self, mask: u8) -> Self {
+        self.write_mask = mask;
+        self
+    }
+
+    pub const fn with_fields(mut self, fields: &'static [Field]) -> Self {
+        self.fields = fields;
+        self
+    }
+
+    pub const fn on_read(mut self, hook: ReadHook<T>) -> Self {
+        self.on_read = Some(hook);
+        self
+    }
+
+    pub const fn on_write(mut self, hook: WriteHook<T>) -> Self {
+        self.on_write = Some(hook);
+        self
+    }
+}
+
+/// The stored values of a device's registers, along with the table that describes them
+pub struct RegisterBank<T: 'static> {
+    name: &'static str,
+    registers: &'static [Register<T>],
+    values: Vec<u8>,
+    /// The mode used for accesses to offsets without a register, unless a strict mode has been set
+    unhandled: StrictMode,
+}
+
+impl<T: 'static> RegisterBank<T> {
+    /// Create a bank for a device with the given name (which is used in log messages) that takes up `size`
+    /// bytes of address space, with the registers set to their reset values
+    pub fn new(name: &'static str, size: usize, registers: &'static [Register<T>]) -> Self {
+        let mut bank = Self {
+            name,
+            registers,
+            values: vec![0; size],
+            unhandled: StrictMode::Warn,
+        };
+        bank.reset();
+        bank
+    }
+
+    /// Only log accesses to offsets without a register at the debug level, unless a strict mode has been set,
+    /// for devices that are normally accessed at registers that aren't implemented
+    pub fn with_expected_unhandled(mut self) -> Self {
+        self.unhandled = StrictMode::Ignore;
+        self
+    }
+
+    pub fn size(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Set all the registers to their reset values
+    pub fn reset(&mut self) {
+        self.values.iter_mut().for_each(|value| *value = 0);
+        for register in self.registers {
+            self.values[register.offset as usize] = register.reset;
+        }
+    }
+
+    /// Returns the stored value of the register at the given offset
+    #[inline]
+    pub fn get(&self, offset: Address) -> u8 {
+        self.values[offset as usize]
+    }
+
+    /// Set the stored value of the register at the given offset, ignoring the register's write mask
+    #[inline]
+    pub fn set(&mut self, offset: Address, value: u8) {
+        self.values[offset as usize] = value;
+    }
+
+    /// Returns the bits of the register under the mask, shifted down to start at bit 0
+    pub fn get_field(&self, offset: Address, mask: u8) -> u8 {
+        (self.get(offset) & mask) >> mask.trailing_zeros()
+    }
+
+    /// Set the bits of the register under the mask, to the given value shifted up to the start of the mask
+    pub fn set_field(&mut self, offset: Address, mask: u8, value: u8) {
+        let value = (value << mask.trailing_zeros()) & mask;
+        self.set(offset, (self.get(offset) & !mask) | value);
+    }
+
+    /// Returns the name and stored value of the register at the given offset, and the value of each of its fields
+    pub fn describe(&self, offset: Address) -> String {
+        let mut description = String::new();
+        for register in self.registers.iter().filter(|register| register.offset == offset) {
+            let value = self.get(offset);
+            write!(description, "{}={:02x}", register.name, value).unwrap();
+            for (i, field) in register.fields.iter().enumerate() {
+                let separator = if i == 0 { " (" } else { ", " };
+                write!(description, "{}{}={:x}", separator, field.name, self.get_field(offset, field.mask)).unwrap();
+            }
+            if !register.fields.is_empty() {
+                description.push(')');
+            }
+        }
+        description
+    }
+
+    fn find(&self, offset: Address, write: bool) -> Option<&'static Register<T>> {
+        self.registers.iter().find(|register| {
+            register.offset == offset
+                && if write {
+                    register.access.writable()
+                } else {
+                    register.access.readable()
+                }
+        })
+    }
+}
+
+/// A device with a table of memory mapped registers
+pub trait RegisterMapped: Sized + 'static {
+    fn register_bank(&mut self) -> &mut RegisterBank<Self>;
+}
+
+/// Read from the registers of a device, calling the read hooks, which can be used to implement `Addressable::read()`
+pub fn read_registers<T: RegisterMapped>(device: &mut T, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+    for (i, byte) in data.iter_mut().enumerate() {
+        let offset = addr + i as Address;
+        let bank = device.register_bank();
+        let (name, hook, value) = match bank.find(offset, false) {
+            Some(register) => (register.name, register.on_read, bank.get(offset) & register.read_mask),
+            None => {
+                strict::report(bank.name, bank.unhandled, strict::read_message(offset));
+                continue;
+            },
+        };
+        let bank_name = bank.name;
+
+        *byte = match hook {
+            Some(hook) => hook(device, clock, value),
+            None => value,
+        };
+        log::trace!(target: bank_name, "read {:02x} from {}", *byte, name);
+    }
+    Ok(())
+}
+
+/// Write to the registers of a device, storing the values and calling the write hooks, which can be used to
+/// implement `Addressable::write()`
+pub fn write_registers<T: RegisterMapped>(device: &mut T, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+    for (i, byte) in data.iter().enumerate() {
+        let offset = addr + i as Address;
+        let bank = device.register_bank();
+        let (hook, mask) = match bank.find(offset, true) {
+            Some(register) => (register.on_write, register.write_mask),
+            None => {
+                strict::report(bank.name, bank.unhandled, strict::write_message(offset, &[*byte]));
+                continue;
+            },
+        };
+
+        if let Some(hook) = hook {
+            hook(device, clock, *byte);
+        }
+
+        let bank = device.register_bank();
+        let value = (bank.get(offset) & !mask) | (*byte & mask);
+        bank.set(offset, value);
+        log::trace!(target: bank.name, "wrote {:02x} to {}", *byte, bank.describe(offset));
+    }
+    Ok(())
+}
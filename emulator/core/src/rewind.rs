@@ -0,0 +1,44 @@
+use std::any::Any;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use femtos::Instant;
+
+use crate::{Bus, Address, Device};
+
+
+/// The changes made by one step of one device, which are undone to step backwards
+pub(crate) struct JournalEntry {
+    pub(crate) device: Device,
+    /// The clock time the device was stepped at, which it will be stepped at again after rewinding
+    pub(crate) clock: Instant,
+    /// The state of the device before the step, if it's a debuggable device that can be rewound
+    pub(crate) state: Option<Box<dyn Any>>,
+    /// The previous contents of the memory written to during the step, on each bus
+    pub(crate) writes: Vec<(Rc<RefCell<Bus>>, Vec<(Address, Vec<u8>)>)>,
+}
+
+/// A ring buffer of the most recent steps of all devices
+///
+/// Only the state of debuggable devices (cpus) and the contents of memory are restored when rewinding, so
+/// other peripherals keep their current state, and will repeat any steps that are rewound.
+pub(crate) struct StepJournal {
+    pub(crate) entries: VecDeque<JournalEntry>,
+    pub(crate) depth: usize,
+}
+
+impl StepJournal {
+    pub(crate) fn new(depth: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(depth),
+            depth,
+        }
+    }
+
+    pub(crate) fn push(&mut self, entry: JournalEntry) {
+        if self.entries.len() >= self.depth {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+}
@@ -0,0 +1,154 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::{Address, DeviceId, Error};
+
+
+/// What to do when a device is accessed at a register that it doesn't implement
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum StrictMode {
+    /// Only log the access at the debug level
+    Ignore,
+    /// Log a warning, which is what most devices do if no mode has been set
+    #[default]
+    Warn,
+    /// Log an error and stop the system in the debugger after the current step
+    Break,
+}
+
+impl std::str::FromStr for StrictMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" | "off" => Ok(StrictMode::Ignore),
+            "warn" => Ok(StrictMode::Warn),
+            "break" | "on" => Ok(StrictMode::Break),
+            _ => Err(format!("invalid strict mode {}, expected ignore, warn, or break", s)),
+        }
+    }
+}
+
+/// An access that a device reported as unhandled, which is waiting for the system to decide what to do with it
+pub(crate) struct UnhandledAccess {
+    /// The device that was accessed, once it's known
+    device: Option<DeviceId>,
+    /// The name that the device reported the access with, which is used as the log target
+    target: String,
+    message: String,
+    /// The mode to use if none has been set for the device or the system
+    default: StrictMode,
+}
+
+thread_local! {
+    // Devices aren't given the system when they're accessed, so the accesses they report are queued until the bus
+    // that made the access, or the system that stepped the device, can work out which device it was
+    static REPORTS: RefCell<Vec<UnhandledAccess>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Report a read of a register that the device doesn't implement
+pub fn unhandled_read(device: &str, addr: Address) {
+    report(device, StrictMode::Warn, read_message(addr));
+}
+
+/// Report a write to a register that the device doesn't implement
+pub fn unhandled_write(device: &str, addr: Address, data: &[u8]) {
+    report(device, StrictMode::Warn, write_message(addr, data));
+}
+
+/// Report some other use of a device feature that isn't implemented
+pub fn unhandled(device: &str, msg: String) {
+    report(device, StrictMode::Warn, msg);
+}
+
+/// Report an unhandled access that's expected in normal use, such as a driver probing for features that aren't
+/// implemented, which is only logged at the debug level unless a strict mode has been set
+pub fn expected(device: &str, msg: String) {
+    report(device, StrictMode::Ignore, msg);
+}
+
+pub(crate) fn read_message(addr: Address) -> String {
+    format!("unhandled read from {:#x}", addr)
+}
+
+pub(crate) fn write_message(addr: Address, data: &[u8]) -> String {
+    format!("unhandled write of {:02x?} to {:#x}", data, addr)
+}
+
+pub(crate) fn report(device: &str, default: StrictMode, message: String) {
+    REPORTS.with(|reports| {
+        reports.borrow_mut().push(UnhandledAccess {
+            device: None,
+            target: device.to_string(),
+            message,
+            default,
+        })
+    });
+}
+
+/// Attribute the reports that haven't been attributed yet to the given device, which the bus has just accessed
+#[inline]
+pub(crate) fn claim(device: DeviceId) {
+    REPORTS.with(|reports| {
+        for report in reports.borrow_mut().iter_mut().filter(|report| report.device.is_none()) {
+            report.device = Some(device);
+        }
+    });
+}
+
+/// Take the reports that were made on this thread, such as to pass them back from a threaded device
+pub(crate) fn take_reports() -> Vec<UnhandledAccess> {
+    REPORTS.with(|reports| reports.take())
+}
+
+/// Queue reports that were made on another thread, to be handled as if they were made on this one
+pub(crate) fn requeue(mut taken: Vec<UnhandledAccess>) {
+    if !taken.is_empty() {
+        REPORTS.with(|reports| reports.borrow_mut().append(&mut taken));
+    }
+}
+
+/// The strict modes that have been set for a system and its devices
+#[derive(Default)]
+pub(crate) struct StrictSettings {
+    system: Option<StrictMode>,
+    devices: HashMap<DeviceId, StrictMode>,
+}
+
+impl StrictSettings {
+    pub fn set_mode(&mut self, mode: StrictMode) {
+        self.system = Some(mode);
+    }
+
+    pub fn set_device_mode(&mut self, device: DeviceId, mode: Option<StrictMode>) {
+        match mode {
+            Some(mode) => self.devices.insert(device, mode),
+            None => self.devices.remove(&device),
+        };
+    }
+
+    /// Returns the mode that has been set for the device, or for the whole system, if any
+    pub fn mode(&self, device: DeviceId) -> Option<StrictMode> {
+        self.devices.get(&device).copied().or(self.system)
+    }
+
+    /// Log the accesses reported since this was last called, attributing any that weren't made through a bus to
+    /// the given device, and return a breakpoint for the first one that should stop the system
+    pub fn handle_reports(&self, device: DeviceId) -> Result<(), Error> {
+        let mut result = Ok(());
+        for report in take_reports() {
+            let target = report.target.as_str();
+            match self.mode(report.device.unwrap_or(device)).unwrap_or(report.default) {
+                StrictMode::Ignore => log::debug!(target: target, "{}", report.message),
+                StrictMode::Warn => log::warn!(target: target, "!!! {}", report.message),
+                StrictMode::Break => {
+                    log::error!(target: target, "{}", report.message);
+                    if result.is_ok() {
+                        result = Err(Error::breakpoint(format!("{}: {}", target, report.message)));
+                    }
+                },
+            }
+        }
+        result
+    }
+}
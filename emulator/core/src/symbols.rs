@@ -0,0 +1,128 @@
+use std::fs;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::error::Error;
+use crate::devices::Address;
+
+
+/// The names of addresses in the running program, which the debugger uses to show labels and accept
+/// names in place of addresses
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    by_address: BTreeMap<Address, Vec<String>>,
+    by_name: HashMap<String, Address>,
+}
+
+impl SymbolTable {
+    /// Load symbols from a file, which is either the output of GNU `nm` (with or without sizes), or a
+    /// simple list of `addr=name` lines, with hex addresses and `#` comments.  Returns the number loaded
+    pub fn load(&mut self, filename: &str) -> Result<usize, Error> {
+        let contents = fs::read_to_string(filename).map_err(|err| Error::new(format!("error reading {}: {}", filename, err)))?;
+
+        let mut count = 0;
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some((address, name)) =
+                parse_symbol_line(line).map_err(|msg| Error::new(format!("{}:{}: {}", filename, lineno + 1, msg)))?
+            {
+                self.insert(name, address);
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Add a symbol, replacing any existing symbol with the same name
+    pub fn insert(&mut self, name: &str, address: Address) {
+        self.remove(name);
+        self.by_name.insert(name.to_string(), address);
+        self.by_address.entry(address).or_default().push(name.to_string());
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        let Some(address) = self.by_name.remove(name) else {
+            return false;
+        };
+        if let Some(names) = self.by_address.get_mut(&address) {
+            names.retain(|existing| existing != name);
+            if names.is_empty() {
+                self.by_address.remove(&address);
+            }
+        }
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.by_address.clear();
+        self.by_name.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    /// Returns the address of the symbol with the given name
+    pub fn get(&self, name: &str) -> Option<Address> {
+        self.by_name.get(name).copied()
+    }
+
+    /// Returns the first name given to exactly this address
+    pub fn name_at(&self, address: Address) -> Option<&str> {
+        self.by_address
+            .get(&address)
+            .and_then(|names| names.first())
+            .map(|name| name.as_str())
+    }
+
+    /// Returns the closest symbol at or before the address, and the offset of the address from it
+    pub fn nearest(&self, address: Address) -> Option<(&str, Address)> {
+        self.by_address
+            .range(..=address)
+            .next_back()
+            .and_then(|(base, names)| names.first().map(|name| (name.as_str(), address - base)))
+    }
+
+    /// Returns the symbols with addresses from `start` up to but not including `end`, in address order
+    pub fn in_range(&self, start: Address, end: Address) -> impl Iterator<Item = (Address, &str)> {
+        self.by_address
+            .range(start..end.max(start))
+            .flat_map(|(address, names)| names.iter().map(move |name| (*address, name.as_str())))
+    }
+
+    /// Returns the name of the closest symbol before the address with the offset from it, such as `main+0x1a`
+    pub fn describe(&self, address: Address) -> Option<String> {
+        self.nearest(address).map(|(name, offset)| match offset {
+            0 => name.to_string(),
+            _ => format!("{}+{:#x}", name, offset),
+        })
+    }
+}
+
+/// Parse a line of `nm` output or an `addr=name` pair, returning `None` for undefined `nm` symbols
+fn parse_symbol_line(line: &str) -> Result<Option<(Address, &str)>, String> {
+    let parse_hex = |text: &str| {
+        let text = text.trim();
+        let text = text.strip_prefix("0x").unwrap_or(text);
+        Address::from_str_radix(text, 16).map_err(|_| format!("invalid address: {}", text))
+    };
+
+    if let Some((address, name)) = line.split_once('=') {
+        return Ok(Some((parse_hex(address)?, name.trim())));
+    }
+
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    match fields.as_slice() {
+        // Undefined symbols have no address
+        [_kind, _name] => Ok(None),
+        [address, _kind, name] | [address, _, _kind, name] => Ok(Some((parse_hex(address)?, name))),
+        _ => Err(format!("unrecognized symbol line: {}", line)),
+    }
+}
@@ -1,9 +1,19 @@
 use std::rc::Rc;
+use std::any::Any;
 use std::cell::{RefCell, RefMut};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time;
 use femtos::{Instant, Duration};
 
-use crate::{Bus, Error, InterruptController, Address, Device};
+use crate::{Bus, Error, InterruptController, Address, Device, DeviceId, MachineInfo, Coverage, AutoSave, Capabilities, SymbolTable};
+use crate::autosave;
+use crate::interrupts::InterruptState;
+use crate::rewind::{StepJournal, JournalEntry};
+use crate::strict::{StrictMode, StrictSettings};
+use crate::trace::{InstructionTrace, TraceFormat, TraceStart};
+use crate::wakeup::{WakeupQueue, WakeupQueueState};
+use crate::writelog::WriteLog;
 
 
 pub struct System {
@@ -16,6 +26,25 @@ pub struct System {
     pub bus: Rc<RefCell<Bus>>,
     pub buses: HashMap<String, Rc<RefCell<Bus>>>,
     pub interrupt_controller: RefCell<InterruptController>,
+
+    /// The number of steps and the real time taken by each device, if profiling has been enabled
+    pub profile: Option<HashMap<DeviceId, DeviceProfile>>,
+
+    /// The description of the machine, which is set by the function that built it
+    pub machine_info: MachineInfo,
+
+    /// The battery-backed memory, and optionally a snapshot, to save when the program exits
+    pub autosave: AutoSave,
+
+    /// The names of addresses in the running program, used by the debugger
+    pub symbols: SymbolTable,
+
+    wakeups: WakeupQueue,
+    next_step_order: usize,
+    interleave: Interleave,
+    journal: Option<StepJournal>,
+    trace: Option<InstructionTrace>,
+    strict: StrictSettings,
 }
 
 impl Default for System {
@@ -30,6 +59,21 @@ impl Default for System {
             bus: Rc::new(RefCell::new(Bus::default())),
             buses: HashMap::new(),
             interrupt_controller: RefCell::new(InterruptController::default()),
+
+            profile: None,
+
+            machine_info: MachineInfo::default(),
+
+            autosave: AutoSave::default(),
+
+            symbols: SymbolTable::default(),
+
+            wakeups: WakeupQueue::default(),
+            next_step_order: 0,
+            interleave: Interleave::default(),
+            journal: None,
+            trace: None,
+            strict: StrictSettings::default(),
         }
     }
 }
@@ -43,6 +87,11 @@ impl System {
         self.interrupt_controller.borrow_mut()
     }
 
+    /// Start recording how many times each device is stepped, and how much real time it takes
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(HashMap::new());
+    }
+
     pub fn get_device(&self, name: &str) -> Result<Device, Error> {
         self.devices
             .get(name)
@@ -50,6 +99,25 @@ impl System {
             .ok_or_else(|| Error::new(format!("system: no device named {}", name)))
     }
 
+    pub fn get_device_by_id(&self, id: DeviceId) -> Option<(&str, Device)> {
+        self.devices
+            .iter()
+            .find(|(_, device)| device.id() == id)
+            .map(|(name, device)| (name.as_str(), device.clone()))
+    }
+
+    /// Returns the devices that have all of the given capabilities, sorted by name
+    pub fn devices_with(&self, capabilities: Capabilities) -> Vec<(&str, Device)> {
+        let mut devices: Vec<(&str, Device)> = self
+            .devices
+            .iter()
+            .filter(|(_, device)| device.has(capabilities))
+            .map(|(name, device)| (name.as_str(), device.clone()))
+            .collect();
+        devices.sort_by_key(|(name, _)| *name);
+        devices
+    }
+
     pub fn add_device(&mut self, name: &str, device: Device) -> Result<(), Error> {
         self.try_add_debuggable(device.clone());
         self.try_queue_device(device.clone());
@@ -76,23 +144,531 @@ impl System {
         Ok(())
     }
 
-    fn process_one_event(&mut self) -> Result<(), Error> {
+    /// Start recording the writes to the named device from every bus it's mapped on, and return the log
+    pub fn log_device_writes(&self, name: &str) -> Result<WriteLog, Error> {
+        let device = self.get_device(name)?;
+        let log = WriteLog::default();
+        self.bus.borrow_mut().log_writes(device.id(), log.clone());
+        for bus in self.buses.values() {
+            bus.borrow_mut().log_writes(device.id(), log.clone());
+        }
+        Ok(log)
+    }
+
+    /// Add a bus other than the system bus, such as one that a coprocessor has to itself, so that the devices on it
+    /// are included when validating the machine, rewinding, and saving its state
+    pub fn add_bus(&mut self, name: &str, bus: Rc<RefCell<Bus>>) {
+        self.buses.insert(name.to_string(), bus);
+    }
+
+    /// Load and then save the contents of a battery-backed memory device, such as cartridge SRAM, to the given file
+    pub fn add_battery_backed_memory(&mut self, filename: &str, device: Device) -> Result<(), Error> {
+        self.autosave.add_memory(filename, device)
+    }
+
+    /// Save the contents of all the memory on the system bus to the given file when the program exits
+    pub fn save_snapshot_on_exit(&mut self, filename: &str) {
+        self.autosave.set_snapshot(Some(filename), self.bus.clone());
+    }
+
+    /// Restore the contents of the memory on the system bus from a snapshot saved on exit
+    pub fn restore_snapshot(&self, filename: &str) -> Result<(), Error> {
+        autosave::restore_snapshot(filename, &mut self.bus.borrow_mut(), self.clock)
+    }
+
+    /// Returns a snapshot of the contents of all the memory on the system bus, for frontends that keep it
+    /// somewhere other than a file
+    pub fn memory_snapshot(&self) -> Result<Vec<u8>, Error> {
+        autosave::snapshot_contents(&self.bus.borrow())
+    }
+
+    /// Restore the contents of the memory on the system bus from a snapshot returned by `memory_snapshot`
+    pub fn restore_memory_snapshot(&self, snapshot: &[u8]) -> Result<(), Error> {
+        autosave::restore_snapshot_contents("snapshot", snapshot, &mut self.bus.borrow_mut(), self.clock)
+    }
+
+    /// Count the reads, writes, and instructions executed at each address of the system bus
+    pub fn record_coverage(&self) -> Coverage {
+        let coverage = Coverage::default();
+        self.bus.borrow_mut().record_coverage(coverage.clone());
+        coverage
+    }
+
+    pub fn stop_recording_coverage(&self) {
+        self.bus.borrow_mut().stop_recording_coverage();
+    }
+
+    /// Returns a coverage report for each device on the system bus, using the names the devices were added with
+    pub fn coverage_report(&self, coverage: &Coverage) -> String {
+        let regions = self
+            .bus
+            .borrow()
+            .blocks()
+            .iter()
+            .map(|block| {
+                let name = self
+                    .get_device_by_id(block.dev.id())
+                    .map(|(name, _)| name.to_string())
+                    .unwrap_or_else(|| format!("{:x}", block.base));
+                (name, block.base, block.size)
+            })
+            .collect::<Vec<_>>();
+        coverage.report(&regions)
+    }
+
+    pub fn stop_device_write_log(&self, name: &str) -> Result<(), Error> {
+        let device = self.get_device(name)?;
+        self.bus.borrow_mut().stop_logging_writes(device.id());
+        for bus in self.buses.values() {
+            bus.borrow_mut().stop_logging_writes(device.id());
+        }
+        Ok(())
+    }
+
+    /// Set the order that the device is stepped in relative to other devices scheduled at the same time.  By
+    /// default, debuggable devices like CPUs have `StepPriority::CPU` and all others have `StepPriority::DEFAULT`
+    pub fn set_step_priority(&mut self, device: &Device, priority: StepPriority) -> Result<(), Error> {
+        let index = self
+            .event_queue
+            .iter()
+            .position(|event| event.device.id() == device.id())
+            .ok_or_else(|| Error::new(format!("system: {} is not a steppable device", self.device_name(device))))?;
+        let mut event_device = self.event_queue.remove(index);
+        event_device.priority = priority;
+        self.queue_device(event_device);
+        Ok(())
+    }
+
+    /// Set how far a device can run ahead of the others when running the simulation, which trades the accuracy of
+    /// the interactions between devices for speed.  Single steps, such as in the debugger, are always exact
+    pub fn set_interleave(&mut self, interleave: Interleave) {
+        self.interleave = interleave;
+    }
+
+    pub fn interleave(&self) -> Interleave {
+        self.interleave
+    }
+
+    /// Set what to do when any device is accessed at a register that it doesn't implement, in place of each
+    /// device's default, which is to log a warning for most devices
+    pub fn set_strict_mode(&mut self, mode: StrictMode) {
+        self.strict.set_mode(mode);
+    }
+
+    /// Set the strict mode of one device, or use the system's mode for it again if `mode` is `None`
+    pub fn set_device_strict_mode(&mut self, device: DeviceId, mode: Option<StrictMode>) {
+        self.strict.set_device_mode(device, mode);
+    }
+
+    /// Returns the strict mode that has been set for the device, or for the whole system, if any
+    pub fn strict_mode(&self, device: DeviceId) -> Option<StrictMode> {
+        self.strict.mode(device)
+    }
+
+    /// Check the machine for configuration problems without running it, such as overlapping devices on a bus,
+    /// no devices to receive interrupts, or any device failing its own self check
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+
+        let buses = Some(("main", &self.bus))
+            .into_iter()
+            .chain(self.buses.iter().map(|(name, bus)| (name.as_str(), bus)));
+        for (bus_name, bus) in buses {
+            for (first, second) in bus.borrow().overlapping_blocks() {
+                report.errors.push(format!(
+                    "{} bus: {} at {:#010x}-{:#010x} overlaps {} at {:#010x}-{:#010x}",
+                    bus_name,
+                    self.device_name(&first.dev),
+                    first.base,
+                    first.base + first.size as Address - 1,
+                    self.device_name(&second.dev),
+                    second.base,
+                    second.base + second.size as Address - 1,
+                ));
+            }
+        }
+
+        if self.event_queue.is_empty() {
+            report
+                .errors
+                .push("no devices can be stepped, so the simulation can't run".to_string());
+        }
+
+        let mut names: Vec<&String> = self.devices.keys().collect();
+        names.sort();
+        if !names.iter().any(|name| self.devices[*name].has(Capabilities::INTERRUPTABLE)) {
+            report
+                .warnings
+                .push("no devices are connected to receive interrupts".to_string());
+        }
+
+        for name in names {
+            report.devices += 1;
+            if let Err(err) = self.devices[name].borrow_mut().self_check(self) {
+                report.errors.push(format!("{}: {}", name, err));
+            }
+        }
+
+        report
+    }
+
+    fn device_name(&self, device: &Device) -> &str {
+        self.devices
+            .iter()
+            .find(|(_, dev)| dev.id() == device.id())
+            .map(|(name, _)| name.as_str())
+            .unwrap_or("(unnamed)")
+    }
+
+    /// Start recording each step, so that up to `depth` of the most recent steps can be undone with `rewind()`
+    pub fn enable_rewind(&mut self, depth: usize) {
+        self.journal = Some(StepJournal::new(depth));
+        for bus in self.all_buses() {
+            bus.borrow_mut().start_undo_journal();
+        }
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.journal = None;
+        for bus in self.all_buses() {
+            bus.borrow_mut().stop_undo_journal();
+        }
+    }
+
+    /// Returns the number of steps that can be undone for the given device, or for all debuggable devices
+    pub fn rewindable_steps(&self, device: Option<&Device>) -> usize {
+        self.journal
+            .as_ref()
+            .map(|journal| {
+                journal
+                    .entries
+                    .iter()
+                    .filter(|entry| entry.state.is_some() && device.map(|device| device.id() == entry.device.id()).unwrap_or(true))
+                    .count()
+            })
+            .unwrap_or(0)
+    }
+
+    /// Undo the most recent steps until `count` steps of the given device have been undone, or of any
+    /// debuggable device if `device` is `None`, returning the number of steps of that device that were undone
+    ///
+    /// The memory written to and the state of the debuggable devices are restored, and the steps of all the
+    /// devices will be taken again, but the other state of peripherals isn't restored.
+    pub fn rewind(&mut self, device: Option<&Device>, count: usize) -> Result<usize, Error> {
+        let mut rewound = 0;
+        while rewound < count {
+            let entry = match self.journal.as_mut().and_then(|journal| journal.entries.pop_back()) {
+                Some(entry) => entry,
+                None => break,
+            };
+
+            for (bus, writes) in entry.writes.iter().rev() {
+                bus.borrow_mut().undo_writes(entry.clock, writes)?;
+            }
+            if let Some(state) = entry.state.as_ref() {
+                if let Some(snapshotable) = entry.device.borrow_mut().as_snapshotable() {
+                    snapshotable.restore_state(state.as_ref());
+                }
+                if device.map(|device| device.id() == entry.device.id()).unwrap_or(true) {
+                    rewound += 1;
+                }
+            }
+
+            if let Some(index) = self
+                .event_queue
+                .iter()
+                .position(|event| event.device.id() == entry.device.id())
+            {
+                let mut event_device = self.event_queue.remove(index);
+                event_device.next_clock = entry.clock;
+                self.queue_device(event_device);
+            }
+            self.clock = entry.clock;
+        }
+        Ok(rewound)
+    }
+
+    /// Save the state of every device that can be saved, along with the clock and when each device will be stepped
+    /// next, so that the system can go back to this point with `restore_state()`
+    pub fn save_state(&self) -> SystemState {
+        let devices = self
+            .all_devices()
+            .into_iter()
+            .filter(|device| device.has(Capabilities::SNAPSHOTABLE))
+            .filter_map(|device| {
+                let state = device.borrow_mut().as_snapshotable()?.save_state()?;
+                Some((device, state))
+            })
+            .collect();
+
+        SystemState {
+            clock: self.clock,
+            devices,
+            next_steps: self
+                .event_queue
+                .iter()
+                .map(|event| (event.device.id(), event.next_clock))
+                .collect(),
+            interrupts: self.interrupt_controller.borrow().save_state(),
+            wakeups: self.wakeups.save_state(),
+        }
+    }
+
+    /// Go back to the point where the given state was saved
+    ///
+    /// Devices that can't save their state keep their current state, and any steps that could be rewound are
+    /// forgotten, since they're no longer in the past.
+    pub fn restore_state(&mut self, state: &SystemState) {
+        for (device, saved) in state.devices.iter() {
+            if let Some(snapshotable) = device.borrow_mut().as_snapshotable() {
+                snapshotable.restore_state(saved.as_ref());
+            }
+        }
+
+        for (id, next_clock) in state.next_steps.iter() {
+            if let Some(index) = self.event_queue.iter().position(|event| event.device.id() == *id) {
+                let mut event_device = self.event_queue.remove(index);
+                event_device.next_clock = *next_clock;
+                self.queue_device(event_device);
+            }
+        }
+
+        self.interrupt_controller.borrow_mut().restore_state(&state.interrupts);
+        self.wakeups.restore_state(&state.wakeups);
+        if let Some(journal) = self.journal.as_mut() {
+            journal.entries.clear();
+        }
+        self.clock = state.clock;
+    }
+
+    /// Returns the devices that have been added to the system, and the devices mapped on any of its buses, once each
+    fn all_devices(&self) -> Vec<Device> {
+        let mut seen = HashSet::new();
+        let mut devices: Vec<Device> = self.devices.values().cloned().collect();
+        for bus in self.all_buses() {
+            devices.extend(bus.borrow().blocks().iter().map(|block| block.dev.clone()));
+        }
+        devices.retain(|device| seen.insert(device.id()));
+        devices
+    }
+
+    fn all_buses(&self) -> Vec<Rc<RefCell<Bus>>> {
+        let mut buses = vec![self.bus.clone()];
+        buses.extend(self.buses.values().filter(|bus| !Rc::ptr_eq(bus, &self.bus)).cloned());
+        buses
+    }
+
+    fn start_journal_entry(&self, device: &Device) -> Option<Box<dyn Any>> {
+        // Any writes since the last step were made outside of a step, such as by the debugger, and are kept
+        for bus in self.all_buses() {
+            bus.borrow_mut().take_undo_journal();
+        }
+        if !device.has(Capabilities::DEBUGGABLE) {
+            return None;
+        }
+        device
+            .borrow_mut()
+            .as_snapshotable()
+            .and_then(|snapshotable| snapshotable.save_state())
+    }
+
+    fn finish_journal_entry(&mut self, device: &Device, state: Option<Box<dyn Any>>, completed: bool) {
+        let writes: Vec<_> = self
+            .all_buses()
+            .into_iter()
+            .map(|bus| {
+                let writes = bus.borrow_mut().take_undo_journal();
+                (bus, writes)
+            })
+            .filter(|(_, writes)| !writes.is_empty())
+            .collect();
+
+        // A step that stopped at a breakpoint before doing anything doesn't need to be undone
+        if !completed && writes.is_empty() {
+            return;
+        }
+
+        let entry = JournalEntry {
+            device: device.clone(),
+            clock: self.clock,
+            state,
+            writes,
+        };
+        if let Some(journal) = self.journal.as_mut() {
+            journal.push(entry);
+        }
+    }
+
+    /// Record which device is about to make accesses on each bus, so that watchpoints can report it
+    fn set_access_device(&self, device: Option<DeviceId>) {
+        self.bus.borrow_mut().set_access_device(device);
+        for bus in self.buses.values() {
+            bus.borrow_mut().set_access_device(device);
+        }
+    }
+
+    /// Move any devices that have been woken up to the front of the queue, to be stepped at the current clock
+    fn process_wakeups(&mut self) {
+        for id in self.wakeups.take() {
+            let index = self.event_queue.iter().position(|event| event.device.id() == id);
+            if let Some(index) = index {
+                if self.event_queue[index].next_clock > self.clock {
+                    let mut event_device = self.event_queue.remove(index);
+                    event_device.next_clock = self.clock;
+                    self.queue_device(event_device);
+                }
+            }
+        }
+    }
+
+    /// Run the next timer if it expires before the next device is stepped, returning false if there isn't one
+    fn process_timer(&mut self) -> Result<bool, Error> {
+        let next_clock = self
+            .event_queue
+            .last()
+            .map(|event| event.next_clock)
+            .unwrap_or(Instant::FOREVER);
+        let Some((when, timer, id)) = self.wakeups.take_timer_before(next_clock) else {
+            return Ok(false);
+        };
+        if when > self.clock {
+            self.clock = when;
+        }
+
+        let Some((_, device)) = self.get_device_by_id(id) else {
+            return Ok(true);
+        };
+        self.set_access_device(Some(id));
+        let result = device.borrow_mut().as_steppable().unwrap().on_timer(self, timer);
+        let unhandled = self.strict.handle_reports(id);
+        let result = result.and_then(|_| unhandled);
+        match result {
+            Err(Error::Breakpoint(mut info)) => {
+                info.device.get_or_insert(id);
+                Err(Error::Breakpoint(info))
+            },
+            result => result.map(|_| true),
+        }
+    }
+
+    /// Step the next device, and if running in slices until `run_until`, keep stepping it until its slice is used up
+    fn process_one_event(&mut self, run_until: Option<Instant>) -> Result<(), Error> {
+        self.process_wakeups();
+        // Timers are run along with the step that follows them, so that each event still steps one device
+        while self.process_timer()? {}
         let mut event_device = self.event_queue.pop().unwrap();
+        let mut result = self.step_device(&mut event_device);
+
+        if let (Interleave::Slice(slice), Some(run_until)) = (self.interleave, run_until) {
+            // Nothing is rolled back when the other devices catch up, so their effects on this device are delayed
+            // instead.  The slice also ends early for timers, wakeups, and errors, so those are never skipped over
+            let limit = self
+                .event_queue
+                .last()
+                .and_then(|event| event.next_clock.checked_add(slice))
+                .unwrap_or(Instant::FOREVER)
+                .min(run_until)
+                .min(self.wakeups.next_timer().unwrap_or(Instant::FOREVER));
+            while result.is_ok() && event_device.next_clock < limit && !self.wakeups.is_pending() {
+                result = self.step_device(&mut event_device);
+            }
+        }
+
+        self.queue_device(event_device);
+        result
+    }
+
+    fn step_device(&mut self, event_device: &mut NextStep) -> Result<(), Error> {
         self.clock = event_device.next_clock;
-        let result = match event_device.device.borrow_mut().as_steppable().unwrap().step(self) {
-            Ok(diff) => {
-                event_device.next_clock = self.clock.checked_add(diff).unwrap();
-                Ok(())
+        self.set_access_device(Some(event_device.device.id()));
+        let saved_state = self.journal.as_ref().map(|_| self.start_journal_entry(&event_device.device));
+        let trace_start = self.trace.as_ref().and_then(|trace| {
+            let mut device = event_device.device.borrow_mut();
+            trace.start_step(self, device.as_debuggable()?)
+        });
+        let started = self.profile.as_ref().map(|_| time::Instant::now());
+        let result = event_device.device.borrow_mut().as_steppable().unwrap().step(self);
+        if let Some(start) = trace_start {
+            self.finish_trace_step(&event_device.device, start);
+        }
+        if let Some(state) = saved_state {
+            self.finish_journal_entry(&event_device.device, state, result.is_ok());
+        }
+        if let (Some(profile), Some(started)) = (self.profile.as_mut(), started) {
+            let entry = profile.entry(event_device.device.id()).or_default();
+            entry.steps += 1;
+            entry.elapsed += started.elapsed();
+        }
+
+        let unhandled = self.strict.handle_reports(event_device.device.id());
+        if let Ok(diff) = &result {
+            event_device.next_clock = self.clock.checked_add(*diff).unwrap();
+        }
+        // A write to a watched address is reported even if the step failed because of it, such as when an unmapped
+        // or read-only address is used to signal the host, since the value was still written by the device
+        let result = match (result, self.bus.borrow_mut().take_watcher_modified()) {
+            (result, Some((index, addr, context, value))) => {
+                if let Err(err) = result {
+                    log::warn!("{}", err);
+                }
+                Err(Error::watchpoint(addr, Some(index), context.pc, Some(value)))
             },
-            Err(err) => Err(err),
+            (Ok(_), None) => unhandled,
+            (Err(err), None) => Err(err),
         };
-        self.queue_device(event_device);
-        result
+
+        // Record which device caused the breakpoint so that the debugger can switch to it
+        match result {
+            Err(Error::Breakpoint(mut info)) => {
+                info.device.get_or_insert(event_device.device.id());
+                Err(Error::Breakpoint(info))
+            },
+            result => result,
+        }
+    }
+
+    /// Write each instruction executed by the debuggable devices to a file, until `stop_trace()` is called
+    pub fn start_trace(&mut self, filename: &str, format: TraceFormat) -> Result<(), Error> {
+        self.stop_trace()?;
+        self.trace = Some(InstructionTrace::create(filename, format)?);
+        Ok(())
+    }
+
+    /// Stop tracing instructions, and return the number that were written to the file
+    pub fn stop_trace(&mut self) -> Result<usize, Error> {
+        match self.trace.take() {
+            Some(mut trace) => {
+                trace.flush()?;
+                Ok(trace.count())
+            },
+            None => Ok(0),
+        }
+    }
+
+    fn finish_trace_step(&mut self, device: &Device, start: TraceStart) {
+        let name = self
+            .get_device_by_id(device.id())
+            .map(|(name, _)| name.to_string())
+            .unwrap_or_default();
+        let result = match (self.trace.as_mut(), device.borrow_mut().as_debuggable()) {
+            (Some(trace), Some(debuggable)) => trace.finish_step(self.clock, device.id(), &name, debuggable, start),
+            _ => Ok(()),
+        };
+
+        // The trace is stopped if it can't be written, rather than stopping the simulation
+        if let Err(err) = result {
+            log::error!("{:?}", err);
+            self.trace = None;
+        }
     }
 
     /// Step the simulation one event exactly
     pub fn step(&mut self) -> Result<(), Error> {
-        match self.process_one_event() {
+        self.step_event(None)
+    }
+
+    fn step_event(&mut self, run_until: Option<Instant>) -> Result<(), Error> {
+        match self.process_one_event(run_until) {
             Ok(()) => {},
             Err(err @ Error::Breakpoint(_)) => {
                 return Err(err);
@@ -123,7 +699,7 @@ impl System {
         loop {
             self.step()?;
 
-            if self.get_next_event_device().borrow_mut().as_debuggable().is_some() {
+            if self.get_next_event_device().has(Capabilities::DEBUGGABLE) {
                 break;
             }
         }
@@ -133,7 +709,7 @@ impl System {
     /// Run the simulation until the given simulation clock time has been reached
     pub fn run_until_clock(&mut self, clock: Instant) -> Result<(), Error> {
         while self.clock < clock {
-            self.step()?;
+            self.step_event(Some(clock))?;
         }
         Ok(())
     }
@@ -143,7 +719,7 @@ impl System {
         let target = self.clock + elapsed;
 
         while self.clock < target {
-            self.step()?;
+            self.step_event(Some(target))?;
         }
         Ok(())
     }
@@ -167,7 +743,7 @@ impl System {
 
     pub fn get_next_debuggable_device(&self) -> Option<Device> {
         for event in self.event_queue.iter().rev() {
-            if event.device.borrow_mut().as_debuggable().is_some() {
+            if event.device.has(Capabilities::DEBUGGABLE) {
                 return Some(event.device.clone());
             }
         }
@@ -175,20 +751,36 @@ impl System {
     }
 
     fn try_add_debuggable(&mut self, device: Device) {
-        if device.borrow_mut().as_debuggable().is_some() {
+        if device.has(Capabilities::DEBUGGABLE) {
             self.debuggables.push(device);
         }
     }
 
     fn try_queue_device(&mut self, device: Device) {
-        if device.borrow_mut().as_steppable().is_some() {
-            self.queue_device(NextStep::new(device));
+        let is_steppable = match device.borrow_mut().as_steppable() {
+            Some(steppable) => {
+                steppable.set_waker(self.wakeups.waker(device.id()));
+                true
+            },
+            None => false,
+        };
+
+        if is_steppable {
+            let mut event_device = NextStep::new(device);
+            if event_device.device.has(Capabilities::DEBUGGABLE) {
+                event_device.priority = StepPriority::CPU;
+            }
+            event_device.order = self.next_step_order;
+            self.next_step_order += 1;
+            self.queue_device(event_device);
         }
     }
 
+    /// Insert the device into the queue, which is sorted so that the last event is the next to run.  Devices
+    /// scheduled at the same time run in order of priority, and then in the order they were added to the system
     fn queue_device(&mut self, device_step: NextStep) {
         for (i, event) in self.event_queue.iter().enumerate().rev() {
-            if event.next_clock > device_step.next_clock {
+            if event.sort_key() > device_step.sort_key() {
                 self.event_queue.insert(i + 1, device_step);
                 return;
             }
@@ -198,9 +790,100 @@ impl System {
 }
 
 
+/// How the steps of the devices are interleaved when running the simulation
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Interleave {
+    /// Always step the device that is furthest behind, which is one instruction at a time for CPUs
+    #[default]
+    Exact,
+    /// Keep stepping the same device until it is up to the given time ahead of the next device, which is faster
+    /// but can delay the effects that devices have on each other, such as interrupts, by up to that much
+    Slice(Duration),
+}
+
+/// The number of times a device was stepped and the real time it took, which is recorded by `System` when profiling
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DeviceProfile {
+    pub steps: u64,
+    pub elapsed: time::Duration,
+}
+
+/// The results of checking a machine with `System::validate`
+#[derive(Clone, Debug, Default)]
+pub struct ValidationReport {
+    pub devices: usize,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for error in self.errors.iter() {
+            writeln!(f, "error: {}", error)?;
+        }
+        for warning in self.warnings.iter() {
+            writeln!(f, "warning: {}", warning)?;
+        }
+        write!(
+            f,
+            "checked {} devices: {} errors, {} warnings",
+            self.devices,
+            self.errors.len(),
+            self.warnings.len()
+        )
+    }
+}
+
+
+/// The state of a system at one point in time, which is saved with `System::save_state()`
+pub struct SystemState {
+    clock: Instant,
+    devices: Vec<(Device, Box<dyn Any>)>,
+    next_steps: Vec<(DeviceId, Instant)>,
+    interrupts: InterruptState,
+    wakeups: WakeupQueueState,
+}
+
+impl SystemState {
+    /// Returns the system's clock when the state was saved
+    pub fn clock(&self) -> Instant {
+        self.clock
+    }
+}
+
+
+/// The order that devices scheduled at the same time are stepped in, from lowest to highest
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StepPriority(pub i16);
+
+impl StepPriority {
+    pub const CPU: StepPriority = StepPriority(0);
+    pub const VIDEO: StepPriority = StepPriority(100);
+    pub const DEFAULT: StepPriority = StepPriority(200);
+    pub const AUDIO: StepPriority = StepPriority(300);
+    /// For devices that use the output of the other devices, such as an audio mixer, which has to run after the
+    /// audio devices have produced their samples
+    pub const OUTPUT: StepPriority = StepPriority(400);
+}
+
+impl Default for StepPriority {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
 pub struct NextStep {
     pub next_clock: Instant,
     pub device: Device,
+    pub priority: StepPriority,
+    /// The order the device was added to the system in, to break ties between devices with the same priority
+    pub order: usize,
 }
 
 impl NextStep {
@@ -208,8 +891,15 @@ impl NextStep {
         Self {
             next_clock: Instant::START,
             device,
+            priority: StepPriority::DEFAULT,
+            order: 0,
         }
     }
+
+    #[inline]
+    fn sort_key(&self) -> (Instant, StepPriority, usize) {
+        (self.next_clock, self.priority, self.order)
+    }
 }
 
 /*
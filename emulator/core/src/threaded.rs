@@ -0,0 +1,209 @@
+use std::any::Any;
+use std::thread;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use femtos::{Instant, Duration};
+
+use crate::{Error, System, Address, Addressable, Steppable, ParallelSteppable, Snapshotable, Transmutable, Capabilities};
+use crate::strict::{self, UnhandledAccess};
+
+
+/// A device that's stepped on a thread of its own, in parallel with the rest of the system
+///
+/// When the system steps it, the step is started on the device's thread and the system carries on with the other
+/// devices.  The step is finished before the device is accessed in any other way, such as by a cpu writing to it or
+/// by its next step, so the device sees the same steps and accesses in the same order as it would if it weren't
+/// threaded.  Since the system has to know when to step it next before the step finishes, each step is followed by
+/// the interval that the previous step returned, so the device must step at a fixed rate, like a sound chip.
+pub struct ThreadedDevice<T> {
+    device: Arc<Mutex<T>>,
+    capabilities: Capabilities,
+    /// The size of the device's address space, which is found when it's created so that it can be returned
+    /// without waiting for a running step
+    size: usize,
+    /// The interval returned by the last step that finished
+    interval: Option<Duration>,
+    /// An error returned by a step that ran on the device's thread, which is returned from the next step
+    error: Option<Error>,
+    running: bool,
+    requests: Option<mpsc::Sender<Instant>>,
+    /// The result of each step, and the unhandled accesses it reported, which are handled on the system's thread
+    results: mpsc::Receiver<(Result<Duration, Error>, Vec<UnhandledAccess>)>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl<T> ThreadedDevice<T>
+where
+    T: Transmutable + ParallelSteppable + 'static,
+{
+    pub fn new(mut device: T) -> Self {
+        let capabilities = device.capabilities() | Capabilities::STEPPABLE | Capabilities::THREADED;
+        let size = device.as_addressable().map(|device| device.size()).unwrap_or(0);
+        let device = Arc::new(Mutex::new(device));
+        let (requests, receiver) = mpsc::channel::<Instant>();
+        let (sender, results) = mpsc::channel();
+
+        let thread = {
+            let device = device.clone();
+            thread::spawn(move || {
+                for clock in receiver {
+                    let result = device.lock().unwrap().step_at(clock);
+                    if sender.send((result, strict::take_reports())).is_err() {
+                        break;
+                    }
+                }
+            })
+        };
+
+        Self {
+            device,
+            capabilities,
+            size,
+            interval: None,
+            error: None,
+            running: false,
+            requests: Some(requests),
+            results,
+            thread: Some(thread),
+        }
+    }
+
+    /// Wait for the step running on the device's thread, if there is one, to finish
+    fn finish_step(&mut self) {
+        if !self.running {
+            return;
+        }
+        self.running = false;
+        match self.results.recv() {
+            Ok((result, reports)) => {
+                strict::requeue(reports);
+                match (result, self.interval) {
+                    (Ok(interval), Some(previous)) if interval != previous => {
+                        self.error = Some(Error::new(format!(
+                            "a threaded device must step at a fixed interval, but it changed from {:?} to {:?}",
+                            previous, interval
+                        )));
+                    },
+                    (Ok(interval), _) => self.interval = Some(interval),
+                    (Err(err), _) => self.error = Some(err),
+                }
+            },
+            Err(_) => self.error = Some(Error::new("the thread of a threaded device has stopped")),
+        }
+    }
+
+    /// Finish the running step, and lock the device to access it
+    fn lock(&mut self) -> MutexGuard<'_, T> {
+        self.finish_step();
+        self.device.lock().unwrap()
+    }
+}
+
+impl<T> Steppable for ThreadedDevice<T>
+where
+    T: Transmutable + ParallelSteppable + 'static,
+{
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        self.finish_step();
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        let Some(interval) = self.interval else {
+            // The first step is run on this thread, to find out how long it'll be until the next one
+            let interval = self.lock().step_at(system.clock)?;
+            self.interval = Some(interval);
+            return Ok(interval);
+        };
+
+        match self.requests.as_ref().map(|requests| requests.send(system.clock)) {
+            Some(Ok(())) => {
+                self.running = true;
+                Ok(interval)
+            },
+            _ => Err(Error::new("the thread of a threaded device has stopped")),
+        }
+    }
+
+    fn on_error(&mut self, system: &System) {
+        if let Some(device) = self.lock().as_steppable() {
+            device.on_error(system);
+        }
+    }
+}
+
+impl<T> Addressable for ThreadedDevice<T>
+where
+    T: Transmutable + ParallelSteppable + 'static,
+{
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        match self.lock().as_addressable() {
+            Some(device) => device.read(clock, addr, data),
+            None => Err(Error::new("threaded device isn't addressable")),
+        }
+    }
+
+    fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        match self.lock().as_addressable() {
+            Some(device) => device.write(clock, addr, data),
+            None => Err(Error::new("threaded device isn't addressable")),
+        }
+    }
+}
+
+impl<T> Snapshotable for ThreadedDevice<T>
+where
+    T: Transmutable + ParallelSteppable + 'static,
+{
+    fn save_state(&mut self) -> Option<Box<dyn Any>> {
+        self.lock().as_snapshotable()?.save_state()
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        if let Some(device) = self.lock().as_snapshotable() {
+            device.restore_state(state);
+        }
+    }
+}
+
+impl<T> Transmutable for ThreadedDevice<T>
+where
+    T: Transmutable + ParallelSteppable + 'static,
+{
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        if self.capabilities.contains(Capabilities::ADDRESSABLE) {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+    fn as_snapshotable(&mut self) -> Option<&mut dyn Snapshotable> {
+        if self.capabilities.contains(Capabilities::SNAPSHOTABLE) {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+    fn capabilities(&mut self) -> Capabilities {
+        self.capabilities
+    }
+}
+
+impl<T> Drop for ThreadedDevice<T> {
+    fn drop(&mut self) {
+        // Closing the channel stops the thread once it's finished the step it's running
+        self.requests.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::fs::File;
+use std::str::FromStr;
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use femtos::Instant;
+
+use crate::{System, Error, Address, DeviceId, Debuggable};
+
+
+/// The magic number at the start of a binary trace file, which is followed by a version byte
+pub const TRACE_MAGIC: &[u8; 8] = b"MOATRACE";
+pub const TRACE_VERSION: u8 = 1;
+
+// The types of the records in a binary trace
+const RECORD_DEVICE: u8 = 0x00;
+const RECORD_STEP: u8 = 0x01;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One line per instruction, with the time in nanoseconds, the device name, the address and disassembly of the
+    /// instruction, and the registers that it changed
+    #[default]
+    Text,
+    /// A record for each instruction, with the time, address, and the index and new value of each changed register,
+    /// preceded by a record for each device with the names of its registers
+    ///
+    /// All values are little endian.  A device record is the type byte 0, then the u32 device number, and then the
+    /// device name and each register name, with each name as a u8 length followed by the bytes, and an empty name
+    /// ending the list.  A step record is the type byte 1, the u32 device number, the u64 time in nanoseconds, the
+    /// u64 address, the u8 length and the bytes of the instruction, the u8 number of changed registers, and then
+    /// for each one a u8 register index and the u64 value.
+    Binary,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(TraceFormat::Text),
+            "binary" | "bin" => Ok(TraceFormat::Binary),
+            _ => Err(format!("invalid trace format {:?}, expected text or binary", s)),
+        }
+    }
+}
+
+/// The state of a device before a step, which is compared to its state after the step
+pub(crate) struct TraceStart {
+    pc: Address,
+    disassembly: Option<(String, Vec<u8>)>,
+    registers: Vec<u64>,
+}
+
+/// A writer for the instructions executed by each debuggable device, for long runs where printing each step
+/// to the console would be too slow to read
+pub(crate) struct InstructionTrace {
+    format: TraceFormat,
+    output: BufWriter<File>,
+    filename: String,
+    /// The number used for each device in a binary trace, and the names of its registers
+    devices: HashMap<DeviceId, (u32, Vec<&'static str>)>,
+    count: usize,
+}
+
+impl InstructionTrace {
+    pub(crate) fn create(filename: &str, format: TraceFormat) -> Result<Self, Error> {
+        let file = File::create(filename).map_err(|err| Error::new(format!("error creating {}: {}", filename, err)))?;
+        let mut trace = Self {
+            format,
+            output: BufWriter::new(file),
+            filename: filename.to_string(),
+            devices: HashMap::new(),
+            count: 0,
+        };
+        if format == TraceFormat::Binary {
+            trace.write(TRACE_MAGIC)?;
+            trace.write(&[TRACE_VERSION])?;
+        }
+        Ok(trace)
+    }
+
+    /// Returns the number of instructions that have been written
+    pub(crate) fn count(&self) -> usize {
+        self.count
+    }
+
+    pub(crate) fn start_step(&self, system: &System, debuggable: &mut dyn Debuggable) -> Option<TraceStart> {
+        let pc = debuggable.get_register("pc")? as Address;
+        let registers = debuggable
+            .register_names()
+            .iter()
+            .map(|name| debuggable.get_register(name).unwrap_or(0))
+            .collect();
+        Some(TraceStart {
+            pc,
+            disassembly: debuggable.disassemble(system, pc),
+            registers,
+        })
+    }
+
+    pub(crate) fn finish_step(
+        &mut self,
+        clock: Instant,
+        device: DeviceId,
+        name: &str,
+        debuggable: &mut dyn Debuggable,
+        start: TraceStart,
+    ) -> Result<(), Error> {
+        if !self.devices.contains_key(&device) {
+            let number = self.devices.len() as u32;
+            let names = debuggable.register_names().to_vec();
+            if self.format == TraceFormat::Binary {
+                self.write_device_record(number, name, &names)?;
+            }
+            self.devices.insert(device, (number, names));
+        }
+
+        let (number, names) = &self.devices[&device];
+        let number = *number;
+        let changes: Vec<(usize, u64)> = names
+            .iter()
+            .zip(start.registers.iter())
+            .enumerate()
+            .filter_map(|(index, (name, previous))| {
+                let value = debuggable.get_register(name).unwrap_or(0);
+                (value != *previous).then_some((index, value))
+            })
+            .collect();
+
+        let nanos = clock.as_duration().as_nanos() as u64;
+        match self.format {
+            TraceFormat::Text => {
+                let mut line = format!("{} {} {:08x}: ", nanos, name, start.pc);
+                match start.disassembly.as_ref() {
+                    Some((text, _)) => line += &format!("{:<40}", text),
+                    None => line += &format!("{:<40}", "??"),
+                }
+                for (index, value) in changes.iter() {
+                    line += &format!(" {}={:x}", names[*index], value);
+                }
+                line.push('\n');
+                self.write(line.as_bytes())?;
+            },
+            TraceFormat::Binary => {
+                let bytes = start.disassembly.map(|(_, bytes)| bytes).unwrap_or_default();
+                let mut record = vec![RECORD_STEP];
+                record.extend_from_slice(&number.to_le_bytes());
+                record.extend_from_slice(&nanos.to_le_bytes());
+                record.extend_from_slice(&(start.pc as u64).to_le_bytes());
+                record.push(bytes.len().min(u8::MAX as usize) as u8);
+                record.extend(bytes.iter().take(u8::MAX as usize));
+                record.push(changes.len() as u8);
+                for (index, value) in changes {
+                    record.push(index as u8);
+                    record.extend_from_slice(&value.to_le_bytes());
+                }
+                self.write(&record)?;
+            },
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<(), Error> {
+        self.output
+            .flush()
+            .map_err(|err| Error::new(format!("error writing to {}: {}", self.filename, err)))
+    }
+
+    fn write_device_record(&mut self, number: u32, name: &str, registers: &[&str]) -> Result<(), Error> {
+        let mut record = vec![RECORD_DEVICE];
+        record.extend_from_slice(&number.to_le_bytes());
+        for name in [name].iter().chain(registers.iter()).chain([""].iter()) {
+            let name = &name.as_bytes()[..name.len().min(u8::MAX as usize)];
+            record.push(name.len() as u8);
+            record.extend_from_slice(name);
+        }
+        self.write(&record)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.output
+            .write_all(data)
+            .map_err(|err| Error::new(format!("error writing to {}: {}", self.filename, err)))
+    }
+}
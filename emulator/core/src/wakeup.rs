@@ -0,0 +1,125 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use femtos::Instant;
+
+use crate::DeviceId;
+
+
+/// A handle to a timer scheduled with `Waker::schedule()`, which can be used to cancel it
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TimerId(u64);
+
+#[derive(Default)]
+struct WakeupState {
+    pending: AtomicBool,
+    devices: Mutex<Vec<DeviceId>>,
+    /// The scheduled timers, which are sorted so that the last one expires first
+    timers: Mutex<Vec<(Instant, TimerId, DeviceId)>>,
+    next_timer: AtomicU64,
+}
+
+/// The devices that were waiting to be woken up and the timers that were scheduled, which are saved with the state
+/// of the system
+#[derive(Clone, Debug)]
+pub(crate) struct WakeupQueueState {
+    devices: Vec<DeviceId>,
+    timers: Vec<(Instant, TimerId, DeviceId)>,
+}
+
+/// The devices which have asked to be stepped at the current clock instead of at their scheduled time, and the
+/// timers which devices have scheduled
+#[derive(Clone, Default)]
+pub(crate) struct WakeupQueue(Arc<WakeupState>);
+
+impl WakeupQueue {
+    pub(crate) fn waker(&self, device: DeviceId) -> Waker {
+        Waker {
+            device,
+            queue: self.clone(),
+        }
+    }
+
+    pub(crate) fn take(&self) -> Vec<DeviceId> {
+        // The flag is checked before every step, so the lock is only taken when there is something to do
+        if !self.0.pending.swap(false, Ordering::Acquire) {
+            return vec![];
+        }
+        std::mem::take(&mut *self.0.devices.lock().unwrap())
+    }
+
+    /// Returns true if any devices have been woken up since the last call to `take()`
+    pub(crate) fn is_pending(&self) -> bool {
+        self.0.pending.load(Ordering::Acquire)
+    }
+
+    /// Returns the time that the next timer expires, if any are scheduled
+    pub(crate) fn next_timer(&self) -> Option<Instant> {
+        self.0.timers.lock().unwrap().last().map(|(when, _, _)| *when)
+    }
+
+    pub(crate) fn save_state(&self) -> WakeupQueueState {
+        WakeupQueueState {
+            devices: self.0.devices.lock().unwrap().clone(),
+            timers: self.0.timers.lock().unwrap().clone(),
+        }
+    }
+
+    pub(crate) fn restore_state(&self, state: &WakeupQueueState) {
+        *self.0.devices.lock().unwrap() = state.devices.clone();
+        *self.0.timers.lock().unwrap() = state.timers.clone();
+        self.0.pending.store(!state.devices.is_empty(), Ordering::Release);
+    }
+
+    /// Remove and return the next timer if it expires at or before the given time
+    pub(crate) fn take_timer_before(&self, clock: Instant) -> Option<(Instant, TimerId, DeviceId)> {
+        let mut timers = self.0.timers.lock().unwrap();
+        match timers.last() {
+            Some((when, _, _)) if *when <= clock => timers.pop(),
+            _ => None,
+        }
+    }
+}
+
+/// A handle given to each steppable device which reschedules the device to be stepped as soon as possible.  It
+/// can be sent to other threads, such as the host's tty readers, so that input doesn't need to be polled for.
+#[derive(Clone)]
+pub struct Waker {
+    device: DeviceId,
+    queue: WakeupQueue,
+}
+
+impl Waker {
+    pub fn wake(&self) {
+        let mut devices = self.queue.0.devices.lock().unwrap();
+        if !devices.contains(&self.device) {
+            devices.push(self.device);
+        }
+        self.queue.0.pending.store(true, Ordering::Release);
+    }
+
+    /// Schedule a one-shot timer which calls the device's `Steppable::on_timer()` at the given time, between the
+    /// steps of other devices.  A time in the past expires as soon as possible
+    pub fn schedule(&self, when: Instant) -> TimerId {
+        let id = TimerId(self.queue.0.next_timer.fetch_add(1, Ordering::Relaxed));
+        let mut timers = self.queue.0.timers.lock().unwrap();
+        // Timers at the same time expire in the order they were scheduled
+        let index = timers
+            .iter()
+            .position(|(existing, _, _)| *existing <= when)
+            .unwrap_or(timers.len());
+        timers.insert(index, (when, id, self.device));
+        id
+    }
+
+    /// Cancel a timer, returning false if it has already expired or been cancelled
+    pub fn cancel(&self, timer: TimerId) -> bool {
+        let mut timers = self.queue.0.timers.lock().unwrap();
+        match timers.iter().position(|(_, id, _)| *id == timer) {
+            Some(index) => {
+                timers.remove(index);
+                true
+            },
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::fs;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::fmt::Write;
+use femtos::{Instant, Duration};
+
+use crate::{Error, Address, Addressable, Capabilities, Device, System};
+
+
+/// A write to one of a device's registers, with the address relative to the start of the device
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoggedWrite {
+    pub clock: Instant,
+    pub addr: Address,
+    pub data: Vec<u8>,
+}
+
+/// A record of the writes made to a device through a `Bus`, which can be replayed into a newly created instance
+/// of the device, such as to reproduce a bug in a sound or video chip without running the rest of the machine
+///
+/// The log can be saved to a text file with one write per line, giving the time in nanoseconds, the address
+/// in hex, and then the data bytes in hex.
+#[derive(Clone, Default)]
+pub struct WriteLog(Rc<RefCell<Vec<LoggedWrite>>>);
+
+impl WriteLog {
+    pub fn record(&self, clock: Instant, addr: Address, data: &[u8]) {
+        self.0.borrow_mut().push(LoggedWrite {
+            clock,
+            addr,
+            data: data.to_vec(),
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+
+    pub fn entries(&self) -> Vec<LoggedWrite> {
+        self.0.borrow().clone()
+    }
+
+    /// Write every logged write to the device in order, at the same clock time it was originally written.  The
+    /// device must have been added to the system, which is run up to the clock of each write before it's made, so
+    /// that a device that steps sees each write at the same point in its own timing as it did originally
+    pub fn replay(&self, system: &mut System, device: &Device) -> Result<(), Error> {
+        if system.get_device_by_id(device.id()).is_none() {
+            return Err(Error::new("writes can only be replayed into a device that has been added to the system"));
+        }
+
+        // The system would have nothing to run if the device doesn't step
+        let steppable = device.has(Capabilities::STEPPABLE);
+        for entry in self.0.borrow().iter() {
+            if steppable {
+                system.run_until_clock(entry.clock)?;
+            }
+            device
+                .borrow_mut()
+                .as_addressable()
+                .ok_or_else(|| Error::new("writes can only be replayed into an addressable device"))?
+                .write(entry.clock, entry.addr, &entry.data)?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, filename: &str) -> Result<(), Error> {
+        let mut contents = String::new();
+        for entry in self.0.borrow().iter() {
+            write!(contents, "{} {:x}", entry.clock.as_duration().as_nanos(), entry.addr).unwrap();
+            for byte in entry.data.iter() {
+                write!(contents, " {:02x}", byte).unwrap();
+            }
+            contents.push('\n');
+        }
+        fs::write(filename, contents).map_err(|err| Error::new(format!("error writing to {}: {}", filename, err)))
+    }
+
+    pub fn load(filename: &str) -> Result<Self, Error> {
+        let contents = fs::read_to_string(filename).map_err(|err| Error::new(format!("error reading {}: {}", filename, err)))?;
+        let log = WriteLog::default();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = parse_entry(line).ok_or_else(|| Error::new(format!("{}:{}: invalid write log entry", filename, i + 1)))?;
+            log.0.borrow_mut().push(entry);
+        }
+        Ok(log)
+    }
+}
+
+fn parse_entry(line: &str) -> Option<LoggedWrite> {
+    let mut fields = line.split_whitespace();
+    let nanos = fields.next()?.parse::<u64>().ok()?;
+    let addr = Address::from_str_radix(fields.next()?, 16).ok()?;
+    let data = fields
+        .map(|byte| u8::from_str_radix(byte, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+
+    Some(LoggedWrite {
+        clock: Instant::START + Duration::from_nanos(nanos),
+        addr,
+        data,
+    })
+}
@@ -0,0 +1,108 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use femtos::{Instant, Duration, Frequency};
+
+use moa_core::{System, Address, Addressable, BankedRegion, MemoryBlock, Device, WriteObserver};
+use moa_m68k::{M68k, M68kType};
+
+const BANK_SIZE: usize = 0x1000;
+/// The address of the register that selects the bank of each slot
+const REGISTER_ADDR: Address = 0x3000;
+/// The address of the memory that the guest program writes its results to
+const RESULT_ADDR: Address = 0x4000;
+
+#[derive(Default)]
+struct Recorder {
+    writes: RefCell<Vec<(Address, usize)>>,
+}
+
+impl WriteObserver for Recorder {
+    fn on_write(&self, addr: Address, len: usize) {
+        self.writes.borrow_mut().push((addr, len));
+    }
+}
+
+fn bank(words: &[(Address, &[u16])]) -> Device {
+    let mut contents = vec![0; BANK_SIZE];
+    for (addr, words) in words {
+        for (i, word) in words.iter().enumerate() {
+            let offset = *addr as usize + i * 2;
+            contents[offset..offset + 2].copy_from_slice(&word.to_be_bytes());
+        }
+    }
+    Device::new(MemoryBlock::new(contents))
+}
+
+#[test]
+fn selecting_a_different_bank_notifies_observers() {
+    let region = BankedRegion::new(2, BANK_SIZE)
+        .with_device(Device::new(MemoryBlock::new(vec![0; BANK_SIZE])))
+        .with_device(Device::new(MemoryBlock::new(vec![0; BANK_SIZE * 2])));
+    let switch = region.switch();
+    let recorder = Rc::new(Recorder::default());
+    switch.observe_selects(0x8000, recorder.clone());
+
+    switch.select(1, 2);
+    switch.select(0, 1);
+    assert_eq!(*recorder.writes.borrow(), vec![(0x9000, BANK_SIZE), (0x8000, BANK_SIZE)]);
+
+    // Selecting the bank that's already visible, or a slot that doesn't exist, doesn't change any memory
+    recorder.writes.borrow_mut().clear();
+    switch.select(1, 2);
+    switch.select(2, 0);
+    assert!(recorder.writes.borrow().is_empty());
+}
+
+#[test]
+fn cached_instructions_are_forgotten_when_the_bank_is_switched() {
+    let mut system = System::default();
+
+    // Slot 0 always shows the vectors and the code that jumps to slot 1, which starts out showing bank 1
+    #[rustfmt::skip]
+    let fixed = bank(&[
+        (0x0000, &[0x0000, 0x2000, 0x0000, 0x0008]),     // initial stack pointer and pc
+        (0x0008, &[0x4EF9, 0x0000, 0x1000]),             // jmp $1000
+    ]);
+    #[rustfmt::skip]
+    let first = bank(&[
+        (0x0000, &[0x13FC, 0x0001, 0x0000, 0x4000]),     // move.b #1, $4000
+        (0x0008, &[0x13FC, 0x0002, 0x0000, 0x3001]),     // move.b #2, $3001
+    ]);
+    // The code after the switch is in bank 2, which jumps back to the instruction cached from bank 1
+    #[rustfmt::skip]
+    let second = bank(&[
+        (0x0000, &[0x13FC, 0x0002, 0x0000, 0x4000]),     // move.b #2, $4000
+        (0x0008, &[0x60FE]),                             // bra.s .
+        (0x0010, &[0x4EF9, 0x0000, 0x1000]),             // jmp $1000
+    ]);
+
+    let region = BankedRegion::new(2, BANK_SIZE)
+        .with_device(fixed)
+        .with_device(first)
+        .with_device(second);
+    let switch = region.switch();
+    system
+        .add_addressable_device(REGISTER_ADDR, Device::new(region.register()))
+        .unwrap();
+    system.add_addressable_device(0x0000, Device::new(region)).unwrap();
+    let result = Device::new(MemoryBlock::new(vec![0; 0x10]));
+    system.add_addressable_device(RESULT_ADDR, result.clone()).unwrap();
+
+    let mut cpu = M68k::from_type(M68kType::MC68000, Frequency::from_mhz(8));
+    cpu.cache_instructions(&mut system.get_bus());
+    system.get_bus().observe_bank_switches(0x0000, &switch);
+    system.add_interruptable_device("cpu", Device::new(cpu)).unwrap();
+
+    system.run_for_duration(Duration::from_micros(100)).unwrap();
+
+    assert_eq!(switch.selected(1), 2);
+    let mut data = [0];
+    result
+        .borrow_mut()
+        .as_addressable()
+        .unwrap()
+        .read(Instant::START, 0, &mut data)
+        .unwrap();
+    assert_eq!(data[0], 2);
+}
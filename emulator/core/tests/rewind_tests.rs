@@ -0,0 +1,110 @@
+use femtos::{Instant, Frequency};
+
+use moa_core::{System, Address, Addressable, MemoryBlock, Device};
+use moa_m68k::{M68k, M68kType};
+
+const RAM_SIZE: usize = 0x2000;
+const REGISTERS: &[&str] = &["pc", "sr", "d0", "d1", "a0", "a7"];
+
+/// The reset vectors followed by a loop that counts in d0, sums the counts in d1, and stores each count in memory
+#[rustfmt::skip]
+const PROGRAM: &[(Address, &[u16])] = &[
+    (0x0000, &[0x0000, 0x2000, 0x0000, 0x0400]),    // initial stack pointer and pc
+    (0x0400, &[
+        0x7000,                                     // moveq #0, %d0
+        0x41F9, 0x0000, 0x1000,                     // lea $1000, %a0
+        0x5280,                                     // loop: addq.l #1, %d0
+        0x20C0,                                     // move.l %d0, (%a0)+
+        0xD280,                                     // add.l %d0, %d1
+        0x60F8,                                     // bra.s loop
+    ]),
+];
+
+struct Machine {
+    system: System,
+    cpu: Device,
+    ram: Device,
+}
+
+impl Machine {
+    fn new() -> Self {
+        let mut system = System::default();
+
+        let mut ram = MemoryBlock::new(vec![0; RAM_SIZE]);
+        for (addr, words) in PROGRAM {
+            for (i, word) in words.iter().enumerate() {
+                ram.write(Instant::START, addr + (i * 2) as Address, &word.to_be_bytes())
+                    .unwrap();
+            }
+        }
+        let ram = Device::new(ram);
+        system.add_addressable_device(0x0000, ram.clone()).unwrap();
+
+        let cpu = Device::new(M68k::from_type(M68kType::MC68000, Frequency::from_mhz(8)));
+        system.add_interruptable_device("cpu", cpu.clone()).unwrap();
+
+        Self {
+            system,
+            cpu,
+            ram,
+        }
+    }
+
+    fn run(&mut self, steps: usize) {
+        for _ in 0..steps {
+            self.system.step().unwrap();
+        }
+    }
+
+    fn registers(&self) -> Vec<u64> {
+        let mut cpu = self.cpu.borrow_mut();
+        let debuggable = cpu.as_debuggable().unwrap();
+        REGISTERS.iter().map(|name| debuggable.get_register(name).unwrap()).collect()
+    }
+
+    fn memory(&self) -> Vec<u8> {
+        let mut data = vec![0; RAM_SIZE];
+        self.ram
+            .borrow_mut()
+            .as_addressable()
+            .unwrap()
+            .read(Instant::START, 0, &mut data)
+            .unwrap();
+        data
+    }
+}
+
+#[test]
+fn rewinding_returns_to_the_state_of_an_earlier_step() {
+    let mut expected = Machine::new();
+    expected.run(13);
+
+    let mut machine = Machine::new();
+    machine.system.enable_rewind(100);
+    machine.run(20);
+    let (registers, memory) = (machine.registers(), machine.memory());
+    assert_eq!(machine.system.rewindable_steps(None), 20);
+
+    assert_eq!(machine.system.rewind(None, 7).unwrap(), 7);
+    assert_eq!(machine.system.rewindable_steps(None), 13);
+    assert_eq!(machine.registers(), expected.registers());
+    assert_eq!(machine.memory(), expected.memory());
+
+    // Running the rewound steps again ends up where it was before rewinding
+    machine.run(7);
+    assert_eq!(machine.registers(), registers);
+    assert_eq!(machine.memory(), memory);
+}
+
+#[test]
+fn only_the_steps_in_the_journal_can_be_rewound() {
+    let mut machine = Machine::new();
+    machine.run(5);
+    assert_eq!(machine.system.rewindable_steps(None), 0);
+    assert_eq!(machine.system.rewind(None, 1).unwrap(), 0);
+
+    machine.system.enable_rewind(4);
+    machine.run(10);
+    assert_eq!(machine.system.rewindable_steps(Some(&machine.cpu)), 4);
+    assert_eq!(machine.system.rewind(Some(&machine.cpu), 10).unwrap(), 4);
+}
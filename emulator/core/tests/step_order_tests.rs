@@ -0,0 +1,70 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use femtos::{Instant, Duration};
+
+use moa_core::{System, Error, Steppable, Transmutable, Device, MemoryBlock, StepPriority};
+
+const INTERVAL: Duration = Duration::from_micros(10);
+
+type Steps = Rc<RefCell<Vec<(Instant, &'static str)>>>;
+
+/// A device that records its name and the time of each step in a list shared with the other devices
+struct Recorder {
+    name: &'static str,
+    steps: Steps,
+}
+
+impl Steppable for Recorder {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        self.steps.borrow_mut().push((system.clock, self.name));
+        Ok(INTERVAL)
+    }
+}
+
+impl Transmutable for Recorder {
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+}
+
+#[test]
+fn devices_at_the_same_time_step_in_order_of_priority_and_then_insertion() {
+    let steps = Steps::default();
+    let mut system = System::default();
+
+    // The devices are added out of order, so that only the priorities put them in order
+    for (name, priority) in [
+        ("audio", StepPriority::AUDIO),
+        ("default_first", StepPriority::DEFAULT),
+        ("video", StepPriority::VIDEO),
+        ("cpu", StepPriority::CPU),
+        ("default_second", StepPriority::DEFAULT),
+    ] {
+        let device = Device::new(Recorder {
+            name,
+            steps: steps.clone(),
+        });
+        system.add_device(name, device.clone()).unwrap();
+        system.set_step_priority(&device, priority).unwrap();
+    }
+
+    for _ in 0..10 {
+        system.step().unwrap();
+    }
+
+    let order = ["cpu", "video", "default_first", "default_second", "audio"];
+    let expected: Vec<(Instant, &str)> = [Instant::START, Instant::START + INTERVAL]
+        .into_iter()
+        .flat_map(|clock| order.iter().map(move |name| (clock, *name)))
+        .collect();
+    assert_eq!(*steps.borrow(), expected);
+}
+
+#[test]
+fn priority_can_only_be_set_for_a_steppable_device() {
+    let mut system = System::default();
+    let memory = Device::new(MemoryBlock::new(vec![0; 0x10]));
+    system.add_addressable_device(0, memory.clone()).unwrap();
+    assert!(system.set_step_priority(&memory, StepPriority::CPU).is_err());
+}
@@ -0,0 +1,124 @@
+use std::sync::{Arc, Mutex};
+
+use femtos::{Instant, Duration};
+
+use moa_core::{System, Error, Address, Addressable, Steppable, ParallelSteppable, Transmutable, Device, ThreadedDevice};
+
+const INTERVAL: Duration = Duration::from_micros(10);
+
+type Samples = Arc<Mutex<Vec<(Instant, u8)>>>;
+
+/// A device that records the value of its register at each step, like a sound chip producing a sample from the
+/// settings of its registers
+struct Recorder {
+    register: u8,
+    samples: Samples,
+}
+
+impl ParallelSteppable for Recorder {
+    fn step_at(&mut self, clock: Instant) -> Result<Duration, Error> {
+        self.samples.lock().unwrap().push((clock, self.register));
+        // Writing 0xFF changes the rate that the device steps at, which a threaded device can't follow
+        if self.register == 0xFF {
+            Ok(INTERVAL + INTERVAL)
+        } else {
+            Ok(INTERVAL)
+        }
+    }
+}
+
+impl Steppable for Recorder {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        self.step_at(system.clock)
+    }
+}
+
+impl Addressable for Recorder {
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, _clock: Instant, _addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        data[0] = self.register;
+        Ok(())
+    }
+
+    fn write(&mut self, _clock: Instant, _addr: Address, data: &[u8]) -> Result<(), Error> {
+        self.register = data[0];
+        Ok(())
+    }
+}
+
+impl Transmutable for Recorder {
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+}
+
+fn build_system(threaded: bool) -> (System, Samples) {
+    let samples = Samples::default();
+    let recorder = Recorder {
+        register: 0,
+        samples: samples.clone(),
+    };
+    let device = if threaded {
+        Device::new(ThreadedDevice::new(recorder))
+    } else {
+        Device::new(recorder)
+    };
+
+    let mut system = System::default();
+    system.add_addressable_device(0, device).unwrap();
+    (system, samples)
+}
+
+/// Write a sequence of values to the device in between its steps, and return the samples it recorded
+fn run_writes(threaded: bool, values: &[u8]) -> Vec<(Instant, u8)> {
+    let (mut system, samples) = build_system(threaded);
+    for value in values {
+        system.run_for_duration(Duration::from_micros(35)).unwrap();
+        system.get_bus().write_u8(system.clock, 0, *value).unwrap();
+    }
+    system.run_for_duration(Duration::from_micros(35)).unwrap();
+
+    // Reading from the device waits for the step that's running on its thread to finish
+    assert_eq!(system.get_bus().read_u8(system.clock, 0).unwrap(), *values.last().unwrap());
+
+    let recorded = samples.lock().unwrap();
+    recorded.clone()
+}
+
+#[test]
+fn threaded_device_matches_unthreaded_device() {
+    let values: Vec<u8> = (1..=50).collect();
+    let expected = run_writes(false, &values);
+    let actual = run_writes(true, &values);
+
+    assert!(expected.len() > values.len());
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn threaded_device_size_is_available_while_stepping() {
+    let mut device = ThreadedDevice::new(Recorder {
+        register: 0,
+        samples: Samples::default(),
+    });
+    let system = System::default();
+    device.step(&system).unwrap();
+    device.step(&system).unwrap();
+    assert_eq!(device.size(), 1);
+}
+
+#[test]
+fn threaded_device_fails_when_the_interval_changes() {
+    let (mut system, _) = build_system(true);
+    system.run_for_duration(Duration::from_micros(35)).unwrap();
+    system.get_bus().write_u8(system.clock, 0, 0xFF).unwrap();
+
+    assert!(system.run_for_duration(Duration::from_micros(100)).is_err());
+}
@@ -0,0 +1,104 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use femtos::{Instant, Duration};
+
+use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable, Device, WriteLog};
+
+const INTERVAL: Duration = Duration::from_micros(10);
+const CHIP_ADDR: Address = 0x100;
+
+type Samples = Rc<RefCell<Vec<(Instant, u8)>>>;
+
+/// A device that records the value of its register at each step
+struct Recorder {
+    register: u8,
+    samples: Samples,
+}
+
+impl Steppable for Recorder {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        self.samples.borrow_mut().push((system.clock, self.register));
+        Ok(INTERVAL)
+    }
+}
+
+impl Addressable for Recorder {
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, _clock: Instant, _addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        data[0] = self.register;
+        Ok(())
+    }
+
+    fn write(&mut self, _clock: Instant, _addr: Address, data: &[u8]) -> Result<(), Error> {
+        self.register = data[0];
+        Ok(())
+    }
+}
+
+impl Transmutable for Recorder {
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+}
+
+fn recorder() -> (Device, Samples) {
+    let samples = Samples::default();
+    let device = Device::new(Recorder {
+        register: 0,
+        samples: samples.clone(),
+    });
+    (device, samples)
+}
+
+#[test]
+fn replayed_writes_are_seen_at_the_same_steps() {
+    let (device, original) = recorder();
+    let mut system = System::default();
+    system.add_peripheral("chip", CHIP_ADDR, device).unwrap();
+    let log = system.log_device_writes("chip").unwrap();
+
+    // Write to the chip through the bus in between its steps, like a cpu would
+    for (micros, value) in [(5, 1), (25, 2), (27, 3), (60, 4)] {
+        let clock = Instant::START + Duration::from_micros(micros);
+        system.run_until_clock(clock).unwrap();
+        system.get_bus().write(clock, CHIP_ADDR, &[value]).unwrap();
+    }
+    system.run_until_clock(Instant::START + Duration::from_micros(100)).unwrap();
+    assert_eq!(log.len(), 4);
+
+    let filename = std::env::temp_dir().join(format!("moa-writelog-{}.log", std::process::id()));
+    let filename = filename.to_str().unwrap();
+    log.save(filename).unwrap();
+    let loaded = WriteLog::load(filename).unwrap();
+    std::fs::remove_file(filename).unwrap();
+    assert_eq!(loaded.entries(), log.entries());
+
+    // The new chip isn't on a bus, so the writes only reach it through the replay
+    let (device, replayed) = recorder();
+    let mut system = System::default();
+    system.add_device("chip", device.clone()).unwrap();
+    loaded.replay(&mut system, &device).unwrap();
+    system.run_until_clock(Instant::START + Duration::from_micros(100)).unwrap();
+
+    assert_eq!(*replayed.borrow(), *original.borrow());
+    let values: Vec<u8> = original.borrow().iter().map(|(_, value)| *value).collect();
+    assert_eq!(values, vec![0, 0, 1, 1, 3, 3, 3, 4, 4, 4, 4]);
+}
+
+#[test]
+fn writes_are_only_replayed_into_a_device_in_the_system() {
+    let (device, _) = recorder();
+    let log = WriteLog::default();
+    log.record(Instant::START, 0, &[1]);
+
+    let mut system = System::default();
+    assert!(log.replay(&mut system, &device).is_err());
+}
@@ -0,0 +1,57 @@
+use std::time;
+
+use femtos::{Instant, Frequency};
+use emulator_hal::{BusAccess, Step};
+use emulator_hal_memory::MemoryBlock;
+
+use moa_m68k::{M68k, M68kType, M68kAddress};
+
+const INIT_STACK: M68kAddress = 0x00002000;
+const INIT_ADDR: M68kAddress = 0x00000010;
+const STEPS: usize = 2_000_000;
+
+#[rustfmt::skip]
+const PROGRAM: &[u16] = &[
+    0x7000,                 // moveq #0, %d0
+    0x207C, 0x0000, 0x1000, // movea.l #0x1000, %a0
+    0xD280,                 // loop: add.l %d0, %d1
+    0x2081,                 // move.l %d1, (%a0)
+    0x5280,                 // addq.l #1, %d0
+    0xE38A,                 // lsl.l #1, %d2
+    0x60F6,                 // bra.s loop
+];
+
+/// Run a short loop for a fixed number of instructions, returning the time it took and the final registers
+fn run_loop(cached: bool) -> (time::Duration, [u32; 8]) {
+    let mut memory = MemoryBlock::from(vec![0; 0x1_0000]);
+    memory.write_beu32(Instant::START, 0, INIT_STACK).unwrap();
+    memory.write_beu32(Instant::START, 4, INIT_ADDR).unwrap();
+    for (i, word) in PROGRAM.iter().enumerate() {
+        memory
+            .write_beu16(Instant::START, INIT_ADDR + (i as M68kAddress * 2), *word)
+            .unwrap();
+    }
+
+    let mut cpu = M68k::from_type(M68kType::MC68000, Frequency::from_mhz(8));
+    if cached {
+        cpu.enable_instruction_cache();
+    }
+
+    let started = time::Instant::now();
+    let mut clock = Instant::START;
+    for _ in 0..STEPS {
+        clock = cpu.step(clock, &mut memory).unwrap();
+    }
+    (started.elapsed(), cpu.state.d_reg)
+}
+
+fn main() {
+    let (uncached, uncached_regs) = run_loop(false);
+    let (cached, cached_regs) = run_loop(true);
+    assert_eq!(uncached_regs, cached_regs);
+
+    let rate = |elapsed: time::Duration| STEPS as f64 / elapsed.as_secs_f64() / 1_000_000.0;
+    println!("decoding every instruction: {:?} ({:.2} million instructions/s)", uncached, rate(uncached));
+    println!("with the instruction cache: {:?} ({:.2} million instructions/s)", cached, rate(cached));
+    println!("speedup: {:.2}x", uncached.as_secs_f64() / cached.as_secs_f64());
+}
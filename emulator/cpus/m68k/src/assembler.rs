@@ -1,10 +1,14 @@
+use std::path::PathBuf;
 use std::collections::HashMap;
 
-use moa_parsing::{self as parser, AssemblyLine, AssemblyOperand, AssemblyParser, ParserError};
+use moa_parsing::{self as parser, AssemblyLine, AssemblyOperand, AssemblyParser, Expression, ParserError};
 
 use super::state::M68kType;
 use super::instructions::Size;
 
+/// The maximum number of times the lines are converted while waiting for the addresses of the labels to settle
+const MAX_PASSES: usize = 8;
+
 #[derive(Clone, Debug)]
 pub struct Error(String);
 
@@ -57,36 +61,17 @@ impl Disallow {
     }
 }
 
-pub enum RelocationType {
-    Displacement,
-    Word(String),
-    Long(String),
-}
-
-pub struct Relocation {
-    pub rtype: RelocationType,
-    pub label: String,
-    pub index: usize,
-    pub from_origin: usize,
-}
-
-impl Relocation {
-    pub fn new(rtype: RelocationType, label: String, index: usize, from_origin: usize) -> Self {
-        Self {
-            rtype,
-            label,
-            index,
-            from_origin,
-        }
-    }
-}
-
 pub struct M68kAssembler {
     pub cputype: M68kType,
     pub labels: HashMap<String, usize>,
-    pub output: Vec<u16>,
-    pub relocations: Vec<Relocation>,
+    pub output: Vec<u8>,
     pub current_origin: usize,
+    include_dir: Option<PathBuf>,
+    previous_labels: HashMap<String, usize>,
+    /// The first label used before its definition in the current pass, and the line it was used on
+    unresolved: Option<(usize, String)>,
+    /// The first error caused by the value of a label, which is only reported once all labels are known
+    deferred: Option<Error>,
 }
 
 impl M68kAssembler {
@@ -95,99 +80,243 @@ impl M68kAssembler {
             cputype,
             labels: HashMap::new(),
             output: vec![],
-            relocations: vec![],
             current_origin: 0,
+            include_dir: None,
+            previous_labels: HashMap::new(),
+            unresolved: None,
+            deferred: None,
         }
     }
 
+    /// Set the directory that the files named by `.include` directives are relative to
+    pub fn with_include_dir(mut self, dir: PathBuf) -> Self {
+        self.include_dir = Some(dir);
+        self
+    }
+
     pub fn assemble(&mut self, text: &str) -> Result<Vec<u8>, Error> {
         self.assemble_in_place(text)?;
-        Ok(self.output.iter().fold(vec![], |mut acc, item| {
-            acc.push((*item >> 8) as u8);
-            acc.push(*item as u8);
-            acc
-        }))
+        Ok(self.output.clone())
     }
 
     pub fn assemble_words(&mut self, text: &str) -> Result<Vec<u16>, Error> {
         self.assemble_in_place(text)?;
-        Ok(self.output.clone())
+        Ok(self
+            .output
+            .chunks(2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], *bytes.get(1).unwrap_or(&0)]))
+            .collect())
     }
 
     pub fn assemble_in_place(&mut self, text: &str) -> Result<(), Error> {
         let lines = self.parse(text)?;
 
-        for (lineno, line) in lines {
-            self.convert(lineno, &line)?;
-        }
+        // Labels can be used before they're defined, and the size of an instruction can depend on the value of a
+        // label, so the lines are converted again until the addresses of the labels stop changing
+        for _ in 0..MAX_PASSES {
+            self.previous_labels = std::mem::take(&mut self.labels);
+            self.output.clear();
+            self.current_origin = 0;
+            self.unresolved = None;
+            self.deferred = None;
+
+            for (lineno, line) in lines.iter() {
+                self.convert(*lineno, line)?;
+            }
 
-        self.apply_relocations()?;
+            if self.labels == self.previous_labels {
+                if let Some((lineno, label)) = self.unresolved.take() {
+                    return Err(Error::new(format!("error at line {}: label undefined {:?}", lineno, label)));
+                }
+                return match self.deferred.take() {
+                    Some(err) => Err(err),
+                    None => Ok(()),
+                };
+            }
+        }
 
-        Ok(())
+        Err(Error::new(format!("the addresses of labels didn't settle after {} passes", MAX_PASSES)))
     }
 
     fn parse(&mut self, text: &str) -> Result<Vec<(usize, AssemblyLine)>, Error> {
-        let mut parser = AssemblyParser::new(text);
+        let mut parser = AssemblyParser::new(text).with_include_dir(self.include_dir.clone());
         Ok(parser.parse()?)
     }
 
-    fn apply_relocations(&mut self) -> Result<(), Error> {
-        for reloc in self.relocations.iter() {
-            match reloc.rtype {
-                RelocationType::Displacement => {
-                    // TODO this doesn't yet take into accound the origin
-                    let location = *self
-                        .labels
-                        .get(&reloc.label)
-                        .ok_or_else(|| Error::new(format!("error during relocation, label undefined {:?}", reloc.label)))?;
-                    self.output[reloc.index] |= ((self.output[reloc.index] as i8 * 2 + 2) - (location as i8 * 2)) as u16 & 0x00ff;
-                },
-                _ => panic!("relocation type unimplemented"),
+    fn current_address(&self) -> usize {
+        self.current_origin + self.output.len()
+    }
+
+    fn push_word(&mut self, word: u16) {
+        self.output.extend_from_slice(&word.to_be_bytes());
+    }
+
+    fn extend_words(&mut self, words: Vec<u16>) {
+        for word in words {
+            self.push_word(word);
+        }
+    }
+
+    fn evaluate(&mut self, lineno: usize, expr: &Expression) -> Result<usize, Error> {
+        // Labels that aren't known yet are given a value of 0 until a later pass
+        let mut unresolved = None;
+        let value = expr.evaluate(lineno, &mut |name| {
+            let value = self.labels.get(name).or_else(|| self.previous_labels.get(name)).copied();
+            if value.is_none() {
+                unresolved.get_or_insert_with(|| name.to_string());
             }
+            Some(value.unwrap_or(0))
+        })?;
+
+        if let Some(label) = unresolved {
+            self.unresolved.get_or_insert((lineno, label));
+        }
+        Ok(value)
+    }
+
+    fn expect_value(&mut self, lineno: usize, operand: &AssemblyOperand) -> Result<usize, Error> {
+        match operand {
+            AssemblyOperand::Immediate(value) => Ok(*value),
+            AssemblyOperand::Label(name) => self.evaluate(lineno, &Expression::Label(name.clone())),
+            AssemblyOperand::Expression(expr) => self.evaluate(lineno, expr),
+            _ => Err(Error::new(format!("error at line {}: expected a value, but found {:?}", lineno, operand))),
+        }
+    }
+
+    /// Replace the labels and expressions in the operand with their values
+    fn resolve_operand(&mut self, lineno: usize, operand: &AssemblyOperand) -> Result<AssemblyOperand, Error> {
+        match operand {
+            AssemblyOperand::Label(_) | AssemblyOperand::Expression(_) => {
+                Ok(AssemblyOperand::Immediate(self.expect_value(lineno, operand)?))
+            },
+            AssemblyOperand::Indirect(list) => Ok(AssemblyOperand::Indirect(
+                list.iter()
+                    .map(|operand| self.resolve_operand(lineno, operand))
+                    .collect::<Result<_, _>>()?,
+            )),
+            _ => Ok(operand.clone()),
         }
-        Ok(())
     }
 
     fn convert(&mut self, lineno: usize, line: &AssemblyLine) -> Result<(), Error> {
         match line {
             AssemblyLine::Directive(name, list) => {
-                println!("skipping directive {} ({:?})", name, list);
+                self.convert_directive(lineno, name, list)?;
             },
             AssemblyLine::Label(label) => {
-                println!("label {}", label);
-                self.labels.insert(label.clone(), self.output.len() - 1);
+                self.define_label(lineno, label, self.current_address())?;
             },
             AssemblyLine::Instruction(name, list) => {
+                if self.current_address() & 1 != 0 {
+                    return Err(Error::new(format!(
+                        "error at line {}: instructions must be at an even address, but it's {:#x}",
+                        lineno,
+                        self.current_address()
+                    )));
+                }
                 self.convert_instruction(lineno, name, list)?;
             },
         }
         Ok(())
     }
 
+    fn define_label(&mut self, lineno: usize, label: &str, value: usize) -> Result<(), Error> {
+        if self.labels.insert(label.to_string(), value).is_some() {
+            return Err(Error::new(format!("error at line {}: label {:?} is already defined", lineno, label)));
+        }
+        Ok(())
+    }
+
+    fn convert_directive(&mut self, lineno: usize, name: &str, args: &[AssemblyOperand]) -> Result<(), Error> {
+        match name {
+            "org" => {
+                parser::expect_args(lineno, args, 1)?;
+                let address = self.expect_value(lineno, &args[0])?;
+                if self.output.is_empty() {
+                    self.current_origin = address;
+                } else if address >= self.current_address() {
+                    self.output.resize(address - self.current_origin, 0);
+                } else {
+                    return Err(Error::new(format!(
+                        "error at line {}: .org {:#x} is before the current address {:#x}",
+                        lineno,
+                        address,
+                        self.current_address()
+                    )));
+                }
+            },
+            "align" | "even" => {
+                let alignment = if name == "even" {
+                    parser::expect_args(lineno, args, 0)?;
+                    2
+                } else {
+                    parser::expect_args(lineno, args, 1)?;
+                    self.expect_value(lineno, &args[0])?
+                };
+                if alignment == 0 {
+                    return Err(Error::new(format!("error at line {}: alignment must be greater than 0", lineno)));
+                }
+                let padding = (alignment - self.current_address() % alignment) % alignment;
+                self.output.resize(self.output.len() + padding, 0);
+            },
+            "space" => {
+                let (size, fill) = match args {
+                    [size] => (self.expect_value(lineno, size)?, 0),
+                    [size, fill] => (self.expect_value(lineno, size)?, self.expect_value(lineno, fill)?),
+                    _ => return Err(Error::new(format!("error at line {}: expected a size and optional fill value", lineno))),
+                };
+                let fill = convert_immediate(lineno, fill, Size::Byte)?[0] as u8;
+                self.output.resize(self.output.len() + size, fill);
+            },
+            "ascii" | "asciiz" => {
+                for arg in args {
+                    let AssemblyOperand::String(string) = arg else {
+                        return Err(Error::new(format!("error at line {}: expected a string, but found {:?}", lineno, arg)));
+                    };
+                    self.output.extend(string.bytes());
+                    if name == "asciiz" {
+                        self.output.push(0);
+                    }
+                }
+            },
+            "byte" | "word" | "long" => {
+                for arg in args {
+                    let value = self.expect_value(lineno, arg)?;
+                    match name {
+                        "byte" => self.output.push(convert_immediate(lineno, value, Size::Byte)?[0] as u8),
+                        "word" => self.extend_words(convert_immediate(lineno, value, Size::Word)?),
+                        _ => self.extend_words(convert_immediate(lineno, value, Size::Long)?),
+                    }
+                }
+            },
+            "equ" => {
+                let [AssemblyOperand::Label(label), value] = args else {
+                    return Err(Error::new(format!("error at line {}: expected a name and a value for .equ", lineno)));
+                };
+                let value = self.expect_value(lineno, value)?;
+                self.define_label(lineno, label, value)?;
+            },
+            _ => {
+                println!("skipping directive {} ({:?})", name, args);
+            },
+        }
+        Ok(())
+    }
+
     fn convert_instruction(&mut self, lineno: usize, mneumonic: &str, args: &[AssemblyOperand]) -> Result<(), Error> {
+        if mneumonic == "bra" || mneumonic == "bsr" {
+            return self.convert_branch_instruction(lineno, mneumonic, args);
+        }
+
+        let args = args
+            .iter()
+            .map(|arg| self.resolve_operand(lineno, arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        let args = args.as_slice();
+
         match mneumonic {
-            "bra" => {
-                let label = parser::expect_label(lineno, args)?;
-                self.output.push(0x6000);
-                self.relocations.push(Relocation::new(
-                    RelocationType::Displacement,
-                    label,
-                    self.output.len() - 1,
-                    self.current_origin,
-                ));
-            },
-            "bsr" => {
-                let label = parser::expect_label(lineno, args)?;
-                self.output.push(0x6100);
-                self.relocations.push(Relocation::new(
-                    RelocationType::Displacement,
-                    label,
-                    self.output.len() - 1,
-                    self.current_origin,
-                ));
-            },
             "illegal" => {
-                self.output.push(0x4AFC);
+                self.push_word(0x4AFC);
             },
 
             "lea" => {
@@ -195,28 +324,28 @@ impl M68kAssembler {
                 let reg = expect_address_register(lineno, &args[0])?;
                 let (effective_address, additional_words) =
                     convert_target(lineno, &args[1], Size::Long, Disallow::NoRegsPrePostOrImmediate)?;
-                self.output.push(0x41C0 | (reg << 9) | effective_address);
-                self.output.extend(additional_words);
+                self.push_word(0x41C0 | (reg << 9) | effective_address);
+                self.extend_words(additional_words);
             },
             "nop" => {
-                self.output.push(0x4E71);
+                self.push_word(0x4E71);
             },
             "rts" => {
-                self.output.push(0x4E75);
+                self.push_word(0x4E75);
             },
             "rte" => {
-                self.output.push(0x4E73);
+                self.push_word(0x4E73);
             },
             "rtr" => {
-                self.output.push(0x4E77);
+                self.push_word(0x4E77);
             },
             "stop" => {
                 let immediate = parser::expect_immediate(lineno, &args[0])?;
-                self.output.push(0x4E72);
-                self.output.extend(convert_immediate(lineno, immediate, Size::Word)?);
+                self.push_word(0x4E72);
+                self.extend_words(convert_immediate(lineno, immediate, Size::Word)?);
             },
             "trapv" => {
-                self.output.push(0x4E76);
+                self.push_word(0x4E76);
             },
 
             _ => {
@@ -226,6 +355,23 @@ impl M68kAssembler {
         Ok(())
     }
 
+    fn convert_branch_instruction(&mut self, lineno: usize, mneumonic: &str, args: &[AssemblyOperand]) -> Result<(), Error> {
+        parser::expect_args(lineno, args, 1)?;
+        let target = self.expect_value(lineno, &args[0])?;
+        let opcode = if mneumonic == "bra" { 0x6000 } else { 0x6100 };
+
+        // Only the 8-bit displacement is supported, where a displacement of 0 would mean a 16-bit displacement follows
+        let displacement = target.wrapping_sub(self.current_address() + 2) as isize;
+        if displacement == 0 || !(-128..=127).contains(&displacement) {
+            self.deferred.get_or_insert(Error::new(format!(
+                "error at line {}: branch target {:#x} is out of range of a short branch",
+                lineno, target
+            )));
+        }
+        self.push_word(opcode | (displacement as u16 & 0x00FF));
+        Ok(())
+    }
+
     fn convert_sized_instruction(&mut self, lineno: usize, mneumonic: &str, args: &[AssemblyOperand]) -> Result<(), Error> {
         let operation_size = get_size_from_mneumonic(mneumonic)
             .ok_or_else(|| Error::new(format!("error at line {}: expected a size specifier (b/w/l)", lineno)));
@@ -303,10 +449,9 @@ impl M68kAssembler {
                 let (effective_address_right, additional_words_right) =
                     convert_target(lineno, &args[1], operation_size, Disallow::None)?;
                 let effective_address_left = (effective_address_left >> 3) | (effective_address_left << 3);
-                self.output
-                    .push(encode_size_for_move(operation_size) | effective_address_left | effective_address_right);
-                self.output.extend(additional_words_left);
-                self.output.extend(additional_words_right);
+                self.push_word(encode_size_for_move(operation_size) | effective_address_left | effective_address_right);
+                self.extend_words(additional_words_left);
+                self.extend_words(additional_words_right);
             },
 
             "neg" => {
@@ -390,9 +535,9 @@ impl M68kAssembler {
         parser::expect_args(lineno, args, 2)?;
         let immediate = parser::expect_immediate(lineno, &args[0])?;
         let (effective_address, additional_words) = convert_target(lineno, &args[1], operation_size, disallow)?;
-        self.output.push(opcode | encode_size(operation_size) | effective_address);
-        self.output.extend(convert_immediate(lineno, immediate, operation_size)?);
-        self.output.extend(additional_words);
+        self.push_word(opcode | encode_size(operation_size) | effective_address);
+        self.extend_words(convert_immediate(lineno, immediate, operation_size)?);
+        self.extend_words(additional_words);
         Ok(())
     }
 
@@ -407,9 +552,8 @@ impl M68kAssembler {
         parser::expect_args(lineno, args, 2)?;
         let (direction, reg, operand) = convert_reg_and_other(lineno, args, Disallow::NoAReg)?;
         let (effective_address, additional_words) = convert_target(lineno, operand, operation_size, disallow)?;
-        self.output
-            .push(opcode | encode_size(operation_size) | direction | (reg << 9) | effective_address);
-        self.output.extend(additional_words);
+        self.push_word(opcode | encode_size(operation_size) | direction | (reg << 9) | effective_address);
+        self.extend_words(additional_words);
         Ok(())
     }
 
@@ -426,9 +570,8 @@ impl M68kAssembler {
         //let (_direction, reg, operand) = convert_reg_and_other(lineno, args, Disallow::NoDReg)?;
         let reg = expect_address_register(lineno, &args[1])?;
         let (effective_address, additional_words) = convert_target(lineno, &args[0], operation_size, disallow)?;
-        self.output
-            .push(opcode | size_bit | (0b11 << 6) | (reg << 9) | effective_address);
-        self.output.extend(additional_words);
+        self.push_word(opcode | size_bit | (0b11 << 6) | (reg << 9) | effective_address);
+        self.extend_words(additional_words);
         Ok(())
     }
 
@@ -442,8 +585,8 @@ impl M68kAssembler {
     ) -> Result<(), Error> {
         parser::expect_args(lineno, args, 1)?;
         let (effective_address, additional_words) = convert_target(lineno, &args[0], operation_size, disallow)?;
-        self.output.push(opcode | encode_size(operation_size) | effective_address);
-        self.output.extend(additional_words);
+        self.push_word(opcode | encode_size(operation_size) | effective_address);
+        self.extend_words(additional_words);
         Ok(())
     }
 
@@ -464,8 +607,8 @@ impl M68kAssembler {
             if let Some(opcode) = opcode {
                 parser::expect_args(lineno, args, 2)?;
                 let immediate = parser::expect_immediate(lineno, &args[0])?;
-                self.output.push(opcode);
-                self.output.extend(convert_immediate(lineno, immediate, Size::Word)?);
+                self.push_word(opcode);
+                self.extend_words(convert_immediate(lineno, immediate, Size::Word)?);
                 return Ok(true);
             }
         }
@@ -505,19 +648,17 @@ impl M68kAssembler {
                 }
 
                 let reg = expect_data_register(lineno, &args[1])?;
-                self.output
-                    .push(opcode | ((immediate as u16) << 9) | direction | encode_size(operation_size) /*(0b0 << 5)*/ | reg);
+                self.push_word(opcode | ((immediate as u16) << 9) | direction | encode_size(operation_size) /*(0b0 << 5)*/ | reg);
             },
             [AssemblyOperand::Register(_), AssemblyOperand::Register(_)] => {
                 let bit_reg = expect_data_register(lineno, &args[0])?;
                 let reg = expect_data_register(lineno, &args[1])?;
-                self.output
-                    .push(opcode | (bit_reg << 9) | direction | encode_size(operation_size) | (0b1 << 5) | reg);
+                self.push_word(opcode | (bit_reg << 9) | direction | encode_size(operation_size) | (0b1 << 5) | reg);
             },
             //[_] => {
             //    let (effective_address, additional_words) = convert_target(lineno, &args[0], Size::Word, Disallow::NoRegsImmediateOrPC)?;
-            //    self.output.push(opcode | effective_address);
-            //    self.output.extend(additional_words);
+            //    self.push_word(opcode | effective_address);
+            //    self.extend_words(additional_words);
             //},
             _ => return Err(Error::new(format!("error at line {}: unexpected addressing mode, found {:?}", lineno, args))),
         }
@@ -652,8 +793,8 @@ fn convert_reg_and_other(
 fn convert_immediate(lineno: usize, value: usize, size: Size) -> Result<Vec<u16>, Error> {
     match size {
         Size::Byte => {
-            if value <= u8::MAX as usize {
-                Ok(vec![value as u16])
+            if fits_in_bits(value, 8) {
+                Ok(vec![value as u16 & 0x00FF])
             } else {
                 Err(Error::new(format!(
                     "error at line {}: immediate number is out of range; must be less than {}, but number is {:?}",
@@ -664,7 +805,7 @@ fn convert_immediate(lineno: usize, value: usize, size: Size) -> Result<Vec<u16>
             }
         },
         Size::Word => {
-            if value <= u16::MAX as usize {
+            if fits_in_bits(value, 16) {
                 Ok(vec![value as u16])
             } else {
                 Err(Error::new(format!(
@@ -679,6 +820,11 @@ fn convert_immediate(lineno: usize, value: usize, size: Size) -> Result<Vec<u16>
     }
 }
 
+/// Returns true if the value fits in the number of bits as either an unsigned number or a negative signed number
+fn fits_in_bits(value: usize, bits: u32) -> bool {
+    value < (1 << bits) || ((value as isize) < 0 && (value as isize) >= -(1 << (bits - 1)))
+}
+
 fn expect_data_register(lineno: usize, operand: &AssemblyOperand) -> Result<u16, Error> {
     if let AssemblyOperand::Register(name) = operand {
         if name.starts_with('d') {
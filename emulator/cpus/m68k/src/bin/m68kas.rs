@@ -1,14 +1,17 @@
 use std::fs;
 use std::env;
+use std::path::Path;
 
 use moa_m68k::M68kType;
 use moa_m68k::assembler::M68kAssembler;
 
 fn main() {
-    let mut assembler = M68kAssembler::new(M68kType::MC68000);
-
     let filename = env::args().nth(1).unwrap();
-    let text = fs::read_to_string(filename).unwrap();
+    let text = fs::read_to_string(&filename).unwrap();
+
+    // Included files are relative to the file being assembled
+    let include_dir = Path::new(&filename).parent().unwrap_or(Path::new("")).to_path_buf();
+    let mut assembler = M68kAssembler::new(M68kType::MC68000).with_include_dir(include_dir);
 
     match assembler.assemble_words(&text) {
         Ok(words) => {
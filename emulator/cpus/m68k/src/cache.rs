@@ -0,0 +1,103 @@
+// Decoded Instruction Cache
+
+use core::fmt;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::instructions::Instruction;
+
+
+/// The number of address bits in each page of memory that is invalidated together
+const PAGE_BITS: u32 = 8;
+
+/// An instruction that was decoded at an address, and the results of decoding it
+#[derive(Clone, Debug)]
+pub struct CachedInstruction {
+    pub is_supervisor: bool,
+    pub end: u32,
+    pub instruction_word: u16,
+    pub instruction: Instruction,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<u32, CachedInstruction>,
+    /// The start addresses of the cached instructions which have words in each page
+    pages: HashMap<u32, Vec<u32>>,
+    hits: u64,
+    misses: u64,
+}
+
+/// A cache of decoded instructions keyed by their address, which is shared between the cpu and the bus so that
+/// any write to memory that holds a cached instruction can invalidate it, including writes made by other devices
+///
+/// The addresses are the ones seen on the bus, so the cache isn't used while the cpu has an MMU
+#[derive(Clone, Default)]
+pub struct InstructionCache(Rc<RefCell<CacheState>>);
+
+impl InstructionCache {
+    pub fn get(&self, start: u32, is_supervisor: bool) -> Option<CachedInstruction> {
+        let mut state = self.0.borrow_mut();
+        let cached = state
+            .entries
+            .get(&start)
+            .filter(|cached| cached.is_supervisor == is_supervisor)
+            .cloned();
+        match cached {
+            Some(_) => state.hits += 1,
+            None => state.misses += 1,
+        }
+        cached
+    }
+
+    pub fn insert(&self, start: u32, cached: CachedInstruction) {
+        let mut state = self.0.borrow_mut();
+        for page in (start >> PAGE_BITS)..=(cached.end.wrapping_sub(1) >> PAGE_BITS).max(start >> PAGE_BITS) {
+            state.pages.entry(page).or_default().push(start);
+        }
+        state.entries.insert(start, cached);
+    }
+
+    /// Forget the instructions in any page of memory that overlaps the given range of addresses
+    pub fn invalidate(&self, addr: u32, len: usize) {
+        let mut state = self.0.borrow_mut();
+        if state.pages.is_empty() {
+            return;
+        }
+        let last = addr.wrapping_add(len.saturating_sub(1) as u32).max(addr);
+        for page in (addr >> PAGE_BITS)..=(last >> PAGE_BITS) {
+            if let Some(starts) = state.pages.remove(&page) {
+                for start in starts {
+                    state.entries.remove(&start);
+                }
+            }
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.0.borrow_mut();
+        state.entries.clear();
+        state.pages.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().entries.is_empty()
+    }
+
+    /// Returns the number of lookups that found a cached instruction, and the number that didn't
+    pub fn stats(&self) -> (u64, u64) {
+        let state = self.0.borrow();
+        (state.hits, state.misses)
+    }
+}
+
+impl fmt::Debug for InstructionCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "InstructionCache({} entries)", self.len())
+    }
+}
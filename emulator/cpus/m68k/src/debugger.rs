@@ -1,10 +1,11 @@
 // m68k Debugger
 
 use core::fmt;
+use std::collections::BTreeMap;
 
 use emulator_hal::{Instant as BusInstant, ErrorType, BusAccess, Inspect, Debug};
 
-use crate::{M68k, M68kError, M68kAddress, M68kCycleExecutor};
+use crate::{M68k, M68kError, M68kAddress, M68kCycleExecutor, Exceptions};
 
 #[derive(Clone, Default)]
 pub struct StackTracer {
@@ -90,6 +91,63 @@ where
 }
 
 
+impl<Instant> M68k<Instant> {
+    /// Enable or disable recording illegal and unsupported instructions instead of stopping on unsupported ones
+    pub fn record_illegal_instructions(&mut self, enable: bool) {
+        self.debugger.illegal_instructions.enabled = enable;
+    }
+
+    pub fn illegal_instructions(&self) -> &IllegalInstructionLog {
+        &self.debugger.illegal_instructions
+    }
+
+    /// Stop before the handler of the given exception vector runs, or stop catching it if `enable` is false
+    pub fn catch_exception_vector(&mut self, vector: u8, enable: bool) {
+        self.debugger.caught_exceptions.retain(|caught| *caught != vector);
+        if enable {
+            self.debugger.caught_exceptions.push(vector);
+        }
+    }
+}
+
+#[rustfmt::skip]
+const EXCEPTION_NAMES: &[(&str, Exceptions)] = &[
+    ("bus",         Exceptions::BusError),
+    ("address",     Exceptions::AddressError),
+    ("illegal",     Exceptions::IllegalInstruction),
+    ("zerodivide",  Exceptions::ZeroDivide),
+    ("chk",         Exceptions::ChkInstruction),
+    ("trapv",       Exceptions::TrapvInstruction),
+    ("privilege",   Exceptions::PrivilegeViolation),
+    ("trace",       Exceptions::Trace),
+    ("linea",       Exceptions::LineAEmulator),
+    ("linef",       Exceptions::LineFEmulator),
+];
+
+/// The first vector of the autovectored interrupts, and of the `TRAP #n` instructions
+const AUTOVECTOR_BASE: u8 = 24;
+const TRAP_BASE: u8 = 32;
+
+/// Parse an exception vector number, or the name of an exception (such as `bus`), an interrupt level (`irq1`
+/// to `irq7`), or a trap instruction (`trap0` to `trap15`)
+pub fn parse_exception_vector(name: &str) -> Option<u8> {
+    let numbered = |prefix: &str, max: u8| {
+        name.strip_prefix(prefix)
+            .and_then(|number| number.parse::<u8>().ok())
+            .filter(|number| *number <= max)
+    };
+
+    if let Some((_, exception)) = EXCEPTION_NAMES.iter().find(|(exception_name, _)| *exception_name == name) {
+        Some(*exception as u8)
+    } else if let Some(level) = numbered("irq", 7).filter(|level| *level > 0) {
+        Some(AUTOVECTOR_BASE + level)
+    } else if let Some(number) = numbered("trap", 15) {
+        Some(TRAP_BASE + number)
+    } else {
+        name.parse::<u8>().ok()
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct M68kDebugger {
     pub(crate) skip_breakpoint: usize,
@@ -97,6 +155,33 @@ pub struct M68kDebugger {
     #[allow(dead_code)]
     pub(crate) step_until_return: Option<usize>,
     pub(crate) stack_tracer: StackTracer,
+    pub(crate) illegal_instructions: IllegalInstructionLog,
+    pub(crate) caught_exceptions: Vec<u8>,
+    /// The vector of a caught exception that was taken in the last step, which is reported before the next one
+    pub(crate) exception_caught: Option<u8>,
+}
+
+/// A count of the illegal and unsupported instructions encountered at each address, which is only recorded
+/// when enabled, and where unsupported instructions are skipped over instead of stopping the cpu
+#[derive(Clone, Default)]
+pub struct IllegalInstructionLog {
+    pub(crate) enabled: bool,
+    entries: BTreeMap<(u32, u16), (usize, String)>,
+}
+
+impl IllegalInstructionLog {
+    pub fn record(&mut self, pc: u32, opcode: u16, description: String) {
+        self.entries.entry((pc, opcode)).or_insert((0, description)).0 += 1;
+    }
+
+    pub fn dump<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        let total: usize = self.entries.values().map(|(count, _)| count).sum();
+        writeln!(writer, "Illegal instructions: {} at {} addresses", total, self.entries.len())?;
+        for ((pc, opcode), (count, description)) in self.entries.iter() {
+            writeln!(writer, "  {:08x}: {:04x} {:>8}x  {}", pc, opcode, count, description)?;
+        }
+        Ok(())
+    }
 }
 
 impl<'a, Bus, BusError, Instant> M68kCycleExecutor<'a, Bus, Instant>
@@ -104,6 +189,15 @@ where
     Bus: BusAccess<M68kAddress, Instant = Instant, Error = BusError>,
     Instant: Copy,
 {
+    /// Record the exception if the debugger is catching it, which includes interrupts that weren't autovectored
+    /// but have a level that's being caught
+    pub fn check_caught_exception(&mut self, number: u8, is_interrupt: bool) {
+        let caught = &self.debugger.caught_exceptions;
+        if caught.contains(&number) || (is_interrupt && caught.contains(&(AUTOVECTOR_BASE + self.state.current_ipl as u8))) {
+            self.debugger.exception_caught = Some(number);
+        }
+    }
+
     pub fn check_breakpoints(&mut self) -> Result<(), M68kError<BusError>> {
         for breakpoint in &self.debugger.breakpoints {
             if *breakpoint == self.state.pc {
@@ -118,4 +212,28 @@ where
         }
         Ok(())
     }
+
+    /// Record the instruction if the result is an illegal or unsupported instruction, and recording is enabled.
+    /// Illegal instructions still cause an exception, but unsupported ones are skipped
+    pub fn record_illegal_instruction(&mut self, result: Result<(), M68kError<BusError>>) -> Result<(), M68kError<BusError>> {
+        if !self.debugger.illegal_instructions.enabled {
+            return result;
+        }
+
+        let pc = self.cycle.decoder.start;
+        let opcode = self.cycle.decoder.instruction_word;
+        match result {
+            Err(M68kError::Exception(Exceptions::IllegalInstruction)) => {
+                self.debugger.illegal_instructions.record(pc, opcode, "illegal".to_string());
+                result
+            },
+            Err(M68kError::UnsupportedInstruction(instruction)) => {
+                self.debugger
+                    .illegal_instructions
+                    .record(pc, opcode, format!("unsupported: {}", instruction));
+                Ok(())
+            },
+            result => result,
+        }
+    }
 }
@@ -1,13 +1,17 @@
 // Instruction Decoding
 
 use core::marker::PhantomData;
+use core::ops::Range;
 use emulator_hal::BusAccess;
 
 use crate::{M68kType, M68kError, M68kBusPort, M68kAddress, Exceptions};
 use crate::instructions::{
     Size, Sign, Direction, XRegister, BaseRegister, IndexRegister, RegOrImmediate, ControlRegister, Condition, Target, Instruction,
-    sign_extend_to_long,
+    FpSize, FpOperation, FpOperand, FpCondition, sign_extend_to_long,
 };
+use crate::fpu;
+use crate::cache::CachedInstruction;
+use crate::disassembler::DisassembledInstruction;
 
 
 const OPCG_BIT_OPS: u8 = 0x0;
@@ -32,6 +36,8 @@ const OPCG_FLINE: u8 = 0xF;
 pub struct M68kDecoder<Instant> {
     pub cputype: M68kType,
     pub is_supervisor: bool,
+    /// Whether to decode the floating point coprocessor's instructions, instead of line F exceptions
+    pub fpu: bool,
     pub start: u32,
     pub end: u32,
     pub instruction_word: u16,
@@ -57,6 +63,7 @@ where
         M68kDecoder {
             cputype,
             is_supervisor,
+            fpu: false,
             start,
             end: start,
             instruction_word: 0,
@@ -65,6 +72,12 @@ where
         }
     }
 
+    #[inline]
+    pub fn with_fpu(mut self, fpu: bool) -> Self {
+        self.fpu = fpu;
+        self
+    }
+
     #[inline]
     pub fn init(&mut self, is_supervisor: bool, start: u32) {
         self.is_supervisor = is_supervisor;
@@ -84,12 +97,28 @@ where
         Bus: BusAccess<M68kAddress, Instant = Instant>,
     {
         self.init(is_supervisor, start);
+        if let Some(cached) = memory.instruction_cache().and_then(|cache| cache.get(start, is_supervisor)) {
+            self.end = cached.end;
+            self.instruction_word = cached.instruction_word;
+            self.instruction = cached.instruction;
+            return Ok(());
+        }
+
         let mut decoding = InstructionDecoding {
             bus,
-            memory,
+            memory: &mut *memory,
             decoder: self,
         };
         self.instruction = decoding.decode_next()?;
+
+        if let Some(cache) = memory.instruction_cache() {
+            cache.insert(start, CachedInstruction {
+                is_supervisor,
+                end: self.end,
+                instruction_word: self.instruction_word,
+                instruction: self.instruction.clone(),
+            });
+        }
         Ok(())
     }
 
@@ -124,6 +153,38 @@ where
             .collect();
         println!("{:#010x}: {}\n\t{}\n", self.start, ins_data.unwrap(), self.instruction);
     }
+
+    /// Decode the instructions that start in the given range of addresses, stopping early at the first one
+    /// that can't be decoded
+    pub fn disassemble<Bus>(
+        &mut self,
+        bus: &mut Bus,
+        memory: &mut M68kBusPort<Instant>,
+        range: Range<u32>,
+    ) -> Vec<DisassembledInstruction>
+    where
+        Bus: BusAccess<M68kAddress, Instant = Instant>,
+    {
+        let mut instructions = vec![];
+        let mut next = range.start;
+        while next < range.end {
+            if self.decode_at(bus, memory, self.is_supervisor, next).is_err() {
+                break;
+            }
+
+            let words: Result<Vec<u16>, _> = (self.start..self.end)
+                .step_by(2)
+                .map(|addr| bus.read_beu16(memory.current_clock, addr))
+                .collect();
+            let Ok(words) = words else {
+                break;
+            };
+
+            instructions.push(DisassembledInstruction::new(self.start, words, self.instruction.clone()));
+            next = self.end;
+        }
+        instructions
+    }
 }
 
 impl<'a, Bus, Instant> InstructionDecoding<'a, Bus, Instant>
@@ -152,7 +213,7 @@ where
             OPCG_MUL_AND => self.decode_group_mul_and(ins),
             OPCG_ADD => self.decode_group_add(ins),
             OPCG_SHIFT => self.decode_group_shift(ins),
-            OPCG_FLINE => Ok(Instruction::UnimplementedF(ins)),
+            OPCG_FLINE => self.decode_group_fline(ins),
             _ => Err(M68kError::Exception(Exceptions::IllegalInstruction)),
         }
     }
@@ -738,6 +799,107 @@ where
         }
     }
 
+    #[inline]
+    fn decode_group_fline(&mut self, ins: u16) -> Result<Instruction, M68kError<Bus::Error>> {
+        // Only the floating point coprocessor is supported, which has coprocessor id 1
+        if !self.decoder.fpu || self.decoder.cputype < M68kType::MC68020 || (ins & 0x0E00) != 0x0200 {
+            return Ok(Instruction::UnimplementedF(ins));
+        }
+
+        match (ins & 0x01C0) >> 6 {
+            0b000 => self.decode_fpu_general(ins),
+            0b010 | 0b011 => {
+                let cond = FpCondition((ins & 0x003F) as u8);
+                let offset = if (ins & 0x0040) == 0 {
+                    sign_extend_to_long(self.read_instruction_word()? as u32, Size::Word)
+                } else {
+                    self.read_instruction_long()? as i32
+                };
+                Ok(Instruction::FBcc(cond, offset))
+            },
+            0b100 => Ok(Instruction::FSAVE(self.decode_lower_effective_address(ins, None)?)),
+            0b101 => Ok(Instruction::FRESTORE(self.decode_lower_effective_address(ins, None)?)),
+            // FScc, FDBcc, and FTRAPcc are left for software to emulate
+            _ => Ok(Instruction::UnimplementedF(ins)),
+        }
+    }
+
+    fn decode_fpu_general(&mut self, ins: u16) -> Result<Instruction, M68kError<Bus::Error>> {
+        let ext = self.read_instruction_word()?;
+        let src_spec = ((ext & 0x1C00) >> 10) as u8;
+        let dest_reg = ((ext & 0x0380) >> 7) as u8;
+        let dir = if (ext & 0x2000) == 0 {
+            Direction::FromTarget
+        } else {
+            Direction::ToTarget
+        };
+
+        match (ext & 0xE000) >> 13 {
+            0b000 => match get_fp_operation(ext) {
+                Some(op) => Ok(Instruction::FOP(op, FpOperand::Register(src_spec), dest_reg)),
+                None => Ok(Instruction::UnimplementedF(ins)),
+            },
+            0b010 if src_spec == 0b111 => {
+                Ok(Instruction::FOP(FpOperation::Move, FpOperand::Constant((ext & 0x007F) as u8), dest_reg))
+            },
+            0b010 => match (get_fp_operation(ext), get_fp_size(src_spec)) {
+                (Some(op), size) if size != FpSize::Packed => {
+                    let src = self.decode_fp_operand(ins, size)?;
+                    Ok(Instruction::FOP(op, src, dest_reg))
+                },
+                _ => Ok(Instruction::UnimplementedF(ins)),
+            },
+            0b011 => match get_fp_size(src_spec) {
+                FpSize::Packed => Ok(Instruction::UnimplementedF(ins)),
+                size => {
+                    let target = self.decode_lower_effective_address(ins, None)?;
+                    Ok(Instruction::FMOVEfromFP(dest_reg, target, size))
+                },
+            },
+            0b100 | 0b101 => {
+                let target = self.decode_lower_effective_address(ins, Some(Size::Long))?;
+                Ok(Instruction::FMOVEMcontrol(target, src_spec, dir))
+            },
+            0b110 | 0b111 => {
+                let mask = if (ext & 0x0800) == 0 {
+                    RegOrImmediate::Immediate(ext as u8)
+                } else {
+                    RegOrImmediate::DReg(((ext & 0x0070) >> 4) as u8)
+                };
+                let target = self.decode_lower_effective_address(ins, None)?;
+                Ok(Instruction::FMOVEM(target, mask, dir))
+            },
+            _ => Ok(Instruction::UnimplementedF(ins)),
+        }
+    }
+
+    fn decode_fp_operand(&mut self, ins: u16, size: FpSize) -> Result<FpOperand, M68kError<Bus::Error>> {
+        let mode = get_low_mode(ins);
+        let reg = get_low_reg(ins);
+
+        // Immediate data is converted now, since it can be longer than a Target::Immediate can hold
+        if mode == 0b111 && reg == 0b100 {
+            let value = match size {
+                FpSize::Byte => self.read_instruction_word()? as i8 as f64,
+                FpSize::Word => self.read_instruction_word()? as i16 as f64,
+                FpSize::Long => self.read_instruction_long()? as i32 as f64,
+                FpSize::Single => f32::from_bits(self.read_instruction_long()?) as f64,
+                FpSize::Double => {
+                    let high = self.read_instruction_long()? as u64;
+                    let low = self.read_instruction_long()? as u64;
+                    f64::from_bits((high << 32) | low)
+                },
+                FpSize::Extended | FpSize::Packed => {
+                    let words = [self.read_instruction_long()?, self.read_instruction_long()?, self.read_instruction_long()?];
+                    fpu::extended_to_f64(words)
+                },
+            };
+            Ok(FpOperand::Immediate(value.to_bits()))
+        } else {
+            Ok(FpOperand::Memory(self.get_mode_as_target(mode, reg, None)?, size))
+        }
+    }
+
     fn read_instruction_word(&mut self) -> Result<u16, M68kError<Bus::Error>> {
         let word = self
             .memory
@@ -891,6 +1053,44 @@ where
     }
 }
 
+fn get_fp_size(spec: u8) -> FpSize {
+    match spec {
+        0b000 => FpSize::Long,
+        0b001 => FpSize::Single,
+        0b010 => FpSize::Extended,
+        0b100 => FpSize::Word,
+        0b101 => FpSize::Double,
+        0b110 => FpSize::Byte,
+        _ => FpSize::Packed,
+    }
+}
+
+fn get_fp_operation(ext: u16) -> Option<FpOperation> {
+    match ext & 0x007F {
+        0x00 => Some(FpOperation::Move),
+        0x01 => Some(FpOperation::Int),
+        0x03 => Some(FpOperation::IntRZ),
+        0x04 => Some(FpOperation::Sqrt),
+        0x0A => Some(FpOperation::Atan),
+        0x0E => Some(FpOperation::Sin),
+        0x0F => Some(FpOperation::Tan),
+        0x10 => Some(FpOperation::Etox),
+        0x14 => Some(FpOperation::Logn),
+        0x15 => Some(FpOperation::Log10),
+        0x16 => Some(FpOperation::Log2),
+        0x18 => Some(FpOperation::Abs),
+        0x1A => Some(FpOperation::Neg),
+        0x1D => Some(FpOperation::Cos),
+        0x20 => Some(FpOperation::Div),
+        0x22 => Some(FpOperation::Add),
+        0x23 => Some(FpOperation::Mul),
+        0x28 => Some(FpOperation::Sub),
+        0x38 => Some(FpOperation::Cmp),
+        0x3A => Some(FpOperation::Tst),
+        _ => None,
+    }
+}
+
 #[inline(always)]
 fn get_high_reg(ins: u16) -> u8 {
     ((ins & 0x0E00) >> 9) as u8
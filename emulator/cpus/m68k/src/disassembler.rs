@@ -0,0 +1,288 @@
+// Structured Disassembly
+
+use std::fmt;
+
+use crate::instructions::{
+    Register, XRegister, BaseRegister, RegOrImmediate, ControlRegister, Target, Instruction, FpOperation, FpOperand, Direction,
+    fp_control,
+};
+
+
+/// An operand of a disassembled instruction, in the order they're written in the assembly syntax
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    /// An effective address, including immediate values and data and address registers used as effective addresses
+    Target(Target),
+    DataRegister(Register),
+    AddressRegister(Register),
+    FpRegister(Register),
+    /// A value encoded into the instruction itself, such as the data of `moveq` or the vector of `trap`
+    Immediate(u32),
+    /// The absolute address that a branch goes to
+    BranchTarget(u32),
+    /// The stack adjustment of `link` and `rtd`
+    Displacement(i32),
+    /// The optional high register and the low register of a 64-bit multiply or divide
+    RegisterPair(Option<Register>, Register),
+    /// The registers moved by `movem`, in the order they're stored in memory
+    RegisterList(Vec<XRegister>),
+    /// The floating point registers moved by `fmovem`
+    FpRegisterList(Vec<Register>),
+    /// The floating point control registers moved by `fmovem`, as a mask of the `fp_control` bits
+    FpControlRegisters(u8),
+    /// The source operand of a floating point operation
+    FpOperand(FpOperand),
+    /// The offset and width of a bit field
+    BitField(RegOrImmediate, RegOrImmediate),
+    StatusRegister,
+    ConditionCodeRegister,
+    UserStackPointer,
+    ControlRegister(ControlRegister),
+}
+
+/// A decoded instruction with its address and the words it was decoded from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisassembledInstruction {
+    pub address: u32,
+    pub words: Vec<u16>,
+    pub instruction: Instruction,
+    /// The mnemonic, including the size suffix (eg. "movel")
+    pub mnemonic: String,
+    pub operands: Vec<Operand>,
+}
+
+impl DisassembledInstruction {
+    pub fn new(address: u32, words: Vec<u16>, instruction: Instruction) -> Self {
+        let text = instruction.to_string();
+        let mnemonic = text.split('\t').next().unwrap_or_default().to_string();
+        let operands = get_operands(address, &instruction);
+        Self {
+            address,
+            words,
+            instruction,
+            mnemonic,
+            operands,
+        }
+    }
+
+    /// Returns the instruction words as big endian bytes
+    pub fn bytes(&self) -> Vec<u8> {
+        self.words.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+
+    /// Returns the address after the end of the instruction
+    pub fn end(&self) -> u32 {
+        self.address.wrapping_add(self.words.len() as u32 * 2)
+    }
+
+    /// Returns the address that the instruction can branch to, if it's a branch with a fixed destination
+    pub fn branch_target(&self) -> Option<u32> {
+        self.operands.iter().find_map(|operand| match operand {
+            Operand::BranchTarget(addr) => Some(*addr),
+            _ => None,
+        })
+    }
+}
+
+impl fmt::Display for DisassembledInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.instruction)
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Target(target) => write!(f, "{}", target),
+            Operand::DataRegister(reg) => write!(f, "%d{}", reg),
+            Operand::AddressRegister(reg) => write!(f, "%a{}", reg),
+            Operand::FpRegister(reg) => write!(f, "%fp{}", reg),
+            Operand::Immediate(value) => write!(f, "#{:#x}", value),
+            Operand::BranchTarget(addr) => write!(f, "{:#010x}", addr),
+            Operand::Displacement(offset) => write!(f, "#{}", offset),
+            Operand::RegisterPair(Some(high), low) => write!(f, "%d{}:%d{}", high, low),
+            Operand::RegisterPair(None, low) => write!(f, "%d{}", low),
+            Operand::RegisterList(regs) => {
+                let names = regs.iter().map(|reg| format!("%{}", reg)).collect::<Vec<String>>();
+                write!(f, "{}", names.join("/"))
+            },
+            Operand::FpRegisterList(regs) => {
+                let names = regs.iter().map(|reg| format!("%fp{}", reg)).collect::<Vec<String>>();
+                write!(f, "{}", names.join("/"))
+            },
+            Operand::FpControlRegisters(mask) => {
+                let names = [(fp_control::FPCR, "%fpcr"), (fp_control::FPSR, "%fpsr"), (fp_control::FPIAR, "%fpiar")]
+                    .into_iter()
+                    .filter(|(bit, _)| mask & bit != 0)
+                    .map(|(_, name)| name)
+                    .collect::<Vec<&str>>();
+                write!(f, "{}", names.join("/"))
+            },
+            Operand::FpOperand(operand) => write!(f, "{}", operand),
+            Operand::BitField(offset, width) => write!(f, "{{{}:{}}}", offset, width),
+            Operand::StatusRegister => write!(f, "%sr"),
+            Operand::ConditionCodeRegister => write!(f, "%ccr"),
+            Operand::UserStackPointer => write!(f, "%usp"),
+            Operand::ControlRegister(reg) => write!(f, "{}", reg),
+        }
+    }
+}
+
+/// Returns the operands of the instruction at the given address, in the order they're written
+fn get_operands(address: u32, instruction: &Instruction) -> Vec<Operand> {
+    // Branch offsets are relative to the address after the opcode word
+    let branch = |offset: i32| Operand::BranchTarget(address.wrapping_add(2).wrapping_add(offset as u32));
+    let target = |target: &Target| Operand::Target(*target);
+    let ordered = |dir: &Direction, target: Operand, other: Operand| match dir {
+        Direction::ToTarget => vec![other, target],
+        Direction::FromTarget => vec![target, other],
+    };
+
+    match instruction {
+        Instruction::ABCD(src, dest)
+        | Instruction::ADD(src, dest, _)
+        | Instruction::ADDX(src, dest, _)
+        | Instruction::AND(src, dest, _)
+        | Instruction::ASL(src, dest, _)
+        | Instruction::ASR(src, dest, _)
+        | Instruction::BCHG(src, dest, _)
+        | Instruction::BCLR(src, dest, _)
+        | Instruction::BSET(src, dest, _)
+        | Instruction::BTST(src, dest, _)
+        | Instruction::CMP(src, dest, _)
+        | Instruction::EOR(src, dest, _)
+        | Instruction::EXG(src, dest)
+        | Instruction::LSL(src, dest, _)
+        | Instruction::LSR(src, dest, _)
+        | Instruction::MOVE(src, dest, _)
+        | Instruction::OR(src, dest, _)
+        | Instruction::ROL(src, dest, _)
+        | Instruction::ROR(src, dest, _)
+        | Instruction::ROXL(src, dest, _)
+        | Instruction::ROXR(src, dest, _)
+        | Instruction::SBCD(src, dest)
+        | Instruction::SUB(src, dest, _)
+        | Instruction::SUBX(src, dest, _) => vec![target(src), target(dest)],
+
+        Instruction::ADDA(src, reg, _)
+        | Instruction::CMPA(src, reg, _)
+        | Instruction::LEA(src, reg)
+        | Instruction::MOVEA(src, reg, _)
+        | Instruction::SUBA(src, reg, _) => vec![target(src), Operand::AddressRegister(*reg)],
+
+        Instruction::CHK(src, reg, _) | Instruction::DIVW(src, reg, _) | Instruction::MULW(src, reg, _) => {
+            vec![target(src), Operand::DataRegister(*reg)]
+        },
+        Instruction::DIVL(src, high, low, _) | Instruction::MULL(src, high, low, _) => {
+            vec![target(src), Operand::RegisterPair(*high, *low)]
+        },
+
+        Instruction::ANDtoCCR(value) | Instruction::EORtoCCR(value) | Instruction::ORtoCCR(value) => {
+            vec![Operand::Immediate(*value as u32), Operand::ConditionCodeRegister]
+        },
+        Instruction::ANDtoSR(value) | Instruction::EORtoSR(value) | Instruction::ORtoSR(value) => {
+            vec![Operand::Immediate(*value as u32), Operand::StatusRegister]
+        },
+
+        Instruction::Bcc(_, offset) | Instruction::BRA(offset) | Instruction::BSR(offset) | Instruction::FBcc(_, offset) => {
+            vec![branch(*offset)]
+        },
+        Instruction::DBcc(_, reg, offset) => vec![Operand::DataRegister(*reg), branch(*offset as i32)],
+
+        Instruction::BFCHG(dest, offset, width)
+        | Instruction::BFCLR(dest, offset, width)
+        | Instruction::BFSET(dest, offset, width)
+        | Instruction::BFTST(dest, offset, width) => vec![target(dest), Operand::BitField(*offset, *width)],
+        Instruction::BFEXTS(src, offset, width, reg)
+        | Instruction::BFEXTU(src, offset, width, reg)
+        | Instruction::BFFFO(src, offset, width, reg) => {
+            vec![target(src), Operand::BitField(*offset, *width), Operand::DataRegister(*reg)]
+        },
+        Instruction::BFINS(reg, dest, offset, width) => {
+            vec![Operand::DataRegister(*reg), target(dest), Operand::BitField(*offset, *width)]
+        },
+
+        Instruction::BKPT(value) | Instruction::TRAP(value) => vec![Operand::Immediate(*value as u32)],
+        Instruction::STOP(value) => vec![Operand::Immediate(*value as u32)],
+        Instruction::MOVEQ(value, reg) => vec![Operand::Immediate(*value as u32), Operand::DataRegister(*reg)],
+
+        Instruction::CLR(dest, _)
+        | Instruction::FRESTORE(dest)
+        | Instruction::FSAVE(dest)
+        | Instruction::JMP(dest)
+        | Instruction::JSR(dest)
+        | Instruction::NBCD(dest)
+        | Instruction::NEG(dest, _)
+        | Instruction::NEGX(dest, _)
+        | Instruction::NOT(dest, _)
+        | Instruction::PEA(dest)
+        | Instruction::Scc(_, dest)
+        | Instruction::TAS(dest)
+        | Instruction::TST(dest, _) => vec![target(dest)],
+
+        Instruction::EXT(reg, _, _) | Instruction::SWAP(reg) => vec![Operand::DataRegister(*reg)],
+        Instruction::UNLK(reg) => vec![Operand::AddressRegister(*reg)],
+        Instruction::LINK(reg, offset) => vec![Operand::AddressRegister(*reg), Operand::Displacement(*offset)],
+        Instruction::RTD(offset) => vec![Operand::Displacement(*offset as i32)],
+
+        Instruction::FOP(FpOperation::Tst, src, _) => vec![Operand::FpOperand(*src)],
+        Instruction::FOP(_, src, reg) => vec![Operand::FpOperand(*src), Operand::FpRegister(*reg)],
+        Instruction::FMOVEfromFP(reg, dest, _) => vec![Operand::FpRegister(*reg), target(dest)],
+        Instruction::FMOVEMcontrol(ea, mask, dir) => ordered(dir, target(ea), Operand::FpControlRegisters(*mask)),
+        Instruction::FMOVEM(ea, RegOrImmediate::DReg(reg), dir) => ordered(dir, target(ea), Operand::DataRegister(*reg)),
+        Instruction::FMOVEM(ea, RegOrImmediate::Immediate(mask), dir) => {
+            ordered(dir, target(ea), Operand::FpRegisterList(fmovem_registers(*mask, ea)))
+        },
+
+        Instruction::MOVEfromSR(ea) => vec![Operand::StatusRegister, target(ea)],
+        Instruction::MOVEtoSR(ea) => vec![target(ea), Operand::StatusRegister],
+        Instruction::MOVEfromCCR(ea) => vec![Operand::ConditionCodeRegister, target(ea)],
+        Instruction::MOVEtoCCR(ea) => vec![target(ea), Operand::ConditionCodeRegister],
+        Instruction::MOVEC(ea, reg, dir) => ordered(dir, target(ea), Operand::ControlRegister(*reg)),
+        Instruction::MOVEUSP(ea, dir) => ordered(dir, target(ea), Operand::UserStackPointer),
+        Instruction::MOVEM(ea, _, dir, mask) => ordered(dir, target(ea), Operand::RegisterList(movem_registers(*mask, ea))),
+        Instruction::MOVEP(dreg, areg, offset, _, dir) => {
+            let memory = Operand::Target(Target::IndirectRegOffset(BaseRegister::AReg(*areg), None, *offset as i32));
+            ordered(dir, memory, Operand::DataRegister(*dreg))
+        },
+
+        Instruction::UnimplementedA(ins) | Instruction::UnimplementedF(ins) => vec![Operand::Immediate(*ins as u32)],
+
+        Instruction::ILLEGAL
+        | Instruction::NOP
+        | Instruction::RESET
+        | Instruction::RTE
+        | Instruction::RTR
+        | Instruction::RTS
+        | Instruction::TRAPV => vec![],
+    }
+}
+
+/// Returns the registers in the mask of a `movem`, where the predecrement mode has the bits in the opposite order
+fn movem_registers(mask: u16, target: &Target) -> Vec<XRegister> {
+    let reversed = matches!(target, Target::IndirectARegDec(_));
+    (0..16)
+        .filter(|reg| {
+            let bit = if reversed { 0x8000 >> reg } else { 0x0001 << reg };
+            mask & bit != 0
+        })
+        .map(|reg| {
+            if reg < 8 {
+                XRegister::DReg(reg)
+            } else {
+                XRegister::AReg(reg - 8)
+            }
+        })
+        .collect()
+}
+
+/// Returns the registers in the mask of an `fmovem`, where the predecrement mode has the bits in the opposite order
+fn fmovem_registers(mask: u8, target: &Target) -> Vec<Register> {
+    let reversed = matches!(target, Target::IndirectARegDec(_));
+    (0..8)
+        .filter(|reg| {
+            let bit = if reversed { 0x01 << reg } else { 0x80 >> reg };
+            mask & bit != 0
+        })
+        .collect()
+}
@@ -8,9 +8,10 @@ use crate::memory::{MemType, MemAccess, M68kBusPort, M68kAddress};
 use crate::decode::M68kDecoder;
 use crate::debugger::M68kDebugger;
 use crate::timing::M68kInstructionTiming;
+use crate::fpu::{self, fpsr};
 use crate::instructions::{
     Register, Size, Sign, Direction, XRegister, BaseRegister, IndexRegister, RegOrImmediate, ControlRegister, Condition, Target,
-    Instruction, sign_extend_to_long,
+    Instruction, FpSize, FpOperation, FpOperand, FpCondition, fp_control, sign_extend_to_long,
 };
 
 
@@ -49,9 +50,11 @@ where
     pub fn new(cpu: &M68k<Instant>, clock: Instant) -> Self {
         let is_supervisor = cpu.state.sr & (Flags::Supervisor as u16) != 0;
         Self {
-            decoder: M68kDecoder::new(cpu.info.chip, is_supervisor, cpu.state.pc),
+            decoder: M68kDecoder::new(cpu.info.chip, is_supervisor, cpu.state.pc).with_fpu(cpu.info.fpu),
             timing: M68kInstructionTiming::new(cpu.info.chip, cpu.info.data_width as u8),
-            memory: M68kBusPort::from_info(&cpu.info, clock),
+            memory: M68kBusPort::from_info(&cpu.info, clock)
+                .with_mmu(cpu.mmu.clone())
+                .with_cache(cpu.cache.clone()),
             current_clock: clock,
         }
     }
@@ -155,6 +158,7 @@ where
         self.check_breakpoints()?;
 
         let result = self.decode_and_execute();
+        let result = self.record_illegal_instruction(result);
         self.process_error(result)?;
 
         // TODO this is called by the step function directly, but should be integrated better
@@ -197,7 +201,8 @@ where
     }
 
     pub fn exception(&mut self, number: u8, is_interrupt: bool) -> Result<(), M68kError<Bus::Error>> {
-        log::debug!("{}: raising exception {}", DEV_NAME, number);
+        log::debug!(target: DEV_NAME, "raising exception {}", number);
+        self.check_caught_exception(number, is_interrupt);
 
         if number == Exceptions::BusError as u8 || number == Exceptions::AddressError as u8 {
             let result = self.setup_group0_exception(number);
@@ -343,6 +348,13 @@ where
             Instruction::EORtoSR(value) => self.execute_eor_to_sr(value),
             Instruction::EXG(target1, target2) => self.execute_exg(target1, target2),
             Instruction::EXT(reg, from_size, to_size) => self.execute_ext(reg, from_size, to_size),
+            Instruction::FBcc(cond, offset) => self.execute_fbcc(cond, offset),
+            Instruction::FOP(op, src, reg) => self.execute_fop(op, src, reg),
+            Instruction::FMOVEfromFP(reg, target, size) => self.execute_fmove_from_fp(reg, target, size),
+            Instruction::FMOVEMcontrol(target, mask, dir) => self.execute_fmovem_control(target, mask, dir),
+            Instruction::FMOVEM(target, mask, dir) => self.execute_fmovem(target, mask, dir),
+            Instruction::FRESTORE(target) => self.execute_frestore(target),
+            Instruction::FSAVE(target) => self.execute_fsave(target),
             Instruction::ILLEGAL => self.execute_illegal(),
             Instruction::JMP(target) => self.execute_jmp(target),
             Instruction::JSR(target) => self.execute_jsr(target),
@@ -395,7 +407,7 @@ where
             Instruction::UnimplementedA(value) => self.execute_unimplemented_a(value),
             Instruction::UnimplementedF(value) => self.execute_unimplemented_f(value),
             _ => {
-                return Err(M68kError::Other("Unsupported instruction".to_string()));
+                return Err(M68kError::UnsupportedInstruction(self.cycle.decoder.instruction.clone()));
             },
         }?;
 
@@ -881,6 +893,206 @@ where
         Ok(())
     }
 
+    fn execute_fbcc(&mut self, cond: FpCondition, offset: i32) -> Result<(), M68kError<Bus::Error>> {
+        let should_branch = self.state.fpu.test_condition(cond);
+        if should_branch {
+            if let Err(err) = self.set_pc(self.cycle.decoder.start.wrapping_add(2).wrapping_add(offset as u32)) {
+                self.state.pc -= 2;
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    fn execute_fop(&mut self, op: FpOperation, src: FpOperand, reg: Register) -> Result<(), M68kError<Bus::Error>> {
+        self.state.fpu.fpiar = self.cycle.decoder.start;
+        self.state.fpu.clear_exceptions();
+
+        let src = self.get_fp_operand(src)?;
+        let dest = self.state.fpu.fp_reg[reg as usize];
+        let result = match op {
+            FpOperation::Move => src,
+            FpOperation::Int => fpu::round_to_integer(src, self.state.fpu.rounding()),
+            FpOperation::IntRZ => src.trunc(),
+            FpOperation::Sqrt => src.sqrt(),
+            FpOperation::Abs => src.abs(),
+            FpOperation::Neg => -src,
+            FpOperation::Sin => src.sin(),
+            FpOperation::Cos => src.cos(),
+            FpOperation::Tan => src.tan(),
+            FpOperation::Atan => src.atan(),
+            FpOperation::Etox => src.exp(),
+            FpOperation::Logn | FpOperation::Log10 | FpOperation::Log2 => {
+                if src == 0.0 {
+                    self.state.fpu.set_exception(fpsr::DZ, fpsr::ACCRUED_DZ);
+                }
+                match op {
+                    FpOperation::Logn => src.ln(),
+                    FpOperation::Log10 => src.log10(),
+                    _ => src.log2(),
+                }
+            },
+            FpOperation::Add => dest + src,
+            FpOperation::Sub => dest - src,
+            FpOperation::Mul => dest * src,
+            FpOperation::Div => {
+                if src == 0.0 && dest.is_finite() && dest != 0.0 {
+                    self.state.fpu.set_exception(fpsr::DZ, fpsr::ACCRUED_DZ);
+                }
+                dest / src
+            },
+            FpOperation::Cmp => {
+                self.state.fpu.set_compare_codes(dest, src);
+                return Ok(());
+            },
+            FpOperation::Tst => {
+                self.state.fpu.set_condition_codes(src);
+                return Ok(());
+            },
+        };
+
+        // A NaN that didn't come from one of the operands is the result of an invalid operation
+        let is_dyadic = matches!(op, FpOperation::Add | FpOperation::Sub | FpOperation::Mul | FpOperation::Div);
+        if result.is_nan() && !src.is_nan() && !(is_dyadic && dest.is_nan()) {
+            self.state.fpu.set_exception(fpsr::OPERR, fpsr::ACCRUED_IOP);
+        }
+
+        let result = self.state.fpu.round_precision(result);
+        self.state.fpu.fp_reg[reg as usize] = result;
+        self.state.fpu.set_condition_codes(result);
+        Ok(())
+    }
+
+    fn execute_fmove_from_fp(&mut self, reg: Register, target: Target, size: FpSize) -> Result<(), M68kError<Bus::Error>> {
+        self.state.fpu.fpiar = self.cycle.decoder.start;
+        self.state.fpu.clear_exceptions();
+
+        let value = self.state.fpu.fp_reg[reg as usize];
+        match size {
+            FpSize::Byte => {
+                let value = self.state.fpu.to_integer(value, i8::MIN as i64, i8::MAX as i64);
+                self.set_target_value(target, value as u32, Size::Byte, Used::Once)?;
+            },
+            FpSize::Word => {
+                let value = self.state.fpu.to_integer(value, i16::MIN as i64, i16::MAX as i64);
+                self.set_target_value(target, value as u32, Size::Word, Used::Once)?;
+            },
+            FpSize::Long => {
+                let value = self.state.fpu.to_integer(value, i32::MIN as i64, i32::MAX as i64);
+                self.set_target_value(target, value as u32, Size::Long, Used::Once)?;
+            },
+            FpSize::Single => {
+                self.set_target_value(target, (value as f32).to_bits(), Size::Long, Used::Once)?;
+            },
+            FpSize::Double => {
+                let addr = self.get_fp_target_address(target, size.in_bytes())?;
+                let bits = value.to_bits();
+                self.set_address_sized(addr, (bits >> 32) as u32, Size::Long)?;
+                self.set_address_sized(addr.wrapping_add(4), bits as u32, Size::Long)?;
+            },
+            FpSize::Extended | FpSize::Packed => {
+                let addr = self.get_fp_target_address(target, size.in_bytes())?;
+                self.set_fp_extended(addr, value)?;
+            },
+        }
+        Ok(())
+    }
+
+    fn execute_fmovem_control(&mut self, target: Target, mask: u8, dir: Direction) -> Result<(), M68kError<Bus::Error>> {
+        let selected: Vec<u8> = [fp_control::FPCR, fp_control::FPSR, fp_control::FPIAR]
+            .into_iter()
+            .filter(|bit| mask & bit != 0)
+            .collect();
+
+        // Only one control register can be moved to or from a data register, so they're all treated as one long word
+        let mut addr = match target {
+            Target::DirectDReg(_) | Target::DirectAReg(_) | Target::Immediate(_) => None,
+            _ => Some(self.get_fp_target_address(target, selected.len() as u32 * 4)?),
+        };
+
+        for bit in selected {
+            match (dir, addr) {
+                (Direction::FromTarget, None) => {
+                    *self.get_fp_control_reg_mut(bit) = self.get_target_value(target, Size::Long, Used::Once)?;
+                },
+                (Direction::ToTarget, None) => {
+                    let value = *self.get_fp_control_reg_mut(bit);
+                    self.set_target_value(target, value, Size::Long, Used::Once)?;
+                },
+                (Direction::FromTarget, Some(current)) => {
+                    *self.get_fp_control_reg_mut(bit) = self.get_address_sized(current, Size::Long)?;
+                    addr = Some(current.wrapping_add(4));
+                },
+                (Direction::ToTarget, Some(current)) => {
+                    let value = *self.get_fp_control_reg_mut(bit);
+                    self.set_address_sized(current, value, Size::Long)?;
+                    addr = Some(current.wrapping_add(4));
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn execute_fmovem(&mut self, target: Target, mask: RegOrImmediate, dir: Direction) -> Result<(), M68kError<Bus::Error>> {
+        let mask = match mask {
+            RegOrImmediate::DReg(reg) => self.state.d_reg[reg as usize] as u8,
+            RegOrImmediate::Immediate(mask) => mask,
+        };
+
+        // The predecrement mode has the bits in the opposite order, but the registers are always in ascending order in memory
+        let reversed = matches!(target, Target::IndirectARegDec(_));
+        let selected: Vec<usize> = (0..8)
+            .filter(|reg| {
+                let bit = if reversed { 0x01 << reg } else { 0x80 >> reg };
+                mask & bit != 0
+            })
+            .collect();
+
+        let mut addr = self.get_fp_target_address(target, selected.len() as u32 * FpSize::Extended.in_bytes())?;
+        for reg in selected {
+            match dir {
+                Direction::ToTarget => self.set_fp_extended(addr, self.state.fpu.fp_reg[reg])?,
+                Direction::FromTarget => self.state.fpu.fp_reg[reg] = self.get_fp_extended(addr)?,
+            }
+            addr = addr.wrapping_add(FpSize::Extended.in_bytes());
+        }
+        Ok(())
+    }
+
+    fn execute_frestore(&mut self, target: Target) -> Result<(), M68kError<Bus::Error>> {
+        self.require_supervisor()?;
+
+        // The size of the frame is in its first long word, so the address register is incremented after it's read
+        let addr = match target {
+            Target::IndirectARegInc(reg) => *self.get_a_reg_mut(reg),
+            _ => self.get_target_address(target)?,
+        };
+        let header = self.get_address_sized(addr, Size::Long)?;
+        let frame_size = if (header >> 24) == 0 {
+            // A null frame resets the coprocessor
+            self.state.fpu.reset();
+            4
+        } else {
+            ((header >> 16) & 0xFF) + 4
+        };
+
+        if let Target::IndirectARegInc(reg) = target {
+            *self.get_a_reg_mut(reg) = addr.wrapping_add(frame_size);
+        }
+        Ok(())
+    }
+
+    fn execute_fsave(&mut self, target: Target) -> Result<(), M68kError<Bus::Error>> {
+        self.require_supervisor()?;
+
+        let addr = self.get_fp_target_address(target, fpu::IDLE_FRAME_SIZE + 4)?;
+        self.set_address_sized(addr, fpu::IDLE_FRAME, Size::Long)?;
+        for offset in (4..fpu::IDLE_FRAME_SIZE + 4).step_by(4) {
+            self.set_address_sized(addr.wrapping_add(offset), 0, Size::Long)?;
+        }
+        Ok(())
+    }
+
     fn execute_illegal(&mut self) -> Result<(), M68kError<Bus::Error>> {
         self.exception(Exceptions::IllegalInstruction as u8, false)?;
         Ok(())
@@ -1509,7 +1721,8 @@ where
     }
 
     fn execute_unimplemented_f(&mut self, _: u16) -> Result<(), M68kError<Bus::Error>> {
-        self.state.pc -= 2;
+        // The FPU's general instructions have an extension word which has already been decoded
+        self.state.pc = self.cycle.decoder.start;
         self.exception(Exceptions::LineFEmulator as u8, false)?;
         Ok(())
     }
@@ -1672,6 +1885,65 @@ where
             .write_data_sized(&mut self.bus, is_supervisor, addr, size, value)
     }
 
+    fn get_fp_operand(&mut self, src: FpOperand) -> Result<f64, M68kError<Bus::Error>> {
+        let value = match src {
+            FpOperand::Register(reg) => self.state.fpu.fp_reg[reg as usize],
+            FpOperand::Immediate(bits) => f64::from_bits(bits),
+            FpOperand::Constant(offset) => fpu::rom_constant(offset),
+            FpOperand::Memory(target, FpSize::Byte) => self.get_target_value(target, Size::Byte, Used::Once)? as i8 as f64,
+            FpOperand::Memory(target, FpSize::Word) => self.get_target_value(target, Size::Word, Used::Once)? as i16 as f64,
+            FpOperand::Memory(target, FpSize::Long) => self.get_target_value(target, Size::Long, Used::Once)? as i32 as f64,
+            FpOperand::Memory(target, FpSize::Single) => {
+                f32::from_bits(self.get_target_value(target, Size::Long, Used::Once)?) as f64
+            },
+            FpOperand::Memory(target, FpSize::Double) => {
+                let addr = self.get_fp_target_address(target, FpSize::Double.in_bytes())?;
+                let high = self.get_address_sized(addr, Size::Long)? as u64;
+                let low = self.get_address_sized(addr.wrapping_add(4), Size::Long)? as u64;
+                f64::from_bits((high << 32) | low)
+            },
+            FpOperand::Memory(target, size) => {
+                let addr = self.get_fp_target_address(target, size.in_bytes())?;
+                self.get_fp_extended(addr)?
+            },
+        };
+        Ok(value)
+    }
+
+    /// Returns the address of a floating point operand in memory, adjusting the address register by the given
+    /// number of bytes if it's a predecrement or postincrement target
+    fn get_fp_target_address(&mut self, target: Target, bytes: u32) -> Result<u32, M68kError<Bus::Error>> {
+        match target {
+            Target::IndirectARegInc(reg) => {
+                let reg_addr = self.get_a_reg_mut(reg);
+                let addr = *reg_addr;
+                *reg_addr = addr.wrapping_add(bytes);
+                Ok(addr)
+            },
+            Target::IndirectARegDec(reg) => {
+                let reg_addr = self.get_a_reg_mut(reg);
+                *reg_addr = (*reg_addr).wrapping_sub(bytes);
+                Ok(*reg_addr)
+            },
+            _ => self.get_target_address(target),
+        }
+    }
+
+    fn get_fp_extended(&mut self, addr: M68kAddress) -> Result<f64, M68kError<Bus::Error>> {
+        let mut words = [0; 3];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = self.get_address_sized(addr.wrapping_add(i as u32 * 4), Size::Long)?;
+        }
+        Ok(fpu::extended_to_f64(words))
+    }
+
+    fn set_fp_extended(&mut self, addr: M68kAddress, value: f64) -> Result<(), M68kError<Bus::Error>> {
+        for (i, word) in fpu::f64_to_extended(value).into_iter().enumerate() {
+            self.set_address_sized(addr.wrapping_add(i as u32 * 4), word, Size::Long)?;
+        }
+        Ok(())
+    }
+
     fn push_word(&mut self, value: u16) -> Result<(), M68kError<Bus::Error>> {
         let is_supervisor = self.is_supervisor();
         *self.get_stack_pointer_mut() -= 2;
@@ -1782,6 +2054,14 @@ where
         }
     }
 
+    fn get_fp_control_reg_mut(&mut self, bit: u8) -> &mut u32 {
+        match bit {
+            fp_control::FPCR => &mut self.state.fpu.fpcr,
+            fp_control::FPSR => &mut self.state.fpu.fpsr,
+            _ => &mut self.state.fpu.fpiar,
+        }
+    }
+
     fn get_stack_pointer_mut(&mut self) -> &mut u32 {
         if self.is_supervisor() {
             &mut self.state.ssp
@@ -0,0 +1,301 @@
+// MC68881/MC68882 Floating Point Coprocessor
+//
+// The registers are kept as double precision floats rather than the 80-bit extended precision of the real
+// chip, so results can differ in the lowest bits, and values outside the range of a double become infinity.
+// Extended precision values are converted when they're loaded from and stored to memory.  The operations that
+// aren't implemented, and the packed decimal format, are decoded as line F instructions, so that software
+// can emulate them like it would on an MC68040.  Exceptions are recorded in the FPSR, but are never trapped.
+
+use core::fmt::{self, Write};
+use std::f64::consts;
+
+use crate::instructions::FpCondition;
+
+
+#[rustfmt::skip]
+pub mod fpsr {
+    // Condition codes
+    pub const NEGATIVE: u32     = 0x0800_0000;
+    pub const ZERO: u32         = 0x0400_0000;
+    pub const INFINITY: u32     = 0x0200_0000;
+    pub const NAN: u32          = 0x0100_0000;
+    pub const CONDITIONS: u32   = 0x0F00_0000;
+
+    // Exception status
+    pub const EXCEPTIONS: u32   = 0x0000_FF00;
+    pub const BSUN: u32         = 0x0000_8000;
+    pub const OPERR: u32        = 0x0000_2000;
+    pub const DZ: u32           = 0x0000_0400;
+
+    // Accrued exceptions
+    pub const ACCRUED_IOP: u32  = 0x0000_0080;
+    pub const ACCRUED_DZ: u32   = 0x0000_0010;
+}
+
+#[rustfmt::skip]
+pub mod fpcr {
+    pub const PRECISION: u32        = 0x0000_00C0;
+    pub const PRECISION_SINGLE: u32 = 0x0000_0040;
+    pub const ROUNDING: u32         = 0x0000_0030;
+}
+
+/// The ways of rounding to an integer, from the rounding mode bits of the FPCR
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    Nearest,
+    Zero,
+    Minus,
+    Plus,
+}
+
+/// The first long word of the frame written by `fsave`, for an MC68881 in the idle state, which is followed
+/// by 0x18 bytes of internal state
+pub const IDLE_FRAME: u32 = 0x1F18_0000;
+pub const IDLE_FRAME_SIZE: u32 = 0x18;
+
+
+#[derive(Clone, Debug)]
+pub struct FpuState {
+    pub fp_reg: [f64; 8],
+    pub fpcr: u32,
+    pub fpsr: u32,
+    pub fpiar: u32,
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        // The data registers are reset to non-signalling NaNs
+        Self {
+            fp_reg: [f64::NAN; 8],
+            fpcr: 0,
+            fpsr: 0,
+            fpiar: 0,
+        }
+    }
+}
+
+// The registers are compared by their bits, so that states with NaNs in the same registers are equal
+impl PartialEq for FpuState {
+    fn eq(&self, other: &Self) -> bool {
+        self.fp_reg
+            .iter()
+            .zip(other.fp_reg.iter())
+            .all(|(a, b)| a.to_bits() == b.to_bits())
+            && self.fpcr == other.fpcr
+            && self.fpsr == other.fpsr
+            && self.fpiar == other.fpiar
+    }
+}
+
+impl Eq for FpuState {}
+
+impl FpuState {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn dump_state<W: Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        for i in 0..4 {
+            writeln!(writer, "FP{}: {:<24e}  FP{}: {:<24e}", i, self.fp_reg[i], i + 4, self.fp_reg[i + 4])?;
+        }
+        writeln!(writer, "FPCR: {:#010x}  FPSR: {:#010x}  FPIAR: {:#010x}", self.fpcr, self.fpsr, self.fpiar)?;
+        Ok(())
+    }
+
+    pub fn rounding(&self) -> Rounding {
+        match (self.fpcr & fpcr::ROUNDING) >> 4 {
+            0b00 => Rounding::Nearest,
+            0b01 => Rounding::Zero,
+            0b10 => Rounding::Minus,
+            _ => Rounding::Plus,
+        }
+    }
+
+    /// Round a result to the precision selected in the FPCR
+    pub fn round_precision(&self, value: f64) -> f64 {
+        if self.fpcr & fpcr::PRECISION == fpcr::PRECISION_SINGLE {
+            value as f32 as f64
+        } else {
+            value
+        }
+    }
+
+    /// Set the condition codes for the result of an operation
+    pub fn set_condition_codes(&mut self, value: f64) {
+        let mut codes = 0;
+        if value.is_sign_negative() {
+            codes |= fpsr::NEGATIVE;
+        }
+        if value == 0.0 {
+            codes |= fpsr::ZERO;
+        }
+        if value.is_infinite() {
+            codes |= fpsr::INFINITY;
+        }
+        if value.is_nan() {
+            codes |= fpsr::NAN;
+        }
+        self.fpsr = (self.fpsr & !fpsr::CONDITIONS) | codes;
+    }
+
+    /// Set the condition codes for the comparison of the destination to the source, as if the source was
+    /// subtracted from the destination, but without losing the result when comparing infinities
+    pub fn set_compare_codes(&mut self, dest: f64, src: f64) {
+        let codes = if dest.is_nan() || src.is_nan() {
+            fpsr::NAN
+        } else if dest == src {
+            // Equal negative values give a negative zero
+            fpsr::ZERO
+                | if dest.is_sign_negative() && src.is_sign_negative() {
+                    fpsr::NEGATIVE
+                } else {
+                    0
+                }
+        } else if dest < src {
+            fpsr::NEGATIVE
+        } else {
+            0
+        };
+        self.fpsr = (self.fpsr & !fpsr::CONDITIONS) | codes;
+    }
+
+    /// Clear the exceptions of the previous instruction, which is done at the start of each arithmetic instruction
+    pub fn clear_exceptions(&mut self) {
+        self.fpsr &= !fpsr::EXCEPTIONS;
+    }
+
+    pub fn set_exception(&mut self, exception: u32, accrued: u32) {
+        self.fpsr |= exception | accrued;
+    }
+
+    /// Returns true if the condition is true for the current condition codes, signalling an exception if the
+    /// condition is one that requires the operands to be ordered and the last result was a NaN
+    pub fn test_condition(&mut self, cond: FpCondition) -> bool {
+        let negative = self.fpsr & fpsr::NEGATIVE != 0;
+        let zero = self.fpsr & fpsr::ZERO != 0;
+        let nan = self.fpsr & fpsr::NAN != 0;
+
+        if cond.0 & 0x10 != 0 && nan {
+            self.set_exception(fpsr::BSUN, fpsr::ACCRUED_IOP);
+        }
+
+        match cond.0 & 0x0F {
+            0x0 => false,
+            0x1 => zero,
+            0x2 => !(nan || zero || negative),
+            0x3 => zero || !(nan || negative),
+            0x4 => negative && !(nan || zero),
+            0x5 => zero || (negative && !nan),
+            0x6 => !(nan || zero),
+            0x7 => !nan,
+            0x8 => nan,
+            0x9 => nan || zero,
+            0xA => nan || !(negative || zero),
+            0xB => nan || zero || !negative,
+            0xC => nan || (negative && !zero),
+            0xD => nan || zero || negative,
+            0xE => !zero,
+            _ => true,
+        }
+    }
+
+    /// Convert a value to an integer with the given range, rounding with the mode in the FPCR, and returning
+    /// the closest value in the range and signalling an operand error if it doesn't fit
+    pub fn to_integer(&mut self, value: f64, min: i64, max: i64) -> i64 {
+        let rounded = round_to_integer(value, self.rounding());
+        if rounded.is_nan() || rounded > max as f64 || rounded < min as f64 {
+            self.set_exception(fpsr::OPERR, fpsr::ACCRUED_IOP);
+            if rounded.is_sign_negative() { min } else { max }
+        } else {
+            rounded as i64
+        }
+    }
+}
+
+pub fn round_to_integer(value: f64, rounding: Rounding) -> f64 {
+    match rounding {
+        Rounding::Nearest => {
+            // Halfway values are rounded to the even integer
+            let rounded = value.round();
+            if (value - value.trunc()).abs() == 0.5 {
+                2.0 * (value / 2.0).round()
+            } else {
+                rounded
+            }
+        },
+        Rounding::Zero => value.trunc(),
+        Rounding::Minus => value.floor(),
+        Rounding::Plus => value.ceil(),
+    }
+}
+
+/// Returns the value of a constant in the coprocessor's ROM, which is zero for the offsets without a constant
+pub fn rom_constant(offset: u8) -> f64 {
+    match offset {
+        0x00 => consts::PI,
+        0x0B => consts::LOG10_2,
+        0x0C => consts::E,
+        0x0D => consts::LOG2_E,
+        0x0E => consts::LOG10_E,
+        0x30 => consts::LN_2,
+        0x31 => consts::LN_10,
+        // Powers of ten from 10^0 to 10^4096, where the last few are too big for a double
+        0x32..=0x3F => 10.0_f64.powi(match offset - 0x32 {
+            0 => 0,
+            power => 1 << (power - 1),
+        }),
+        _ => 0.0,
+    }
+}
+
+/// Convert an extended precision value, as it's stored in memory in three long words, to a double
+pub fn extended_to_f64(words: [u32; 3]) -> f64 {
+    let negative = words[0] & 0x8000_0000 != 0;
+    let exponent = ((words[0] >> 16) & 0x7FFF) as i32;
+    let mantissa = ((words[1] as u64) << 32) | words[2] as u64;
+
+    let value = if exponent == 0x7FFF {
+        // The explicit integer bit is ignored for infinities and NaNs
+        if mantissa << 1 == 0 { f64::INFINITY } else { f64::NAN }
+    } else if mantissa == 0 {
+        0.0
+    } else {
+        scale(mantissa as f64, exponent - 16383 - 63)
+    };
+
+    if negative { -value } else { value }
+}
+
+/// Convert a double to an extended precision value, as it's stored in memory in three long words
+pub fn f64_to_extended(value: f64) -> [u32; 3] {
+    let bits = value.to_bits();
+    let sign = (bits >> 63) as u32;
+    let exponent = ((bits >> 52) & 0x7FF) as u32;
+    let fraction = bits & 0x000F_FFFF_FFFF_FFFF;
+
+    let (exponent, mantissa) = match (exponent, fraction) {
+        (0x7FF, 0) => (0x7FFF, 0),
+        (0x7FF, _) => (0x7FFF, (1 << 63) | (fraction << 11)),
+        (0, 0) => (0, 0),
+        (0, _) => {
+            // Denormalized doubles can be normalized in the larger exponent range of the extended format
+            let shift = fraction.leading_zeros();
+            (16383 + 63 - 1074 - shift, fraction << shift)
+        },
+        _ => (exponent - 1023 + 16383, (1 << 63) | (fraction << 11)),
+    };
+    [(sign << 31) | (exponent << 16), (mantissa >> 32) as u32, mantissa as u32]
+}
+
+/// Multiply a value by 2 to the given power, in steps that don't overflow the exponent of a double
+fn scale(mut value: f64, mut power: i32) -> f64 {
+    while power > 1023 {
+        value *= f64::from_bits(0x7FE0_0000_0000_0000);
+        power -= 1023;
+    }
+    while power < -1022 {
+        value *= f64::from_bits(0x0010_0000_0000_0000);
+        power += 1022;
+    }
+    value * f64::from_bits(((power + 1023) as u64) << 52)
+}
@@ -87,6 +87,62 @@ pub enum Target {
     IndirectMemory(u32, Size),
 }
 
+/// The data formats of floating point coprocessor operands
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FpSize {
+    Byte,
+    Word,
+    Long,
+    Single,
+    Double,
+    Extended,
+    Packed,
+}
+
+/// The operations of the floating point coprocessor's general instructions, which store their result in a
+/// floating point register, except for `Cmp` and `Tst` which only set the condition codes
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FpOperation {
+    Move,
+    Int,
+    IntRZ,
+    Sqrt,
+    Abs,
+    Neg,
+    Sin,
+    Cos,
+    Tan,
+    Atan,
+    Etox,
+    Logn,
+    Log10,
+    Log2,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Cmp,
+    Tst,
+}
+
+/// The source operand of a general floating point instruction
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FpOperand {
+    Register(Register),
+    Memory(Target, FpSize),
+    /// An immediate value, converted to a double precision float when decoded, and stored as its bits
+    Immediate(u64),
+    /// A constant from the coprocessor's ROM, loaded by `fmovecr`
+    Constant(u8),
+}
+
+/// The condition predicate of a floating point branch, which is the 6-bit field from the instruction
+///
+/// The lower 4 bits select the condition, and bit 4 selects whether an unordered comparison (with a NaN) is
+/// signalled as an exception.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FpCondition(pub u8);
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Instruction {
     ABCD(Target, Target),
@@ -131,6 +187,14 @@ pub enum Instruction {
     EXG(Target, Target),
     EXT(Register, Size, Size),
 
+    FBcc(FpCondition, i32),
+    FOP(FpOperation, FpOperand, Register),
+    FMOVEfromFP(Register, Target, FpSize),
+    FMOVEMcontrol(Target, u8, Direction),
+    FMOVEM(Target, RegOrImmediate, Direction),
+    FRESTORE(Target),
+    FSAVE(Target),
+
     ILLEGAL,
 
     JMP(Target),
@@ -223,6 +287,27 @@ impl Size {
 }
 
 
+impl FpSize {
+    pub fn in_bytes(&self) -> u32 {
+        match self {
+            FpSize::Byte => 1,
+            FpSize::Word => 2,
+            FpSize::Long | FpSize::Single => 4,
+            FpSize::Double => 8,
+            FpSize::Extended | FpSize::Packed => 12,
+        }
+    }
+}
+
+/// The bits in the register list of an `fmovem` of the control registers
+#[rustfmt::skip]
+pub mod fp_control {
+    pub const FPCR: u8  = 0b100;
+    pub const FPSR: u8  = 0b010;
+    pub const FPIAR: u8 = 0b001;
+}
+
+
 impl fmt::Display for Sign {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -265,6 +350,71 @@ impl fmt::Display for Condition {
     }
 }
 
+impl fmt::Display for FpSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FpSize::Byte => write!(f, "b"),
+            FpSize::Word => write!(f, "w"),
+            FpSize::Long => write!(f, "l"),
+            FpSize::Single => write!(f, "s"),
+            FpSize::Double => write!(f, "d"),
+            FpSize::Extended => write!(f, "x"),
+            FpSize::Packed => write!(f, "p"),
+        }
+    }
+}
+
+impl fmt::Display for FpOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FpOperation::Move => "fmove",
+            FpOperation::Int => "fint",
+            FpOperation::IntRZ => "fintrz",
+            FpOperation::Sqrt => "fsqrt",
+            FpOperation::Abs => "fabs",
+            FpOperation::Neg => "fneg",
+            FpOperation::Sin => "fsin",
+            FpOperation::Cos => "fcos",
+            FpOperation::Tan => "ftan",
+            FpOperation::Atan => "fatan",
+            FpOperation::Etox => "fetox",
+            FpOperation::Logn => "flogn",
+            FpOperation::Log10 => "flog10",
+            FpOperation::Log2 => "flog2",
+            FpOperation::Add => "fadd",
+            FpOperation::Sub => "fsub",
+            FpOperation::Mul => "fmul",
+            FpOperation::Div => "fdiv",
+            FpOperation::Cmp => "fcmp",
+            FpOperation::Tst => "ftst",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for FpCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const NAMES: [&str; 32] = [
+            "f", "eq", "ogt", "oge", "olt", "ole", "ogl", "or", "un", "ueq", "ugt", "uge", "ult", "ule", "ne", "t", "sf", "seq",
+            "gt", "ge", "lt", "le", "gl", "gle", "ngle", "ngl", "nle", "nlt", "nge", "ngt", "sne", "st",
+        ];
+        match NAMES.get(self.0 as usize) {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "{:#04x}", self.0),
+        }
+    }
+}
+
+fn fmt_fp_control(mask: u8) -> String {
+    let mut output = vec![];
+    for (bit, name) in [(fp_control::FPCR, "%fpcr"), (fp_control::FPSR, "%fpsr"), (fp_control::FPIAR, "%fpiar")] {
+        if mask & bit != 0 {
+            output.push(name);
+        }
+    }
+    output.join("/")
+}
+
 impl fmt::Display for ControlRegister {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -346,6 +496,35 @@ impl fmt::Display for Target {
     }
 }
 
+impl fmt::Display for FpOperand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FpOperand::Register(reg) => write!(f, "%fp{}", reg),
+            FpOperand::Memory(target, _) => write!(f, "{}", target),
+            FpOperand::Immediate(bits) => write!(f, "#{}", f64::from_bits(*bits)),
+            FpOperand::Constant(offset) => write!(f, "#{:#04x}", offset),
+        }
+    }
+}
+
+fn fmt_fmovem_mask(mask: RegOrImmediate, target: &Target) -> String {
+    let mask = match mask {
+        RegOrImmediate::DReg(reg) => return format!("%d{}", reg),
+        RegOrImmediate::Immediate(mask) => mask,
+    };
+
+    // The predecrement mode has the bits in the opposite order, with FP0 in bit 0
+    let reversed = matches!(target, Target::IndirectARegDec(_));
+    (0..8)
+        .filter(|reg| {
+            let bit = if reversed { 0x01 << reg } else { 0x80 >> reg };
+            mask & bit != 0
+        })
+        .map(|reg| format!("%fp{}", reg))
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
 fn fmt_movem_mask(mut mask: u16, target: &Target) -> String {
     let mut output = vec![];
 
@@ -445,6 +624,22 @@ impl fmt::Display for Instruction {
                 reg
             ),
 
+            Instruction::FBcc(cond, offset) => write!(f, "fb{}\t{}", cond, offset),
+            Instruction::FOP(FpOperation::Move, src @ FpOperand::Constant(_), reg) => write!(f, "fmovecr\t{}, %fp{}", src, reg),
+            Instruction::FOP(FpOperation::Tst, src, _) => write!(f, "ftst{}\t{}", fmt_fp_size(src), src),
+            Instruction::FOP(op, src, reg) => write!(f, "{}{}\t{}, %fp{}", op, fmt_fp_size(src), src, reg),
+            Instruction::FMOVEfromFP(reg, target, size) => write!(f, "fmove{}\t%fp{}, {}", size, reg, target),
+            Instruction::FMOVEMcontrol(target, mask, dir) => match dir {
+                Direction::ToTarget => write!(f, "fmoveml\t{}, {}", fmt_fp_control(*mask), target),
+                Direction::FromTarget => write!(f, "fmoveml\t{}, {}", target, fmt_fp_control(*mask)),
+            },
+            Instruction::FMOVEM(target, mask, dir) => match dir {
+                Direction::ToTarget => write!(f, "fmovemx\t{}, {}", fmt_fmovem_mask(*mask, target), target),
+                Direction::FromTarget => write!(f, "fmovemx\t{}, {}", target, fmt_fmovem_mask(*mask, target)),
+            },
+            Instruction::FRESTORE(target) => write!(f, "frestore\t{}", target),
+            Instruction::FSAVE(target) => write!(f, "fsave\t{}", target),
+
             Instruction::ILLEGAL => write!(f, "illegal"),
 
             Instruction::JMP(target) => write!(f, "jmp\t{}", target),
@@ -528,3 +723,11 @@ impl fmt::Display for Instruction {
         }
     }
 }
+
+fn fmt_fp_size(operand: &FpOperand) -> String {
+    match operand {
+        FpOperand::Memory(_, size) => size.to_string(),
+        FpOperand::Immediate(_) => "d".to_string(),
+        _ => "x".to_string(),
+    }
+}
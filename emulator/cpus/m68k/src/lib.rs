@@ -1,7 +1,10 @@
 pub mod assembler;
+pub mod cache;
 pub mod debugger;
 pub mod decode;
+pub mod disassembler;
 pub mod execute;
+pub mod fpu;
 pub mod instructions;
 pub mod memory;
 pub mod state;
@@ -13,9 +16,12 @@ pub mod moa;
 
 pub use crate::assembler::M68kAssembler;
 pub use crate::debugger::M68kDebugger;
-pub use crate::state::{M68k, M68kType, M68kState, M68kError, CpuInfo, Exceptions};
-pub use crate::memory::{M68kAddress, M68kAddressSpace, M68kBusPort};
+pub use crate::state::{M68k, M68kType, M68kState, M68kError, CpuInfo, AddressWidth, Exceptions};
+pub use crate::memory::{M68kAddress, M68kAddressSpace, M68kBusPort, M68kMmu, M68kMmuRef, FunctionCode, MemAccess};
 pub use crate::decode::{M68kDecoder, InstructionDecoding};
+pub use crate::cache::{InstructionCache, CachedInstruction};
+pub use crate::disassembler::{DisassembledInstruction, Operand};
 pub use crate::execute::{M68kCycle, M68kCycleExecutor};
-pub use crate::timing::M68kInstructionTiming;
+pub use crate::timing::{M68kInstructionTiming, RefreshTiming};
+pub use crate::fpu::FpuState;
 pub use crate::instructions::*;
@@ -1,8 +1,11 @@
 use core::cmp;
-use core::fmt::Write;
+use core::fmt::{self, Write};
+use std::rc::Rc;
+use std::cell::RefCell;
 use emulator_hal::{Instant as BusInstant, BusAccess};
 
 use crate::{M68kError, CpuInfo};
+use crate::cache::InstructionCache;
 use crate::state::Exceptions;
 use crate::instructions::Size;
 
@@ -115,6 +118,32 @@ impl<Instant> MemoryRequest<Instant> {
     }
 }
 
+/// A memory management unit, which translates the logical address of each access made by the cpu into a
+/// physical address on the bus
+///
+/// The function code tells whether the access is to program or data space, and whether the cpu is in supervisor
+/// mode, so that each can be mapped separately and protected.  Returning `None` causes a bus error exception,
+/// with the logical address and function code in the exception stack frame.
+pub trait M68kMmu {
+    fn translate(&mut self, code: FunctionCode, access: MemAccess, size: Size, addr: u32) -> Option<u32>;
+}
+
+/// A shared reference to the MMU that the cpu uses, which is given to each of its bus cycles
+#[derive(Clone)]
+pub struct M68kMmuRef(pub Rc<RefCell<dyn M68kMmu>>);
+
+impl M68kMmuRef {
+    pub fn new<T: M68kMmu + 'static>(mmu: T) -> Self {
+        Self(Rc::new(RefCell::new(mmu)))
+    }
+}
+
+impl fmt::Debug for M68kMmuRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "M68kMmuRef")
+    }
+}
+
 //pub type M68kAddress = (FunctionCode, u32);
 pub type M68kAddress = u32;
 pub type M68kAddressSpace = (FunctionCode, u32);
@@ -132,6 +161,8 @@ pub struct M68kBusPort<Instant> {
     pub address_mask: u32,
     pub cycle_start_clock: Instant,
     pub current_clock: Instant,
+    pub mmu: Option<M68kMmuRef>,
+    pub cache: Option<InstructionCache>,
 }
 
 
@@ -146,6 +177,8 @@ where
             address_mask: 0xFFFF_FFFF,
             cycle_start_clock: Instant::START,
             current_clock: Instant::START,
+            mmu: None,
+            cache: None,
         }
     }
 }
@@ -158,9 +191,43 @@ where
         Self {
             request: MemoryRequest::new(clock),
             data_bytewidth: info.data_width as usize / 8,
-            address_mask: 1_u32.checked_shl(info.address_width as u32).unwrap_or(0).wrapping_sub(1),
+            address_mask: info.address_width.mask(),
             cycle_start_clock: clock,
             current_clock: clock,
+            mmu: None,
+            cache: None,
+        }
+    }
+
+    pub fn with_mmu(mut self, mmu: Option<M68kMmuRef>) -> Self {
+        self.mmu = mmu;
+        self
+    }
+
+    pub fn with_cache(mut self, cache: Option<InstructionCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Returns the cache of decoded instructions, unless there's an MMU which makes the addresses of the cached
+    /// instructions different from the addresses that are written to
+    #[inline]
+    pub fn instruction_cache(&self) -> Option<&InstructionCache> {
+        match self.mmu {
+            None => self.cache.as_ref(),
+            Some(_) => None,
+        }
+    }
+
+    /// Translate a logical address using the MMU, if there is one, using the function code of the current request
+    fn translate<BusError>(&mut self, addr: M68kAddress, size: Size) -> Result<M68kAddress, M68kError<BusError>> {
+        match self.mmu.as_ref() {
+            Some(mmu) => mmu
+                .0
+                .borrow_mut()
+                .translate(self.request.code, self.request.access, size, addr)
+                .ok_or(M68kError::Exception(Exceptions::BusError)),
+            None => Ok(addr),
         }
     }
 
@@ -195,6 +262,9 @@ where
         Bus: BusAccess<M68kAddress, Instant = Instant, Error = BusError>,
     {
         let addr = addr & self.address_mask;
+        if let Some(cache) = self.cache.as_ref() {
+            cache.invalidate(addr, data.len());
+        }
         for i in (0..data.len()).step_by(self.data_bytewidth) {
             let addr_index = (addr + i as M68kAddress) & self.address_mask;
             let end = cmp::min(i + self.data_bytewidth, data.len());
@@ -246,6 +316,7 @@ where
         Bus: BusAccess<M68kAddress, Instant = Instant, Error = BusError>,
     {
         self.start_request(is_supervisor, addr, size, MemAccess::Read, MemType::Data, false)?;
+        let addr = self.translate(addr, size)?;
         self.read_sized(bus, addr, size)
     }
 
@@ -261,6 +332,7 @@ where
         Bus: BusAccess<M68kAddress, Instant = Instant, Error = BusError>,
     {
         self.start_request(is_supervisor, addr, size, MemAccess::Write, MemType::Data, false)?;
+        let addr = self.translate(addr, size)?;
         self.write_sized(bus, addr, size, value)
     }
 
@@ -274,6 +346,7 @@ where
         Bus: BusAccess<M68kAddress, Instant = Instant, Error = BusError>,
     {
         self.request.instruction(is_supervisor, addr)?;
+        let addr = self.translate(addr, Size::Word)?;
         Ok(self.read_sized(bus, addr, Size::Word)? as u16)
     }
 
@@ -287,6 +360,7 @@ where
         Bus: BusAccess<M68kAddress, Instant = Instant, Error = BusError>,
     {
         self.request.instruction(is_supervisor, addr)?;
+        let addr = self.translate(addr, Size::Long)?;
         self.read_sized(bus, addr, Size::Long)
     }
 
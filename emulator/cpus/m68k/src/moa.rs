@@ -1,30 +1,66 @@
+use std::any::Any;
+use std::rc::Rc;
 use femtos::{Instant, Duration};
 use emulator_hal::{ErrorType, BusAdapter};
 
-use moa_core::{System, Error, Address, Steppable, Interruptable, Addressable, Debuggable, Transmutable};
+use moa_core::{
+    System, Error, Address, Bus, InterruptController, Steppable, Interruptable, Addressable, Debuggable, Snapshotable,
+    Transmutable, WriteObserver,
+};
 
-use crate::{M68k, M68kError, M68kDecoder, M68kCycle, M68kBusPort};
+use crate::{M68k, M68kState, M68kError, M68kDecoder, M68kCycle, M68kBusPort, InstructionCache};
+use crate::state::Flags;
+use crate::debugger::parse_exception_vector;
 
-impl Steppable for M68k<Instant> {
-    fn step(&mut self, system: &System) -> Result<Duration, Error> {
-        let cycle = M68kCycle::new(self, system.clock);
+impl M68k<Instant> {
+    /// Cache the decoded instructions, which are invalidated by any write to the given bus that the cpu runs from.
+    /// The memory mapped on the bus must not be changed any other way, such as by bank switching inside a device,
+    /// unless the bank switches are also observed with `Bus::observe_bank_switches` after this is called
+    pub fn cache_instructions(&mut self, bus: &mut Bus) {
+        let cache = self.enable_instruction_cache();
+        bus.observe_writes(Rc::new(cache));
+    }
 
-        let mut bus = system.bus.borrow_mut();
-        let mut adapter: BusAdapter<u32, u64, &mut dyn Addressable, Error> = BusAdapter::new(&mut *bus, |addr| addr as u64);
+    /// Execute one instruction using the given bus and interrupt controller, which is used instead of `step` for a
+    /// cpu that has its own bus, separate from the system's bus
+    pub fn step_on_bus(&mut self, clock: Instant, bus: &mut Bus, interrupts: &mut InterruptController) -> Result<Duration, Error> {
+        // An exception caught during the last step stops the cpu before the first instruction of its handler
+        if let Some(number) = self.debugger.exception_caught.take() {
+            return Err(Error::exception_caught(self.state.pc as Address, format!("exception {}", number)));
+        }
+
+        let cycle = M68kCycle::new(self, clock);
+
+        bus.set_access_pc(self.state.pc as Address);
+        let mut adapter: BusAdapter<u32, u64, &mut dyn Addressable, Error> = BusAdapter::new(bus, |addr| addr as u64);
 
         let mut executor = cycle.begin(self, &mut adapter);
-        executor.check_breakpoints()?;
+        executor.check_breakpoints().map_err(|err| match err {
+            M68kError::Breakpoint => {
+                let pc = executor.state.pc;
+                let id = executor.debugger.breakpoints.iter().position(|addr| *addr == pc);
+                Error::breakpoint_at(pc as Address, id)
+            },
+            err => Error::from(err),
+        })?;
         executor.step()?;
 
-        let interrupt = system.get_interrupt_controller().check();
+        let interrupt = interrupts.check();
         if let (priority, Some(_)) = executor.check_pending_interrupts(interrupt)? {
-            log::debug!("interrupt: {:?} @ {} ns", priority, system.clock.as_duration().as_nanos());
-            system.get_interrupt_controller().acknowledge(priority as u8)?;
+            log::debug!("interrupt: {:?} @ {} ns", priority, clock.as_duration().as_nanos());
+            interrupts.acknowledge(priority as u8)?;
         }
 
         self.cycle = Some(executor.end());
         Ok(self.last_cycle_duration())
     }
+}
+
+impl Steppable for M68k<Instant> {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        let mut bus = system.bus.borrow_mut();
+        self.step_on_bus(system.clock, &mut bus, &mut system.get_interrupt_controller())
+    }
 
     fn on_error(&mut self, _system: &System) {
         let mut output = String::with_capacity(256);
@@ -47,6 +83,48 @@ impl Transmutable for M68k<Instant> {
     fn as_debuggable(&mut self) -> Option<&mut dyn Debuggable> {
         Some(self)
     }
+
+    fn as_snapshotable(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
+
+    fn self_check(&mut self, system: &System) -> Result<(), Error> {
+        let mut bus = system.get_bus();
+        bus.get_device_at(0, 8)
+            .map_err(|_| Error::new("the reset vector at 0x00000000 isn't mapped to a device"))?;
+
+        let mask = self.info.address_width.mask() as Address;
+        let pc = bus.read_beu32(system.clock, 4)? as Address & mask;
+        if pc & 1 != 0 {
+            return Err(Error::new(format!("the reset vector points to an odd address {:#010x}", pc)));
+        }
+        bus.get_device_at(pc, 2)
+            .map_err(|_| Error::new(format!("the reset vector points to {:#010x}, which isn't mapped to a device", pc)))?;
+        Ok(())
+    }
+}
+
+impl Snapshotable for M68k<Instant> {
+    fn save_state(&mut self) -> Option<Box<dyn Any>> {
+        Some(Box::new((self.state.clone(), self.cycle.clone())))
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        if let Some((state, cycle)) = state.downcast_ref::<(M68kState, Option<M68kCycle<Instant>>)>() {
+            self.state = state.clone();
+            self.cycle = cycle.clone();
+        }
+        // The memory may be restored without going through the bus, so the decoded instructions can't be trusted
+        if let Some(cache) = self.cache.as_ref() {
+            cache.clear();
+        }
+    }
+}
+
+impl WriteObserver for InstructionCache {
+    fn on_write(&self, addr: Address, len: usize) {
+        self.invalidate(addr as u32, len);
+    }
 }
 
 impl<BusError> From<Error> for M68kError<BusError> {
@@ -65,8 +143,9 @@ impl<BusError: ErrorType> From<M68kError<BusError>> for Error {
             M68kError::Halted => Self::Other("cpu halted".to_string()),
             M68kError::Exception(ex) => Self::Processor(ex as u32),
             M68kError::Interrupt(num) => Self::Processor(num as u32),
-            M68kError::Breakpoint => Self::Breakpoint("breakpoint".to_string()),
+            M68kError::Breakpoint => Self::breakpoint("breakpoint"),
             M68kError::InvalidTarget(target) => Self::new(target.to_string()),
+            M68kError::UnsupportedInstruction(instruction) => Self::new(format!("unsupported instruction: {}", instruction)),
             M68kError::BusError(msg) => Self::Other(format!("{:?}", msg)),
             M68kError::Other(msg) => Self::Other(msg),
         }
@@ -90,8 +169,8 @@ impl Debuggable for M68k<Instant> {
         let mut adapter: BusAdapter<u32, u64, &mut dyn Addressable, Error> = BusAdapter::new(&mut *bus, |addr| addr as u64);
 
         // TODO this is called by the debugger, but should be called some other way
-        let mut decoder = M68kDecoder::new(self.info.chip, true, self.state.pc);
-        decoder.decode_at(&mut adapter, &mut M68kBusPort::default(), true, self.state.pc)?;
+        let mut decoder = M68kDecoder::new(self.info.chip, true, self.state.pc).with_fpu(self.info.fpu);
+        decoder.decode_at(&mut adapter, &mut M68kBusPort::from_info(&self.info, system.clock), true, self.state.pc)?;
         decoder.dump_decoded(system.clock, &mut adapter);
         let mut writer = String::new();
         self.dump_state(&mut writer)?;
@@ -100,7 +179,7 @@ impl Debuggable for M68k<Instant> {
     }
 
     fn print_disassembly(&mut self, system: &System, addr: Address, count: usize) {
-        let mut decoder = M68kDecoder::new(self.info.chip, true, 0);
+        let mut decoder = M68kDecoder::new(self.info.chip, true, 0).with_fpu(self.info.fpu);
         let mut memory = M68kBusPort::from_info(&self.info, system.clock);
 
         let mut bus = system.bus.borrow_mut();
@@ -114,16 +193,92 @@ impl Debuggable for M68k<Instant> {
             "ds" | "stack" | "dumpstack" => {
                 println!("Stack:");
                 for addr in &self.debugger.stack_tracer.calls {
-                    println!("  {:08x}", system.bus.borrow_mut().read_beu32(system.clock, *addr as Address)?);
+                    let return_addr = system.bus.borrow_mut().read_beu32(system.clock, *addr as Address)? as Address;
+                    match system.symbols.describe(return_addr) {
+                        Some(symbol) => println!("  {:08x} <{}>", return_addr, symbol),
+                        None => println!("  {:08x}", return_addr),
+                    }
                 }
             },
             "so" | "stepout" => {
                 self.debugger.step_until_return = Some(self.debugger.stack_tracer.calls.len() - 1);
             },
+            "illegal" => match args.get(1) {
+                Some(&"on") => self.debugger.illegal_instructions.enabled = true,
+                Some(&"off") => self.debugger.illegal_instructions.enabled = false,
+                Some(&"report") if !self.debugger.illegal_instructions.enabled => {},
+                None | Some(&"report") => {
+                    let mut output = String::new();
+                    self.debugger.illegal_instructions.dump(&mut output)?;
+                    print!("{}", output);
+                },
+                _ => return Err(Error::new("Usage: illegal [on | off | report]")),
+            },
             _ => {
                 return Ok(true);
             },
         }
         Ok(false)
     }
+
+    fn catch_exception(&mut self, exception: &str, enable: bool) -> Result<(), Error> {
+        let vector = parse_exception_vector(exception).ok_or_else(|| Error::new(format!("unknown exception: {}", exception)))?;
+        self.catch_exception_vector(vector, enable);
+        Ok(())
+    }
+
+    fn get_register(&mut self, name: &str) -> Option<u64> {
+        // The floating point registers are returned as the bits of the double they're stored as
+        if let Some(number) = name.strip_prefix("fp").and_then(|number| number.parse::<usize>().ok()) {
+            return self.state.fpu.fp_reg.get(number).map(|value| value.to_bits());
+        }
+
+        let is_supervisor = self.state.sr & (Flags::Supervisor as u16) != 0;
+        let value = match name {
+            "pc" => self.state.pc,
+            "sr" => self.state.sr as u32,
+            "ccr" => (self.state.sr & 0x00FF) as u32,
+            "usp" => self.state.usp,
+            "ssp" => self.state.ssp,
+            "vbr" => self.state.vbr,
+            "fpcr" => self.state.fpu.fpcr,
+            "fpsr" => self.state.fpu.fpsr,
+            "fpiar" => self.state.fpu.fpiar,
+            "a7" | "sp" if is_supervisor => self.state.ssp,
+            "a7" | "sp" => self.state.usp,
+            _ => {
+                let (kind, number) = name.split_at(1);
+                let number = number.parse::<usize>().ok()?;
+                match kind {
+                    "d" => *self.state.d_reg.get(number)?,
+                    "a" => *self.state.a_reg.get(number)?,
+                    _ => return None,
+                }
+            },
+        };
+        Some(value as u64)
+    }
+
+    fn register_names(&self) -> &'static [&'static str] {
+        if self.info.fpu {
+            &[
+                "d0", "d1", "d2", "d3", "d4", "d5", "d6", "d7", "a0", "a1", "a2", "a3", "a4", "a5", "a6", "usp", "ssp", "sr",
+                "fp0", "fp1", "fp2", "fp3", "fp4", "fp5", "fp6", "fp7", "fpcr", "fpsr", "fpiar",
+            ]
+        } else {
+            &["d0", "d1", "d2", "d3", "d4", "d5", "d6", "d7", "a0", "a1", "a2", "a3", "a4", "a5", "a6", "usp", "ssp", "sr"]
+        }
+    }
+
+    fn disassemble(&mut self, system: &System, addr: Address) -> Option<(String, Vec<u8>)> {
+        let is_supervisor = self.state.sr & (Flags::Supervisor as u16) != 0;
+        let mut decoder = M68kDecoder::new(self.info.chip, is_supervisor, 0).with_fpu(self.info.fpu);
+        let mut memory = M68kBusPort::from_info(&self.info, system.clock);
+
+        let mut bus = system.bus.borrow_mut();
+        let mut adapter: BusAdapter<u32, u64, &mut dyn Addressable, Error> = BusAdapter::new(&mut *bus, |addr| addr as u64);
+        let start = addr as u32;
+        let disassembly = decoder.disassemble(&mut adapter, &mut memory, start..start.wrapping_add(1));
+        disassembly.first().map(|ins| (ins.to_string(), ins.bytes()))
+    }
 }
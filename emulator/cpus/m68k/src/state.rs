@@ -5,7 +5,11 @@ use core::fmt::{self, Write};
 use emulator_hal::Instant as BusInstant;
 
 use crate::{M68kDebugger, M68kCycle};
-use crate::instructions::Target;
+use crate::memory::M68kMmuRef;
+use crate::cache::InstructionCache;
+use crate::fpu::FpuState;
+use crate::instructions::{Target, Instruction};
+use crate::timing::RefreshTiming;
 
 
 pub type ClockCycles = u16;
@@ -21,6 +25,13 @@ pub enum AddressWidth {
     A20 = 20, // MC68008 48-Pin
 }
 
+impl AddressWidth {
+    /// Returns the mask of the address lines that are connected to the bus
+    pub fn mask(self) -> u32 {
+        1_u32.checked_shl(self as u32).unwrap_or(0).wrapping_sub(1)
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(u8)]
@@ -49,6 +60,8 @@ pub struct CpuInfo {
     pub address_width: AddressWidth,
     pub data_width: DataWidth,
     pub frequency: Frequency,
+    /// Whether an MC68881/MC68882 floating point coprocessor is attached, which requires an MC68020 or later
+    pub fpu: bool,
 }
 
 /// The variant of the 68k family of CPUs that is being emulated
@@ -86,6 +99,7 @@ impl CpuInfo {
                 address_width: AddressWidth::A22,
                 data_width: DataWidth::D8,
                 frequency,
+                fpu: false,
             },
             M68kType::MC68000 | M68kType::MC68010 => Self {
                 chip: cputype,
@@ -93,6 +107,7 @@ impl CpuInfo {
                 address_width: AddressWidth::A24,
                 data_width: DataWidth::D16,
                 frequency,
+                fpu: false,
             },
             M68kType::MC68020 | M68kType::MC68030 => Self {
                 chip: cputype,
@@ -100,9 +115,22 @@ impl CpuInfo {
                 address_width: AddressWidth::A32,
                 data_width: DataWidth::D32,
                 frequency,
+                fpu: false,
             },
         }
     }
+
+    /// Override the number of address lines, such as for the 48-pin MC68008 which only has 20
+    pub fn with_address_width(mut self, address_width: AddressWidth) -> Self {
+        self.address_width = address_width;
+        self
+    }
+
+    /// Attach a floating point coprocessor, whose instructions are otherwise line F exceptions
+    pub fn with_fpu(mut self, fpu: bool) -> Self {
+        self.fpu = fpu;
+        self
+    }
 }
 
 const FLAGS_ON_RESET: u16 = 0x2700;
@@ -174,6 +202,8 @@ pub struct M68kState {
     pub usp: u32,
 
     pub vbr: u32,
+
+    pub fpu: FpuState,
 }
 
 #[derive(Clone, Debug, thiserror::Error)]
@@ -188,6 +218,8 @@ pub enum M68kError<BusError> {
     Breakpoint,
     #[error("invalid instruction target, direct value used as a pointer: {0:?}")]
     InvalidTarget(Target),
+    #[error("unsupported instruction: {0}")]
+    UnsupportedInstruction(Instruction),
     #[error("bus error")]
     BusError(BusError),
     #[error("error: {0}")]
@@ -206,6 +238,12 @@ pub struct M68k<Instant> {
     pub debugger: M68kDebugger,
     pub stats: M68kStatistics,
     pub cycle: Option<M68kCycle<Instant>>,
+    /// The memory management unit that translates addresses, if the system has one
+    pub mmu: Option<M68kMmuRef>,
+    /// The cycles stolen by DRAM refresh, if they're being emulated
+    pub refresh: Option<RefreshTiming>,
+    /// The instructions that have already been decoded, if caching them has been enabled
+    pub cache: Option<InstructionCache>,
 }
 
 impl Default for M68kState {
@@ -223,6 +261,8 @@ impl Default for M68kState {
             usp: 0,
 
             vbr: 0,
+
+            fpu: FpuState::default(),
         }
     }
 }
@@ -252,6 +292,9 @@ where
             debugger: M68kDebugger::default(),
             stats: Default::default(),
             cycle: None,
+            mmu: None,
+            refresh: None,
+            cache: None,
         }
     }
 
@@ -259,8 +302,37 @@ where
         Self::new(CpuInfo::from_type(cputype, freq))
     }
 
+    /// Translate the addresses of all accesses using the given MMU, which can also cause bus errors
+    pub fn set_mmu(&mut self, mmu: M68kMmuRef) {
+        self.mmu = Some(mmu);
+    }
+
+    /// Stall the CPU for `stall` clocks out of every `period` clocks, to emulate DRAM refresh
+    pub fn set_refresh_timing(&mut self, period: u32, stall: u32) {
+        self.refresh = Some(RefreshTiming::new(period, stall));
+    }
+
+    /// Keep the decoded instructions to reuse when the same address is executed again, and return the cache so
+    /// that anything else which writes to memory can invalidate it
+    pub fn enable_instruction_cache(&mut self) -> InstructionCache {
+        self.cache.get_or_insert_with(InstructionCache::default).clone()
+    }
+
+    /// Set the number of address lines, which determines where addresses wrap around on the bus
+    pub fn set_address_width(&mut self, address_width: AddressWidth) {
+        self.info.address_width = address_width;
+    }
+
+    /// Attach or remove the floating point coprocessor
+    pub fn set_fpu(&mut self, fpu: bool) {
+        self.info.fpu = fpu;
+    }
+
     pub fn dump_state<W: Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
         self.state.dump_state(writer)?;
+        if self.info.fpu {
+            self.state.fpu.dump_state(writer)?;
+        }
 
         if let Some(cycle) = self.cycle.as_ref() {
             writeln!(writer, "Current Instruction: {:#010x} {:?}", cycle.decoder.start, cycle.decoder.instruction)?;
@@ -271,9 +343,14 @@ where
         Ok(())
     }
 
+    /// Returns the duration of the last instruction, including the cycles stolen by any DRAM refreshes that
+    /// occurred while it ran, so it should only be called once per instruction
     #[inline]
-    pub fn last_cycle_duration(&self) -> Instant::Duration {
-        let clocks = self.cycle.as_ref().map(|cycle| cycle.timing.calculate_clocks()).unwrap_or(4);
+    pub fn last_cycle_duration(&mut self) -> Instant::Duration {
+        let mut clocks = self.cycle.as_ref().map(|cycle| cycle.timing.calculate_clocks()).unwrap_or(4);
+        if let Some(refresh) = self.refresh.as_mut() {
+            clocks += refresh.stolen_clocks(clocks as u32) as ClockCycles;
+        }
         //self.info.frequency.period_duration() * clocks as u64
         Instant::hertz_to_duration(self.info.frequency.as_hz() as u64) * clocks as u32
     }
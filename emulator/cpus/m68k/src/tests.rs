@@ -442,3 +442,68 @@ mod execute_unit_tests {
         });
     }
 }
+
+
+#[cfg(test)]
+mod address_width_unit_tests {
+    use femtos::{Instant, Frequency};
+    use emulator_hal::BusAccess;
+    use emulator_hal_memory::MemoryBlock;
+
+    use crate::{CpuInfo, AddressWidth, M68kType};
+    use crate::instructions::Size;
+    use crate::memory::M68kBusPort;
+
+    fn run_address_width_test<F>(info: CpuInfo, mut test_func: F)
+    where
+        F: FnMut(&mut M68kBusPort<Instant>, &mut MemoryBlock<Instant>),
+    {
+        let mut memory = MemoryBlock::from(vec![0; 0x10_0000]);
+        let mut port = M68kBusPort::from_info(&info, Instant::START);
+        test_func(&mut port, &mut memory);
+    }
+
+    #[test]
+    fn address_width_masks() {
+        assert_eq!(AddressWidth::A20.mask(), 0x000F_FFFF);
+        assert_eq!(AddressWidth::A22.mask(), 0x003F_FFFF);
+        assert_eq!(AddressWidth::A24.mask(), 0x00FF_FFFF);
+        assert_eq!(AddressWidth::A32.mask(), 0xFFFF_FFFF);
+    }
+
+    #[test]
+    fn address_width_68008_52pin_wraps_at_4mb() {
+        let info = CpuInfo::from_type(M68kType::MC68008, Frequency::from_mhz(8));
+        run_address_width_test(info, |port, memory| {
+            port.write_data_sized(memory, true, 0x0040_0020, Size::Long, 0x12345678)
+                .unwrap();
+            assert_eq!(memory.read_beu32(Instant::START, 0x20).unwrap(), 0x12345678);
+            assert_eq!(port.read_data_sized(memory, true, 0x00C0_0020, Size::Long).unwrap(), 0x12345678);
+        });
+    }
+
+    #[test]
+    fn address_width_68008_48pin_wraps_at_1mb() {
+        let info = CpuInfo::from_type(M68kType::MC68008, Frequency::from_mhz(8)).with_address_width(AddressWidth::A20);
+        run_address_width_test(info, |port, memory| {
+            port.write_data_sized(memory, true, 0x0010_0010, Size::Word, 0xABCD).unwrap();
+            assert_eq!(memory.read_beu16(Instant::START, 0x10).unwrap(), 0xABCD);
+
+            // An 8-bit bus splits the long word into bytes, which wrap individually past the end of the address space
+            port.write_data_sized(memory, true, 0x000F_FFFE, Size::Long, 0x11223344)
+                .unwrap();
+            assert_eq!(memory.read_beu16(Instant::START, 0x000F_FFFE).unwrap(), 0x1122);
+            assert_eq!(memory.read_beu16(Instant::START, 0x0000_0000).unwrap(), 0x3344);
+        });
+    }
+
+    #[test]
+    fn address_width_68000_wraps_at_16mb() {
+        let info = CpuInfo::from_type(M68kType::MC68000, Frequency::from_mhz(8));
+        run_address_width_test(info, |port, memory| {
+            port.write_data_sized(memory, true, 0xFF00_0030, Size::Long, 0xDEADBEEF)
+                .unwrap();
+            assert_eq!(memory.read_beu32(Instant::START, 0x30).unwrap(), 0xDEADBEEF);
+        });
+    }
+}
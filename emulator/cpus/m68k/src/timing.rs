@@ -19,6 +19,35 @@ pub struct M68kInstructionTiming {
     pub per_rep: u8,
 }
 
+/// Cycles taken away from the CPU to refresh the DRAM, such as on the Sega Genesis, where about 2 cycles out
+/// of every 128 are used for refresh
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RefreshTiming {
+    /// The number of CPU clocks between each refresh
+    pub period: u32,
+    /// The number of clocks that the CPU is stalled for by each refresh
+    pub stall: u32,
+    elapsed: u32,
+}
+
+impl RefreshTiming {
+    pub fn new(period: u32, stall: u32) -> Self {
+        Self {
+            period: period.max(1),
+            stall,
+            elapsed: 0,
+        }
+    }
+
+    /// Returns the number of clocks stolen by the refreshes that occurred while the given number of clocks ran
+    pub fn stolen_clocks(&mut self, clocks: u32) -> u32 {
+        self.elapsed += clocks;
+        let refreshes = self.elapsed / self.period;
+        self.elapsed %= self.period;
+        refreshes * self.stall
+    }
+}
+
 impl M68kInstructionTiming {
     pub fn new(cputype: M68kType, bus_width: u8) -> Self {
         let bus_size = if bus_width <= 16 { Size::Word } else { Size::Long };
@@ -0,0 +1,66 @@
+use femtos::{Instant, Frequency};
+use emulator_hal::{BusAccess, Step};
+use emulator_hal_memory::MemoryBlock;
+
+use moa_m68k::{M68k, M68kType, M68kAddress};
+
+const INIT_STACK: M68kAddress = 0x00002000;
+const INIT_ADDR: M68kAddress = 0x00000010;
+
+fn load_program(program: &[u16]) -> MemoryBlock<Instant> {
+    let mut memory = MemoryBlock::from(vec![0; 0x1_0000]);
+    memory.write_beu32(Instant::START, 0, INIT_STACK).unwrap();
+    memory.write_beu32(Instant::START, 4, INIT_ADDR).unwrap();
+    for (i, word) in program.iter().enumerate() {
+        memory
+            .write_beu16(Instant::START, INIT_ADDR + (i as M68kAddress * 2), *word)
+            .unwrap();
+    }
+    memory
+}
+
+#[test]
+fn cached_instructions_are_reused() {
+    #[rustfmt::skip]
+    let mut memory = load_program(&[
+        0x5280, // loop: addq.l #1, %d0
+        0x60FC, // bra.s loop
+    ]);
+
+    let mut cpu = M68k::from_type(M68kType::MC68000, Frequency::from_mhz(8));
+    let cache = cpu.enable_instruction_cache();
+    let mut clock = Instant::START;
+    // The first step resets the cpu
+    for _ in 0..21 {
+        clock = cpu.step(clock, &mut memory).unwrap();
+    }
+
+    assert_eq!(cpu.state.d_reg[0], 10);
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.stats(), (18, 2));
+}
+
+#[test]
+fn cached_instructions_are_invalidated_when_written() {
+    #[rustfmt::skip]
+    let mut memory = load_program(&[
+        0x4E71,                 // loop: nop
+        0x7001,                 // moveq #1, %d0
+        0x31FC, 0x7002, 0x0012, // move.w #0x7002, (0x0012).w
+        0x60F4,                 // bra.s loop
+    ]);
+
+    let mut cpu = M68k::from_type(M68kType::MC68000, Frequency::from_mhz(8));
+    cpu.enable_instruction_cache();
+    let mut clock = Instant::START;
+    for _ in 0..3 {
+        clock = cpu.step(clock, &mut memory).unwrap();
+    }
+    assert_eq!(cpu.state.d_reg[0], 1);
+
+    // The moveq was changed by the move, so it must be decoded again the second time around the loop
+    for _ in 0..4 {
+        clock = cpu.step(clock, &mut memory).unwrap();
+    }
+    assert_eq!(cpu.state.d_reg[0], 2);
+}
@@ -2,7 +2,7 @@ use femtos::{Instant, Frequency};
 use emulator_hal::BusAccess;
 use emulator_hal_memory::MemoryBlock;
 
-use moa_m68k::{M68k, M68kType, M68kAddress};
+use moa_m68k::{M68k, M68kType, M68kAddress, M68kDecoder, M68kBusPort, Operand};
 use moa_m68k::instructions::{Instruction, Target, Size, Sign, XRegister, BaseRegister, IndexRegister, Direction};
 use moa_m68k::assembler::M68kAssembler;
 use moa_m68k::execute::M68kCycle;
@@ -122,6 +122,34 @@ pub fn run_decode_tests() {
     }
 }
 
+#[test]
+pub fn run_disassemble_test() {
+    let (cpu, _, mut memory) = init_decode_test(M68kType::MC68000);
+    // moveq #1, %d0; bra.s to the moveq; movem.l %d0/%a1, -(%a7)
+    load_memory(&mut memory, &[0x7001, 0x60FC, 0x48E7, 0x8040]);
+
+    let mut port = M68kBusPort::from_info(&cpu.info, Instant::START);
+    let mut decoder = M68kDecoder::new(M68kType::MC68000, true, 0);
+    let disassembly = decoder.disassemble(&mut memory, &mut port, INIT_ADDR..INIT_ADDR + 8);
+
+    assert_eq!(disassembly.len(), 3);
+    assert_eq!(disassembly[0].address, INIT_ADDR);
+    assert_eq!(disassembly[0].words, vec![0x7001]);
+    assert_eq!(disassembly[0].mnemonic, "moveq");
+    assert_eq!(disassembly[0].operands, vec![Operand::Immediate(1), Operand::DataRegister(0)]);
+
+    assert_eq!(disassembly[1].address, INIT_ADDR + 2);
+    assert_eq!(disassembly[1].branch_target(), Some(INIT_ADDR));
+
+    assert_eq!(disassembly[2].address, INIT_ADDR + 4);
+    assert_eq!(disassembly[2].words, vec![0x48E7, 0x8040]);
+    assert_eq!(disassembly[2].mnemonic, "moveml");
+    assert_eq!(disassembly[2].operands, vec![
+        Operand::RegisterList(vec![XRegister::DReg(0), XRegister::AReg(1)]),
+        Operand::Target(Target::IndirectARegDec(7))
+    ]);
+}
+
 #[test]
 #[ignore]
 pub fn run_assembler_tests() {
@@ -158,6 +186,46 @@ pub fn run_assembler_tests() {
     }
 }
 
+#[test]
+pub fn run_assembler_directives_test() {
+    let text = "
+        .equ    COUNT, 3
+        .org    0x1000
+    start:
+        bra     next
+        .asciiz \"mo\"
+        .even
+    next:
+        nop
+        .align  8
+    table:
+        .long   start, next - start
+        .word   -2, COUNT << 4
+        .byte   'A', table + 2 >> 8
+        .space  COUNT, 0xff
+    ";
+
+    let mut assembler = M68kAssembler::new(M68kType::MC68000);
+    let data = assembler.assemble(text).unwrap();
+    assert_eq!(assembler.current_origin, 0x1000);
+    assert_eq!(assembler.labels["next"], 0x1006);
+    assert_eq!(assembler.labels["table"], 0x1008);
+
+    #[rustfmt::skip]
+    let expected = [
+        0x60, 0x04,
+        b'm', b'o', 0x00, 0x00,
+        0x4E, 0x71,
+        0x00, 0x00, 0x10, 0x00,
+        0x00, 0x00, 0x00, 0x06,
+        0xFF, 0xFE,
+        0x00, 0x30,
+        0x41, 0x10,
+        0xFF, 0xFF, 0xFF,
+    ];
+    assert_eq!(data, expected);
+}
+
 
 /*
 #[test]
@@ -2,7 +2,7 @@ use femtos::{Instant, Frequency};
 use emulator_hal::{BusAccess, Step};
 use emulator_hal_memory::MemoryBlock;
 
-use moa_m68k::{M68k, M68kType, M68kAddress};
+use moa_m68k::{M68k, M68kType, M68kAddress, M68kError, M68kMmu, M68kMmuRef, FunctionCode, MemAccess, Exceptions};
 use moa_m68k::state::M68kState;
 use moa_m68k::execute::{M68kCycle, M68kCycleExecutor};
 use moa_m68k::instructions::{Instruction, Target, Size, Sign, Direction, Condition};
@@ -708,3 +708,116 @@ const TEST_CASES: &'static [TestCase] = &[
         fini: TestState { pc: 0x00000002, ssp: 0x00000000, usp: 0x00000000, d0: 0x00000080, d1: 0x0000007F, a0: 0x00000000, a1: 0x00000000, sr: 0x2719, mem: 0x00000000 },
     },
 ];
+
+/// Maps data accesses 0x1000 bytes higher than program accesses, and faults on any data access above 0x8000
+struct TestMmu;
+
+impl M68kMmu for TestMmu {
+    fn translate(&mut self, code: FunctionCode, _access: MemAccess, _size: Size, addr: u32) -> Option<u32> {
+        match code {
+            FunctionCode::SupervisorData | FunctionCode::UserData if addr >= 0x8000 => None,
+            FunctionCode::SupervisorData | FunctionCode::UserData => Some(addr + 0x1000),
+            _ => Some(addr),
+        }
+    }
+}
+
+/// Runs a MOVE.L (A0), D0 with the test MMU, and returns the value loaded into D0, or None if a bus error occurred
+#[allow(clippy::uninit_vec)]
+fn run_mmu_test(a0: u32) -> Option<u32> {
+    let len = 0x10_0000;
+    let mut data = Vec::with_capacity(len);
+    unsafe {
+        data.set_len(len);
+    }
+    let mut memory = MemoryBlock::from(data);
+    memory.write_beu32(Instant::START, 0, INIT_STACK).unwrap();
+    memory.write_beu32(Instant::START, 4, INIT_ADDR).unwrap();
+    memory.write_beu32(Instant::START, MEM_ADDR, 0x12345678).unwrap();
+    memory.write_beu32(Instant::START, MEM_ADDR + 0x1000, 0x87654321).unwrap();
+
+    let mut cpu = M68k::from_type(M68kType::MC68010, Frequency::from_mhz(10));
+    cpu.step(Instant::START, &mut memory).unwrap();
+    cpu.set_mmu(M68kMmuRef::new(TestMmu));
+
+    let cycle = M68kCycle::new(&cpu, Instant::START);
+    let mut executor = cycle.begin(&mut cpu, &mut memory);
+    load_memory(&mut executor.bus, &[0x2010]);
+    *executor.state = build_state(&TestState {
+        pc: 0x00000000,
+        ssp: 0x00000000,
+        usp: 0x00000000,
+        d0: 0x00000000,
+        d1: 0x00000000,
+        a0,
+        a1: 0x00000000,
+        sr: 0x2700,
+        mem: 0x00000000,
+    });
+
+    executor.decode_next().unwrap();
+    match executor.execute_current() {
+        Ok(()) => Some(executor.state.d_reg[0]),
+        Err(M68kError::Exception(Exceptions::BusError)) => None,
+        Err(err) => panic!("unexpected error: {:?}", err),
+    }
+}
+
+#[test]
+pub fn run_mmu_translation() {
+    assert_eq!(run_mmu_test(MEM_ADDR), Some(0x87654321));
+}
+
+#[test]
+pub fn run_mmu_bus_error() {
+    assert_eq!(run_mmu_test(0x9000), None);
+}
+
+#[test]
+#[allow(clippy::uninit_vec)]
+pub fn run_fpu_arithmetic() {
+    let len = 0x10_0000;
+    let mut data = Vec::with_capacity(len);
+    unsafe {
+        data.set_len(len);
+    }
+    let mut memory = MemoryBlock::from(data);
+    memory.write_beu32(Instant::START, 0, INIT_STACK).unwrap();
+    memory.write_beu32(Instant::START, 4, INIT_ADDR).unwrap();
+
+    let mut cpu = M68k::from_type(M68kType::MC68020, Frequency::from_mhz(10));
+    cpu.set_fpu(true);
+    cpu.step(Instant::START, &mut memory).unwrap();
+
+    let cycle = M68kCycle::new(&cpu, Instant::START);
+    let mut executor = cycle.begin(&mut cpu, &mut memory);
+    // fmove.l #3, %fp0; fdiv.l #2, %fp0; fmove.l %fp0, %d0
+    #[rustfmt::skip]
+    let program = [
+        0xF23C, 0x4000, 0x0000, 0x0003,
+        0xF23C, 0x4020, 0x0000, 0x0002,
+        0xF200, 0x6000,
+    ];
+    load_memory(&mut executor.bus, &program);
+    *executor.state = build_state(&TestState {
+        pc: 0x00000000,
+        ssp: 0x00000000,
+        usp: 0x00000000,
+        d0: 0x00000000,
+        d1: 0x00000000,
+        a0: 0x00000000,
+        a1: 0x00000000,
+        sr: 0x2700,
+        mem: 0x00000000,
+    });
+
+    for _ in 0..3 {
+        executor.decode_next().unwrap();
+        executor.execute_current().unwrap();
+    }
+
+    assert_eq!(executor.state.fpu.fp_reg[0], 1.5);
+    // Halfway values are rounded to the even integer by default
+    assert_eq!(executor.state.d_reg[0], 2);
+    assert_eq!(executor.state.pc, 20);
+}
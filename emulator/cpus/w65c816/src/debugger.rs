@@ -0,0 +1,24 @@
+use crate::state::{W65C816Error, W65C816Address};
+
+#[derive(Clone, Default)]
+pub struct W65C816Debugger {
+    pub(crate) skip_breakpoint: usize,
+    pub(crate) breakpoints: Vec<W65C816Address>,
+}
+
+impl W65C816Debugger {
+    pub fn check_breakpoints(&mut self, pc: W65C816Address) -> Result<(), W65C816Error> {
+        for breakpoint in &self.breakpoints {
+            if *breakpoint == pc {
+                if self.skip_breakpoint > 0 {
+                    self.skip_breakpoint -= 1;
+                    return Ok(());
+                } else {
+                    self.skip_breakpoint = 1;
+                    return Err(W65C816Error::Breakpoint);
+                }
+            }
+        }
+        Ok(())
+    }
+}
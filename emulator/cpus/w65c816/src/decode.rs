@@ -0,0 +1,285 @@
+use emulator_hal::BusAccess;
+
+use crate::instructions::{Instruction, Operation, AddressingMode};
+use crate::state::{W65C816Error, W65C816Address};
+
+
+const ALU_OPERATIONS: [Operation; 8] = [
+    Operation::ORA,
+    Operation::AND,
+    Operation::EOR,
+    Operation::ADC,
+    Operation::STA,
+    Operation::LDA,
+    Operation::CMP,
+    Operation::SBC,
+];
+
+const SHIFT_OPERATIONS: [Operation; 4] = [Operation::ASL, Operation::ROL, Operation::LSR, Operation::ROR];
+
+#[derive(Clone, Debug)]
+pub struct W65C816Decoder {
+    pub start: W65C816Address,
+    pub end: W65C816Address,
+    pub instruction: Instruction,
+}
+
+impl Default for W65C816Decoder {
+    fn default() -> Self {
+        Self {
+            start: 0,
+            end: 0,
+            instruction: Instruction {
+                opcode: 0xEA,
+                operation: Operation::NOP,
+                mode: AddressingMode::Implied,
+                operand: 0,
+                operand_size: 0,
+            },
+        }
+    }
+}
+
+impl W65C816Decoder {
+    /// Decode the instruction at the given 24-bit address, using the widths of the accumulator and index
+    /// registers to determine the size of immediate operands.  The operand bytes wrap around within the bank
+    pub fn decode_at<Bus>(
+        bus: &mut Bus,
+        clock: Bus::Instant,
+        start: W65C816Address,
+        wide_accumulator: bool,
+        wide_index: bool,
+    ) -> Result<Self, W65C816Error>
+    where
+        Bus: BusAccess<W65C816Address>,
+    {
+        let bank = start & 0xFF_0000;
+        let read = |bus: &mut Bus, offset: u32| -> Result<u8, W65C816Error> {
+            bus.read_u8(clock, bank | (start.wrapping_add(offset) & 0xFFFF))
+                .map_err(|err| W65C816Error::BusError(format!("{:?}", err)))
+        };
+
+        let opcode = read(bus, 0)?;
+        let (operation, mode) = decode_opcode(opcode);
+        let operand_size = mode.operand_size(wide_accumulator, wide_index);
+        let mut operand = 0;
+        for i in 0..operand_size {
+            operand |= (read(bus, i + 1)? as u32) << (i * 8);
+        }
+
+        Ok(Self {
+            start,
+            end: bank | (start.wrapping_add(1 + operand_size) & 0xFFFF),
+            instruction: Instruction {
+                opcode,
+                operation,
+                mode,
+                operand,
+                operand_size,
+            },
+        })
+    }
+
+    pub fn dump_disassembly<Bus>(bus: &mut Bus, start: W65C816Address, length: W65C816Address)
+    where
+        Bus: BusAccess<W65C816Address>,
+    {
+        let mut next = start;
+        while next < (start + length) {
+            match W65C816Decoder::decode_at(bus, Bus::Instant::START, next, false, false) {
+                Ok(decoder) => {
+                    println!("{:06x}: {}", decoder.start, decoder.instruction);
+                    next = decoder.end;
+                },
+                Err(err) => {
+                    println!("{:?}", err);
+                    return;
+                },
+            }
+        }
+    }
+}
+
+/// Returns the operation and addressing mode of an opcode.  Every opcode is defined on the 65C816
+pub fn decode_opcode(opcode: u8) -> (Operation, AddressingMode) {
+    let row = opcode >> 4;
+    let column = opcode & 0x0F;
+    let odd_row = row & 0x01 != 0;
+
+    // Most of the odd columns, and column 2 of the odd rows, are the accumulator operations
+    if opcode == 0x89 {
+        return (Operation::BIT, AddressingMode::ImmediateM);
+    }
+    if column & 0x01 != 0 && column != 0x0B || column == 0x02 && odd_row {
+        let operation = ALU_OPERATIONS[(row >> 1) as usize];
+        let mode = match (odd_row, column) {
+            (false, 0x1) => AddressingMode::DirectIndexedIndirect,
+            (false, 0x3) => AddressingMode::StackRelative,
+            (false, 0x5) => AddressingMode::Direct,
+            (false, 0x7) => AddressingMode::DirectIndirectLong,
+            (false, 0x9) => AddressingMode::ImmediateM,
+            (false, 0xD) => AddressingMode::Absolute,
+            (false, 0xF) => AddressingMode::AbsoluteLong,
+            (true, 0x1) => AddressingMode::DirectIndirectIndexed,
+            (true, 0x2) => AddressingMode::DirectIndirect,
+            (true, 0x3) => AddressingMode::StackRelativeIndirectIndexed,
+            (true, 0x5) => AddressingMode::DirectX,
+            (true, 0x7) => AddressingMode::DirectIndirectLongIndexed,
+            (true, 0x9) => AddressingMode::AbsoluteY,
+            (true, 0xD) => AddressingMode::AbsoluteX,
+            (true, 0xF) => AddressingMode::AbsoluteLongX,
+            _ => unreachable!(),
+        };
+        return (operation, mode);
+    }
+
+    // The shifts and rotates are in columns 6, A, and E of the first half
+    if row < 0x8 && (column == 0x6 || column == 0xE || column == 0xA && !odd_row) {
+        let operation = SHIFT_OPERATIONS[(row >> 1) as usize];
+        let mode = match (odd_row, column) {
+            (false, 0x6) => AddressingMode::Direct,
+            (false, 0xA) => AddressingMode::Accumulator,
+            (false, 0xE) => AddressingMode::Absolute,
+            (true, 0x6) => AddressingMode::DirectX,
+            (true, 0xE) => AddressingMode::AbsoluteX,
+            _ => unreachable!(),
+        };
+        return (operation, mode);
+    }
+
+    match opcode {
+        0x00 => (Operation::BRK, AddressingMode::Immediate8),
+        0x02 => (Operation::COP, AddressingMode::Immediate8),
+        0x04 => (Operation::TSB, AddressingMode::Direct),
+        0x08 => (Operation::PHP, AddressingMode::Implied),
+        0x0B => (Operation::PHD, AddressingMode::Implied),
+        0x0C => (Operation::TSB, AddressingMode::Absolute),
+
+        0x10 => (Operation::BPL, AddressingMode::Relative),
+        0x14 => (Operation::TRB, AddressingMode::Direct),
+        0x18 => (Operation::CLC, AddressingMode::Implied),
+        0x1A => (Operation::INC, AddressingMode::Accumulator),
+        0x1B => (Operation::TCS, AddressingMode::Implied),
+        0x1C => (Operation::TRB, AddressingMode::Absolute),
+
+        0x20 => (Operation::JSR, AddressingMode::Absolute),
+        0x22 => (Operation::JSL, AddressingMode::AbsoluteLong),
+        0x24 => (Operation::BIT, AddressingMode::Direct),
+        0x28 => (Operation::PLP, AddressingMode::Implied),
+        0x2B => (Operation::PLD, AddressingMode::Implied),
+        0x2C => (Operation::BIT, AddressingMode::Absolute),
+
+        0x30 => (Operation::BMI, AddressingMode::Relative),
+        0x34 => (Operation::BIT, AddressingMode::DirectX),
+        0x38 => (Operation::SEC, AddressingMode::Implied),
+        0x3A => (Operation::DEC, AddressingMode::Accumulator),
+        0x3B => (Operation::TSC, AddressingMode::Implied),
+        0x3C => (Operation::BIT, AddressingMode::AbsoluteX),
+
+        0x40 => (Operation::RTI, AddressingMode::Implied),
+        0x42 => (Operation::WDM, AddressingMode::Immediate8),
+        0x44 => (Operation::MVP, AddressingMode::BlockMove),
+        0x48 => (Operation::PHA, AddressingMode::Implied),
+        0x4B => (Operation::PHK, AddressingMode::Implied),
+        0x4C => (Operation::JMP, AddressingMode::Absolute),
+
+        0x50 => (Operation::BVC, AddressingMode::Relative),
+        0x54 => (Operation::MVN, AddressingMode::BlockMove),
+        0x58 => (Operation::CLI, AddressingMode::Implied),
+        0x5A => (Operation::PHY, AddressingMode::Implied),
+        0x5B => (Operation::TCD, AddressingMode::Implied),
+        0x5C => (Operation::JML, AddressingMode::AbsoluteLong),
+
+        0x60 => (Operation::RTS, AddressingMode::Implied),
+        0x62 => (Operation::PER, AddressingMode::RelativeLong),
+        0x64 => (Operation::STZ, AddressingMode::Direct),
+        0x68 => (Operation::PLA, AddressingMode::Implied),
+        0x6B => (Operation::RTL, AddressingMode::Implied),
+        0x6C => (Operation::JMP, AddressingMode::AbsoluteIndirect),
+
+        0x70 => (Operation::BVS, AddressingMode::Relative),
+        0x74 => (Operation::STZ, AddressingMode::DirectX),
+        0x78 => (Operation::SEI, AddressingMode::Implied),
+        0x7A => (Operation::PLY, AddressingMode::Implied),
+        0x7B => (Operation::TDC, AddressingMode::Implied),
+        0x7C => (Operation::JMP, AddressingMode::AbsoluteIndexedIndirect),
+
+        0x80 => (Operation::BRA, AddressingMode::Relative),
+        0x82 => (Operation::BRL, AddressingMode::RelativeLong),
+        0x84 => (Operation::STY, AddressingMode::Direct),
+        0x86 => (Operation::STX, AddressingMode::Direct),
+        0x88 => (Operation::DEY, AddressingMode::Implied),
+        0x8A => (Operation::TXA, AddressingMode::Implied),
+        0x8B => (Operation::PHB, AddressingMode::Implied),
+        0x8C => (Operation::STY, AddressingMode::Absolute),
+        0x8E => (Operation::STX, AddressingMode::Absolute),
+
+        0x90 => (Operation::BCC, AddressingMode::Relative),
+        0x94 => (Operation::STY, AddressingMode::DirectX),
+        0x96 => (Operation::STX, AddressingMode::DirectY),
+        0x98 => (Operation::TYA, AddressingMode::Implied),
+        0x9A => (Operation::TXS, AddressingMode::Implied),
+        0x9B => (Operation::TXY, AddressingMode::Implied),
+        0x9C => (Operation::STZ, AddressingMode::Absolute),
+        0x9E => (Operation::STZ, AddressingMode::AbsoluteX),
+
+        0xA0 => (Operation::LDY, AddressingMode::ImmediateX),
+        0xA2 => (Operation::LDX, AddressingMode::ImmediateX),
+        0xA4 => (Operation::LDY, AddressingMode::Direct),
+        0xA6 => (Operation::LDX, AddressingMode::Direct),
+        0xA8 => (Operation::TAY, AddressingMode::Implied),
+        0xAA => (Operation::TAX, AddressingMode::Implied),
+        0xAB => (Operation::PLB, AddressingMode::Implied),
+        0xAC => (Operation::LDY, AddressingMode::Absolute),
+        0xAE => (Operation::LDX, AddressingMode::Absolute),
+
+        0xB0 => (Operation::BCS, AddressingMode::Relative),
+        0xB4 => (Operation::LDY, AddressingMode::DirectX),
+        0xB6 => (Operation::LDX, AddressingMode::DirectY),
+        0xB8 => (Operation::CLV, AddressingMode::Implied),
+        0xBA => (Operation::TSX, AddressingMode::Implied),
+        0xBB => (Operation::TYX, AddressingMode::Implied),
+        0xBC => (Operation::LDY, AddressingMode::AbsoluteX),
+        0xBE => (Operation::LDX, AddressingMode::AbsoluteY),
+
+        0xC0 => (Operation::CPY, AddressingMode::ImmediateX),
+        0xC2 => (Operation::REP, AddressingMode::Immediate8),
+        0xC4 => (Operation::CPY, AddressingMode::Direct),
+        0xC6 => (Operation::DEC, AddressingMode::Direct),
+        0xC8 => (Operation::INY, AddressingMode::Implied),
+        0xCA => (Operation::DEX, AddressingMode::Implied),
+        0xCB => (Operation::WAI, AddressingMode::Implied),
+        0xCC => (Operation::CPY, AddressingMode::Absolute),
+        0xCE => (Operation::DEC, AddressingMode::Absolute),
+
+        0xD0 => (Operation::BNE, AddressingMode::Relative),
+        0xD4 => (Operation::PEI, AddressingMode::DirectIndirect),
+        0xD6 => (Operation::DEC, AddressingMode::DirectX),
+        0xD8 => (Operation::CLD, AddressingMode::Implied),
+        0xDA => (Operation::PHX, AddressingMode::Implied),
+        0xDB => (Operation::STP, AddressingMode::Implied),
+        0xDC => (Operation::JML, AddressingMode::AbsoluteIndirectLong),
+        0xDE => (Operation::DEC, AddressingMode::AbsoluteX),
+
+        0xE0 => (Operation::CPX, AddressingMode::ImmediateX),
+        0xE2 => (Operation::SEP, AddressingMode::Immediate8),
+        0xE4 => (Operation::CPX, AddressingMode::Direct),
+        0xE6 => (Operation::INC, AddressingMode::Direct),
+        0xE8 => (Operation::INX, AddressingMode::Implied),
+        0xEA => (Operation::NOP, AddressingMode::Implied),
+        0xEB => (Operation::XBA, AddressingMode::Implied),
+        0xEC => (Operation::CPX, AddressingMode::Absolute),
+        0xEE => (Operation::INC, AddressingMode::Absolute),
+
+        0xF0 => (Operation::BEQ, AddressingMode::Relative),
+        0xF4 => (Operation::PEA, AddressingMode::Absolute),
+        0xF6 => (Operation::INC, AddressingMode::DirectX),
+        0xF8 => (Operation::SED, AddressingMode::Implied),
+        0xFA => (Operation::PLX, AddressingMode::Implied),
+        0xFB => (Operation::XCE, AddressingMode::Implied),
+        0xFC => (Operation::JSR, AddressingMode::AbsoluteIndexedIndirect),
+        0xFE => (Operation::INC, AddressingMode::AbsoluteX),
+
+        _ => unreachable!("every opcode is covered by the cases above"),
+    }
+}
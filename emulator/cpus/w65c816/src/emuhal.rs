@@ -0,0 +1,98 @@
+use core::fmt;
+use emulator_hal::{BusAccess, Instant as EmuInstant, ErrorType, Step, Inspect, Debug};
+
+use crate::state::{W65C816, W65C816Error, W65C816Address, Status};
+
+impl ErrorType for W65C816Error {}
+
+impl<Instant, Bus> Step<W65C816Address, Bus> for W65C816<Instant>
+where
+    Instant: EmuInstant,
+    Bus: BusAccess<W65C816Address, Instant = Instant>,
+{
+    type Error = W65C816Error;
+
+    fn is_running(&mut self) -> bool {
+        matches!(self.state.status, Status::Running | Status::Waiting)
+    }
+
+    fn reset(&mut self, _now: Bus::Instant, _bus: &mut Bus) -> Result<(), Self::Error> {
+        self.clear_state();
+        Ok(())
+    }
+
+    fn step(&mut self, now: Bus::Instant, bus: &mut Bus) -> Result<Bus::Instant, Self::Error> {
+        let mut executor = self.begin(now, bus)?;
+        let clocks = executor.step_one()?;
+        self.previous_cycle = executor.end();
+        Ok(now + Instant::hertz_to_duration(self.frequency.as_hz() as u64) * clocks as u32)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum W65C816Info {
+    State,
+}
+
+impl<Bus, BusError, Instant, Writer> Inspect<W65C816Address, Bus, Writer> for W65C816<Instant>
+where
+    Bus: BusAccess<W65C816Address, Instant = Instant, Error = BusError>,
+    BusError: ErrorType,
+    Instant: EmuInstant,
+    Writer: fmt::Write,
+{
+    type InfoType = W65C816Info;
+
+    type Error = W65C816Error;
+
+    fn inspect(&mut self, info: Self::InfoType, _bus: &mut Bus, writer: &mut Writer) -> Result<(), Self::Error> {
+        match info {
+            W65C816Info::State => self
+                .dump_state(writer)
+                .map_err(|_| W65C816Error::Other("error while formatting state".to_string())),
+        }
+    }
+
+    fn brief_summary(&mut self, bus: &mut Bus, writer: &mut Writer) -> Result<(), Self::Error> {
+        self.inspect(W65C816Info::State, bus, writer)
+    }
+
+    fn detailed_summary(&mut self, bus: &mut Bus, writer: &mut Writer) -> Result<(), Self::Error> {
+        self.inspect(W65C816Info::State, bus, writer)
+    }
+}
+
+/// Control the execution of a CPU device for debugging purposes
+impl<Bus, BusError, Instant, Writer> Debug<W65C816Address, Bus, Writer> for W65C816<Instant>
+where
+    Bus: BusAccess<W65C816Address, Instant = Instant, Error = BusError>,
+    BusError: ErrorType,
+    Instant: EmuInstant,
+    Writer: fmt::Write,
+{
+    type DebugError = W65C816Error;
+
+    fn get_execution_address(&mut self) -> Result<W65C816Address, Self::DebugError> {
+        Ok(self.state.program_address())
+    }
+
+    fn set_execution_address(&mut self, address: W65C816Address) -> Result<(), Self::DebugError> {
+        self.state.pbr = (address >> 16) as u8;
+        self.state.pc = address as u16;
+        Ok(())
+    }
+
+    fn add_breakpoint(&mut self, address: W65C816Address) {
+        self.debugger.breakpoints.push(address);
+    }
+
+    fn remove_breakpoint(&mut self, address: W65C816Address) {
+        if let Some(index) = self.debugger.breakpoints.iter().position(|a| *a == address) {
+            self.debugger.breakpoints.remove(index);
+        }
+    }
+
+    fn clear_breakpoints(&mut self) {
+        self.debugger.breakpoints.clear();
+    }
+}
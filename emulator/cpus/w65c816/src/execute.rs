@@ -0,0 +1,891 @@
+use emulator_hal::{BusAccess, Instant as EmuInstant};
+
+use crate::decode::W65C816Decoder;
+use crate::instructions::{AddressingMode, Operation};
+use crate::state::{W65C816, W65C816Error, W65C816State, W65C816Signals, W65C816Address, Status, Flags, vectors};
+use crate::debugger::W65C816Debugger;
+
+
+const ADDRESS_MASK: W65C816Address = 0xFF_FFFF;
+
+/// The number of clocks each opcode takes with 8-bit registers, a page-aligned direct page register, no page
+/// crossings, and no branches taken
+#[rustfmt::skip]
+const OPCODE_CLOCKS: [u8; 256] = [
+    7, 6, 7, 4, 5, 3, 5, 6, 3, 2, 2, 4, 6, 4, 6, 5,
+    2, 5, 5, 7, 5, 4, 6, 6, 2, 4, 2, 2, 6, 4, 7, 5,
+    6, 6, 8, 4, 3, 3, 5, 6, 4, 2, 2, 5, 4, 4, 6, 5,
+    2, 5, 5, 7, 4, 4, 6, 6, 2, 4, 2, 2, 4, 4, 7, 5,
+    6, 6, 2, 4, 7, 3, 5, 6, 3, 2, 2, 3, 3, 4, 6, 5,
+    2, 5, 5, 7, 7, 4, 6, 6, 2, 4, 3, 2, 4, 4, 7, 5,
+    6, 6, 6, 4, 3, 3, 5, 6, 4, 2, 2, 6, 5, 4, 6, 5,
+    2, 5, 5, 7, 4, 4, 6, 6, 2, 4, 4, 2, 6, 4, 7, 5,
+    2, 6, 4, 4, 3, 3, 3, 6, 2, 2, 2, 3, 4, 4, 4, 5,
+    2, 6, 5, 7, 4, 4, 4, 6, 2, 5, 2, 2, 4, 5, 5, 5,
+    2, 6, 2, 4, 3, 3, 3, 6, 2, 2, 2, 4, 4, 4, 4, 5,
+    2, 5, 5, 7, 4, 4, 4, 6, 2, 4, 2, 2, 4, 4, 4, 5,
+    2, 6, 3, 4, 3, 3, 5, 6, 2, 2, 2, 3, 4, 4, 6, 5,
+    2, 5, 5, 7, 6, 4, 6, 6, 2, 4, 3, 3, 6, 4, 7, 5,
+    2, 6, 3, 4, 3, 3, 5, 6, 2, 2, 2, 3, 4, 4, 6, 5,
+    2, 5, 5, 7, 5, 4, 6, 6, 2, 4, 4, 2, 8, 4, 7, 5,
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Target {
+    Accumulator,
+    Immediate(u16),
+    /// A 24-bit address, where 16-bit values continue into the next bank
+    Memory(W65C816Address),
+    /// An address in bank 0, where 16-bit values wrap around within the bank, used for the direct page and stack
+    Bank0(u16),
+}
+
+#[derive(Clone)]
+pub struct W65C816Cycle<Instant> {
+    pub current_clock: Instant,
+    pub decoder: W65C816Decoder,
+    pub took_branch: bool,
+    /// True if an indexed address crossed into another page, which takes an extra clock for some instructions
+    pub page_crossed: bool,
+}
+
+impl<Instant> W65C816Cycle<Instant> {
+    pub fn at_time(current_clock: Instant) -> Self {
+        Self {
+            current_clock,
+            decoder: Default::default(),
+            took_branch: false,
+            page_crossed: false,
+        }
+    }
+}
+
+impl<Instant> W65C816<Instant>
+where
+    Instant: EmuInstant,
+{
+    pub(crate) fn begin<'a, Bus>(
+        &'a mut self,
+        clock: Instant,
+        bus: &'a mut Bus,
+    ) -> Result<ExecuteNext<'a, &'a mut Bus, Instant>, W65C816Error>
+    where
+        Bus: BusAccess<W65C816Address, Instant = Instant>,
+    {
+        let executor = ExecuteNext {
+            state: &mut self.state,
+            signals: &mut self.signals,
+            debugger: &mut self.debugger,
+            cycle: W65C816Cycle::at_time(clock),
+            bus,
+        };
+
+        Ok(executor)
+    }
+}
+
+pub(crate) struct ExecuteNext<'a, Bus, Instant>
+where
+    Bus: BusAccess<W65C816Address, Instant = Instant>,
+{
+    pub(crate) state: &'a mut W65C816State,
+    signals: &'a mut W65C816Signals,
+    pub(crate) debugger: &'a mut W65C816Debugger,
+    cycle: W65C816Cycle<Instant>,
+    bus: Bus,
+}
+
+impl<'a, Bus, Instant> ExecuteNext<'a, Bus, Instant>
+where
+    Bus: BusAccess<W65C816Address, Instant = Instant>,
+    Instant: EmuInstant,
+{
+    pub(crate) fn end(self) -> W65C816Cycle<Instant> {
+        self.cycle
+    }
+
+    pub(crate) fn step_one(&mut self) -> Result<u16, W65C816Error> {
+        if self.signals.reset {
+            self.signals.reset = false;
+            return self.reset();
+        }
+
+        match self.state.status {
+            Status::Init => self.reset(),
+            Status::Stopped => Err(W65C816Error::Stopped),
+            Status::Waiting if !self.signals.nmi && !self.signals.irq => Ok(1),
+            Status::Waiting | Status::Running => {
+                self.state.status = Status::Running;
+                if self.signals.nmi {
+                    self.signals.nmi = false;
+                    self.interrupt(vectors::NATIVE_NMI, vectors::EMULATION_NMI, false)
+                } else if self.signals.irq && !self.get_flag(Flags::IrqDisable) {
+                    self.interrupt(vectors::NATIVE_IRQ, vectors::EMULATION_IRQ, false)
+                } else {
+                    self.cycle_one()
+                }
+            },
+        }
+    }
+
+    fn reset(&mut self) -> Result<u16, W65C816Error> {
+        *self.state = W65C816State::default();
+        self.state.pc = self.read_bank0_u16(vectors::RESET)?;
+        self.state.status = Status::Running;
+        Ok(8)
+    }
+
+    fn cycle_one(&mut self) -> Result<u16, W65C816Error> {
+        let pc = self.state.program_address();
+        self.debugger.check_breakpoints(pc)?;
+
+        let (wide_accumulator, wide_index) = (self.state.wide_accumulator(), self.state.wide_index());
+        self.cycle.decoder = W65C816Decoder::decode_at(&mut self.bus, self.cycle.current_clock, pc, wide_accumulator, wide_index)?;
+        self.state.pc = self.cycle.decoder.end as u16;
+
+        let native = !self.state.emulation;
+        let direct_page_offset = self.state.d & 0x00FF != 0;
+        self.execute_current()?;
+
+        let instruction = &self.cycle.decoder.instruction;
+        let mode = instruction.mode;
+        let mut clocks = OPCODE_CLOCKS[instruction.opcode as usize] as u16;
+
+        let accesses_memory = !matches!(mode, AddressingMode::Implied | AddressingMode::Accumulator);
+        let is_store = matches!(instruction.operation, Operation::STA | Operation::STX | Operation::STY | Operation::STZ);
+        let is_read_modify_write = accesses_memory
+            && matches!(
+                instruction.operation,
+                Operation::ASL
+                    | Operation::LSR
+                    | Operation::ROL
+                    | Operation::ROR
+                    | Operation::INC
+                    | Operation::DEC
+                    | Operation::TSB
+                    | Operation::TRB
+            );
+
+        match instruction.operation {
+            Operation::ORA
+            | Operation::AND
+            | Operation::EOR
+            | Operation::ADC
+            | Operation::STA
+            | Operation::LDA
+            | Operation::CMP
+            | Operation::SBC
+            | Operation::BIT
+            | Operation::STZ
+                if accesses_memory && wide_accumulator =>
+            {
+                clocks += 1
+            },
+            _ if is_read_modify_write && wide_accumulator => clocks += 2,
+            Operation::LDX | Operation::LDY | Operation::STX | Operation::STY | Operation::CPX | Operation::CPY if wide_index => {
+                clocks += 1
+            },
+            Operation::PHA | Operation::PLA if wide_accumulator => clocks += 1,
+            Operation::PHX | Operation::PHY | Operation::PLX | Operation::PLY if wide_index => clocks += 1,
+            Operation::BRK | Operation::COP | Operation::RTI if native => clocks += 1,
+            _ => {},
+        }
+
+        if mode.is_direct() && direct_page_offset {
+            clocks += 1;
+        }
+
+        // Indexed reads take an extra clock to fix the high byte of the address, which the stores and
+        // read-modify-write instructions always take
+        let indexed = matches!(mode, AddressingMode::AbsoluteX | AddressingMode::AbsoluteY | AddressingMode::DirectIndirectIndexed);
+        if indexed && !is_store && !is_read_modify_write && (wide_index || self.cycle.page_crossed) {
+            clocks += 1;
+        }
+
+        if self.cycle.took_branch {
+            clocks += 1;
+            let next = self.cycle.decoder.end as u16;
+            if !native && mode == AddressingMode::Relative && next & 0xFF00 != self.state.pc & 0xFF00 {
+                clocks += 1;
+            }
+        }
+
+        Ok(clocks)
+    }
+
+    fn execute_current(&mut self) -> Result<(), W65C816Error> {
+        let instruction = self.cycle.decoder.instruction;
+        let wide_accumulator = self.state.wide_accumulator();
+        let wide_index = self.state.wide_index();
+
+        match instruction.operation {
+            Operation::ADC => {
+                let value = self.read_operand(wide_accumulator)?;
+                self.add_with_carry(value, wide_accumulator);
+            },
+            Operation::AND => {
+                let value = self.read_operand(wide_accumulator)?;
+                self.set_accumulator(self.state.a & value, wide_accumulator);
+            },
+            Operation::ASL => self.read_modify_write(wide_accumulator, |cpu, value, wide| {
+                cpu.set_flag(Flags::Carry, value & sign_bit(wide) != 0);
+                value << 1
+            })?,
+            Operation::BCC => self.branch_if(!self.get_flag(Flags::Carry)),
+            Operation::BCS => self.branch_if(self.get_flag(Flags::Carry)),
+            Operation::BEQ => self.branch_if(self.get_flag(Flags::Zero)),
+            Operation::BIT => {
+                let value = self.read_operand(wide_accumulator)?;
+                let sign = sign_bit(wide_accumulator);
+                self.set_flag(Flags::Zero, self.state.a & value & mask(wide_accumulator) == 0);
+                if instruction.mode != AddressingMode::ImmediateM {
+                    self.set_flag(Flags::Negative, value & sign != 0);
+                    self.set_flag(Flags::Overflow, value & (sign >> 1) != 0);
+                }
+            },
+            Operation::BMI => self.branch_if(self.get_flag(Flags::Negative)),
+            Operation::BNE => self.branch_if(!self.get_flag(Flags::Zero)),
+            Operation::BPL => self.branch_if(!self.get_flag(Flags::Negative)),
+            Operation::BRA | Operation::BRL => self.branch_if(true),
+            Operation::BRK => {
+                self.interrupt(vectors::NATIVE_BRK, vectors::EMULATION_IRQ, true)?;
+            },
+            Operation::BVC => self.branch_if(!self.get_flag(Flags::Overflow)),
+            Operation::BVS => self.branch_if(self.get_flag(Flags::Overflow)),
+            Operation::CLC => self.set_flag(Flags::Carry, false),
+            Operation::CLD => self.set_flag(Flags::Decimal, false),
+            Operation::CLI => self.set_flag(Flags::IrqDisable, false),
+            Operation::CLV => self.set_flag(Flags::Overflow, false),
+            Operation::CMP => {
+                let value = self.read_operand(wide_accumulator)?;
+                self.compare(self.state.a, value, wide_accumulator);
+            },
+            Operation::COP => {
+                self.interrupt(vectors::NATIVE_COP, vectors::EMULATION_COP, true)?;
+            },
+            Operation::CPX => {
+                let value = self.read_operand(wide_index)?;
+                self.compare(self.state.x, value, wide_index);
+            },
+            Operation::CPY => {
+                let value = self.read_operand(wide_index)?;
+                self.compare(self.state.y, value, wide_index);
+            },
+            Operation::DEC => self.read_modify_write(wide_accumulator, |_, value, _| value.wrapping_sub(1))?,
+            Operation::DEX => {
+                self.state.x = self.state.x.wrapping_sub(1) & mask(wide_index);
+                self.set_nz(self.state.x, wide_index);
+            },
+            Operation::DEY => {
+                self.state.y = self.state.y.wrapping_sub(1) & mask(wide_index);
+                self.set_nz(self.state.y, wide_index);
+            },
+            Operation::EOR => {
+                let value = self.read_operand(wide_accumulator)?;
+                self.set_accumulator(self.state.a ^ value, wide_accumulator);
+            },
+            Operation::INC => self.read_modify_write(wide_accumulator, |_, value, _| value.wrapping_add(1))?,
+            Operation::INX => {
+                self.state.x = self.state.x.wrapping_add(1) & mask(wide_index);
+                self.set_nz(self.state.x, wide_index);
+            },
+            Operation::INY => {
+                self.state.y = self.state.y.wrapping_add(1) & mask(wide_index);
+                self.set_nz(self.state.y, wide_index);
+            },
+            Operation::JML => {
+                let addr = match instruction.mode {
+                    AddressingMode::AbsoluteIndirectLong => self.read_bank0_u24(instruction.operand as u16)?,
+                    _ => instruction.operand,
+                };
+                self.jump_long(addr);
+            },
+            Operation::JMP => {
+                self.state.pc = self.jump_target()?;
+            },
+            Operation::JSL => {
+                self.push_u8(self.state.pbr)?;
+                self.push_u16(self.state.pc.wrapping_sub(1))?;
+                self.jump_long(instruction.operand);
+            },
+            Operation::JSR => {
+                let target = self.jump_target()?;
+                self.push_u16(self.state.pc.wrapping_sub(1))?;
+                self.state.pc = target;
+            },
+            Operation::LDA => {
+                let value = self.read_operand(wide_accumulator)?;
+                self.set_accumulator(value, wide_accumulator);
+            },
+            Operation::LDX => {
+                self.state.x = self.read_operand(wide_index)?;
+                self.set_nz(self.state.x, wide_index);
+            },
+            Operation::LDY => {
+                self.state.y = self.read_operand(wide_index)?;
+                self.set_nz(self.state.y, wide_index);
+            },
+            Operation::LSR => self.read_modify_write(wide_accumulator, |cpu, value, _| {
+                cpu.set_flag(Flags::Carry, value & 0x01 != 0);
+                value >> 1
+            })?,
+            Operation::MVN | Operation::MVP => self.block_move(instruction.operation == Operation::MVN, wide_index)?,
+            Operation::NOP | Operation::WDM => {},
+            Operation::ORA => {
+                let value = self.read_operand(wide_accumulator)?;
+                self.set_accumulator(self.state.a | value, wide_accumulator);
+            },
+            Operation::PEA => self.push_u16(instruction.operand as u16)?,
+            Operation::PEI => {
+                let addr = self.state.d.wrapping_add(instruction.operand as u16);
+                let value = self.read_bank0_u16(addr)?;
+                self.push_u16(value)?;
+            },
+            Operation::PER => {
+                let value = instruction.branch_target(self.state.pc);
+                self.push_u16(value)?;
+            },
+            Operation::PHA => self.push_sized(self.state.a, wide_accumulator)?,
+            Operation::PHB => self.push_u8(self.state.dbr)?,
+            Operation::PHD => self.push_u16(self.state.d)?,
+            Operation::PHK => self.push_u8(self.state.pbr)?,
+            Operation::PHP => self.push_u8(self.state.p)?,
+            Operation::PHX => self.push_sized(self.state.x, wide_index)?,
+            Operation::PHY => self.push_sized(self.state.y, wide_index)?,
+            Operation::PLA => {
+                let value = self.pull_sized(wide_accumulator)?;
+                self.set_accumulator(value, wide_accumulator);
+            },
+            Operation::PLB => {
+                self.state.dbr = self.pull_u8()?;
+                self.set_nz(self.state.dbr as u16, false);
+            },
+            Operation::PLD => {
+                self.state.d = self.pull_u16()?;
+                self.set_nz(self.state.d, true);
+            },
+            Operation::PLP => {
+                self.state.p = self.pull_u8()?;
+                self.update_register_widths();
+            },
+            Operation::PLX => {
+                self.state.x = self.pull_sized(wide_index)?;
+                self.set_nz(self.state.x, wide_index);
+            },
+            Operation::PLY => {
+                self.state.y = self.pull_sized(wide_index)?;
+                self.set_nz(self.state.y, wide_index);
+            },
+            Operation::REP => {
+                self.state.p &= !(instruction.operand as u8);
+                self.update_register_widths();
+            },
+            Operation::ROL => self.read_modify_write(wide_accumulator, |cpu, value, wide| {
+                let carry = cpu.get_flag(Flags::Carry) as u16;
+                cpu.set_flag(Flags::Carry, value & sign_bit(wide) != 0);
+                (value << 1) | carry
+            })?,
+            Operation::ROR => self.read_modify_write(wide_accumulator, |cpu, value, wide| {
+                let carry = if cpu.get_flag(Flags::Carry) { sign_bit(wide) } else { 0 };
+                cpu.set_flag(Flags::Carry, value & 0x01 != 0);
+                (value >> 1) | carry
+            })?,
+            Operation::RTI => {
+                self.state.p = self.pull_u8()?;
+                self.update_register_widths();
+                self.state.pc = self.pull_u16()?;
+                if !self.state.emulation {
+                    self.state.pbr = self.pull_u8()?;
+                }
+            },
+            Operation::RTL => {
+                self.state.pc = self.pull_u16()?.wrapping_add(1);
+                self.state.pbr = self.pull_u8()?;
+            },
+            Operation::RTS => {
+                self.state.pc = self.pull_u16()?.wrapping_add(1);
+            },
+            Operation::SBC => {
+                let value = self.read_operand(wide_accumulator)?;
+                self.subtract_with_borrow(value, wide_accumulator);
+            },
+            Operation::SEC => self.set_flag(Flags::Carry, true),
+            Operation::SED => self.set_flag(Flags::Decimal, true),
+            Operation::SEI => self.set_flag(Flags::IrqDisable, true),
+            Operation::SEP => {
+                self.state.p |= instruction.operand as u8;
+                self.update_register_widths();
+            },
+            Operation::STA => self.write_operand(self.state.a, wide_accumulator)?,
+            Operation::STP => self.state.status = Status::Stopped,
+            Operation::STX => self.write_operand(self.state.x, wide_index)?,
+            Operation::STY => self.write_operand(self.state.y, wide_index)?,
+            Operation::STZ => self.write_operand(0, wide_accumulator)?,
+            Operation::TAX => {
+                self.state.x = self.state.a & mask(wide_index);
+                self.set_nz(self.state.x, wide_index);
+            },
+            Operation::TAY => {
+                self.state.y = self.state.a & mask(wide_index);
+                self.set_nz(self.state.y, wide_index);
+            },
+            Operation::TCD => {
+                self.state.d = self.state.a;
+                self.set_nz(self.state.d, true);
+            },
+            Operation::TCS => self.set_stack_pointer(self.state.a),
+            Operation::TDC => {
+                self.state.a = self.state.d;
+                self.set_nz(self.state.a, true);
+            },
+            Operation::TRB | Operation::TSB => {
+                let set = instruction.operation == Operation::TSB;
+                self.read_modify_write(wide_accumulator, |cpu, value, wide| {
+                    cpu.set_flag(Flags::Zero, value & cpu.state.a & mask(wide) == 0);
+                    if set { value | cpu.state.a } else { value & !cpu.state.a }
+                })?;
+            },
+            Operation::TSC => {
+                self.state.a = self.state.s;
+                self.set_nz(self.state.a, true);
+            },
+            Operation::TSX => {
+                self.state.x = self.state.s & mask(wide_index);
+                self.set_nz(self.state.x, wide_index);
+            },
+            Operation::TXA => self.set_accumulator(self.state.x, wide_accumulator),
+            Operation::TXS => self.set_stack_pointer(self.state.x),
+            Operation::TXY => {
+                self.state.y = self.state.x;
+                self.set_nz(self.state.y, wide_index);
+            },
+            Operation::TYA => self.set_accumulator(self.state.y, wide_accumulator),
+            Operation::TYX => {
+                self.state.x = self.state.y;
+                self.set_nz(self.state.x, wide_index);
+            },
+            Operation::WAI => self.state.status = Status::Waiting,
+            Operation::XBA => {
+                self.state.a = self.state.a.swap_bytes();
+                self.set_nz(self.state.a & 0xFF, false);
+            },
+            Operation::XCE => {
+                let carry = self.get_flag(Flags::Carry);
+                self.set_flag(Flags::Carry, self.state.emulation);
+                self.state.emulation = carry;
+                if carry {
+                    self.state.p |= Flags::MemoryWidth as u8 | Flags::IndexWidth as u8;
+                    self.set_stack_pointer(self.state.s);
+                }
+                self.update_register_widths();
+            },
+        }
+        Ok(())
+    }
+
+    /// Push the program counter and status, and jump to the vector for the current mode.  Software interrupts
+    /// set the break flag in the pushed status when in emulation mode, which shares its bit with the X flag
+    fn interrupt(&mut self, native_vector: u16, emulation_vector: u16, software: bool) -> Result<u16, W65C816Error> {
+        let vector = if self.state.emulation {
+            self.push_u16(self.state.pc)?;
+            let status = if software {
+                self.state.p | Flags::IndexWidth as u8
+            } else {
+                self.state.p & !(Flags::IndexWidth as u8)
+            };
+            self.push_u8(status)?;
+            emulation_vector
+        } else {
+            self.push_u8(self.state.pbr)?;
+            self.push_u16(self.state.pc)?;
+            self.push_u8(self.state.p)?;
+            native_vector
+        };
+
+        self.set_flag(Flags::IrqDisable, true);
+        self.set_flag(Flags::Decimal, false);
+        self.state.pbr = 0;
+        self.state.pc = self.read_bank0_u16(vector)?;
+        Ok(if self.state.emulation { 7 } else { 8 })
+    }
+
+    fn branch_if(&mut self, condition: bool) {
+        if condition {
+            self.state.pc = self.cycle.decoder.instruction.branch_target(self.state.pc);
+            self.cycle.took_branch = true;
+        }
+    }
+
+    fn jump_long(&mut self, addr: W65C816Address) {
+        self.state.pbr = (addr >> 16) as u8;
+        self.state.pc = addr as u16;
+    }
+
+    /// Returns the target address of a JMP or JSR within the program bank
+    fn jump_target(&mut self) -> Result<u16, W65C816Error> {
+        let instruction = self.cycle.decoder.instruction;
+        match instruction.mode {
+            AddressingMode::AbsoluteIndirect => self.read_bank0_u16(instruction.operand as u16),
+            AddressingMode::AbsoluteIndexedIndirect => {
+                let pointer = (instruction.operand as u16).wrapping_add(self.state.x);
+                let bank = (self.state.pbr as W65C816Address) << 16;
+                let low = self.read_u8(bank | pointer as W65C816Address)?;
+                let high = self.read_u8(bank | pointer.wrapping_add(1) as W65C816Address)?;
+                Ok(u16::from_le_bytes([low, high]))
+            },
+            _ => Ok(instruction.operand as u16),
+        }
+    }
+
+    /// Move one byte of a block move, and repeat the instruction until the accumulator wraps around to 0xFFFF
+    fn block_move(&mut self, increment: bool, wide_index: bool) -> Result<(), W65C816Error> {
+        let operand = self.cycle.decoder.instruction.operand;
+        let dest_bank = operand & 0xFF;
+        let src_bank = (operand >> 8) & 0xFF;
+
+        self.state.dbr = dest_bank as u8;
+        let value = self.read_u8((src_bank << 16) | self.state.x as W65C816Address)?;
+        self.write_u8((dest_bank << 16) | self.state.y as W65C816Address, value)?;
+
+        let step = if increment { 1 } else { 0xFFFF };
+        self.state.x = self.state.x.wrapping_add(step) & mask(wide_index);
+        self.state.y = self.state.y.wrapping_add(step) & mask(wide_index);
+        self.state.a = self.state.a.wrapping_sub(1);
+        if self.state.a != 0xFFFF {
+            self.state.pc = self.cycle.decoder.start as u16;
+        }
+        Ok(())
+    }
+
+    fn add_with_carry(&mut self, value: u16, wide: bool) {
+        let mask = mask(wide) as u32;
+        let accumulator = self.state.a as u32 & mask;
+        let value = value as u32 & mask;
+        let carry = self.get_flag(Flags::Carry) as u32;
+        let bits = if wide { 16 } else { 8 };
+
+        let (result, carry) = if self.get_flag(Flags::Decimal) {
+            let mut result = 0;
+            let mut carry = carry;
+            for shift in (0..bits).step_by(4) {
+                let mut digit = ((accumulator >> shift) & 0xF) + ((value >> shift) & 0xF) + carry;
+                carry = (digit > 9) as u32;
+                if carry != 0 {
+                    digit += 6;
+                }
+                result |= (digit & 0xF) << shift;
+            }
+            (result, carry != 0)
+        } else {
+            let result = accumulator + value + carry;
+            (result & mask, result > mask)
+        };
+
+        let sign = sign_bit(wide) as u32;
+        self.set_flag(Flags::Overflow, !(accumulator ^ value) & (accumulator ^ result) & sign != 0);
+        self.set_flag(Flags::Carry, carry);
+        self.set_accumulator(result as u16, wide);
+    }
+
+    fn subtract_with_borrow(&mut self, value: u16, wide: bool) {
+        let mask = mask(wide) as u32;
+        let accumulator = self.state.a as u32 & mask;
+        let value = value as u32 & mask;
+        let carry = self.get_flag(Flags::Carry) as u32;
+        let bits = if wide { 16 } else { 8 };
+
+        let (result, carry) = if self.get_flag(Flags::Decimal) {
+            let mut result = 0;
+            let mut borrow = 1 - carry as i32;
+            for shift in (0..bits).step_by(4) {
+                let mut digit = ((accumulator >> shift) & 0xF) as i32 - ((value >> shift) & 0xF) as i32 - borrow;
+                borrow = (digit < 0) as i32;
+                if borrow != 0 {
+                    digit += 10;
+                }
+                result |= ((digit as u32) & 0xF) << shift;
+            }
+            (result, borrow == 0)
+        } else {
+            let result = accumulator + (!value & mask) + carry;
+            (result & mask, result > mask)
+        };
+
+        let sign = sign_bit(wide) as u32;
+        self.set_flag(Flags::Overflow, (accumulator ^ value) & (accumulator ^ result) & sign != 0);
+        self.set_flag(Flags::Carry, carry);
+        self.set_accumulator(result as u16, wide);
+    }
+
+    fn compare(&mut self, register: u16, value: u16, wide: bool) {
+        let register = register & mask(wide);
+        let value = value & mask(wide);
+        self.set_flag(Flags::Carry, register >= value);
+        self.set_nz(register.wrapping_sub(value) & mask(wide), wide);
+    }
+
+    /// Apply an operation to the accumulator or memory operand, and set the N and Z flags from the result
+    fn read_modify_write<F>(&mut self, wide: bool, operation: F) -> Result<(), W65C816Error>
+    where
+        F: FnOnce(&mut Self, u16, bool) -> u16,
+    {
+        let target = self.operand_target()?;
+        let value = self.read_target(target, wide)?;
+        let result = operation(self, value, wide) & mask(wide);
+        match self.cycle.decoder.instruction.operation {
+            Operation::TSB | Operation::TRB => {},
+            _ => self.set_nz(result, wide),
+        }
+        self.write_target(target, result, wide)
+    }
+
+    fn read_operand(&mut self, wide: bool) -> Result<u16, W65C816Error> {
+        let target = self.operand_target()?;
+        self.read_target(target, wide)
+    }
+
+    fn write_operand(&mut self, value: u16, wide: bool) -> Result<(), W65C816Error> {
+        let target = self.operand_target()?;
+        self.write_target(target, value, wide)
+    }
+
+    fn read_target(&mut self, target: Target, wide: bool) -> Result<u16, W65C816Error> {
+        match target {
+            Target::Accumulator => Ok(self.state.a & mask(wide)),
+            Target::Immediate(value) => Ok(value & mask(wide)),
+            Target::Memory(addr) => {
+                let low = self.read_u8(addr)? as u16;
+                if wide {
+                    let high = self.read_u8((addr + 1) & ADDRESS_MASK)? as u16;
+                    Ok(low | (high << 8))
+                } else {
+                    Ok(low)
+                }
+            },
+            Target::Bank0(addr) => {
+                if wide {
+                    self.read_bank0_u16(addr)
+                } else {
+                    Ok(self.read_u8(addr as W65C816Address)? as u16)
+                }
+            },
+        }
+    }
+
+    fn write_target(&mut self, target: Target, value: u16, wide: bool) -> Result<(), W65C816Error> {
+        match target {
+            Target::Accumulator => {
+                self.state.a = if wide {
+                    value
+                } else {
+                    (self.state.a & 0xFF00) | (value & 0xFF)
+                };
+                Ok(())
+            },
+            Target::Immediate(_) => Err(W65C816Error::Other(format!(
+                "cannot write to an immediate operand in {}",
+                self.cycle.decoder.instruction
+            ))),
+            Target::Memory(addr) => {
+                self.write_u8(addr, value as u8)?;
+                if wide {
+                    self.write_u8((addr + 1) & ADDRESS_MASK, (value >> 8) as u8)?;
+                }
+                Ok(())
+            },
+            Target::Bank0(addr) => {
+                self.write_u8(addr as W65C816Address, value as u8)?;
+                if wide {
+                    self.write_u8(addr.wrapping_add(1) as W65C816Address, (value >> 8) as u8)?;
+                }
+                Ok(())
+            },
+        }
+    }
+
+    /// Calculate the effective address of the current instruction's operand
+    fn operand_target(&mut self) -> Result<Target, W65C816Error> {
+        let instruction = self.cycle.decoder.instruction;
+        let operand = instruction.operand;
+        let data_bank = (self.state.dbr as W65C816Address) << 16;
+
+        let target = match instruction.mode {
+            AddressingMode::Implied | AddressingMode::Accumulator => Target::Accumulator,
+            AddressingMode::ImmediateM | AddressingMode::ImmediateX | AddressingMode::Immediate8 => {
+                Target::Immediate(operand as u16)
+            },
+            AddressingMode::Absolute => Target::Memory(data_bank | operand),
+            AddressingMode::AbsoluteX => self.indexed(data_bank | operand, self.state.x),
+            AddressingMode::AbsoluteY => self.indexed(data_bank | operand, self.state.y),
+            AddressingMode::AbsoluteLong => Target::Memory(operand),
+            AddressingMode::AbsoluteLongX => Target::Memory((operand + self.state.x as W65C816Address) & ADDRESS_MASK),
+            AddressingMode::Direct => Target::Bank0(self.direct_address(operand as u8, 0)),
+            AddressingMode::DirectX => Target::Bank0(self.direct_address(operand as u8, self.state.x)),
+            AddressingMode::DirectY => Target::Bank0(self.direct_address(operand as u8, self.state.y)),
+            AddressingMode::DirectIndirect => {
+                let pointer = self.read_bank0_u16(self.direct_address(operand as u8, 0))?;
+                Target::Memory(data_bank | pointer as W65C816Address)
+            },
+            AddressingMode::DirectIndexedIndirect => {
+                let pointer = self.read_bank0_u16(self.direct_address(operand as u8, self.state.x))?;
+                Target::Memory(data_bank | pointer as W65C816Address)
+            },
+            AddressingMode::DirectIndirectIndexed => {
+                let pointer = self.read_bank0_u16(self.direct_address(operand as u8, 0))?;
+                self.indexed(data_bank | pointer as W65C816Address, self.state.y)
+            },
+            AddressingMode::DirectIndirectLong => {
+                let pointer = self.read_bank0_u24(self.direct_address(operand as u8, 0))?;
+                Target::Memory(pointer)
+            },
+            AddressingMode::DirectIndirectLongIndexed => {
+                let pointer = self.read_bank0_u24(self.direct_address(operand as u8, 0))?;
+                Target::Memory((pointer + self.state.y as W65C816Address) & ADDRESS_MASK)
+            },
+            AddressingMode::StackRelative => Target::Bank0(self.state.s.wrapping_add(operand as u16)),
+            AddressingMode::StackRelativeIndirectIndexed => {
+                let pointer = self.read_bank0_u16(self.state.s.wrapping_add(operand as u16))?;
+                Target::Memory(((data_bank | pointer as W65C816Address) + self.state.y as W65C816Address) & ADDRESS_MASK)
+            },
+            AddressingMode::AbsoluteIndirect
+            | AddressingMode::AbsoluteIndexedIndirect
+            | AddressingMode::AbsoluteIndirectLong
+            | AddressingMode::Relative
+            | AddressingMode::RelativeLong
+            | AddressingMode::BlockMove => {
+                return Err(W65C816Error::Other(format!("invalid operand for {}", instruction)));
+            },
+        };
+        Ok(target)
+    }
+
+    fn indexed(&mut self, base: W65C816Address, index: u16) -> Target {
+        let addr = (base + index as W65C816Address) & ADDRESS_MASK;
+        self.cycle.page_crossed = base & 0xFFFF00 != addr & 0xFFFF00;
+        Target::Memory(addr)
+    }
+
+    /// Returns the bank 0 address of a direct page offset.  In emulation mode, when the direct page is aligned
+    /// to a page, the indexed address wraps around within that page like on the 6502
+    fn direct_address(&self, offset: u8, index: u16) -> u16 {
+        if self.state.emulation && self.state.d & 0x00FF == 0 {
+            self.state.d | (offset as u16).wrapping_add(index) & 0x00FF
+        } else {
+            self.state.d.wrapping_add(offset as u16).wrapping_add(index)
+        }
+    }
+
+    fn set_accumulator(&mut self, value: u16, wide: bool) {
+        if wide {
+            self.state.a = value;
+        } else {
+            self.state.a = (self.state.a & 0xFF00) | (value & 0x00FF);
+        }
+        self.set_nz(value & mask(wide), wide);
+    }
+
+    fn set_stack_pointer(&mut self, value: u16) {
+        self.state.s = if self.state.emulation {
+            0x0100 | (value & 0x00FF)
+        } else {
+            value
+        };
+    }
+
+    /// Force the register widths after the status register has changed.  The upper bytes of the index registers
+    /// are cleared when they're 8 bits wide, but the upper byte of the accumulator is kept
+    fn update_register_widths(&mut self) {
+        if self.state.emulation {
+            self.state.p |= Flags::MemoryWidth as u8 | Flags::IndexWidth as u8;
+        }
+        if self.state.p & Flags::IndexWidth as u8 != 0 {
+            self.state.x &= 0x00FF;
+            self.state.y &= 0x00FF;
+        }
+    }
+
+    fn push_u8(&mut self, value: u8) -> Result<(), W65C816Error> {
+        self.write_u8(self.state.s as W65C816Address, value)?;
+        self.set_stack_pointer(self.state.s.wrapping_sub(1));
+        Ok(())
+    }
+
+    fn push_u16(&mut self, value: u16) -> Result<(), W65C816Error> {
+        self.push_u8((value >> 8) as u8)?;
+        self.push_u8(value as u8)
+    }
+
+    fn push_sized(&mut self, value: u16, wide: bool) -> Result<(), W65C816Error> {
+        if wide {
+            self.push_u16(value)
+        } else {
+            self.push_u8(value as u8)
+        }
+    }
+
+    fn pull_u8(&mut self) -> Result<u8, W65C816Error> {
+        self.set_stack_pointer(self.state.s.wrapping_add(1));
+        self.read_u8(self.state.s as W65C816Address)
+    }
+
+    fn pull_u16(&mut self) -> Result<u16, W65C816Error> {
+        let low = self.pull_u8()?;
+        let high = self.pull_u8()?;
+        Ok(u16::from_le_bytes([low, high]))
+    }
+
+    fn pull_sized(&mut self, wide: bool) -> Result<u16, W65C816Error> {
+        if wide { self.pull_u16() } else { Ok(self.pull_u8()? as u16) }
+    }
+
+    fn read_bank0_u16(&mut self, addr: u16) -> Result<u16, W65C816Error> {
+        let low = self.read_u8(addr as W65C816Address)?;
+        let high = self.read_u8(addr.wrapping_add(1) as W65C816Address)?;
+        Ok(u16::from_le_bytes([low, high]))
+    }
+
+    fn read_bank0_u24(&mut self, addr: u16) -> Result<W65C816Address, W65C816Error> {
+        let low = self.read_bank0_u16(addr)? as W65C816Address;
+        let bank = self.read_u8(addr.wrapping_add(2) as W65C816Address)? as W65C816Address;
+        Ok((bank << 16) | low)
+    }
+
+    fn read_u8(&mut self, addr: W65C816Address) -> Result<u8, W65C816Error> {
+        self.bus
+            .read_u8(self.cycle.current_clock, addr & ADDRESS_MASK)
+            .map_err(|err| W65C816Error::BusError(format!("{:?}", err)))
+    }
+
+    fn write_u8(&mut self, addr: W65C816Address, value: u8) -> Result<(), W65C816Error> {
+        self.bus
+            .write_u8(self.cycle.current_clock, addr & ADDRESS_MASK, value)
+            .map_err(|err| W65C816Error::BusError(format!("{:?}", err)))
+    }
+
+    fn get_flag(&self, flag: Flags) -> bool {
+        self.state.p & flag as u8 != 0
+    }
+
+    fn set_flag(&mut self, flag: Flags, value: bool) {
+        if value {
+            self.state.p |= flag as u8;
+        } else {
+            self.state.p &= !(flag as u8);
+        }
+    }
+
+    fn set_nz(&mut self, value: u16, wide: bool) {
+        self.set_flag(Flags::Zero, value & mask(wide) == 0);
+        self.set_flag(Flags::Negative, value & sign_bit(wide) != 0);
+    }
+}
+
+#[inline]
+fn mask(wide: bool) -> u16 {
+    if wide { 0xFFFF } else { 0x00FF }
+}
+
+#[inline]
+fn sign_bit(wide: bool) -> u16 {
+    if wide { 0x8000 } else { 0x0080 }
+}
@@ -0,0 +1,156 @@
+use core::fmt;
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[rustfmt::skip]
+pub enum Operation {
+    ADC, AND, ASL, BCC, BCS, BEQ, BIT, BMI, BNE, BPL, BRA, BRK, BRL, BVC, BVS, CLC,
+    CLD, CLI, CLV, CMP, COP, CPX, CPY, DEC, DEX, DEY, EOR, INC, INX, INY, JML, JMP,
+    JSL, JSR, LDA, LDX, LDY, LSR, MVN, MVP, NOP, ORA, PEA, PEI, PER, PHA, PHB, PHD,
+    PHK, PHP, PHX, PHY, PLA, PLB, PLD, PLP, PLX, PLY, REP, ROL, ROR, RTI, RTL, RTS,
+    SBC, SEC, SED, SEI, SEP, STA, STP, STX, STY, STZ, TAX, TAY, TCD, TCS, TDC, TRB,
+    TSB, TSC, TSX, TXA, TXS, TXY, TYA, TYX, WAI, WDM, XBA, XCE,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    /// An immediate value the size of the accumulator, which depends on the M flag
+    ImmediateM,
+    /// An immediate value the size of the index registers, which depends on the X flag
+    ImmediateX,
+    /// An immediate byte, which is used by REP, SEP, and the signature byte of BRK, COP, and WDM
+    Immediate8,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    AbsoluteLong,
+    AbsoluteLongX,
+    /// `(abs)`, which reads the pointer from bank 0
+    AbsoluteIndirect,
+    /// `(abs,X)`, which reads the pointer from the program bank
+    AbsoluteIndexedIndirect,
+    /// `[abs]`, which reads a 24-bit pointer from bank 0
+    AbsoluteIndirectLong,
+    Direct,
+    DirectX,
+    DirectY,
+    DirectIndirect,
+    DirectIndexedIndirect,
+    DirectIndirectIndexed,
+    DirectIndirectLong,
+    DirectIndirectLongIndexed,
+    StackRelative,
+    StackRelativeIndirectIndexed,
+    Relative,
+    RelativeLong,
+    /// The destination and source banks of MVN and MVP
+    BlockMove,
+}
+
+impl AddressingMode {
+    /// Returns the number of operand bytes that follow the opcode, given the widths of the registers
+    pub fn operand_size(&self, wide_accumulator: bool, wide_index: bool) -> u32 {
+        match self {
+            AddressingMode::Implied | AddressingMode::Accumulator => 0,
+            AddressingMode::ImmediateM => 1 + wide_accumulator as u32,
+            AddressingMode::ImmediateX => 1 + wide_index as u32,
+            AddressingMode::Immediate8
+            | AddressingMode::Direct
+            | AddressingMode::DirectX
+            | AddressingMode::DirectY
+            | AddressingMode::DirectIndirect
+            | AddressingMode::DirectIndexedIndirect
+            | AddressingMode::DirectIndirectIndexed
+            | AddressingMode::DirectIndirectLong
+            | AddressingMode::DirectIndirectLongIndexed
+            | AddressingMode::StackRelative
+            | AddressingMode::StackRelativeIndirectIndexed
+            | AddressingMode::Relative => 1,
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY
+            | AddressingMode::AbsoluteIndirect
+            | AddressingMode::AbsoluteIndexedIndirect
+            | AddressingMode::AbsoluteIndirectLong
+            | AddressingMode::RelativeLong
+            | AddressingMode::BlockMove => 2,
+            AddressingMode::AbsoluteLong | AddressingMode::AbsoluteLongX => 3,
+        }
+    }
+
+    pub fn is_direct(&self) -> bool {
+        matches!(
+            self,
+            AddressingMode::Direct
+                | AddressingMode::DirectX
+                | AddressingMode::DirectY
+                | AddressingMode::DirectIndirect
+                | AddressingMode::DirectIndexedIndirect
+                | AddressingMode::DirectIndirectIndexed
+                | AddressingMode::DirectIndirectLong
+                | AddressingMode::DirectIndirectLongIndexed
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: u8,
+    pub operation: Operation,
+    pub mode: AddressingMode,
+    /// The operand bytes in little endian order, so the block moves have the destination bank in the lower
+    /// byte and the source bank in the upper byte
+    pub operand: u32,
+    pub operand_size: u32,
+}
+
+impl Instruction {
+    /// Returns the target of a branch, given the address of the next instruction
+    pub fn branch_target(&self, next: u16) -> u16 {
+        match self.mode {
+            AddressingMode::Relative => next.wrapping_add(self.operand as u8 as i8 as u16),
+            _ => next.wrapping_add(self.operand as u16),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = format!("{:?}", self.operation).to_lowercase();
+        let value = self.operand;
+        match self.mode {
+            AddressingMode::Implied => write!(f, "{}", name),
+            AddressingMode::Accumulator => write!(f, "{} a", name),
+            AddressingMode::ImmediateM | AddressingMode::ImmediateX | AddressingMode::Immediate8 => {
+                if self.operand_size == 2 {
+                    write!(f, "{} #${:04x}", name, value)
+                } else {
+                    write!(f, "{} #${:02x}", name, value)
+                }
+            },
+            AddressingMode::Absolute => write!(f, "{} ${:04x}", name, value),
+            AddressingMode::AbsoluteX => write!(f, "{} ${:04x},x", name, value),
+            AddressingMode::AbsoluteY => write!(f, "{} ${:04x},y", name, value),
+            AddressingMode::AbsoluteLong => write!(f, "{} ${:06x}", name, value),
+            AddressingMode::AbsoluteLongX => write!(f, "{} ${:06x},x", name, value),
+            AddressingMode::AbsoluteIndirect => write!(f, "{} (${:04x})", name, value),
+            AddressingMode::AbsoluteIndexedIndirect => write!(f, "{} (${:04x},x)", name, value),
+            AddressingMode::AbsoluteIndirectLong => write!(f, "{} [${:04x}]", name, value),
+            AddressingMode::Direct => write!(f, "{} ${:02x}", name, value),
+            AddressingMode::DirectX => write!(f, "{} ${:02x},x", name, value),
+            AddressingMode::DirectY => write!(f, "{} ${:02x},y", name, value),
+            AddressingMode::DirectIndirect => write!(f, "{} (${:02x})", name, value),
+            AddressingMode::DirectIndexedIndirect => write!(f, "{} (${:02x},x)", name, value),
+            AddressingMode::DirectIndirectIndexed => write!(f, "{} (${:02x}),y", name, value),
+            AddressingMode::DirectIndirectLong => write!(f, "{} [${:02x}]", name, value),
+            AddressingMode::DirectIndirectLongIndexed => write!(f, "{} [${:02x}],y", name, value),
+            AddressingMode::StackRelative => write!(f, "{} ${:02x},s", name, value),
+            AddressingMode::StackRelativeIndirectIndexed => write!(f, "{} (${:02x},s),y", name, value),
+            AddressingMode::Relative => write!(f, "{} {:+}", name, value as u8 as i8),
+            AddressingMode::RelativeLong => write!(f, "{} {:+}", name, value as u16 as i16),
+            AddressingMode::BlockMove => write!(f, "{} ${:02x},${:02x}", name, value >> 8, value & 0xFF),
+        }
+    }
+}
@@ -0,0 +1,15 @@
+mod debugger;
+mod decode;
+mod emuhal;
+mod execute;
+mod instructions;
+mod state;
+
+#[cfg(feature = "moa")]
+pub mod moa;
+
+pub use crate::state::{W65C816, W65C816Address, W65C816Error, W65C816State, W65C816Signals, Status, Flags};
+pub use crate::decode::{W65C816Decoder, decode_opcode};
+pub use crate::execute::W65C816Cycle;
+pub use crate::instructions::{Operation, AddressingMode, Instruction};
+pub use crate::emuhal::W65C816Info;
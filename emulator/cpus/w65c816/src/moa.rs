@@ -0,0 +1,180 @@
+use std::any::Any;
+use femtos::{Instant, Duration};
+use emulator_hal::{BusAdapter, Instant as EmuInstant};
+
+use moa_core::{System, Error, Address, Addressable, Steppable, Interruptable, Debuggable, Snapshotable, Transmutable};
+
+use crate::{W65C816, W65C816Error, W65C816Decoder, W65C816State, W65C816Cycle, W65C816Signals};
+
+/// The interrupt controller priority that's connected to the NMI input.  Any lower priority asserts IRQ
+const NMI_PRIORITY: u8 = 7;
+
+impl W65C816<Instant> {
+    fn dump_breakpoints<W: std::fmt::Write>(&self, writer: &mut W) -> Result<(), std::fmt::Error> {
+        writeln!(writer, "Breakpoints:")?;
+        for (id, addr) in self.debugger.breakpoints.iter().enumerate() {
+            writeln!(writer, "  {}: {:#08x}", id, addr)?;
+        }
+        Ok(())
+    }
+}
+
+impl Steppable for W65C816<Instant> {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        let (pending, priority, _) = system.get_interrupt_controller().check();
+        if pending && priority == NMI_PRIORITY {
+            system.get_interrupt_controller().acknowledge(priority)?;
+            self.signals.nmi = true;
+        }
+        // IRQ is level triggered, so it stays asserted until the device clears it
+        self.signals.irq = pending && priority < NMI_PRIORITY;
+
+        let mut bus = system.bus.borrow_mut();
+        bus.set_access_pc(self.state.program_address() as Address);
+        let mut adapter: BusAdapter<u32, u64, &mut dyn Addressable, Error> = BusAdapter::new(&mut *bus, |addr| addr as u64);
+
+        let mut executor = self.begin(system.clock, &mut adapter)?;
+        let clocks = match executor.step_one() {
+            Ok(clocks) => clocks,
+            Err(W65C816Error::Breakpoint) => {
+                let pc = executor.state.program_address();
+                let id = executor.debugger.breakpoints.iter().position(|addr| *addr == pc);
+                return Err(Error::breakpoint_at(pc as Address, id));
+            },
+            Err(err) => return Err(err.into()),
+        };
+        self.previous_cycle = executor.end();
+        Ok(Instant::hertz_to_duration(self.frequency.as_hz() as u64) * clocks as u32)
+    }
+
+    fn on_error(&mut self, _system: &System) {
+        let mut output = String::with_capacity(256);
+        let _ = self.dump_state(&mut output);
+        println!("{}", output);
+    }
+}
+
+impl Interruptable for W65C816<Instant> {}
+
+impl Transmutable for W65C816<Instant> {
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+
+    fn as_interruptable(&mut self) -> Option<&mut dyn Interruptable> {
+        Some(self)
+    }
+
+    fn as_debuggable(&mut self) -> Option<&mut dyn Debuggable> {
+        Some(self)
+    }
+
+    fn as_snapshotable(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
+}
+
+impl Snapshotable for W65C816<Instant> {
+    fn save_state(&mut self) -> Option<Box<dyn Any>> {
+        Some(Box::new((self.state.clone(), self.previous_cycle.clone(), self.signals.clone())))
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        if let Some((state, cycle, signals)) = state.downcast_ref::<(W65C816State, W65C816Cycle<Instant>, W65C816Signals)>() {
+            self.state = state.clone();
+            self.previous_cycle = cycle.clone();
+            self.signals = signals.clone();
+        }
+    }
+}
+
+impl From<W65C816Error> for Error {
+    fn from(err: W65C816Error) -> Self {
+        match err {
+            W65C816Error::Stopped => Self::Other("cpu stopped".to_string()),
+            W65C816Error::Breakpoint => Self::breakpoint("breakpoint"),
+            W65C816Error::BusError(msg) | W65C816Error::Other(msg) => Self::Other(msg),
+        }
+    }
+}
+
+impl Debuggable for W65C816<Instant> {
+    fn add_breakpoint(&mut self, addr: Address) {
+        self.debugger.breakpoints.push(addr as u32);
+    }
+
+    fn remove_breakpoint(&mut self, addr: Address) {
+        if let Some(index) = self.debugger.breakpoints.iter().position(|a| *a == addr as u32) {
+            self.debugger.breakpoints.remove(index);
+        }
+    }
+
+    fn print_current_step(&mut self, _system: &System) -> Result<(), Error> {
+        let mut output = String::with_capacity(256);
+        self.dump_state(&mut output)?;
+        println!("{}", output);
+        Ok(())
+    }
+
+    fn print_disassembly(&mut self, system: &System, addr: Address, count: usize) {
+        let mut bus = system.bus.borrow_mut();
+        let mut adapter: BusAdapter<u32, u64, &mut dyn Addressable, Error> = BusAdapter::new(&mut *bus, |addr| addr as u64);
+
+        W65C816Decoder::dump_disassembly(&mut adapter, addr as u32, count as u32);
+    }
+
+    fn run_command(&mut self, _system: &System, args: &[&str]) -> Result<bool, Error> {
+        match args[0] {
+            "bl" | "breakpoints" => {
+                let mut output = String::new();
+                self.dump_breakpoints(&mut output)?;
+                print!("{}", output);
+            },
+            _ => {
+                return Ok(true);
+            },
+        }
+        Ok(false)
+    }
+
+    fn get_register(&mut self, name: &str) -> Option<u64> {
+        let state = &self.state;
+        let value = match name {
+            "a" | "c" => state.a,
+            "x" => state.x,
+            "y" => state.y,
+            "s" | "sp" => state.s,
+            "d" => state.d,
+            "pc" => state.pc,
+            "pbr" | "k" => state.pbr as u16,
+            "dbr" | "b" => state.dbr as u16,
+            "p" => state.p as u16,
+            "e" => state.emulation as u16,
+            _ => return None,
+        };
+        Some(value as u64)
+    }
+
+    fn register_names(&self) -> &'static [&'static str] {
+        &["a", "x", "y", "s", "d", "pbr", "dbr", "p", "e"]
+    }
+
+    fn disassemble(&mut self, system: &System, addr: Address) -> Option<(String, Vec<u8>)> {
+        let mut bus = system.bus.borrow_mut();
+        let decoder = {
+            let mut adapter: BusAdapter<u32, u64, &mut dyn Addressable, Error> = BusAdapter::new(&mut *bus, |addr| addr as u64);
+            W65C816Decoder::decode_at(
+                &mut adapter,
+                Instant::START,
+                addr as u32,
+                self.state.wide_accumulator(),
+                self.state.wide_index(),
+            )
+            .ok()?
+        };
+
+        let mut bytes = vec![0; decoder.instruction.operand_size as usize + 1];
+        bus.read(Instant::START, decoder.start as Address, &mut bytes).ok()?;
+        Some((decoder.instruction.to_string(), bytes))
+    }
+}
@@ -0,0 +1,183 @@
+use core::fmt::{self, Write};
+use femtos::Frequency;
+use emulator_hal::Instant as EmuInstant;
+
+use crate::debugger::W65C816Debugger;
+use crate::execute::W65C816Cycle;
+
+
+/// A 24-bit address, made of the 8-bit bank and the 16-bit address within the bank
+pub type W65C816Address = u32;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    Init,
+    Running,
+    /// Stopped by WAI until an interrupt occurs
+    Waiting,
+    /// Stopped by STP until the cpu is reset
+    Stopped,
+}
+
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[rustfmt::skip]
+pub enum Flags {
+    Carry       = 0x01,
+    Zero        = 0x02,
+    IrqDisable  = 0x04,
+    Decimal     = 0x08,
+    /// The width of the index registers (8-bit when set), or the break flag in emulation mode
+    IndexWidth  = 0x10,
+    /// The width of the accumulator and memory operations (8-bit when set)
+    MemoryWidth = 0x20,
+    Overflow    = 0x40,
+    Negative    = 0x80,
+}
+
+#[rustfmt::skip]
+pub mod vectors {
+    pub const NATIVE_COP: u16       = 0xFFE4;
+    pub const NATIVE_BRK: u16       = 0xFFE6;
+    pub const NATIVE_NMI: u16       = 0xFFEA;
+    pub const NATIVE_IRQ: u16       = 0xFFEE;
+    pub const EMULATION_COP: u16    = 0xFFF4;
+    pub const EMULATION_NMI: u16    = 0xFFFA;
+    pub const RESET: u16            = 0xFFFC;
+    pub const EMULATION_IRQ: u16    = 0xFFFE;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct W65C816State {
+    pub status: Status,
+
+    /// The full 16-bit accumulator (C), where the upper byte is B when the accumulator is 8 bits wide
+    pub a: u16,
+    pub x: u16,
+    pub y: u16,
+    pub s: u16,
+    pub d: u16,
+    pub pc: u16,
+    /// The program bank register (K)
+    pub pbr: u8,
+    /// The data bank register (B)
+    pub dbr: u8,
+    pub p: u8,
+    /// True when in 6502 emulation mode, which is the mode after a reset
+    pub emulation: bool,
+}
+
+impl Default for W65C816State {
+    fn default() -> Self {
+        Self {
+            status: Status::Init,
+
+            a: 0,
+            x: 0,
+            y: 0,
+            s: 0x01FF,
+            d: 0,
+            pc: 0,
+            pbr: 0,
+            dbr: 0,
+            p: Flags::MemoryWidth as u8 | Flags::IndexWidth as u8 | Flags::IrqDisable as u8,
+            emulation: true,
+        }
+    }
+}
+
+impl W65C816State {
+    /// Returns true if the accumulator and memory operations are 16 bits wide
+    pub fn wide_accumulator(&self) -> bool {
+        !self.emulation && self.p & Flags::MemoryWidth as u8 == 0
+    }
+
+    /// Returns true if the index registers are 16 bits wide
+    pub fn wide_index(&self) -> bool {
+        !self.emulation && self.p & Flags::IndexWidth as u8 == 0
+    }
+
+    /// Returns the full 24-bit address of the program counter
+    pub fn program_address(&self) -> W65C816Address {
+        ((self.pbr as W65C816Address) << 16) | self.pc as W65C816Address
+    }
+}
+
+/// The interrupt inputs, which are checked between instructions
+#[derive(Clone, Debug, Default)]
+pub struct W65C816Signals {
+    pub reset: bool,
+    /// The level of the IRQ input, which causes an interrupt whenever interrupts are enabled
+    pub irq: bool,
+    /// The NMI input, which causes one interrupt each time it's set
+    pub nmi: bool,
+}
+
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum W65C816Error {
+    #[error("cpu stopped")]
+    Stopped,
+    #[error("breakpoint reached")]
+    Breakpoint,
+    #[error("bus error: {0}")]
+    BusError(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+#[derive(Clone)]
+pub struct W65C816<Instant> {
+    pub frequency: Frequency,
+    pub state: W65C816State,
+    pub debugger: W65C816Debugger,
+    pub previous_cycle: W65C816Cycle<Instant>,
+    pub signals: W65C816Signals,
+}
+
+impl<Instant> W65C816<Instant>
+where
+    Instant: EmuInstant,
+{
+    pub fn new(frequency: Frequency) -> Self {
+        Self {
+            frequency,
+            state: W65C816State::default(),
+            debugger: W65C816Debugger::default(),
+            previous_cycle: W65C816Cycle::at_time(Instant::START),
+            signals: W65C816Signals::default(),
+        }
+    }
+
+    pub fn clear_state(&mut self) {
+        self.state = W65C816State::default();
+        self.debugger = W65C816Debugger::default();
+    }
+
+    pub fn dump_state<W: Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        writeln!(
+            writer,
+            "Status: {:?}  Mode: {}",
+            self.state.status,
+            if self.state.emulation { "emulation" } else { "native" }
+        )?;
+        writeln!(writer, "PC: {:02x}:{:04x}", self.state.pbr, self.state.pc)?;
+        writeln!(writer, "A: {:#06x}    X: {:#06x}    Y: {:#06x}", self.state.a, self.state.x, self.state.y)?;
+        writeln!(writer, "S: {:#06x}    D: {:#06x}    DBR: {:#04x}", self.state.s, self.state.d, self.state.dbr)?;
+
+        let flags: String = "NVMXDIZC"
+            .chars()
+            .enumerate()
+            .map(|(i, flag)| if self.state.p & (0x80 >> i) != 0 { flag } else { '-' })
+            .collect();
+        writeln!(writer, "P: {:#04x} ({})", self.state.p, flags)?;
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "Current Instruction: {:06x}: {}",
+            self.previous_cycle.decoder.start, self.previous_cycle.decoder.instruction
+        )?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use femtos::{Instant, Duration, Frequency};
+
+use emulator_hal::{BusAccess, Step};
+use emulator_hal_memory::MemoryBlock;
+
+use moa_w65c816::{W65C816, Flags, Operation, AddressingMode, decode_opcode};
+
+const INIT_PC: u16 = 0x8000;
+
+fn init_execute_test(program: &[u8]) -> (W65C816<Instant>, MemoryBlock<Instant>) {
+    let mut memory = MemoryBlock::from(vec![0; 0x10_0000]);
+    memory.write_u8(Instant::START, 0xFFFC, INIT_PC as u8).unwrap();
+    memory.write_u8(Instant::START, 0xFFFD, (INIT_PC >> 8) as u8).unwrap();
+    for (i, byte) in program.iter().enumerate() {
+        memory.write_u8(Instant::START, INIT_PC as u32 + i as u32, *byte).unwrap();
+    }
+
+    // The first step loads the reset vector
+    let mut cpu = W65C816::new(Frequency::from_mhz(4));
+    cpu.reset(Instant::START, &mut memory).unwrap();
+    cpu.step(Instant::START, &mut memory).unwrap();
+    assert_eq!(cpu.state.pc, INIT_PC);
+    assert!(cpu.state.emulation);
+
+    (cpu, memory)
+}
+
+fn run_steps(cpu: &mut W65C816<Instant>, memory: &mut MemoryBlock<Instant>, count: usize) -> Instant {
+    let mut clock = Instant::START;
+    for _ in 0..count {
+        clock = cpu.step(clock, memory).unwrap();
+    }
+    clock
+}
+
+#[test]
+fn every_opcode_decodes() {
+    for opcode in 0..=0xFF {
+        let (operation, _) = decode_opcode(opcode);
+        assert_eq!(operation == Operation::NOP, opcode == 0xEA, "opcode {:#04x}", opcode);
+    }
+    assert_eq!(decode_opcode(0x89), (Operation::BIT, AddressingMode::ImmediateM));
+    assert_eq!(decode_opcode(0xB7), (Operation::LDA, AddressingMode::DirectIndirectLongIndexed));
+}
+
+#[test]
+fn switch_to_native_mode_and_load_16_bits() {
+    // clc; xce; rep #$30; lda #$1234; ldx #$8000
+    let (mut cpu, mut memory) = init_execute_test(&[0x18, 0xFB, 0xC2, 0x30, 0xA9, 0x34, 0x12, 0xA2, 0x00, 0x80]);
+    run_steps(&mut cpu, &mut memory, 5);
+
+    assert!(!cpu.state.emulation);
+    assert_eq!(cpu.state.a, 0x1234);
+    assert_eq!(cpu.state.x, 0x8000);
+    assert_ne!(cpu.state.p & Flags::Negative as u8, 0);
+    assert_eq!(cpu.state.pc, INIT_PC + 10);
+}
+
+#[test]
+fn decimal_add_with_carry() {
+    // sed; sec; lda #$58; adc #$46
+    let (mut cpu, mut memory) = init_execute_test(&[0xF8, 0x38, 0xA9, 0x58, 0x69, 0x46]);
+    run_steps(&mut cpu, &mut memory, 4);
+
+    assert_eq!(cpu.state.a & 0xFF, 0x05);
+    assert_ne!(cpu.state.p & Flags::Carry as u8, 0);
+}
+
+#[test]
+fn long_call_and_return() {
+    // jsl $018000, which contains rtl
+    let (mut cpu, mut memory) = init_execute_test(&[0x22, 0x00, 0x80, 0x01]);
+    memory.write_u8(Instant::START, 0x01_8000, 0x6B).unwrap();
+
+    run_steps(&mut cpu, &mut memory, 1);
+    assert_eq!(cpu.state.pbr, 0x01);
+    assert_eq!(cpu.state.pc, 0x8000);
+
+    run_steps(&mut cpu, &mut memory, 1);
+    assert_eq!(cpu.state.pbr, 0x00);
+    assert_eq!(cpu.state.pc, INIT_PC + 4);
+    assert_eq!(cpu.state.s, 0x01FF);
+}
+
+#[test]
+fn block_move_repeats_until_done() {
+    // clc; xce; rep #$30; lda #$0002; ldx #$1000; ldy #$2000; mvn $02,$01
+    let (mut cpu, mut memory) =
+        init_execute_test(&[0x18, 0xFB, 0xC2, 0x30, 0xA9, 0x02, 0x00, 0xA2, 0x00, 0x10, 0xA0, 0x00, 0x20, 0x54, 0x02, 0x01]);
+    for (i, byte) in [0xAA, 0xBB, 0xCC].iter().enumerate() {
+        memory.write_u8(Instant::START, 0x01_1000 + i as u32, *byte).unwrap();
+    }
+
+    run_steps(&mut cpu, &mut memory, 6 + 3);
+
+    let mut moved = [0; 3];
+    memory.read(Instant::START, 0x02_2000, &mut moved).unwrap();
+    assert_eq!(moved, [0xAA, 0xBB, 0xCC]);
+    assert_eq!(cpu.state.a, 0xFFFF);
+    assert_eq!(cpu.state.dbr, 0x02);
+    assert_eq!(cpu.state.pc, INIT_PC + 16);
+}
+
+#[test]
+fn wide_accumulator_takes_an_extra_clock() {
+    // lda $10; clc; xce; rep #$20; lda $10
+    let (mut cpu, mut memory) = init_execute_test(&[0xA5, 0x10, 0x18, 0xFB, 0xC2, 0x20, 0xA5, 0x10]);
+    let clock_period = Duration::from_nanos(250);
+
+    let clock = run_steps(&mut cpu, &mut memory, 1);
+    assert_eq!(clock.as_duration(), clock_period * 3);
+    run_steps(&mut cpu, &mut memory, 3);
+    let clock = run_steps(&mut cpu, &mut memory, 1);
+    assert_eq!(clock.as_duration(), clock_period * 4);
+}
@@ -0,0 +1,839 @@
+use std::collections::HashMap;
+
+use moa_parsing::{self as parser, AssemblyLine, AssemblyOperand, AssemblyParser, BinaryOperator, Expression, ParserError};
+
+use crate::instructions::{Register, RegisterPair, IndexRegister, IndexRegisterHalf, SpecialRegister};
+
+#[derive(Clone, Debug)]
+pub struct Error(String);
+
+impl Error {
+    pub fn new(msg: String) -> Self {
+        Self(msg)
+    }
+}
+
+impl From<ParserError> for Error {
+    fn from(err: ParserError) -> Self {
+        Self(err.0)
+    }
+}
+
+
+const PREFIX_IX: u8 = 0xDD;
+const PREFIX_IY: u8 = 0xFD;
+const PREFIX_BITS: u8 = 0xCB;
+const PREFIX_EXTENDED: u8 = 0xED;
+
+#[rustfmt::skip]
+const IMPLIED_INSTRUCTIONS: &[(&str, &[u8])] = &[
+    ("nop",     &[0x00]),
+    ("halt",    &[0x76]),
+    ("di",      &[0xF3]),
+    ("ei",      &[0xFB]),
+    ("exx",     &[0xD9]),
+    ("daa",     &[0x27]),
+    ("cpl",     &[0x2F]),
+    ("ccf",     &[0x3F]),
+    ("scf",     &[0x37]),
+    ("rlca",    &[0x07]),
+    ("rrca",    &[0x0F]),
+    ("rla",     &[0x17]),
+    ("rra",     &[0x1F]),
+    ("neg",     &[0xED, 0x44]),
+    ("retn",    &[0xED, 0x45]),
+    ("reti",    &[0xED, 0x4D]),
+    ("rrd",     &[0xED, 0x67]),
+    ("rld",     &[0xED, 0x6F]),
+    ("ldi",     &[0xED, 0xA0]),
+    ("cpi",     &[0xED, 0xA1]),
+    ("ini",     &[0xED, 0xA2]),
+    ("outi",    &[0xED, 0xA3]),
+    ("ldd",     &[0xED, 0xA8]),
+    ("cpd",     &[0xED, 0xA9]),
+    ("ind",     &[0xED, 0xAA]),
+    ("outd",    &[0xED, 0xAB]),
+    ("ldir",    &[0xED, 0xB0]),
+    ("cpir",    &[0xED, 0xB1]),
+    ("inir",    &[0xED, 0xB2]),
+    ("otir",    &[0xED, 0xB3]),
+    ("lddr",    &[0xED, 0xB8]),
+    ("cpdr",    &[0xED, 0xB9]),
+    ("indr",    &[0xED, 0xBA]),
+    ("otdr",    &[0xED, 0xBB]),
+];
+
+/// The 8-bit arithmetic instructions, with the base opcode of the register form and the opcode of the immediate form
+#[rustfmt::skip]
+const ALU_INSTRUCTIONS: &[(&str, u8, u8)] = &[
+    ("add",     0x80, 0xC6),
+    ("adc",     0x88, 0xCE),
+    ("sub",     0x90, 0xD6),
+    ("sbc",     0x98, 0xDE),
+    ("and",     0xA0, 0xE6),
+    ("xor",     0xA8, 0xEE),
+    ("or",      0xB0, 0xF6),
+    ("cp",      0xB8, 0xFE),
+];
+
+/// The CB-prefixed shift and rotate instructions, with their base opcode
+#[rustfmt::skip]
+const SHIFT_INSTRUCTIONS: &[(&str, u8)] = &[
+    ("rlc",     0x00),
+    ("rrc",     0x08),
+    ("rl",      0x10),
+    ("rr",      0x18),
+    ("sla",     0x20),
+    ("sra",     0x28),
+    ("sll",     0x30),
+    ("srl",     0x38),
+];
+
+#[rustfmt::skip]
+const CONDITIONS: &[(&str, u8)] = &[
+    ("nz",      0),
+    ("z",       1),
+    ("nc",      2),
+    ("c",       3),
+    ("po",      4),
+    ("pe",      5),
+    ("p",       6),
+    ("m",       7),
+];
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Operand {
+    Register(Register),
+    IndexRegisterHalf(IndexRegisterHalf),
+    RegisterPair(RegisterPair),
+    ShadowAF,
+    SpecialRegister(SpecialRegister),
+    IndirectRegisterPair(RegisterPair),
+    IndirectPortC,
+    IndirectIndexed(IndexRegister, i8),
+    /// A value, where any expression other than a number is filled in after all lines have been assembled
+    Immediate(Expression),
+    IndirectImmediate(Expression),
+}
+
+#[derive(Copy, Clone, Debug)]
+enum FixupType {
+    Byte,
+    Word,
+    /// A displacement relative to the address following the byte, as used by `jr` and `djnz`
+    Relative,
+}
+
+struct Fixup {
+    ftype: FixupType,
+    expr: Expression,
+    lineno: usize,
+    index: usize,
+}
+
+/// The prefix, register number, and index displacement used to encode an 8-bit operand
+type Register8 = (Option<u8>, u8, Option<i8>);
+
+/// Assembles Z80 code into binary, which can be loaded into memory at the address given by [`Z80Assembler::origin`]
+#[derive(Default)]
+pub struct Z80Assembler {
+    pub labels: HashMap<String, usize>,
+    pub output: Vec<u8>,
+    origin: usize,
+    fixups: Vec<Fixup>,
+}
+
+impl Z80Assembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The address of the first byte of output, as set by an `.org` directive before any code
+    pub fn origin(&self) -> usize {
+        self.origin
+    }
+
+    pub fn assemble(&mut self, text: &str) -> Result<Vec<u8>, Error> {
+        self.assemble_in_place(text)?;
+        Ok(self.output.clone())
+    }
+
+    pub fn assemble_in_place(&mut self, text: &str) -> Result<(), Error> {
+        let lines = self.parse(text)?;
+
+        for (lineno, line) in lines {
+            self.convert(lineno, &line)?;
+        }
+
+        self.apply_fixups()?;
+
+        Ok(())
+    }
+
+    fn parse(&mut self, text: &str) -> Result<Vec<(usize, AssemblyLine)>, Error> {
+        let mut parser = AssemblyParser::new(text);
+        Ok(parser.parse()?)
+    }
+
+    fn current_address(&self) -> usize {
+        self.origin + self.output.len()
+    }
+
+    fn apply_fixups(&mut self) -> Result<(), Error> {
+        for fixup in self.fixups.iter() {
+            let value = fixup
+                .expr
+                .evaluate(fixup.lineno, &mut |name| self.labels.get(name).copied())?;
+
+            match fixup.ftype {
+                FixupType::Byte => {
+                    self.output[fixup.index] = check_byte(fixup.lineno, value)?;
+                },
+                FixupType::Word => {
+                    let value = check_word(fixup.lineno, value)?;
+                    self.output[fixup.index] = value as u8;
+                    self.output[fixup.index + 1] = (value >> 8) as u8;
+                },
+                FixupType::Relative => {
+                    let next = self.origin + fixup.index + 1;
+                    self.output[fixup.index] = check_relative(fixup.lineno, value, next)?;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn convert(&mut self, lineno: usize, line: &AssemblyLine) -> Result<(), Error> {
+        match line {
+            AssemblyLine::Directive(name, list) => {
+                self.convert_directive(lineno, name, list)?;
+            },
+            AssemblyLine::Label(label) => {
+                self.define_label(lineno, label, self.current_address())?;
+            },
+            AssemblyLine::Instruction(name, list) => {
+                self.convert_instruction(lineno, &name.to_lowercase(), list)?;
+            },
+        }
+        Ok(())
+    }
+
+    fn define_label(&mut self, lineno: usize, label: &str, value: usize) -> Result<(), Error> {
+        if self.labels.insert(label.to_string(), value).is_some() {
+            return Err(Error::new(format!("error at line {}: label {:?} is already defined", lineno, label)));
+        }
+        Ok(())
+    }
+
+    fn convert_directive(&mut self, lineno: usize, name: &str, args: &[AssemblyOperand]) -> Result<(), Error> {
+        match name.to_lowercase().as_str() {
+            "org" => {
+                parser::expect_args(lineno, args, 1)?;
+                let address = self.evaluate_now(lineno, &args[0])?;
+                if self.output.is_empty() {
+                    self.origin = address;
+                } else if address >= self.current_address() {
+                    self.output.resize(address - self.origin, 0);
+                } else {
+                    return Err(Error::new(format!(
+                        "error at line {}: .org {:#06x} is before the current address {:#06x}",
+                        lineno,
+                        address,
+                        self.current_address()
+                    )));
+                }
+            },
+            "equ" => {
+                let [AssemblyOperand::Label(label), value] = args else {
+                    return Err(Error::new(format!("error at line {}: expected a name and a value for .equ", lineno)));
+                };
+                let value = self.evaluate_now(lineno, value)?;
+                self.define_label(lineno, label, value)?;
+            },
+            "byte" | "db" => {
+                for arg in args {
+                    match arg {
+                        AssemblyOperand::String(string) => self.output.extend(string.bytes()),
+                        _ => self.emit_byte(lineno, &expect_expression(lineno, arg)?)?,
+                    }
+                }
+            },
+            "word" | "dw" => {
+                for arg in args {
+                    self.emit_word(lineno, &expect_expression(lineno, arg)?)?;
+                }
+            },
+            _ => return Err(Error::new(format!("error at line {}: unknown directive .{}", lineno, name))),
+        }
+        Ok(())
+    }
+
+    /// Evaluate an operand that can only use the labels that have already been defined
+    fn evaluate_now(&self, lineno: usize, operand: &AssemblyOperand) -> Result<usize, Error> {
+        let expr = expect_expression(lineno, operand)?;
+        Ok(expr.evaluate(lineno, &mut |name| self.labels.get(name).copied())?)
+    }
+
+    fn convert_instruction(&mut self, lineno: usize, mneumonic: &str, args: &[AssemblyOperand]) -> Result<(), Error> {
+        if let Some((_, bytes)) = IMPLIED_INSTRUCTIONS.iter().find(|(name, _)| *name == mneumonic) {
+            parser::expect_args(lineno, args, 0)?;
+            self.output.extend_from_slice(bytes);
+            return Ok(());
+        }
+
+        if let Some((_, base, immediate)) = ALU_INSTRUCTIONS.iter().find(|(name, _, _)| *name == mneumonic) {
+            return self.convert_alu_instruction(lineno, mneumonic, *base, *immediate, args);
+        }
+
+        if let Some((_, base)) = SHIFT_INSTRUCTIONS.iter().find(|(name, _)| *name == mneumonic) {
+            parser::expect_args(lineno, args, 1)?;
+            let target = self.expect_register8(lineno, &self.convert_operand(lineno, &args[0])?)?;
+            self.emit_bits_instruction(target, *base);
+            return Ok(());
+        }
+
+        let operands = args
+            .iter()
+            .map(|arg| self.convert_operand(lineno, arg))
+            .collect::<Result<Vec<Operand>, Error>>()?;
+
+        match (mneumonic, operands.as_slice()) {
+            ("ld", [dest, src]) => self.convert_load(lineno, dest, src)?,
+
+            ("push", [Operand::RegisterPair(pair)]) | ("pop", [Operand::RegisterPair(pair)]) => {
+                let (prefix, code) = pair_code(lineno, *pair, true)?;
+                let base = if mneumonic == "push" { 0xC5 } else { 0xC1 };
+                self.emit_prefixed(prefix, &[base | (code << 4)]);
+            },
+
+            ("ex", [Operand::RegisterPair(RegisterPair::DE), Operand::RegisterPair(RegisterPair::HL)]) => self.output.push(0xEB),
+            ("ex", [Operand::RegisterPair(RegisterPair::AF), Operand::ShadowAF]) => self.output.push(0x08),
+            ("ex", [Operand::IndirectRegisterPair(RegisterPair::SP), Operand::RegisterPair(pair)]) => {
+                let prefix = index_prefix(lineno, *pair)?;
+                self.emit_prefixed(prefix, &[0xE3]);
+            },
+
+            ("inc", [Operand::RegisterPair(pair)]) | ("dec", [Operand::RegisterPair(pair)]) => {
+                let (prefix, code) = pair_code(lineno, *pair, false)?;
+                let base = if mneumonic == "inc" { 0x03 } else { 0x0B };
+                self.emit_prefixed(prefix, &[base | (code << 4)]);
+            },
+            ("inc", [target]) | ("dec", [target]) => {
+                let (prefix, code, offset) = self.expect_register8(lineno, target)?;
+                let base = if mneumonic == "inc" { 0x04 } else { 0x05 };
+                self.emit_prefixed(prefix, &[base | (code << 3)]);
+                self.emit_offset(offset);
+            },
+
+            ("bit", [bit, target]) | ("res", [bit, target]) | ("set", [bit, target]) => {
+                let bit = expect_number(lineno, bit, 7)? as u8;
+                let target = self.expect_register8(lineno, target)?;
+                let base = match mneumonic {
+                    "bit" => 0x40,
+                    "res" => 0x80,
+                    _ => 0xC0,
+                };
+                self.emit_bits_instruction(target, base | (bit << 3));
+            },
+
+            ("jp", [Operand::IndirectRegisterPair(pair)]) => {
+                let prefix = index_prefix(lineno, *pair)?;
+                self.emit_prefixed(prefix, &[0xE9]);
+            },
+            ("jp", [Operand::Immediate(target)]) => {
+                self.output.push(0xC3);
+                self.emit_word(lineno, target)?;
+            },
+            ("jp", [cond, Operand::Immediate(target)]) => {
+                let cond = expect_condition(lineno, cond, 7)?;
+                self.output.push(0xC2 | (cond << 3));
+                self.emit_word(lineno, target)?;
+            },
+            ("call", [Operand::Immediate(target)]) => {
+                self.output.push(0xCD);
+                self.emit_word(lineno, target)?;
+            },
+            ("call", [cond, Operand::Immediate(target)]) => {
+                let cond = expect_condition(lineno, cond, 7)?;
+                self.output.push(0xC4 | (cond << 3));
+                self.emit_word(lineno, target)?;
+            },
+            ("jr", [Operand::Immediate(target)]) => {
+                self.output.push(0x18);
+                self.emit_relative(lineno, target)?;
+            },
+            ("jr", [cond, Operand::Immediate(target)]) => {
+                let cond = expect_condition(lineno, cond, 3)?;
+                self.output.push(0x20 | (cond << 3));
+                self.emit_relative(lineno, target)?;
+            },
+            ("djnz", [Operand::Immediate(target)]) => {
+                self.output.push(0x10);
+                self.emit_relative(lineno, target)?;
+            },
+            ("ret", []) => self.output.push(0xC9),
+            ("ret", [cond]) => {
+                let cond = expect_condition(lineno, cond, 7)?;
+                self.output.push(0xC0 | (cond << 3));
+            },
+            ("rst", [vector]) => {
+                let vector = expect_number(lineno, vector, 0x38)? as u8;
+                if vector & 0x07 != 0 {
+                    return Err(Error::new(format!("error at line {}: invalid rst vector {:#04x}", lineno, vector)));
+                }
+                self.output.push(0xC7 | vector);
+            },
+            ("im", [mode]) => {
+                let opcode = match expect_number(lineno, mode, 2)? {
+                    0 => 0x46,
+                    1 => 0x56,
+                    _ => 0x5E,
+                };
+                self.output.extend_from_slice(&[PREFIX_EXTENDED, opcode]);
+            },
+
+            ("in", [Operand::Register(Register::A), Operand::IndirectImmediate(port)]) => {
+                self.output.push(0xDB);
+                self.emit_byte(lineno, port)?;
+            },
+            ("in", [Operand::IndirectPortC]) | ("in", [Operand::Register(Register::F), Operand::IndirectPortC]) => {
+                self.output.extend_from_slice(&[PREFIX_EXTENDED, 0x70]);
+            },
+            ("in", [Operand::Register(reg), Operand::IndirectPortC]) => {
+                let code = register_code(lineno, *reg)?;
+                self.output.extend_from_slice(&[PREFIX_EXTENDED, 0x40 | (code << 3)]);
+            },
+            ("out", [Operand::IndirectImmediate(port), Operand::Register(Register::A)]) => {
+                self.output.push(0xD3);
+                self.emit_byte(lineno, port)?;
+            },
+            ("out", [Operand::IndirectPortC, Operand::Immediate(Expression::Number(0))]) => {
+                self.output.extend_from_slice(&[PREFIX_EXTENDED, 0x71]);
+            },
+            ("out", [Operand::IndirectPortC, Operand::Register(reg)]) => {
+                let code = register_code(lineno, *reg)?;
+                self.output.extend_from_slice(&[PREFIX_EXTENDED, 0x41 | (code << 3)]);
+            },
+
+            _ => {
+                return Err(Error::new(format!(
+                    "error at line {}: unrecognized instruction or invalid operands: {} {:?}",
+                    lineno, mneumonic, args
+                )));
+            },
+        }
+        Ok(())
+    }
+
+    fn convert_alu_instruction(
+        &mut self,
+        lineno: usize,
+        mneumonic: &str,
+        base: u8,
+        immediate: u8,
+        args: &[AssemblyOperand],
+    ) -> Result<(), Error> {
+        let operands = args
+            .iter()
+            .map(|arg| self.convert_operand(lineno, arg))
+            .collect::<Result<Vec<Operand>, Error>>()?;
+
+        let src = match operands.as_slice() {
+            [Operand::RegisterPair(dest), Operand::RegisterPair(src)] => {
+                return self.convert_alu16_instruction(lineno, mneumonic, *dest, *src);
+            },
+            [Operand::Register(Register::A), src] => src,
+            // The accumulator can be left out for any of the 8-bit operations
+            [src] => src,
+            _ => return Err(Error::new(format!("error at line {}: invalid operands for {}", lineno, mneumonic))),
+        };
+
+        match src {
+            Operand::Immediate(value) => {
+                self.output.push(immediate);
+                self.emit_byte(lineno, value)?;
+            },
+            _ => {
+                let (prefix, code, offset) = self.expect_register8(lineno, src)?;
+                self.emit_prefixed(prefix, &[base | code]);
+                self.emit_offset(offset);
+            },
+        }
+        Ok(())
+    }
+
+    fn convert_alu16_instruction(
+        &mut self,
+        lineno: usize,
+        mneumonic: &str,
+        dest: RegisterPair,
+        src: RegisterPair,
+    ) -> Result<(), Error> {
+        let (prefix, dest_code) = pair_code(lineno, dest, false)?;
+        let (src_prefix, src_code) = pair_code(lineno, src, false)?;
+        if dest_code != 2 || (src_code == 2 && src_prefix != prefix) {
+            return Err(Error::new(format!("error at line {}: invalid register pairs for {}", lineno, mneumonic)));
+        }
+
+        match mneumonic {
+            "add" => self.emit_prefixed(prefix, &[0x09 | (src_code << 4)]),
+            "adc" if prefix.is_none() => self.output.extend_from_slice(&[PREFIX_EXTENDED, 0x4A | (src_code << 4)]),
+            "sbc" if prefix.is_none() => self.output.extend_from_slice(&[PREFIX_EXTENDED, 0x42 | (src_code << 4)]),
+            _ => return Err(Error::new(format!("error at line {}: invalid register pairs for {}", lineno, mneumonic))),
+        }
+        Ok(())
+    }
+
+    fn convert_load(&mut self, lineno: usize, dest: &Operand, src: &Operand) -> Result<(), Error> {
+        match (dest, src) {
+            (Operand::SpecialRegister(reg), Operand::Register(Register::A)) => {
+                let opcode = if *reg == SpecialRegister::I { 0x47 } else { 0x4F };
+                self.output.extend_from_slice(&[PREFIX_EXTENDED, opcode]);
+            },
+            (Operand::Register(Register::A), Operand::SpecialRegister(reg)) => {
+                let opcode = if *reg == SpecialRegister::I { 0x57 } else { 0x5F };
+                self.output.extend_from_slice(&[PREFIX_EXTENDED, opcode]);
+            },
+
+            (Operand::Register(Register::A), Operand::IndirectRegisterPair(RegisterPair::BC)) => self.output.push(0x0A),
+            (Operand::Register(Register::A), Operand::IndirectRegisterPair(RegisterPair::DE)) => self.output.push(0x1A),
+            (Operand::IndirectRegisterPair(RegisterPair::BC), Operand::Register(Register::A)) => self.output.push(0x02),
+            (Operand::IndirectRegisterPair(RegisterPair::DE), Operand::Register(Register::A)) => self.output.push(0x12),
+            (Operand::Register(Register::A), Operand::IndirectImmediate(addr)) => {
+                self.output.push(0x3A);
+                self.emit_word(lineno, addr)?;
+            },
+            (Operand::IndirectImmediate(addr), Operand::Register(Register::A)) => {
+                self.output.push(0x32);
+                self.emit_word(lineno, addr)?;
+            },
+
+            (Operand::RegisterPair(RegisterPair::SP), Operand::RegisterPair(pair)) => {
+                let prefix = index_prefix(lineno, *pair)?;
+                self.emit_prefixed(prefix, &[0xF9]);
+            },
+            (Operand::RegisterPair(pair), Operand::Immediate(value)) => {
+                let (prefix, code) = pair_code(lineno, *pair, false)?;
+                self.emit_prefixed(prefix, &[0x01 | (code << 4)]);
+                self.emit_word(lineno, value)?;
+            },
+            (Operand::RegisterPair(pair), Operand::IndirectImmediate(addr)) => {
+                let (prefix, code) = pair_code(lineno, *pair, false)?;
+                if code == 2 {
+                    self.emit_prefixed(prefix, &[0x2A]);
+                } else {
+                    self.output.extend_from_slice(&[PREFIX_EXTENDED, 0x4B | (code << 4)]);
+                }
+                self.emit_word(lineno, addr)?;
+            },
+            (Operand::IndirectImmediate(addr), Operand::RegisterPair(pair)) => {
+                let (prefix, code) = pair_code(lineno, *pair, false)?;
+                if code == 2 {
+                    self.emit_prefixed(prefix, &[0x22]);
+                } else {
+                    self.output.extend_from_slice(&[PREFIX_EXTENDED, 0x43 | (code << 4)]);
+                }
+                self.emit_word(lineno, addr)?;
+            },
+
+            (dest, Operand::Immediate(value)) => {
+                let (prefix, code, offset) = self.expect_register8(lineno, dest)?;
+                self.emit_prefixed(prefix, &[0x06 | (code << 3)]);
+                self.emit_offset(offset);
+                self.emit_byte(lineno, value)?;
+            },
+            (dest, src) => {
+                let (dest_prefix, dest_code, dest_offset) = self.expect_register8(lineno, dest)?;
+                let (src_prefix, src_code, src_offset) = self.expect_register8(lineno, src)?;
+
+                // A prefix applies to both operands, so an index register can only be combined with registers other
+                // than H and L, except when the other operand is indexed, which leaves H and L unchanged
+                let conflicts = (dest_code == 6 && src_code == 6)
+                    || match (dest_prefix, src_prefix) {
+                        (Some(dest), Some(src)) => dest != src || dest_offset.is_some() || src_offset.is_some(),
+                        (Some(_), None) => dest_offset.is_none() && (4..=6).contains(&src_code),
+                        (None, Some(_)) => src_offset.is_none() && (4..=6).contains(&dest_code),
+                        (None, None) => false,
+                    };
+                if conflicts {
+                    return Err(Error::new(format!("error at line {}: invalid combination of operands for ld", lineno)));
+                }
+
+                self.emit_prefixed(dest_prefix.or(src_prefix), &[0x40 | (dest_code << 3) | src_code]);
+                self.emit_offset(dest_offset.or(src_offset));
+            },
+        }
+        Ok(())
+    }
+
+    fn convert_operand(&self, lineno: usize, operand: &AssemblyOperand) -> Result<Operand, Error> {
+        match operand {
+            AssemblyOperand::Register(name) | AssemblyOperand::Label(name) => {
+                Ok(register_by_name(name).unwrap_or_else(|| Operand::Immediate(Expression::Label(name.clone()))))
+            },
+            AssemblyOperand::Immediate(_) | AssemblyOperand::Expression(_) => {
+                Ok(Operand::Immediate(expect_expression(lineno, operand)?))
+            },
+            AssemblyOperand::Indirect(list) => match list.as_slice() {
+                [AssemblyOperand::Register(name) | AssemblyOperand::Label(name)] => match register_by_name(name) {
+                    Some(Operand::RegisterPair(pair)) if pair != RegisterPair::AF => Ok(Operand::IndirectRegisterPair(pair)),
+                    Some(Operand::Register(Register::C)) => Ok(Operand::IndirectPortC),
+                    Some(_) => Err(Error::new(format!("error at line {}: register {} can't be used indirectly", lineno, name))),
+                    None => Ok(Operand::IndirectImmediate(Expression::Label(name.clone()))),
+                },
+                [AssemblyOperand::Expression(expr)] => match split_index(expr) {
+                    Some((index, offset)) => {
+                        // The displacement is part of the opcode, so it can only use labels that are already defined
+                        let offset = offset.evaluate(lineno, &mut |name| self.labels.get(name).copied())? as isize;
+                        if !(-128..=127).contains(&offset) {
+                            return Err(Error::new(format!("error at line {}: displacement {} is out of range", lineno, offset)));
+                        }
+                        Ok(Operand::IndirectIndexed(index, offset as i8))
+                    },
+                    None => Ok(Operand::IndirectImmediate(expr.clone())),
+                },
+                [AssemblyOperand::Immediate(value)] => Ok(Operand::IndirectImmediate(Expression::Number(*value))),
+                _ => Err(Error::new(format!("error at line {}: invalid indirect operand {:?}", lineno, list))),
+            },
+            _ => Err(Error::new(format!("error at line {}: invalid operand {:?}", lineno, operand))),
+        }
+    }
+
+    fn expect_register8(&self, lineno: usize, operand: &Operand) -> Result<Register8, Error> {
+        match operand {
+            Operand::Register(reg) => Ok((None, register_code(lineno, *reg)?, None)),
+            Operand::IndexRegisterHalf(half) => Ok(match half {
+                IndexRegisterHalf::IXH => (Some(PREFIX_IX), 4, None),
+                IndexRegisterHalf::IXL => (Some(PREFIX_IX), 5, None),
+                IndexRegisterHalf::IYH => (Some(PREFIX_IY), 4, None),
+                IndexRegisterHalf::IYL => (Some(PREFIX_IY), 5, None),
+            }),
+            Operand::IndirectRegisterPair(RegisterPair::HL) => Ok((None, 6, None)),
+            Operand::IndirectRegisterPair(RegisterPair::IX) => Ok((Some(PREFIX_IX), 6, Some(0))),
+            Operand::IndirectRegisterPair(RegisterPair::IY) => Ok((Some(PREFIX_IY), 6, Some(0))),
+            Operand::IndirectIndexed(IndexRegister::IX, offset) => Ok((Some(PREFIX_IX), 6, Some(*offset))),
+            Operand::IndirectIndexed(IndexRegister::IY, offset) => Ok((Some(PREFIX_IY), 6, Some(*offset))),
+            _ => Err(Error::new(format!(
+                "error at line {}: expected an 8-bit register operand, found {:?}",
+                lineno, operand
+            ))),
+        }
+    }
+
+    fn emit_prefixed(&mut self, prefix: Option<u8>, bytes: &[u8]) {
+        if let Some(prefix) = prefix {
+            self.output.push(prefix);
+        }
+        self.output.extend_from_slice(bytes);
+    }
+
+    fn emit_offset(&mut self, offset: Option<i8>) {
+        if let Some(offset) = offset {
+            self.output.push(offset as u8);
+        }
+    }
+
+    /// Emit a CB-prefixed instruction, which puts the displacement of an indexed operand before the opcode
+    fn emit_bits_instruction(&mut self, (prefix, code, offset): Register8, opcode: u8) {
+        self.emit_prefixed(prefix, &[PREFIX_BITS]);
+        self.emit_offset(offset);
+        self.output.push(opcode | code);
+    }
+
+    fn emit_byte(&mut self, lineno: usize, value: &Expression) -> Result<(), Error> {
+        match value {
+            Expression::Number(number) => self.output.push(check_byte(lineno, *number)?),
+            expr => {
+                self.add_fixup(FixupType::Byte, lineno, expr);
+                self.output.push(0);
+            },
+        }
+        Ok(())
+    }
+
+    fn emit_word(&mut self, lineno: usize, value: &Expression) -> Result<(), Error> {
+        match value {
+            Expression::Number(number) => {
+                let number = check_word(lineno, *number)?;
+                self.output.extend_from_slice(&number.to_le_bytes());
+            },
+            expr => {
+                self.add_fixup(FixupType::Word, lineno, expr);
+                self.output.extend_from_slice(&[0, 0]);
+            },
+        }
+        Ok(())
+    }
+
+    fn emit_relative(&mut self, lineno: usize, target: &Expression) -> Result<(), Error> {
+        match target {
+            Expression::Number(number) => {
+                let displacement = check_relative(lineno, *number, self.current_address() + 1)?;
+                self.output.push(displacement);
+            },
+            expr => {
+                self.add_fixup(FixupType::Relative, lineno, expr);
+                self.output.push(0);
+            },
+        }
+        Ok(())
+    }
+
+    fn add_fixup(&mut self, ftype: FixupType, lineno: usize, expr: &Expression) {
+        self.fixups.push(Fixup {
+            ftype,
+            expr: expr.clone(),
+            lineno,
+            index: self.output.len(),
+        });
+    }
+}
+
+/// Returns the index register and displacement of an expression like `ix+5` or `iy-label`
+fn split_index(expr: &Expression) -> Option<(IndexRegister, Expression)> {
+    let Expression::Binary(op @ (BinaryOperator::Add | BinaryOperator::Subtract), left, right) = expr else {
+        return None;
+    };
+    let displacement = match op {
+        BinaryOperator::Add => (**right).clone(),
+        _ => Expression::Negate(right.clone()),
+    };
+
+    match &**left {
+        Expression::Label(name) => match register_by_name(name) {
+            Some(Operand::RegisterPair(RegisterPair::IX)) => Some((IndexRegister::IX, displacement)),
+            Some(Operand::RegisterPair(RegisterPair::IY)) => Some((IndexRegister::IY, displacement)),
+            _ => None,
+        },
+        // Operators are left associative, so the register is in the left-most operand of something like `ix+1+2`
+        left => {
+            let (index, offset) = split_index(left)?;
+            Some((index, Expression::Binary(BinaryOperator::Add, Box::new(offset), Box::new(displacement))))
+        },
+    }
+}
+
+fn expect_expression(lineno: usize, operand: &AssemblyOperand) -> Result<Expression, Error> {
+    match operand {
+        AssemblyOperand::Immediate(value) => Ok(Expression::Number(*value)),
+        AssemblyOperand::Label(name) => Ok(Expression::Label(name.clone())),
+        AssemblyOperand::Expression(expr) => Ok(expr.clone()),
+        _ => Err(Error::new(format!("error at line {}: expected a value, found {:?}", lineno, operand))),
+    }
+}
+
+fn register_by_name(name: &str) -> Option<Operand> {
+    Some(match name.to_lowercase().as_str() {
+        "a" => Operand::Register(Register::A),
+        "b" => Operand::Register(Register::B),
+        "c" => Operand::Register(Register::C),
+        "d" => Operand::Register(Register::D),
+        "e" => Operand::Register(Register::E),
+        "h" => Operand::Register(Register::H),
+        "l" => Operand::Register(Register::L),
+        "f" => Operand::Register(Register::F),
+        "ixh" => Operand::IndexRegisterHalf(IndexRegisterHalf::IXH),
+        "ixl" => Operand::IndexRegisterHalf(IndexRegisterHalf::IXL),
+        "iyh" => Operand::IndexRegisterHalf(IndexRegisterHalf::IYH),
+        "iyl" => Operand::IndexRegisterHalf(IndexRegisterHalf::IYL),
+        "bc" => Operand::RegisterPair(RegisterPair::BC),
+        "de" => Operand::RegisterPair(RegisterPair::DE),
+        "hl" => Operand::RegisterPair(RegisterPair::HL),
+        "sp" => Operand::RegisterPair(RegisterPair::SP),
+        "af" => Operand::RegisterPair(RegisterPair::AF),
+        "ix" => Operand::RegisterPair(RegisterPair::IX),
+        "iy" => Operand::RegisterPair(RegisterPair::IY),
+        "af'" => Operand::ShadowAF,
+        "i" => Operand::SpecialRegister(SpecialRegister::I),
+        "r" => Operand::SpecialRegister(SpecialRegister::R),
+        _ => return None,
+    })
+}
+
+fn register_code(lineno: usize, reg: Register) -> Result<u8, Error> {
+    match reg {
+        Register::B => Ok(0),
+        Register::C => Ok(1),
+        Register::D => Ok(2),
+        Register::E => Ok(3),
+        Register::H => Ok(4),
+        Register::L => Ok(5),
+        Register::A => Ok(7),
+        Register::F => Err(Error::new(format!("error at line {}: register f can't be used here", lineno))),
+    }
+}
+
+/// Returns the prefix and number of a 16-bit register pair, where the index registers take the place of HL
+fn pair_code(lineno: usize, pair: RegisterPair, allow_af: bool) -> Result<(Option<u8>, u8), Error> {
+    match pair {
+        RegisterPair::BC => Ok((None, 0)),
+        RegisterPair::DE => Ok((None, 1)),
+        RegisterPair::HL => Ok((None, 2)),
+        RegisterPair::IX => Ok((Some(PREFIX_IX), 2)),
+        RegisterPair::IY => Ok((Some(PREFIX_IY), 2)),
+        RegisterPair::SP if !allow_af => Ok((None, 3)),
+        RegisterPair::AF if allow_af => Ok((None, 3)),
+        _ => Err(Error::new(format!("error at line {}: register pair {:?} can't be used here", lineno, pair))),
+    }
+}
+
+/// Returns the prefix for an instruction that operates on HL, IX, or IY
+fn index_prefix(lineno: usize, pair: RegisterPair) -> Result<Option<u8>, Error> {
+    match pair {
+        RegisterPair::HL => Ok(None),
+        RegisterPair::IX => Ok(Some(PREFIX_IX)),
+        RegisterPair::IY => Ok(Some(PREFIX_IY)),
+        _ => Err(Error::new(format!("error at line {}: expected hl, ix, or iy, found {:?}", lineno, pair))),
+    }
+}
+
+fn expect_condition(lineno: usize, operand: &Operand, max: u8) -> Result<u8, Error> {
+    let name = match operand {
+        Operand::Register(Register::C) => "c".to_string(),
+        Operand::Immediate(Expression::Label(name)) => name.to_lowercase(),
+        _ => return Err(Error::new(format!("error at line {}: expected a condition, found {:?}", lineno, operand))),
+    };
+    match CONDITIONS.iter().find(|(cond, _)| *cond == name) {
+        Some((_, code)) if *code <= max => Ok(*code),
+        _ => Err(Error::new(format!("error at line {}: invalid condition {}", lineno, name))),
+    }
+}
+
+fn expect_number(lineno: usize, operand: &Operand, max: usize) -> Result<usize, Error> {
+    match operand {
+        Operand::Immediate(Expression::Number(number)) if *number <= max => Ok(*number),
+        _ => Err(Error::new(format!(
+            "error at line {}: expected a number from 0 to {}, found {:?}",
+            lineno, max, operand
+        ))),
+    }
+}
+
+/// Returns true if the value fits in the number of bits as either an unsigned number or a negative signed number
+fn fits_in_bits(value: usize, bits: u32) -> bool {
+    value < (1 << bits) || ((value as isize) < 0 && (value as isize) >= -(1 << (bits - 1)))
+}
+
+fn check_byte(lineno: usize, value: usize) -> Result<u8, Error> {
+    if fits_in_bits(value, 8) {
+        Ok(value as u8)
+    } else {
+        Err(Error::new(format!("error at line {}: value {:#x} doesn't fit in a byte", lineno, value)))
+    }
+}
+
+fn check_word(lineno: usize, value: usize) -> Result<u16, Error> {
+    if fits_in_bits(value, 16) {
+        Ok(value as u16)
+    } else {
+        Err(Error::new(format!("error at line {}: value {:#x} doesn't fit in a word", lineno, value)))
+    }
+}
+
+fn check_relative(lineno: usize, target: usize, next: usize) -> Result<u8, Error> {
+    let displacement = target as isize - next as isize;
+    i8::try_from(displacement)
+        .map(|displacement| displacement as u8)
+        .map_err(|_| Error::new(format!("error at line {}: branch target {:#06x} is out of range", lineno, target)))
+}
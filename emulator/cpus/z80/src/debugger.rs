@@ -1,12 +1,71 @@
-use crate::state::{Z80Error, Z80Address};
+use core::fmt;
+use std::collections::BTreeMap;
+
+use crate::state::{Z80, Z80Error, Z80Address};
+
+impl<Instant> Z80<Instant> {
+    /// Enable or disable recording unimplemented instructions, which are skipped over instead of stopping the cpu
+    pub fn record_illegal_instructions(&mut self, enable: bool) {
+        self.debugger.illegal_instructions.enabled = enable;
+    }
+
+    pub fn illegal_instructions(&self) -> &IllegalInstructionLog {
+        &self.debugger.illegal_instructions
+    }
+
+    /// Stop before the handler of a maskable interrupt runs, which is either any interrupt if `vector` is `None`,
+    /// or only those where the interrupting device put the given vector on the data bus
+    pub fn catch_interrupt(&mut self, vector: Option<u8>, enable: bool) {
+        self.debugger.caught_interrupts.retain(|caught| *caught != vector);
+        if enable {
+            self.debugger.caught_interrupts.push(vector);
+        }
+    }
+}
 
 #[derive(Clone, Default)]
 pub struct Z80Debugger {
     pub(crate) skip_breakpoint: usize,
     pub(crate) breakpoints: Vec<u16>,
+    pub(crate) illegal_instructions: IllegalInstructionLog,
+    pub(crate) caught_interrupts: Vec<Option<u8>>,
+    /// The vector of a caught interrupt that was accepted in the last step, which is reported before the next one
+    pub(crate) interrupt_caught: Option<u8>,
+}
+
+/// A count of the unimplemented instructions encountered at each address, which is only recorded when enabled
+#[derive(Clone, Default)]
+pub struct IllegalInstructionLog {
+    pub(crate) enabled: bool,
+    entries: BTreeMap<Z80Address, (usize, String)>,
+}
+
+impl IllegalInstructionLog {
+    pub fn record(&mut self, pc: Z80Address, description: String) {
+        self.entries.entry(pc).or_insert((0, description)).0 += 1;
+    }
+
+    pub fn dump<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        let total: usize = self.entries.values().map(|(count, _)| count).sum();
+        writeln!(writer, "Illegal instructions: {} at {} addresses", total, self.entries.len())?;
+        for (pc, (count, description)) in self.entries.iter() {
+            writeln!(writer, "  {:04x}: {:>8}x  {}", pc, count, description)?;
+        }
+        Ok(())
+    }
 }
 
 impl Z80Debugger {
+    pub fn check_caught_interrupt(&mut self, vector: u8) {
+        if self
+            .caught_interrupts
+            .iter()
+            .any(|caught| caught.is_none() || *caught == Some(vector))
+        {
+            self.interrupt_caught = Some(vector);
+        }
+    }
+
     pub fn check_breakpoints(&mut self, pc: Z80Address) -> Result<(), Z80Error> {
         for breakpoint in &self.breakpoints {
             if *breakpoint == pc {
@@ -1,7 +1,7 @@
 use core::fmt::Write;
 use emulator_hal::{BusAccess, Instant as EmuInstant};
 
-use crate::state::{Z80Error, Z80Address, Z80AddressSpace};
+use crate::state::{Z80Type, Z80Error, Z80Address, Z80AddressSpace, Z80BusCycle, Z80BusCycleKind};
 use crate::instructions::{
     Direction, Condition, Register, RegisterPair, IndexRegister, IndexRegisterHalf, SpecialRegister, InterruptMode, Target,
     LoadTarget, UndocumentedCopy, Instruction,
@@ -42,26 +42,41 @@ impl Z80Decoder {
 }
 
 impl Z80Decoder {
-    pub fn decode_at<Bus>(bus: &mut Bus, clock: Bus::Instant, start: Z80Address) -> Result<Self, Z80Error>
+    pub fn decode_at<Bus>(cputype: Z80Type, bus: &mut Bus, clock: Bus::Instant, start: Z80Address) -> Result<Self, Z80Error>
+    where
+        Bus: BusAccess<Z80AddressSpace>,
+    {
+        Self::decode_cycles_at(cputype, bus, clock, start).map(|(decoder, _)| decoder)
+    }
+
+    /// Decode the instruction at the given address, and also return the machine cycles used to fetch it
+    pub fn decode_cycles_at<Bus>(
+        cputype: Z80Type,
+        bus: &mut Bus,
+        clock: Bus::Instant,
+        start: Z80Address,
+    ) -> Result<(Self, Vec<Z80BusCycle>), Z80Error>
     where
         Bus: BusAccess<Z80AddressSpace>,
     {
         let mut decoder: DecodeNext<'_, Bus, Bus::Instant> = DecodeNext {
+            cputype,
             clock,
             bus,
             decoder: Z80Decoder::new(start),
+            bus_cycles: vec![],
         };
         decoder.decode_one()?;
-        Ok(decoder.decoder)
+        Ok((decoder.decoder, decoder.bus_cycles))
     }
 
-    pub fn dump_disassembly<Bus>(bus: &mut Bus, start: Z80Address, length: Z80Address)
+    pub fn dump_disassembly<Bus>(cputype: Z80Type, bus: &mut Bus, start: Z80Address, length: Z80Address)
     where
         Bus: BusAccess<Z80AddressSpace>,
     {
         let mut next = start;
         while next < (start + length) {
-            match Z80Decoder::decode_at(bus, Bus::Instant::START, next) {
+            match Z80Decoder::decode_at(cputype, bus, Bus::Instant::START, next) {
                 Ok(mut decoder) => {
                     decoder.dump_decoded(bus);
                     next = decoder.end;
@@ -104,9 +119,11 @@ pub struct DecodeNext<'a, Bus, Instant>
 where
     Bus: BusAccess<Z80AddressSpace, Instant = Instant>,
 {
+    cputype: Z80Type,
     clock: Instant,
     bus: &'a mut Bus,
     decoder: Z80Decoder,
+    bus_cycles: Vec<Z80BusCycle>,
 }
 
 impl<'a, Bus, Instant> DecodeNext<'a, Bus, Instant>
@@ -115,7 +132,10 @@ where
     Instant: EmuInstant,
 {
     pub fn decode_one(&mut self) -> Result<(), Z80Error> {
-        let ins = self.read_instruction_byte()?;
+        let mut ins = self.read_opcode_byte()?;
+        if self.cputype == Z80Type::I8080 {
+            ins = get_i8080_opcode(ins);
+        }
         self.decoder.instruction = self.decode_bare(ins, 0)?;
         Ok(())
     }
@@ -284,7 +304,7 @@ where
     }
 
     pub fn decode_prefix_cb(&mut self) -> Result<Instruction, Z80Error> {
-        let ins = self.read_instruction_byte()?;
+        let ins = self.read_opcode_byte()?;
         match get_ins_x(ins) {
             0 => Ok(get_rot_instruction(get_ins_y(ins), get_register(get_ins_z(ins)), None)),
             1 => Ok(Instruction::BIT(get_ins_y(ins), get_register(get_ins_z(ins)))),
@@ -312,7 +332,7 @@ where
     }
 
     pub fn decode_prefix_ed(&mut self) -> Result<Instruction, Z80Error> {
-        let ins = self.read_instruction_byte()?;
+        let ins = self.read_opcode_byte()?;
 
         match get_ins_x(ins) {
             0 => Ok(Instruction::NOP),
@@ -405,7 +425,7 @@ where
     }
 
     pub fn decode_prefix_dd_fd(&mut self, index_reg: IndexRegister) -> Result<Instruction, Z80Error> {
-        let ins = self.read_instruction_byte()?;
+        let ins = self.read_opcode_byte()?;
 
         if ins == 0xCB {
             return self.decode_sub_prefix_cb(index_reg);
@@ -581,27 +601,32 @@ where
         Ok(result)
     }
 
+    fn read_opcode_byte(&mut self) -> Result<u8, Z80Error> {
+        self.read_byte(Z80BusCycleKind::OpcodeFetch)
+    }
 
     fn read_instruction_byte(&mut self) -> Result<u8, Z80Error> {
-        let byte = self
-            .bus
-            .read_u8(self.clock, Z80AddressSpace::Memory(self.decoder.end))
-            .map_err(|err| Z80Error::BusError(format!("{:?}", err)))?;
-        self.decoder.end = self.decoder.end.wrapping_add(1);
-        Ok(byte)
+        self.read_byte(Z80BusCycleKind::MemoryRead)
     }
 
     fn read_instruction_word(&mut self) -> Result<u16, Z80Error> {
         let mut bytes = [0; 2];
         for byte in bytes.iter_mut() {
-            *byte = self
-                .bus
-                .read_u8(self.clock, Z80AddressSpace::Memory(self.decoder.end))
-                .map_err(|err| Z80Error::BusError(format!("{:?}", err)))?;
-            self.decoder.end = self.decoder.end.wrapping_add(1);
+            *byte = self.read_byte(Z80BusCycleKind::MemoryRead)?;
         }
         Ok(u16::from_le_bytes(bytes))
     }
+
+    fn read_byte(&mut self, kind: Z80BusCycleKind) -> Result<u8, Z80Error> {
+        let addr = self.decoder.end;
+        let byte = self
+            .bus
+            .read_u8(self.clock, Z80AddressSpace::Memory(addr))
+            .map_err(|err| Z80Error::BusError(format!("{:?}", err)))?;
+        self.bus_cycles.push(Z80BusCycle::new(kind, addr, byte));
+        self.decoder.end = addr.wrapping_add(1);
+        Ok(byte)
+    }
 }
 
 impl From<IndexRegister> for RegisterPair {
@@ -613,6 +638,18 @@ impl From<IndexRegister> for RegisterPair {
     }
 }
 
+/// Returns the equivalent opcode for the opcodes that the 8080 executes differently, which are the ones used by the
+/// Z80 for its relative jumps, exchanges, and prefixes, and which the 8080 treats as duplicates of other opcodes
+fn get_i8080_opcode(ins: u8) -> u8 {
+    match ins {
+        0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => 0x00,
+        0xCB => 0xC3,
+        0xD9 => 0xC9,
+        0xDD | 0xED | 0xFD => 0xCD,
+        _ => ins,
+    }
+}
+
 fn get_alu_instruction(alu: u8, target: Target) -> Instruction {
     match alu {
         0 => Instruction::ADDa(target),
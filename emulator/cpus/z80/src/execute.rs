@@ -5,7 +5,9 @@ use crate::instructions::{
     Condition, Instruction, LoadTarget, Target, Register, InterruptMode, RegisterPair, IndexRegister, SpecialRegister,
     IndexRegisterHalf, Size, Direction, UndocumentedCopy,
 };
-use crate::state::{Z80, Z80Error, Z80State, Z80Signals, Z80Address, Z80AddressSpace, Status, Flags};
+use crate::state::{
+    Z80, Z80Type, Z80Error, Z80State, Z80Signals, Z80Address, Z80AddressSpace, Z80BusCycle, Z80BusCycleKind, Status, Flags,
+};
 use crate::timing::Z80InstructionCycles;
 use crate::debugger::Z80Debugger;
 
@@ -14,6 +16,10 @@ const FLAGS_NUMERIC: u8 = 0xC0;
 const FLAGS_ARITHMETIC: u8 = 0x17;
 const FLAGS_CARRY_HALF_CARRY: u8 = 0x11;
 
+/// The 8080 doesn't have the AddSubtract, F3, or F5 flags, and the AddSubtract bit always reads as 1
+const I8080_FLAGS_MASK: u8 = 0xD5;
+const I8080_FLAGS_SET: u8 = 0x02;
+
 
 enum RotateType {
     Bit8,
@@ -25,6 +31,8 @@ pub struct Z80Cycle<Instant> {
     pub current_clock: Instant,
     pub decoder: Z80Decoder,
     pub took_branch: bool,
+    /// The bus transactions of each machine cycle, including the instruction fetches, in the order they occurred
+    pub bus_cycles: Vec<Z80BusCycle>,
 }
 
 impl<Instant> Z80Cycle<Instant> {
@@ -33,6 +41,7 @@ impl<Instant> Z80Cycle<Instant> {
             current_clock,
             decoder: Default::default(),
             took_branch: false,
+            bus_cycles: vec![],
         }
     }
 }
@@ -49,7 +58,11 @@ where
     where
         Bus: BusAccess<Z80AddressSpace, Instant = Instant>,
     {
+        // An interrupt isn't accepted until after the instruction that follows EI
+        let interrupt_inhibited = self.previous_cycle.decoder.instruction == Instruction::EI;
         let executor = ExecuteNext {
+            cputype: self.cputype,
+            interrupt_inhibited,
             state: &mut self.state,
             signals: &mut self.signals,
             debugger: &mut self.debugger,
@@ -65,9 +78,11 @@ pub(crate) struct ExecuteNext<'a, Bus, Instant>
 where
     Bus: BusAccess<Z80AddressSpace, Instant = Instant>,
 {
-    state: &'a mut Z80State,
+    cputype: Z80Type,
+    interrupt_inhibited: bool,
+    pub(crate) state: &'a mut Z80State,
     signals: &'a mut Z80Signals,
-    debugger: &'a mut Z80Debugger,
+    pub(crate) debugger: &'a mut Z80Debugger,
     cycle: Z80Cycle<Instant>,
     bus: Bus,
 }
@@ -82,10 +97,19 @@ where
     }
 
     pub(crate) fn step_one(&mut self) -> Result<u16, Z80Error> {
-        let clocks = if self.signals.reset.get() {
-            self.reset()?
-        } else if self.signals.bus_request.get() {
+        if self.signals.reset.get() {
+            self.signals.bus_ack.set(false);
+            return self.reset();
+        }
+
+        // Bus requests are only checked between instructions, so the bus is granted at the end of the one in progress
+        let bus_granted = self.signals.bus_request.get();
+        self.signals.bus_ack.set(bus_granted);
+
+        let clocks = if bus_granted {
             4
+        } else if self.signals.interrupt.get() && self.state.iff1 && !self.interrupt_inhibited {
+            self.accept_interrupt()?
         } else {
             self.step_internal()?
         };
@@ -95,6 +119,8 @@ where
     fn step_internal(&mut self) -> Result<u16, Z80Error> {
         match self.state.status {
             Status::Init => self.init(),
+            // While halted, the cpu executes NOPs until an interrupt occurs, which can't happen if they're disabled
+            Status::Halted if self.state.iff1 => Ok(4),
             Status::Halted => Err(Z80Error::Halted),
             Status::Running => match self.cycle_one() {
                 Ok(clocks) => Ok(clocks),
@@ -109,6 +135,37 @@ where
         Ok(16)
     }
 
+    /// Accept a maskable interrupt, using the value the interrupting device put on the data bus, which is an
+    /// RST instruction in mode 0, and the low byte of the vector table address in mode 2
+    fn accept_interrupt(&mut self) -> Result<u16, Z80Error> {
+        if self.state.status == Status::Halted {
+            self.state.status = Status::Running;
+            self.state.pc = self.state.pc.wrapping_add(1);
+        }
+        self.state.iff1 = false;
+        self.state.iff2 = false;
+
+        let vector = self.signals.interrupt_vector.get();
+        self.debugger.check_caught_interrupt(vector);
+        self.push_word(self.state.pc)?;
+        match self.state.im {
+            InterruptMode::Mode2 => {
+                let table = ((self.state.i as u16) << 8) | vector as u16;
+                self.state.pc = self.read_port_u16(table)?;
+                Ok(19)
+            },
+            // Only the RST instructions are supported in mode 0, and anything else is treated as RST 38h
+            InterruptMode::Mode0 if (vector & 0xC7) == 0xC7 => {
+                self.state.pc = (vector & 0x38) as u16;
+                Ok(13)
+            },
+            _ => {
+                self.state.pc = 0x0038;
+                Ok(13)
+            },
+        }
+    }
+
     fn reset(&mut self) -> Result<u16, Z80Error> {
         *self.state = Default::default();
         Ok(16)
@@ -118,15 +175,39 @@ where
         self.debugger.check_breakpoints(self.state.pc)?;
 
         self.decode_next()?;
-        self.execute_current()?;
+        match self.execute_current() {
+            Err(Z80Error::Unimplemented(instruction)) if self.debugger.illegal_instructions.enabled => {
+                self.debugger
+                    .illegal_instructions
+                    .record(self.cycle.decoder.start, format!("unimplemented: {:?}", instruction));
+                return Ok(Z80InstructionCycles::from_instruction(&instruction, self.cycle.decoder.extra_instruction_bytes)
+                    .map(|cycles| cycles.calculate_cycles(false))
+                    .unwrap_or(4));
+            },
+            result => result?,
+        }
+
+        if self.is_i8080() {
+            self.state.reg[Register::F as usize] = (self.get_flags() & I8080_FLAGS_MASK) | I8080_FLAGS_SET;
+            let opcode = self.cycle.bus_cycles[0].data;
+            return Ok(Z80InstructionCycles::from_i8080_opcode(opcode).calculate_cycles(self.cycle.took_branch));
+        }
+
         Ok(
             Z80InstructionCycles::from_instruction(&self.cycle.decoder.instruction, self.cycle.decoder.extra_instruction_bytes)?
                 .calculate_cycles(self.cycle.took_branch),
         )
     }
 
+    fn is_i8080(&self) -> bool {
+        self.cputype == Z80Type::I8080
+    }
+
     fn decode_next(&mut self) -> Result<(), Z80Error> {
-        self.cycle.decoder = Z80Decoder::decode_at(&mut self.bus, self.cycle.current_clock, self.state.pc)?;
+        let (decoder, bus_cycles) =
+            Z80Decoder::decode_cycles_at(self.cputype, &mut self.bus, self.cycle.current_clock, self.state.pc)?;
+        self.cycle.decoder = decoder;
+        self.cycle.bus_cycles = bus_cycles;
         self.increment_refresh(self.cycle.decoder.end.saturating_sub(self.cycle.decoder.start) as u8);
         self.state.pc = self.cycle.decoder.end;
         Ok(())
@@ -280,9 +361,11 @@ where
         let dest = self.get_register_pair_value(dest_pair);
 
         let (result, carry, _, half_carry) = add_words(dest, src);
-        self.set_flag(Flags::AddSubtract, false);
         self.set_flag(Flags::Carry, carry);
-        self.set_flag(Flags::HalfCarry, half_carry);
+        if !self.is_i8080() {
+            self.set_flag(Flags::AddSubtract, false);
+            self.set_flag(Flags::HalfCarry, half_carry);
+        }
 
         self.set_register_pair_value(dest_pair, result);
         Ok(())
@@ -293,7 +376,9 @@ where
         let value = self.get_target_value(target)?;
         let result = acc & value;
         self.set_register_value(Register::A, result);
-        self.set_logic_op_flags(result, false, true);
+        // The 8080 sets the half carry to the OR of bit 3 of the operands
+        let half_carry = !self.is_i8080() || (acc | value) & 0x08 != 0;
+        self.set_logic_op_flags(result, false, half_carry);
         Ok(())
     }
 
@@ -324,8 +409,10 @@ where
     }
 
     fn execute_ccf(&mut self) -> Result<(), Z80Error> {
-        self.set_flag(Flags::AddSubtract, false);
-        self.set_flag(Flags::HalfCarry, self.get_flag(Flags::Carry));
+        if !self.is_i8080() {
+            self.set_flag(Flags::AddSubtract, false);
+            self.set_flag(Flags::HalfCarry, self.get_flag(Flags::Carry));
+        }
         self.set_flag(Flags::Carry, !self.get_flag(Flags::Carry));
         Ok(())
     }
@@ -351,8 +438,10 @@ where
     fn execute_cpl(&mut self) -> Result<(), Z80Error> {
         let value = self.get_register_value(Register::A);
         self.set_register_value(Register::A, !value);
-        self.set_flag(Flags::HalfCarry, true);
-        self.set_flag(Flags::AddSubtract, true);
+        if !self.is_i8080() {
+            self.set_flag(Flags::HalfCarry, true);
+            self.set_flag(Flags::AddSubtract, true);
+        }
         Ok(())
     }
 
@@ -746,8 +835,10 @@ where
     fn execute_rla(&mut self) -> Result<(), Z80Error> {
         let value = self.get_register_value(Register::A);
         let (result, out_bit) = self.rotate_left(value, RotateType::Bit9);
-        self.set_flag(Flags::AddSubtract, false);
-        self.set_flag(Flags::HalfCarry, false);
+        if !self.is_i8080() {
+            self.set_flag(Flags::AddSubtract, false);
+            self.set_flag(Flags::HalfCarry, false);
+        }
         self.set_flag(Flags::Carry, out_bit);
         self.set_register_value(Register::A, result);
         Ok(())
@@ -767,8 +858,10 @@ where
     fn execute_rlca(&mut self) -> Result<(), Z80Error> {
         let value = self.get_register_value(Register::A);
         let (result, out_bit) = self.rotate_left(value, RotateType::Bit8);
-        self.set_flag(Flags::AddSubtract, false);
-        self.set_flag(Flags::HalfCarry, false);
+        if !self.is_i8080() {
+            self.set_flag(Flags::AddSubtract, false);
+            self.set_flag(Flags::HalfCarry, false);
+        }
         self.set_flag(Flags::Carry, out_bit);
         self.set_register_value(Register::A, result);
         Ok(())
@@ -806,8 +899,10 @@ where
     fn execute_rra(&mut self) -> Result<(), Z80Error> {
         let value = self.get_register_value(Register::A);
         let (result, out_bit) = self.rotate_right(value, RotateType::Bit9);
-        self.set_flag(Flags::AddSubtract, false);
-        self.set_flag(Flags::HalfCarry, false);
+        if !self.is_i8080() {
+            self.set_flag(Flags::AddSubtract, false);
+            self.set_flag(Flags::HalfCarry, false);
+        }
         self.set_flag(Flags::Carry, out_bit);
         self.set_register_value(Register::A, result);
         Ok(())
@@ -827,8 +922,10 @@ where
     fn execute_rrca(&mut self) -> Result<(), Z80Error> {
         let value = self.get_register_value(Register::A);
         let (result, out_bit) = self.rotate_right(value, RotateType::Bit8);
-        self.set_flag(Flags::AddSubtract, false);
-        self.set_flag(Flags::HalfCarry, false);
+        if !self.is_i8080() {
+            self.set_flag(Flags::AddSubtract, false);
+            self.set_flag(Flags::HalfCarry, false);
+        }
         self.set_flag(Flags::Carry, out_bit);
         self.set_register_value(Register::A, result);
         Ok(())
@@ -890,8 +987,10 @@ where
     }
 
     fn execute_scf(&mut self) -> Result<(), Z80Error> {
-        self.set_flag(Flags::AddSubtract, false);
-        self.set_flag(Flags::HalfCarry, false);
+        if !self.is_i8080() {
+            self.set_flag(Flags::AddSubtract, false);
+            self.set_flag(Flags::HalfCarry, false);
+        }
         self.set_flag(Flags::Carry, true);
         Ok(())
     }
@@ -1126,16 +1225,21 @@ where
 
     fn read_port_u8(&mut self, addr: u16) -> Result<u8, Z80Error> {
         self.increment_refresh(1);
-        self.bus
+        let value = self
+            .bus
             .read_u8(self.cycle.current_clock, Z80AddressSpace::Memory(addr as Z80Address))
-            .map_err(|err| Z80Error::BusError(format!("{:?}", err)))
+            .map_err(|err| Z80Error::BusError(format!("{:?}", err)))?;
+        self.record_bus_cycle(Z80BusCycleKind::MemoryRead, addr, value);
+        Ok(value)
     }
 
     fn write_port_u8(&mut self, addr: u16, value: u8) -> Result<(), Z80Error> {
         self.increment_refresh(1);
         self.bus
             .write_u8(self.cycle.current_clock, Z80AddressSpace::Memory(addr as Z80Address), value)
-            .map_err(|err| Z80Error::BusError(format!("{:?}", err)))
+            .map_err(|err| Z80Error::BusError(format!("{:?}", err)))?;
+        self.record_bus_cycle(Z80BusCycleKind::MemoryWrite, addr, value);
+        Ok(())
     }
 
     /// Read a u16 value through this CPU's memory port
@@ -1150,6 +1254,7 @@ where
                 .bus
                 .read_u8(self.cycle.current_clock, Z80AddressSpace::Memory(addr))
                 .map_err(|err| Z80Error::BusError(format!("{:?}", err)))?;
+            self.record_bus_cycle(Z80BusCycleKind::MemoryRead, addr, *byte);
             addr = addr.wrapping_add(1);
         }
         Ok(u16::from_le_bytes(bytes))
@@ -1166,6 +1271,7 @@ where
             self.bus
                 .write_u8(self.cycle.current_clock, Z80AddressSpace::Memory(addr), *byte)
                 .map_err(|err| Z80Error::BusError(format!("{:?}", err)))?;
+            self.record_bus_cycle(Z80BusCycleKind::MemoryWrite, addr, *byte);
             addr = addr.wrapping_add(1);
         }
         Ok(())
@@ -1177,6 +1283,7 @@ where
             .bus
             .read_u8(self.cycle.current_clock, Z80AddressSpace::IO(addr))
             .map_err(|err| Z80Error::BusError(format!("{:?}", err)))?;
+        self.record_bus_cycle(Z80BusCycleKind::IoRead, addr, bytes_read);
         Ok(bytes_read)
     }
 
@@ -1185,9 +1292,14 @@ where
         self.bus
             .write_u8(self.cycle.current_clock, Z80AddressSpace::IO(addr), value)
             .map_err(|err| Z80Error::BusError(format!("{:?}", err)))?;
+        self.record_bus_cycle(Z80BusCycleKind::IoWrite, addr, value);
         Ok(())
     }
 
+    fn record_bus_cycle(&mut self, kind: Z80BusCycleKind, addr: u16, data: u8) {
+        self.cycle.bus_cycles.push(Z80BusCycle::new(kind, addr, data));
+    }
+
     fn get_register_value(&mut self, reg: Register) -> u8 {
         self.state.reg[reg as usize]
     }
@@ -1299,6 +1411,14 @@ where
         self.state.reg[Register::F as usize] = 0;
         self.set_numeric_flags(value, size);
 
+        // The 8080 sets the parity flag instead of the overflow flag, and subtracts by adding the complement,
+        // so its half carry is the inverse of the Z80's half borrow
+        let (overflow, half_carry) = if self.is_i8080() {
+            ((value as u8).count_ones() & 0x01 == 0, half_carry ^ addsub)
+        } else {
+            (overflow, half_carry)
+        };
+
         let addsub_flag = if addsub { Flags::AddSubtract as u8 } else { 0 };
         let overflow_flag = if overflow { Flags::Parity as u8 } else { 0 };
         let carry_flag = if carry { Flags::Carry as u8 } else { 0 };
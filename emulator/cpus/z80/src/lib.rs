@@ -1,3 +1,4 @@
+pub mod assembler;
 mod debugger;
 mod decode;
 mod emuhal;
@@ -11,8 +12,10 @@ pub mod moa;
 #[cfg(feature = "moa")]
 pub use crate::moa::MoaZ80;
 
-pub use crate::state::{Z80, Z80Type, Z80Address, Z80IOAddress, Z80Error, Z80State, Status, Flags};
+pub use crate::state::{Z80, Z80Type, Z80Address, Z80IOAddress, Z80Error, Z80State, Z80BusCycle, Z80BusCycleKind, Status, Flags};
+pub use crate::assembler::Z80Assembler;
 pub use crate::decode::Z80Decoder;
+pub use crate::debugger::IllegalInstructionLog;
 pub use crate::execute::Z80Cycle;
 pub use crate::instructions::{
     Size, Direction, Condition, Register, RegisterPair, IndexRegister, IndexRegisterHalf, SpecialRegister, InterruptMode, Target,
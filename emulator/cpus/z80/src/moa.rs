@@ -1,11 +1,17 @@
+use std::fmt;
+use std::any::Any;
 use std::rc::Rc;
 use std::cell::RefCell;
 use femtos::{Instant, Duration};
 use emulator_hal::{BusAdapter, NoBus, Instant as EmuInstant};
 
-use moa_core::{System, Error, Bus, Address, Steppable, Interruptable, /* Signalable, Signal,*/ Debuggable, Transmutable};
+use moa_core::{
+    System, Error, Bus, Address, Addressable, Steppable, Interruptable, /* Signalable, Signal,*/ Debuggable, Inspectable,
+    Snapshotable, Transmutable, InterruptChain,
+};
 
-use crate::{Z80, Z80Error, Z80Decoder};
+use crate::{Z80, Z80Error, Z80Decoder, Z80State, Z80Cycle, Z80BusCycle};
+use crate::state::Z80Signals;
 use crate::instructions::Register;
 use crate::emuhal::Z80Port;
 
@@ -14,7 +20,88 @@ where
     Instant: EmuInstant,
 {
     pub bus: Rc<RefCell<Bus>>,
+    /// The bus used by the `in` and `out` instructions, which is given the full 16-bit port address
+    pub io_bus: Rc<RefCell<Bus>>,
     pub cpu: Z80<Instant>,
+    contention: Option<Rc<ContentionFn>>,
+    interrupt_chain: Option<InterruptChain>,
+}
+
+/// Returns the number of clocks that a bus cycle is delayed by another device sharing the bus, given the
+/// number of clocks since the cpu started when the bus cycle begins
+pub type ContentionFn = dyn Fn(u64, &Z80BusCycle) -> u16;
+
+impl MoaZ80<Instant> {
+    /// Create a cpu using the given memory bus, with nothing connected to its I/O ports
+    pub fn new(cpu: Z80<Instant>, bus: Rc<RefCell<Bus>>) -> Self {
+        let io_bus = Rc::new(RefCell::new(Bus::default()));
+        io_bus.borrow_mut().set_ignore_unmapped(true);
+        Self {
+            bus,
+            io_bus,
+            cpu,
+            contention: None,
+            interrupt_chain: None,
+        }
+    }
+
+    pub fn with_io_bus(mut self, io_bus: Rc<RefCell<Bus>>) -> Self {
+        self.io_bus = io_bus;
+        self
+    }
+
+    /// Delay each bus cycle by the number of clocks returned by the given function, such as when the video
+    /// circuit has priority over the cpu for some of the memory
+    pub fn with_contention<F>(mut self, contention: F) -> Self
+    where
+        F: Fn(u64, &Z80BusCycle) -> u16 + 'static,
+    {
+        self.contention = Some(Rc::new(contention));
+        self
+    }
+
+    /// Drive the maskable interrupt input from a daisy chain of devices, with the vector supplied by the highest
+    /// priority device that's interrupting.  All of the devices must be connected to the chain before it's given
+    pub fn with_interrupt_chain(mut self, chain: InterruptChain) -> Self {
+        self.interrupt_chain = Some(chain);
+        self
+    }
+
+    fn contended_clocks(&self, start: u64) -> u16 {
+        let contention = match &self.contention {
+            Some(contention) => contention,
+            None => return 0,
+        };
+
+        // The start of each bus cycle is estimated from the lengths of the previous cycles, without the
+        // internal clocks between them
+        let mut clock = start;
+        let mut delay = 0;
+        for bus_cycle in self.cpu.previous_cycle.bus_cycles.iter() {
+            let extra = contention(clock, bus_cycle);
+            delay += extra;
+            clock += (extra + bus_cycle.kind.clocks()) as u64;
+        }
+        delay
+    }
+
+    /// Write the cpu's registers and current instruction to the given writer, using the system bus to
+    /// read the instruction bytes
+    pub fn dump_state<W: fmt::Write>(&mut self, system: &System, writer: &mut W) -> Result<(), fmt::Error> {
+        let bus = &mut *system.bus.borrow_mut();
+        let mut adapter = BusAdapter::<_, _, _, Z80Error>::new(bus, |addr| addr as u64);
+        let mut io_bus = NoBus::new();
+        let mut bus = Z80Port::new(&mut adapter, &mut io_bus);
+        self.cpu.dump_state(writer, system.clock, &mut bus)
+    }
+
+    fn dump_breakpoints<W: fmt::Write>(&self, writer: &mut W) -> Result<(), fmt::Error> {
+        writeln!(writer, "Breakpoints:")?;
+        for (id, addr) in self.cpu.debugger.breakpoints.iter().enumerate() {
+            writeln!(writer, "  {}: {:#06x}", id, addr)?;
+        }
+        Ok(())
+    }
 }
 
 impl Steppable for MoaZ80<Instant>
@@ -22,24 +109,45 @@ where
     Instant: EmuInstant,
 {
     fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        // An interrupt caught during the last step stops the cpu before the first instruction of its handler
+        if let Some(vector) = self.cpu.debugger.interrupt_caught.take() {
+            let description = format!("interrupt with vector {:02x}", vector);
+            return Err(Error::exception_caught(self.cpu.state.pc as Address, description));
+        }
+
+        if let Some(chain) = &self.interrupt_chain {
+            let vector = chain.check();
+            self.cpu.signals.interrupt.set(vector.is_some());
+            self.cpu.signals.interrupt_vector.set(vector.unwrap_or(0xFF));
+        }
+
         let bus = &mut *self.bus.borrow_mut();
+        bus.set_access_pc(self.cpu.state.pc as Address);
         let mut adapter = BusAdapter::<_, _, _, Z80Error>::new(bus, |addr| addr as u64);
-        let mut io_bus = NoBus::new();
-        let mut bus = Z80Port::new(&mut adapter, &mut io_bus);
+        let io_bus = &mut *self.io_bus.borrow_mut();
+        let mut io_adapter = BusAdapter::<_, _, _, Z80Error>::new(io_bus, |addr| addr as u64);
+        let mut bus = Z80Port::new(&mut adapter, &mut io_adapter);
 
         let mut executor = self.cpu.begin(system.clock, &mut bus)?;
-        let clocks = executor.step_one()?;
+        let clocks = match executor.step_one() {
+            Ok(clocks) => clocks,
+            Err(Z80Error::Breakpoint) => {
+                let pc = executor.state.pc;
+                let id = executor.debugger.breakpoints.iter().position(|addr| *addr == pc);
+                return Err(Error::breakpoint_at(pc as Address, id));
+            },
+            Err(err) => return Err(err.into()),
+        };
         self.cpu.previous_cycle = executor.end();
-        Ok(Instant::hertz_to_duration(self.cpu.frequency.as_hz() as u64) * clocks as u32)
+
+        let period = Instant::hertz_to_duration(self.cpu.frequency.as_hz() as u64);
+        let clocks = clocks + self.contended_clocks(system.clock.as_duration() / period);
+        Ok(period * clocks as u32)
     }
 
     fn on_error(&mut self, system: &System) {
-        let bus = &mut *system.bus.borrow_mut();
-        let mut adapter = BusAdapter::<_, _, _, Z80Error>::new(bus, |addr| addr as u64);
-        let mut io_bus = NoBus::new();
-        let mut bus = Z80Port::new(&mut adapter, &mut io_bus);
         let mut output = String::with_capacity(256);
-        let _ = self.cpu.dump_state(&mut output, system.clock, &mut bus);
+        let _ = self.dump_state(system, &mut output);
         println!("{}", output);
     }
 }
@@ -78,6 +186,14 @@ impl Transmutable for MoaZ80<Instant> {
         Some(self)
     }
 
+    fn as_inspectable(&mut self) -> Option<&mut dyn Inspectable> {
+        Some(self)
+    }
+
+    fn as_snapshotable(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
+
     //#[inline]
     //fn as_signalable(&mut self) -> Option<&mut dyn Signalable> {
     //    Some(self)
@@ -88,7 +204,7 @@ impl From<Z80Error> for Error {
     fn from(err: Z80Error) -> Self {
         match err {
             Z80Error::Halted => Self::Other("cpu halted".to_string()),
-            Z80Error::Breakpoint => Self::Breakpoint("breakpoint".to_string()),
+            Z80Error::Breakpoint => Self::breakpoint("breakpoint"),
             Z80Error::Unimplemented(instruction) => Self::new(format!("unimplemented instruction {:?}", instruction)),
             Z80Error::UnexpectedInstruction(instruction) => Self::new(format!("unexpected instruction {:?}", instruction)),
             Z80Error::Other(msg) => Self::Other(msg),
@@ -119,14 +235,16 @@ impl Debuggable for MoaZ80<Instant> {
     }
 
     fn print_current_step(&mut self, system: &System) -> Result<(), Error> {
-        let bus = &mut *system.bus.borrow_mut();
-        let mut adapter = BusAdapter::<_, _, _, Z80Error>::new(bus, |addr| addr as u64);
-        let mut io_bus = NoBus::new();
-        let mut bus = Z80Port::new(&mut adapter, &mut io_bus);
+        {
+            let bus = &mut *system.bus.borrow_mut();
+            let mut adapter = BusAdapter::<_, _, _, Z80Error>::new(bus, |addr| addr as u64);
+            let mut io_bus = NoBus::new();
+            let mut bus = Z80Port::new(&mut adapter, &mut io_bus);
+            self.cpu.previous_cycle.decoder.dump_decoded(&mut bus);
+        }
 
-        self.cpu.previous_cycle.decoder.dump_decoded(&mut bus);
         let mut output = String::with_capacity(256);
-        let _ = self.cpu.dump_state(&mut output, system.clock, &mut bus);
+        self.dump_state(system, &mut output)?;
         println!("{}", output);
         Ok(())
     }
@@ -137,16 +255,114 @@ impl Debuggable for MoaZ80<Instant> {
         let mut io_bus = NoBus::new();
         let mut bus = Z80Port::new(&mut adapter, &mut io_bus);
 
-        Z80Decoder::dump_disassembly(&mut bus, addr as u16, count as u16);
+        Z80Decoder::dump_disassembly(self.cpu.cputype, &mut bus, addr as u16, count as u16);
     }
 
     fn run_command(&mut self, _system: &System, args: &[&str]) -> Result<bool, Error> {
         match args[0] {
             "l" => self.cpu.state.reg[Register::L as usize] = 0x05,
+            "bl" | "breakpoints" => {
+                let mut output = String::new();
+                self.dump_breakpoints(&mut output)?;
+                print!("{}", output);
+            },
+            "illegal" => match args.get(1) {
+                Some(&"on") => self.cpu.record_illegal_instructions(true),
+                Some(&"off") => self.cpu.record_illegal_instructions(false),
+                Some(&"report") if !self.cpu.illegal_instructions().enabled => {},
+                None | Some(&"report") => {
+                    let mut output = String::new();
+                    self.cpu.illegal_instructions().dump(&mut output)?;
+                    print!("{}", output);
+                },
+                _ => return Err(Error::new("Usage: illegal [on | off | report]")),
+            },
             _ => {
                 return Ok(true);
             },
         }
         Ok(false)
     }
+
+    /// Catch any maskable interrupt with `int`, or only those with the given hex vector on the data bus
+    fn catch_exception(&mut self, exception: &str, enable: bool) -> Result<(), Error> {
+        let vector = match exception {
+            "int" | "irq" => None,
+            vector => Some(u8::from_str_radix(vector, 16).map_err(|_| Error::new(format!("unknown interrupt: {}", exception)))?),
+        };
+        self.cpu.catch_interrupt(vector, enable);
+        Ok(())
+    }
+
+    fn get_register(&mut self, name: &str) -> Option<u64> {
+        let state = &self.cpu.state;
+        let pair = |index: usize| u16::from_be_bytes([state.reg[index], state.reg[index + 1]]);
+        let value = match name {
+            "b" => state.reg[Register::B as usize] as u16,
+            "c" => state.reg[Register::C as usize] as u16,
+            "d" => state.reg[Register::D as usize] as u16,
+            "e" => state.reg[Register::E as usize] as u16,
+            "h" => state.reg[Register::H as usize] as u16,
+            "l" => state.reg[Register::L as usize] as u16,
+            "a" => state.reg[Register::A as usize] as u16,
+            "f" => state.reg[Register::F as usize] as u16,
+            "bc" => pair(Register::B as usize),
+            "de" => pair(Register::D as usize),
+            "hl" => pair(Register::H as usize),
+            "af" => pair(Register::A as usize),
+            "ix" => state.ix,
+            "iy" => state.iy,
+            "sp" => state.sp,
+            "pc" => state.pc,
+            "i" => state.i as u16,
+            "r" => state.r as u16,
+            _ => return None,
+        };
+        Some(value as u64)
+    }
+
+    fn register_names(&self) -> &'static [&'static str] {
+        &["af", "bc", "de", "hl", "ix", "iy", "sp", "i", "r"]
+    }
+
+    fn disassemble(&mut self, _system: &System, addr: Address) -> Option<(String, Vec<u8>)> {
+        let bus = &mut *self.bus.borrow_mut();
+        let decoder = {
+            let mut adapter = BusAdapter::<_, _, _, Z80Error>::new(&mut *bus, |addr| addr as u64);
+            let mut io_bus = NoBus::new();
+            let mut port = Z80Port::new(&mut adapter, &mut io_bus);
+            Z80Decoder::decode_at(self.cpu.cputype, &mut port, Instant::START, addr as u16).ok()?
+        };
+
+        let mut bytes = vec![0; decoder.end.saturating_sub(decoder.start) as usize];
+        bus.read(Instant::START, decoder.start as Address, &mut bytes).ok()?;
+        Some((format!("{:?}", decoder.instruction), bytes))
+    }
+}
+
+impl Snapshotable for MoaZ80<Instant> {
+    fn save_state(&mut self) -> Option<Box<dyn Any>> {
+        Some(Box::new((self.cpu.state.clone(), self.cpu.previous_cycle.clone(), self.cpu.signals.clone())))
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        if let Some((state, cycle, signals)) = state.downcast_ref::<(Z80State, Z80Cycle<Instant>, Z80Signals)>() {
+            self.cpu.state = state.clone();
+            self.cpu.previous_cycle = cycle.clone();
+            self.cpu.signals = signals.clone();
+        }
+    }
+}
+
+impl Inspectable for MoaZ80<Instant> {
+    fn inspect(&mut self, system: &System, args: &[&str]) -> Result<(), Error> {
+        let mut output = String::with_capacity(256);
+        match args[0] {
+            "" | "state" => self.dump_state(system, &mut output)?,
+            "breakpoints" => self.dump_breakpoints(&mut output)?,
+            _ => return Err(Error::new("Usage: inspect <device> [state | breakpoints]")),
+        }
+        print!("{}", output);
+        Ok(())
+    }
 }
@@ -13,6 +13,8 @@ use crate::instructions::{Instruction, Register, InterruptMode};
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Z80Type {
     Z80,
+    /// The Intel 8080, which has none of the Z80's extra instructions, and has different flags
+    I8080,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -90,12 +92,33 @@ impl Z80State {
     }
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 pub struct Z80Signals {
     //pub reset: bool,
     //pub bus_request: bool,
     pub reset: Signal<bool>,
     pub bus_request: Signal<bool>,
+    /// The bus acknowledge output, which is set once the cpu has finished its current instruction and released the
+    /// bus in response to a bus request.  It's never set while the cpu is held in reset
+    pub bus_ack: Signal<bool>,
+    /// The maskable interrupt input, which is level triggered and accepted while the interrupts are enabled
+    pub interrupt: Signal<bool>,
+    /// The value on the data bus when a maskable interrupt is acknowledged, which is supplied by the interrupting
+    /// device.  It's used as the instruction in mode 0, and the low byte of the vector table address in mode 2
+    pub interrupt_vector: Signal<u8>,
+}
+
+impl Default for Z80Signals {
+    fn default() -> Self {
+        Self {
+            reset: Signal::default(),
+            bus_request: Signal::default(),
+            bus_ack: Signal::default(),
+            interrupt: Signal::default(),
+            // The data bus floats high when no device supplies a vector
+            interrupt_vector: Signal::new(0xFF),
+        }
+    }
 }
 
 #[derive(Clone, Debug, thiserror::Error)]
@@ -124,6 +147,54 @@ pub enum Z80AddressSpace {
     IO(Z80IOAddress),
 }
 
+/// The kind of machine cycle that accessed the bus
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Z80BusCycleKind {
+    /// The M1 cycle, which reads an opcode or opcode prefix and then refreshes the dynamic memory
+    OpcodeFetch,
+    MemoryRead,
+    MemoryWrite,
+    IoRead,
+    IoWrite,
+}
+
+impl Z80BusCycleKind {
+    /// Returns the number of clock cycles (T states) the machine cycle takes without any wait states
+    pub fn clocks(&self) -> u16 {
+        match self {
+            Z80BusCycleKind::OpcodeFetch => 4,
+            Z80BusCycleKind::MemoryRead | Z80BusCycleKind::MemoryWrite => 3,
+            Z80BusCycleKind::IoRead | Z80BusCycleKind::IoWrite => 4,
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        matches!(self, Z80BusCycleKind::MemoryWrite | Z80BusCycleKind::IoWrite)
+    }
+
+    pub fn is_io(&self) -> bool {
+        matches!(self, Z80BusCycleKind::IoRead | Z80BusCycleKind::IoWrite)
+    }
+}
+
+/// A bus transaction made by one of the machine cycles of an instruction, in the order they occurred
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Z80BusCycle {
+    pub kind: Z80BusCycleKind,
+    pub addr: u16,
+    pub data: u8,
+}
+
+impl Z80BusCycle {
+    pub fn new(kind: Z80BusCycleKind, addr: u16, data: u8) -> Self {
+        Self {
+            kind,
+            addr,
+            data,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Z80<Instant> {
     pub cputype: Z80Type,
@@ -151,7 +222,7 @@ where
 
     pub fn from_type(cputype: Z80Type, frequency: Frequency) -> Self {
         match cputype {
-            Z80Type::Z80 => Self::new(cputype, frequency),
+            Z80Type::Z80 | Z80Type::I8080 => Self::new(cputype, frequency),
         }
     }
 
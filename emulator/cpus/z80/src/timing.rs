@@ -276,3 +276,41 @@ impl Z80InstructionCycles {
         Ok(Z80InstructionCycles::Single(cycles + extra))
     }
 }
+
+/// The number of clock cycles each opcode of the Intel 8080 takes, which is the time taken when a conditional
+/// call or return isn't taken
+#[rustfmt::skip]
+const I8080_CYCLES: [u8; 256] = [
+    4, 10,  7,  5,  5,  5,  7,  4,  4, 10,  7,  5,  5,  5,  7,  4,
+    4, 10,  7,  5,  5,  5,  7,  4,  4, 10,  7,  5,  5,  5,  7,  4,
+    4, 10, 16,  5,  5,  5,  7,  4,  4, 10, 16,  5,  5,  5,  7,  4,
+    4, 10, 13,  5, 10, 10, 10,  4,  4, 10, 13,  5,  5,  5,  7,  4,
+    5,  5,  5,  5,  5,  5,  7,  5,  5,  5,  5,  5,  5,  5,  7,  5,
+    5,  5,  5,  5,  5,  5,  7,  5,  5,  5,  5,  5,  5,  5,  7,  5,
+    5,  5,  5,  5,  5,  5,  7,  5,  5,  5,  5,  5,  5,  5,  7,  5,
+    7,  7,  7,  7,  7,  7,  7,  7,  5,  5,  5,  5,  5,  5,  7,  5,
+    4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,
+    4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,
+    4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,
+    4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,
+    5, 10, 10, 10, 11, 11,  7, 11,  5, 10, 10, 10, 11, 17,  7, 11,
+    5, 10, 10, 10, 11, 11,  7, 11,  5, 10, 10, 10, 11, 17,  7, 11,
+    5, 10, 10, 18, 11, 11,  7, 11,  5,  5, 10,  4, 11, 17,  7, 11,
+    5, 10, 10,  4, 11, 11,  7, 11,  5,  5, 10,  4, 11, 17,  7, 11,
+];
+
+impl Z80InstructionCycles {
+    /// Returns the cycles taken by an instruction on the Intel 8080, which only uses the first byte, since
+    /// the 8080 doesn't have any prefixed instructions
+    pub fn from_i8080_opcode(opcode: u8) -> Z80InstructionCycles {
+        let cycles = I8080_CYCLES[opcode as usize] as u16;
+        match opcode & 0xC7 {
+            // Conditional returns and calls take 6 extra cycles when taken
+            0xC0 | 0xC4 => Z80InstructionCycles::Branch {
+                taken: cycles + 6,
+                not_taken: cycles,
+            },
+            _ => Z80InstructionCycles::Single(cycles),
+        }
+    }
+}
@@ -3,9 +3,11 @@ use femtos::{Instant, Frequency};
 use emulator_hal::{BusAccess, Step, NoBus};
 use emulator_hal_memory::MemoryBlock;
 
-use moa_z80::{Z80, Z80Type, Z80Port, Instruction, LoadTarget, Target, Register, RegisterPair, IndexRegister, IndexRegisterHalf};
+use moa_z80::{
+    Z80, Z80Type, Z80Port, Z80Assembler, Instruction, LoadTarget, Target, Register, RegisterPair, IndexRegister, IndexRegisterHalf,
+};
 
-fn init_decode_test() -> (Z80<Instant>, MemoryBlock<Instant>) {
+fn init_decode_test(cputype: Z80Type) -> (Z80<Instant>, MemoryBlock<Instant>) {
     // Insert basic initialization
     let len = 0x10_0000;
     let mut data = Vec::with_capacity(len);
@@ -16,7 +18,7 @@ fn init_decode_test() -> (Z80<Instant>, MemoryBlock<Instant>) {
     let mut io = NoBus::new();
 
     // Initialize the CPU and make sure it's in the expected state
-    let mut cpu = Z80::new(Z80Type::Z80, Frequency::from_mhz(4));
+    let mut cpu = Z80::new(cputype, Frequency::from_mhz(4));
     let mut bus = Z80Port::new(&mut memory, &mut io);
     cpu.reset(Instant::START, &mut bus).unwrap();
     cpu.step(Instant::START, &mut bus).unwrap();
@@ -30,8 +32,8 @@ fn load_memory(memory: &mut MemoryBlock<Instant>, data: &[u8]) {
     }
 }
 
-fn run_decode_test(data: &[u8]) -> Instruction {
-    let (mut cpu, mut memory) = init_decode_test();
+fn run_decode_test(cputype: Z80Type, data: &[u8]) -> Instruction {
+    let (mut cpu, mut memory) = init_decode_test(cputype);
     load_memory(&mut memory, data);
     let mut io = NoBus::new();
     let mut bus = Z80Port::new(&mut memory, &mut io);
@@ -44,7 +46,7 @@ fn run_all_decode_tests() {
     let mut failures = vec![];
 
     for (data, expected_instruction) in DECODE_TESTS {
-        let instruction = run_decode_test(data);
+        let instruction = run_decode_test(Z80Type::Z80, data);
         if instruction != *expected_instruction {
             failures.push((data, instruction, expected_instruction));
         }
@@ -76,3 +78,114 @@ const DECODE_TESTS: &'static [(&[u8], Instruction)] = &[
     (&[0xDD, 0x84],         Instruction::ADDa(Target::DirectRegHalf(IndexRegisterHalf::IXH))),
     (&[0xDD, 0x85],         Instruction::ADDa(Target::DirectRegHalf(IndexRegisterHalf::IXL))),
 ];
+
+#[test]
+fn run_i8080_decode_tests() {
+    for (data, expected_instruction) in I8080_DECODE_TESTS {
+        let instruction = run_decode_test(Z80Type::I8080, data);
+        assert_eq!(instruction, *expected_instruction, "for {:?}", data);
+    }
+}
+
+#[rustfmt::skip]
+const I8080_DECODE_TESTS: &'static [(&[u8], Instruction)] = &[
+    (&[0x08],               Instruction::NOP),
+    (&[0x10, 0x12],         Instruction::NOP),
+    (&[0x38, 0x12],         Instruction::NOP),
+    (&[0xCB, 0x34, 0x12],   Instruction::JP(0x1234)),
+    (&[0xD9],               Instruction::RET),
+    (&[0xDD, 0x34, 0x12],   Instruction::CALL(0x1234)),
+    (&[0xED, 0x34, 0x12],   Instruction::CALL(0x1234)),
+    (&[0xFD, 0x34, 0x12],   Instruction::CALL(0x1234)),
+];
+
+#[test]
+fn run_assembler_tests() {
+    for (text, expected_data) in ASSEMBLER_TESTS {
+        let mut assembler = Z80Assembler::new();
+        let data = assembler.assemble(text).unwrap();
+        assert_eq!(data, *expected_data, "for {:?}", text);
+
+        // The opcode tables of the decoder and the assembler are independent, so they can be used to check each other
+        if let Some((_, expected_instruction)) = DECODE_TESTS.iter().find(|(data, _)| *data == expected_data) {
+            assert_eq!(run_decode_test(Z80Type::Z80, &data), *expected_instruction, "for {:?}", text);
+        }
+    }
+}
+
+#[rustfmt::skip]
+const ASSEMBLER_TESTS: &'static [(&str, &[u8])] = &[
+    ("nop",                 &[0x00]),
+    ("ld bc, 0x0201",       &[0x01, 0x01, 0x02]),
+    ("ld (bc), a",          &[0x02]),
+    ("inc bc",              &[0x03]),
+    ("inc b",               &[0x04]),
+    ("dec b",               &[0x05]),
+    ("add ix, bc",          &[0xDD, 0x09]),
+    ("ld b, ixh",           &[0xDD, 0x44]),
+    ("ld h, (ix+0x12)",     &[0xDD, 0x66, 0x12]),
+    ("ld l, (ix+0x12)",     &[0xDD, 0x6E, 0x12]),
+    ("add a, ixh",          &[0xDD, 0x84]),
+    ("add ixl",             &[0xDD, 0x85]),
+    ("ld (iy-2), 0x55",     &[0xFD, 0x36, 0xFE, 0x55]),
+    ("ld a, (ix+1+2)",      &[0xDD, 0x7E, 0x03]),
+    ("ld a, -1",            &[0x3E, 0xFF]),
+    ("cp 'A'",              &[0xFE, 0x41]),
+    ("ld a, (hl)",          &[0x7E]),
+    ("ld sp, ix",           &[0xDD, 0xF9]),
+    ("ld de, (0x1234)",     &[0xED, 0x5B, 0x34, 0x12]),
+    ("ld (0x1234), hl",     &[0x22, 0x34, 0x12]),
+    ("ld a, i",             &[0xED, 0x57]),
+    ("sbc hl, de",          &[0xED, 0x52]),
+    ("cp 0x10",             &[0xFE, 0x10]),
+    ("ex af, af'",          &[0x08]),
+    ("ex (sp), iy",         &[0xFD, 0xE3]),
+    ("push af",             &[0xF5]),
+    ("pop ix",              &[0xDD, 0xE1]),
+    ("bit 7, (ix+1)",       &[0xDD, 0xCB, 0x01, 0x7E]),
+    ("set 0, a",            &[0xCB, 0xC7]),
+    ("srl (hl)",            &[0xCB, 0x3E]),
+    ("jp nz, 0x1234",       &[0xC2, 0x34, 0x12]),
+    ("jp (hl)",             &[0xE9]),
+    ("ret pe",              &[0xE8]),
+    ("rst 0x38",            &[0xFF]),
+    ("im 1",                &[0xED, 0x56]),
+    ("in a, (0x10)",        &[0xDB, 0x10]),
+    ("out (c), e",          &[0xED, 0x59]),
+    ("ldir",                &[0xED, 0xB0]),
+];
+
+#[test]
+fn run_assembler_labels_test() {
+    let text = "
+        .equ PORT, 0x10
+        .org 0x100
+    start:
+        ld b, 4             ; loop counter
+    loop:
+        in a, (PORT)
+        djnz loop
+        jr c, start
+        call done
+    done:
+        ret
+        .word start, done - start
+    ";
+
+    let mut assembler = Z80Assembler::new();
+    let data = assembler.assemble(text).unwrap();
+    assert_eq!(assembler.origin(), 0x100);
+
+    #[rustfmt::skip]
+    let expected = [
+        0x06, 0x04,
+        0xDB, 0x10,
+        0x10, 0xFC,
+        0x38, 0xF8,
+        0xCD, 0x0B, 0x01,
+        0xC9,
+        0x00, 0x01,
+        0x0B, 0x00,
+    ];
+    assert_eq!(data, expected);
+}
@@ -1,4 +1,5 @@
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
 use femtos::{Instant, Duration};
 
 use moa_host::{Audio, Sample, AudioFrame, ClockedQueue};
@@ -6,6 +7,9 @@ use moa_host::{Audio, Sample, AudioFrame, ClockedQueue};
 
 pub const SAMPLE_RATE: usize = 48000;
 
+/// The level above which the mixed output is gradually compressed instead of being clamped
+const SOFT_CLIP_THRESHOLD: f32 = 0.8;
+
 pub struct AudioSource {
     id: usize,
     sample_rate: usize,
@@ -60,10 +64,16 @@ impl Audio for AudioSource {
 #[derive(Clone)]
 pub struct AudioMixer(Arc<Mutex<AudioMixerInner>>);
 
+struct MixerSource {
+    queue: ClockedQueue<AudioFrame>,
+    gain: f32,
+}
+
 pub struct AudioMixerInner {
     sample_rate: usize,
-    sources: Vec<ClockedQueue<AudioFrame>>,
+    sources: Vec<MixerSource>,
     output: AudioOutput,
+    assembled_until: Instant,
 }
 
 impl AudioMixer {
@@ -72,6 +82,7 @@ impl AudioMixer {
             sample_rate,
             sources: vec![],
             output: AudioOutput::default(),
+            assembled_until: Instant::START,
         })))
     }
 
@@ -82,14 +93,38 @@ impl AudioMixer {
     pub fn borrow_mut(&self) -> MutexGuard<'_, AudioMixerInner> {
         self.0.lock().unwrap()
     }
+
+    /// Add the mixer to the system, to be stepped after the devices that it mixes, which might be stepping on
+    /// threads of their own
+    pub fn add_to_system(&self, system: &mut System) -> Result<(), Error> {
+        let device = Device::new(self.clone());
+        system.add_device("mixer", device.clone())?;
+        system.set_step_priority(&device, StepPriority::OUTPUT)
+    }
 }
 
 impl AudioMixerInner {
     pub fn add_source(&mut self, source: ClockedQueue<AudioFrame>) -> usize {
-        self.sources.push(source);
+        self.sources.push(MixerSource {
+            queue: source,
+            gain: 1.0,
+        });
         self.sources.len() - 1
     }
 
+    /// Set the volume of a source relative to the others, where 1.0 is the default
+    pub fn set_source_gain(&mut self, id: usize, gain: f32) {
+        if let Some(source) = self.sources.get_mut(id) {
+            source.gain = gain;
+        }
+    }
+
+    /// Add noise to the samples when they're converted to an integer output format, to avoid the distortion of
+    /// quiet sounds caused by rounding
+    pub fn set_dither(&mut self, dither: bool) {
+        self.output.dither.store(dither, Ordering::Relaxed);
+    }
+
     pub fn num_sources(&self) -> usize {
         self.sources.len()
     }
@@ -107,45 +142,107 @@ impl AudioMixerInner {
     }
 
     fn assemble_frame(&mut self, frame_start: Instant, frame_duration: Duration) {
+        // If the system was rolled back to an earlier state, such as when running ahead, the audio for the time
+        // that's run again has already been output, so the samples for it are dropped instead of played twice
+        let frame_end = frame_start + frame_duration;
+        if frame_start < self.assembled_until {
+            for source in &self.sources {
+                while source.queue.peek_clock().map(|clock| clock < frame_end).unwrap_or(false) {
+                    source.queue.pop_next();
+                }
+            }
+            return;
+        }
+        self.assembled_until = frame_end;
+
         let sample_duration = self.sample_duration();
         let samples = (frame_duration / sample_duration) as usize;
 
-        let mut data = vec![Sample(0.0, 0.0); samples];
-
+        // The sources are accumulated at a higher precision, and then scaled down so that the total of several
+        // sources at a typical level fits without clipping.  Scaling by the square root of the number of sources
+        // instead of the number itself keeps a single loud source from being made too quiet
+        let mut mix = vec![(0.0f64, 0.0f64); samples];
         for source in &self.sources {
             let mut index = 0;
-            while index < data.len() {
-                if let Some((clock, mut frame)) = source.pop_next() {
+            while index < mix.len() {
+                if let Some((clock, mut frame)) = source.queue.pop_next() {
                     index = (clock.duration_since(frame_start) / sample_duration) as usize;
-                    let size = frame.data.len().min(data.len() - index);
+                    let size = frame.data.len().min(mix.len() - index);
                     frame
                         .data
                         .iter()
-                        .zip(&mut data[index..index + size])
-                        .for_each(|(source, dest)| {
-                            dest.0 += source.0;
-                            dest.1 += source.1;
+                        .zip(&mut mix[index..index + size])
+                        .for_each(|(sample, dest)| {
+                            dest.0 += (sample.0 * source.gain) as f64;
+                            dest.1 += (sample.1 * source.gain) as f64;
                         });
                     index += size;
                     if size < frame.data.len() {
                         frame.data.drain(0..size);
-                        source.put_back(clock, frame);
+                        source.queue.put_back(clock, frame);
                     }
                 }
             }
         }
 
-        // Average each sample, and clamp it to the 1 to -1 range
-        for sample in data.iter_mut() {
-            sample.0 = (sample.0 / self.sources.len() as f32).clamp(-1.0, 1.0);
-            sample.1 = (sample.1 / self.sources.len() as f32).clamp(-1.0, 1.0);
-        }
+        let headroom = 1.0 / (self.sources.len().max(1) as f64).sqrt();
+        let data = mix
+            .iter()
+            .map(|(left, right)| Sample(soft_clip((left * headroom) as f32), soft_clip((right * headroom) as f32)))
+            .collect();
 
         self.output.add_frame(frame_start, AudioFrame::new(self.sample_rate, data));
     }
 }
 
-use moa_core::{Transmutable, Steppable, Error, System};
+/// Limit a sample to the 1 to -1 range, by compressing the levels above the threshold so that loud peaks are
+/// rounded off rather than cut off
+fn soft_clip(value: f32) -> f32 {
+    let level = value.abs();
+    if level <= SOFT_CLIP_THRESHOLD {
+        value
+    } else {
+        let range = 1.0 - SOFT_CLIP_THRESHOLD;
+        let level = SOFT_CLIP_THRESHOLD + range * ((level - SOFT_CLIP_THRESHOLD) / range).tanh();
+        level.copysign(value)
+    }
+}
+
+/// Triangular noise of up to one step of the output format, which is added before a sample is rounded
+pub struct Dither {
+    state: u32,
+}
+
+impl Default for Dither {
+    fn default() -> Self {
+        Self {
+            state: 0x2545_F491,
+        }
+    }
+}
+
+impl Dither {
+    // A xorshift generator is random enough for noise, and can run in the audio callback without locking
+    fn next_random(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state as f32 / u32::MAX as f32
+    }
+
+    fn next(&mut self) -> f32 {
+        self.next_random() - self.next_random()
+    }
+
+    /// Convert a sample to a signed 16-bit value, with dithering if it's enabled
+    pub fn convert_i16(&mut self, value: f32, enabled: bool) -> i16 {
+        let scaled = value * i16::MAX as f32;
+        let noise = if enabled { self.next() } else { 0.0 };
+        (scaled + noise).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+    }
+}
+
+use moa_core::{Transmutable, Steppable, Error, System, Device, StepPriority};
 
 impl Steppable for AudioMixer {
     fn step(&mut self, system: &System) -> Result<Duration, Error> {
@@ -169,12 +266,14 @@ impl Transmutable for AudioMixer {
 #[derive(Clone)]
 pub struct AudioOutput {
     queue: ClockedQueue<AudioFrame>,
+    dither: Arc<AtomicBool>,
 }
 
 impl Default for AudioOutput {
     fn default() -> Self {
         Self {
             queue: ClockedQueue::new(5000),
+            dither: Arc::new(AtomicBool::new(false)),
         }
     }
 }
@@ -195,4 +294,38 @@ impl AudioOutput {
     pub fn is_empty(&self) -> bool {
         self.queue.is_empty()
     }
+
+    pub fn dither(&self) -> bool {
+        self.dither.load(Ordering::Relaxed)
+    }
+
+    /// Fill the buffer of interleaved stereo samples from the queued frames, converting each sample with the given
+    /// function, and leave any samples that didn't fit for the next call
+    pub fn fill_buffer<T, F>(&self, data: &mut [T], mut convert: F)
+    where
+        F: FnMut(f32) -> T,
+    {
+        let mut index = 0;
+        while index < data.len() {
+            if let Some((clock, mut frame)) = self.receive() {
+                let size = (frame.data.len() * 2).min(data.len() - index);
+                frame
+                    .data
+                    .iter()
+                    .zip(data[index..index + size].chunks_mut(2))
+                    .for_each(|(sample, location)| {
+                        location[0] = convert(sample.0);
+                        location[1] = convert(sample.1);
+                    });
+                index += size;
+                if size < frame.data.len() * 2 {
+                    frame.data.drain(0..size / 2);
+                    self.put_back(clock, frame);
+                }
+            } else {
+                log::debug!("missed an audio frame");
+                break;
+            }
+        }
+    }
 }
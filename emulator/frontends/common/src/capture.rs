@@ -0,0 +1,229 @@
+//! Saving the video output as PNG screenshots, and recording it as an animated GIF or APNG
+//!
+//! A [`VideoRecorder`] receives its own copy of every frame by teeing the frontend's [`FrameReceiver`], so it
+//! can be updated whenever the display is, without the display missing any frames.  The delay of each frame
+//! is taken from the simulated time between frames, so the recording plays at the speed of the machine
+//! even if the simulation was running slower or faster than real time.
+
+use std::path::Path;
+use std::fs::File;
+use std::io::BufWriter;
+
+use femtos::Instant;
+
+use moa_core::Error;
+use moa_host::{Frame, FrameReceiver, PixelEncoding};
+
+
+/// The shortest delay between GIF frames, since most viewers play anything shorter at 10 frames per second
+const GIF_MIN_DELAY_CS: u64 = 2;
+
+/// How much effort to put into choosing the palette of each GIF frame, from 1 (best) to 30 (fastest)
+const GIF_QUANTIZE_SPEED: i32 = 10;
+
+/// The delay of the last frame, which has no following frame to measure it against
+const LAST_FRAME_DELAY_MS: u64 = 17;
+
+
+/// The format of a recording, which is chosen by the extension of its filename
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VideoFormat {
+    Gif,
+    Apng,
+}
+
+impl VideoFormat {
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        let extension = Path::new(filename).extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "gif" => Some(VideoFormat::Gif),
+            "png" | "apng" => Some(VideoFormat::Apng),
+            _ => None,
+        }
+    }
+}
+
+/// Save the frame as a PNG image
+pub fn save_screenshot(frame: &Frame, filename: &str) -> Result<(), Error> {
+    let error = |err: &dyn std::fmt::Display| Error::new(format!("error saving screenshot {}: {}", filename, err));
+
+    let file = File::create(filename).map_err(|err| error(&err))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), frame.width, frame.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|err| error(&err))?;
+    writer
+        .write_image_data(&frame_to_rgba(frame, frame.width, frame.height))
+        .map_err(|err| error(&err))?;
+    writer.finish().map_err(|err| error(&err))
+}
+
+/// Returns the first filename of the form `{prefix}-{n}.png` which doesn't already exist
+pub fn next_screenshot_filename(prefix: &str) -> String {
+    (1..)
+        .map(|i| format!("{}-{}.png", prefix, i))
+        .find(|filename| !Path::new(filename).exists())
+        .unwrap()
+}
+
+enum RecorderOutput {
+    Gif(gif::Encoder<BufWriter<File>>),
+    /// The APNG header includes the number of frames, so the frames and their delays in milliseconds are
+    /// kept until the recording is finished
+    Apng(Vec<(Vec<u8>, u64)>),
+}
+
+/// Records every frame sent to a video source into an animated GIF or APNG file
+pub struct VideoRecorder {
+    filename: String,
+    receiver: FrameReceiver,
+    size: (u32, u32),
+    crop: Option<(u32, u32, u32, u32)>,
+    output: RecorderOutput,
+    start: Option<Instant>,
+    /// The frame waiting for the next one to arrive, so its delay can be known, and when it arrived
+    pending: Option<(Instant, Vec<u8>)>,
+    /// The total of the delays of the frames written so far, in the output format's units
+    written: u64,
+}
+
+impl VideoRecorder {
+    /// Start recording the frames from the given video source into the file, where the format is chosen
+    /// by the file's extension
+    pub fn new(receiver: &FrameReceiver, filename: &str) -> Result<Self, Error> {
+        Self::with_crop(receiver, filename, None)
+    }
+
+    /// Start recording like [`VideoRecorder::new`], with the given number of pixels removed from the left,
+    /// top, right, and bottom edges of each frame
+    pub fn with_crop(receiver: &FrameReceiver, filename: &str, crop: Option<(u32, u32, u32, u32)>) -> Result<Self, Error> {
+        let format = VideoFormat::from_filename(filename)
+            .ok_or_else(|| Error::new(format!("{}: recordings must be a .gif, .png, or .apng file", filename)))?;
+
+        let (mut width, mut height) = receiver.max_size();
+        if let Some((left, top, right, bottom)) = crop {
+            width = width.saturating_sub(left + right).max(1);
+            height = height.saturating_sub(top + bottom).max(1);
+        }
+
+        let output = match format {
+            VideoFormat::Gif => {
+                let error = |err: &dyn std::fmt::Display| Error::new(format!("error recording to {}: {}", filename, err));
+                let file = File::create(filename).map_err(|err| error(&err))?;
+                let mut encoder =
+                    gif::Encoder::new(BufWriter::new(file), width as u16, height as u16, &[]).map_err(|err| error(&err))?;
+                encoder.set_repeat(gif::Repeat::Infinite).map_err(|err| error(&err))?;
+                RecorderOutput::Gif(encoder)
+            },
+            VideoFormat::Apng => RecorderOutput::Apng(vec![]),
+        };
+
+        Ok(Self {
+            filename: filename.to_string(),
+            receiver: receiver.tee(),
+            size: (width, height),
+            crop,
+            output,
+            start: None,
+            pending: None,
+            written: 0,
+        })
+    }
+
+    /// Add any frames that have been sent since the last update to the recording
+    pub fn update(&mut self) -> Result<(), Error> {
+        while let Some((clock, mut frame)) = self.receiver.next() {
+            if let Some((left, top, right, bottom)) = self.crop {
+                frame.crop(left, top, right, bottom);
+            }
+            let rgba = frame_to_rgba(&frame, self.size.0, self.size.1);
+            let start = *self.start.get_or_insert(clock);
+
+            let elapsed_ms = clock.duration_since(start).as_nanos() as f64 / 1_000_000.0;
+            if let Some((_, previous)) = self.pending.take() {
+                let delay = match self.output {
+                    RecorderOutput::Gif(_) => (elapsed_ms / 10.0).round() as u64 - self.written,
+                    RecorderOutput::Apng(_) => elapsed_ms.round() as u64 - self.written,
+                };
+
+                // GIF delays are in hundredths of a second, so frames that come too quickly are dropped
+                if matches!(self.output, RecorderOutput::Gif(_)) && delay < GIF_MIN_DELAY_CS {
+                    self.pending = Some((clock, previous));
+                    continue;
+                }
+                self.write_frame(previous, delay)?;
+            }
+            self.pending = Some((clock, rgba));
+        }
+        Ok(())
+    }
+
+    /// Write the last frame and finish the file
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.update()?;
+        if let Some((_, last)) = self.pending.take() {
+            let delay = match self.output {
+                RecorderOutput::Gif(_) => LAST_FRAME_DELAY_MS.div_ceil(10).max(GIF_MIN_DELAY_CS),
+                RecorderOutput::Apng(_) => LAST_FRAME_DELAY_MS,
+            };
+            self.write_frame(last, delay)?;
+        }
+
+        let error = |err: &dyn std::fmt::Display| Error::new(format!("error recording to {}: {}", self.filename, err));
+        match self.output {
+            RecorderOutput::Gif(encoder) => {
+                encoder.into_inner().map_err(|err| error(&err))?;
+            },
+            RecorderOutput::Apng(frames) => {
+                if frames.is_empty() {
+                    return Err(Error::new(format!("{}: no frames were recorded", self.filename)));
+                }
+                let file = File::create(&self.filename).map_err(|err| error(&err))?;
+                let mut encoder = png::Encoder::new(BufWriter::new(file), self.size.0, self.size.1);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_animated(frames.len() as u32, 0).map_err(|err| error(&err))?;
+                let mut writer = encoder.write_header().map_err(|err| error(&err))?;
+                for (data, delay) in frames {
+                    writer.set_frame_delay(delay as u16, 1000).map_err(|err| error(&err))?;
+                    writer.write_image_data(&data).map_err(|err| error(&err))?;
+                }
+                writer.finish().map_err(|err| error(&err))?;
+            },
+        }
+        Ok(())
+    }
+
+    fn write_frame(&mut self, mut data: Vec<u8>, delay: u64) -> Result<(), Error> {
+        self.written += delay;
+        match &mut self.output {
+            RecorderOutput::Gif(encoder) => {
+                let mut frame = gif::Frame::from_rgba_speed(self.size.0 as u16, self.size.1 as u16, &mut data, GIF_QUANTIZE_SPEED);
+                frame.delay = delay as u16;
+                encoder
+                    .write_frame(&frame)
+                    .map_err(|err| Error::new(format!("error recording to {}: {}", self.filename, err)))?;
+            },
+            RecorderOutput::Apng(frames) => frames.push((data, delay)),
+        }
+        Ok(())
+    }
+}
+
+/// Returns the pixels of the frame as opaque 8-bit RGBA values, cut off or padded with black to the given size
+fn frame_to_rgba(frame: &Frame, width: u32, height: u32) -> Vec<u8> {
+    let mut data = vec![0; (width * height * 4) as usize];
+    for y in 0..height.min(frame.height) {
+        for x in 0..width.min(frame.width) {
+            let pixel = frame.bitmap[(x + y * frame.width) as usize];
+            let (r, g, b) = match frame.encoding {
+                PixelEncoding::RGBA => (pixel >> 24, pixel >> 16, pixel >> 8),
+                PixelEncoding::ARGB => (pixel >> 16, pixel >> 8, pixel),
+                PixelEncoding::ABGR => (pixel, pixel >> 8, pixel >> 16),
+            };
+            let i = ((x + y * width) * 4) as usize;
+            data[i..i + 4].copy_from_slice(&[r as u8, g as u8, b as u8, 0xFF]);
+        }
+    }
+    data
+}
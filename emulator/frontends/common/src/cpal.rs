@@ -3,7 +3,7 @@ use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
 };
 
-use crate::audio::{AudioOutput, SAMPLE_RATE};
+use crate::audio::{AudioOutput, Dither, SAMPLE_RATE};
 
 #[allow(dead_code)]
 pub struct CpalAudioOutput {
@@ -16,49 +16,45 @@ impl CpalAudioOutput {
             .default_output_device()
             .expect("No sound output device available");
 
-        let config: StreamConfig = device
+        // Floating point output is used if possible, and otherwise the samples are converted to 16-bit
+        let config = device
             .supported_output_configs()
             .expect("error while querying configs")
-            .find(|config| config.sample_format() == SampleFormat::F32 && config.channels() == 2)
+            .filter(|config| config.channels() == 2)
+            .filter(|config| matches!(config.sample_format(), SampleFormat::F32 | SampleFormat::I16))
+            .max_by_key(|config| config.sample_format() == SampleFormat::F32)
             .expect("no supported config?!")
-            .with_sample_rate(SampleRate(SAMPLE_RATE as u32))
-            .into();
+            .with_sample_rate(SampleRate(SAMPLE_RATE as u32));
+        let format = config.sample_format();
+        let config: StreamConfig = config.into();
 
-        let data_callback = move |data: &mut [f32], _info: &OutputCallbackInfo| {
-            let mut index = 0;
-            while index < data.len() {
-                if let Some((clock, mut frame)) = output.receive() {
-                    let size = (frame.data.len() * 2).min(data.len() - index);
-                    frame
-                        .data
-                        .iter()
-                        .zip(data[index..index + size].chunks_mut(2))
-                        .for_each(|(sample, location)| {
-                            location[0] = sample.0;
-                            location[1] = sample.1;
-                        });
-                    index += size;
-                    if size < frame.data.len() * 2 {
-                        frame.data.drain(0..size / 2);
-                        output.put_back(clock, frame);
-                    }
-                } else {
-                    log::debug!("missed an audio frame");
-                    break;
-                }
-            }
+        let error_callback = move |err| {
+            log::error!("ERROR: {:?}", err);
         };
 
-        let stream = device
-            .build_output_stream(
+        let stream = match format {
+            SampleFormat::I16 => {
+                let mut dither = Dither::default();
+                device.build_output_stream(
+                    &config,
+                    move |data: &mut [i16], _info: &OutputCallbackInfo| {
+                        let enabled = output.dither();
+                        output.fill_buffer(data, |sample| dither.convert_i16(sample, enabled));
+                    },
+                    error_callback,
+                    None,
+                )
+            },
+            _ => device.build_output_stream(
                 &config,
-                data_callback,
-                move |err| {
-                    log::error!("ERROR: {:?}", err);
+                move |data: &mut [f32], _info: &OutputCallbackInfo| {
+                    output.fill_buffer(data, |sample| sample);
                 },
+                error_callback,
                 None,
-            )
-            .unwrap();
+            ),
+        }
+        .unwrap();
 
         stream.play().unwrap();
 
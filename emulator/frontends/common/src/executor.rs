@@ -0,0 +1,457 @@
+use std::mem;
+use std::time::Duration;
+use std::collections::VecDeque;
+use instant::Instant;
+use femtos::{Duration as FemtosDuration, Instant as FemtosInstant};
+
+use moa_core::{System, SystemState, Error, MachineInfo};
+
+
+/// The slowest and fastest speeds that the speed hotkeys will adjust the simulation to
+pub const MIN_SPEED: f32 = 1.0 / 64.0;
+pub const MAX_SPEED: f32 = 64.0;
+
+/// The fraction of the normal speed that the machine runs at while slow motion is held
+pub const SLOW_MOTION_SPEED: f32 = 0.25;
+
+/// The most host time that's simulated in one update, so that after the host stalls (eg. while the window is
+/// being dragged, or the debugger is open), the machine doesn't try to catch up all at once
+const MAX_ELAPSED: Duration = Duration::from_millis(250);
+
+/// The shortest time spent running frames in each update while fast forwarding, even if the frontend updates
+/// more often than that
+const MIN_FAST_FORWARD_TIME: Duration = Duration::from_millis(10);
+
+/// The frame rate used for machines that don't give their refresh rate
+const DEFAULT_REFRESH_HZ: u64 = 60;
+
+
+/// Paces the simulation against the host's time, independently of how often the frontend updates, with
+/// controls to pause it, advance it one frame at a time, fast forward it as fast as the host can run it, or
+/// run it in slow motion
+///
+/// The frontend calls `update` each time around its loop, which runs the system for the host time that passed
+/// since the last update, multiplied by the speed.  While paused, the system only runs when a frame is
+/// advanced with `step_frame`, which runs it for the length of one of the machine's frames.
+///
+/// With run-ahead, the system is kept some frames ahead of the time that's due, so that the effect of an input
+/// shows up that many frames sooner.  The state of the system is saved at each frame, and when the input changes,
+/// it goes back to the state at the time that's due, and runs the frames ahead again with the new input.  The
+/// audio for the frames that are run again has already been given to the `AudioMixer`, which drops the samples
+/// for any time it's already assembled, so the audio isn't played twice, but it's the audio from before the input
+/// changed.
+pub struct Executor {
+    speed: f32,
+    paused: bool,
+    fast_forward: bool,
+    slow_motion: bool,
+    frames_to_step: usize,
+    frame_duration: Duration,
+    time_limit: Option<FemtosDuration>,
+    last_update: Option<Instant>,
+    run_ahead: usize,
+    input_changed: bool,
+    real_clock: Option<FemtosInstant>,
+    saved_states: VecDeque<SystemState>,
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            paused: false,
+            fast_forward: false,
+            slow_motion: false,
+            frames_to_step: 0,
+            frame_duration: Duration::from_nanos(1_000_000_000 / DEFAULT_REFRESH_HZ),
+            time_limit: None,
+            last_update: None,
+            run_ahead: 0,
+            input_changed: false,
+            real_clock: None,
+            saved_states: VecDeque::new(),
+        }
+    }
+}
+
+impl Executor {
+    /// Create an executor whose frames are the length of the machine's frames
+    pub fn for_machine(machine_info: &MachineInfo) -> Self {
+        let mut executor = Self::default();
+        if let Some(rate) = machine_info.refresh_rate.filter(|rate| rate.as_hz() > 0) {
+            executor.frame_duration = Duration::from_nanos(1_000_000_000 / rate.as_hz() as u64);
+        }
+        executor
+    }
+
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.set_speed(speed);
+        self
+    }
+
+    /// Start with the system paused, until it's resumed or a frame is advanced
+    pub fn with_paused(mut self, paused: bool) -> Self {
+        self.paused = paused;
+        self
+    }
+
+    /// Never run the system past the given simulated time, such as for a timeout
+    pub fn with_time_limit(mut self, limit: Option<FemtosDuration>) -> Self {
+        self.time_limit = limit;
+        self
+    }
+
+    /// Run the system the given number of frames ahead of the time that's due
+    pub fn with_run_ahead(mut self, frames: usize) -> Self {
+        self.run_ahead = frames;
+        self
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    /// Multiply the speed by the given factor, and return the new speed
+    pub fn change_speed(&mut self, factor: f32) -> f32 {
+        self.set_speed(self.speed * factor);
+        self.speed
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.frames_to_step = 0;
+    }
+
+    /// Pause the system if it's running, or resume it if it's paused, and return whether it's now paused
+    pub fn toggle_pause(&mut self) -> bool {
+        self.set_paused(!self.paused);
+        self.paused
+    }
+
+    /// Pause the system, and run it for one frame on the next update
+    pub fn step_frame(&mut self) {
+        self.paused = true;
+        self.frames_to_step += 1;
+    }
+
+    pub fn is_fast_forward(&self) -> bool {
+        self.fast_forward
+    }
+
+    /// Run the system as fast as the host can, instead of at its speed, until this is cleared
+    pub fn set_fast_forward(&mut self, fast_forward: bool) {
+        self.fast_forward = fast_forward;
+    }
+
+    pub fn is_slow_motion(&self) -> bool {
+        self.slow_motion
+    }
+
+    /// Run the system at a quarter of its speed, until this is cleared
+    pub fn set_slow_motion(&mut self, slow_motion: bool) {
+        self.slow_motion = slow_motion;
+    }
+
+    pub fn run_ahead(&self) -> usize {
+        self.run_ahead
+    }
+
+    pub fn set_run_ahead(&mut self, frames: usize) {
+        self.run_ahead = frames;
+    }
+
+    /// Note that the input to the system has changed, so that when running ahead, the frames that were already
+    /// run ahead are run again with the new input on the next update
+    pub fn input_changed(&mut self) {
+        self.input_changed = true;
+    }
+
+    /// Forget the time of the last update, so that the time until the next one isn't simulated, such as after
+    /// stopping in the debugger
+    pub fn reset(&mut self) {
+        self.last_update = None;
+        self.stop_running_ahead();
+    }
+
+    /// Returns the speed that the system is currently run at, including slow motion
+    pub fn effective_speed(&self) -> f32 {
+        if self.slow_motion {
+            self.speed * SLOW_MOTION_SPEED
+        } else {
+            self.speed
+        }
+    }
+
+    /// Run the system for the time that's due since the last update, and return the amount of simulated time
+    /// that it was run for
+    ///
+    /// If the system stops early, such as at a breakpoint, the error is returned, and the rest of the time is
+    /// dropped rather than being run on the next update.
+    pub fn update(&mut self, system: &mut System) -> Result<Duration, Error> {
+        let now = Instant::now();
+        let elapsed = self
+            .last_update
+            .map(|last| now.duration_since(last))
+            .unwrap_or(Duration::ZERO)
+            .min(MAX_ELAPSED);
+        self.last_update = Some(now);
+
+        if self.frames_to_step > 0 {
+            self.stop_running_ahead();
+            self.frames_to_step -= 1;
+            return self.run_for(system, self.frame_duration);
+        }
+        if self.paused {
+            return Ok(Duration::ZERO);
+        }
+
+        if self.fast_forward {
+            self.stop_running_ahead();
+            // Run whole frames until the host time since the last update has been used up, so that the frontend
+            // still gets to draw and check for input about as often as it would otherwise
+            let budget = elapsed.max(MIN_FAST_FORWARD_TIME);
+            let mut total = Duration::ZERO;
+            while now.elapsed() < budget {
+                let ran = self.run_for(system, self.frame_duration)?;
+                if ran.is_zero() {
+                    break;
+                }
+                total += ran;
+            }
+            return Ok(total);
+        }
+
+        let duration = elapsed.mul_f32(self.effective_speed());
+        if self.run_ahead > 0 {
+            return self.run_ahead_for(system, duration);
+        }
+        self.stop_running_ahead();
+        self.run_for(system, duration)
+    }
+
+    fn run_ahead_for(&mut self, system: &mut System, duration: Duration) -> Result<Duration, Error> {
+        let real_clock = self.real_clock.unwrap_or(system.clock);
+        if mem::take(&mut self.input_changed) {
+            // Go back to the newest state that isn't past the time that's due, and discard the ones after it,
+            // which were run with the old input
+            while self.saved_states.back().is_some_and(|state| state.clock() > real_clock) {
+                self.saved_states.pop_back();
+            }
+            if let Some(state) = self.saved_states.back() {
+                system.restore_state(state);
+            }
+        }
+
+        let real_clock = real_clock + FemtosDuration::from_nanos(duration.as_nanos() as u64);
+        self.real_clock = Some(real_clock);
+
+        let frame_duration = FemtosDuration::from_nanos(self.frame_duration.as_nanos() as u64);
+        let target = real_clock + frame_duration * self.run_ahead as u32;
+        while system.clock < target {
+            let frame_due = self
+                .saved_states
+                .back()
+                .map(|state| system.clock.duration_since(state.clock()) >= frame_duration)
+                .unwrap_or(true);
+            if frame_due {
+                self.saved_states.push_back(system.save_state());
+            }
+
+            let remaining = Duration::from_nanos(target.duration_since(system.clock).as_nanos() as u64);
+            let ran = self.run_for(system, remaining.min(self.frame_duration))?;
+            if ran.is_zero() {
+                break;
+            }
+        }
+
+        // Only the newest state that isn't past the time that's due will be gone back to
+        while self.saved_states.get(1).is_some_and(|state| state.clock() <= real_clock) {
+            self.saved_states.pop_front();
+        }
+        Ok(duration)
+    }
+
+    /// Carry on from the frames that were already run ahead, instead of going back to the time that's due, since
+    /// the only difference would be the time that the last input was seen
+    fn stop_running_ahead(&mut self) {
+        self.real_clock = None;
+        self.input_changed = false;
+        self.saved_states.clear();
+    }
+
+    fn run_for(&self, system: &mut System, duration: Duration) -> Result<Duration, Error> {
+        let mut duration = FemtosDuration::from_nanos(duration.as_nanos() as u64);
+        if let Some(limit) = self.time_limit {
+            let clock = system.clock.as_duration();
+            let remaining = if clock < limit { limit - clock } else { FemtosDuration::ZERO };
+            if remaining < duration {
+                duration = remaining;
+            }
+        }
+
+        if duration != FemtosDuration::ZERO {
+            system.run_for_duration(duration)?;
+        }
+        Ok(Duration::from_nanos(duration.as_nanos() as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use femtos::Frequency;
+
+    use moa_core::{Steppable, Snapshotable, Transmutable, Device};
+
+    use super::*;
+
+    /// The length of a frame at 50 frames per second
+    const FRAME: Duration = Duration::from_millis(20);
+
+    type Log = Rc<RefCell<Vec<(FemtosInstant, u8)>>>;
+
+    /// A device that logs the input it sees at each step, like a cpu reading a controller, and that saves the log
+    /// with its state, so that the steps which are run again replace the ones that were rolled back
+    struct InputLogger {
+        input: Rc<Cell<u8>>,
+        log: Log,
+    }
+
+    impl Steppable for InputLogger {
+        fn step(&mut self, system: &System) -> Result<FemtosDuration, Error> {
+            self.log.borrow_mut().push((system.clock, self.input.get()));
+            Ok(FemtosDuration::from_millis(1))
+        }
+    }
+
+    impl Snapshotable for InputLogger {
+        fn save_state(&mut self) -> Option<Box<dyn Any>> {
+            Some(Box::new(self.log.borrow().clone()))
+        }
+
+        fn restore_state(&mut self, state: &dyn Any) {
+            if let Some(log) = state.downcast_ref::<Vec<(FemtosInstant, u8)>>() {
+                *self.log.borrow_mut() = log.clone();
+            }
+        }
+    }
+
+    impl Transmutable for InputLogger {
+        fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+            Some(self)
+        }
+
+        fn as_snapshotable(&mut self) -> Option<&mut dyn Snapshotable> {
+            Some(self)
+        }
+    }
+
+    fn build_system() -> (System, Rc<Cell<u8>>, Log) {
+        let input = Rc::new(Cell::new(0));
+        let log = Log::default();
+        let logger = InputLogger {
+            input: input.clone(),
+            log: log.clone(),
+        };
+
+        let mut system = System::default();
+        system.add_device("logger", Device::new(logger)).unwrap();
+        (system, input, log)
+    }
+
+    fn build_executor(run_ahead: usize) -> Executor {
+        let machine_info = MachineInfo::new("test").with_video(320, 240, Frequency::from_hz(50));
+        Executor::for_machine(&machine_info).with_run_ahead(run_ahead)
+    }
+
+    fn assert_states_pruned(executor: &Executor) {
+        let real_clock = executor.real_clock.unwrap();
+        assert!(executor.saved_states[0].clock() <= real_clock);
+        assert!(executor.saved_states.iter().skip(1).all(|state| state.clock() > real_clock));
+        assert!(executor.saved_states.len() <= executor.run_ahead + 2);
+    }
+
+    #[test]
+    fn run_ahead_matches_running_without_it() {
+        let (mut system, input, expected) = build_system();
+        let executor = build_executor(0);
+        for frame in 0..12 {
+            if frame == 5 {
+                input.set(1);
+            }
+            executor.run_for(&mut system, FRAME).unwrap();
+        }
+        let expected_clock = system.clock;
+
+        let (mut system, input, actual) = build_system();
+        let mut executor = build_executor(2);
+        for frame in 0..10 {
+            if frame == 5 {
+                input.set(1);
+                executor.input_changed();
+            }
+            executor.run_ahead_for(&mut system, FRAME).unwrap();
+            assert_states_pruned(&executor);
+        }
+
+        assert_eq!(system.clock, expected_clock);
+        assert_eq!(*actual.borrow(), *expected.borrow());
+    }
+
+    #[test]
+    fn run_ahead_goes_back_to_the_frame_before_the_time_thats_due() {
+        let (mut system, input, log) = build_system();
+        let mut executor = build_executor(2);
+
+        // Updates that aren't a whole number of frames leave the time that's due in the middle of a frame
+        executor.run_ahead_for(&mut system, Duration::from_millis(25)).unwrap();
+        executor.run_ahead_for(&mut system, Duration::from_millis(25)).unwrap();
+        assert_states_pruned(&executor);
+        assert_eq!(system.clock, FemtosInstant::START + FemtosDuration::from_millis(90));
+
+        input.set(1);
+        executor.input_changed();
+        executor.run_ahead_for(&mut system, Duration::ZERO).unwrap();
+        assert_eq!(system.clock, FemtosInstant::START + FemtosDuration::from_millis(90));
+
+        // The state at 40ms was saved after the step at 40ms, so the new input is seen from the step after it
+        let saved_at = FemtosInstant::START + FemtosDuration::from_millis(40);
+        let log = log.borrow();
+        assert_eq!(log.len(), 91);
+        assert!(log.windows(2).all(|steps| steps[0].0 < steps[1].0));
+        assert!(log.iter().all(|(clock, input)| *input == u8::from(*clock > saved_at)));
+    }
+
+    #[test]
+    fn stepping_or_fast_forwarding_stops_running_ahead() {
+        let (mut system, _, _) = build_system();
+        let mut executor = build_executor(2).with_time_limit(Some(FemtosDuration::from_secs(1)));
+
+        executor.run_ahead_for(&mut system, FRAME).unwrap();
+        executor.input_changed();
+        executor.step_frame();
+        executor.update(&mut system).unwrap();
+        assert!(executor.saved_states.is_empty());
+        assert!(executor.real_clock.is_none());
+        assert!(!executor.input_changed);
+
+        executor.set_paused(false);
+        executor.run_ahead_for(&mut system, FRAME).unwrap();
+        executor.input_changed();
+        executor.set_fast_forward(true);
+        executor.update(&mut system).unwrap();
+        assert!(executor.saved_states.is_empty());
+        assert!(executor.real_clock.is_none());
+        assert!(!executor.input_changed);
+    }
+}
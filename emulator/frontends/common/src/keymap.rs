@@ -0,0 +1,132 @@
+//! Mapping the host's keys to the emulated controllers and keyboard
+//!
+//! A keymap is a TOML file with a table for each emulated controller, which gives the key that presses each
+//! button.  The keys are named the same as in scripts, and a key can press buttons on more than one controller.
+//! The `keyboard` table gives the emulated key that each host key presses, for keyboard layouts other than
+//! QWERTY, or for machines with keys that the host doesn't have.  Keys that aren't in it press the same key.
+//!
+//! ```toml
+//! [controller.A]
+//! up = "Up"
+//! down = "Down"
+//! a = "A"
+//! start = "Enter"
+//!
+//! [controller.B]
+//! up = "I"
+//! down = "K"
+//!
+//! [keyboard]
+//! CapsLock = "LeftCtrl"
+//! LeftAlt = "LeftSuper"
+//! ```
+//!
+//! The buttons are `up`, `down`, `left`, `right`, `a`, `b`, `c`, `x`, `y`, `z`, `start`, and `mode`.  When no
+//! keymap is given, the default one in `keymap.toml` is used, which maps the keys for controllers A and B, and
+//! doesn't remap the keyboard.
+
+use std::fs;
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use moa_core::Error;
+use moa_host::{Key, ControllerDevice, ControllerInput, ControllerEvent};
+
+
+const DEFAULT_KEYMAP: &str = include_str!("keymap.toml");
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Button {
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+    C,
+    X,
+    Y,
+    Z,
+    Start,
+    Mode,
+}
+
+impl Button {
+    pub fn input(self, state: bool) -> ControllerInput {
+        match self {
+            Button::Up => ControllerInput::DpadUp(state),
+            Button::Down => ControllerInput::DpadDown(state),
+            Button::Left => ControllerInput::DpadLeft(state),
+            Button::Right => ControllerInput::DpadRight(state),
+            Button::A => ControllerInput::ButtonA(state),
+            Button::B => ControllerInput::ButtonB(state),
+            Button::C => ControllerInput::ButtonC(state),
+            Button::X => ControllerInput::ButtonX(state),
+            Button::Y => ControllerInput::ButtonY(state),
+            Button::Z => ControllerInput::ButtonZ(state),
+            Button::Start => ControllerInput::Start(state),
+            Button::Mode => ControllerInput::Mode(state),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyMapFile {
+    #[serde(default)]
+    controller: HashMap<ControllerDevice, HashMap<Button, Key>>,
+    #[serde(default)]
+    keyboard: HashMap<Key, Key>,
+}
+
+/// The buttons on the emulated controllers and the emulated keys that each of the host's keys press
+#[derive(Clone, Debug)]
+pub struct KeyMap {
+    controllers: Vec<(Key, ControllerDevice, Button)>,
+    keyboard: HashMap<Key, Key>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self::parse(DEFAULT_KEYMAP).expect("the default keymap is valid")
+    }
+}
+
+impl KeyMap {
+    pub fn load(filename: &str) -> Result<Self, Error> {
+        let contents =
+            fs::read_to_string(filename).map_err(|err| Error::new(format!("keymap: error reading {}: {}", filename, err)))?;
+        Self::parse(&contents).map_err(|err| Error::new(format!("{}: {}", filename, err)))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        let file: KeyMapFile = toml::from_str(contents).map_err(|err| Error::new(err.to_string()))?;
+
+        let mut controllers = vec![];
+        for (device, buttons) in file.controller {
+            for (button, key) in buttons {
+                controllers.push((key, device, button));
+            }
+        }
+        Ok(Self {
+            controllers,
+            keyboard: file.keyboard,
+        })
+    }
+
+    /// Returns the emulated key that the given host key presses
+    pub fn map_key(&self, key: Key) -> Key {
+        self.keyboard.get(&key).copied().unwrap_or(key)
+    }
+
+    /// Returns the controller events for the given key being pressed or released
+    pub fn controller_events(&self, key: Key, state: bool) -> Vec<ControllerEvent> {
+        self.controllers
+            .iter()
+            .filter(|(mapped, _, _)| *mapped == key)
+            .map(|(_, device, button)| ControllerEvent::new(*device, button.input(state)))
+            .collect()
+    }
+}
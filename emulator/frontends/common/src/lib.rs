@@ -1,10 +1,39 @@
 #[cfg(feature = "tty")]
 pub mod tty;
 
+#[cfg(feature = "net")]
+pub mod net;
+#[cfg(feature = "net")]
+pub use crate::net::NetworkBackend;
+
 pub mod audio;
+pub mod executor;
+pub mod machines;
 pub use crate::audio::{AudioMixer, AudioSource};
+pub use crate::executor::Executor;
+pub use crate::machines::{MachineEntry, MACHINES};
 
 #[cfg(feature = "audio")]
 pub mod cpal;
 #[cfg(feature = "audio")]
 pub use crate::cpal::CpalAudioOutput;
+
+#[cfg(feature = "keymap")]
+pub mod keymap;
+#[cfg(feature = "keymap")]
+pub use crate::keymap::KeyMap;
+
+#[cfg(feature = "script")]
+pub mod script;
+#[cfg(feature = "script")]
+pub use crate::script::ScriptDriver;
+
+#[cfg(feature = "script")]
+pub mod scripting;
+#[cfg(feature = "script")]
+pub use crate::scripting::RhaiScript;
+
+#[cfg(feature = "capture")]
+pub mod capture;
+#[cfg(feature = "capture")]
+pub use crate::capture::VideoRecorder;
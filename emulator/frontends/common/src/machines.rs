@@ -0,0 +1,197 @@
+use std::fmt;
+
+use moa_core::{System, Error};
+
+/// A description of one of the machines that the frontend binaries can run
+#[derive(Copy, Clone, Debug)]
+pub struct MachineEntry {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub binaries: &'static [&'static str],
+    pub default_rom: Option<&'static str>,
+    pub options: &'static [(&'static str, &'static str)],
+}
+
+pub const MACHINES: &[MachineEntry] = &[
+    MachineEntry {
+        name: "genesis",
+        description: "Sega Genesis/Mega Drive",
+        binaries: &["moa-genesis", "moa-console-genesis"],
+        default_rom: None,
+        options: &[
+            ("rom", "ROM file to load (flat binary or .smd)"),
+            ("rom_data", "ROM contents to use instead of loading a file"),
+            ("rom_database", "No-Intro DAT file to identify the ROM with (--rom-db)"),
+            ("poll_inputs_at_vblank", "only read controller inputs at the start of vblank (--vblank-input)"),
+            ("three_button", "connect 3-button controllers instead of 6-button ones (--three-button)"),
+            ("overscan", "draw the border around the display in the background colour (--overscan)"),
+            ("refresh_cycles", "stall the 68000 for the cycles used by DRAM refresh (--refresh-cycles)"),
+            ("interleave_us", "let each cpu run this many microseconds ahead of other devices (--interleave)"),
+            ("cd_bios", "Sega CD BIOS to boot instead of the ROM (--cd-bios)"),
+            ("cd_image", "CDROM image to insert into the Sega CD, as a .cue or .iso (--cd)"),
+            ("video_standard", "build an NTSC or a PAL console (--video-standard)"),
+            ("threaded_audio", "run the sound chips on threads of their own (--threaded-audio)"),
+        ],
+    },
+    MachineEntry {
+        name: "computie",
+        description: "Computie 68k single board computer",
+        binaries: &["moa-computie"],
+        default_rom: Some("binaries/computie/monitor.bin"),
+        options: &[
+            ("rom", "monitor ROM file to load at the start of memory"),
+            ("ram", "size of RAM in bytes"),
+            ("frequency", "CPU clock frequency"),
+            ("disk", "disk image attached to the ATA controller"),
+            ("disk_overlay", "copy-on-write file to store the sectors written to the disk (--overlay)"),
+        ],
+    },
+    MachineEntry {
+        name: "trs80",
+        description: "TRS-80 Model I",
+        binaries: &["moa-trs80"],
+        default_rom: Some("binaries/trs80/level2.rom"),
+        options: &[
+            ("rom", "BASIC ROM file to load at the start of memory"),
+            ("memory", "size of RAM in bytes"),
+            ("frequency", "CPU clock frequency"),
+            ("disks", "JV1, JV3, or DMK disk images for drives 0 to 3 (--disk)"),
+        ],
+    },
+    MachineEntry {
+        name: "zx-spectrum",
+        description: "ZX Spectrum 48K",
+        binaries: &["moa-zx-spectrum"],
+        default_rom: Some("binaries/zxspectrum/48.rom"),
+        options: &[
+            ("rom", "16K ROM file to load at the start of memory"),
+            ("frequency", "CPU clock frequency"),
+            ("contended_memory", "delay the cpu for the memory shared with the ULA (--no-contention to disable)"),
+        ],
+    },
+    MachineEntry {
+        name: "cpm",
+        description: "Z80 CP/M 2.2 machine with the z80pack console and disk ports",
+        binaries: &["moa-cpm"],
+        default_rom: Some("binaries/cpm/drivea.dsk"),
+        options: &[
+            ("disks", "disk image files for drives A, B, and so on"),
+            ("frequency", "CPU clock frequency"),
+            ("i8080", "emulate an Intel 8080 instead of a Z80 (--i8080)"),
+        ],
+    },
+    MachineEntry {
+        name: "custom",
+        description: "Machine built from a TOML definition of its cpu, memory, and devices",
+        binaries: &["moa-machine"],
+        default_rom: Some("binaries/custom/computie.toml"),
+        options: &[("definition", "TOML file describing the machine")],
+    },
+    MachineEntry {
+        name: "bare",
+        description: "Generic 68k or Z80 board for running bare ROM images",
+        binaries: &["moa-run"],
+        default_rom: None,
+        options: &[
+            ("cpu", "cpu type, such as m68k or z80"),
+            ("frequency", "CPU clock frequency"),
+            ("roms", "ROM files and the addresses to load them at (--rom FILE@ADDR)"),
+            ("ram", "sizes and addresses of the RAM (--ram SIZE@ADDR)"),
+            ("serial", "serial chips and their addresses (--serial TYPE@ADDR)"),
+        ],
+    },
+    MachineEntry {
+        name: "macintosh",
+        description: "Macintosh 512k (incomplete)",
+        binaries: &["moa-macintosh"],
+        default_rom: Some("binaries/macintosh/Macintosh 512k.rom"),
+        options: &[
+            ("rom", "ROM file to load"),
+            ("disk", "400K or 800K disk image to insert into the internal drive"),
+            ("serial", "connect the modem and printer ports to ptys (--serial)"),
+        ],
+    },
+    MachineEntry {
+        name: "atari-st",
+        description: "Atari ST",
+        binaries: &["moa-atari-st"],
+        default_rom: Some("binaries/atarist/tos102.img"),
+        options: &[
+            ("rom", "TOS ROM image, which is loaded at the address in its header (--tos)"),
+            ("ram", "size of RAM in bytes"),
+            ("disk", "disk image in the .ST format to insert into drive A"),
+            ("monochrome", "connect a monochrome monitor (--monochrome)"),
+        ],
+    },
+    MachineEntry {
+        name: "testbench",
+        description: "Bare 68010 machine for running ELF programs",
+        binaries: &["moa-testbench"],
+        default_rom: None,
+        options: &[
+            ("elf", "statically linked m68k ELF program to run"),
+            ("ram", "size of RAM in bytes"),
+            ("stack_size", "size of the stack at the top of RAM"),
+            ("frequency", "CPU clock frequency"),
+            ("sandbox", "directory the program can read files from (--sandbox)"),
+        ],
+    },
+    MachineEntry {
+        name: "synth",
+        description: "YM2612 and SN76489 tester",
+        binaries: &["moa-synth"],
+        default_rom: None,
+        options: &[],
+    },
+];
+
+pub fn find_machine(name: &str) -> Option<&'static MachineEntry> {
+    MACHINES.iter().find(|machine| machine.name == name)
+}
+
+pub fn print_machines() {
+    for machine in MACHINES {
+        println!("{}", machine);
+    }
+}
+
+/// Print the effective configuration of a machine after all the command line arguments have been applied
+pub fn print_config(frontend: &[(&str, String)], options: &dyn fmt::Debug) {
+    println!("frontend:");
+    for (name, value) in frontend {
+        println!("  {}: {}", name, value);
+    }
+    println!("machine: {:#?}", options);
+}
+
+/// Print a report of the problems found in a machine without running it, and return the exit code to use,
+/// which is non-zero if the machine couldn't be built (eg. a ROM failed to load) or has any errors
+pub fn validate(system: &Result<System, Error>) -> i32 {
+    match system {
+        Ok(system) => {
+            let report = system.validate();
+            println!("{}", report);
+            if report.is_ok() { 0 } else { 1 }
+        },
+        Err(err) => {
+            println!("error: failed to build the machine: {}", err);
+            println!("checked 0 devices: 1 errors, 0 warnings");
+            1
+        },
+    }
+}
+
+impl fmt::Display for MachineEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} - {}", self.name, self.description)?;
+        writeln!(f, "  binaries: {}", self.binaries.join(", "))?;
+        writeln!(f, "  default rom: {}", self.default_rom.unwrap_or("(none)"))?;
+        if !self.options.is_empty() {
+            writeln!(f, "  options:")?;
+            for (name, help) in self.options {
+                writeln!(f, "    {:<12} {}", name, help)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+//! Backends for the emulated machine's network interfaces
+//!
+//! The backend is given on the command line as one of:
+//! * `nat` forwards the machine's TCP and UDP connections through the host's own sockets, so it needs no
+//!   privileges or setup.  The host is reachable from the machine at the gateway address, which is 192.168.1.2
+//!   unless it's given as `nat:ADDR`.  Ping only works to the gateway
+//! * `tun:NAME` passes the packets to a TUN interface on Linux, which must already exist and belong to the user,
+//!   (eg. `sudo ip tuntap add dev tun0 mode tun user $USER`), and be routed by the host like any other interface
+//! * `udp:LOCAL,REMOTE` sends each packet as a UDP datagram to the REMOTE address, and receives them on the LOCAL
+//!   address, which can connect two emulated machines together (eg. `udp:127.0.0.1:5001,127.0.0.1:5002` and
+//!   `udp:127.0.0.1:5002,127.0.0.1:5001`)
+//! * `none` leaves the machine without a network
+
+mod nat;
+#[cfg(target_os = "linux")]
+mod tun;
+mod udp;
+
+use std::fmt;
+use std::str::FromStr;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{mpsc, Arc, Mutex};
+
+use moa_core::Error;
+use moa_host::{HostError, Network};
+
+pub use crate::net::nat::NatOptions;
+pub use crate::net::udp::UdpTunnelOptions;
+
+
+/// The gateway address of the `nat` backend, unless another is given
+pub const DEFAULT_GATEWAY: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 2);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NetworkBackend {
+    None,
+    Nat(NatOptions),
+    Tun(String),
+    Udp(UdpTunnelOptions),
+}
+
+impl Default for NetworkBackend {
+    fn default() -> Self {
+        NetworkBackend::Nat(NatOptions::default())
+    }
+}
+
+impl FromStr for NetworkBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "none" => Ok(NetworkBackend::None),
+            None if s == "nat" => Ok(NetworkBackend::Nat(NatOptions::default())),
+            Some(("nat", gateway)) => {
+                let gateway = gateway.parse().map_err(|_| format!("{} is not an IPv4 address", gateway))?;
+                Ok(NetworkBackend::Nat(NatOptions {
+                    gateway,
+                }))
+            },
+            Some(("tun", name)) if !name.is_empty() => Ok(NetworkBackend::Tun(name.to_string())),
+            Some(("udp", addrs)) => {
+                let (local, remote) = addrs
+                    .split_once(',')
+                    .ok_or_else(|| format!("expected {} to be of the form LOCAL,REMOTE", addrs))?;
+                let parse = |addr: &str| {
+                    addr.parse::<SocketAddr>()
+                        .map_err(|_| format!("{} is not a socket address", addr))
+                };
+                Ok(NetworkBackend::Udp(UdpTunnelOptions {
+                    local: parse(local)?,
+                    remote: parse(remote)?,
+                }))
+            },
+            _ => Err(format!("{} is not none, nat, nat:ADDR, tun:NAME, or udp:LOCAL,REMOTE", s)),
+        }
+    }
+}
+
+impl fmt::Display for NetworkBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkBackend::None => write!(f, "none"),
+            NetworkBackend::Nat(options) => write!(f, "nat:{}", options.gateway),
+            NetworkBackend::Tun(name) => write!(f, "tun:{}", name),
+            NetworkBackend::Udp(options) => write!(f, "udp:{},{}", options.local, options.remote),
+        }
+    }
+}
+
+impl NetworkBackend {
+    /// Open the backend for the named network interface of the machine
+    pub fn open(&self, name: &str) -> Result<Box<dyn Network>, HostError<Error>> {
+        let (network, peer) = channel_network(self.to_string());
+        match self {
+            NetworkBackend::None => return Err(HostError::NetworkNotSupported),
+            NetworkBackend::Nat(options) => nat::spawn(options.clone(), peer),
+            #[cfg(target_os = "linux")]
+            NetworkBackend::Tun(device) => tun::spawn(device, peer).map_err(HostError::Specific)?,
+            #[cfg(not(target_os = "linux"))]
+            NetworkBackend::Tun(_) => {
+                return Err(HostError::Specific(Error::new("TUN interfaces are only supported on Linux")));
+            },
+            NetworkBackend::Udp(options) => udp::spawn(options, peer).map_err(HostError::Specific)?,
+        }
+        log::info!("network {} is connected to {}", name, self);
+        Ok(Box::new(network))
+    }
+}
+
+
+type ReceiveNotify = Arc<Mutex<Option<Box<dyn Fn() + Send>>>>;
+
+/// The emulated machine's end of a backend that runs in its own thread
+struct ChannelNetwork {
+    name: String,
+    outgoing: mpsc::Sender<Vec<u8>>,
+    incoming: mpsc::Receiver<Vec<u8>>,
+    notify: ReceiveNotify,
+}
+
+/// The backend's end of the connection to the emulated machine
+struct NetworkPeer {
+    from_machine: mpsc::Receiver<Vec<u8>>,
+    to_machine: MachineSender,
+}
+
+/// Passes packets from the backend to the emulated machine, which can be moved to another thread of the backend
+#[derive(Clone)]
+struct MachineSender {
+    sender: mpsc::Sender<Vec<u8>>,
+    notify: ReceiveNotify,
+}
+
+fn channel_network(name: String) -> (ChannelNetwork, NetworkPeer) {
+    let (outgoing, from_machine) = mpsc::channel();
+    let (to_machine, incoming) = mpsc::channel();
+    let notify = Arc::new(Mutex::new(None));
+    let network = ChannelNetwork {
+        name,
+        outgoing,
+        incoming,
+        notify: notify.clone(),
+    };
+    let peer = NetworkPeer {
+        from_machine,
+        to_machine: MachineSender {
+            sender: to_machine,
+            notify,
+        },
+    };
+    (network, peer)
+}
+
+impl NetworkPeer {
+    fn deliver(&self, packet: Vec<u8>) -> bool {
+        self.to_machine.deliver(packet)
+    }
+}
+
+impl MachineSender {
+    /// Pass a packet to the machine, returning false if the machine has gone away
+    fn deliver(&self, packet: Vec<u8>) -> bool {
+        if self.sender.send(packet).is_err() {
+            return false;
+        }
+        if let Some(notify) = self.notify.lock().unwrap().as_ref() {
+            notify();
+        }
+        true
+    }
+}
+
+impl Network for ChannelNetwork {
+    fn device_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn send(&mut self, packet: &[u8]) {
+        // The backend only stops if it failed, which it will have already reported
+        let _ = self.outgoing.send(packet.to_vec());
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        self.incoming.try_recv().ok()
+    }
+
+    fn on_receive(&mut self, notify: Box<dyn Fn() + Send>) -> bool {
+        *self.notify.lock().unwrap() = Some(notify);
+        true
+    }
+}
@@ -0,0 +1,701 @@
+//! A user-space NAT, which forwards the TCP and UDP connections of the emulated machine through the host's
+//! sockets, acting as the other end of each connection.  The link to the machine doesn't lose packets, so TCP only
+//! needs enough retransmission to recover from the machine running out of buffers
+
+use std::thread;
+use std::io::{self, Read, Write};
+use std::collections::{HashMap, VecDeque};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4, TcpStream, UdpSocket};
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::net::{NetworkPeer, DEFAULT_GATEWAY};
+
+
+const PROTOCOL_ICMP: u8 = 1;
+const PROTOCOL_TCP: u8 = 6;
+const PROTOCOL_UDP: u8 = 17;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+const TTL: u8 = 64;
+
+/// The segment size used if the machine doesn't give one, which is the minimum every host must accept
+const DEFAULT_MSS: usize = 536;
+/// The segment size the machine is told to use, which its own MTU will usually lower
+const NAT_MSS: u16 = 1460;
+/// The most data from the machine that's waiting to be written to a host socket, which is the window it's given
+const TCP_WINDOW: usize = 32768;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(1);
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+const UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long to wait before checking again when there was nothing to do
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NatOptions {
+    /// The address of the NAT as seen by the machine.  Connections to it go to the host's loopback address
+    pub gateway: Ipv4Addr,
+}
+
+impl Default for NatOptions {
+    fn default() -> Self {
+        Self {
+            gateway: DEFAULT_GATEWAY,
+        }
+    }
+}
+
+pub(super) fn spawn(options: NatOptions, peer: NetworkPeer) {
+    thread::spawn(move || {
+        let mut nat = Nat::new(options);
+        loop {
+            let mut busy = false;
+            loop {
+                match peer.from_machine.try_recv() {
+                    Ok(packet) => {
+                        nat.handle_packet(&packet);
+                        busy = true;
+                    },
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => return,
+                }
+            }
+
+            nat.poll();
+            for packet in nat.outgoing.drain(..) {
+                if !peer.deliver(packet) {
+                    return;
+                }
+                busy = true;
+            }
+
+            if !busy {
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    });
+}
+
+
+struct Nat {
+    options: NatOptions,
+    /// The packets waiting to be delivered to the machine
+    outgoing: Vec<Vec<u8>>,
+    udp: HashMap<(SocketAddrV4, SocketAddrV4), UdpBinding>,
+    tcp: HashMap<(SocketAddrV4, SocketAddrV4), TcpConnection>,
+}
+
+impl Nat {
+    fn new(options: NatOptions) -> Self {
+        Self {
+            options,
+            outgoing: vec![],
+            udp: HashMap::new(),
+            tcp: HashMap::new(),
+        }
+    }
+
+    fn handle_packet(&mut self, packet: &[u8]) {
+        let ip = match Ipv4Packet::parse(packet) {
+            Some(ip) => ip,
+            None => {
+                log::debug!("nat: dropping a packet that isn't an unfragmented IPv4 packet");
+                return;
+            },
+        };
+
+        match ip.protocol {
+            PROTOCOL_ICMP => self.handle_icmp(&ip),
+            PROTOCOL_UDP => self.handle_udp(&ip),
+            PROTOCOL_TCP => self.handle_tcp(&ip),
+            protocol => log::debug!("nat: dropping a packet for unsupported protocol {}", protocol),
+        }
+    }
+
+    /// Returns the address on the host to connect to for an address the machine is connecting to
+    fn host_addr(&self, remote: SocketAddrV4) -> SocketAddr {
+        if *remote.ip() == self.options.gateway {
+            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, remote.port()))
+        } else {
+            SocketAddr::V4(remote)
+        }
+    }
+
+    fn handle_icmp(&mut self, ip: &Ipv4Packet) {
+        // Unprivileged programs can't send pings, so only the gateway answers them
+        if ip.payload.len() < 8 || ip.payload[0] != ICMP_ECHO_REQUEST || ip.dst != self.options.gateway {
+            log::debug!("nat: dropping an ICMP message for {}", ip.dst);
+            return;
+        }
+
+        let mut reply = ip.payload.to_vec();
+        reply[0] = ICMP_ECHO_REPLY;
+        reply[2..4].copy_from_slice(&[0, 0]);
+        let sum = checksum(&reply, 0);
+        reply[2..4].copy_from_slice(&sum.to_be_bytes());
+        self.outgoing.push(build_ipv4(ip.dst, ip.src, PROTOCOL_ICMP, &reply));
+    }
+
+    fn handle_udp(&mut self, ip: &Ipv4Packet) {
+        if ip.payload.len() < 8 {
+            return;
+        }
+        let src_port = u16::from_be_bytes([ip.payload[0], ip.payload[1]]);
+        let dst_port = u16::from_be_bytes([ip.payload[2], ip.payload[3]]);
+        let length = (u16::from_be_bytes([ip.payload[4], ip.payload[5]]) as usize).clamp(8, ip.payload.len());
+        let guest = SocketAddrV4::new(ip.src, src_port);
+        let remote = SocketAddrV4::new(ip.dst, dst_port);
+
+        if !self.udp.contains_key(&(guest, remote)) {
+            match UdpBinding::open(self.host_addr(remote)) {
+                Ok(binding) => {
+                    self.udp.insert((guest, remote), binding);
+                },
+                Err(err) => {
+                    log::warn!("nat: unable to open a UDP socket for {}: {}", remote, err);
+                    return;
+                },
+            }
+        }
+
+        let binding = self.udp.get_mut(&(guest, remote)).unwrap();
+        binding.last_used = Instant::now();
+        if let Err(err) = binding.socket.send(&ip.payload[8..length]) {
+            log::debug!("nat: error sending to {}: {}", remote, err);
+        }
+    }
+
+    fn handle_tcp(&mut self, ip: &Ipv4Packet) {
+        let segment = match TcpSegment::parse(ip.payload) {
+            Some(segment) => segment,
+            None => return,
+        };
+        let guest = SocketAddrV4::new(ip.src, segment.src_port);
+        let remote = SocketAddrV4::new(ip.dst, segment.dst_port);
+
+        if let Some(connection) = self.tcp.get_mut(&(guest, remote)) {
+            if segment.flags & TCP_RST != 0 {
+                self.tcp.remove(&(guest, remote));
+            } else {
+                connection.handle_segment(&segment, &mut self.outgoing);
+            }
+        } else if segment.flags & (TCP_SYN | TCP_ACK | TCP_RST) == TCP_SYN {
+            let connection = TcpConnection::connect(guest, remote, self.host_addr(remote), &segment);
+            self.tcp.insert((guest, remote), connection);
+        } else if segment.flags & TCP_RST == 0 {
+            // The connection doesn't exist, so the machine is told to forget it too
+            let (seq, flags) = if segment.flags & TCP_ACK != 0 {
+                (segment.ack, TCP_RST)
+            } else {
+                (0, TCP_RST | TCP_ACK)
+            };
+            let ack = segment.seq.wrapping_add(segment.sequence_len());
+            let reply = build_tcp(remote, guest, seq, ack, flags, 0, None, &[]);
+            self.outgoing.push(reply);
+        }
+    }
+
+    fn poll(&mut self) {
+        let now = Instant::now();
+
+        let mut buf = [0; 65535];
+        self.udp.retain(|(guest, remote), binding| {
+            loop {
+                match binding.socket.recv(&mut buf) {
+                    Ok(count) => {
+                        binding.last_used = now;
+                        self.outgoing.push(build_udp(*remote, *guest, &buf[..count]));
+                    },
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(err) => {
+                        log::debug!("nat: error receiving from {}: {}", remote, err);
+                        break;
+                    },
+                }
+            }
+            now.duration_since(binding.last_used) < UDP_IDLE_TIMEOUT
+        });
+
+        let outgoing = &mut self.outgoing;
+        self.tcp.retain(|_, connection| connection.poll(now, outgoing));
+    }
+}
+
+
+struct UdpBinding {
+    socket: UdpSocket,
+    last_used: Instant,
+}
+
+impl UdpBinding {
+    fn open(addr: SocketAddr) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            last_used: Instant::now(),
+        })
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum TcpState {
+    /// Waiting for the host's connection to the remote address to be made
+    Connecting,
+    /// The SYN-ACK has been sent to the machine, which hasn't acknowledged it yet
+    SynReceived,
+    Established,
+}
+
+/// One of the machine's TCP connections, which the NAT is the remote end of
+struct TcpConnection {
+    guest: SocketAddrV4,
+    remote: SocketAddrV4,
+    state: TcpState,
+    connecting: Option<mpsc::Receiver<io::Result<TcpStream>>>,
+    stream: Option<TcpStream>,
+
+    /// The next sequence number expected from the machine
+    rcv_nxt: u32,
+    /// The oldest sequence number sent to the machine that it hasn't acknowledged
+    snd_una: u32,
+    /// The sequence number of the next byte to send to the machine
+    snd_nxt: u32,
+    /// The data sent to the machine that hasn't been acknowledged, starting at `snd_una`
+    unacked: VecDeque<u8>,
+    /// The data from the machine that hasn't been written to the host's socket yet
+    to_host: VecDeque<u8>,
+    window: usize,
+    mss: usize,
+
+    /// The host's socket has been closed for reading, and the FIN has been sent to the machine
+    fin_sent: bool,
+    /// The machine has sent its FIN, so the host's socket is closed for writing once the data is written
+    fin_received: bool,
+    host_shutdown: bool,
+
+    last_progress: Instant,
+    last_activity: Instant,
+}
+
+impl TcpConnection {
+    fn connect(guest: SocketAddrV4, remote: SocketAddrV4, host_addr: SocketAddr, syn: &TcpSegment) -> Self {
+        // Connecting can take a while, so it's done on its own thread
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(TcpStream::connect_timeout(&host_addr, CONNECT_TIMEOUT));
+        });
+
+        let isn = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.subsec_nanos())
+            .unwrap_or(0);
+        let now = Instant::now();
+        Self {
+            guest,
+            remote,
+            state: TcpState::Connecting,
+            connecting: Some(receiver),
+            stream: None,
+            rcv_nxt: syn.seq.wrapping_add(1),
+            snd_una: isn,
+            snd_nxt: isn,
+            unacked: VecDeque::new(),
+            to_host: VecDeque::new(),
+            window: syn.window as usize,
+            mss: syn.mss.filter(|mss| *mss > 0).map(|mss| mss as usize).unwrap_or(DEFAULT_MSS),
+            fin_sent: false,
+            fin_received: false,
+            host_shutdown: false,
+            last_progress: now,
+            last_activity: now,
+        }
+    }
+
+    /// Build a segment to the machine with the given sequence number, which acknowledges everything received
+    fn segment(&self, seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let window = TCP_WINDOW.saturating_sub(self.to_host.len()) as u16;
+        let mss = if flags & TCP_SYN != 0 { Some(NAT_MSS) } else { None };
+        build_tcp(self.remote, self.guest, seq, self.rcv_nxt, flags | TCP_ACK, window, mss, payload)
+    }
+
+    fn handle_segment(&mut self, segment: &TcpSegment, outgoing: &mut Vec<Vec<u8>>) {
+        self.last_activity = Instant::now();
+        self.window = segment.window as usize;
+
+        if segment.flags & TCP_SYN != 0 {
+            // The machine didn't get the SYN-ACK, so it sent the SYN again
+            if self.state == TcpState::SynReceived {
+                outgoing.push(self.segment(self.snd_una, TCP_SYN, &[]));
+            }
+            return;
+        }
+
+        if segment.flags & TCP_ACK != 0 {
+            let acked = segment.ack.wrapping_sub(self.snd_una) as usize;
+            let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            if acked > 0 && acked <= in_flight {
+                if self.state == TcpState::SynReceived {
+                    self.state = TcpState::Established;
+                } else {
+                    // The FIN is acknowledged along with the data, but it isn't in the buffer
+                    let count = acked.min(self.unacked.len());
+                    self.unacked.drain(..count);
+                }
+                self.snd_una = segment.ack;
+                self.last_progress = Instant::now();
+            }
+        }
+
+        if self.state != TcpState::Established {
+            return;
+        }
+
+        // Only data that's next in sequence is accepted, and anything else is acknowledged again so the machine
+        // knows what's missing
+        let mut acknowledge = !segment.payload.is_empty();
+        if segment.seq == self.rcv_nxt && self.to_host.len() + segment.payload.len() <= TCP_WINDOW {
+            self.to_host.extend(segment.payload);
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(segment.payload.len() as u32);
+
+            if segment.flags & TCP_FIN != 0 && !self.fin_received {
+                self.fin_received = true;
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                acknowledge = true;
+            }
+        }
+
+        if acknowledge {
+            outgoing.push(self.segment(self.snd_nxt, 0, &[]));
+        }
+    }
+
+    /// Pass data between the host's socket and the machine, and returns false when the connection is finished
+    fn poll(&mut self, now: Instant, outgoing: &mut Vec<Vec<u8>>) -> bool {
+        match self.state {
+            TcpState::Connecting => return self.poll_connecting(now, outgoing),
+            TcpState::SynReceived => {
+                if now.duration_since(self.last_progress) > RETRANSMIT_TIMEOUT {
+                    outgoing.push(self.segment(self.snd_una, TCP_SYN, &[]));
+                    self.last_progress = now;
+                }
+            },
+            TcpState::Established => {
+                if let Err(err) = self.transfer(now, outgoing) {
+                    log::debug!("nat: connection to {} failed: {}", self.remote, err);
+                    outgoing.push(self.segment(self.snd_nxt, TCP_RST, &[]));
+                    return false;
+                }
+                self.retransmit(now, outgoing);
+
+                if self.fin_sent && self.fin_received && self.snd_una == self.snd_nxt {
+                    return false;
+                }
+            },
+        }
+
+        now.duration_since(self.last_activity) < TCP_IDLE_TIMEOUT
+    }
+
+    fn poll_connecting(&mut self, now: Instant, outgoing: &mut Vec<Vec<u8>>) -> bool {
+        let result = match self.connecting.as_ref().map(|receiver| receiver.try_recv()) {
+            Some(Ok(result)) => result,
+            Some(Err(mpsc::TryRecvError::Empty)) => return true,
+            _ => return false,
+        };
+        self.connecting = None;
+
+        match result.and_then(|stream| {
+            stream.set_nonblocking(true)?;
+            stream.set_nodelay(true)?;
+            Ok(stream)
+        }) {
+            Ok(stream) => {
+                self.stream = Some(stream);
+                self.state = TcpState::SynReceived;
+                outgoing.push(self.segment(self.snd_nxt, TCP_SYN, &[]));
+                self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                self.last_progress = now;
+                true
+            },
+            Err(err) => {
+                log::debug!("nat: unable to connect to {}: {}", self.remote, err);
+                outgoing.push(self.segment(0, TCP_RST, &[]));
+                false
+            },
+        }
+    }
+
+    fn transfer(&mut self, now: Instant, outgoing: &mut Vec<Vec<u8>>) -> io::Result<()> {
+        let stream = self.stream.as_mut().unwrap();
+
+        while !self.to_host.is_empty() {
+            let (data, _) = self.to_host.as_slices();
+            match stream.write(data) {
+                Ok(count) => {
+                    self.to_host.drain(..count);
+                },
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        if self.fin_received && self.to_host.is_empty() && !self.host_shutdown {
+            stream.shutdown(Shutdown::Write)?;
+            self.host_shutdown = true;
+        }
+
+        // Only as much as the machine has room for is read from the host
+        let mut buf = vec![0; self.mss];
+        while !self.fin_sent {
+            let space = self.window.saturating_sub(self.unacked.len()).min(self.mss);
+            if space == 0 {
+                break;
+            }
+
+            // The retransmission timer starts from the first segment that's waiting to be acknowledged
+            if self.snd_una == self.snd_nxt {
+                self.last_progress = now;
+            }
+
+            let stream = self.stream.as_mut().unwrap();
+            match stream.read(&mut buf[..space]) {
+                Ok(0) => {
+                    outgoing.push(self.segment(self.snd_nxt, TCP_FIN, &[]));
+                    self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                    self.fin_sent = true;
+                },
+                Ok(count) => {
+                    outgoing.push(self.segment(self.snd_nxt, TCP_PSH, &buf[..count]));
+                    self.unacked.extend(&buf[..count]);
+                    self.snd_nxt = self.snd_nxt.wrapping_add(count as u32);
+                },
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+            self.last_activity = now;
+        }
+        Ok(())
+    }
+
+    /// Send everything that hasn't been acknowledged again if nothing has been acknowledged for a while
+    fn retransmit(&mut self, now: Instant, outgoing: &mut Vec<Vec<u8>>) {
+        if self.snd_una == self.snd_nxt || now.duration_since(self.last_progress) < RETRANSMIT_TIMEOUT {
+            return;
+        }
+
+        let data: Vec<u8> = self.unacked.iter().copied().collect();
+        // At least one segment is sent, even if the window is closed, to find out when it opens again
+        let limit = self.window.max(1).min(data.len());
+        let mut seq = self.snd_una;
+        for chunk in data.chunks(self.mss) {
+            let sent = seq.wrapping_sub(self.snd_una) as usize;
+            if sent > 0 && sent >= limit {
+                break;
+            }
+            outgoing.push(self.segment(seq, TCP_PSH, chunk));
+            seq = seq.wrapping_add(chunk.len() as u32);
+        }
+        if self.fin_sent && seq.wrapping_add(1) == self.snd_nxt {
+            outgoing.push(self.segment(seq, TCP_FIN, &[]));
+        }
+        self.last_progress = now;
+    }
+}
+
+
+struct Ipv4Packet<'a> {
+    protocol: u8,
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    payload: &'a [u8],
+}
+
+impl<'a> Ipv4Packet<'a> {
+    fn parse(packet: &'a [u8]) -> Option<Self> {
+        if packet.len() < 20 || packet[0] >> 4 != 4 {
+            return None;
+        }
+        let header_len = (packet[0] & 0x0F) as usize * 4;
+        let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+        if header_len < 20 || total_len < header_len || total_len > packet.len() {
+            return None;
+        }
+        // Fragments aren't reassembled
+        let fragment = u16::from_be_bytes([packet[6], packet[7]]);
+        if fragment & 0x3FFF != 0 {
+            return None;
+        }
+
+        Some(Self {
+            protocol: packet[9],
+            src: Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]),
+            dst: Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]),
+            payload: &packet[header_len..total_len],
+        })
+    }
+}
+
+struct TcpSegment<'a> {
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    mss: Option<u16>,
+    payload: &'a [u8],
+}
+
+impl<'a> TcpSegment<'a> {
+    fn parse(segment: &'a [u8]) -> Option<Self> {
+        if segment.len() < 20 {
+            return None;
+        }
+        let header_len = (segment[12] >> 4) as usize * 4;
+        if header_len < 20 || header_len > segment.len() {
+            return None;
+        }
+
+        let mut mss = None;
+        let mut options = &segment[20..header_len];
+        while let Some(&kind) = options.first() {
+            match kind {
+                0 => break,
+                1 => options = &options[1..],
+                _ => {
+                    let len = *options.get(1)? as usize;
+                    if len < 2 || len > options.len() {
+                        break;
+                    }
+                    if kind == 2 && len == 4 {
+                        mss = Some(u16::from_be_bytes([options[2], options[3]]));
+                    }
+                    options = &options[len..];
+                },
+            }
+        }
+
+        Some(Self {
+            src_port: u16::from_be_bytes([segment[0], segment[1]]),
+            dst_port: u16::from_be_bytes([segment[2], segment[3]]),
+            seq: u32::from_be_bytes([segment[4], segment[5], segment[6], segment[7]]),
+            ack: u32::from_be_bytes([segment[8], segment[9], segment[10], segment[11]]),
+            flags: segment[13],
+            window: u16::from_be_bytes([segment[14], segment[15]]),
+            mss,
+            payload: &segment[header_len..],
+        })
+    }
+
+    /// Returns the amount of sequence space the segment uses, which includes the SYN and FIN flags
+    fn sequence_len(&self) -> u32 {
+        let flags = (self.flags & TCP_SYN != 0) as u32 + (self.flags & TCP_FIN != 0) as u32;
+        self.payload.len() as u32 + flags
+    }
+}
+
+fn build_ipv4(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let total_len = (20 + payload.len()) as u16;
+    let mut packet = Vec::with_capacity(total_len as usize);
+    packet.extend_from_slice(&[0x45, 0x00]);
+    packet.extend_from_slice(&total_len.to_be_bytes());
+    // The packets have the Don't Fragment flag set, so the identification can always be 0
+    packet.extend_from_slice(&[0x00, 0x00, 0x40, 0x00, TTL, protocol, 0x00, 0x00]);
+    packet.extend_from_slice(&src.octets());
+    packet.extend_from_slice(&dst.octets());
+    let sum = checksum(&packet, 0);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+fn build_udp(src: SocketAddrV4, dst: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+    let len = (8 + payload.len()) as u16;
+    let mut segment = Vec::with_capacity(len as usize);
+    segment.extend_from_slice(&src.port().to_be_bytes());
+    segment.extend_from_slice(&dst.port().to_be_bytes());
+    segment.extend_from_slice(&len.to_be_bytes());
+    segment.extend_from_slice(&[0, 0]);
+    segment.extend_from_slice(payload);
+
+    // A checksum of 0 means there isn't one, so it's sent as all ones instead
+    let sum = match checksum(&segment, pseudo_header_sum(*src.ip(), *dst.ip(), PROTOCOL_UDP, segment.len())) {
+        0 => 0xFFFF,
+        sum => sum,
+    };
+    segment[6..8].copy_from_slice(&sum.to_be_bytes());
+    build_ipv4(*src.ip(), *dst.ip(), PROTOCOL_UDP, &segment)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build_tcp(
+    src: SocketAddrV4,
+    dst: SocketAddrV4,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    mss: Option<u16>,
+    payload: &[u8],
+) -> Vec<u8> {
+    let header_len = if mss.is_some() { 24 } else { 20 };
+    let mut segment = Vec::with_capacity(header_len + payload.len());
+    segment.extend_from_slice(&src.port().to_be_bytes());
+    segment.extend_from_slice(&dst.port().to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.extend_from_slice(&[((header_len / 4) << 4) as u8, flags]);
+    segment.extend_from_slice(&window.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    if let Some(mss) = mss {
+        segment.extend_from_slice(&[2, 4]);
+        segment.extend_from_slice(&mss.to_be_bytes());
+    }
+    segment.extend_from_slice(payload);
+
+    let sum = checksum(&segment, pseudo_header_sum(*src.ip(), *dst.ip(), PROTOCOL_TCP, segment.len()));
+    segment[16..18].copy_from_slice(&sum.to_be_bytes());
+    build_ipv4(*src.ip(), *dst.ip(), PROTOCOL_TCP, &segment)
+}
+
+fn pseudo_header_sum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, len: usize) -> u32 {
+    let mut header = [0; 12];
+    header[0..4].copy_from_slice(&src.octets());
+    header[4..8].copy_from_slice(&dst.octets());
+    header[9] = protocol;
+    header[10..12].copy_from_slice(&(len as u16).to_be_bytes());
+    sum_words(&header, 0)
+}
+
+fn sum_words(data: &[u8], mut sum: u32) -> u32 {
+    let mut words = data.chunks_exact(2);
+    for word in &mut words {
+        sum += u16::from_be_bytes([word[0], word[1]]) as u32;
+    }
+    if let [last] = words.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    sum
+}
+
+/// Returns the internet checksum (RFC 1071) of the data, added to the sum of a pseudo-header
+fn checksum(data: &[u8], initial: u32) -> u16 {
+    let mut sum = sum_words(data, initial);
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
@@ -0,0 +1,86 @@
+//! Passes the emulated machine's packets to a TUN interface on the host, which must already exist and be owned by
+//! the user running the emulator, so that no privileges are needed to open it
+
+use std::thread;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+
+use moa_core::Error;
+
+use crate::net::NetworkPeer;
+
+
+const IFF_TUN: i16 = 0x0001;
+const IFF_NO_PI: i16 = 0x1000;
+const IFNAMSIZ: usize = 16;
+
+#[repr(C)]
+struct IfReq {
+    name: [u8; IFNAMSIZ],
+    flags: i16,
+    _pad: [u8; 22],
+}
+
+nix::ioctl_write_ptr_bad!(tun_set_iff, 0x400454ca, IfReq);
+
+pub(super) fn spawn(device: &str, peer: NetworkPeer) -> Result<(), Error> {
+    if device.len() >= IFNAMSIZ {
+        return Err(Error::new(format!("{} is too long for an interface name", device)));
+    }
+
+    let mut reader = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/net/tun")
+        .map_err(|err| Error::new(format!("error opening /dev/net/tun: {}", err)))?;
+
+    let mut request = IfReq {
+        name: [0; IFNAMSIZ],
+        flags: IFF_TUN | IFF_NO_PI,
+        _pad: [0; 22],
+    };
+    request.name[..device.len()].copy_from_slice(device.as_bytes());
+    unsafe { tun_set_iff(reader.as_raw_fd(), &request) }
+        .map_err(|err| Error::new(format!("error attaching to TUN interface {}: {}", device, err)))?;
+
+    let mut writer: File = reader
+        .try_clone()
+        .map_err(|err| Error::new(format!("error opening TUN interface {}: {}", device, err)))?;
+
+    let NetworkPeer {
+        from_machine,
+        to_machine,
+    } = peer;
+
+    let name = device.to_string();
+    thread::spawn(move || {
+        // Each read returns one packet, which can't be larger than the interface's MTU
+        let mut buf = [0; 65535];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(count) => {
+                    if !to_machine.deliver(buf[..count].to_vec()) {
+                        return;
+                    }
+                },
+                Err(err) => {
+                    log::error!("error reading from TUN interface {}: {}", name, err);
+                    return;
+                },
+            }
+        }
+    });
+
+    let name = device.to_string();
+    thread::spawn(move || {
+        while let Ok(packet) = from_machine.recv() {
+            if let Err(err) = writer.write_all(&packet) {
+                log::error!("error writing to TUN interface {}: {}", name, err);
+                return;
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+//! Tunnels the emulated machine's packets over UDP, with each packet sent as one datagram to the remote address,
+//! and each datagram received on the local address passed to the machine.  Two emulators can be connected to each
+//! other this way, or a machine can be connected to a program that routes its packets
+
+use std::thread;
+use std::net::{SocketAddr, UdpSocket};
+
+use moa_core::Error;
+
+use crate::net::NetworkPeer;
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UdpTunnelOptions {
+    pub local: SocketAddr,
+    pub remote: SocketAddr,
+}
+
+pub(super) fn spawn(options: &UdpTunnelOptions, peer: NetworkPeer) -> Result<(), Error> {
+    let receiver = UdpSocket::bind(options.local)
+        .map_err(|err| Error::new(format!("error binding a UDP socket to {}: {}", options.local, err)))?;
+    let sender = receiver
+        .try_clone()
+        .map_err(|err| Error::new(format!("error opening a UDP socket: {}", err)))?;
+
+    let NetworkPeer {
+        from_machine,
+        to_machine,
+    } = peer;
+
+    let remote = options.remote;
+    thread::spawn(move || {
+        let mut buf = [0; 65535];
+        loop {
+            match receiver.recv_from(&mut buf) {
+                Ok((count, source)) => {
+                    // Anyone could send to the socket, so only the other end of the tunnel is listened to
+                    if source != remote {
+                        log::debug!("udp tunnel: ignoring a datagram from {}", source);
+                        continue;
+                    }
+                    if !to_machine.deliver(buf[..count].to_vec()) {
+                        return;
+                    }
+                },
+                Err(err) => {
+                    log::error!("udp tunnel: error receiving: {}", err);
+                    return;
+                },
+            }
+        }
+    });
+
+    thread::spawn(move || {
+        while let Ok(packet) = from_machine.recv() {
+            if let Err(err) = sender.send_to(&packet, remote) {
+                log::debug!("udp tunnel: error sending to {}: {}", remote, err);
+            }
+        }
+    });
+
+    Ok(())
+}
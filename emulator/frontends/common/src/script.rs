@@ -0,0 +1,289 @@
+//! Timed event scripts for automated demos and tests
+//!
+//! A script is a TOML file with a list of events, each of which performs an action once the emulated time
+//! reaches the given number of seconds.  The events are run by a [`ScriptDriver`] device added to the system,
+//! which sends inputs through the same queues the frontend uses, and accesses memory through the system bus.
+//!
+//! ```toml
+//! [[event]]
+//! at = 1.5
+//! action = "key"
+//! key = "Enter"
+//! pressed = true
+//!
+//! [[event]]
+//! at = 2.0
+//! action = "button"
+//! controller = "A"
+//! button = "start"
+//! pressed = true
+//!
+//! [[event]]
+//! at = 5.0
+//! action = "assert_memory"
+//! address = 0xff0000
+//! data = [0x12, 0x34]
+//!
+//! [[event]]
+//! at = 5.0
+//! action = "exit"
+//! code = 0
+//! ```
+//!
+//! The other actions are `axis` (with `controller`, `axis`, and `value`), `paddle` (with `controller` and
+//! `position`), `dial` (with `controller` and `delta`), `write_memory` (with `address` and `data`), `print`
+//! (with `message`), and `command` (with `device` and `args`), which runs a debugger command on the named device.
+
+use std::fs;
+
+use femtos::{Instant, Duration};
+use serde::Deserialize;
+
+use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable};
+use moa_host::{Key, KeyEvent, ControllerDevice, ControllerAxis, ControllerInput, ControllerEvent, EventSender};
+
+use crate::keymap::Button;
+
+
+/// How long to wait before checking again after the last event has run
+const IDLE_INTERVAL_US: u64 = 1_000_000;
+
+const DEV_NAME: &str = "script";
+
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Script {
+    #[serde(rename = "event", default)]
+    events: Vec<ScriptEvent>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ScriptEvent {
+    /// The emulated time, in seconds, when the action is performed
+    at: f64,
+    #[serde(flatten)]
+    action: ScriptAction,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum ScriptAction {
+    Key {
+        key: Key,
+        #[serde(default = "pressed_default")]
+        pressed: bool,
+    },
+    Button {
+        #[serde(default = "controller_default")]
+        controller: ControllerDevice,
+        button: Button,
+        #[serde(default = "pressed_default")]
+        pressed: bool,
+    },
+    Axis {
+        #[serde(default = "controller_default")]
+        controller: ControllerDevice,
+        axis: ControllerAxis,
+        value: i16,
+    },
+    Paddle {
+        #[serde(default = "controller_default")]
+        controller: ControllerDevice,
+        position: u16,
+    },
+    Dial {
+        #[serde(default = "controller_default")]
+        controller: ControllerDevice,
+        delta: i16,
+    },
+    WriteMemory {
+        address: Address,
+        data: Vec<u8>,
+    },
+    AssertMemory {
+        address: Address,
+        data: Vec<u8>,
+    },
+    Print {
+        message: String,
+    },
+    Command {
+        device: String,
+        args: Vec<String>,
+    },
+    Exit {
+        #[serde(default)]
+        code: i32,
+    },
+}
+
+fn pressed_default() -> bool {
+    true
+}
+
+fn controller_default() -> ControllerDevice {
+    ControllerDevice::A
+}
+
+
+/// A device which performs the actions of a script at their scheduled emulated times
+pub struct ScriptDriver {
+    events: Vec<(Instant, ScriptAction)>,
+    next: usize,
+    keyboard: Option<EventSender<KeyEvent>>,
+    controllers: Option<EventSender<ControllerEvent>>,
+}
+
+impl ScriptDriver {
+    pub fn load(filename: &str) -> Result<Self, Error> {
+        let contents =
+            fs::read_to_string(filename).map_err(|err| Error::new(format!("{}: error reading {}: {}", DEV_NAME, filename, err)))?;
+        Self::parse(&contents).map_err(|err| Error::new(format!("{}: {}", filename, err)))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        let script: Script = toml::from_str(contents).map_err(|err| Error::new(err.to_string()))?;
+
+        let mut events = vec![];
+        for event in script.events {
+            if !event.at.is_finite() || event.at < 0.0 {
+                return Err(Error::new(format!("invalid event time {}", event.at)));
+            }
+            let at = Instant::START + Duration::from_nanos((event.at * 1_000_000_000.0) as u64);
+            events.push((at, event.action));
+        }
+        // Events at the same time are run in the order they appear in the script
+        events.sort_by_key(|(at, _)| *at);
+
+        Ok(Self {
+            events,
+            next: 0,
+            keyboard: None,
+            controllers: None,
+        })
+    }
+
+    /// Send key actions to the given keyboard queue, usually the one registered with the frontend by the machine
+    pub fn with_keyboard(mut self, keyboard: Option<EventSender<KeyEvent>>) -> Self {
+        self.keyboard = keyboard;
+        self
+    }
+
+    /// Send button actions to the given controller queue, usually the one registered with the frontend by the machine
+    pub fn with_controllers(mut self, controllers: Option<EventSender<ControllerEvent>>) -> Self {
+        self.controllers = controllers;
+        self
+    }
+
+    fn send_controller(&self, controller: ControllerDevice, input: ControllerInput) -> Result<(), Error> {
+        let controllers = self
+            .controllers
+            .as_ref()
+            .ok_or_else(|| Error::new(format!("{}: this machine has no controllers", DEV_NAME)))?;
+        controllers.send(ControllerEvent::new(controller, input));
+        Ok(())
+    }
+
+    fn run_action(&mut self, system: &System, action: &ScriptAction) -> Result<(), Error> {
+        match action {
+            ScriptAction::Key {
+                key,
+                pressed,
+            } => {
+                let keyboard = self
+                    .keyboard
+                    .as_ref()
+                    .ok_or_else(|| Error::new(format!("{}: this machine has no keyboard", DEV_NAME)))?;
+                keyboard.send(KeyEvent::new(*key, *pressed));
+            },
+            ScriptAction::Button {
+                controller,
+                button,
+                pressed,
+            } => {
+                self.send_controller(*controller, button.input(*pressed))?;
+            },
+            ScriptAction::Axis {
+                controller,
+                axis,
+                value,
+            } => {
+                self.send_controller(*controller, ControllerInput::Axis(*axis, *value))?;
+            },
+            ScriptAction::Paddle {
+                controller,
+                position,
+            } => {
+                self.send_controller(*controller, ControllerInput::Paddle(*position))?;
+            },
+            ScriptAction::Dial {
+                controller,
+                delta,
+            } => {
+                self.send_controller(*controller, ControllerInput::Dial(*delta))?;
+            },
+            ScriptAction::WriteMemory {
+                address,
+                data,
+            } => {
+                system.get_bus().write(system.clock, *address, data)?;
+            },
+            ScriptAction::AssertMemory {
+                address,
+                data,
+            } => {
+                let mut actual = vec![0; data.len()];
+                system.get_bus().read(system.clock, *address, &mut actual)?;
+                if actual != *data {
+                    return Err(Error::Assertion(format!("expected {:02x?} at {:#010x} but found {:02x?}", data, address, actual)));
+                }
+            },
+            ScriptAction::Print {
+                message,
+            } => {
+                println!("{}", message);
+            },
+            ScriptAction::Command {
+                device,
+                args,
+            } => {
+                let device = system.get_device(device)?;
+                let mut device = device.borrow_mut();
+                let debuggable = device
+                    .as_debuggable()
+                    .ok_or_else(|| Error::new(format!("{}: device doesn't support commands", DEV_NAME)))?;
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                debuggable.run_command(system, &args)?;
+            },
+            ScriptAction::Exit {
+                code,
+            } => {
+                return Err(Error::exit(*code));
+            },
+        }
+        Ok(())
+    }
+}
+
+impl Steppable for ScriptDriver {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        while self.next < self.events.len() && self.events[self.next].0 <= system.clock {
+            let action = self.events[self.next].1.clone();
+            self.next += 1;
+            log::debug!(target: DEV_NAME, "running {:?}", action);
+            self.run_action(system, &action)?;
+        }
+
+        match self.events.get(self.next) {
+            Some((at, _)) => Ok(at.duration_since(system.clock)),
+            None => Ok(Duration::from_micros(IDLE_INTERVAL_US)),
+        }
+    }
+}
+
+impl Transmutable for ScriptDriver {
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+}
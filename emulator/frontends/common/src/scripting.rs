@@ -0,0 +1,359 @@
+//! Rhai scripts which hook memory accesses and timers, to automate a machine or stub out a peripheral
+//!
+//! When the script is loaded, its top level code is run once to register the hooks, which are then called
+//! while the machine runs:
+//!
+//! ```rhai
+//! // Called for each byte read from 0xF00000 to 0xF0000F, which should be an unmapped range
+//! on_read(0xF00000, 0x10, |addr| {
+//!     if addr == 0xF00001 { 0x80 } else { 0 }
+//! });
+//!
+//! // Called for each byte written to the same range
+//! on_write(0xF00000, 0x10, |addr, value| {
+//!     print(`wrote ${value} to ${addr}`);
+//! });
+//!
+//! // Called every 10ms of emulated time
+//! every(0.010, || {
+//!     write_u8(0x100000, read_u8(0x100000) + 1);
+//!     if register("pc") == 0x1000 {
+//!         exit(0);
+//!     }
+//! });
+//! ```
+//!
+//! The functions that scripts can call are `on_read`, `on_write`, `every`, `read_u8`, `read_u16`, `read_u32`,
+//! `write_u8`, `write_u16`, `write_u32` (which are big endian), `register` (which returns the value of a cpu
+//! register), `time` (which returns the emulated time in seconds), and `exit`.  The memory functions can't be
+//! used from inside of read and write hooks, because the bus is in use by the access that called the hook.
+
+use std::fs;
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+
+use femtos::{Instant, Duration};
+use rhai::{Engine, EvalAltResult, FnPtr, FuncArgs, Dynamic, Variant, AST};
+
+use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable, Bus, Device};
+
+
+/// How long to wait before checking again when the script has no timers
+const IDLE_INTERVAL_US: u64 = 1_000_000;
+
+const DEV_NAME: &str = "rhai";
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+
+struct MemoryHook {
+    address: Address,
+    size: usize,
+    on_read: Option<FnPtr>,
+    on_write: Option<FnPtr>,
+}
+
+struct Timer {
+    interval: Duration,
+    next: Instant,
+    callback: FnPtr,
+}
+
+/// The state shared between the functions registered with the engine, and the devices which call the hooks
+struct ScriptContext {
+    /// Hooks can only be added while the top level of the script is being run, before the devices are added
+    loading: Cell<bool>,
+    hooks: RefCell<Vec<MemoryHook>>,
+    timers: RefCell<Vec<Timer>>,
+    clock: Cell<Instant>,
+    exit_code: Cell<Option<i32>>,
+    bus: RefCell<Option<Rc<RefCell<Bus>>>>,
+    debuggables: RefCell<Vec<Device>>,
+}
+
+impl Default for ScriptContext {
+    fn default() -> Self {
+        Self {
+            loading: Cell::new(false),
+            hooks: RefCell::new(vec![]),
+            timers: RefCell::new(vec![]),
+            clock: Cell::new(Instant::START),
+            exit_code: Cell::new(None),
+            bus: RefCell::new(None),
+            debuggables: RefCell::new(vec![]),
+        }
+    }
+}
+
+impl ScriptContext {
+    fn add_hook(&self, address: i64, size: i64, on_read: Option<FnPtr>, on_write: Option<FnPtr>) -> ScriptResult<()> {
+        if !self.loading.get() {
+            return Err("hooks can only be added by the top level of the script".into());
+        }
+        if address < 0 || size <= 0 {
+            return Err(format!("invalid hook range {:#x} with size {:#x}", address, size).into());
+        }
+        let (address, size) = (address as Address, size as usize);
+
+        let mut hooks = self.hooks.borrow_mut();
+        if let Some(hook) = hooks.iter_mut().find(|hook| hook.address == address && hook.size == size) {
+            hook.on_read = on_read.or(hook.on_read.take());
+            hook.on_write = on_write.or(hook.on_write.take());
+            return Ok(());
+        }
+        let overlaps = hooks
+            .iter()
+            .any(|hook| address < hook.address + hook.size as Address && hook.address < address + size as Address);
+        if overlaps {
+            return Err(format!("hook at {:#x} overlaps another hook with a different range", address).into());
+        }
+        hooks.push(MemoryHook {
+            address,
+            size,
+            on_read,
+            on_write,
+        });
+        Ok(())
+    }
+
+    fn with_bus<T>(&self, f: impl FnOnce(&mut Bus, Instant) -> Result<T, Error>) -> ScriptResult<T> {
+        let bus = self.bus.borrow();
+        let bus = bus.as_ref().ok_or("memory can't be accessed while the script is loading")?;
+        let mut bus = bus
+            .try_borrow_mut()
+            .map_err(|_| "memory can't be accessed from inside a read or write hook")?;
+        f(&mut bus, self.clock.get()).map_err(|err| err.to_string().into())
+    }
+
+    fn register(&self, name: &str) -> ScriptResult<i64> {
+        for device in self.debuggables.borrow().iter() {
+            let mut device = device
+                .try_borrow_mut()
+                .map_err(|_| "registers can't be read from inside a read or write hook")?;
+            if let Some(value) = device.as_debuggable().and_then(|debuggable| debuggable.get_register(name)) {
+                return Ok(value as i64);
+            }
+        }
+        Err(format!("no register named {}", name).into())
+    }
+}
+
+
+/// A Rhai script, and the engine used to run its hooks
+pub struct RhaiScript {
+    engine: Engine,
+    ast: AST,
+    context: Rc<ScriptContext>,
+}
+
+impl RhaiScript {
+    pub fn load(filename: &str) -> Result<Self, Error> {
+        let contents =
+            fs::read_to_string(filename).map_err(|err| Error::new(format!("{}: error reading {}: {}", DEV_NAME, filename, err)))?;
+        Self::parse(&contents).map_err(|err| Error::new(format!("{}: {}", filename, err)))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        let context = Rc::new(ScriptContext::default());
+        let engine = create_engine(&context);
+        let ast = engine.compile(contents).map_err(|err| Error::new(err.to_string()))?;
+
+        Ok(Self {
+            engine,
+            ast,
+            context,
+        })
+    }
+
+    /// Run the top level of the script to register its hooks, and then add the devices which call them to the
+    /// system.  The read and write hooks are mapped onto the system's main bus
+    pub fn install(self, system: &mut System) -> Result<(), Error> {
+        self.context.loading.set(true);
+        let result = self.engine.run_ast(&self.ast);
+        self.context.loading.set(false);
+        if let Some(code) = self.context.exit_code.take() {
+            return Err(Error::exit(code));
+        }
+        result.map_err(|err| Error::new(format!("{}: {}", DEV_NAME, err)))?;
+
+        self.context.clock.set(system.clock);
+        *self.context.bus.borrow_mut() = Some(system.bus.clone());
+        *self.context.debuggables.borrow_mut() = system.debuggables.clone();
+        for timer in self.context.timers.borrow_mut().iter_mut() {
+            timer.next = system.clock + timer.interval;
+        }
+
+        let hooks: Vec<MemoryHook> = self.context.hooks.borrow_mut().drain(..).collect();
+        let runner = Rc::new(self);
+        for (i, hook) in hooks.into_iter().enumerate() {
+            let address = hook.address;
+            let region = ScriptedRegion {
+                hook,
+                script: runner.clone(),
+            };
+            system.add_peripheral(&format!("{}_hook{}", DEV_NAME, i), address, Device::new(region))?;
+        }
+        system.add_device(
+            DEV_NAME,
+            Device::new(ScriptTimers {
+                script: runner,
+            }),
+        )?;
+        Ok(())
+    }
+
+    fn call<T: Variant + Clone>(&self, clock: Instant, callback: &FnPtr, args: impl FuncArgs) -> Result<T, Error> {
+        self.context.clock.set(clock);
+        let result = callback.call::<T>(&self.engine, &self.ast, args);
+        if let Some(code) = self.context.exit_code.take() {
+            return Err(Error::exit(code));
+        }
+        result.map_err(|err| Error::new(format!("{}: {}", DEV_NAME, err)))
+    }
+}
+
+fn create_engine(context: &Rc<ScriptContext>) -> Engine {
+    let mut engine = Engine::new();
+
+    let ctx = context.clone();
+    engine.register_fn("on_read", move |address: i64, size: i64, callback: FnPtr| {
+        ctx.add_hook(address, size, Some(callback), None)
+    });
+    let ctx = context.clone();
+    engine.register_fn("on_write", move |address: i64, size: i64, callback: FnPtr| {
+        ctx.add_hook(address, size, None, Some(callback))
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("every", move |seconds: f64, callback: FnPtr| -> ScriptResult<()> {
+        if !ctx.loading.get() {
+            return Err("timers can only be added by the top level of the script".into());
+        }
+        if !seconds.is_finite() || seconds <= 0.0 {
+            return Err(format!("invalid timer interval {}", seconds).into());
+        }
+        let interval = Duration::from_nanos((seconds * 1_000_000_000.0) as u64);
+        ctx.timers.borrow_mut().push(Timer {
+            interval,
+            next: Instant::START,
+            callback,
+        });
+        Ok(())
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("read_u8", move |address: i64| {
+        ctx.with_bus(|bus, clock| bus.read_u8(clock, address as Address).map(|value| value as i64))
+    });
+    let ctx = context.clone();
+    engine.register_fn("read_u16", move |address: i64| {
+        ctx.with_bus(|bus, clock| bus.read_beu16(clock, address as Address).map(|value| value as i64))
+    });
+    let ctx = context.clone();
+    engine.register_fn("read_u32", move |address: i64| {
+        ctx.with_bus(|bus, clock| bus.read_beu32(clock, address as Address).map(|value| value as i64))
+    });
+    let ctx = context.clone();
+    engine.register_fn("write_u8", move |address: i64, value: i64| {
+        ctx.with_bus(|bus, clock| bus.write_u8(clock, address as Address, value as u8))
+    });
+    let ctx = context.clone();
+    engine.register_fn("write_u16", move |address: i64, value: i64| {
+        ctx.with_bus(|bus, clock| bus.write_beu16(clock, address as Address, value as u16))
+    });
+    let ctx = context.clone();
+    engine.register_fn("write_u32", move |address: i64, value: i64| {
+        ctx.with_bus(|bus, clock| bus.write_beu32(clock, address as Address, value as u32))
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("register", move |name: &str| ctx.register(name));
+
+    let ctx = context.clone();
+    engine.register_fn("time", move || ctx.clock.get().as_duration().as_nanos() as f64 / 1_000_000_000.0);
+
+    let ctx = context.clone();
+    engine.register_fn("exit", move |code: i64| -> ScriptResult<()> {
+        ctx.exit_code.set(Some(code as i32));
+        Err("exit".into())
+    });
+
+    engine
+}
+
+
+/// A device on the bus which calls the read and write hooks for its range of addresses
+struct ScriptedRegion {
+    hook: MemoryHook,
+    script: Rc<RhaiScript>,
+}
+
+impl Addressable for ScriptedRegion {
+    fn size(&self) -> usize {
+        self.hook.size
+    }
+
+    fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        for (i, byte) in data.iter_mut().enumerate() {
+            let address = (self.hook.address + addr + i as Address) as i64;
+            *byte = match &self.hook.on_read {
+                Some(callback) => self.script.call::<i64>(clock, callback, (address,))? as u8,
+                None => 0,
+            };
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        if let Some(callback) = &self.hook.on_write {
+            for (i, byte) in data.iter().enumerate() {
+                let address = (self.hook.address + addr + i as Address) as i64;
+                self.script.call::<Dynamic>(clock, callback, (address, *byte as i64))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Transmutable for ScriptedRegion {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+}
+
+
+/// A device which calls the timer hooks at their intervals
+struct ScriptTimers {
+    script: Rc<RhaiScript>,
+}
+
+impl Steppable for ScriptTimers {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        let context = &self.script.context;
+        let count = context.timers.borrow().len();
+        for i in 0..count {
+            let callback = {
+                let mut timers = context.timers.borrow_mut();
+                let timer = &mut timers[i];
+                if timer.next > system.clock {
+                    continue;
+                }
+                timer.next = system.clock + timer.interval;
+                timer.callback.clone()
+            };
+            self.script.call::<Dynamic>(system.clock, &callback, ())?;
+        }
+
+        let next = context.timers.borrow().iter().map(|timer| timer.next).min();
+        match next {
+            Some(next) => Ok(next.duration_since(system.clock)),
+            None => Ok(Duration::from_micros(IDLE_INTERVAL_US)),
+        }
+    }
+}
+
+impl Transmutable for ScriptTimers {
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+}
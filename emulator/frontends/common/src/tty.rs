@@ -1,13 +1,22 @@
+//! Connections for the emulated serial ports
+//!
+//! Each serial port can be connected to one of the following, given as `NAME=TRANSPORT` on the command line:
+//! * `pty` opens a pseudo-terminal, which is only available on unix, and is the default there
+//! * `tcp:ADDR` listens for a connection on a TCP address, such as `tcp:127.0.0.1:4321`, and can be opened with
+//!   `telnet` or `pyserial-miniterm socket://127.0.0.1:4321`.  Output is dropped while nothing is connected
+//! * `pipe:PATH` reads from the named pipe `PATH.in` and writes to `PATH.out`, which must both exist (they can be
+//!   made with `mkfifo`, or with `\\.\pipe\` names on Windows)
+
+use std::fmt;
 use std::thread;
-use std::sync::mpsc;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
 use std::time::Duration;
-use std::io::{Read, Write};
-use std::os::unix::io::AsRawFd;
-
-use nix::fcntl::OFlag;
-use nix::pty::{self, PtyMaster};
-use nix::fcntl::{fcntl, FcntlArg};
+use std::io::{self, Read, Write};
+use std::fs::{File, OpenOptions};
+use std::net::{TcpListener, TcpStream};
 
+use moa_core::Error;
 use moa_host::Tty;
 
 
@@ -17,10 +26,91 @@ pub enum SimplePtyError {
     PtsName,
 }
 
+/// The way an emulated serial port is connected to the host
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum SerialTransport {
+    #[default]
+    Pty,
+    Tcp(String),
+    Pipe(String),
+}
+
+impl FromStr for SerialTransport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "pty" => Ok(SerialTransport::Pty),
+            Some(("tcp", addr)) if !addr.is_empty() => Ok(SerialTransport::Tcp(addr.to_string())),
+            Some(("pipe", path)) if !path.is_empty() => Ok(SerialTransport::Pipe(path.to_string())),
+            _ => Err(format!("{} is not pty, tcp:ADDR, or pipe:PATH", s)),
+        }
+    }
+}
+
+impl fmt::Display for SerialTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerialTransport::Pty => write!(f, "pty"),
+            SerialTransport::Tcp(addr) => write!(f, "tcp:{}", addr),
+            SerialTransport::Pipe(path) => write!(f, "pipe:{}", path),
+        }
+    }
+}
+
+/// Parse a `NAME=TRANSPORT` argument that connects the named serial port
+pub fn parse_serial_port(arg: &str) -> Result<(String, SerialTransport), String> {
+    let (name, transport) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("{} should be given as NAME=TRANSPORT", arg))?;
+    Ok((name.to_string(), transport.parse()?))
+}
+
+/// The transports to connect each of the machine's serial ports to, by the name of the port
+#[derive(Clone, Debug, Default)]
+pub struct SerialPorts {
+    ports: Vec<(String, SerialTransport)>,
+}
+
+impl SerialPorts {
+    pub fn new(ports: Vec<(String, SerialTransport)>) -> Self {
+        Self {
+            ports,
+        }
+    }
+
+    pub fn transport(&self, name: &str) -> SerialTransport {
+        self.ports
+            .iter()
+            .find(|(port, _)| port == name)
+            .map(|(_, transport)| transport.clone())
+            .unwrap_or_default()
+    }
+
+    /// Open the connection for the named serial port, which is a pty unless another transport was given for it
+    pub fn open(&self, name: &str) -> Result<SimplePty, Error> {
+        let transport = self.transport(name);
+        let pty = match &transport {
+            SerialTransport::Pty => SimplePty::open().map_err(|err| Error::new(format!("error opening pty: {:?}", err))),
+            SerialTransport::Tcp(addr) => SimplePty::listen(addr),
+            SerialTransport::Pipe(path) => SimplePty::open_pipe(path),
+        }
+        .map_err(|err| Error::new(format!("serial port {} ({}): {}", name, transport, err)))?;
+        log::info!("serial port {} is connected to {}", name, pty.name);
+        Ok(pty)
+    }
+}
+
+
+type InputNotify = Arc<Mutex<Option<Box<dyn Fn() + Send>>>>;
+
+/// An emulated serial port's end of a connection, which passes data to and from the threads that handle the
+/// host's end
 pub struct SimplePty {
     pub name: String,
     input: mpsc::Receiver<u8>,
     output: mpsc::Sender<u8>,
+    notify: InputNotify,
 }
 
 impl SimplePty {
@@ -29,10 +119,15 @@ impl SimplePty {
             name,
             input,
             output,
+            notify: Arc::new(Mutex::new(None)),
         }
     }
 
+    #[cfg(unix)]
     pub fn open() -> Result<SimplePty, SimplePtyError> {
+        use nix::fcntl::OFlag;
+        use nix::pty;
+
         let pty = pty::posix_openpt(OFlag::O_RDWR)
             .and_then(|pty| {
                 pty::grantpt(&pty)?;
@@ -46,23 +141,118 @@ impl SimplePty {
         let (output_tx, output_rx) = mpsc::channel();
         let shared = SimplePty::new(name.clone(), input_rx, output_tx);
 
-        SimplePty::spawn_poller(pty, name, input_tx, output_rx);
+        SimplePty::spawn_poller(pty, name, input_tx, output_rx, shared.notify.clone());
+        Ok(shared)
+    }
+
+    #[cfg(not(unix))]
+    pub fn open() -> Result<SimplePty, SimplePtyError> {
+        Err(SimplePtyError::Open)
+    }
+
+    /// Listen for TCP connections on the given address, one at a time.  The name is a pyserial URL for the address
+    pub fn listen(addr: &str) -> Result<SimplePty, Error> {
+        let listener = TcpListener::bind(addr).map_err(|err| Error::new(format!("error listening on {}: {}", addr, err)))?;
+        let local = listener.local_addr().map_err(|err| Error::new(err.to_string()))?;
+
+        let (input_tx, input_rx) = mpsc::channel();
+        let (output_tx, output_rx) = mpsc::channel();
+        let shared = SimplePty::new(format!("socket://{}", local), input_rx, output_tx);
+
+        let notify = shared.notify.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        log::warn!("serial: error accepting a connection on {}: {}", local, err);
+                        continue;
+                    },
+                };
+                log::info!("serial: {} connected to {}", stream.peer_addr().map(|a| a.to_string()).unwrap_or_default(), local);
+
+                // Anything written while nothing was connected is dropped, rather than sent all at once
+                while output_rx.try_recv().is_ok() {}
+                if let Err(err) = poll_socket(stream, &input_tx, &output_rx, &notify) {
+                    log::warn!("serial: connection to {} closed: {}", local, err);
+                }
+            }
+        });
+        Ok(shared)
+    }
+
+    /// Read from the named pipe `PATH.in` and write to `PATH.out`, opening them again if they are closed
+    pub fn open_pipe(path: &str) -> Result<SimplePty, Error> {
+        let (input_path, output_path) = (format!("{}.in", path), format!("{}.out", path));
+        for path in [&input_path, &output_path] {
+            // Opening a pipe waits for the other end, so they are only opened by the threads below
+            if !path.starts_with(r"\\.\pipe\") && std::fs::metadata(path).is_err() {
+                return Err(Error::new(format!("{} doesn't exist", path)));
+            }
+        }
+
+        let (input_tx, input_rx) = mpsc::channel();
+        let (output_tx, output_rx) = mpsc::channel::<u8>();
+        let shared = SimplePty::new(path.to_string(), input_rx, output_tx);
+
+        let notify = shared.notify.clone();
+        thread::spawn(move || {
+            let mut buf = [0; 256];
+            loop {
+                let mut pipe = reopen(&input_path, OpenOptions::new().read(true));
+                loop {
+                    match pipe.read(&mut buf) {
+                        Ok(count) if count > 0 => forward_input(&buf[..count], &input_tx, &notify),
+                        // The writer closed its end, so wait for the next one
+                        Ok(_) => break,
+                        Err(err) => {
+                            log::warn!("serial: error reading from {}: {}", input_path, err);
+                            break;
+                        },
+                    }
+                }
+            }
+        });
+
+        thread::spawn(move || {
+            let mut pipe = None;
+            while let Ok(data) = output_rx.recv() {
+                let file = pipe.get_or_insert_with(|| reopen(&output_path, OpenOptions::new().write(true)));
+                if let Err(err) = file.write_all(&[data]) {
+                    log::warn!("serial: error writing to {}: {}", output_path, err);
+                    pipe = None;
+                }
+            }
+        });
         Ok(shared)
     }
 
-    fn spawn_poller(mut pty: PtyMaster, name: String, input_tx: mpsc::Sender<u8>, output_rx: mpsc::Receiver<u8>) {
+    #[cfg(unix)]
+    fn spawn_poller(
+        mut pty: nix::pty::PtyMaster,
+        name: String,
+        input_tx: mpsc::Sender<u8>,
+        output_rx: mpsc::Receiver<u8>,
+        notify: InputNotify,
+    ) {
+        use std::os::unix::io::AsRawFd;
+        use nix::fcntl::{fcntl, FcntlArg, OFlag};
+
         thread::spawn(move || {
             println!("pty: spawned reader for {}", name);
 
             fcntl(pty.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).unwrap();
 
-            let mut buf = [0; 1];
+            let mut buf = [0; 256];
             loop {
+                let mut received = false;
                 match pty.read(&mut buf) {
-                    Ok(_) => {
-                        input_tx.send(buf[0]).unwrap();
+                    Ok(count) if count > 0 => {
+                        forward_input(&buf[..count], &input_tx, &notify);
+                        received = true;
                     },
-                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {},
+                    Ok(_) => {},
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {},
                     Err(err) => {
                         println!("ERROR: {:?}", err);
                     },
@@ -72,12 +262,80 @@ impl SimplePty {
                     pty.write_all(&[data]).unwrap();
                 }
 
-                thread::sleep(Duration::from_millis(10));
+                // Check again right away if there was input, in case there's more waiting
+                if !received {
+                    thread::sleep(Duration::from_millis(10));
+                }
             }
         });
     }
 }
 
+fn forward_input(data: &[u8], input_tx: &mpsc::Sender<u8>, notify: &InputNotify) {
+    for byte in data {
+        // The emulated device is gone if the channel is closed, and there's nothing left to do
+        let _ = input_tx.send(*byte);
+    }
+    if let Some(notify) = notify.lock().unwrap().as_ref() {
+        notify();
+    }
+}
+
+/// Pass data between a TCP connection and the emulated device until the connection is closed
+fn poll_socket(
+    mut stream: TcpStream,
+    input_tx: &mpsc::Sender<u8>,
+    output_rx: &mpsc::Receiver<u8>,
+    notify: &InputNotify,
+) -> io::Result<()> {
+    stream.set_nonblocking(true)?;
+    stream.set_nodelay(true)?;
+
+    let mut buf = [0; 256];
+    loop {
+        let mut received = false;
+        match stream.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(count) => {
+                forward_input(&buf[..count], input_tx, notify);
+                received = true;
+            },
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {},
+            Err(err) => return Err(err),
+        }
+
+        let output: Vec<u8> = output_rx.try_iter().collect();
+        if !output.is_empty() {
+            // The socket is non-blocking, so a full buffer has to be waited out here
+            let mut written = 0;
+            while written < output.len() {
+                match stream.write(&output[written..]) {
+                    Ok(count) => written += count,
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(1)),
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        if !received {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// Open a pipe, trying again until it succeeds, since the other end may not have been created yet
+fn reopen(path: &str, options: &OpenOptions) -> File {
+    loop {
+        match options.open(path) {
+            Ok(file) => return file,
+            Err(err) => {
+                log::debug!("serial: unable to open {}: {}", path, err);
+                thread::sleep(Duration::from_millis(500));
+            },
+        }
+    }
+}
+
 impl Tty for SimplePty {
     fn device_name(&self) -> String {
         self.name.clone()
@@ -94,4 +352,9 @@ impl Tty for SimplePty {
         self.output.send(output).unwrap();
         true
     }
+
+    fn on_input(&mut self, notify: Box<dyn Fn() + Send>) -> bool {
+        *self.notify.lock().unwrap() = Some(notify);
+        true
+    }
 }
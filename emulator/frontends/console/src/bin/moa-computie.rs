@@ -12,15 +12,26 @@ fn main() {
                 .value_name("FILE")
                 .help("ROM file to load at the start of memory"),
         )
+        .arg(
+            Arg::new("overlay")
+                .long("overlay")
+                .value_name("FILE")
+                .help("Store the sectors written to the disk in this file, instead of only in memory"),
+        )
         .get_matches();
 
     let mut options = ComputieOptions::default();
     if let Some(filename) = matches.get_one::<String>("ROM") {
         options.rom = filename.to_string();
     }
+    options.disk_overlay = matches.get_one::<String>("overlay").cloned();
+
+    if ConsoleFrontend::introspect(&matches, &options) {
+        return;
+    }
 
-    let frontend = ConsoleFrontend;
+    let frontend = ConsoleFrontend::new(&matches);
 
-    let system = build_computie(&frontend, options).unwrap();
-    frontend.start(matches, system);
+    let system = build_computie(&frontend, options);
+    frontend.run(matches, system);
 }
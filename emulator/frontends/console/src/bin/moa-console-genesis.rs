@@ -1,20 +1,63 @@
-use clap::{Arg};
+use clap::{Arg, ArgAction};
 
 use moa_console::ConsoleFrontend;
+use moa_core::VideoStandard;
 use moa_systems_genesis::{build_genesis, SegaGenesisOptions};
 
 fn main() {
     let matches = ConsoleFrontend::args("Sega Genesis/Mega Drive Emulator")
         .arg(Arg::new("ROM").help("ROM file to load (must be flat binary)"))
+        .arg(
+            Arg::new("rom-db")
+                .long("rom-db")
+                .help("No-Intro DAT file to use for identifying the ROM"),
+        )
+        .arg(
+            Arg::new("refresh-cycles")
+                .long("refresh-cycles")
+                .action(ArgAction::SetTrue)
+                .help("Emulate the 68000 cycles lost to DRAM refresh, which some cycle-counted code depends on"),
+        )
+        .arg(
+            Arg::new("interleave")
+                .long("interleave")
+                .value_name("MICROSECONDS")
+                .value_parser(clap::value_parser!(u64))
+                .help("Run each cpu up to this far ahead of the other devices before switching, for speed over accuracy"),
+        )
+        .arg(
+            Arg::new("threaded-audio")
+                .long("threaded-audio")
+                .action(ArgAction::SetTrue)
+                .help("Run the sound chips on threads of their own, in parallel with the cpus"),
+        )
+        .arg(
+            Arg::new("video-standard")
+                .long("video-standard")
+                .value_name("STANDARD")
+                .value_parser(clap::value_parser!(VideoStandard))
+                .help("Build an NTSC or a PAL console, which sets the frame rate and the clock speeds (ntsc or pal)"),
+        )
         .get_matches();
 
-    let mut frontend = ConsoleFrontend;
+    let mut frontend = ConsoleFrontend::default();
 
     let mut options = SegaGenesisOptions::default();
     if let Some(filename) = matches.get_one::<String>("ROM") {
         options.rom = filename.to_string();
     }
+    options.rom_database = matches.get_one::<String>("rom-db").cloned();
+    options.refresh_cycles = matches.get_flag("refresh-cycles");
+    options.interleave_us = matches.get_one::<u64>("interleave").cloned();
+    options.threaded_audio = matches.get_flag("threaded-audio");
+    if let Some(standard) = matches.get_one::<VideoStandard>("video-standard") {
+        options.video_standard = *standard;
+    }
+
+    if ConsoleFrontend::introspect(&matches, &options) {
+        return;
+    }
 
-    let system = build_genesis(&mut frontend, options).unwrap();
-    frontend.start(matches, system);
+    let system = build_genesis(&mut frontend, options);
+    frontend.run(matches, system);
 }
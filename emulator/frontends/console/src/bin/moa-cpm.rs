@@ -0,0 +1,35 @@
+use clap::{Arg, ArgAction};
+
+use moa_console::ConsoleFrontend;
+use moa_systems_cpm::{build_cpm, CpmOptions};
+
+fn main() {
+    let matches = ConsoleFrontend::args("CP/M 2.2 Emulator")
+        .arg(
+            Arg::new("DISK")
+                .action(ArgAction::Append)
+                .help("Disk image files to use for drives A, B, and so on, in the z80pack format"),
+        )
+        .arg(
+            Arg::new("i8080")
+                .long("i8080")
+                .action(ArgAction::SetTrue)
+                .help("Emulate an Intel 8080 instead of a Z80"),
+        )
+        .get_matches();
+
+    let mut options = CpmOptions::default();
+    if let Some(disks) = matches.get_many::<String>("DISK") {
+        options.disks = disks.cloned().collect();
+    }
+    options.i8080 = matches.get_flag("i8080");
+
+    if ConsoleFrontend::introspect(&matches, &options) {
+        return;
+    }
+
+    let frontend = ConsoleFrontend::new(&matches);
+
+    let system = build_cpm(&frontend, options);
+    frontend.run(matches, system);
+}
@@ -0,0 +1,24 @@
+use clap::Arg;
+
+use moa_console::ConsoleFrontend;
+use moa_systems_custom::{build_custom, CustomOptions};
+
+fn main() {
+    let matches = ConsoleFrontend::args("Custom Machine Emulator")
+        .arg(Arg::new("DEFINITION").help("TOML file describing the cpu, memory, and devices of the machine"))
+        .get_matches();
+
+    let mut options = CustomOptions::default();
+    if let Some(filename) = matches.get_one::<String>("DEFINITION") {
+        options.definition = filename.clone();
+    }
+
+    if ConsoleFrontend::introspect(&matches, &options) {
+        return;
+    }
+
+    let frontend = ConsoleFrontend::new(&matches);
+
+    let system = build_custom(&frontend, options);
+    frontend.run(matches, system);
+}
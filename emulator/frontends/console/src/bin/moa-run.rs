@@ -0,0 +1,118 @@
+use clap::{Arg, ArgAction};
+use femtos::Frequency;
+
+use moa_core::Address;
+use moa_console::ConsoleFrontend;
+use moa_systems_custom::{build_bare, BareOptions, CpuKind, DeviceKind};
+
+fn main() {
+    let matches = ConsoleFrontend::args("Bare ROM Runner")
+        .arg(
+            Arg::new("cpu")
+                .long("cpu")
+                .value_name("TYPE")
+                .value_parser(parse_cpu)
+                .help("The cpu to use, which is m68k (a 68000), z80, 8080, 68000, 68008, 68010, 68020, or 68030"),
+        )
+        .arg(
+            Arg::new("frequency")
+                .long("frequency")
+                .value_name("HZ")
+                .value_parser(clap::value_parser!(u32))
+                .help("The cpu clock frequency, which defaults to 8MHz for the 68000 family and 4MHz for the Z80"),
+        )
+        .arg(
+            Arg::new("rom")
+                .long("rom")
+                .value_name("FILE@ADDR")
+                .action(ArgAction::Append)
+                .value_parser(parse_rom)
+                .help("Load a ROM image at the given hex address"),
+        )
+        .arg(
+            Arg::new("ram")
+                .long("ram")
+                .value_name("SIZE@ADDR")
+                .action(ArgAction::Append)
+                .value_parser(parse_ram)
+                .help("Add RAM of the given size at the given hex address, where the size can end in K or M"),
+        )
+        .arg(
+            Arg::new("serial")
+                .long("serial")
+                .value_name("TYPE@ADDR")
+                .action(ArgAction::Append)
+                .value_parser(parse_serial)
+                .help("Add a serial chip (mc68681, mc68901, or z8530) at the given hex address, connected to a pty"),
+        )
+        .get_matches();
+
+    let mut options = BareOptions::default();
+    if let Some(cpu) = matches.get_one::<CpuKind>("cpu") {
+        options.cpu = *cpu;
+    }
+    options.frequency = matches.get_one::<u32>("frequency").map(|hz| Frequency::from_hz(*hz));
+    if let Some(roms) = matches.get_many::<(String, Address)>("rom") {
+        options.roms = roms.cloned().collect();
+    }
+    if let Some(ram) = matches.get_many::<(usize, Address)>("ram") {
+        options.ram = ram.cloned().collect();
+    }
+    if let Some(serial) = matches.get_many::<(DeviceKind, Address)>("serial") {
+        options.serial = serial.cloned().collect();
+    }
+
+    if ConsoleFrontend::introspect(&matches, &options) {
+        return;
+    }
+
+    let frontend = ConsoleFrontend::new(&matches);
+
+    let system = build_bare(&frontend, options);
+    frontend.run(matches, system);
+}
+
+fn parse_cpu(arg: &str) -> Result<CpuKind, String> {
+    match arg {
+        "m68k" => Ok(CpuKind::MC68000),
+        _ => CpuKind::from_name(arg).ok_or_else(|| format!("{} is not a supported cpu", arg)),
+    }
+}
+
+fn parse_rom(arg: &str) -> Result<(String, Address), String> {
+    let (filename, address) = split_at_address(arg)?;
+    Ok((filename.to_string(), address))
+}
+
+fn parse_ram(arg: &str) -> Result<(usize, Address), String> {
+    let (size, address) = split_at_address(arg)?;
+    let (digits, multiplier) = match size.chars().last() {
+        Some('K') | Some('k') => (&size[..size.len() - 1], 1024),
+        Some('M') | Some('m') => (&size[..size.len() - 1], 1024 * 1024),
+        _ => (size, 1),
+    };
+    let size = match digits.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => digits.parse::<usize>(),
+    };
+    let size = size.map_err(|_| format!("{} is not a valid size", arg))?;
+    Ok((size * multiplier, address))
+}
+
+fn parse_serial(arg: &str) -> Result<(DeviceKind, Address), String> {
+    let (name, address) = split_at_address(arg)?;
+    match DeviceKind::from_name(name) {
+        Some(kind) if kind.is_serial() => Ok((kind, address)),
+        _ => Err(format!("{} is not a supported serial chip", name)),
+    }
+}
+
+/// Split an argument of the form `VALUE@ADDR`, where the address is in hex
+fn split_at_address(arg: &str) -> Result<(&str, Address), String> {
+    let (value, address) = arg
+        .rsplit_once('@')
+        .ok_or_else(|| format!("expected {} to be of the form VALUE@ADDR", arg))?;
+    let address =
+        Address::from_str_radix(address.trim_start_matches("0x"), 16).map_err(|_| format!("{} is not a hex address", address))?;
+    Ok((value, address))
+}
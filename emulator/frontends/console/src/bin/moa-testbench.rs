@@ -0,0 +1,35 @@
+use clap::Arg;
+
+use moa_console::ConsoleFrontend;
+use moa_systems_testbench::{build_testbench, TestbenchOptions};
+
+fn main() {
+    let matches = ConsoleFrontend::args("M68k ELF Testbench")
+        .arg(
+            Arg::new("ELF")
+                .required(true)
+                .help("Statically linked m68k ELF program to run"),
+        )
+        .arg(
+            Arg::new("sandbox")
+                .long("sandbox")
+                .value_name("DIR")
+                .help("Directory that the program can read files from using semihosting calls"),
+        )
+        .get_matches();
+
+    let mut options = TestbenchOptions::default();
+    if let Some(filename) = matches.get_one::<String>("ELF") {
+        options.elf = filename.to_string();
+    }
+    options.sandbox = matches.get_one::<String>("sandbox").cloned();
+
+    if ConsoleFrontend::introspect(&matches, &options) {
+        return;
+    }
+
+    let frontend = ConsoleFrontend::default();
+
+    let system = build_testbench(&frontend, options);
+    frontend.run(matches, system);
+}
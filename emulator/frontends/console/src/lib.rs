@@ -1,12 +1,26 @@
 use clap::{Command, Arg, ArgAction, ArgMatches};
+use std::fmt;
+use std::time;
+use std::thread;
+use std::process;
 use std::io::{self, Write};
 use femtos::Duration;
 
-use moa_core::{Error, System};
+use moa_core::{Error, EmulatorErrorKind, System, Address, Device, logging};
 use moa_debugger::{Debugger, DebugControl};
-use moa_host::{Host, HostError, Tty, ControllerEvent, Audio, DummyAudio, FrameReceiver, EventSender};
+use moa_common::machines;
+use moa_common::{ScriptDriver, RhaiScript};
+use moa_common::tty::{SerialPorts, SerialTransport, parse_serial_port};
+use moa_common::{Executor, NetworkBackend};
+use moa_host::{Host, HostError, Tty, Network, KeyEvent, ControllerEvent, Audio, DummyAudio, FrameReceiver, EventSender};
 
-pub struct ConsoleFrontend;
+/// A frontend without any video, which only keeps the input queues so that a script can drive them
+pub struct ConsoleFrontend {
+    keyboard: Option<EventSender<KeyEvent>>,
+    controllers: Option<EventSender<ControllerEvent>>,
+    serial_ports: SerialPorts,
+    network: NetworkBackend,
+}
 
 impl Host for ConsoleFrontend {
     type Error = Error;
@@ -17,13 +31,26 @@ impl Host for ConsoleFrontend {
         //.map_err(|err| Error::new(format!("console: error opening pty: {:?}", err)))?))
     }
 
+    fn add_serial_port(&self, name: &str) -> Result<Box<dyn Tty>, HostError<Self::Error>> {
+        Ok(Box::new(self.serial_ports.open(name).map_err(HostError::Specific)?))
+    }
+
+    fn add_network(&self, name: &str) -> Result<Box<dyn Network>, HostError<Self::Error>> {
+        self.network.open(name)
+    }
+
     fn add_video_source(&mut self, _receiver: FrameReceiver) -> Result<(), HostError<Self::Error>> {
         println!("console: add_window() is not supported from the console; ignoring request...");
         Ok(())
     }
 
-    fn register_controllers(&mut self, _sender: EventSender<ControllerEvent>) -> Result<(), HostError<Self::Error>> {
-        println!("console: register_controller() is not supported from the console; ignoring request...");
+    fn register_controllers(&mut self, sender: EventSender<ControllerEvent>) -> Result<(), HostError<Self::Error>> {
+        self.controllers = Some(sender);
+        Ok(())
+    }
+
+    fn register_keyboard(&mut self, sender: EventSender<KeyEvent>) -> Result<(), HostError<Self::Error>> {
+        self.keyboard = Some(sender);
         Ok(())
     }
 
@@ -35,11 +62,30 @@ impl Host for ConsoleFrontend {
 
 impl Default for ConsoleFrontend {
     fn default() -> Self {
-        Self
+        Self {
+            keyboard: None,
+            controllers: None,
+            serial_ports: SerialPorts::default(),
+            network: NetworkBackend::default(),
+        }
     }
 }
 
 impl ConsoleFrontend {
+    /// Create the frontend with the connections for the serial ports given by the `--serial-port` arguments, and
+    /// the network given by `--network`
+    pub fn new(matches: &ArgMatches) -> Self {
+        let ports = matches
+            .get_many::<(String, SerialTransport)>("serial-port")
+            .map(|ports| ports.cloned().collect())
+            .unwrap_or_default();
+        Self {
+            serial_ports: SerialPorts::new(ports),
+            network: matches.get_one::<NetworkBackend>("network").cloned().unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
     pub fn args(application_name: &'static str) -> Command {
         Command::new(application_name)
             .arg(
@@ -55,6 +101,164 @@ impl ConsoleFrontend {
                     .action(ArgAction::SetTrue)
                     .help("Start the debugger before running machine"),
             )
+            .arg(
+                Arg::new("symbols")
+                    .long("symbols")
+                    .value_name("FILE")
+                    .help("Load symbols for the debugger from the output of nm, or a file of addr=name lines"),
+            )
+            .arg(
+                Arg::new("serial-port")
+                    .long("serial-port")
+                    .value_name("NAME=TRANSPORT")
+                    .action(ArgAction::Append)
+                    .value_parser(parse_serial_port)
+                    .help("Connect the named serial port to a pty, tcp:ADDR, or pipe:PATH (eg. port-a=tcp:127.0.0.1:4321)"),
+            )
+            .arg(
+                Arg::new("network")
+                    .long("network")
+                    .value_name("BACKEND")
+                    .value_parser(clap::value_parser!(NetworkBackend))
+                    .help("Give the machine's network interfaces to nat, nat:ADDR, tun:NAME, udp:LOCAL,REMOTE, or none (defaults to nat)"),
+            )
+            .arg(
+                Arg::new("timeout")
+                    .long("timeout")
+                    .value_name("SECONDS")
+                    .value_parser(clap::value_parser!(f64))
+                    .help("Exit with a failure if the guest hasn't exited after this much simulated time"),
+            )
+            .arg(
+                Arg::new("speed")
+                    .long("speed")
+                    .value_name("FACTOR")
+                    .value_parser(clap::value_parser!(f32))
+                    .help("Run at this multiple of real time, instead of as fast as possible"),
+            )
+            .arg(
+                Arg::new("exit-address")
+                    .long("exit-address")
+                    .value_name("ADDR")
+                    .value_parser(parse_address)
+                    .help("Exit when the guest writes to this hex address, using the value written as the exit code"),
+            )
+            .arg(
+                Arg::new("script")
+                    .long("script")
+                    .value_name("FILE")
+                    .help("Run the timed actions in a TOML script file, or the hooks in a .rhai script file"),
+            )
+            .arg(
+                Arg::new("illegal-report")
+                    .long("illegal-report")
+                    .action(ArgAction::SetTrue)
+                    .help("Record illegal and unimplemented instructions instead of stopping, and print a summary on exit"),
+            )
+            .arg(
+                Arg::new("snapshot-on-exit")
+                    .long("snapshot-on-exit")
+                    .value_name("FILE")
+                    .help("Save the contents of the machine's memory to FILE when exiting, including after a crash"),
+            )
+            .arg(
+                Arg::new("restore-snapshot")
+                    .long("restore-snapshot")
+                    .value_name("FILE")
+                    .help("Restore the contents of the machine's memory from a snapshot saved on exit"),
+            )
+            .arg(
+                Arg::new("validate")
+                    .long("validate")
+                    .action(ArgAction::SetTrue)
+                    .help("Build the machine and check it for configuration problems, and then exit without running it"),
+            )
+            .arg(
+                Arg::new("list-machines")
+                    .long("list-machines")
+                    .action(ArgAction::SetTrue)
+                    .help("List the available machines and their options, and then exit"),
+            )
+            .arg(
+                Arg::new("dump-config")
+                    .long("dump-config")
+                    .action(ArgAction::SetTrue)
+                    .help("Print the effective configuration, and then exit"),
+            )
+    }
+
+    /// Handle the `--list-machines` and `--dump-config` arguments, returning true if the program should exit
+    /// instead of running the machine
+    pub fn introspect(matches: &ArgMatches, options: &dyn fmt::Debug) -> bool {
+        if matches.get_flag("list-machines") {
+            machines::print_machines();
+            return true;
+        }
+
+        if matches.get_flag("dump-config") {
+            let log_level = matches
+                .get_one::<String>("log-level")
+                .cloned()
+                .unwrap_or_else(|| "info".to_string());
+            let serial_ports: Vec<String> = matches
+                .get_many::<(String, SerialTransport)>("serial-port")
+                .map(|ports| ports.map(|(name, transport)| format!("{}={}", name, transport)).collect())
+                .unwrap_or_default();
+            let network = matches.get_one::<NetworkBackend>("network").cloned().unwrap_or_default();
+            let frontend = [
+                ("log-level", log_level),
+                ("debugger", matches.get_flag("debugger").to_string()),
+                ("timeout", format!("{:?}", matches.get_one::<f64>("timeout"))),
+                ("speed", format!("{:?}", matches.get_one::<f32>("speed"))),
+                ("exit-address", format!("{:?}", matches.get_one::<Address>("exit-address"))),
+                ("script", format!("{:?}", matches.get_one::<String>("script"))),
+                ("serial-port", serial_ports.join(",")),
+                ("network", network.to_string()),
+                ("illegal-report", matches.get_flag("illegal-report").to_string()),
+            ];
+            machines::print_config(&frontend, options);
+            return true;
+        }
+
+        false
+    }
+
+    /// Run the machine that was built, or if `--validate` was given, check it and exit with the result
+    pub fn run(self, matches: ArgMatches, system: Result<System, Error>) {
+        if matches.get_flag("validate") {
+            process::exit(machines::validate(&system));
+        }
+
+        let system = system.and_then(|mut system| {
+            match matches.get_one::<String>("script") {
+                Some(filename) if filename.ends_with(".rhai") => {
+                    RhaiScript::load(filename)?.install(&mut system)?;
+                },
+                Some(filename) => {
+                    let script = ScriptDriver::load(filename)?
+                        .with_keyboard(self.keyboard.clone())
+                        .with_controllers(self.controllers.clone());
+                    system.add_device("script", Device::new(script))?;
+                },
+                None => {},
+            }
+            if let Some(filename) = matches.get_one::<String>("restore-snapshot") {
+                system.restore_snapshot(filename)?;
+            }
+            if let Some(filename) = matches.get_one::<String>("snapshot-on-exit") {
+                system.save_snapshot_on_exit(filename);
+            }
+            system.autosave.save_on_panic();
+            Ok(system)
+        });
+
+        match system {
+            Ok(system) => self.start(matches, system),
+            Err(err) => {
+                eprintln!("Error building the machine: {}", err);
+                process::exit(EXIT_ERROR);
+            },
+        }
     }
 
     pub fn start(self, matches: ArgMatches, mut system: System) {
@@ -67,12 +271,37 @@ impl ConsoleFrontend {
             _ => log::Level::Info,
         };
 
-        // Start the logger
-        simple_logger::SimpleLogger::new()
-            .with_level(log_level.to_level_filter())
-            .without_timestamps()
-            .init()
-            .unwrap();
+        // Start the logger, which accepts every level so that the level of each device can be changed while running
+        let logger = simple_logger::SimpleLogger::new()
+            .with_level(log::LevelFilter::Trace)
+            .without_timestamps();
+        logging::init(Box::new(logger), log_level.to_level_filter()).unwrap();
+
+        let timeout = matches
+            .get_one::<f64>("timeout")
+            .map(|secs| Duration::from_nanos((secs * 1_000_000_000.0) as u64));
+        let exit_address = matches.get_one::<Address>("exit-address").cloned();
+        if let Some(addr) = exit_address {
+            system.get_bus().add_watcher(addr);
+        }
+        if matches.get_flag("illegal-report") {
+            run_debuggable_command(&system, &["illegal", "on"]);
+        }
+        let started = time::Instant::now();
+
+        // Without a speed, the machine is run as fast as possible, a few frames at a time
+        let mut executor = Executor::default().with_time_limit(timeout);
+        match matches.get_one::<f32>("speed") {
+            Some(speed) => executor.set_speed(*speed),
+            None => executor.set_fast_forward(true),
+        }
+
+        if let Some(filename) = matches.get_one::<String>("symbols") {
+            if let Err(err) = system.symbols.load(filename) {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
 
         // Run the main loop
         let mut debugger = Debugger::default();
@@ -98,17 +327,76 @@ impl ConsoleFrontend {
                         },
                     }
                 }
+                executor.reset();
             }
 
-            match system.run_for_duration(Duration::MAX - system.clock.as_duration()) {
-                Ok(()) => {},
-                Err(Error::Breakpoint(_)) => {
-                    run_debugger = true;
+            if timeout.map(|limit| system.clock.as_duration() >= limit).unwrap_or(false) {
+                finish(&system, started, EXIT_TIMEOUT, "timed out");
+            }
+
+            match executor.update(&mut system) {
+                Ok(_) if !executor.is_fast_forward() => thread::sleep(time::Duration::from_millis(1)),
+                Ok(_) => {},
+                Err(Error::Breakpoint(info)) if info.watchpoint && info.address.is_some() && info.address == exit_address => {
+                    // Values that don't fit in an exit code exit with the largest one, so that they still fail
+                    let code = info.value.unwrap_or(0xFF).min(0xFF) as i32;
+                    let reason = if code == 0 { "passed" } else { "failed" };
+                    finish(&system, started, code, reason);
+                },
+                Err(Error::Breakpoint(info)) => {
+                    if debugger.breakpoint_occurred(&system, &info) {
+                        run_debugger = true;
+                    }
+                },
+                Err(Error::Assertion(msg)) => {
+                    println!("Assertion failed: {}", msg);
+                    finish(&system, started, 1, "failed");
+                },
+                Err(Error::Emulator(EmulatorErrorKind::Exit(code), _)) => {
+                    let reason = if code == 0 { "passed" } else { "failed" };
+                    finish(&system, started, code, reason);
                 },
                 Err(err) => {
-                    panic!("{:?}", err);
+                    println!("Error: {}", err);
+                    finish(&system, started, EXIT_ERROR, "emulator error");
                 },
             }
         }
     }
 }
+
+/// The exit code used when the guest doesn't exit before the `--timeout` (the same as the `timeout` command)
+pub const EXIT_TIMEOUT: i32 = 124;
+
+/// The exit code used when the simulation stops because of an error in the emulator
+pub const EXIT_ERROR: i32 = 125;
+
+fn finish(system: &System, started: time::Instant, code: i32, reason: &str) -> ! {
+    run_debuggable_command(system, &["illegal", "report"]);
+    if let Err(err) = system.autosave.save() {
+        eprintln!("Error saving: {}", err);
+    }
+    eprintln!(
+        "{} with exit code {} after {:.6}s of simulated time ({:.2}s real time)",
+        reason,
+        code,
+        system.clock.as_duration().as_nanos() as f64 / 1_000_000_000.0,
+        started.elapsed().as_secs_f64(),
+    );
+    process::exit(code);
+}
+
+/// Run a debugger command on every debuggable device which supports it
+fn run_debuggable_command(system: &System, args: &[&str]) {
+    for device in system.debuggables.iter() {
+        if let Some(debuggable) = device.borrow_mut().as_debuggable() {
+            if let Err(err) = debuggable.run_command(system, args) {
+                println!("Error: {}", err);
+            }
+        }
+    }
+}
+
+fn parse_address(arg: &str) -> Result<Address, String> {
+    Address::from_str_radix(arg.trim_start_matches("0x"), 16).map_err(|_| format!("{} is not a hex address", arg))
+}
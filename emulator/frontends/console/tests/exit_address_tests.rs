@@ -0,0 +1,94 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// The address of the RAM given to the machine, which is also the top of the stack
+const RAM_ADDR: u32 = 0x0001_0000;
+/// An address that isn't mapped to any device
+const UNMAPPED_ADDR: u32 = 0x0030_0000;
+/// An address inside the ROM, which holds 0xEE
+const ROM_ADDR: u32 = 0x0000_0100;
+
+/// Build a 68000 ROM image which runs the given instructions from 0x8, and then loops forever
+fn build_rom(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0xEE; 0x200];
+    rom[0..4].copy_from_slice(&(RAM_ADDR + 0x1_0000).to_be_bytes());
+    rom[4..8].copy_from_slice(&8u32.to_be_bytes());
+    rom[8..8 + program.len()].copy_from_slice(program);
+    // bra.s to itself
+    rom[8 + program.len()..10 + program.len()].copy_from_slice(&[0x60, 0xFE]);
+    rom
+}
+
+/// Encode `move.b #value, (addr).l`
+fn move_byte(value: u8, addr: u32) -> Vec<u8> {
+    let mut data = vec![0x13, 0xFC, 0x00, value];
+    data.extend(addr.to_be_bytes());
+    data
+}
+
+/// Encode `move.w #value, (addr).l`
+fn move_word(value: u16, addr: u32) -> Vec<u8> {
+    let mut data = vec![0x33, 0xFC];
+    data.extend(value.to_be_bytes());
+    data.extend(addr.to_be_bytes());
+    data
+}
+
+/// Run the ROM with moa-run, exiting when the given address is written, and return the process's exit code
+fn run_rom(name: &str, program: &[u8], exit_address: u32) -> i32 {
+    let path: PathBuf = std::env::temp_dir().join(format!("moa-exit-address-{}-{}.bin", name, std::process::id()));
+    fs::write(&path, build_rom(program)).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_moa-run"))
+        .arg("--cpu")
+        .arg("m68k")
+        .arg("--rom")
+        .arg(format!("{}@0", path.display()))
+        .arg("--ram")
+        .arg(format!("64K@{:x}", RAM_ADDR))
+        .arg("--exit-address")
+        .arg(format!("{:x}", exit_address))
+        .arg("--timeout")
+        .arg("0.1")
+        .status()
+        .unwrap();
+
+    fs::remove_file(&path).unwrap();
+    status.code().unwrap()
+}
+
+#[test]
+fn byte_written_to_ram_is_the_exit_code() {
+    assert_eq!(run_rom("byte", &move_byte(5, RAM_ADDR), RAM_ADDR), 5);
+}
+
+#[test]
+fn word_written_is_the_exit_code() {
+    assert_eq!(run_rom("word", &move_word(3, RAM_ADDR), RAM_ADDR), 3);
+}
+
+#[test]
+fn zero_written_passes() {
+    assert_eq!(run_rom("zero", &move_byte(0, RAM_ADDR), RAM_ADDR), 0);
+}
+
+#[test]
+fn value_written_to_an_unmapped_address_is_the_exit_code() {
+    assert_eq!(run_rom("unmapped", &move_byte(7, UNMAPPED_ADDR), UNMAPPED_ADDR), 7);
+}
+
+#[test]
+fn value_written_to_rom_is_the_exit_code() {
+    assert_eq!(run_rom("rom", &move_word(0, ROM_ADDR), ROM_ADDR), 0);
+}
+
+#[test]
+fn values_too_large_for_an_exit_code_fail() {
+    assert_eq!(run_rom("large", &move_word(0x100, RAM_ADDR), RAM_ADDR), 0xFF);
+}
+
+#[test]
+fn guest_that_never_exits_times_out() {
+    assert_eq!(run_rom("timeout", &[], RAM_ADDR), 124);
+}
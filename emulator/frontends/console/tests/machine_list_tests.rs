@@ -0,0 +1,43 @@
+use std::fmt::Debug;
+
+use moa_common::MACHINES;
+use moa_systems_computie::ComputieOptions;
+use moa_systems_cpm::CpmOptions;
+use moa_systems_custom::{CustomOptions, BareOptions};
+use moa_systems_genesis::SegaGenesisOptions;
+use moa_systems_testbench::TestbenchOptions;
+
+/// Returns the names of the fields of an options struct, from the top level of its pretty printed debug output
+fn field_names(options: &dyn Debug) -> Vec<String> {
+    format!("{:#?}", options)
+        .lines()
+        .filter_map(|line| line.strip_prefix("    "))
+        .filter(|line| !line.starts_with(' '))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+fn listed_options(machine: &str) -> Vec<String> {
+    let entry = MACHINES
+        .iter()
+        .find(|entry| entry.name == machine)
+        .unwrap_or_else(|| panic!("{} isn't in the list of machines", machine));
+    entry.options.iter().map(|(name, _)| name.to_string()).collect()
+}
+
+#[test]
+fn listed_options_match_the_options_of_each_machine() {
+    let machines: [(&str, &dyn Debug); 6] = [
+        ("genesis", &SegaGenesisOptions::default()),
+        ("computie", &ComputieOptions::default()),
+        ("cpm", &CpmOptions::default()),
+        ("custom", &CustomOptions::default()),
+        ("bare", &BareOptions::default()),
+        ("testbench", &TestbenchOptions::default()),
+    ];
+
+    for (machine, options) in machines {
+        assert_eq!(listed_options(machine), field_names(options), "options listed for {}", machine);
+    }
+}
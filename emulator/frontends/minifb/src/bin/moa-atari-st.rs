@@ -0,0 +1,29 @@
+use clap::{Arg, ArgAction};
+
+use moa_systems_atari_st::{build_atari_st, AtariStOptions};
+
+fn main() {
+    let matches = moa_minifb::new("Atari ST Emulator")
+        .arg(Arg::new("DISK").help("Disk image in the .ST format to insert into drive A"))
+        .arg(Arg::new("tos").long("tos").value_name("FILE").help("TOS ROM image to boot"))
+        .arg(
+            Arg::new("monochrome")
+                .long("monochrome")
+                .action(ArgAction::SetTrue)
+                .help("Connect a monochrome monitor instead of a colour monitor"),
+        )
+        .get_matches();
+
+    let mut options = AtariStOptions::default();
+    if let Some(filename) = matches.get_one::<String>("tos") {
+        options.rom = filename.clone();
+    }
+    options.disk = matches.get_one::<String>("DISK").cloned();
+    options.monochrome = matches.get_flag("monochrome");
+
+    if moa_minifb::introspect(&matches, &options) {
+        return;
+    }
+
+    moa_minifb::run(matches, |frontend| build_atari_st(frontend, options));
+}
@@ -1,16 +1,105 @@
-use clap::Arg;
+use std::process;
 
+use clap::{Arg, ArgAction};
+
+use moa_core::VideoStandard;
 use moa_systems_genesis::{build_genesis, SegaGenesisOptions};
 
 fn main() {
     let matches = moa_minifb::new("Sega Genesis/Mega Drive Emulator")
         .arg(Arg::new("ROM").help("ROM file to load (must be flat binary)"))
+        .arg(
+            Arg::new("rom-db")
+                .long("rom-db")
+                .help("No-Intro DAT file to use for identifying the ROM"),
+        )
+        .arg(
+            Arg::new("vblank-input")
+                .long("vblank-input")
+                .action(ArgAction::SetTrue)
+                .help("Only read controller inputs at the start of each emulated vblank, for reproducible input timing"),
+        )
+        .arg(
+            Arg::new("three-button")
+                .long("three-button")
+                .action(ArgAction::SetTrue)
+                .help("Connect 3-button controllers instead of 6-button controllers, for games that misread them"),
+        )
+        .arg(
+            Arg::new("overscan")
+                .long("overscan")
+                .action(ArgAction::SetTrue)
+                .help("Draw the border around the display, which shows the background colour"),
+        )
+        .arg(
+            Arg::new("refresh-cycles")
+                .long("refresh-cycles")
+                .action(ArgAction::SetTrue)
+                .help("Emulate the 68000 cycles lost to DRAM refresh, which some cycle-counted code depends on"),
+        )
+        .arg(
+            Arg::new("interleave")
+                .long("interleave")
+                .value_name("MICROSECONDS")
+                .value_parser(clap::value_parser!(u64))
+                .help("Run each cpu up to this far ahead of the other devices before switching, for speed over accuracy"),
+        )
+        .arg(
+            Arg::new("video-standard")
+                .long("video-standard")
+                .value_name("STANDARD")
+                .value_parser(clap::value_parser!(VideoStandard))
+                .help("Build an NTSC or a PAL console, which sets the frame rate and the clock speeds (ntsc or pal)"),
+        )
+        .arg(
+            Arg::new("threaded-audio")
+                .long("threaded-audio")
+                .action(ArgAction::SetTrue)
+                .help("Run the sound chips on threads of their own, in parallel with the cpus"),
+        )
+        .arg(
+            Arg::new("cd-bios")
+                .long("cd-bios")
+                .help("Sega CD BIOS to boot, which attaches a Sega CD instead of loading the ROM"),
+        )
+        .arg(
+            Arg::new("cd")
+                .long("cd")
+                .requires("cd-bios")
+                .help("CDROM image to insert into the Sega CD (.cue with .bin tracks, or .iso)"),
+        )
         .get_matches();
 
     let mut options = SegaGenesisOptions::default();
     if let Some(filename) = matches.get_one::<String>("ROM") {
         options.rom = filename.to_string();
     }
+    options.rom_database = matches.get_one::<String>("rom-db").cloned();
+    options.poll_inputs_at_vblank = matches.get_flag("vblank-input");
+    options.three_button = matches.get_flag("three-button");
+    options.overscan = matches.get_flag("overscan");
+    options.refresh_cycles = matches.get_flag("refresh-cycles");
+    options.threaded_audio = matches.get_flag("threaded-audio");
+    options.interleave_us = matches.get_one::<u64>("interleave").cloned();
+    options.cd_bios = matches.get_one::<String>("cd-bios").cloned();
+    options.cd_image = matches.get_one::<String>("cd").cloned();
+    if let Some(standard) = matches.get_one::<VideoStandard>("video-standard") {
+        options.video_standard = *standard;
+    }
+
+    if moa_minifb::introspect(&matches, &options) {
+        return;
+    }
+
+    if options.rom.is_empty() && options.cd_bios.is_none() {
+        match moa_minifb::pick_rom_file("Select a Sega Genesis ROM", &["bin", "md", "gen", "smd"]) {
+            Some(filename) => options.rom = filename,
+            None => {
+                eprintln!("No ROM file given");
+                process::exit(1);
+            },
+        }
+    }
 
     moa_minifb::run(matches, |frontend| build_genesis(frontend, options));
 }
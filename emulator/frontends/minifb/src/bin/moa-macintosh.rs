@@ -1,7 +1,25 @@
-use moa_systems_macintosh::build_macintosh_512k;
+use clap::{Arg, ArgAction};
+
+use moa_systems_macintosh::{build_macintosh_512k, MacintoshOptions};
 
 fn main() {
-    let matches = moa_minifb::new("Macintosh 512k Emulator").get_matches();
+    let matches = moa_minifb::new("Macintosh 512k Emulator")
+        .arg(Arg::new("DISK").help("400K or 800K disk image to boot from (raw sectors or DiskCopy 4.2)"))
+        .arg(
+            Arg::new("serial")
+                .long("serial")
+                .action(ArgAction::SetTrue)
+                .help("Connect the modem and printer ports to ptys, or to the connections given with --serial-port"),
+        )
+        .get_matches();
+
+    let mut options = MacintoshOptions::default();
+    options.disk = matches.get_one::<String>("DISK").cloned();
+    options.serial = matches.get_flag("serial");
+
+    if moa_minifb::introspect(&matches, &options) {
+        return;
+    }
 
-    moa_minifb::run(matches, build_macintosh_512k);
+    moa_minifb::run(matches, |frontend| build_macintosh_512k(frontend, options));
 }
@@ -83,6 +83,10 @@ fn initialize_ym(ym_sound: Device) -> Result<(), Error> {
 fn main() {
     let matches = moa_minifb::new("YM2612 Tester/Synth").get_matches();
 
+    if moa_minifb::introspect(&matches, &()) {
+        return;
+    }
+
     moa_minifb::run(matches, |host| {
         let mut system = System::default();
 
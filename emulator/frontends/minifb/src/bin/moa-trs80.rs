@@ -1,3 +1,6 @@
+use std::path::Path;
+use std::process;
+
 use clap::{Arg, ArgAction};
 
 use moa_systems_trs80::{build_trs80, Trs80Options};
@@ -12,12 +15,36 @@ fn main() {
                 .value_name("FILE")
                 .help("ROM file to load at the start of memory"),
         )
+        .arg(
+            Arg::new("DISK")
+                .long("disk")
+                .action(ArgAction::Append)
+                .value_name("FILE")
+                .help("Disk image in the JV1, JV3, or DMK format for the next drive, which the ROM boots from"),
+        )
         .get_matches();
 
     let mut options = Trs80Options::default();
     if let Some(filename) = matches.get_one::<String>("ROM") {
         options.rom = filename.to_string();
     }
+    if let Some(disks) = matches.get_many::<String>("DISK") {
+        options.disks = disks.cloned().collect();
+    }
+
+    if moa_minifb::introspect(&matches, &options) {
+        return;
+    }
+
+    if !Path::new(&options.rom).exists() {
+        match moa_minifb::pick_rom_file("Select a TRS-80 BASIC ROM", &["rom", "bin"]) {
+            Some(filename) => options.rom = filename,
+            None => {
+                eprintln!("ROM file {} not found", options.rom);
+                process::exit(1);
+            },
+        }
+    }
 
     moa_minifb::run(matches, |frontend| build_trs80(frontend, options));
 }
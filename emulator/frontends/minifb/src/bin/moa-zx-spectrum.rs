@@ -0,0 +1,47 @@
+use std::path::Path;
+use std::process;
+
+use clap::{Arg, ArgAction};
+
+use moa_systems_zx_spectrum::{build_zx_spectrum, ZxSpectrumOptions};
+
+fn main() {
+    let matches = moa_minifb::new("ZX Spectrum 48K Emulator")
+        .arg(
+            Arg::new("ROM")
+                .short('r')
+                .long("rom")
+                .action(ArgAction::Set)
+                .value_name("FILE")
+                .help("16K ROM file to load at the start of memory"),
+        )
+        .arg(
+            Arg::new("no-contention")
+                .long("no-contention")
+                .action(ArgAction::SetTrue)
+                .help("Don't delay the cpu when it accesses the memory shared with the ULA"),
+        )
+        .get_matches();
+
+    let mut options = ZxSpectrumOptions::default();
+    if let Some(filename) = matches.get_one::<String>("ROM") {
+        options.rom = filename.to_string();
+    }
+    options.contended_memory = !matches.get_flag("no-contention");
+
+    if moa_minifb::introspect(&matches, &options) {
+        return;
+    }
+
+    if !Path::new(&options.rom).exists() {
+        match moa_minifb::pick_rom_file("Select a ZX Spectrum 48K ROM", &["rom", "bin"]) {
+            Some(filename) => options.rom = filename,
+            None => {
+                eprintln!("ROM file {} not found", options.rom);
+                process::exit(1);
+            },
+        }
+    }
+
+    moa_minifb::run(matches, |frontend| build_zx_spectrum(frontend, options));
+}
@@ -0,0 +1,185 @@
+use gilrs::{Gilrs, GamepadId, Button, Axis, EventType};
+use gilrs::ff::{self, EffectBuilder, BaseEffect, BaseEffectType, Replay, Ticks};
+
+use moa_host::{
+    ControllerDevice, ControllerAxis, ControllerInput, ControllerEvent, ControllerOutput, ControllerFeedback, EventReceiver,
+    HeldInputs,
+};
+
+
+const DEVICES: [ControllerDevice; 4] = [ControllerDevice::A, ControllerDevice::B, ControllerDevice::C, ControllerDevice::D];
+
+/// Physical game controllers, which are each assigned to the first emulated controller that's free when they're
+/// connected, so unplugging one controller doesn't change which emulated controller the others are playing
+pub struct Gamepads {
+    gilrs: Gilrs,
+    slots: Vec<Option<GamepadId>>,
+    held: HeldInputs,
+    // Effects stop playing when they're dropped, so the last one for each controller is kept here
+    effects: Vec<Option<ff::Effect>>,
+}
+
+impl Gamepads {
+    pub fn new() -> Option<Self> {
+        let gilrs = Gilrs::new()
+            .map_err(|err| log::warn!("unable to access game controllers: {}", err))
+            .ok()?;
+        let mut slots: Vec<Option<GamepadId>> = gilrs.gamepads().map(|(id, _)| Some(id)).take(DEVICES.len()).collect();
+        slots.resize(DEVICES.len(), None);
+
+        Some(Self {
+            gilrs,
+            slots,
+            held: HeldInputs::default(),
+            effects: (0..DEVICES.len()).map(|_| None).collect(),
+        })
+    }
+
+    /// Returns the button and axis events received from the physical controllers since the last update, including
+    /// the release of everything that was held down on a controller that was disconnected
+    pub fn update(&mut self) -> Vec<ControllerEvent> {
+        let mut events = vec![];
+        while let Some(event) = self.gilrs.next_event() {
+            let input = match event.event {
+                EventType::Connected => {
+                    self.connect(event.id);
+                    continue;
+                },
+                EventType::Disconnected => {
+                    if let Some(slot) = self.slot_of(event.id) {
+                        log::info!("controller {:?} disconnected", DEVICES[slot]);
+                        self.slots[slot] = None;
+                        events.extend(self.held.release_device(DEVICES[slot]));
+                    }
+                    continue;
+                },
+                EventType::ButtonPressed(button, _) => map_button(button, true),
+                EventType::ButtonReleased(button, _) => map_button(button, false),
+                EventType::AxisChanged(axis, value, _) => map_axis(axis, value),
+                // The analog triggers are reported as buttons with a value from 0 to 1
+                EventType::ButtonChanged(Button::LeftTrigger2, value, _) => Some(trigger(ControllerAxis::LeftTrigger, value)),
+                EventType::ButtonChanged(Button::RightTrigger2, value, _) => Some(trigger(ControllerAxis::RightTrigger, value)),
+                _ => continue,
+            };
+
+            let device = self.slot_of(event.id).map(|slot| DEVICES[slot]);
+            if let (Some(device), Some(input)) = (device, input) {
+                let event = ControllerEvent::new(device, input);
+                self.held.update_button(event);
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    pub fn apply_feedback(&mut self, receiver: &EventReceiver<ControllerFeedback>) {
+        while let Some(feedback) = receiver.receive() {
+            let index = DEVICES.iter().position(|device| *device == feedback.device).unwrap();
+            let id = match self.slots[index] {
+                Some(id) => id,
+                None => continue,
+            };
+
+            match feedback.output {
+                ControllerOutput::Rumble {
+                    strong,
+                    weak,
+                    duration_ms,
+                } => {
+                    if let Err(err) = self.rumble(index, id, strong, weak, duration_ms) {
+                        log::warn!("unable to rumble controller {:?}: {}", feedback.device, err);
+                    }
+                },
+                ControllerOutput::Led(led, state) => {
+                    // gilrs doesn't have a way of controlling the indicator lights
+                    log::debug!("ignoring led {} set to {} on controller {:?}", led, state, feedback.device);
+                },
+            }
+        }
+    }
+
+    fn connect(&mut self, id: GamepadId) {
+        // The controllers that were connected at startup can also be reported as connected
+        if self.slot_of(id).is_some() {
+            return;
+        }
+        let name = self.gilrs.gamepad(id).name().to_string();
+        match self.slots.iter().position(|slot| slot.is_none()) {
+            Some(slot) => {
+                log::info!("controller {:?} connected: {}", DEVICES[slot], name);
+                self.slots[slot] = Some(id);
+            },
+            None => log::warn!("ignoring game controller {}, since every emulated controller is in use", name),
+        }
+    }
+
+    fn slot_of(&self, id: GamepadId) -> Option<usize> {
+        self.slots.iter().position(|slot| *slot == Some(id))
+    }
+
+    fn rumble(&mut self, index: usize, id: GamepadId, strong: u16, weak: u16, duration_ms: u32) -> Result<(), ff::Error> {
+        self.effects[index] = None;
+        if !self.gilrs.gamepad(id).is_ff_supported() || (strong == 0 && weak == 0) {
+            return Ok(());
+        }
+
+        let scheduling = Replay {
+            play_for: Ticks::from_ms(duration_ms),
+            ..Default::default()
+        };
+        let effect = EffectBuilder::new()
+            .add_effect(BaseEffect {
+                kind: BaseEffectType::Strong {
+                    magnitude: strong,
+                },
+                scheduling,
+                ..Default::default()
+            })
+            .add_effect(BaseEffect {
+                kind: BaseEffectType::Weak {
+                    magnitude: weak,
+                },
+                scheduling,
+                ..Default::default()
+            })
+            .gamepads(&[id])
+            .finish(&mut self.gilrs)?;
+        effect.play()?;
+        self.effects[index] = Some(effect);
+        Ok(())
+    }
+}
+
+fn map_button(button: Button, state: bool) -> Option<ControllerInput> {
+    match button {
+        Button::South => Some(ControllerInput::ButtonA(state)),
+        Button::East => Some(ControllerInput::ButtonB(state)),
+        Button::RightTrigger => Some(ControllerInput::ButtonC(state)),
+        Button::West => Some(ControllerInput::ButtonX(state)),
+        Button::North => Some(ControllerInput::ButtonY(state)),
+        Button::LeftTrigger => Some(ControllerInput::ButtonZ(state)),
+        Button::DPadUp => Some(ControllerInput::DpadUp(state)),
+        Button::DPadDown => Some(ControllerInput::DpadDown(state)),
+        Button::DPadLeft => Some(ControllerInput::DpadLeft(state)),
+        Button::DPadRight => Some(ControllerInput::DpadRight(state)),
+        Button::Start => Some(ControllerInput::Start(state)),
+        Button::Select | Button::Mode => Some(ControllerInput::Mode(state)),
+        _ => None,
+    }
+}
+
+/// Convert an axis from gilrs, which goes from -1.0 to 1.0 with up being positive, to a controller axis
+fn map_axis(axis: Axis, value: f32) -> Option<ControllerInput> {
+    let (axis, value) = match axis {
+        Axis::LeftStickX => (ControllerAxis::LeftX, value),
+        Axis::LeftStickY => (ControllerAxis::LeftY, -value),
+        Axis::RightStickX => (ControllerAxis::RightX, value),
+        Axis::RightStickY => (ControllerAxis::RightY, -value),
+        _ => return None,
+    };
+    Some(ControllerInput::Axis(axis, (value.clamp(-1.0, 1.0) * i16::MAX as f32) as i16))
+}
+
+fn trigger(axis: ControllerAxis, value: f32) -> ControllerInput {
+    ControllerInput::Axis(axis, (value.clamp(0.0, 1.0) * i16::MAX as f32) as i16)
+}
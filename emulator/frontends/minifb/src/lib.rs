@@ -1,4 +1,7 @@
+use std::fmt;
+use std::mem;
 use std::thread;
+use std::process;
 use std::io::{self, Write};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
@@ -7,35 +10,69 @@ use minifb::{self, Key, MouseMode, MouseButton};
 use clap::{Command, Arg, ArgAction, ArgMatches};
 use femtos::{Duration as FemtosDuration};
 
-use moa_core::{System, Error, Device};
+use moa_core::{System, Error, Device, DeviceProfile, MachineInfo, StrictMode, Capabilities, logging};
 use moa_debugger::{Debugger, DebugControl};
 use moa_host::{
-    Host, HostError, Audio, KeyEvent, MouseEvent, MouseState, ControllerDevice, ControllerEvent, EventSender, PixelEncoding, Frame,
-    FrameReceiver,
+    Host, HostError, Tty, Audio, KeyEvent, MouseEvent, MouseState, ControllerDevice, ControllerInput, ControllerEvent,
+    ControllerFeedback, EventSender, EventReceiver, PixelEncoding, Frame, FrameReceiver, HeldInputs, PasteQueue,
+    DEFAULT_PASTE_RATE,
 };
 
-use moa_common::{AudioMixer, AudioSource};
+use moa_common::{AudioMixer, AudioSource, Executor};
+use moa_common::machines;
+use moa_common::{ScriptDriver, RhaiScript};
+use moa_common::{capture, VideoRecorder};
+use moa_common::KeyMap;
+use moa_common::tty::{SerialPorts, SerialTransport, parse_serial_port};
 use moa_common::CpalAudioOutput;
 
-mod controllers;
+#[cfg(feature = "gamepad")]
+mod gamepad;
 mod keys;
 
 use crate::keys::map_key;
-use crate::controllers::map_controller_a;
 
 
-const WIDTH: u32 = 320;
-const HEIGHT: u32 = 224;
+/// The size of the window for machines that don't describe their video output
+const DEFAULT_WINDOW_SIZE: (u32, u32) = (320, 224);
+
+/// The rate of updating the window for machines that don't give their refresh rate
+const DEFAULT_REFRESH_HZ: f64 = 60.0;
+
+/// How much simulated time to run between checking for new frames when benchmarking
+const BENCH_INTERVAL_US: u64 = 1_000;
 
 
 pub fn new(name: &'static str) -> Command {
     Command::new(name)
         .arg(Arg::new("scale").short('s').long("scale").help("Scale the screen"))
+        .arg(
+            Arg::new("crop")
+                .long("crop")
+                .value_name("LEFT,TOP,RIGHT,BOTTOM")
+                .value_parser(parse_crop)
+                .help("Remove the given number of pixels from each edge of the screen, such as to hide the overscan"),
+        )
         .arg(
             Arg::new("speed")
                 .short('x')
                 .long("speed")
-                .help("Adjust the speed of the simulation"),
+                .value_parser(parse_speed)
+                .help("Adjust the speed of the simulation (eg. 0.25 for slow motion)"),
+        )
+        .arg(
+            Arg::new("run-ahead")
+                .long("run-ahead")
+                .value_name("FRAMES")
+                .value_parser(clap::value_parser!(usize))
+                .help("Run the simulation this many frames ahead to hide the machine's input lag, if it can save its state"),
+        )
+        .arg(
+            Arg::new("paste-rate")
+                .long("paste-rate")
+                .value_name("CHARS")
+                .value_parser(parse_speed)
+                .help("The number of characters per second to type when pasting with F10 or the debugger's paste command"),
         )
         .arg(
             Arg::new("threaded")
@@ -50,6 +87,13 @@ pub fn new(name: &'static str) -> Command {
                 .long("log-level")
                 .help("Set the type of log messages to print"),
         )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .value_name("MODE")
+                .value_parser(clap::value_parser!(StrictMode))
+                .help("What to do when a device register that isn't implemented is accessed (ignore, warn, or break)"),
+        )
         .arg(
             Arg::new("debugger")
                 .short('d')
@@ -57,6 +101,12 @@ pub fn new(name: &'static str) -> Command {
                 .action(ArgAction::SetTrue)
                 .help("Start the debugger before running machine"),
         )
+        .arg(
+            Arg::new("symbols")
+                .long("symbols")
+                .value_name("FILE")
+                .help("Load symbols for the debugger from the output of nm, or a file of addr=name lines"),
+        )
         .arg(
             Arg::new("disable-audio")
                 .short('a')
@@ -64,12 +114,198 @@ pub fn new(name: &'static str) -> Command {
                 .action(ArgAction::SetTrue)
                 .help("Disable audio output"),
         )
+        .arg(
+            Arg::new("dither")
+                .long("dither")
+                .action(ArgAction::SetTrue)
+                .help("Add dithering noise when the audio is converted to 16-bit output"),
+        )
+        .arg(
+            Arg::new("mouse-paddle")
+                .long("mouse-paddle")
+                .action(ArgAction::SetTrue)
+                .help("Use the mouse as a paddle and dial on controller A, with the left button as button A"),
+        )
+        .arg(
+            Arg::new("bench-frames")
+                .long("bench-frames")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u64))
+                .help("Run the machine for N frames without a window or audio, and then print the performance"),
+        )
+        .arg(
+            Arg::new("script")
+                .long("script")
+                .value_name("FILE")
+                .help("Run the timed actions in a TOML script file, or the hooks in a .rhai script file"),
+        )
+        .arg(
+            Arg::new("keymap")
+                .long("keymap")
+                .value_name("FILE")
+                .help("Load the keys that play the emulated controllers and keyboard from a TOML file"),
+        )
+        .arg(
+            Arg::new("serial-port")
+                .long("serial-port")
+                .value_name("NAME=TRANSPORT")
+                .action(ArgAction::Append)
+                .value_parser(parse_serial_port)
+                .help("Connect the named serial port to a pty, tcp:ADDR, or pipe:PATH (eg. modem=tcp:127.0.0.1:4321)"),
+        )
+        .arg(
+            Arg::new("view")
+                .long("view")
+                .value_name("NAME")
+                .action(ArgAction::Append)
+                .help("Open a window for a debug view of the machine, or \"all\" for every view (can be repeated)"),
+        )
+        .arg(
+            Arg::new("record-video")
+                .long("record-video")
+                .value_name("FILE")
+                .help("Record the screen to an animated .gif or .png file, which is written when exiting"),
+        )
+        .arg(
+            Arg::new("snapshot-on-exit")
+                .long("snapshot-on-exit")
+                .value_name("FILE")
+                .help("Save the contents of the machine's memory to FILE when exiting, including after a crash"),
+        )
+        .arg(
+            Arg::new("restore-snapshot")
+                .long("restore-snapshot")
+                .value_name("FILE")
+                .help("Restore the contents of the machine's memory from a snapshot saved on exit"),
+        )
+        .arg(
+            Arg::new("validate")
+                .long("validate")
+                .action(ArgAction::SetTrue)
+                .help("Build the machine and check it for configuration problems, and then exit without opening a window"),
+        )
+        .arg(
+            Arg::new("list-machines")
+                .long("list-machines")
+                .action(ArgAction::SetTrue)
+                .help("List the available machines and their options, and then exit"),
+        )
+        .arg(
+            Arg::new("dump-config")
+                .long("dump-config")
+                .action(ArgAction::SetTrue)
+                .help("Print the effective configuration, and then exit"),
+        )
+}
+
+/// Handle the `--list-machines` and `--dump-config` arguments, returning true if the program should exit
+/// instead of running the machine
+pub fn introspect(matches: &ArgMatches, options: &dyn fmt::Debug) -> bool {
+    if matches.get_flag("list-machines") {
+        machines::print_machines();
+        return true;
+    }
+
+    if matches.get_flag("dump-config") {
+        let arg = |name: &str, default: &str| {
+            matches
+                .get_one::<String>(name)
+                .cloned()
+                .unwrap_or_else(|| default.to_string())
+        };
+
+        let paste_rate = matches.get_one::<f32>("paste-rate").cloned().unwrap_or(DEFAULT_PASTE_RATE);
+        let frontend = [
+            ("scale", arg("scale", "2")),
+            ("speed", matches.get_one::<f32>("speed").cloned().unwrap_or(1.0).to_string()),
+            ("run-ahead", matches.get_one::<usize>("run-ahead").cloned().unwrap_or(0).to_string()),
+            ("paste-rate", paste_rate.to_string()),
+            ("log-level", arg("log-level", "warn")),
+            ("threaded", matches.get_flag("threaded").to_string()),
+            ("strict", format!("{:?}", matches.get_one::<StrictMode>("strict"))),
+            ("debugger", matches.get_flag("debugger").to_string()),
+            ("disable-audio", matches.get_flag("disable-audio").to_string()),
+            ("script", format!("{:?}", matches.get_one::<String>("script"))),
+            ("keymap", format!("{:?}", matches.get_one::<String>("keymap"))),
+            ("bench-frames", format!("{:?}", matches.get_one::<u64>("bench-frames"))),
+            ("view", format!("{:?}", view_names(matches))),
+            ("serial-port", format!("{:?}", serial_ports(matches))),
+            ("record-video", format!("{:?}", matches.get_one::<String>("record-video"))),
+            ("crop", format!("{:?}", matches.get_one::<(u32, u32, u32, u32)>("crop"))),
+        ];
+        machines::print_config(&frontend, options);
+        return true;
+    }
+
+    false
+}
+
+fn serial_ports(matches: &ArgMatches) -> SerialPorts {
+    let ports = matches
+        .get_many::<(String, SerialTransport)>("serial-port")
+        .map(|ports| ports.cloned().collect())
+        .unwrap_or_default();
+    SerialPorts::new(ports)
+}
+
+fn view_names(matches: &ArgMatches) -> Vec<String> {
+    matches
+        .get_many::<String>("view")
+        .map(|names| names.cloned().collect())
+        .unwrap_or_default()
+}
+
+fn parse_speed(arg: &str) -> Result<f32, String> {
+    match arg.parse::<f32>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err(format!("{} is not a positive number", arg)),
+    }
+}
+
+fn parse_crop(arg: &str) -> Result<(u32, u32, u32, u32), String> {
+    let edges = arg
+        .split(',')
+        .map(|edge| edge.trim().parse::<u32>())
+        .collect::<Result<Vec<u32>, _>>();
+    match edges.as_deref() {
+        Ok([left, top, right, bottom]) => Ok((*left, *top, *right, *bottom)),
+        _ => Err(format!("{} is not 4 comma separated numbers of pixels", arg)),
+    }
+}
+
+/// Ask the user to choose a ROM file using the native open file dialog, if the frontend was compiled with
+/// the `file-dialog` feature
+#[cfg(feature = "file-dialog")]
+pub fn pick_rom_file(title: &str, extensions: &[&str]) -> Option<String> {
+    rfd::FileDialog::new()
+        .set_title(title)
+        .add_filter("ROM Image", extensions)
+        .add_filter("All Files", &["*"])
+        .pick_file()
+        .map(|path| path.to_string_lossy().to_string())
+}
+
+#[cfg(not(feature = "file-dialog"))]
+pub fn pick_rom_file(_title: &str, _extensions: &[&str]) -> Option<String> {
+    None
 }
 
 pub fn run<I>(matches: ArgMatches, init: I)
 where
     I: FnOnce(&mut MiniFrontendBuilder) -> Result<System, Error> + Send + 'static,
 {
+    if matches.get_flag("validate") {
+        let mut frontend = MiniFrontendBuilder::default();
+        process::exit(machines::validate(&init(&mut frontend)));
+    }
+
+    if let Some(frames) = matches.get_one::<u64>("bench-frames").cloned() {
+        let mut frontend = MiniFrontendBuilder::default();
+        let system = init(&mut frontend).unwrap();
+        run_benchmark(system, frontend.video.take(), frames);
+        return;
+    }
+
     if matches.get_flag("threaded") {
         run_threaded(matches, init);
     } else {
@@ -81,8 +317,13 @@ pub fn run_inline<I>(matches: ArgMatches, init: I)
 where
     I: FnOnce(&mut MiniFrontendBuilder) -> Result<System, Error>,
 {
-    let mut frontend = MiniFrontendBuilder::default();
-    let system = init(&mut frontend).unwrap();
+    let mut frontend = MiniFrontendBuilder::default()
+        .with_views(view_names(&matches))
+        .with_serial_ports(serial_ports(&matches));
+    let mut system = init(&mut frontend).unwrap();
+    add_script(&matches, &frontend, &mut system).unwrap();
+    setup_autosave(&matches, &mut system).unwrap();
+    setup_strict_mode(&matches, &mut system);
 
     frontend.build().start(matches, Some(system));
 }
@@ -91,12 +332,21 @@ pub fn run_threaded<I>(matches: ArgMatches, init: I)
 where
     I: FnOnce(&mut MiniFrontendBuilder) -> Result<System, Error> + Send + 'static,
 {
-    let frontend = Arc::new(Mutex::new(MiniFrontendBuilder::default()));
+    let frontend = MiniFrontendBuilder::default()
+        .with_views(view_names(&matches))
+        .with_serial_ports(serial_ports(&matches));
+    let frontend = Arc::new(Mutex::new(frontend));
 
     {
         let frontend = frontend.clone();
+        let script = matches.clone();
         thread::spawn(move || {
             let mut system = init(&mut frontend.lock().unwrap()).unwrap();
+            add_script(&script, &frontend.lock().unwrap(), &mut system).unwrap();
+            // The window closing exits the program without stopping this thread, so the memory is only saved on a panic
+            setup_autosave(&script, &mut system).unwrap();
+            setup_strict_mode(&script, &mut system);
+            frontend.lock().unwrap().machine_info = system.machine_info.clone();
             frontend.lock().unwrap().finalize();
             system.run_forever().unwrap();
         });
@@ -107,6 +357,109 @@ where
     frontend.lock().unwrap().build().start(matches, None);
 }
 
+/// Run the machine as fast as possible for the given number of frames, discarding the video and audio, and
+/// print the speed of the simulation and how much time each device took
+pub fn run_benchmark(mut system: System, video: Option<FrameReceiver>, frames: u64) {
+    let video = match video {
+        Some(video) => video,
+        None => {
+            eprintln!("This machine has no video output to count frames with");
+            process::exit(1);
+        },
+    };
+
+    system.enable_profiling();
+    let started = Instant::now();
+    let mut count = 0;
+    while count < frames {
+        if let Err(err) = system.run_for_duration(FemtosDuration::from_micros(BENCH_INTERVAL_US)) {
+            eprintln!("Error after {} frames: {}", count, err);
+            break;
+        }
+        while count < frames && video.next().is_some() {
+            count += 1;
+        }
+    }
+    let real_time = started.elapsed().as_secs_f64();
+    let sim_time = system.clock.as_duration().as_nanos() as f64 / 1_000_000_000.0;
+
+    let mut devices: Vec<(String, DeviceProfile)> = system
+        .profile
+        .iter()
+        .flatten()
+        .map(|(id, profile)| {
+            let name = system
+                .get_device_by_id(*id)
+                .map(|(name, _)| name.to_string())
+                .unwrap_or_default();
+            (name, *profile)
+        })
+        .collect();
+    devices.sort_by(|a, b| b.1.elapsed.cmp(&a.1.elapsed));
+    let instructions: u64 = devices
+        .iter()
+        .filter(|(name, _)| {
+            system
+                .get_device(name)
+                .map(|device| device.has(Capabilities::DEBUGGABLE))
+                .unwrap_or(false)
+        })
+        .map(|(_, profile)| profile.steps)
+        .sum();
+
+    println!("ran {} frames ({:.3}s simulated) in {:.3}s", count, sim_time, real_time);
+    println!("  frames per second:       {:.1}", count as f64 / real_time);
+    println!("  instructions per second: {:.0}", instructions as f64 / real_time);
+    println!("  speed:                   {:.2}x", sim_time / real_time);
+    println!("  {:<16} {:>12} {:>14} {:>10} {:>6}", "device", "steps", "steps/s", "time (s)", "%");
+    for (name, profile) in devices {
+        let elapsed = profile.elapsed.as_secs_f64();
+        println!(
+            "  {:<16} {:>12} {:>14.0} {:>10.3} {:>5.1}%",
+            name,
+            profile.steps,
+            profile.steps as f64 / real_time,
+            elapsed,
+            elapsed / real_time * 100.0
+        );
+    }
+}
+
+/// Add a device to run the `--script` file, if one was given, using the input queues registered by the machine
+fn add_script(matches: &ArgMatches, frontend: &MiniFrontendBuilder, system: &mut System) -> Result<(), Error> {
+    match matches.get_one::<String>("script") {
+        Some(filename) if filename.ends_with(".rhai") => {
+            RhaiScript::load(filename)?.install(system)?;
+        },
+        Some(filename) => {
+            let script = ScriptDriver::load(filename)?
+                .with_keyboard(frontend.keyboard.clone())
+                .with_controllers(frontend.controllers.clone());
+            system.add_device("script", Device::new(script))?;
+        },
+        None => {},
+    }
+    Ok(())
+}
+
+/// Restore the memory snapshot if one was given, and save the battery-backed memory and the snapshot on exit
+fn setup_autosave(matches: &ArgMatches, system: &mut System) -> Result<(), Error> {
+    if let Some(filename) = matches.get_one::<String>("restore-snapshot") {
+        system.restore_snapshot(filename)?;
+    }
+    if let Some(filename) = matches.get_one::<String>("snapshot-on-exit") {
+        system.save_snapshot_on_exit(filename);
+    }
+    system.autosave.save_on_panic();
+    Ok(())
+}
+
+fn setup_strict_mode(matches: &ArgMatches, system: &mut System) {
+    if let Some(mode) = matches.get_one::<StrictMode>("strict") {
+        system.set_strict_mode(*mode);
+    }
+}
+
 fn wait_until_initialized(frontend: Arc<Mutex<MiniFrontendBuilder>>) {
     while !frontend.lock().unwrap().finalized {
         thread::sleep(Duration::from_millis(10));
@@ -116,10 +469,16 @@ fn wait_until_initialized(frontend: Arc<Mutex<MiniFrontendBuilder>>) {
 
 pub struct MiniFrontendBuilder {
     video: Option<FrameReceiver>,
+    /// The names of the views to open windows for, and the video sources that have been added for them
+    views: Vec<String>,
+    view_sources: Vec<(String, FrameReceiver)>,
     controllers: Option<EventSender<ControllerEvent>>,
+    feedback: Option<EventReceiver<ControllerFeedback>>,
     keyboard: Option<EventSender<KeyEvent>>,
     mouse: Option<EventSender<MouseEvent>>,
     mixer: Option<AudioMixer>,
+    machine_info: MachineInfo,
+    serial_ports: SerialPorts,
     finalized: bool,
 }
 
@@ -127,16 +486,33 @@ impl Default for MiniFrontendBuilder {
     fn default() -> Self {
         Self {
             video: None,
+            views: vec![],
+            view_sources: vec![],
             controllers: None,
+            feedback: None,
             keyboard: None,
             mouse: None,
             mixer: Some(AudioMixer::with_default_rate()),
+            machine_info: MachineInfo::default(),
+            serial_ports: SerialPorts::default(),
             finalized: false,
         }
     }
 }
 
 impl MiniFrontendBuilder {
+    /// Accept the named video sources with the given names, or all of them if one of the names is "all"
+    pub fn with_views(mut self, views: Vec<String>) -> Self {
+        self.views = views;
+        self
+    }
+
+    /// Connect the named serial ports to the given transports, instead of ptys
+    pub fn with_serial_ports(mut self, serial_ports: SerialPorts) -> Self {
+        self.serial_ports = serial_ports;
+        self
+    }
+
     pub fn finalize(&mut self) {
         self.finalized = true;
     }
@@ -144,16 +520,25 @@ impl MiniFrontendBuilder {
     pub fn build(&mut self) -> MiniFrontend {
         let video = std::mem::take(&mut self.video);
         let controllers = std::mem::take(&mut self.controllers);
+        let feedback = std::mem::take(&mut self.feedback);
         let keyboard = std::mem::take(&mut self.keyboard);
         let mouse = std::mem::take(&mut self.mouse);
         let mixer = std::mem::take(&mut self.mixer);
-        MiniFrontend::new(video, controllers, keyboard, mouse, mixer.unwrap())
+        let mut frontend = MiniFrontend::new(video, controllers, keyboard, mouse, mixer.unwrap());
+        frontend.feedback = feedback;
+        frontend.views = std::mem::take(&mut self.view_sources);
+        frontend.machine_info = std::mem::take(&mut self.machine_info);
+        frontend
     }
 }
 
 impl Host for MiniFrontendBuilder {
     type Error = Error;
 
+    fn add_serial_port(&self, name: &str) -> Result<Box<dyn Tty>, HostError<Self::Error>> {
+        Ok(Box::new(self.serial_ports.open(name).map_err(HostError::Specific)?))
+    }
+
     fn add_video_source(&mut self, receiver: FrameReceiver) -> Result<(), HostError<Self::Error>> {
         if self.video.is_some() {
             return Err(HostError::Specific(Error::new("Only one video source can be registered with this frontend")));
@@ -162,6 +547,14 @@ impl Host for MiniFrontendBuilder {
         Ok(())
     }
 
+    fn add_named_video_source(&mut self, name: &str, receiver: FrameReceiver) -> Result<(), HostError<Self::Error>> {
+        if !self.views.iter().any(|view| view == name || view == "all") {
+            return Err(HostError::VideoSourceNotSupported);
+        }
+        self.view_sources.push((name.to_string(), receiver));
+        Ok(())
+    }
+
     fn add_audio_source(&mut self) -> Result<Box<dyn Audio>, HostError<Self::Error>> {
         let source = AudioSource::new(self.mixer.as_ref().unwrap().clone());
         Ok(Box::new(source))
@@ -177,6 +570,17 @@ impl Host for MiniFrontendBuilder {
         Ok(())
     }
 
+    #[cfg(feature = "gamepad")]
+    fn register_controller_feedback(&mut self, receiver: EventReceiver<ControllerFeedback>) -> Result<(), HostError<Self::Error>> {
+        if self.feedback.is_some() {
+            return Err(HostError::Specific(Error::new(
+                "A controller feedback queue has already been registered with the frontend",
+            )));
+        }
+        self.feedback = Some(receiver);
+        Ok(())
+    }
+
     fn register_keyboard(&mut self, sender: EventSender<KeyEvent>) -> Result<(), HostError<Self::Error>> {
         if self.keyboard.is_some() {
             return Err(HostError::Specific(Error::new("A keyboard updater has already been registered with the frontend")));
@@ -199,11 +603,23 @@ pub struct MiniFrontend {
     pub modifiers: u16,
     pub mouse_state: MouseState,
     pub video: Option<FrameReceiver>,
+    /// The named video sources, which are each shown in their own window
+    pub views: Vec<(String, FrameReceiver)>,
     pub controllers: Option<EventSender<ControllerEvent>>,
+    pub feedback: Option<EventReceiver<ControllerFeedback>>,
     pub keyboard: Option<EventSender<KeyEvent>>,
     pub mouse: Option<EventSender<MouseEvent>>,
     pub audio: Option<CpalAudioOutput>,
     pub mixer: AudioMixer,
+    pub machine_info: MachineInfo,
+    pub keymap: KeyMap,
+    paste: PasteQueue,
+    held: HeldInputs,
+    /// Whether any input has been sent to the machine since the last update, which has to be rerun when running ahead
+    inputs_changed: bool,
+    focused: bool,
+    /// The last horizontal position and left button state of the mouse, when it's used as a paddle
+    mouse_paddle: Option<(f32, bool)>,
 }
 
 impl MiniFrontend {
@@ -218,11 +634,20 @@ impl MiniFrontend {
             modifiers: 0,
             mouse_state: Default::default(),
             video,
+            views: vec![],
             controllers,
+            feedback: None,
             keyboard,
             mouse,
             audio: None,
             mixer,
+            machine_info: MachineInfo::default(),
+            keymap: KeyMap::default(),
+            paste: PasteQueue::default(),
+            held: HeldInputs::default(),
+            inputs_changed: false,
+            focused: true,
+            mouse_paddle: None,
         }
     }
 
@@ -236,16 +661,24 @@ impl MiniFrontend {
             _ => log::Level::Warn,
         };
 
-        simple_logger::SimpleLogger::new()
-            .with_level(log_level.to_level_filter())
-            .without_timestamps()
-            .init()
-            .unwrap();
+        // The logger accepts every level so that the level of each device can be changed while running
+        let logger = simple_logger::SimpleLogger::new()
+            .with_level(log::LevelFilter::Trace)
+            .without_timestamps();
+        logging::init(Box::new(logger), log_level.to_level_filter()).unwrap();
+
+        if let (Some(filename), Some(system)) = (matches.get_one::<String>("symbols"), system.as_mut()) {
+            if let Err(err) = system.symbols.load(filename) {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
 
         if self.mixer.borrow_mut().num_sources() != 0 && !matches.get_flag("disable-audio") {
             if let Some(system) = system.as_mut() {
-                system.add_device("mixer", Device::new(self.mixer.clone())).unwrap();
+                self.mixer.add_to_system(system).unwrap();
             }
+            self.mixer.borrow_mut().set_dither(matches.get_flag("dither"));
             self.audio = Some(CpalAudioOutput::create_audio_output(self.mixer.borrow_mut().get_sink()));
         }
 
@@ -257,29 +690,104 @@ impl MiniFrontend {
                 Some(8) => minifb::Scale::X8,
                 _ => minifb::Scale::X2,
             },
+            // Frames can be narrower than the window (eg. the Genesis in H32 mode), so keep their aspect ratio
+            scale_mode: minifb::ScaleMode::AspectRatioStretch,
             ..Default::default()
         };
 
-        let speed = matches.get_one::<f32>("speed").cloned().unwrap_or(1.0);
+        if let Some(filename) = matches.get_one::<String>("keymap") {
+            match KeyMap::load(filename) {
+                Ok(keymap) => self.keymap = keymap,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                },
+            }
+        }
+
+        if let Some(rate) = matches.get_one::<f32>("paste-rate") {
+            self.paste = PasteQueue::new(*rate);
+        }
+
+        let mouse_paddle = matches.get_flag("mouse-paddle");
+
+        let crop = matches.get_one::<(u32, u32, u32, u32)>("crop").cloned();
+        if let Some(system) = system.as_ref() {
+            self.machine_info = system.machine_info.clone();
+        }
 
-        let mut size = (WIDTH, HEIGHT);
+        let mut size = self.machine_info.video_size.unwrap_or(DEFAULT_WINDOW_SIZE);
         if let Some(queue) = self.video.as_mut() {
             size = queue.max_size();
             queue.request_encoding(PixelEncoding::ARGB);
         }
+        // The window is stretched to the machine's aspect ratio, unless the picture is cropped
+        let mut window_size = self.machine_info.window_size().unwrap_or(size);
+        if let Some((left, top, right, bottom)) = crop {
+            size = (size.0.saturating_sub(left + right).max(1), size.1.saturating_sub(top + bottom).max(1));
+            window_size = size;
+        }
+
+        let mut recorder = match (matches.get_one::<String>("record-video"), self.video.as_ref()) {
+            (Some(filename), Some(queue)) => match VideoRecorder::with_crop(queue, filename, crop) {
+                Ok(recorder) => Some(recorder),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                },
+            },
+            (Some(_), None) => {
+                eprintln!("This machine has no video output to record");
+                process::exit(1);
+            },
+            (None, _) => None,
+        };
 
-        let mut window = minifb::Window::new("Test - ESC to exit", size.0 as usize, size.1 as usize, options).unwrap_or_else(|e| {
+        let title = match self.machine_info.name.as_str() {
+            "" => "Test - ESC to exit".to_string(),
+            name => format!("{} - ESC to exit", name),
+        };
+        let mut window = minifb::Window::new(&title, window_size.0 as usize, window_size.1 as usize, options).unwrap_or_else(|e| {
             panic!("{}", e);
         });
 
-        // Limit to max ~60 fps update rate
-        window.limit_update_rate(Some(Duration::from_micros(16600)));
-        //let nanoseconds_per_frame = (16_600_000 as f32 * speed) as u64;
+        let mut views: Vec<(minifb::Window, FrameReceiver)> = std::mem::take(&mut self.views)
+            .into_iter()
+            .map(|(name, receiver)| {
+                let (width, height) = receiver.max_size();
+                receiver.request_encoding(PixelEncoding::ARGB);
+                let mut view = minifb::Window::new(&name, width as usize, height as usize, options).unwrap_or_else(|e| {
+                    panic!("{}", e);
+                });
+                // The main window's update already waits for the next frame
+                view.limit_update_rate(None);
+                (view, receiver)
+            })
+            .collect();
+
+        // Limit the update rate to the machine's refresh rate
+        let refresh_hz = self
+            .machine_info
+            .refresh_rate
+            .map(|rate| rate.as_hz() as f64)
+            .unwrap_or(DEFAULT_REFRESH_HZ);
+        window.limit_update_rate(Some(Duration::from_secs_f64(1.0 / refresh_hz)));
+
+        let mut executor = Executor::for_machine(&self.machine_info);
+        if let Some(speed) = matches.get_one::<f32>("speed") {
+            executor.set_speed(*speed);
+        }
+        if let Some(frames) = matches.get_one::<usize>("run-ahead") {
+            executor.set_run_ahead(*frames);
+        }
+
+        #[cfg(feature = "gamepad")]
+        let mut gamepads = gamepad::Gamepads::new();
 
         let mut debugger = Debugger::default();
         let mut run_debugger = matches.get_flag("debugger");
-        let mut update_timer = Instant::now();
         let mut last_frame = Frame::new(size.0, size.1, PixelEncoding::ARGB);
+        let mut redraw = true;
         while window.is_open() && !window.is_key_down(Key::Escape) {
             if run_debugger {
                 if let Some(system) = system.as_mut() {
@@ -288,9 +796,20 @@ impl MiniFrontend {
                         let mut buffer = String::new();
                         io::stdout().write_all(b"> ").unwrap();
                         io::stdin().read_line(&mut buffer).unwrap();
-                        match debugger.run_command(system, &buffer) {
+                        let result = debugger.run_command(system, &buffer);
+                        if let Some(speed) = debugger.take_speed() {
+                            executor.set_speed(speed);
+                        }
+                        if let Some(filename) = debugger.take_screenshot() {
+                            save_screenshot(&self.machine_info, &last_frame, filename);
+                        }
+                        if let Some(text) = debugger.take_paste() {
+                            self.paste_text(&text);
+                        }
+                        match result {
                             Ok(DebugControl::Exit) => {
                                 run_debugger = false;
+                                executor.reset();
                             },
                             Ok(_) => {},
                             Err(err) => {
@@ -299,39 +818,75 @@ impl MiniFrontend {
                         }
                     }
                 }
-            } else {
-                let frame_time = update_timer.elapsed();
-                update_timer = Instant::now();
-                //println!("new frame after {:?}us", frame_time.as_micros());
-
-                //let run_timer = Instant::now();
-                if let Some(system) = system.as_mut() {
-                    //system.run_for(nanoseconds_per_frame).unwrap();
-                    match system.run_for_duration(FemtosDuration::from_nanos((frame_time.as_nanos() as f32 * speed) as u64)) {
-                        Ok(()) => {},
-                        Err(Error::Breakpoint(_)) => {
+            } else if let Some(system) = system.as_mut() {
+                executor.set_fast_forward(window.is_key_down(Key::PageUp));
+                executor.set_slow_motion(window.is_key_down(Key::PageDown));
+                if mem::take(&mut self.inputs_changed) {
+                    executor.input_changed();
+                }
+                let simulated = match executor.update(system) {
+                    Ok(simulated) => simulated,
+                    Err(Error::Breakpoint(info)) => {
+                        if debugger.breakpoint_occurred(system, &info) {
                             run_debugger = true;
-                        },
-                        Err(err) => panic!("{:?}", err),
-                    }
-                    //system.run_until_break().unwrap();
+                        }
+                        Duration::ZERO
+                    },
+                    Err(err) => panic!("{:?}", err),
+                };
+
+                // Pasted text is typed at a rate in simulated time, so it isn't typed faster than the machine runs
+                for event in self.paste.update(simulated) {
+                    self.send_key_event(event);
                 }
-                //let sim_time = run_timer.elapsed().as_micros();
-                //println!("ran simulation for {:?}us in {:?}us (avg: {:?}us)", frame_time.as_micros(), sim_time, frame_time.as_micros() as f64 / sim_time as f64);
             }
 
+            // The release events for any keys held down when the window loses focus would go to another window
+            let focused = window.is_active();
+            if self.focused && !focused {
+                self.release_held_inputs();
+            }
+            self.focused = focused;
+
             for key in window.get_keys_pressed(minifb::KeyRepeat::No) {
                 self.check_key(key, true);
 
                 // Process special keys
-                if let Key::D = key {
-                    run_debugger = true;
+                match key {
+                    Key::D => run_debugger = true,
+                    Key::NumPadPlus => change_speed(&mut executor, 2.0),
+                    Key::NumPadMinus => change_speed(&mut executor, 0.5),
+                    Key::Pause => {
+                        let paused = executor.toggle_pause();
+                        println!("simulation is {}", if paused { "paused" } else { "running" });
+                    },
+                    Key::End => executor.step_frame(),
+                    Key::F10 => self.paste_clipboard(),
+                    Key::F12 => save_screenshot(&self.machine_info, &last_frame, None),
+                    _ => {},
                 }
             }
             for key in window.get_keys_released() {
                 self.check_key(key, false);
             }
 
+            #[cfg(feature = "gamepad")]
+            if let Some(gamepads) = gamepads.as_mut() {
+                // Controller input is ignored while in the background, like the keyboard
+                for event in gamepads.update() {
+                    if self.focused {
+                        self.send_controller_event(event);
+                    }
+                }
+                if let Some(receiver) = self.feedback.as_ref() {
+                    gamepads.apply_feedback(receiver);
+                }
+            }
+
+            if mouse_paddle && self.focused {
+                self.update_mouse_paddle(&window);
+            }
+
             if let Some(sender) = self.mouse.as_mut() {
                 if let Some((x, y)) = window.get_mouse_pos(MouseMode::Clamp) {
                     let left = window.get_mouse_down(MouseButton::Left);
@@ -339,34 +894,173 @@ impl MiniFrontend {
                     let middle = window.get_mouse_down(MouseButton::Middle);
 
                     let next_state = MouseState::with(left, right, middle, x as u32, y as u32);
-                    self.mouse_state
-                        .to_events(next_state)
-                        .into_iter()
-                        .for_each(|event| sender.send(event));
+                    for event in self.mouse_state.to_events(next_state) {
+                        sender.send(event);
+                        self.inputs_changed = true;
+                    }
+                }
+            }
+
+            if let Some(queue) = recorder.as_mut() {
+                if let Err(err) = queue.update() {
+                    eprintln!("{}", err);
+                    recorder = None;
                 }
             }
 
             if let Some(queue) = self.video.as_mut() {
                 if let Some((_clock, frame)) = queue.latest() {
-                    last_frame = frame
+                    last_frame = frame;
+                    if let Some((left, top, right, bottom)) = crop {
+                        last_frame.crop(left, top, right, bottom);
+                    }
+                    redraw |= !last_frame.is_unchanged();
+                }
+                // The window keeps the last buffer it was given, so it only needs a new one when the frame changed
+                if redraw {
+                    window
+                        .update_with_buffer(&last_frame.bitmap, last_frame.width as usize, last_frame.height as usize)
+                        .unwrap();
+                    redraw = false;
+                } else {
+                    window.update();
                 }
-                window
-                    .update_with_buffer(&last_frame.bitmap, last_frame.width as usize, last_frame.height as usize)
-                    .unwrap();
+            }
+
+            // Closing a view's window only stops showing it, but the machine keeps drawing it
+            views.retain_mut(|(view, receiver)| {
+                match receiver.latest() {
+                    Some((_, frame)) if !frame.is_unchanged() => {
+                        view.update_with_buffer(&frame.bitmap, frame.width as usize, frame.height as usize)
+                            .unwrap();
+                    },
+                    _ => view.update(),
+                }
+                view.is_open()
+            });
+        }
+
+        if let Some(recorder) = recorder {
+            match recorder.finish() {
+                Ok(()) => println!("saved the recording to {}", matches.get_one::<String>("record-video").unwrap()),
+                Err(err) => eprintln!("{}", err),
+            }
+        }
+
+        if let Some(system) = system.as_ref() {
+            if let Err(err) = system.autosave.save() {
+                eprintln!("Error saving: {}", err);
             }
         }
     }
 
     fn check_key(&mut self, key: Key, state: bool) {
+        let key = map_key(key);
+        self.send_key_event(KeyEvent::new(self.keymap.map_key(key), state));
+
+        for event in self.keymap.controller_events(key, state) {
+            self.send_controller_event(event);
+        }
+    }
+
+    /// Send the position of the mouse across the window as the paddle position, the distance it moved as the
+    /// dial movement, and the left button as button A
+    fn update_mouse_paddle(&mut self, window: &minifb::Window) {
+        let Some((x, _)) = window.get_mouse_pos(MouseMode::Clamp) else {
+            return;
+        };
+        let width = window.get_size().0.max(2) as f32;
+        let button = window.get_mouse_down(MouseButton::Left);
+        let previous = self.mouse_paddle.replace((x, button));
+        let (last_x, last_button) = previous.unwrap_or((x, false));
+
+        let device = ControllerDevice::A;
+        if x != last_x || previous.is_none() {
+            let position = (x / (width - 1.0) * u16::MAX as f32).clamp(0.0, u16::MAX as f32) as u16;
+            self.send_controller_event(ControllerEvent::new(device, ControllerInput::Paddle(position)));
+            let delta = (x - last_x).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            if delta != 0 {
+                self.send_controller_event(ControllerEvent::new(device, ControllerInput::Dial(delta)));
+            }
+        }
+        if button != last_button {
+            self.send_controller_event(ControllerEvent::new(device, ControllerInput::ButtonA(button)));
+        }
+    }
+
+    fn send_key_event(&mut self, event: KeyEvent) {
         if let Some(sender) = self.keyboard.as_mut() {
-            sender.send(KeyEvent::new(map_key(key), state));
+            self.held.update_key(event);
+            sender.send(event);
+            self.inputs_changed = true;
+        }
+    }
+
+    /// Type the text on the host's clipboard into the machine
+    #[cfg(feature = "clipboard")]
+    fn paste_clipboard(&mut self) {
+        match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_text()) {
+            Ok(text) => self.paste_text(&text),
+            Err(err) => log::warn!("unable to read the clipboard: {}", err),
+        }
+    }
+
+    #[cfg(not(feature = "clipboard"))]
+    fn paste_clipboard(&mut self) {
+        log::warn!("pasting from the clipboard needs the `clipboard` feature");
+    }
+
+    fn paste_text(&mut self, text: &str) {
+        if self.keyboard.is_none() {
+            log::warn!("unable to paste, since the machine doesn't have a keyboard");
+            return;
         }
+        let skipped = self.paste.push_text(text);
+        if skipped > 0 {
+            log::warn!("skipped {} characters that can't be typed", skipped);
+        }
+    }
 
+    fn send_controller_event(&mut self, event: ControllerEvent) {
         if let Some(sender) = self.controllers.as_mut() {
-            if let Some(input) = map_controller_a(key, state) {
-                let event = ControllerEvent::new(ControllerDevice::A, input);
+            self.held.update_button(event);
+            sender.send(event);
+            self.inputs_changed = true;
+        }
+    }
+
+    fn release_held_inputs(&mut self) {
+        self.inputs_changed = true;
+        for event in self.held.release_keys() {
+            if let Some(sender) = self.keyboard.as_mut() {
+                sender.send(event);
+            }
+        }
+        for event in self.held.release_buttons() {
+            if let Some(sender) = self.controllers.as_mut() {
                 sender.send(event);
             }
         }
     }
 }
+
+/// Save the frame that's being displayed as a PNG, to a new numbered file named after the machine if no
+/// filename is given
+fn save_screenshot(machine_info: &MachineInfo, frame: &Frame, filename: Option<String>) {
+    let filename = filename.unwrap_or_else(|| {
+        let prefix = match machine_info.name.as_str() {
+            "" => "screenshot".to_string(),
+            name => format!("{}-screenshot", name),
+        };
+        capture::next_screenshot_filename(&prefix)
+    });
+    match capture::save_screenshot(frame, &filename) {
+        Ok(()) => println!("saved a screenshot to {}", filename),
+        Err(err) => eprintln!("{}", err),
+    }
+}
+
+fn change_speed(executor: &mut Executor, factor: f32) {
+    let speed = executor.change_speed(factor);
+    println!("simulation speed is now {}x", speed);
+}
@@ -1,14 +1,37 @@
 
 import * as Emulator from './moa-genesis.js';
+import * as Storage from './storage.js';
 
-window.addEventListener("load", () => {
-    Emulator.default();
+// How often to save the battery-backed memory while running, in milliseconds
+const SAVE_INTERVAL = 5000;
+
+// The name of the ROM file that's loaded, which the saves are stored under
+let rom_name = null;
+let running_system = null;
+
+window.addEventListener("load", async () => {
+    await Emulator.default();
+
+    // Load the last ROM that was used, if there is one
+    const rom = await Storage.load("rom").catch(err => console.error(err));
+    if (rom) {
+        set_rom(rom.name, rom.data);
+    }
 });
 
-function initialize_emulator() {
+async function initialize_emulator() {
     const host = Emulator.new_host();
     const system = Emulator.load_system(host, Emulator.get_load_system_fn());
 
+    // Restore the game saves before the game has a chance to read them
+    for (const name of Emulator.saved_memory_names(system)) {
+        const contents = await Storage.load(`${rom_name}/${name}`).catch(err => console.error(err));
+        if (contents) {
+            Emulator.restore_saved_memory(system, name, contents);
+        }
+    }
+    running_system = system;
+
     //Emulator.start_system(system);
     let last_update = performance.now();
     setTimeout(function refreshFrame() {
@@ -24,8 +47,12 @@ function initialize_emulator() {
             // Calculate the timeout needed to fill the time that was *not* taken by the sim
             const remaining = Math.max(diff - runtime - (diff * 0.1), 1);
             setTimeout(refreshFrame, remaining);
+        } else {
+            save_memories(system);
+            clearInterval(save_timer);
         }
     }, 0);
+    const save_timer = setInterval(() => save_memories(system), SAVE_INTERVAL);
 
     const controllers = Emulator.get_controllers(host);
     function button_event(e) {
@@ -54,20 +81,79 @@ const frame_rate = setInterval(function () {
     frame_rate_el.value = Emulator.get_frames_since();
 }, 1000);
 
-// Load a new ROM file
-const reader = new FileReader();
-reader.onloadend = function (e) {
-    let data = new Uint8Array(reader.result);
+// Save the contents of each battery-backed memory under the name of the ROM
+function save_memories(system) {
+    for (const name of Emulator.saved_memory_names(system)) {
+        const contents = Emulator.read_saved_memory(system, name);
+        if (contents) {
+            Storage.store(`${rom_name}/${name}`, contents).catch(err => console.error(err));
+        }
+    }
+}
+
+function set_rom(name, data) {
+    rom_name = name;
+    Emulator.set_rom_data(data);
+    document.getElementById("rom-name").textContent = name;
+}
+
+// Load a new ROM file, and remember it for the next time the page is opened
+async function load_rom_file(file) {
+    let data = new Uint8Array(await file.arrayBuffer());
     // If the SMD file magic number is present, then convert it before loading
     if (data[8] == 0xAA && data[9] == 0xBB)
         data = Emulator.smd_to_bin(data);
-    Emulator.set_rom_data(data);
-};
+    set_rom(file.name, data);
+    Storage.store("rom", { name: file.name, data: data }).catch(err => console.error(err));
+}
 
 const file_input = document.getElementById("rom-file");
 file_input.addEventListener("change", e => {
     document.getElementById("video").focus();
-    reader.readAsArrayBuffer(file_input.files[0])
+    load_rom_file(file_input.files[0]);
+});
+
+const video_screen = document.getElementById("video-screen");
+video_screen.addEventListener("dragover", e => {
+    e.preventDefault();
+    video_screen.classList.add("dragging");
+});
+video_screen.addEventListener("dragleave", e => {
+    video_screen.classList.remove("dragging");
+});
+video_screen.addEventListener("drop", e => {
+    e.preventDefault();
+    video_screen.classList.remove("dragging");
+    if (e.dataTransfer.files.length > 0) {
+        load_rom_file(e.dataTransfer.files[0]);
+    }
+});
+
+// Save the game when the page is closed or hidden, since it might not get another chance
+document.addEventListener("visibilitychange", () => {
+    if (document.visibilityState == "hidden" && running_system && Emulator.is_running()) {
+        save_memories(running_system);
+    }
+});
+
+document.getElementById("save-state").addEventListener("click", () => {
+    document.getElementById("video").focus();
+    if (running_system && Emulator.is_running()) {
+        const snapshot = Emulator.save_state(running_system);
+        if (snapshot) {
+            Storage.store(`${rom_name}/state`, snapshot).catch(err => console.error(err));
+        }
+    }
+});
+
+document.getElementById("load-state").addEventListener("click", async () => {
+    document.getElementById("video").focus();
+    if (running_system && Emulator.is_running()) {
+        const snapshot = await Storage.load(`${rom_name}/state`).catch(err => console.error(err));
+        if (snapshot) {
+            Emulator.load_state(running_system, snapshot);
+        }
+    }
 });
 
 document.getElementById("reset").addEventListener("click", () => {
@@ -79,6 +165,8 @@ document.getElementById("power").addEventListener("click", () => {
     document.getElementById("video").focus();
     if (Emulator.is_running())
         Emulator.request_stop();
+    else if (rom_name === null)
+        alert("Choose a ROM file to load first");
     else
         initialize_emulator();
 });
@@ -0,0 +1,36 @@
+
+// Keeps the last ROM that was loaded, and the saves for each ROM, in the browser's IndexedDB storage
+
+const DB_NAME = "moa";
+const STORE_NAME = "files";
+
+let db_promise = null;
+
+function open_db() {
+    if (!db_promise) {
+        db_promise = new Promise((resolve, reject) => {
+            const request = indexedDB.open(DB_NAME, 1);
+            request.onupgradeneeded = () => request.result.createObjectStore(STORE_NAME);
+            request.onsuccess = () => resolve(request.result);
+            request.onerror = () => reject(request.error);
+        });
+    }
+    return db_promise;
+}
+
+function transaction(mode, action) {
+    return open_db().then(db => new Promise((resolve, reject) => {
+        const request = action(db.transaction(STORE_NAME, mode).objectStore(STORE_NAME));
+        request.onsuccess = () => resolve(request.result);
+        request.onerror = () => reject(request.error);
+    }));
+}
+
+// Returns the value stored under the key, or undefined if there isn't one
+export function load(key) {
+    return transaction("readonly", store => store.get(key));
+}
+
+export function store(key, value) {
+    return transaction("readwrite", store => store.put(value, key));
+}
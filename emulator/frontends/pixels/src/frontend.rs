@@ -5,7 +5,10 @@ use winit::event::{Event, VirtualKeyCode, WindowEvent, ElementState};
 use winit::event_loop::{ControlFlow, EventLoop};
 
 use moa_core::{System, Error};
-use moa_host::{Host, HostError, PixelEncoding, Frame, ControllerDevice, ControllerInput, ControllerEvent, EventSender, Audio, DummyAudio, FrameReceiver};
+use moa_host::{
+    Host, HostError, PixelEncoding, Frame, ControllerDevice, ControllerInput, ControllerEvent, EventSender, Audio, DummyAudio,
+    FrameReceiver, HeldInputs, Rect,
+};
 use moa_common::{AudioMixer, AudioSource, CpalAudioOutput};
 
 use crate::settings;
@@ -88,6 +91,7 @@ pub async fn run_loop(host: PixelsFrontend) {
     };
 
     let mut mute = false;
+    let mut held = HeldInputs::default();
     let mut last_size = (WIDTH, HEIGHT);
     let mut last_frame = Frame::new(WIDTH, HEIGHT, PixelEncoding::ABGR);
     //let mut update_timer = Instant::now();
@@ -101,17 +105,24 @@ pub async fn run_loop(host: PixelsFrontend) {
             //update_timer = Instant::now();
 
             if let Some(updater) = host.video.as_ref() {
+                let mut dirty = Rect::default();
                 if let Some((clock, frame)) = updater.latest() {
                     last_frame = frame;
+                    dirty = last_frame.dirty_rect();
                 }
 
                 if (last_frame.width, last_frame.height) != last_size {
                     last_size = (last_frame.width, last_frame.height);
                     pixels.resize_buffer(last_frame.width, last_frame.height);
+                    dirty = Rect::new(0, 0, last_frame.width, last_frame.height);
                 }
 
-                let buffer = pixels.frame_mut();
-                buffer.copy_from_slice(unsafe { std::slice::from_raw_parts(last_frame.bitmap.as_ptr() as *const u8, last_frame.bitmap.len() * 4) });
+                // Only the lines that changed are copied into the buffer, which keeps the rest from the last frame
+                if !dirty.is_empty() {
+                    let lines = (dirty.y * last_frame.width) as usize..((dirty.y + dirty.height) * last_frame.width) as usize;
+                    let buffer = &mut pixels.frame_mut()[lines.start * 4..lines.end * 4];
+                    buffer.copy_from_slice(unsafe { std::slice::from_raw_parts(last_frame.bitmap[lines].as_ptr() as *const u8, buffer.len()) });
+                }
             }
 
             if pixels
@@ -141,12 +152,26 @@ pub async fn run_loop(host: PixelsFrontend) {
                 if let Some(sender) = host.controllers.as_ref() {
                     if let Some(key) = key {
                         let event = ControllerEvent::new(ControllerDevice::A, key);
+                        held.update_button(event);
                         sender.send(event);
                     }
                 }
             }
         }
 
+        // Release any held buttons when the window loses focus, since their release events will go elsewhere
+        if let Event::WindowEvent {
+            event: WindowEvent::Focused(false),
+            ..
+        } = event
+        {
+            if let Some(sender) = host.controllers.as_ref() {
+                for event in held.release_buttons() {
+                    sender.send(event);
+                }
+            }
+        }
+
         if let Some(output) = audio_output.as_ref() {
             let requested_mute = settings::get().mute;
             if requested_mute != mute {
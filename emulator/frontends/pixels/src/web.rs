@@ -13,15 +13,14 @@ use wasm_bindgen::JsCast;
 use wasm_bindgen::closure::Closure;
 
 use femtos::{Duration as FemtosDuration};
-use moa_core::{System, Device};
+use moa_core::System;
+use moa_common::Executor;
 use moa_host::{ControllerInput, ControllerDevice, ControllerEvent, EventSender};
 
 use crate::settings;
 use crate::frontend::{self, PixelsFrontend, LoadSystemFn};
 
 pub fn start(load: LoadSystemFn) {
-    settings::set_rom_data(include_bytes!("../sonic.bin").to_vec());
-
     std::panic::set_hook(Box::new(console_error_panic_hook::hook));
     console_log::init_with_level(log::Level::Warn).expect("error initializing logger");
 
@@ -131,11 +130,52 @@ pub fn load_system(handle: &mut HostHandle, load: LoadSystemFnHandle) -> SystemH
     let mut system = load.0(&mut handle.0, settings::get().rom_data.clone()).unwrap();
     let mixer = handle.0.get_mixer();
     if mixer.borrow_mut().num_sources() > 0 {
-        system.add_device("mixer", Device::new(mixer.clone())).unwrap();
+        mixer.add_to_system(&mut system).unwrap();
     }
     SystemHandle(system)
 }
 
+/// Returns the names of the system's battery-backed memories, which the page saves in the browser's storage
+#[wasm_bindgen]
+pub fn saved_memory_names(handle: &SystemHandle) -> Box<[JsValue]> {
+    let names = handle.0.autosave.memory_names();
+    names.iter().map(|name| JsValue::from_str(name)).collect()
+}
+
+#[wasm_bindgen]
+pub fn read_saved_memory(handle: &SystemHandle, name: String) -> Option<Vec<u8>> {
+    handle
+        .0
+        .autosave
+        .read_memory(&name)
+        .map_err(|err| log::error!("{}", err))
+        .ok()
+}
+
+#[wasm_bindgen]
+pub fn restore_saved_memory(handle: &SystemHandle, name: String, contents: Vec<u8>) {
+    if let Err(err) = handle.0.autosave.restore_memory(&name, &contents) {
+        log::error!("{}", err);
+    }
+}
+
+/// Returns a snapshot of all of the system's memory, which can be restored later to continue from this point
+#[wasm_bindgen]
+pub fn save_state(handle: &SystemHandle) -> Option<Vec<u8>> {
+    handle.0.memory_snapshot().map_err(|err| log::error!("{}", err)).ok()
+}
+
+#[wasm_bindgen]
+pub fn load_state(handle: &SystemHandle, snapshot: Vec<u8>) -> bool {
+    match handle.0.restore_memory_snapshot(&snapshot) {
+        Ok(()) => true,
+        Err(err) => {
+            log::error!("{}", err);
+            false
+        },
+    }
+}
+
 #[wasm_bindgen]
 pub fn run_system_for(handle: &mut SystemHandle, nanos: u32) -> usize {
     let run_timer = Instant::now();
@@ -233,11 +273,36 @@ pub fn start_system(handle: SystemHandle) -> Handle {
 #[wasm_bindgen]
 pub struct Handle(Rc<RefCell<Emulator>>);
 
+#[wasm_bindgen]
+pub fn toggle_pause(handle: &Handle) -> bool {
+    handle.0.borrow_mut().executor.toggle_pause()
+}
+
+#[wasm_bindgen]
+pub fn step_frame(handle: &Handle) {
+    handle.0.borrow_mut().executor.step_frame();
+}
+
+#[wasm_bindgen]
+pub fn set_fast_forward(handle: &Handle, fast_forward: bool) {
+    handle.0.borrow_mut().executor.set_fast_forward(fast_forward);
+}
+
+#[wasm_bindgen]
+pub fn set_slow_motion(handle: &Handle, slow_motion: bool) {
+    handle.0.borrow_mut().executor.set_slow_motion(slow_motion);
+}
+
+#[wasm_bindgen]
+pub fn set_system_speed(handle: &Handle, speed: f32) {
+    handle.0.borrow_mut().executor.set_speed(speed);
+}
+
 pub struct Emulator {
     running: bool,
     //frontend: PixelsFrontend,
     system: System,
-    last_update: Instant,
+    executor: Executor,
 }
 
 impl Emulator {
@@ -245,7 +310,7 @@ impl Emulator {
         Rc::new(RefCell::new(Self {
             running: false,
             system,
-            last_update: Instant::now(),
+            executor: Executor::default(),
         }))
     }
 
@@ -254,29 +319,29 @@ impl Emulator {
     }
 }
 
-/// This updater tries to work like the JS one, by simulating the amount of time that has passed
-/// since the last update, but it doesn't work too well yet
+/// This updater simulates the amount of time that has passed since the last update, using the executor
+/// to pace it, so that it can be paused, stepped, or fast forwarded from the page
 fn update(emulator: Rc<RefCell<Emulator>>) {
     let run_timer = Instant::now();
-    let last_update = {
+    let result = {
         let mut emulator = emulator.borrow_mut();
-        let last_update = emulator.last_update;
-        emulator.last_update = run_timer;
-        last_update
+        let Emulator {
+            system,
+            executor,
+            ..
+        } = &mut *emulator;
+        executor.update(system)
     };
 
-    let diff = run_timer.duration_since(last_update);
-    let nanoseconds_per_frame = FemtosDuration::from_nanos(diff.as_nanos() as u64);
-    //let nanoseconds_per_frame = (16_600_000 as f32 * settings::get().speed) as Clock;
-    if let Err(err) = emulator.borrow_mut().system.run_for_duration(nanoseconds_per_frame) {
-        log::error!("{:?}", err);
+    match result {
+        Ok(simulated) => {
+            log::debug!("ran simulation for {:?}ms in {:?}ms", simulated.as_millis(), run_timer.elapsed().as_millis())
+        },
+        Err(err) => log::error!("{:?}", err),
     }
-    let run_time = run_timer.elapsed().as_millis();
-    log::debug!("ran simulation for {:?}ms in {:?}ms", nanoseconds_per_frame / 1_000_000_u32, run_time);
 
     let running = emulator.borrow().running;
     if running {
-        let remaining = (diff.as_millis() - run_time - (diff.as_millis() / 10)).max(1);
         set_timeout(emulator, 16);
     }
 }
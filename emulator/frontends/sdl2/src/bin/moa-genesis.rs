@@ -0,0 +1,44 @@
+use std::process;
+
+use clap::{Arg, ArgAction};
+
+use moa_systems_genesis::{build_genesis, SegaGenesisOptions};
+
+fn main() {
+    let matches = moa_sdl2::new("Sega Genesis/Mega Drive Emulator")
+        .arg(Arg::new("ROM").help("ROM file to load (must be flat binary)"))
+        .arg(
+            Arg::new("overscan")
+                .long("overscan")
+                .action(ArgAction::SetTrue)
+                .help("Draw the border around the display, which shows the background colour"),
+        )
+        .arg(
+            Arg::new("interleave")
+                .long("interleave")
+                .value_name("MICROSECONDS")
+                .value_parser(clap::value_parser!(u64))
+                .help("Run each cpu up to this far ahead of the other devices before switching, for speed over accuracy"),
+        )
+        .arg(
+            Arg::new("threaded-audio")
+                .long("threaded-audio")
+                .action(ArgAction::SetTrue)
+                .help("Run the sound chips on threads of their own, in parallel with the cpus"),
+        )
+        .get_matches();
+
+    let mut options = SegaGenesisOptions::default();
+    match matches.get_one::<String>("ROM") {
+        Some(filename) => options.rom = filename.to_string(),
+        None => {
+            eprintln!("No ROM file given");
+            process::exit(1);
+        },
+    }
+    options.overscan = matches.get_flag("overscan");
+    options.interleave_us = matches.get_one::<u64>("interleave").cloned();
+    options.threaded_audio = matches.get_flag("threaded-audio");
+
+    moa_sdl2::run(matches, |frontend| build_genesis(frontend, options));
+}
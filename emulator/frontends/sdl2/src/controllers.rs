@@ -0,0 +1,53 @@
+use sdl2::keyboard::Scancode;
+use sdl2::controller::{Button, Axis};
+use moa_host::{ControllerAxis, ControllerInput};
+
+/// Map the keys that play controller A, which are A, S, and D for the buttons on a QWERTY layout
+pub fn map_controller_a(scancode: Scancode, state: bool) -> Option<ControllerInput> {
+    match scancode {
+        Scancode::A => Some(ControllerInput::ButtonA(state)),
+        Scancode::S => Some(ControllerInput::ButtonB(state)),
+        Scancode::D => Some(ControllerInput::ButtonC(state)),
+        Scancode::Q => Some(ControllerInput::ButtonX(state)),
+        Scancode::W => Some(ControllerInput::ButtonY(state)),
+        Scancode::E => Some(ControllerInput::ButtonZ(state)),
+        Scancode::Up => Some(ControllerInput::DpadUp(state)),
+        Scancode::Down => Some(ControllerInput::DpadDown(state)),
+        Scancode::Left => Some(ControllerInput::DpadLeft(state)),
+        Scancode::Right => Some(ControllerInput::DpadRight(state)),
+        Scancode::Return => Some(ControllerInput::Start(state)),
+        Scancode::M => Some(ControllerInput::Mode(state)),
+        _ => None,
+    }
+}
+
+pub fn map_button(button: Button, state: bool) -> Option<ControllerInput> {
+    match button {
+        Button::A => Some(ControllerInput::ButtonA(state)),
+        Button::B => Some(ControllerInput::ButtonB(state)),
+        Button::RightShoulder => Some(ControllerInput::ButtonC(state)),
+        Button::X => Some(ControllerInput::ButtonX(state)),
+        Button::Y => Some(ControllerInput::ButtonY(state)),
+        Button::LeftShoulder => Some(ControllerInput::ButtonZ(state)),
+        Button::DPadUp => Some(ControllerInput::DpadUp(state)),
+        Button::DPadDown => Some(ControllerInput::DpadDown(state)),
+        Button::DPadLeft => Some(ControllerInput::DpadLeft(state)),
+        Button::DPadRight => Some(ControllerInput::DpadRight(state)),
+        Button::Start => Some(ControllerInput::Start(state)),
+        Button::Back | Button::Guide => Some(ControllerInput::Mode(state)),
+        _ => None,
+    }
+}
+
+/// Convert an axis from SDL, which already has the same range and direction as a controller axis
+pub fn map_axis(axis: Axis, value: i16) -> ControllerInput {
+    let axis = match axis {
+        Axis::LeftX => ControllerAxis::LeftX,
+        Axis::LeftY => ControllerAxis::LeftY,
+        Axis::RightX => ControllerAxis::RightX,
+        Axis::RightY => ControllerAxis::RightY,
+        Axis::TriggerLeft => ControllerAxis::LeftTrigger,
+        Axis::TriggerRight => ControllerAxis::RightTrigger,
+    };
+    ControllerInput::Axis(axis, value)
+}
@@ -0,0 +1,145 @@
+use sdl2::GameControllerSubsystem;
+use sdl2::controller::GameController;
+use sdl2::event::Event;
+
+use moa_host::{ControllerDevice, ControllerInput, ControllerEvent, ControllerOutput, ControllerFeedback, EventReceiver, HeldInputs};
+
+use crate::controllers::{map_button, map_axis};
+
+
+const DEVICES: [ControllerDevice; 4] = [ControllerDevice::A, ControllerDevice::B, ControllerDevice::C, ControllerDevice::D];
+
+/// Physical game controllers, which are each assigned to the first emulated controller that's free when they're
+/// connected, so unplugging one controller doesn't change which emulated controller the others are playing
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    slots: Vec<Option<GameController>>,
+    held: HeldInputs,
+}
+
+impl Gamepads {
+    pub fn new(subsystem: GameControllerSubsystem) -> Self {
+        // The controllers that are already connected are reported as added when the events are first polled
+        Self {
+            subsystem,
+            slots: (0..DEVICES.len()).map(|_| None).collect(),
+            held: HeldInputs::default(),
+        }
+    }
+
+    /// Returns the controller events for an SDL event from a physical controller, and handles the controllers
+    /// being connected and disconnected, which releases everything that was held down on a disconnected one
+    pub fn handle_event(&mut self, event: &Event) -> Vec<ControllerEvent> {
+        if let Event::ControllerDeviceRemoved {
+            which,
+            ..
+        } = *event
+        {
+            return match self.slot_of(which) {
+                Some(slot) => {
+                    log::info!("controller {:?} disconnected", DEVICES[slot]);
+                    self.slots[slot] = None;
+                    self.held.release_device(DEVICES[slot])
+                },
+                None => vec![],
+            };
+        }
+
+        let event = match self.controller_event(event) {
+            Some(event) => event,
+            None => return vec![],
+        };
+        self.held.update_button(event);
+        vec![event]
+    }
+
+    fn controller_event(&mut self, event: &Event) -> Option<ControllerEvent> {
+        match *event {
+            Event::ControllerDeviceAdded {
+                which,
+                ..
+            } => {
+                self.connect(which);
+                None
+            },
+            Event::ControllerButtonDown {
+                which,
+                button,
+                ..
+            } => self.event_for(which, map_button(button, true)?),
+            Event::ControllerButtonUp {
+                which,
+                button,
+                ..
+            } => self.event_for(which, map_button(button, false)?),
+            Event::ControllerAxisMotion {
+                which,
+                axis,
+                value,
+                ..
+            } => self.event_for(which, map_axis(axis, value)),
+            _ => None,
+        }
+    }
+
+    pub fn apply_feedback(&mut self, receiver: &EventReceiver<ControllerFeedback>) {
+        while let Some(feedback) = receiver.receive() {
+            let index = DEVICES.iter().position(|device| *device == feedback.device).unwrap();
+            let controller = match self.slots[index].as_mut() {
+                Some(controller) => controller,
+                None => continue,
+            };
+
+            match feedback.output {
+                ControllerOutput::Rumble {
+                    strong,
+                    weak,
+                    duration_ms,
+                } => {
+                    if let Err(err) = controller.set_rumble(strong, weak, duration_ms) {
+                        log::warn!("unable to rumble controller {:?}: {}", feedback.device, err);
+                    }
+                },
+                ControllerOutput::Led(led, state) => {
+                    // SDL can only set the colour of a controller's light bar, not turn on the player lights
+                    log::debug!("ignoring led {} set to {} on controller {:?}", led, state, feedback.device);
+                },
+            }
+        }
+    }
+
+    fn connect(&mut self, joystick_index: u32) {
+        let controller = match self.subsystem.open(joystick_index) {
+            Ok(controller) => controller,
+            Err(err) => {
+                log::warn!("unable to open game controller {}: {}", joystick_index, err);
+                return;
+            },
+        };
+
+        // The same controller can be reported as added more than once
+        if self.slot_of(controller.instance_id()).is_some() {
+            return;
+        }
+        match self.slots.iter().position(|slot| slot.is_none()) {
+            Some(slot) => {
+                log::info!("controller {:?} connected: {}", DEVICES[slot], controller.name());
+                self.slots[slot] = Some(controller);
+            },
+            None => log::warn!("ignoring game controller {}, since every emulated controller is in use", controller.name()),
+        }
+    }
+
+    fn slot_of(&self, instance_id: u32) -> Option<usize> {
+        self.slots.iter().position(|slot| {
+            slot.as_ref()
+                .map(|controller| controller.instance_id() == instance_id)
+                .unwrap_or(false)
+        })
+    }
+
+    fn event_for(&self, instance_id: u32, input: ControllerInput) -> Option<ControllerEvent> {
+        self.slot_of(instance_id)
+            .map(|slot| ControllerEvent::new(DEVICES[slot], input))
+    }
+}
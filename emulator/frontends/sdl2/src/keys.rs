@@ -0,0 +1,111 @@
+use sdl2::keyboard::Scancode;
+use moa_host::Key;
+
+/// Map the physical position of a key, so the emulated keyboard's layout doesn't depend on the host's layout
+pub fn map_key(scancode: Scancode) -> Key {
+    match scancode {
+        Scancode::Num0 => Key::Num0,
+        Scancode::Num1 => Key::Num1,
+        Scancode::Num2 => Key::Num2,
+        Scancode::Num3 => Key::Num3,
+        Scancode::Num4 => Key::Num4,
+        Scancode::Num5 => Key::Num5,
+        Scancode::Num6 => Key::Num6,
+        Scancode::Num7 => Key::Num7,
+        Scancode::Num8 => Key::Num8,
+        Scancode::Num9 => Key::Num9,
+        Scancode::A => Key::A,
+        Scancode::B => Key::B,
+        Scancode::C => Key::C,
+        Scancode::D => Key::D,
+        Scancode::E => Key::E,
+        Scancode::F => Key::F,
+        Scancode::G => Key::G,
+        Scancode::H => Key::H,
+        Scancode::I => Key::I,
+        Scancode::J => Key::J,
+        Scancode::K => Key::K,
+        Scancode::L => Key::L,
+        Scancode::M => Key::M,
+        Scancode::N => Key::N,
+        Scancode::O => Key::O,
+        Scancode::P => Key::P,
+        Scancode::Q => Key::Q,
+        Scancode::R => Key::R,
+        Scancode::S => Key::S,
+        Scancode::T => Key::T,
+        Scancode::U => Key::U,
+        Scancode::V => Key::V,
+        Scancode::W => Key::W,
+        Scancode::X => Key::X,
+        Scancode::Y => Key::Y,
+        Scancode::Z => Key::Z,
+        Scancode::F1 => Key::F1,
+        Scancode::F2 => Key::F2,
+        Scancode::F3 => Key::F3,
+        Scancode::F4 => Key::F4,
+        Scancode::F5 => Key::F5,
+        Scancode::F6 => Key::F6,
+        Scancode::F7 => Key::F7,
+        Scancode::F8 => Key::F8,
+        Scancode::F9 => Key::F9,
+        Scancode::F10 => Key::F10,
+        Scancode::F11 => Key::F11,
+        Scancode::F12 => Key::F12,
+        Scancode::Down => Key::Down,
+        Scancode::Left => Key::Left,
+        Scancode::Right => Key::Right,
+        Scancode::Up => Key::Up,
+        Scancode::Apostrophe => Key::Apostrophe,
+        Scancode::Grave => Key::Backquote,
+        Scancode::Backslash => Key::Backslash,
+        Scancode::Comma => Key::Comma,
+        Scancode::Equals => Key::Equals,
+        Scancode::LeftBracket => Key::LeftBracket,
+        Scancode::Minus => Key::Minus,
+        Scancode::Period => Key::Period,
+        Scancode::RightBracket => Key::RightBracket,
+        Scancode::Semicolon => Key::Semicolon,
+        Scancode::Slash => Key::Slash,
+        Scancode::Backspace => Key::Backspace,
+        Scancode::Delete => Key::Delete,
+        Scancode::End => Key::End,
+        Scancode::Return => Key::Enter,
+        Scancode::Escape => Key::Escape,
+        Scancode::Home => Key::Home,
+        Scancode::Insert => Key::Insert,
+        Scancode::PageDown => Key::PageDown,
+        Scancode::PageUp => Key::PageUp,
+        Scancode::Pause => Key::Pause,
+        Scancode::Space => Key::Space,
+        Scancode::Tab => Key::Tab,
+        Scancode::NumLockClear => Key::NumLock,
+        Scancode::CapsLock => Key::CapsLock,
+        Scancode::ScrollLock => Key::ScrollLock,
+        Scancode::LShift => Key::LeftShift,
+        Scancode::RShift => Key::RightShift,
+        Scancode::LCtrl => Key::LeftCtrl,
+        Scancode::RCtrl => Key::RightCtrl,
+        Scancode::Kp0 => Key::NumPad0,
+        Scancode::Kp1 => Key::NumPad1,
+        Scancode::Kp2 => Key::NumPad2,
+        Scancode::Kp3 => Key::NumPad3,
+        Scancode::Kp4 => Key::NumPad4,
+        Scancode::Kp5 => Key::NumPad5,
+        Scancode::Kp6 => Key::NumPad6,
+        Scancode::Kp7 => Key::NumPad7,
+        Scancode::Kp8 => Key::NumPad8,
+        Scancode::Kp9 => Key::NumPad9,
+        Scancode::KpPeriod => Key::NumPadDot,
+        Scancode::KpDivide => Key::NumPadSlash,
+        Scancode::KpMultiply => Key::NumPadAsterisk,
+        Scancode::KpMinus => Key::NumPadMinus,
+        Scancode::KpPlus => Key::NumPadPlus,
+        Scancode::KpEnter => Key::NumPadEnter,
+        Scancode::LAlt => Key::LeftAlt,
+        Scancode::RAlt => Key::RightAlt,
+        Scancode::LGui => Key::LeftSuper,
+        Scancode::RGui => Key::RightSuper,
+        _ => Key::Unknown,
+    }
+}
@@ -0,0 +1,566 @@
+use std::mem;
+use std::process;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Scancode;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::{Texture, WindowCanvas};
+use sdl2::video::FullscreenType;
+use clap::{Command, Arg, ArgAction, ArgMatches};
+
+use moa_core::{System, Error, MachineInfo, logging};
+use moa_debugger::{Debugger, DebugControl};
+use moa_host::{
+    Host, HostError, Audio, KeyEvent, ControllerDevice, ControllerEvent, ControllerFeedback, EventSender, EventReceiver,
+    PixelEncoding, Frame, FrameReceiver, HeldInputs, PasteQueue,
+};
+
+use moa_common::{AudioMixer, AudioSource, Executor};
+use moa_common::audio::{AudioOutput, SAMPLE_RATE};
+use moa_common::capture;
+use moa_common::KeyMap;
+
+mod controllers;
+mod gamepad;
+mod keys;
+
+use crate::keys::map_key;
+use crate::controllers::map_controller_a;
+use crate::gamepad::Gamepads;
+
+
+/// The size of the window for machines that don't describe their video output
+const DEFAULT_WINDOW_SIZE: (u32, u32) = (320, 224);
+
+
+
+pub fn new(name: &'static str) -> Command {
+    Command::new(name)
+        .arg(
+            Arg::new("scale")
+                .short('s')
+                .long("scale")
+                .value_parser(clap::value_parser!(u32))
+                .help("Scale the window (default 2)"),
+        )
+        .arg(
+            Arg::new("fullscreen")
+                .short('f')
+                .long("fullscreen")
+                .action(ArgAction::SetTrue)
+                .help("Start in fullscreen (F11 toggles fullscreen while running)"),
+        )
+        .arg(
+            Arg::new("filter")
+                .long("filter")
+                .value_name("FILTER")
+                .value_parser(["nearest", "linear"])
+                .help("How to smooth the screen when it's scaled (nearest or linear)"),
+        )
+        .arg(
+            Arg::new("integer-scale")
+                .long("integer-scale")
+                .action(ArgAction::SetTrue)
+                .help("Only scale the screen by whole numbers, leaving a border around it if it doesn't fit exactly"),
+        )
+        .arg(
+            Arg::new("speed")
+                .short('x')
+                .long("speed")
+                .value_parser(parse_speed)
+                .help("Adjust the speed of the simulation (eg. 0.25 for slow motion)"),
+        )
+        .arg(
+            Arg::new("run-ahead")
+                .long("run-ahead")
+                .value_name("FRAMES")
+                .value_parser(clap::value_parser!(usize))
+                .help("Run the simulation this many frames ahead to hide the machine's input lag, if it can save its state"),
+        )
+        .arg(
+            Arg::new("paste-rate")
+                .long("paste-rate")
+                .value_name("CHARS")
+                .value_parser(parse_speed)
+                .help("The number of characters per second to type when pasting with F10 or the debugger's paste command"),
+        )
+        .arg(
+            Arg::new("log-level")
+                .short('l')
+                .long("log-level")
+                .help("Set the type of log messages to print"),
+        )
+        .arg(
+            Arg::new("debugger")
+                .short('d')
+                .long("debugger")
+                .action(ArgAction::SetTrue)
+                .help("Start the debugger before running machine"),
+        )
+        .arg(
+            Arg::new("keymap")
+                .long("keymap")
+                .value_name("FILE")
+                .help("Load the keys that play the emulated controllers and keyboard from a TOML file"),
+        )
+        .arg(
+            Arg::new("disable-audio")
+                .short('a')
+                .long("disable-audio")
+                .action(ArgAction::SetTrue)
+                .help("Disable audio output"),
+        )
+}
+
+fn parse_speed(arg: &str) -> Result<f32, String> {
+    match arg.parse::<f32>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err(format!("{} is not a positive number", arg)),
+    }
+}
+
+pub fn run<I>(matches: ArgMatches, init: I)
+where
+    I: FnOnce(&mut SdlFrontendBuilder) -> Result<System, Error>,
+{
+    let mut frontend = SdlFrontendBuilder::default();
+    let system = init(&mut frontend).unwrap();
+    let result = frontend.build().start(matches, system);
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+
+pub struct SdlFrontendBuilder {
+    video: Option<FrameReceiver>,
+    controllers: Option<EventSender<ControllerEvent>>,
+    feedback: Option<EventReceiver<ControllerFeedback>>,
+    keyboard: Option<EventSender<KeyEvent>>,
+    mixer: AudioMixer,
+}
+
+impl Default for SdlFrontendBuilder {
+    fn default() -> Self {
+        Self {
+            video: None,
+            controllers: None,
+            feedback: None,
+            keyboard: None,
+            mixer: AudioMixer::with_default_rate(),
+        }
+    }
+}
+
+impl SdlFrontendBuilder {
+    pub fn build(self) -> SdlFrontend {
+        SdlFrontend {
+            video: self.video,
+            controllers: self.controllers,
+            feedback: self.feedback,
+            keyboard: self.keyboard,
+            mixer: self.mixer,
+            keymap: None,
+            paste: PasteQueue::default(),
+            held: HeldInputs::default(),
+            inputs_changed: false,
+        }
+    }
+}
+
+impl Host for SdlFrontendBuilder {
+    type Error = Error;
+
+    fn add_video_source(&mut self, receiver: FrameReceiver) -> Result<(), HostError<Self::Error>> {
+        if self.video.is_some() {
+            return Err(HostError::Specific(Error::new("Only one video source can be registered with this frontend")));
+        }
+        self.video = Some(receiver);
+        Ok(())
+    }
+
+    fn add_audio_source(&mut self) -> Result<Box<dyn Audio>, HostError<Self::Error>> {
+        Ok(Box::new(AudioSource::new(self.mixer.clone())))
+    }
+
+    fn register_controllers(&mut self, sender: EventSender<ControllerEvent>) -> Result<(), HostError<Self::Error>> {
+        if self.controllers.is_some() {
+            return Err(HostError::Specific(Error::new(
+                "A controller updater has already been registered with the frontend",
+            )));
+        }
+        self.controllers = Some(sender);
+        Ok(())
+    }
+
+    fn register_controller_feedback(&mut self, receiver: EventReceiver<ControllerFeedback>) -> Result<(), HostError<Self::Error>> {
+        if self.feedback.is_some() {
+            return Err(HostError::Specific(Error::new(
+                "A controller feedback queue has already been registered with the frontend",
+            )));
+        }
+        self.feedback = Some(receiver);
+        Ok(())
+    }
+
+    fn register_keyboard(&mut self, sender: EventSender<KeyEvent>) -> Result<(), HostError<Self::Error>> {
+        if self.keyboard.is_some() {
+            return Err(HostError::Specific(Error::new("A keyboard updater has already been registered with the frontend")));
+        }
+        self.keyboard = Some(sender);
+        Ok(())
+    }
+}
+
+
+/// Plays the mixed audio from the SDL audio thread
+struct SdlAudio {
+    output: AudioOutput,
+}
+
+impl AudioCallback for SdlAudio {
+    type Channel = f32;
+
+    fn callback(&mut self, data: &mut [f32]) {
+        // Any part of the buffer that there aren't samples for yet is played as silence
+        data.fill(0.0);
+        self.output.fill_buffer(data, |sample| sample);
+    }
+}
+
+
+pub struct SdlFrontend {
+    video: Option<FrameReceiver>,
+    controllers: Option<EventSender<ControllerEvent>>,
+    feedback: Option<EventReceiver<ControllerFeedback>>,
+    keyboard: Option<EventSender<KeyEvent>>,
+    mixer: AudioMixer,
+    keymap: Option<KeyMap>,
+    paste: PasteQueue,
+    held: HeldInputs,
+    /// Whether any input has been sent to the machine since the last update, which has to be rerun when running ahead
+    inputs_changed: bool,
+}
+
+impl SdlFrontend {
+    pub fn start(&mut self, matches: ArgMatches, mut system: System) -> Result<(), String> {
+        let log_level = match matches.get_one("log-level").map(|s: &String| s.as_str()) {
+            Some("trace") => log::Level::Trace,
+            Some("debug") => log::Level::Debug,
+            Some("info") => log::Level::Info,
+            Some("warn") => log::Level::Warn,
+            Some("error") => log::Level::Error,
+            _ => log::Level::Warn,
+        };
+        let logger = simple_logger::SimpleLogger::new()
+            .with_level(log::LevelFilter::Trace)
+            .without_timestamps();
+        logging::init(Box::new(logger), log_level.to_level_filter()).map_err(|err| err.to_string())?;
+
+        if let Some(filename) = matches.get_one::<String>("keymap") {
+            self.keymap = Some(KeyMap::load(filename).map_err(|err| err.to_string())?);
+        }
+        if let Some(rate) = matches.get_one::<f32>("paste-rate") {
+            self.paste = PasteQueue::new(*rate);
+        }
+
+        let sdl = sdl2::init()?;
+        let video = sdl.video()?;
+        let mut gamepads = Gamepads::new(sdl.game_controller()?);
+        let mut events = sdl.event_pump()?;
+
+        let _audio = if self.mixer.borrow_mut().num_sources() != 0 && !matches.get_flag("disable-audio") {
+            self.mixer.add_to_system(&mut system).map_err(|err| err.to_string())?;
+            Some(self.open_audio(&sdl)?)
+        } else {
+            None
+        };
+
+        let machine_info = system.machine_info.clone();
+        let mut size = machine_info.video_size.unwrap_or(DEFAULT_WINDOW_SIZE);
+        if let Some(queue) = self.video.as_ref() {
+            size = queue.max_size();
+            queue.request_encoding(PixelEncoding::ARGB);
+        }
+        let window_size = machine_info.window_size().unwrap_or(size);
+        let scale = matches.get_one::<u32>("scale").cloned().unwrap_or(2).max(1);
+
+        // The filter has to be chosen before the texture is created
+        let filter = matches.get_one::<String>("filter").map(|s| s.as_str()).unwrap_or("nearest");
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", filter);
+
+        let title = match machine_info.name.as_str() {
+            "" => "Test - ESC to exit".to_string(),
+            name => format!("{} - ESC to exit", name),
+        };
+        let window = video
+            .window(&title, window_size.0 * scale, window_size.1 * scale)
+            .position_centered()
+            .resizable()
+            .build()
+            .map_err(|err| err.to_string())?;
+        let mut canvas = window.into_canvas().present_vsync().build().map_err(|err| err.to_string())?;
+        // The frame is stretched to fill the window's aspect ratio, like the machine's screen would be
+        canvas
+            .set_logical_size(window_size.0, window_size.1)
+            .map_err(|err| err.to_string())?;
+        canvas.set_integer_scale(matches.get_flag("integer-scale"))?;
+        if matches.get_flag("fullscreen") {
+            toggle_fullscreen(&mut canvas)?;
+        }
+
+        let texture_creator = canvas.texture_creator();
+        let mut texture = texture_creator
+            .create_texture_streaming(PixelFormatEnum::ARGB8888, size.0, size.1)
+            .map_err(|err| err.to_string())?;
+
+        let mut executor = Executor::for_machine(&machine_info);
+        if let Some(speed) = matches.get_one::<f32>("speed") {
+            executor.set_speed(*speed);
+        }
+        if let Some(frames) = matches.get_one::<usize>("run-ahead") {
+            executor.set_run_ahead(*frames);
+        }
+        let mut debugger = Debugger::default();
+        let mut run_debugger = matches.get_flag("debugger");
+        let mut last_frame = Frame::new(size.0, size.1, PixelEncoding::ARGB);
+        'running: loop {
+            if run_debugger {
+                debugger.print_step(&mut system).map_err(|err| err.to_string())?;
+                if debugger.check_auto_command(&mut system).map_err(|err| err.to_string())? != DebugControl::Continue {
+                    let mut buffer = String::new();
+                    io::stdout().write_all(b"> ").unwrap();
+                    io::stdin().read_line(&mut buffer).unwrap();
+                    match debugger.run_command(&mut system, &buffer) {
+                        Ok(DebugControl::Exit) => run_debugger = false,
+                        Ok(_) => {},
+                        Err(err) => println!("Error: {:?}", err),
+                    }
+                    if let Some(speed) = debugger.take_speed() {
+                        executor.set_speed(speed);
+                    }
+                    if let Some(text) = debugger.take_paste() {
+                        self.paste_text(&text);
+                    }
+                }
+                // The time spent in the debugger isn't simulated when it continues
+                executor.reset();
+            } else {
+                let keys = events.keyboard_state();
+                executor.set_fast_forward(keys.is_scancode_pressed(Scancode::PageUp));
+                executor.set_slow_motion(keys.is_scancode_pressed(Scancode::PageDown));
+                if mem::take(&mut self.inputs_changed) {
+                    executor.input_changed();
+                }
+                let simulated = match executor.update(&mut system) {
+                    Ok(simulated) => simulated,
+                    Err(Error::Breakpoint(info)) => {
+                        if debugger.breakpoint_occurred(&system, &info) {
+                            run_debugger = true;
+                        }
+                        Duration::ZERO
+                    },
+                    Err(err) => return Err(format!("{:?}", err)),
+                };
+
+                // Pasted text is typed at a rate in simulated time, so it isn't typed faster than the machine runs
+                for event in self.paste.update(simulated) {
+                    self.send_key_event(event);
+                }
+            }
+
+            for event in events.poll_iter() {
+                match event {
+                    Event::Quit {
+                        ..
+                    }
+                    | Event::KeyDown {
+                        scancode: Some(Scancode::Escape),
+                        ..
+                    } => break 'running,
+                    Event::KeyDown {
+                        scancode: Some(scancode),
+                        repeat: false,
+                        ..
+                    } => {
+                        self.check_key(scancode, true);
+                        match scancode {
+                            Scancode::F9 => run_debugger = true,
+                            Scancode::F10 => match video.clipboard().clipboard_text() {
+                                Ok(text) => self.paste_text(&text),
+                                Err(err) => log::warn!("unable to read the clipboard: {}", err),
+                            },
+                            Scancode::F11 => toggle_fullscreen(&mut canvas)?,
+                            Scancode::F12 => save_screenshot(&machine_info, &last_frame),
+                            Scancode::KpPlus => change_speed(&mut executor, 2.0),
+                            Scancode::KpMinus => change_speed(&mut executor, 0.5),
+                            Scancode::Pause => {
+                                let paused = executor.toggle_pause();
+                                println!("simulation is {}", if paused { "paused" } else { "running" });
+                            },
+                            Scancode::End => executor.step_frame(),
+                            _ => {},
+                        }
+                    },
+                    Event::KeyUp {
+                        scancode: Some(scancode),
+                        ..
+                    } => self.check_key(scancode, false),
+                    // The release events for any keys held down when the window loses focus would go to another window
+                    Event::Window {
+                        win_event: WindowEvent::FocusLost,
+                        ..
+                    } => self.release_held_inputs(),
+                    event => {
+                        for event in gamepads.handle_event(&event) {
+                            self.send_controller_event(event);
+                        }
+                    },
+                }
+            }
+
+            if let Some(receiver) = self.feedback.as_ref() {
+                gamepads.apply_feedback(receiver);
+            }
+
+            if let Some(queue) = self.video.as_ref() {
+                if let Some((_clock, frame)) = queue.latest() {
+                    update_texture(&mut texture, &frame)?;
+                    last_frame = frame;
+                }
+            }
+
+            // Presenting the canvas waits for the host's vsync, which paces the loop
+            canvas.clear();
+            let source = Rect::new(0, 0, last_frame.width.max(1), last_frame.height.max(1));
+            canvas.copy(&texture, source, None)?;
+            canvas.present();
+        }
+
+        if let Err(err) = system.autosave.save() {
+            eprintln!("Error saving: {}", err);
+        }
+        Ok(())
+    }
+
+    fn open_audio(&self, sdl: &sdl2::Sdl) -> Result<AudioDevice<SdlAudio>, String> {
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE as i32),
+            channels: Some(2),
+            samples: None,
+        };
+        let output = self.mixer.borrow_mut().get_sink();
+        let device = sdl.audio()?.open_playback(None, &desired, |_spec| SdlAudio {
+            output,
+        })?;
+        device.resume();
+        Ok(device)
+    }
+
+    fn check_key(&mut self, scancode: Scancode, state: bool) {
+        let key = map_key(scancode);
+        let emulated = self.keymap.as_ref().map(|keymap| keymap.map_key(key)).unwrap_or(key);
+        self.send_key_event(KeyEvent::new(emulated, state));
+
+        let events = match self.keymap.as_ref() {
+            Some(keymap) => keymap.controller_events(key, state),
+            None => map_controller_a(scancode, state)
+                .map(|input| vec![ControllerEvent::new(ControllerDevice::A, input)])
+                .unwrap_or_default(),
+        };
+        for event in events {
+            self.send_controller_event(event);
+        }
+    }
+
+    fn send_key_event(&mut self, event: KeyEvent) {
+        if let Some(sender) = self.keyboard.as_mut() {
+            self.held.update_key(event);
+            sender.send(event);
+            self.inputs_changed = true;
+        }
+    }
+
+    fn paste_text(&mut self, text: &str) {
+        if self.keyboard.is_none() {
+            log::warn!("unable to paste, since the machine doesn't have a keyboard");
+            return;
+        }
+        let skipped = self.paste.push_text(text);
+        if skipped > 0 {
+            log::warn!("skipped {} characters that can't be typed", skipped);
+        }
+    }
+
+    fn send_controller_event(&mut self, event: ControllerEvent) {
+        if let Some(sender) = self.controllers.as_mut() {
+            self.held.update_button(event);
+            sender.send(event);
+            self.inputs_changed = true;
+        }
+    }
+
+    fn release_held_inputs(&mut self) {
+        self.inputs_changed = true;
+        for event in self.held.release_keys() {
+            if let Some(sender) = self.keyboard.as_mut() {
+                sender.send(event);
+            }
+        }
+        for event in self.held.release_buttons() {
+            if let Some(sender) = self.controllers.as_mut() {
+                sender.send(event);
+            }
+        }
+    }
+}
+
+/// Copy the parts of the frame that changed into the top left of the texture, which is as big as the largest frame
+fn update_texture(texture: &mut Texture, frame: &Frame) -> Result<(), String> {
+    let dirty = frame.dirty_rect();
+    if dirty.is_empty() {
+        return Ok(());
+    }
+    let width = frame.width as usize;
+    texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+        for y in dirty.y as usize..(dirty.y + dirty.height) as usize {
+            let line = &frame.bitmap[y * width..(y + 1) * width];
+            let row = &mut buffer[y * pitch..];
+            for x in dirty.x as usize..(dirty.x + dirty.width) as usize {
+                row[x * 4..x * 4 + 4].copy_from_slice(&line[x].to_ne_bytes());
+            }
+        }
+    })
+}
+
+fn toggle_fullscreen(canvas: &mut WindowCanvas) -> Result<(), String> {
+    let window = canvas.window_mut();
+    let fullscreen = match window.fullscreen_state() {
+        FullscreenType::Off => FullscreenType::Desktop,
+        _ => FullscreenType::Off,
+    };
+    window.set_fullscreen(fullscreen)
+}
+
+/// Save the frame that's being displayed as a PNG, to a new numbered file named after the machine
+fn save_screenshot(machine_info: &MachineInfo, frame: &Frame) {
+    let prefix = match machine_info.name.as_str() {
+        "" => "screenshot".to_string(),
+        name => format!("{}-screenshot", name),
+    };
+    let filename = capture::next_screenshot_filename(&prefix);
+    match capture::save_screenshot(frame, &filename) {
+        Ok(()) => println!("saved a screenshot to {}", filename),
+        Err(err) => eprintln!("{}", err),
+    }
+}
+
+fn change_speed(executor: &mut Executor, factor: f32) {
+    let speed = executor.change_speed(factor);
+    println!("simulation speed is now {}x", speed);
+}
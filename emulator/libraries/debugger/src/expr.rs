@@ -0,0 +1,438 @@
+//! Expressions for the conditions of breakpoints and watchpoints
+//!
+//! An expression is made of numbers, register names, memory reads, and the usual C operators, for
+//! example `d0 == 12 && w[a0 + 4] != 0`.  Numbers are in hex like the addresses given to other commands, and
+//! can also have a `0x` prefix, which is needed for a number that starts with a letter, since `ff` is taken
+//! to be the name of a register.  Registers are named as the cpu names them, in lowercase.  Memory is read
+//! big-endian with `b[addr]` for a byte, `w[addr]` for a word, and `l[addr]` for a long word.  The name `value`
+//! is the data that was written to the address of a watchpoint.  Comparisons result in 1 or 0, and a
+//! condition is true if it is non-zero.
+
+use std::fmt;
+
+use moa_core::{Error, Address};
+
+
+/// The register values, memory, and watchpoint data that an expression is evaluated with
+pub trait ExprContext {
+    fn register(&mut self, name: &str) -> Option<u64>;
+    fn read_memory(&mut self, addr: Address, size: usize) -> Result<u64, Error>;
+    fn value(&self) -> Option<u64>;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Subtract,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Complement,
+    Negate,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Number(u64),
+    Register(String),
+    Value,
+    Memory(usize, Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Expr, Error> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+        };
+        let expr = parser.parse_binary(0)?;
+        if parser.pos < tokens.len() {
+            return Err(Error::new(format!("unexpected {} in expression", tokens[parser.pos])));
+        }
+        Ok(expr)
+    }
+
+    pub fn evaluate(&self, context: &mut dyn ExprContext) -> Result<u64, Error> {
+        match self {
+            Expr::Number(number) => Ok(*number),
+            Expr::Register(name) => context
+                .register(name)
+                .ok_or_else(|| Error::new(format!("unknown register {}", name))),
+            Expr::Value => context
+                .value()
+                .ok_or_else(|| Error::new("value can only be used in the condition of a watchpoint")),
+            Expr::Memory(size, addr) => {
+                let addr = addr.evaluate(context)?;
+                context.read_memory(addr as Address, *size)
+            },
+            Expr::Unary(op, expr) => {
+                let value = expr.evaluate(context)?;
+                Ok(match op {
+                    UnaryOp::Not => (value == 0) as u64,
+                    UnaryOp::Complement => !value,
+                    UnaryOp::Negate => value.wrapping_neg(),
+                })
+            },
+            Expr::Binary(op, left, right) => {
+                let left = left.evaluate(context)?;
+                // The right side isn't evaluated if it can't change the result, so a memory read can be guarded
+                match op {
+                    BinaryOp::Or if left != 0 => return Ok(1),
+                    BinaryOp::And if left == 0 => return Ok(0),
+                    _ => {},
+                }
+                let right = right.evaluate(context)?;
+                Ok(match op {
+                    BinaryOp::Or | BinaryOp::And => (right != 0) as u64,
+                    BinaryOp::Equal => (left == right) as u64,
+                    BinaryOp::NotEqual => (left != right) as u64,
+                    BinaryOp::Less => (left < right) as u64,
+                    BinaryOp::LessEqual => (left <= right) as u64,
+                    BinaryOp::Greater => (left > right) as u64,
+                    BinaryOp::GreaterEqual => (left >= right) as u64,
+                    BinaryOp::BitOr => left | right,
+                    BinaryOp::BitXor => left ^ right,
+                    BinaryOp::BitAnd => left & right,
+                    BinaryOp::Add => left.wrapping_add(right),
+                    BinaryOp::Subtract => left.wrapping_sub(right),
+                })
+            },
+        }
+    }
+
+    /// Returns an error for the first register named in the expression that the context doesn't have, which is
+    /// checked when the expression is given, rather than when it's first evaluated
+    pub fn check_registers(&self, context: &mut dyn ExprContext) -> Result<(), Error> {
+        match self {
+            Expr::Number(_) | Expr::Value => Ok(()),
+            Expr::Register(name) => match context.register(name) {
+                Some(_) => Ok(()),
+                None => Err(Error::new(format!(
+                    "unknown register {} in expression (numbers that start with a letter need a 0x prefix)",
+                    name
+                ))),
+            },
+            Expr::Memory(_, expr) | Expr::Unary(_, expr) => expr.check_registers(context),
+            Expr::Binary(_, left, right) => {
+                left.check_registers(context)?;
+                right.check_registers(context)
+            },
+        }
+    }
+
+    /// Returns true if the expression evaluates to a non-zero value
+    pub fn is_true(&self, context: &mut dyn ExprContext) -> Result<bool, Error> {
+        Ok(self.evaluate(context)? != 0)
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Number(u64),
+    Name(String),
+    Operator(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(number) => write!(f, "{:x}", number),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Operator(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+// The longer operators must be before the shorter ones that they start with
+const OPERATORS: &[&str] = &["||", "&&", "==", "!=", "<=", ">=", "<", ">", "|", "^", "&", "+", "-", "!", "~", "(", ")", "[", "]"];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = vec![];
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Operator(op));
+            rest = &rest[op.len()..];
+        } else {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            if end == 0 {
+                return Err(Error::new(format!("unexpected character {:?} in expression", rest.chars().next().unwrap())));
+            }
+
+            let word = &rest[..end];
+            if word.starts_with(|c: char| c.is_ascii_digit()) {
+                let digits = word.strip_prefix("0x").unwrap_or(word);
+                let number =
+                    u64::from_str_radix(digits, 16).map_err(|_| Error::new(format!("invalid number {} in expression", word)))?;
+                tokens.push(Token::Number(number));
+            } else {
+                tokens.push(Token::Name(word.to_lowercase()));
+            }
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// The binary operators in each level of precedence, from lowest to highest
+const PRECEDENCE: &[&[(&str, BinaryOp)]] = &[
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[
+        ("==", BinaryOp::Equal),
+        ("!=", BinaryOp::NotEqual),
+        ("<", BinaryOp::Less),
+        ("<=", BinaryOp::LessEqual),
+        (">", BinaryOp::Greater),
+        (">=", BinaryOp::GreaterEqual),
+    ],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Subtract)],
+];
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn check_operator(&mut self, op: &str) -> bool {
+        match self.tokens.get(self.pos) {
+            Some(Token::Operator(next)) if *next == op => {
+                self.pos += 1;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn expect_operator(&mut self, op: &str) -> Result<(), Error> {
+        if self.check_operator(op) {
+            Ok(())
+        } else {
+            Err(Error::new(format!("expected {} in expression", op)))
+        }
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, Error> {
+        if level >= PRECEDENCE.len() {
+            return self.parse_unary();
+        }
+
+        let mut left = self.parse_binary(level + 1)?;
+        'operators: loop {
+            for (op, binary_op) in PRECEDENCE[level] {
+                if self.check_operator(op) {
+                    let right = self.parse_binary(level + 1)?;
+                    left = Expr::Binary(*binary_op, Box::new(left), Box::new(right));
+                    continue 'operators;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, Error> {
+        for (op, unary_op) in [("!", UnaryOp::Not), ("~", UnaryOp::Complement), ("-", UnaryOp::Negate)] {
+            if self.check_operator(op) {
+                return Ok(Expr::Unary(unary_op, Box::new(self.parse_unary()?)));
+            }
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, Error> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Expr::Number(*number)),
+            Some(Token::Operator("(")) => {
+                let expr = self.parse_binary(0)?;
+                self.expect_operator(")")?;
+                Ok(expr)
+            },
+            Some(Token::Name(name)) => {
+                let size = match name.as_str() {
+                    "b" => 1,
+                    "w" => 2,
+                    "l" => 4,
+                    "value" => return Ok(Expr::Value),
+                    _ => 0,
+                };
+                if size != 0 && self.check_operator("[") {
+                    let addr = self.parse_binary(0)?;
+                    self.expect_operator("]")?;
+                    Ok(Expr::Memory(size, Box::new(addr)))
+                } else {
+                    Ok(Expr::Register(name.clone()))
+                }
+            },
+            Some(token) => Err(Error::new(format!("unexpected {} in expression", token))),
+            None => Err(Error::new("unexpected end of expression")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestContext {
+        memory: Vec<u8>,
+        reads: Vec<Address>,
+        value: Option<u64>,
+    }
+
+    impl TestContext {
+        fn new() -> Self {
+            Self {
+                memory: (0..0x20).collect(),
+                reads: vec![],
+                value: None,
+            }
+        }
+    }
+
+    impl ExprContext for TestContext {
+        fn register(&mut self, name: &str) -> Option<u64> {
+            match name {
+                "d0" => Some(0x12),
+                "a0" => Some(0x10),
+                _ => None,
+            }
+        }
+
+        fn read_memory(&mut self, addr: Address, size: usize) -> Result<u64, Error> {
+            self.reads.push(addr);
+            let data = self
+                .memory
+                .get(addr as usize..addr as usize + size)
+                .ok_or_else(|| Error::new(format!("no memory at {:x}", addr)))?;
+            Ok(data.iter().fold(0, |acc, byte| (acc << 8) | *byte as u64))
+        }
+
+        fn value(&self) -> Option<u64> {
+            self.value
+        }
+    }
+
+    fn evaluate(text: &str) -> u64 {
+        Expr::parse(text).unwrap().evaluate(&mut TestContext::new()).unwrap()
+    }
+
+    #[test]
+    fn operators_have_c_precedence() {
+        assert_eq!(evaluate("1 + 2 & 3"), 3);
+        assert_eq!(evaluate("2 | 1 ^ 3 & 1"), 2);
+        assert_eq!(evaluate("3 == 3 | 4"), 0);
+        assert_eq!(evaluate("1 || 0 && 0"), 1);
+        assert_eq!(evaluate("(1 || 0) && 0"), 0);
+        assert_eq!(evaluate("5 - 2 - 1"), 2);
+        assert_eq!(evaluate("!0 + 1"), 2);
+        assert_eq!(evaluate("-1 + 2"), 1);
+        assert_eq!(evaluate("~0 == 0xffffffffffffffff"), 1);
+    }
+
+    #[test]
+    fn comparisons_result_in_one_or_zero() {
+        assert_eq!(evaluate("2 < 3"), 1);
+        assert_eq!(evaluate("3 <= 3"), 1);
+        assert_eq!(evaluate("2 > 3"), 0);
+        assert_eq!(evaluate("3 >= 4"), 0);
+        assert_eq!(evaluate("3 != 4"), 1);
+    }
+
+    #[test]
+    fn numbers_are_hex() {
+        assert_eq!(evaluate("10"), 0x10);
+        assert_eq!(evaluate("0x1F"), 0x1F);
+        assert_eq!(evaluate("0xff"), 0xFF);
+        assert_eq!(Expr::parse("ff").unwrap(), Expr::Register("ff".to_string()));
+    }
+
+    #[test]
+    fn registers_are_read_from_the_context() {
+        assert_eq!(evaluate("d0 == 12"), 1);
+        assert_eq!(evaluate("D0 + A0"), 0x22);
+    }
+
+    #[test]
+    fn memory_is_read_big_endian() {
+        assert_eq!(evaluate("b[4]"), 0x04);
+        assert_eq!(evaluate("w[4]"), 0x0405);
+        assert_eq!(evaluate("l[a0 + 2]"), 0x12131415);
+        assert_eq!(evaluate("b[b[3]]"), 0x03);
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        let mut context = TestContext::new();
+        let expr = Expr::parse("0 && b[100]").unwrap();
+        assert_eq!(expr.evaluate(&mut context).unwrap(), 0);
+        let expr = Expr::parse("1 || l[100]").unwrap();
+        assert_eq!(expr.evaluate(&mut context).unwrap(), 1);
+        assert!(context.reads.is_empty());
+
+        let expr = Expr::parse("1 && b[100]").unwrap();
+        assert!(expr.evaluate(&mut context).is_err());
+        assert_eq!(context.reads, vec![0x100]);
+    }
+
+    #[test]
+    fn value_is_the_data_written_to_a_watchpoint() {
+        let expr = Expr::parse("value == 0xff").unwrap();
+        let mut context = TestContext::new();
+        assert!(expr.evaluate(&mut context).is_err());
+
+        context.value = Some(0xFF);
+        assert!(expr.is_true(&mut context).unwrap());
+        context.value = Some(0xFE);
+        assert!(!expr.is_true(&mut context).unwrap());
+    }
+
+    #[test]
+    fn unknown_registers_are_rejected() {
+        let mut context = TestContext::new();
+        let expr = Expr::parse("value == ff").unwrap();
+        assert!(expr.check_registers(&mut context).is_err());
+        assert!(expr.evaluate(&mut context).is_err());
+
+        let expr = Expr::parse("!(d0 == 1) && w[a0] != value").unwrap();
+        assert!(expr.check_registers(&mut context).is_ok());
+        let expr = Expr::parse("d0 == 1 || b[d7]").unwrap();
+        assert!(expr.check_registers(&mut context).is_err());
+    }
+
+    #[test]
+    fn invalid_expressions_are_errors() {
+        for text in ["", "1 +", "(1", "1)", "1 2", "b[1", "1 $ 2", "0xzz", "== 1"] {
+            assert!(Expr::parse(text).is_err(), "{:?} was parsed", text);
+        }
+    }
+}
@@ -1,4 +1,18 @@
-use moa_core::{Error, System, Address, Addressable};
+mod expr;
+
+use std::collections::HashMap;
+
+use moa_core::{
+    Error, BreakpointInfo, System, Address, Addressable, Debuggable, Device, DeviceId, WriteLog, Coverage, StrictMode, TraceFormat,
+    MemoryRegion, RegionColor, Capabilities, strict, logging,
+};
+use log::LevelFilter;
+
+use crate::expr::{Expr, ExprContext};
+
+
+/// The number of steps of all devices that are recorded by default for stepping backwards
+const DEFAULT_REWIND_DEPTH: usize = 100_000;
 
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -16,17 +30,113 @@ pub enum DebugControl {
 pub struct Debugger {
     repeat_command: Option<(u32, String)>,
     trace_only: bool,
+    target: Option<DeviceId>,
+    speed: Option<f32>,
+    screenshot: Option<Option<String>>,
+    paste: Option<String>,
+    write_logs: HashMap<String, WriteLog>,
+    breakpoint_conditions: HashMap<(DeviceId, Address), Expr>,
+    watch_conditions: HashMap<Address, Expr>,
+    coverage: Option<Coverage>,
 }
 
 
 impl Debugger {
-    pub fn breakpoint_occurred(&mut self) {
+    /// Returns true if the debugger should be entered for the given breakpoint, which is false if the
+    /// breakpoint has a condition that isn't met
+    pub fn breakpoint_occurred(&mut self, system: &System, info: &BreakpointInfo) -> bool {
+        if !self.check_condition(system, info) {
+            return false;
+        }
+
         self.trace_only = false;
+
+        let name = info.device.and_then(|id| system.get_device_by_id(id)).map(|(name, _)| name);
+        let kind = if info.watchpoint { "Watchpoint" } else { "Breakpoint" };
+        match (name, info.pc, info.address) {
+            (_, Some(pc), Some(addr)) if info.watchpoint => {
+                println!("{} for {:08x} in {} from PC {:08x}", kind, addr, name.unwrap_or("system"), pc)
+            },
+            (_, _, Some(addr)) if info.watchpoint => println!("{} for {:08x} in {}", kind, addr, name.unwrap_or("system")),
+            (Some(name), Some(_), _) if info.exception => println!("{} in {}", info.msg, name),
+            (Some(name), Some(pc), _) => match system.symbols.describe(pc as Address) {
+                Some(symbol) => println!("{} reached in {} at {:08x} <{}>", kind, name, pc, symbol),
+                None => println!("{} reached in {} at {:08x}", kind, name, pc),
+            },
+            _ => println!("{}: {}", kind, info.msg),
+        }
+
+        // Only switch to devices that can be debugged, so that watchpoints triggered by peripherals don't hide the cpu
+        self.target = info.device.filter(|id| {
+            system
+                .get_device_by_id(*id)
+                .map(|(_, device)| device.has(Capabilities::DEBUGGABLE))
+                .unwrap_or(false)
+        });
+        true
+    }
+
+    fn check_condition(&self, system: &System, info: &BreakpointInfo) -> bool {
+        let condition = match (info.watchpoint, info.device, info.pc, info.address) {
+            (true, _, _, Some(addr)) => self.watch_conditions.get(&addr),
+            (false, Some(id), Some(pc), _) if !info.exception => self.breakpoint_conditions.get(&(id, pc)),
+            _ => None,
+        };
+        let condition = match condition {
+            Some(condition) => condition,
+            None => return true,
+        };
+
+        // Registers are read from the device that caused the breakpoint, or the current target for peripherals
+        let device = info
+            .device
+            .and_then(|id| system.get_device_by_id(id))
+            .map(|(_, device)| device)
+            .filter(|device| device.has(Capabilities::DEBUGGABLE))
+            .or_else(|| self.get_target(system));
+
+        let mut context = ConditionContext {
+            system,
+            device,
+            value: info.value,
+        };
+        match condition.is_true(&mut context) {
+            Ok(result) => result,
+            Err(err) => {
+                // Stop so that a mistake in the condition can be fixed
+                println!("Error evaluating condition: {}", err);
+                true
+            },
+        }
+    }
+
+    /// Returns the device that commands should apply to, which is either the device that last reached a
+    /// breakpoint, or the next debuggable device to be run
+    pub fn get_target(&self, system: &System) -> Option<Device> {
+        self.target
+            .and_then(|id| system.get_device_by_id(id))
+            .map(|(_, device)| device)
+            .or_else(|| system.get_next_debuggable_device())
+    }
+
+    /// Returns the simulation speed requested with the `speed` command, if it was used since the last call
+    pub fn take_speed(&mut self) -> Option<f32> {
+        self.speed.take()
+    }
+
+    /// Returns a request from the `screenshot` command to save the screen, with the filename if one was given
+    pub fn take_screenshot(&mut self) -> Option<Option<String>> {
+        self.screenshot.take()
+    }
+
+    /// Returns the text from the `paste` command to type into the machine, if it was used since the last call
+    pub fn take_paste(&mut self) -> Option<String> {
+        self.paste.take()
     }
 
     pub fn print_step(&mut self, system: &mut System) -> Result<(), Error> {
         println!("@ {} ns", system.clock.as_duration().as_nanos());
-        if let Some(device) = system.get_next_debuggable_device() {
+        if let Some(device) = self.get_target(system) {
             device.borrow_mut().as_debuggable().unwrap().print_current_step(system)?;
         }
         Ok(())
@@ -59,22 +169,41 @@ impl Debugger {
 
         match args[0] {
             "b" | "break" | "breakpoint" => {
-                if args.len() != 2 {
-                    println!("Usage: breakpoint <addr>");
+                if args.len() < 2 || (args.len() > 2 && (args[2] != "if" || args.len() < 4)) {
+                    println!("Usage: breakpoint <addr|symbol> [if <condition>]");
                 } else {
-                    let (name, addr) = parse_address(args[1])?;
-                    match name {
+                    let (name, addr) = parse_address(system, args[1])?;
+                    let condition = if args.len() > 2 {
+                        let device = match name {
+                            Some(name) => Some(system.get_device(name)?),
+                            None => self.get_target(system),
+                        };
+                        Some(parse_condition(system, device, &args[3..])?)
+                    } else {
+                        None
+                    };
+                    let device = match name {
                         Some(name) => {
                             let target = system.get_device(name)?;
                             target.borrow_mut().as_debuggable().unwrap().add_breakpoint(addr);
                             println!("Breakpoint set for devices {:?} at {:08x}", name, addr);
+                            Some(target)
                         },
                         None => {
-                            if let Some(device) = system.get_next_debuggable_device() {
+                            let target = self.get_target(system);
+                            if let Some(device) = target.as_ref() {
                                 device.borrow_mut().as_debuggable().unwrap().add_breakpoint(addr);
                                 println!("Breakpoint set for {:08x}", addr);
                             }
+                            target
                         },
+                    };
+
+                    if let Some(device) = device {
+                        match condition {
+                            Some(condition) => self.breakpoint_conditions.insert((device.id(), addr), condition),
+                            None => self.breakpoint_conditions.remove(&(device.id(), addr)),
+                        };
                     }
                 }
             },
@@ -82,27 +211,48 @@ impl Debugger {
                 if args.len() != 2 {
                     println!("Usage: remove <addr>");
                 } else {
-                    let (name, addr) = parse_address(args[1])?;
-                    match name {
+                    let (name, addr) = parse_address(system, args[1])?;
+                    let device = match name {
                         Some(name) => {
                             let target = system.get_device(name)?;
                             target.borrow_mut().as_debuggable().unwrap().remove_breakpoint(addr);
                             println!("Breakpoint removed for devices {:?} at {:08x}", name, addr);
+                            Some(target)
                         },
                         None => {
-                            if let Some(device) = system.get_next_debuggable_device() {
+                            let target = self.get_target(system);
+                            if let Some(device) = target.as_ref() {
                                 device.borrow_mut().as_debuggable().unwrap().remove_breakpoint(addr);
                                 println!("Breakpoint removed for {:08x}", addr);
                             }
+                            target
                         },
+                    };
+
+                    if let Some(device) = device {
+                        self.breakpoint_conditions.remove(&(device.id(), addr));
                     }
                 }
             },
             "w" | "watch" => {
-                if args.len() != 2 {
-                    println!("Usage: watch <addr>");
+                if args.len() < 2 {
+                    println!("Usage: watch <addr> [write] [if] [<condition>]");
                 } else {
-                    let addr = Address::from_str_radix(args[1], 16).map_err(|_| Error::new("Unable to parse address"))?;
+                    let addr = resolve_address(system, args[1])?;
+                    // Only writes can be watched, but the keyword is accepted so that the condition reads naturally
+                    let mut rest = &args[2..];
+                    for keyword in ["write", "if"] {
+                        if rest.first() == Some(&keyword) {
+                            rest = &rest[1..];
+                        }
+                    }
+
+                    if rest.is_empty() {
+                        self.watch_conditions.remove(&addr);
+                    } else {
+                        let condition = parse_condition(system, self.get_target(system), rest)?;
+                        self.watch_conditions.insert(addr, condition);
+                    }
                     system.get_bus().add_watcher(addr);
                 }
             },
@@ -110,20 +260,21 @@ impl Debugger {
                 if args.len() != 2 {
                     println!("Usage: remove_watch <addr>");
                 } else {
-                    let addr = Address::from_str_radix(args[1], 16).map_err(|_| Error::new("Unable to parse address"))?;
+                    let addr = resolve_address(system, args[1])?;
                     system.get_bus().remove_watcher(addr);
+                    self.watch_conditions.remove(&addr);
                 }
             },
 
             "d" | "dump" => {
                 if args.len() > 1 {
-                    let addr = u32::from_str_radix(args[1], 16).map_err(|_| Error::new("Unable to parse address"))?;
+                    let addr = resolve_address(system, args[1])?;
                     let len = if args.len() > 2 {
                         u32::from_str_radix(args[2], 16).map_err(|_| Error::new("Unable to parse length"))?
                     } else {
                         0x20
                     };
-                    system.get_bus().dump_memory(system.clock, addr as Address, len as Address);
+                    system.get_bus().dump_memory(system.clock, addr, len as Address);
                 } else {
                     //self.port.dump_memory(self.state.ssp as Address, 0x40 as Address);
                 }
@@ -141,9 +292,26 @@ impl Debugger {
                         .inspect(system, subargs)?;
                 }
             },
+            "devices" => {
+                for (name, device) in system.devices_with(Capabilities::NONE) {
+                    println!("{:<16} {}", name, device.capabilities());
+                }
+            },
+            "writelog" => {
+                self.write_log_command(system, &args)?;
+            },
+            "strict" => {
+                self.strict_command(system, &args)?;
+            },
+            "log" => {
+                self.log_command(&args)?;
+            },
+            "coverage" => {
+                self.coverage_command(system, &args)?;
+            },
             "dis" | "disassemble" => {
                 let addr = if args.len() > 1 {
-                    Address::from_str_radix(args[1], 16).map_err(|_| Error::new("Unable to parse address"))?
+                    resolve_address(system, args[1])?
                 } else {
                     0
                 };
@@ -154,28 +322,115 @@ impl Debugger {
                     0x1000
                 };
 
-                if let Some(device) = system.get_next_debuggable_device() {
+                if let Some(device) = self.get_target(system) {
+                    print_disassembly(system, device.borrow_mut().as_debuggable().unwrap(), addr, count);
+                }
+            },
+            "tag" => {
+                self.tag_command(system, &args)?;
+            },
+            "untag" => {
+                if args.len() != 2 {
+                    println!("Usage: untag <name>");
+                } else if !system.get_bus().remove_region(args[1]) {
+                    println!("No region named {}", args[1]);
+                }
+            },
+            "where" => {
+                self.where_command(system, &args)?;
+            },
+            "catch" | "uncatch" => {
+                if args.len() != 3 || args[1] != "exception" {
+                    println!("Usage: {} exception <vector or name>", args[0]);
+                } else if let Some(device) = self.get_target(system) {
+                    let enable = args[0] == "catch";
                     device
                         .borrow_mut()
                         .as_debuggable()
                         .unwrap()
-                        .print_disassembly(system, addr, count);
+                        .catch_exception(args[2], enable)?;
+                    if enable {
+                        println!("Catching exception {}", args[2]);
+                    } else {
+                        println!("No longer catching exception {}", args[2]);
+                    }
                 }
             },
+            "sym" | "symbols" => {
+                self.symbols_command(system, &args)?;
+            },
             "c" | "continue" => {
                 self.check_repeat_arg(&args)?;
+                self.target = None;
                 return Ok(DebugControl::Exit);
             },
             "s" | "step" => {
                 self.check_repeat_arg(&args)?;
-                system.step_until_debuggable()?;
+                self.step(system)?;
+                return Ok(DebugControl::Wait);
+            },
+            "rs" | "rstep" => {
+                let count = match args.get(1) {
+                    Some(count) => count.parse::<usize>().map_err(|_| Error::new("Unable to parse step count"))?,
+                    None => 1,
+                };
+                let target = self.get_target(system);
+                let rewound = system.rewind(target.as_ref(), count)?;
+                if rewound < count {
+                    println!("Only {} steps could be undone (use `rewind on` to record steps)", rewound);
+                }
                 return Ok(DebugControl::Wait);
             },
+            "rewind" => {
+                self.rewind_command(system, &args)?;
+            },
+            "t" | "trace" if args.len() > 1 => {
+                self.trace_command(system, &args)?;
+            },
             "t" | "trace" => {
                 self.trace_only = true;
-                system.step_until_debuggable()?;
+                self.step(system)?;
                 return Ok(DebugControl::Continue);
             },
+            "speed" => {
+                if args.len() != 2 {
+                    println!("Usage: speed <factor>");
+                } else {
+                    let speed = args[1].parse::<f32>().map_err(|_| Error::new("Unable to parse speed"))?;
+                    if speed <= 0.0 || !speed.is_finite() {
+                        return Err(Error::new("Speed must be a positive number"));
+                    }
+                    self.speed = Some(speed);
+                    println!("Simulation speed set to {}x", speed);
+                }
+            },
+            "screenshot" => {
+                if args.len() > 2 {
+                    println!("Usage: screenshot [<filename>]");
+                } else {
+                    self.screenshot = Some(args.get(1).map(|filename| filename.to_string()));
+                }
+            },
+            "paste" => {
+                // The text is everything after the command, including any spaces
+                let text = command.trim_start()[args[0].len()..]
+                    .trim_start()
+                    .trim_end_matches(['\r', '\n']);
+                if text.is_empty() {
+                    println!("Usage: paste <text>, where \\n types Enter");
+                } else {
+                    self.paste = Some(unescape(text));
+                }
+            },
+            "pastefile" => {
+                if args.len() != 2 {
+                    println!("Usage: pastefile <filename>");
+                } else {
+                    let text = std::fs::read_to_string(args[1])
+                        .map_err(|err| Error::new(format!("Unable to read {}: {}", args[1], err)))?;
+                    self.paste = Some(text);
+                }
+            },
             "setb" | "setw" | "setl" => {
                 if args.len() != 3 {
                     println!("Usage: set[b|w|l] <addr> <data>");
@@ -201,7 +456,7 @@ impl Debugger {
             //    return Ok(true);
             //},
             _ => {
-                if let Some(device) = system.get_next_debuggable_device() {
+                if let Some(device) = self.get_target(system) {
                     if device.borrow_mut().as_debuggable().unwrap().run_command(system, &args)? {
                         println!("Error: unknown command {}", args[0]);
                     }
@@ -211,6 +466,263 @@ impl Debugger {
         Ok(DebugControl::Wait)
     }
 
+    fn step(&mut self, system: &mut System) -> Result<(), Error> {
+        match self.target.and_then(|id| system.get_device_by_id(id)) {
+            Some((_, device)) => system.step_until_device(device),
+            None => system.step_until_debuggable(),
+        }
+    }
+
+    fn write_log_command(&mut self, system: &mut System, args: &[&str]) -> Result<(), Error> {
+        match args {
+            [_, name, "start"] => {
+                let log = system.log_device_writes(name)?;
+                self.write_logs.insert(name.to_string(), log);
+                println!("logging writes to {}", name);
+            },
+            [_, name, "stop"] => {
+                system.stop_device_write_log(name)?;
+                if let Some(log) = self.write_logs.get(*name) {
+                    println!("stopped logging writes to {} after {} writes", name, log.len());
+                }
+            },
+            [_, name, "save", filename] => {
+                let log = self
+                    .write_logs
+                    .get(*name)
+                    .ok_or_else(|| Error::new(format!("no writes have been logged for {}", name)))?;
+                log.save(filename)?;
+                println!("saved {} writes to {}", log.len(), filename);
+            },
+            [_, name, "clear"] => {
+                if let Some(log) = self.write_logs.get(*name) {
+                    log.clear();
+                }
+            },
+            _ => println!("Usage: writelog <device_name> start|stop|clear|save <filename>"),
+        }
+        Ok(())
+    }
+
+    fn trace_command(&mut self, system: &mut System, args: &[&str]) -> Result<(), Error> {
+        match args {
+            [_, "start", filename] | [_, "start", filename, _] => {
+                let format = match args.get(3) {
+                    Some(format) => format.parse::<TraceFormat>().map_err(Error::new)?,
+                    None => TraceFormat::Text,
+                };
+                system.start_trace(filename, format)?;
+                println!("writing executed instructions to {}", filename);
+            },
+            [_, "stop"] => {
+                let count = system.stop_trace()?;
+                println!("stopped tracing after {} instructions", count);
+            },
+            _ => println!("Usage: trace [start <filename> [text|binary] | stop]"),
+        }
+        Ok(())
+    }
+
+    fn coverage_command(&mut self, system: &mut System, args: &[&str]) -> Result<(), Error> {
+        match args {
+            [_, "start"] => {
+                self.coverage = Some(system.record_coverage());
+                println!("recording accesses to the system bus");
+            },
+            [_, "stop"] => {
+                system.stop_recording_coverage();
+                println!("stopped recording accesses");
+            },
+            [_, "clear"] => {
+                if let Some(coverage) = self.coverage.as_ref() {
+                    coverage.clear();
+                }
+            },
+            [_, "report"] | [_, "report", _] => {
+                let coverage = self
+                    .coverage
+                    .as_ref()
+                    .ok_or_else(|| Error::new("no accesses have been recorded (use `coverage start`)"))?;
+                let report = system.coverage_report(coverage);
+                match args.get(2) {
+                    Some(filename) => {
+                        std::fs::write(filename, report)
+                            .map_err(|err| Error::new(format!("error writing to {}: {}", filename, err)))?;
+                        println!("saved coverage report to {}", filename);
+                    },
+                    None => print!("{}", report),
+                }
+            },
+            [_, "save", filename] => {
+                let coverage = self
+                    .coverage
+                    .as_ref()
+                    .ok_or_else(|| Error::new("no accesses have been recorded (use `coverage start`)"))?;
+                coverage.save_heatmap(filename)?;
+                println!("saved access counts to {}", filename);
+            },
+            _ => println!("Usage: coverage start|stop|clear|report [<filename>]|save <filename>"),
+        }
+        Ok(())
+    }
+
+    fn rewind_command(&mut self, system: &mut System, args: &[&str]) -> Result<(), Error> {
+        match args {
+            [_, "on"] | [_, "on", _] => {
+                let depth = match args.get(2) {
+                    Some(depth) => depth.parse::<usize>().map_err(|_| Error::new("Unable to parse depth"))?,
+                    None => DEFAULT_REWIND_DEPTH,
+                };
+                system.enable_rewind(depth);
+                println!("recording up to {} steps for stepping backwards with rstep", depth);
+                println!("only memory and the state of cpus are restored, so other peripherals aren't rewound");
+            },
+            [_, "off"] => {
+                system.disable_rewind();
+                println!("stopped recording steps");
+            },
+            [_] => {
+                let target = self.get_target(system);
+                println!("{} steps can be undone", system.rewindable_steps(target.as_ref()));
+            },
+            _ => {
+                println!("Usage: rewind [on [<depth>] | off]");
+                println!("Stepping backwards restores memory and the state of cpus, but not other peripherals");
+            },
+        }
+        Ok(())
+    }
+
+    fn tag_command(&mut self, system: &mut System, args: &[&str]) -> Result<(), Error> {
+        match args {
+            [_] => {
+                for region in system.get_bus().regions() {
+                    println!("{}", region);
+                }
+            },
+            [_, addr, len, name] | [_, addr, len, name, _] => {
+                let base = Address::from_str_radix(addr, 16).map_err(|_| Error::new("Unable to parse address"))?;
+                let size = Address::from_str_radix(len, 16).map_err(|_| Error::new("Unable to parse length"))?;
+                let mut region = MemoryRegion::new(name, base, size);
+                if let Some(color) = args.get(4) {
+                    region = region.with_color(color.parse::<RegionColor>().map_err(Error::new)?);
+                }
+                println!("tagged {}", region);
+                system.get_bus().add_region(region);
+            },
+            _ => println!("Usage: tag [<addr> <length> <name> [<color>]]"),
+        }
+        Ok(())
+    }
+
+    fn symbols_command(&mut self, system: &mut System, args: &[&str]) -> Result<(), Error> {
+        match args {
+            [_] => {
+                for (addr, name) in system.symbols.in_range(0, Address::MAX) {
+                    println!("{:08x} {}", addr, name);
+                }
+            },
+            [_, "load", filename] => {
+                let count = system.symbols.load(filename)?;
+                println!("loaded {} symbols from {}", count, filename);
+            },
+            [_, "clear"] => {
+                system.symbols.clear();
+            },
+            [_, "add", name, addr] => {
+                let addr = Address::from_str_radix(addr, 16).map_err(|_| Error::new("Unable to parse address"))?;
+                system.symbols.insert(name, addr);
+            },
+            [_, start, end] => {
+                let start = resolve_address(system, start)?;
+                let end = resolve_address(system, end)?;
+                for (addr, name) in system.symbols.in_range(start, end) {
+                    println!("{:08x} {}", addr, name);
+                }
+            },
+            _ => println!("Usage: symbols [load <filename> | clear | add <name> <addr> | <start> <end>]"),
+        }
+        Ok(())
+    }
+
+    fn where_command(&mut self, system: &System, args: &[&str]) -> Result<(), Error> {
+        if args.len() != 2 {
+            println!("Usage: where <addr>");
+            return Ok(());
+        }
+        let addr = resolve_address(system, args[1])?;
+
+        let bus = system.get_bus();
+        if let Some(symbol) = system.symbols.describe(addr) {
+            println!("{:08x} is {}", addr, symbol);
+        }
+        match bus.region_at(addr) {
+            Some(region) => println!("{:08x} is in {}", addr, region),
+            None => println!("{:08x} is not in a tagged region", addr),
+        }
+        match bus.get_device_at(addr, 1) {
+            Ok((device, offset)) => {
+                let name = system
+                    .get_device_by_id(device.id())
+                    .map(|(name, _)| name)
+                    .unwrap_or("an unnamed device");
+                println!("{:08x} is mapped to {} at offset {:#x}", addr, name, offset);
+            },
+            Err(_) => println!("{:08x} is not mapped to a device", addr),
+        }
+        Ok(())
+    }
+
+    fn strict_command(&mut self, system: &mut System, args: &[&str]) -> Result<(), Error> {
+        let parse = |mode: &str| mode.parse::<StrictMode>().map_err(Error::new);
+        match args {
+            [_, mode] => {
+                let mode = parse(mode)?;
+                system.set_strict_mode(mode);
+                println!("strict mode set to {:?}", mode);
+            },
+            [_, name, "default"] => {
+                let device = system.get_device(name)?;
+                system.set_device_strict_mode(device.id(), None);
+                println!("strict mode for {} set to the system's mode", name);
+            },
+            [_, name, mode] => {
+                let mode = parse(mode)?;
+                let device = system.get_device(name)?;
+                system.set_device_strict_mode(device.id(), Some(mode));
+                println!("strict mode for {} set to {:?}", name, mode);
+            },
+            _ => println!("Usage: strict [<device>] ignore|warn|break|default"),
+        }
+        Ok(())
+    }
+
+    fn log_command(&mut self, args: &[&str]) -> Result<(), Error> {
+        let parse = |level: &str| {
+            level
+                .parse::<LevelFilter>()
+                .map_err(|_| Error::new(format!("invalid log level {}, expected off, error, warn, info, debug, or trace", level)))
+        };
+        match args {
+            [_, level] => {
+                let level = parse(level)?;
+                logging::set_log_level(level);
+                println!("log level set to {}", level);
+            },
+            [_, name, "default"] => {
+                logging::set_device_log_level(name, None);
+                println!("log level for {} set to the global level", name);
+            },
+            [_, name, level] => {
+                let level = parse(level)?;
+                logging::set_device_log_level(name, Some(level));
+                println!("log level for {} set to {}", name, level);
+            },
+            _ => println!("Usage: log [<device_type>] off|error|warn|info|debug|trace|default"),
+        }
+        Ok(())
+    }
+
     fn check_repeat_arg(&mut self, args: &[&str]) -> Result<(), Error> {
         if args.len() > 1 {
             let count = args[1]
@@ -222,7 +734,87 @@ impl Debugger {
     }
 }
 
-fn parse_address(arg: &str) -> Result<(Option<&str>, Address), Error> {
+/// Evaluates conditions using the registers of the device that reached a breakpoint, and the system bus
+struct ConditionContext<'a> {
+    system: &'a System,
+    device: Option<Device>,
+    value: Option<u64>,
+}
+
+impl<'a> ExprContext for ConditionContext<'a> {
+    fn register(&mut self, name: &str) -> Option<u64> {
+        self.device
+            .as_ref()
+            .and_then(|device| device.borrow_mut().as_debuggable().and_then(|debug| debug.get_register(name)))
+    }
+
+    fn read_memory(&mut self, addr: Address, size: usize) -> Result<u64, Error> {
+        let mut data = [0; 8];
+        self.system.get_bus().read(self.system.clock, addr, &mut data[..size])?;
+        Ok(data[..size].iter().fold(0, |acc, byte| (acc << 8) | *byte as u64))
+    }
+
+    fn value(&self) -> Option<u64> {
+        self.value
+    }
+}
+
+/// Parse the condition of a breakpoint or watchpoint, and check that the device it will be evaluated with has
+/// the registers it names, so that a mistyped name doesn't make the condition fail every time it's checked
+fn parse_condition(system: &System, device: Option<Device>, args: &[&str]) -> Result<Expr, Error> {
+    let condition = Expr::parse(&args.join(" "))?;
+    if device.is_some() {
+        let mut context = ConditionContext {
+            system,
+            device,
+            value: None,
+        };
+        condition.check_registers(&mut context)?;
+    }
+    Ok(condition)
+}
+
+/// Print the disassembly with the name of each tagged region where it starts and the labels of any
+/// symbols, or use the device's own output if it can't disassemble single instructions
+fn print_disassembly(system: &System, debuggable: &mut dyn Debuggable, addr: Address, count: usize) {
+    let end = addr.saturating_add(count as Address);
+    let mut next = addr;
+    let mut region = None;
+    while next < end {
+        let Some((text, bytes)) = debuggable.disassemble(system, next) else {
+            if next == addr {
+                debuggable.print_disassembly(system, addr, count);
+            }
+            return;
+        };
+
+        let current = system.get_bus().region_at(next).cloned();
+        if current != region {
+            if let Some(current) = current.as_ref() {
+                println!("{}:", current.colored_name());
+            }
+            region = current;
+        }
+        for (_, name) in system.symbols.in_range(next, next + bytes.len().max(1) as Address) {
+            println!("{}:", name);
+        }
+
+        let data = bytes
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<String>>()
+            .join(" ");
+        println!("  {:08x}: {:<24} {}", next, data, text);
+        next += bytes.len().max(1) as Address;
+    }
+}
+
+/// Parse an address, which can be prefixed by a device name, as in `cpu:1000` or `cpu:main`
+fn parse_address<'a>(system: &System, arg: &'a str) -> Result<(Option<&'a str>, Address), Error> {
+    if let Some(addr) = system.symbols.get(arg) {
+        return Ok((None, addr));
+    }
+
     let (name, addrstr) = match arg.find(':') {
         Some(index) => {
             let (name, addrstr) = arg.split_at(index);
@@ -231,6 +823,36 @@ fn parse_address(arg: &str) -> Result<(Option<&str>, Address), Error> {
         None => (None, arg),
     };
 
-    let addr = Address::from_str_radix(addrstr, 16).map_err(|_| Error::new("Unable to parse address"))?;
+    let addr = resolve_address(system, addrstr)?;
     Ok((name, addr))
 }
+
+/// Parse a hex address or the name of a symbol, where symbols take priority over names that are also valid
+/// hex numbers (such as `add`), which can instead be written with a leading zero
+fn resolve_address(system: &System, arg: &str) -> Result<Address, Error> {
+    match system.symbols.get(arg) {
+        Some(addr) => Ok(addr),
+        None => {
+            Address::from_str_radix(arg, 16).map_err(|_| Error::new(format!("Unable to parse address or find symbol: {}", arg)))
+        },
+    }
+}
+
+/// Replace the escapes `\n`, `\t`, and `\\` in text given to the `paste` command
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
@@ -0,0 +1,41 @@
+/// A pixel from one of the layers of the screen, as a palette number and a colour index in that palette, where
+/// colour 0 is transparent
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TilePixel {
+    pub palette: u8,
+    pub colour: u8,
+    /// Whether the tile or sprite this pixel came from is drawn in front of the layers without priority
+    pub priority: bool,
+}
+
+impl TilePixel {
+    pub fn new(palette: u8, colour: u8, priority: bool) -> Self {
+        Self {
+            palette,
+            colour,
+            priority,
+        }
+    }
+
+    #[inline]
+    pub fn is_transparent(&self) -> bool {
+        self.colour == 0
+    }
+
+    /// The index of the colour in a colour RAM with the given number of colours in each palette
+    #[inline]
+    pub fn index(&self, colours_per_palette: usize) -> usize {
+        self.palette as usize * colours_per_palette + self.colour as usize
+    }
+}
+
+/// Returns the index of the visible pixel out of the given layers, which are in order from front to back.  The
+/// first opaque pixel with priority is visible, or otherwise the first opaque pixel without priority, or `None`
+/// if all the pixels are transparent, in which case the background colour is visible
+#[inline]
+pub fn composite(layers: &[TilePixel]) -> Option<usize> {
+    layers
+        .iter()
+        .position(|pixel| pixel.priority && !pixel.is_transparent())
+        .or_else(|| layers.iter().position(|pixel| !pixel.priority && !pixel.is_transparent()))
+}
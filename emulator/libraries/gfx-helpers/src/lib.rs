@@ -0,0 +1,14 @@
+//! Helpers for the tile and sprite based video devices
+//!
+//! These cover the parts of rendering that most tile based video chips have in common: decoding a pixel of a
+//! tile from video memory, arranging the tiles of a multi-tile sprite, finding the sprites on each line, and
+//! choosing which layer's pixel is visible.  Converting the final colour to a host pixel is left to each device,
+//! since the colour RAM formats and effects like shadow/highlight are specific to each chip.
+
+mod layers;
+mod sprites;
+mod tiles;
+
+pub use crate::layers::{TilePixel, composite};
+pub use crate::sprites::{CellOrder, sprite_cell, sprites_by_line};
+pub use crate::tiles::{Flip, TileFormat, TileLayout};
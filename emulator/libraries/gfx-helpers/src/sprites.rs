@@ -0,0 +1,42 @@
+use crate::tiles::Flip;
+
+/// The order that the tiles of a multi-tile sprite are stored in
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CellOrder {
+    /// Each row of tiles is stored one after another
+    RowMajor,
+    /// Each column of tiles is stored one after another, such as on the Genesis
+    ColumnMajor,
+}
+
+/// Returns the offset from the sprite's first tile of the tile to draw at the given cell of the sprite, where
+/// the size is the width and height of the sprite in tiles.  Flipping a sprite mirrors the order of its tiles
+/// as well as the pixels in each tile
+#[inline]
+pub fn sprite_cell(size: (usize, usize), cell: (usize, usize), flip: Flip, order: CellOrder) -> usize {
+    let x = if flip.horizontal { size.0 - 1 - cell.0 } else { cell.0 };
+    let y = if flip.vertical { size.1 - 1 - cell.1 } else { cell.1 };
+
+    match order {
+        CellOrder::RowMajor => y * size.0 + x,
+        CellOrder::ColumnMajor => x * size.1 + y,
+    }
+}
+
+/// Returns the list of sprites that appear on each line of the screen, given the top line and height in pixels
+/// of each sprite.  The sprites on each line are kept in the same order as they are given, which is usually
+/// their order of priority
+pub fn sprites_by_line<I>(sprites: I, lines: usize) -> Vec<Vec<usize>>
+where
+    I: IntoIterator<Item = (isize, usize)>,
+{
+    let mut by_line = vec![vec![]; lines];
+    for (i, (top, height)) in sprites.into_iter().enumerate() {
+        let start = top.clamp(0, lines as isize) as usize;
+        let end = (top + height as isize).clamp(0, lines as isize) as usize;
+        for line in by_line[start..end].iter_mut() {
+            line.push(i);
+        }
+    }
+    by_line
+}
@@ -0,0 +1,112 @@
+/// The way the bits of each pixel are arranged in the data of a tile
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TileFormat {
+    /// Each pixel's bits are stored together, with the leftmost pixel in the most significant bits of each byte,
+    /// such as the 4 bits per pixel tiles of the Genesis
+    Linear { bits_per_pixel: u8 },
+    /// Each row is stored as one bitplane after another, such as the 4 plane tiles of the Master System
+    PlanarRows { planes: u8 },
+    /// Each bitplane of the whole tile is stored one after another, such as the 2 plane tiles of the NES
+    Planar { planes: u8 },
+}
+
+impl TileFormat {
+    pub fn bits_per_pixel(&self) -> usize {
+        match *self {
+            TileFormat::Linear {
+                bits_per_pixel,
+            } => bits_per_pixel as usize,
+            TileFormat::PlanarRows {
+                planes,
+            }
+            | TileFormat::Planar {
+                planes,
+            } => planes as usize,
+        }
+    }
+}
+
+/// Whether a tile is mirrored horizontally or vertically when drawn
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Flip {
+    pub horizontal: bool,
+    pub vertical: bool,
+}
+
+impl Flip {
+    pub fn new(horizontal: bool, vertical: bool) -> Self {
+        Self {
+            horizontal,
+            vertical,
+        }
+    }
+}
+
+/// The size and pixel format of the tiles in video memory.  The width must be a multiple of 8 pixels
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TileLayout {
+    pub format: TileFormat,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl TileLayout {
+    pub const fn new(format: TileFormat, width: usize, height: usize) -> Self {
+        Self {
+            format,
+            width,
+            height,
+        }
+    }
+
+    pub fn bytes_per_tile(&self) -> usize {
+        self.width * self.height * self.format.bits_per_pixel() / 8
+    }
+
+    /// The address of the given tile number, where tiles are stored one after another starting at `base`
+    pub fn tile_addr(&self, base: usize, tile: usize) -> usize {
+        base + tile * self.bytes_per_tile()
+    }
+
+    /// Returns the colour index of the pixel at the given position in the tile which starts at `tile_addr`.  The
+    /// position is in screen order, so the flip is applied before reading the tile data
+    #[inline]
+    pub fn pixel(&self, data: &[u8], tile_addr: usize, x: usize, y: usize, flip: Flip) -> u8 {
+        let x = if flip.horizontal { self.width - 1 - x } else { x };
+        let y = if flip.vertical { self.height - 1 - y } else { y };
+
+        match self.format {
+            TileFormat::Linear {
+                bits_per_pixel,
+            } => {
+                let bits = bits_per_pixel as usize;
+                let bit_offset = (y * self.width + x) * bits;
+                let shift = 8 - bits - (bit_offset % 8);
+                (data[tile_addr + bit_offset / 8] >> shift) & ((1 << bits) - 1) as u8
+            },
+            TileFormat::PlanarRows {
+                planes,
+            } => {
+                let row_bytes = self.width / 8;
+                let row_addr = tile_addr + y * row_bytes * planes as usize + x / 8;
+                (0..planes as usize)
+                    .fold(0, |value, plane| value | (((data[row_addr + plane * row_bytes] >> (7 - x % 8)) & 0x01) << plane))
+            },
+            TileFormat::Planar {
+                planes,
+            } => {
+                let plane_bytes = self.width * self.height / 8;
+                let byte_addr = tile_addr + (y * self.width + x) / 8;
+                (0..planes as usize)
+                    .fold(0, |value, plane| value | (((data[byte_addr + plane * plane_bytes] >> (7 - x % 8)) & 0x01) << plane))
+            },
+        }
+    }
+
+    /// Decode a whole row of a tile into `output`, which must be at least as long as the tile is wide
+    pub fn decode_row(&self, data: &[u8], tile_addr: usize, y: usize, flip: Flip, output: &mut [u8]) {
+        for (x, pixel) in output[..self.width].iter_mut().enumerate() {
+            *pixel = self.pixel(data, tile_addr, x, y, flip);
+        }
+    }
+}
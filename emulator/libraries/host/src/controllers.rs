@@ -1,4 +1,5 @@
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
 pub enum ControllerDevice {
     A,
     B,
@@ -6,6 +7,22 @@ pub enum ControllerDevice {
     D,
 }
 
+/// The analog sticks and triggers of a controller
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ControllerAxis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+/// The position of an analog axis when it's released, which is the centre of a stick or a trigger that isn't pulled
+pub const AXIS_CENTER: i16 = 0;
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ControllerInput {
     DpadUp(bool),
@@ -20,6 +37,62 @@ pub enum ControllerInput {
     ButtonZ(bool),
     Start(bool),
     Mode(bool),
+    /// The position of an analog axis, from -32768 (left or up) to 32767 (right or down), with triggers going
+    /// from 0 when released to 32767 when fully pulled
+    Axis(ControllerAxis, i16),
+    /// The absolute position of a paddle knob, from 0 when turned fully left to 65535 when turned fully right
+    Paddle(u16),
+    /// The distance a dial or spinner has turned since the last event, with positive values being clockwise
+    Dial(i16),
+}
+
+impl ControllerInput {
+    /// Returns true if a button is pressed or an axis is away from its centre, which are the inputs that need
+    /// to be released when the window loses focus
+    pub fn is_pressed(&self) -> bool {
+        match *self {
+            ControllerInput::DpadUp(state)
+            | ControllerInput::DpadDown(state)
+            | ControllerInput::DpadLeft(state)
+            | ControllerInput::DpadRight(state)
+            | ControllerInput::ButtonA(state)
+            | ControllerInput::ButtonB(state)
+            | ControllerInput::ButtonC(state)
+            | ControllerInput::ButtonX(state)
+            | ControllerInput::ButtonY(state)
+            | ControllerInput::ButtonZ(state)
+            | ControllerInput::Start(state)
+            | ControllerInput::Mode(state) => state,
+            ControllerInput::Axis(_, value) => value != AXIS_CENTER,
+            ControllerInput::Paddle(_) | ControllerInput::Dial(_) => false,
+        }
+    }
+
+    /// Returns true for the inputs that have a range of values rather than being pressed or released
+    pub fn is_analog(&self) -> bool {
+        matches!(self, ControllerInput::Axis(..) | ControllerInput::Paddle(_) | ControllerInput::Dial(_))
+    }
+
+    /// Returns the same input with the given state, where releasing an axis returns it to the centre, and the
+    /// other analog inputs are unchanged
+    pub fn with_state(self, state: bool) -> Self {
+        match self {
+            ControllerInput::DpadUp(_) => ControllerInput::DpadUp(state),
+            ControllerInput::DpadDown(_) => ControllerInput::DpadDown(state),
+            ControllerInput::DpadLeft(_) => ControllerInput::DpadLeft(state),
+            ControllerInput::DpadRight(_) => ControllerInput::DpadRight(state),
+            ControllerInput::ButtonA(_) => ControllerInput::ButtonA(state),
+            ControllerInput::ButtonB(_) => ControllerInput::ButtonB(state),
+            ControllerInput::ButtonC(_) => ControllerInput::ButtonC(state),
+            ControllerInput::ButtonX(_) => ControllerInput::ButtonX(state),
+            ControllerInput::ButtonY(_) => ControllerInput::ButtonY(state),
+            ControllerInput::ButtonZ(_) => ControllerInput::ButtonZ(state),
+            ControllerInput::Start(_) => ControllerInput::Start(state),
+            ControllerInput::Mode(_) => ControllerInput::Mode(state),
+            ControllerInput::Axis(axis, _) if !state => ControllerInput::Axis(axis, AXIS_CENTER),
+            input @ (ControllerInput::Axis(..) | ControllerInput::Paddle(_) | ControllerInput::Dial(_)) => input,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -36,3 +109,28 @@ impl ControllerEvent {
         }
     }
 }
+
+/// Feedback that an emulated device can send back to a physical controller, if the host supports it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ControllerOutput {
+    /// Vibrate the controller with the given strength of the low and high frequency motors (0 to turn off)
+    /// for the given number of milliseconds
+    Rumble { strong: u16, weak: u16, duration_ms: u32 },
+    /// Turn one of the controller's indicator lights on or off
+    Led(u8, bool),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ControllerFeedback {
+    pub device: ControllerDevice,
+    pub output: ControllerOutput,
+}
+
+impl ControllerFeedback {
+    pub fn new(device: ControllerDevice, output: ControllerOutput) -> Self {
+        Self {
+            device,
+            output,
+        }
+    }
+}
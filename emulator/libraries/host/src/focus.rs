@@ -0,0 +1,53 @@
+use crate::keys::KeyEvent;
+use crate::controllers::{ControllerDevice, ControllerEvent};
+
+
+/// The keys and controller buttons that the host has reported as held down, so that they can all be released
+/// when the window loses focus, since the window won't receive the release events while it's in the background
+#[derive(Clone, Debug, Default)]
+pub struct HeldInputs {
+    keys: Vec<KeyEvent>,
+    buttons: Vec<ControllerEvent>,
+}
+
+impl HeldInputs {
+    pub fn update_key(&mut self, event: KeyEvent) {
+        self.keys.retain(|held| held.key != event.key);
+        if event.state {
+            self.keys.push(event);
+        }
+    }
+
+    pub fn update_button(&mut self, event: ControllerEvent) {
+        self.buttons
+            .retain(|held| held.device != event.device || held.input.with_state(false) != event.input.with_state(false));
+        if event.input.is_pressed() {
+            self.buttons.push(event);
+        }
+    }
+
+    /// Returns the release events for every key that is held down, and forgets them
+    pub fn release_keys(&mut self) -> Vec<KeyEvent> {
+        self.keys.drain(..).map(|event| KeyEvent::new(event.key, false)).collect()
+    }
+
+    /// Returns the release events for every controller button that is held down, and forgets them
+    pub fn release_buttons(&mut self) -> Vec<ControllerEvent> {
+        self.buttons
+            .drain(..)
+            .map(|event| ControllerEvent::new(event.device, event.input.with_state(false)))
+            .collect()
+    }
+
+    /// Returns the release events for the buttons held down on one controller, such as when it's disconnected
+    pub fn release_device(&mut self, device: ControllerDevice) -> Vec<ControllerEvent> {
+        let mut released = vec![];
+        self.buttons.retain(|event| {
+            if event.device == device {
+                released.push(ControllerEvent::new(device, event.input.with_state(false)));
+            }
+            event.device != device
+        });
+        released
+    }
+}
@@ -5,6 +5,9 @@ use crate::traits::ClockedQueue;
 
 pub const MASK_COLOUR: u32 = 0xFFFFFFFF;
 
+/// The number of frames a teed receiver can fall behind by before the oldest are dropped
+const TEE_QUEUE_SIZE: usize = 60;
+
 #[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
 pub enum PixelEncoding {
     #[default]
@@ -37,12 +40,63 @@ impl Pixel {
     }
 }
 
+/// A rectangular area of a frame, in pixels
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Returns the smallest rectangle that contains both rectangles
+    pub fn union(self, other: Rect) -> Rect {
+        if self.is_empty() {
+            return other;
+        }
+        if other.is_empty() {
+            return self;
+        }
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+        Rect::new(x, y, right - x, bottom - y)
+    }
+
+    /// Returns the part of this rectangle that is inside the other, which is empty if they don't overlap
+    pub fn intersection(self, other: Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        Rect::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y))
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct Frame {
     pub width: u32,
     pub height: u32,
     pub encoding: PixelEncoding,
     pub bitmap: Vec<u32>,
+    /// The area that changed since the previous frame sent by the same source, which is empty if nothing
+    /// changed, or `None` if the whole frame must be redrawn
+    pub dirty: Option<Rect>,
 }
 
 impl Frame {
@@ -52,6 +106,7 @@ impl Frame {
             height,
             encoding,
             bitmap: vec![0; (width * height) as usize],
+            dirty: None,
         }
     }
 
@@ -63,6 +118,65 @@ impl Frame {
         self.width = width;
         self.height = height;
         self.bitmap.resize((width * height) as usize, 0);
+        self.dirty = None;
+    }
+
+    /// Returns the area that changed since the previous frame, which is the whole frame if it isn't known
+    pub fn dirty_rect(&self) -> Rect {
+        self.dirty.unwrap_or(Rect::new(0, 0, self.width, self.height))
+    }
+
+    /// Returns true if the frame is known to be the same as the previous frame
+    pub fn is_unchanged(&self) -> bool {
+        self.dirty.map(|rect| rect.is_empty()).unwrap_or(false)
+    }
+
+    /// Add an area to the changes since the previous frame
+    pub fn mark_dirty(&mut self, rect: Rect) {
+        if let Some(dirty) = self.dirty.as_mut() {
+            *dirty = dirty.union(rect);
+        }
+    }
+
+    /// Set the changed area to the smallest rectangle containing every pixel that is different in the previous
+    /// frame, or to the whole frame if the previous frame had a different size or encoding
+    pub fn find_changes(&mut self, previous: &Frame) {
+        if (self.width, self.height, self.encoding) != (previous.width, previous.height, previous.encoding) {
+            self.dirty = None;
+            return;
+        }
+
+        let width = self.width as usize;
+        let changed_line = |y: &usize| self.bitmap[y * width..(y + 1) * width] != previous.bitmap[y * width..(y + 1) * width];
+        let top = match (0..self.height as usize).find(changed_line) {
+            Some(top) => top,
+            None => {
+                self.dirty = Some(Rect::default());
+                return;
+            },
+        };
+        let bottom = (top..self.height as usize).rev().find(changed_line).unwrap_or(top);
+
+        let mut left = width;
+        let mut right = 0;
+        for y in top..=bottom {
+            let line = &self.bitmap[y * width..(y + 1) * width];
+            let previous_line = &previous.bitmap[y * width..(y + 1) * width];
+            if let Some(x) = line.iter().zip(previous_line).position(|(a, b)| a != b) {
+                left = left.min(x);
+                right = right.max(width - line.iter().zip(previous_line).rev().position(|(a, b)| a != b).unwrap());
+            }
+        }
+        self.dirty = Some(Rect::new(left as u32, top as u32, (right - left) as u32, (bottom - top + 1) as u32));
+    }
+
+    /// Add the changes made in an older frame that is being skipped, so that this frame's changes are relative
+    /// to the frame before the skipped one
+    pub fn include_changes(&mut self, older: &Frame) {
+        match older.dirty {
+            Some(rect) if (older.width, older.height) == (self.width, self.height) => self.mark_dirty(rect),
+            _ => self.dirty = None,
+        }
     }
 
     #[inline]
@@ -105,18 +219,71 @@ impl Frame {
         let value = value.encode(self.encoding);
         self.bitmap.iter_mut().for_each(|pixel| *pixel = value);
     }
+
+    /// Returns the CRC-32 of the frame's size and the RGB colours of its pixels, which is the same for any
+    /// encoding and doesn't change between builds, so it can be compared against the hashes of golden images
+    pub fn crc32(&self) -> u32 {
+        let mut crc = !0;
+        let mut add_byte = |byte: u8| {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            }
+        };
+
+        self.width.to_be_bytes().into_iter().for_each(&mut add_byte);
+        self.height.to_be_bytes().into_iter().for_each(&mut add_byte);
+        for pixel in &self.bitmap {
+            let (r, g, b) = match self.encoding {
+                PixelEncoding::RGBA => (pixel >> 24, pixel >> 16, pixel >> 8),
+                PixelEncoding::ARGB => (pixel >> 16, pixel >> 8, *pixel),
+                PixelEncoding::ABGR => (*pixel, pixel >> 8, pixel >> 16),
+            };
+            add_byte(r as u8);
+            add_byte(g as u8);
+            add_byte(b as u8);
+        }
+        !crc
+    }
+
+    /// Remove the given number of pixels from each edge of the frame, such as to hide the overscan area
+    pub fn crop(&mut self, left: u32, top: u32, right: u32, bottom: u32) {
+        let left = left.min(self.width);
+        let top = top.min(self.height);
+        let width = self.width.saturating_sub(left + right);
+        let height = self.height.saturating_sub(top + bottom);
+
+        let mut bitmap = Vec::with_capacity((width * height) as usize);
+        for y in top..(top + height) {
+            let start = (y * self.width + left) as usize;
+            bitmap.extend_from_slice(&self.bitmap[start..start + width as usize]);
+        }
+
+        self.width = width;
+        self.height = height;
+        self.bitmap = bitmap;
+        self.dirty = self.dirty.map(|dirty| {
+            let visible = dirty.intersection(Rect::new(left, top, width, height));
+            match visible.is_empty() {
+                true => Rect::default(),
+                false => Rect::new(visible.x - left, visible.y - top, visible.width, visible.height),
+            }
+        });
+    }
 }
 
 pub fn frame_queue(width: u32, height: u32) -> (FrameSender, FrameReceiver) {
     let sender = FrameSender {
         encoding: Arc::new(Mutex::new(PixelEncoding::RGBA)),
         queue: ClockedQueue::new(10),
+        taps: Arc::new(Mutex::new(vec![])),
     };
 
     let receiver = FrameReceiver {
         max_size: (width, height),
         encoding: sender.encoding.clone(),
         queue: sender.queue.clone(),
+        taps: sender.taps.clone(),
     };
 
     (sender, receiver)
@@ -125,6 +292,7 @@ pub fn frame_queue(width: u32, height: u32) -> (FrameSender, FrameReceiver) {
 pub struct FrameSender {
     encoding: Arc<Mutex<PixelEncoding>>,
     queue: ClockedQueue<Frame>,
+    taps: Arc<Mutex<Vec<ClockedQueue<Frame>>>>,
 }
 
 impl FrameSender {
@@ -133,7 +301,12 @@ impl FrameSender {
     }
 
     pub fn add(&self, clock: Instant, frame: Frame) {
-        self.queue.push(clock, frame);
+        // A frame that is dropped because a receiver fell behind passes its changes on to the next frame
+        let merge = |dropped: Frame, next: &mut Frame| next.include_changes(&dropped);
+        for tap in self.taps.lock().unwrap().iter() {
+            tap.push_merging(clock, frame.clone(), merge);
+        }
+        self.queue.push_merging(clock, frame, merge);
     }
 }
 
@@ -141,6 +314,7 @@ pub struct FrameReceiver {
     max_size: (u32, u32),
     encoding: Arc<Mutex<PixelEncoding>>,
     queue: ClockedQueue<Frame>,
+    taps: Arc<Mutex<Vec<ClockedQueue<Frame>>>>,
 }
 
 impl FrameReceiver {
@@ -152,7 +326,142 @@ impl FrameReceiver {
         *self.encoding.lock().unwrap() = encoding;
     }
 
+    /// Returns another receiver which is sent a copy of every frame from now on, so that something like a
+    /// video recorder can see the same frames as the display.  The encoding is shared with this receiver,
+    /// so the copies have whatever encoding the display requested.
+    pub fn tee(&self) -> FrameReceiver {
+        let queue = ClockedQueue::new(TEE_QUEUE_SIZE);
+        self.taps.lock().unwrap().push(queue.clone());
+        FrameReceiver {
+            max_size: self.max_size,
+            encoding: self.encoding.clone(),
+            queue,
+            taps: self.taps.clone(),
+        }
+    }
+
+    /// Returns the newest frame in the queue and discards the rest, with their changes added to the newest frame
     pub fn latest(&self) -> Option<(Instant, Frame)> {
-        self.queue.pop_latest()
+        let mut latest: Option<(Instant, Frame)> = None;
+        while let Some((clock, mut frame)) = self.queue.pop_next() {
+            if let Some((_, older)) = latest.as_ref() {
+                frame.include_changes(older);
+            }
+            latest = Some((clock, frame));
+        }
+        latest
+    }
+
+    /// Returns the oldest frame in the queue, so that every frame can be received in order
+    pub fn next(&self) -> Option<(Instant, Frame)> {
+        self.queue.pop_next()
+    }
+}
+
+/// Collects the [`Frame::crc32`] hash of every frame sent to a video source, so the rendered output can be
+/// compared against a list of golden hashes
+pub struct FrameHasher {
+    receiver: FrameReceiver,
+    hashes: Vec<(Instant, u32)>,
+}
+
+impl FrameHasher {
+    /// Start collecting the hashes of the frames from now on, using a tee of the given receiver
+    pub fn new(receiver: &FrameReceiver) -> Self {
+        Self {
+            receiver: receiver.tee(),
+            hashes: vec![],
+        }
+    }
+
+    /// Hash any frames that have been sent since the last update
+    pub fn update(&mut self) {
+        while let Some((clock, frame)) = self.receiver.next() {
+            self.hashes.push((clock, frame.crc32()));
+        }
+    }
+
+    /// Returns the time each frame was sent, and its hash
+    pub fn hashes(&mut self) -> &[(Instant, u32)] {
+        self.update();
+        &self.hashes
+    }
+
+    /// Returns the index of the first frame whose hash is different from the expected list, or the length of
+    /// the shorter list if one ends before the other, or `None` if they're the same
+    pub fn first_mismatch(&mut self, expected: &[u32]) -> Option<usize> {
+        let hashes = self.hashes();
+        let mismatch = hashes.iter().zip(expected).position(|((_, hash), expected)| hash != expected);
+        match mismatch {
+            Some(index) => Some(index),
+            None if hashes.len() != expected.len() => Some(hashes.len().min(expected.len())),
+            None => None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.update();
+        self.hashes.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use femtos::Duration;
+
+    use super::*;
+
+    fn picture(encoding: PixelEncoding) -> Frame {
+        let mut frame = Frame::new(2, 1, encoding);
+        frame.set_pixel(0, 0, Pixel::Rgb(1, 2, 3));
+        frame.set_pixel(1, 0, Pixel::Rgba(4, 5, 6, 0x80));
+        frame
+    }
+
+    fn shade(value: u8) -> Frame {
+        let mut frame = Frame::new(2, 2, PixelEncoding::RGBA);
+        frame.clear(Pixel::Rgb(value, value, value));
+        frame
+    }
+
+    #[test]
+    fn crc_is_the_crc32_of_the_size_and_colours() {
+        // The same as a CRC-32 of 00000002 00000001 010203 040506, such as from `zlib.crc32()`
+        assert_eq!(picture(PixelEncoding::RGBA).crc32(), 0x1F94_1D35);
+    }
+
+    #[test]
+    fn crc_is_the_same_for_every_encoding() {
+        let crc = picture(PixelEncoding::RGBA).crc32();
+        assert_eq!(picture(PixelEncoding::ARGB).crc32(), crc);
+        assert_eq!(picture(PixelEncoding::ABGR).crc32(), crc);
+    }
+
+    #[test]
+    fn crc_includes_the_size() {
+        let mut frame = Frame::new(1, 2, PixelEncoding::RGBA);
+        frame.set_pixel(0, 0, Pixel::Rgb(1, 2, 3));
+        frame.set_pixel(0, 1, Pixel::Rgb(4, 5, 6));
+        assert_ne!(frame.crc32(), picture(PixelEncoding::RGBA).crc32());
+    }
+
+    #[test]
+    fn hasher_finds_the_first_mismatch() {
+        let (sender, receiver) = frame_queue(2, 2);
+        let mut hasher = FrameHasher::new(&receiver);
+        for i in 0..3 {
+            sender.add(Instant::START + Duration::from_millis(i as u64), shade(i));
+        }
+        let expected: Vec<u32> = (0..3).map(|i| shade(i).crc32()).collect();
+
+        assert_eq!(hasher.hashes().len(), 3);
+        assert_eq!(hasher.first_mismatch(&expected), None);
+        assert_eq!(hasher.first_mismatch(&[expected[0], 0, expected[2]]), Some(1));
+        assert_eq!(hasher.first_mismatch(&expected[..2]), Some(2));
+        assert_eq!(hasher.first_mismatch(&[expected[0], expected[1], expected[2], 0]), Some(3));
+        assert_eq!(hasher.first_mismatch(&[]), Some(0));
+
+        hasher.clear();
+        assert_eq!(hasher.first_mismatch(&[]), None);
     }
 }
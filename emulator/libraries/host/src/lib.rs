@@ -1,15 +1,23 @@
 mod audio;
 mod controllers;
+mod focus;
 mod gfx;
 mod input;
 mod keys;
 mod mouse;
+mod paste;
+mod slip;
 mod traits;
 
 pub use crate::audio::{Sample, AudioFrame};
-pub use crate::gfx::{Pixel, PixelEncoding, Frame, FrameSender, FrameReceiver, frame_queue};
+pub use crate::gfx::{Pixel, PixelEncoding, Rect, Frame, FrameSender, FrameReceiver, FrameHasher, frame_queue};
 pub use crate::keys::{Key, KeyEvent};
 pub use crate::mouse::{MouseButton, MouseEventType, MouseEvent, MouseState};
-pub use crate::controllers::{ControllerDevice, ControllerInput, ControllerEvent};
+pub use crate::controllers::{
+    ControllerDevice, ControllerAxis, ControllerInput, ControllerEvent, ControllerOutput, ControllerFeedback, AXIS_CENTER,
+};
 pub use crate::input::{EventSender, EventReceiver, event_queue};
-pub use crate::traits::{Host, HostError, Tty, Audio, ClockedQueue, DummyAudio};
+pub use crate::focus::HeldInputs;
+pub use crate::paste::{PasteQueue, DEFAULT_PASTE_RATE, char_to_key};
+pub use crate::traits::{Host, HostError, Tty, Network, Audio, ClockedQueue, DummyAudio};
+pub use crate::slip::SlipTty;
@@ -0,0 +1,145 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::keys::{Key, KeyEvent};
+
+
+/// The number of characters typed per second when pasting, unless another rate is given
+pub const DEFAULT_PASTE_RATE: f32 = 20.0;
+
+/// Text that is being typed into the emulated machine, by pressing and releasing the keys for each character
+/// at a fixed rate, so that machines that scan their keyboard don't miss any of them
+#[derive(Clone, Debug)]
+pub struct PasteQueue {
+    events: VecDeque<KeyEvent>,
+    /// The keys that have been pressed and not yet released
+    pressed: Vec<Key>,
+    /// The time between each key event, which is half the time for each character
+    interval: Duration,
+    elapsed: Duration,
+}
+
+impl Default for PasteQueue {
+    fn default() -> Self {
+        Self::new(DEFAULT_PASTE_RATE)
+    }
+}
+
+impl PasteQueue {
+    /// Create a queue that types the given number of characters per second
+    pub fn new(chars_per_second: f32) -> Self {
+        Self {
+            events: VecDeque::new(),
+            pressed: vec![],
+            interval: Duration::from_secs_f32(0.5 / chars_per_second),
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// Add the text to the end of what's being typed, and return the number of characters that can't be typed
+    /// with the emulated keys, which are skipped
+    pub fn push_text(&mut self, text: &str) -> usize {
+        let mut skipped = 0;
+        // Line endings are typed as a single Enter
+        for c in text.replace("\r\n", "\n").chars() {
+            let (key, shifted) = match char_to_key(c) {
+                Some(typed) => typed,
+                None => {
+                    skipped += 1;
+                    continue;
+                },
+            };
+
+            if shifted {
+                self.events.push_back(KeyEvent::new(Key::LeftShift, true));
+            }
+            self.events.push_back(KeyEvent::new(key, true));
+            self.events.push_back(KeyEvent::new(key, false));
+            if shifted {
+                self.events.push_back(KeyEvent::new(Key::LeftShift, false));
+            }
+        }
+        skipped
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Stop typing, and return the release events for any keys that are still pressed
+    pub fn clear(&mut self) -> Vec<KeyEvent> {
+        self.elapsed = Duration::ZERO;
+        self.events.clear();
+        self.pressed.drain(..).map(|key| KeyEvent::new(key, false)).collect()
+    }
+
+    /// Returns the key events that are due after the given amount of time has passed since the last update
+    pub fn update(&mut self, elapsed: Duration) -> Vec<KeyEvent> {
+        let mut events = vec![];
+        if self.events.is_empty() {
+            self.elapsed = Duration::ZERO;
+            return events;
+        }
+
+        self.elapsed += elapsed;
+        while self.elapsed >= self.interval {
+            self.elapsed -= self.interval;
+            let event = match self.events.pop_front() {
+                Some(event) => event,
+                None => break,
+            };
+            self.pressed.retain(|key| *key != event.key);
+            if event.state {
+                self.pressed.push(event.key);
+            }
+            events.push(event);
+        }
+        events
+    }
+}
+
+/// Returns the key that types the given character on a US keyboard, and whether shift needs to be held down
+pub fn char_to_key(c: char) -> Option<(Key, bool)> {
+    if c.is_ascii_alphabetic() {
+        return Some((letter_key(c.to_ascii_lowercase())?, c.is_ascii_uppercase()));
+    }
+
+    #[rustfmt::skip]
+    let typed = match c {
+        '1' => (Key::Num1, false),          '!' => (Key::Num1, true),
+        '2' => (Key::Num2, false),          '@' => (Key::Num2, true),
+        '3' => (Key::Num3, false),          '#' => (Key::Num3, true),
+        '4' => (Key::Num4, false),          '$' => (Key::Num4, true),
+        '5' => (Key::Num5, false),          '%' => (Key::Num5, true),
+        '6' => (Key::Num6, false),          '^' => (Key::Num6, true),
+        '7' => (Key::Num7, false),          '&' => (Key::Num7, true),
+        '8' => (Key::Num8, false),          '*' => (Key::Num8, true),
+        '9' => (Key::Num9, false),          '(' => (Key::Num9, true),
+        '0' => (Key::Num0, false),          ')' => (Key::Num0, true),
+        '-' => (Key::Minus, false),         '_' => (Key::Minus, true),
+        '=' => (Key::Equals, false),        '+' => (Key::Equals, true),
+        '[' => (Key::LeftBracket, false),   '{' => (Key::LeftBracket, true),
+        ']' => (Key::RightBracket, false),  '}' => (Key::RightBracket, true),
+        '\\' => (Key::Backslash, false),    '|' => (Key::Backslash, true),
+        ';' => (Key::Semicolon, false),     ':' => (Key::Semicolon, true),
+        '\'' => (Key::Apostrophe, false),   '"' => (Key::Apostrophe, true),
+        '`' => (Key::Backquote, false),     '~' => (Key::Backquote, true),
+        ',' => (Key::Comma, false),         '<' => (Key::Comma, true),
+        '.' => (Key::Period, false),        '>' => (Key::Period, true),
+        '/' => (Key::Slash, false),         '?' => (Key::Slash, true),
+        ' ' => (Key::Space, false),
+        '\n' | '\r' => (Key::Enter, false),
+        '\t' => (Key::Tab, false),
+        _ => return None,
+    };
+    Some(typed)
+}
+
+fn letter_key(c: char) -> Option<Key> {
+    #[rustfmt::skip]
+    const LETTERS: [Key; 26] = [
+        Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L, Key::M,
+        Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    ];
+    LETTERS.get((c as usize).wrapping_sub('a' as usize)).copied()
+}
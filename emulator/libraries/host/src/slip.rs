@@ -0,0 +1,103 @@
+use std::collections::VecDeque;
+
+use crate::traits::{Network, Tty};
+
+
+const END: u8 = 0xC0;
+const ESC: u8 = 0xDB;
+const ESC_END: u8 = 0xDC;
+const ESC_ESC: u8 = 0xDD;
+
+/// The largest packet that will be accepted from the machine, which is the largest an IPv4 packet can be
+const MAX_PACKET: usize = 65535;
+
+/// A tty that speaks SLIP (RFC 1055) to the emulated machine, so that a serial port can be its network interface.
+/// The bytes written by the machine are decoded into packets for the network, and the packets received from the
+/// network are encoded for the machine to read
+pub struct SlipTty {
+    network: Box<dyn Network>,
+    /// The packet being decoded from the bytes written by the machine
+    packet: Vec<u8>,
+    escaped: bool,
+    /// Packets larger than the maximum are dropped, up until the next END
+    overflowed: bool,
+    /// The encoded bytes of the received packets, which are waiting to be read by the machine
+    encoded: VecDeque<u8>,
+}
+
+impl SlipTty {
+    pub fn new(network: Box<dyn Network>) -> Self {
+        Self {
+            network,
+            packet: vec![],
+            escaped: false,
+            overflowed: false,
+            encoded: VecDeque::new(),
+        }
+    }
+
+    fn encode(&mut self, packet: &[u8]) {
+        // Starting with END flushes any line noise that came before the packet
+        self.encoded.push_back(END);
+        for byte in packet {
+            match *byte {
+                END => self.encoded.extend([ESC, ESC_END]),
+                ESC => self.encoded.extend([ESC, ESC_ESC]),
+                byte => self.encoded.push_back(byte),
+            }
+        }
+        self.encoded.push_back(END);
+    }
+
+    fn end_packet(&mut self) {
+        if !self.packet.is_empty() && !self.overflowed {
+            self.network.send(&self.packet);
+        }
+        self.packet.clear();
+        self.escaped = false;
+        self.overflowed = false;
+    }
+}
+
+impl Tty for SlipTty {
+    fn device_name(&self) -> String {
+        format!("slip:{}", self.network.device_name())
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        if self.encoded.is_empty() {
+            let packet = self.network.receive()?;
+            self.encode(&packet);
+        }
+        self.encoded.pop_front()
+    }
+
+    fn write(&mut self, output: u8) -> bool {
+        let byte = match (self.escaped, output) {
+            (false, END) => {
+                self.end_packet();
+                return true;
+            },
+            (false, ESC) => {
+                self.escaped = true;
+                return true;
+            },
+            (true, ESC_END) => END,
+            (true, ESC_ESC) => ESC,
+            // A protocol violation, which RFC 1055 says to leave in the packet
+            (_, byte) => byte,
+        };
+        self.escaped = false;
+
+        if self.packet.len() < MAX_PACKET {
+            self.packet.push(byte);
+        } else {
+            self.overflowed = true;
+        }
+        true
+    }
+
+    fn on_input(&mut self, notify: Box<dyn Fn() + Send>) -> bool {
+        self.network.on_receive(notify)
+    }
+}
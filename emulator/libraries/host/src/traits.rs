@@ -7,9 +7,9 @@ use femtos::Instant;
 use crate::gfx::FrameReceiver;
 use crate::audio::Sample;
 use crate::keys::KeyEvent;
-use crate::controllers::ControllerEvent;
+use crate::controllers::{ControllerEvent, ControllerFeedback};
 use crate::mouse::MouseEvent;
-use crate::input::EventSender;
+use crate::input::{EventSender, EventReceiver};
 
 #[derive(Clone, Debug, thiserror::Error)]
 pub enum HostError<E> {
@@ -17,8 +17,10 @@ pub enum HostError<E> {
     VideoSourceNotSupported,
     AudioSourceNotSupported,
     ControllerNotSupported,
+    ControllerFeedbackNotSupported,
     KeyboardNotSupported,
     MouseNotSupported,
+    NetworkNotSupported,
     #[from(E)]
     Specific(E),
 }
@@ -34,8 +36,10 @@ where
             HostError::VideoSourceNotSupported => write!(f, "This frontend doesn't support windows"),
             HostError::AudioSourceNotSupported => write!(f, "This frontend doesn't support the sound"),
             HostError::ControllerNotSupported => write!(f, "This frontend doesn't support game controllers"),
+            HostError::ControllerFeedbackNotSupported => write!(f, "This frontend doesn't support controller feedback"),
             HostError::KeyboardNotSupported => write!(f, "This frontend doesn't support the keyboard"),
             HostError::MouseNotSupported => write!(f, "This frontend doesn't support the mouse"),
+            HostError::NetworkNotSupported => write!(f, "This frontend doesn't support networking"),
             HostError::Specific(err) => write!(f, "{}", err),
         }
     }
@@ -48,10 +52,22 @@ pub trait Host {
         Err(HostError::TTYNotSupported)
     }
 
+    /// Add a tty for the named serial port, which the frontend can connect to a pty, a socket, or a pipe, depending
+    /// on how it was configured for that port
+    fn add_serial_port(&self, _name: &str) -> Result<Box<dyn Tty>, HostError<Self::Error>> {
+        self.add_pty()
+    }
+
     fn add_video_source(&mut self, _receiver: FrameReceiver) -> Result<(), HostError<Self::Error>> {
         Err(HostError::VideoSourceNotSupported)
     }
 
+    /// Add another video output alongside the main display, such as a debug view of a video chip's memory, which
+    /// the frontend shows in its own window.  An error means the view won't be shown, so it needn't be drawn
+    fn add_named_video_source(&mut self, _name: &str, _receiver: FrameReceiver) -> Result<(), HostError<Self::Error>> {
+        Err(HostError::VideoSourceNotSupported)
+    }
+
     fn add_audio_source(&mut self) -> Result<Box<dyn Audio>, HostError<Self::Error>> {
         Err(HostError::AudioSourceNotSupported)
     }
@@ -60,6 +76,11 @@ pub trait Host {
         Err(HostError::ControllerNotSupported)
     }
 
+    /// Register a queue of rumble and LED outputs which the frontend will apply to the physical controllers
+    fn register_controller_feedback(&mut self, _receiver: EventReceiver<ControllerFeedback>) -> Result<(), HostError<Self::Error>> {
+        Err(HostError::ControllerFeedbackNotSupported)
+    }
+
     fn register_keyboard(&mut self, _sender: EventSender<KeyEvent>) -> Result<(), HostError<Self::Error>> {
         Err(HostError::KeyboardNotSupported)
     }
@@ -67,6 +88,11 @@ pub trait Host {
     fn register_mouse(&mut self, _sender: EventSender<MouseEvent>) -> Result<(), HostError<Self::Error>> {
         Err(HostError::MouseNotSupported)
     }
+
+    /// Add a connection to the host's network for the named network interface of the machine
+    fn add_network(&self, _name: &str) -> Result<Box<dyn Network>, HostError<Self::Error>> {
+        Err(HostError::NetworkNotSupported)
+    }
 }
 
 
@@ -74,9 +100,32 @@ pub trait Tty {
     fn device_name(&self) -> String;
     fn read(&mut self) -> Option<u8>;
     fn write(&mut self, output: u8) -> bool;
+
+    /// Call the given function, from any thread, whenever new input arrives, so the device reading from the tty
+    /// can be woken up instead of polling.  Returns false if the tty can't notify, and must be polled instead
+    fn on_input(&mut self, _notify: Box<dyn Fn() + Send>) -> bool {
+        false
+    }
 }
 
-pub trait Audio {
+/// A link that carries IPv4 packets between the emulated machine and the host's network
+pub trait Network {
+    fn device_name(&self) -> String;
+    /// Send a packet from the emulated machine to the network
+    fn send(&mut self, packet: &[u8]);
+    /// Returns the next packet from the network for the emulated machine, if one has arrived
+    fn receive(&mut self) -> Option<Vec<u8>>;
+
+    /// Call the given function, from any thread, whenever a packet arrives.  Returns false if the network can't
+    /// notify, and must be polled instead
+    fn on_receive(&mut self, _notify: Box<dyn Fn() + Send>) -> bool {
+        false
+    }
+}
+
+/// A source of audio samples, which can be sent to another thread so that the device producing them can be
+/// stepped on its own thread
+pub trait Audio: Send {
     fn samples_per_second(&self) -> usize;
     fn write_samples(&mut self, clock: Instant, buffer: &[Sample]);
 }
@@ -99,6 +148,24 @@ impl<T: Clone> ClockedQueue<T> {
         queue.push_back((clock, data));
     }
 
+    /// Add an item, and if the oldest item has to be dropped to stay within the limit, combine it into the item
+    /// that follows it using the given function
+    pub fn push_merging<F>(&self, clock: Instant, mut data: T, merge: F)
+    where
+        F: FnOnce(T, &mut T),
+    {
+        let mut queue = self.0.lock().unwrap();
+        if queue.len() > self.1 {
+            if let Some((_, dropped)) = queue.pop_front() {
+                match queue.front_mut() {
+                    Some((_, next)) => merge(dropped, next),
+                    None => merge(dropped, &mut data),
+                }
+            }
+        }
+        queue.push_back((clock, data));
+    }
+
     pub fn pop_next(&self) -> Option<(Instant, T)> {
         self.0.lock().unwrap().pop_front()
     }
@@ -1,5 +1,11 @@
+use std::fs;
 use std::str::Chars;
 use std::iter::Peekable;
+use std::path::PathBuf;
+
+
+/// The maximum depth of nested `.include` directives, to catch files that include themselves
+const MAX_INCLUDE_DEPTH: usize = 16;
 
 
 pub struct ParserError(pub String);
@@ -12,12 +18,12 @@ impl ParserError {
 
 #[derive(Debug)]
 pub enum AssemblyLine {
-    Directive(String, Vec<String>),
+    Directive(String, Vec<AssemblyOperand>),
     Label(String),
     Instruction(String, Vec<AssemblyOperand>),
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum AssemblyOperand {
     Register(String),
     Indirect(Vec<AssemblyOperand>),
@@ -25,33 +31,156 @@ pub enum AssemblyOperand {
     IndirectPost(Vec<AssemblyOperand>, String),
     Immediate(usize),
     Label(String),
+    /// An expression that refers to labels, which can only be evaluated by the assembler
+    Expression(Expression),
+    String(String),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Xor,
+}
+
+impl BinaryOperator {
+    fn from_token(token: &str) -> Option<Self> {
+        match token {
+            "+" => Some(BinaryOperator::Add),
+            "-" => Some(BinaryOperator::Subtract),
+            "*" => Some(BinaryOperator::Multiply),
+            "/" => Some(BinaryOperator::Divide),
+            "<" => Some(BinaryOperator::ShiftLeft),
+            ">" => Some(BinaryOperator::ShiftRight),
+            "&" => Some(BinaryOperator::And),
+            "^" => Some(BinaryOperator::Xor),
+            _ => None,
+        }
+    }
+
+    fn precedence(&self) -> usize {
+        match self {
+            BinaryOperator::Multiply | BinaryOperator::Divide => 5,
+            BinaryOperator::Add | BinaryOperator::Subtract => 4,
+            BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight => 3,
+            BinaryOperator::And => 2,
+            BinaryOperator::Xor => 1,
+        }
+    }
+}
+
+/// An arithmetic expression of numbers and labels, using wrapping arithmetic like the assembler's addresses
+///
+/// Since `|` starts a comment, `%` starts a register, and parentheses are used for indirect operands, there is
+/// no bitwise or, no modulo, and no grouping other than by the precedence of the operators
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expression {
+    Number(usize),
+    Label(String),
+    Negate(Box<Expression>),
+    Not(Box<Expression>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    /// Evaluate the expression, using `lookup` to get the value of each label
+    pub fn evaluate<F>(&self, lineno: usize, lookup: &mut F) -> Result<usize, ParserError>
+    where
+        F: FnMut(&str) -> Option<usize>,
+    {
+        match self {
+            Expression::Number(value) => Ok(*value),
+            Expression::Label(name) => {
+                lookup(name).ok_or_else(|| ParserError::new(format!("error at line {}: label undefined {:?}", lineno, name)))
+            },
+            Expression::Negate(expr) => Ok(expr.evaluate(lineno, lookup)?.wrapping_neg()),
+            Expression::Not(expr) => Ok(!expr.evaluate(lineno, lookup)?),
+            Expression::Binary(op, left, right) => {
+                let left = left.evaluate(lineno, lookup)?;
+                let right = right.evaluate(lineno, lookup)?;
+                match op {
+                    BinaryOperator::Add => Ok(left.wrapping_add(right)),
+                    BinaryOperator::Subtract => Ok(left.wrapping_sub(right)),
+                    BinaryOperator::Multiply => Ok(left.wrapping_mul(right)),
+                    BinaryOperator::Divide if right == 0 => {
+                        Err(ParserError::new(format!("error at line {}: division by zero", lineno)))
+                    },
+                    BinaryOperator::Divide => Ok(left / right),
+                    BinaryOperator::ShiftLeft => Ok(left.checked_shl(right as u32).unwrap_or(0)),
+                    BinaryOperator::ShiftRight => Ok(left.checked_shr(right as u32).unwrap_or(0)),
+                    BinaryOperator::And => Ok(left & right),
+                    BinaryOperator::Xor => Ok(left ^ right),
+                }
+            },
+        }
+    }
 }
 
 
 pub struct AssemblyParser<'input> {
     lexer: AssemblyLexer<'input>,
+    include_dir: Option<PathBuf>,
+    depth: usize,
 }
 
 impl<'input> AssemblyParser<'input> {
     pub fn new(input: &'input str) -> Self {
         Self {
             lexer: AssemblyLexer::new(input),
+            include_dir: None,
+            depth: 0,
         }
     }
 
+    /// Set the directory that the files named by `.include` directives are relative to, instead of the current directory
+    pub fn with_include_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.include_dir = dir;
+        self
+    }
+
     pub fn parse(&mut self) -> Result<Vec<(usize, AssemblyLine)>, ParserError> {
         let mut output = vec![];
         loop {
             let lineno = self.lexer.get_next_lineno();
-            if let Some(line) = self.parse_line()? {
-                output.push((lineno, line));
-            } else {
-                break;
+            match self.parse_line()? {
+                Some(AssemblyLine::Directive(name, args)) if name == "include" => {
+                    output.extend(self.parse_include(lineno, &args)?);
+                },
+                Some(line) => output.push((lineno, line)),
+                None => break,
             }
         }
         Ok(output)
     }
 
+    fn parse_include(&mut self, lineno: usize, args: &[AssemblyOperand]) -> Result<Vec<(usize, AssemblyLine)>, ParserError> {
+        let filename = match args {
+            [AssemblyOperand::String(filename)] => filename,
+            _ => return Err(ParserError::new(format!("error at line {}: expected a filename for .include", lineno))),
+        };
+        if self.depth >= MAX_INCLUDE_DEPTH {
+            return Err(ParserError::new(format!("error at line {}: includes are nested too deeply", lineno)));
+        }
+
+        let path = match &self.include_dir {
+            Some(dir) => dir.join(filename),
+            None => PathBuf::from(filename),
+        };
+        let text = fs::read_to_string(&path)
+            .map_err(|err| ParserError::new(format!("error at line {}: unable to include {:?}: {}", lineno, path, err)))?;
+
+        let mut parser = AssemblyParser::new(&text).with_include_dir(self.include_dir.clone());
+        parser.depth = self.depth + 1;
+        parser
+            .parse()
+            .map_err(|err| ParserError::new(format!("in {:?} included at line {}: {}", path, lineno, err.0)))
+    }
+
     fn parse_line(&mut self) -> Result<Option<AssemblyLine>, ParserError> {
         let token = loop {
             match self.lexer.get_next() {
@@ -68,7 +197,7 @@ impl<'input> AssemblyParser<'input> {
         let result = match token.as_str() {
             "." => {
                 let name = self.lexer.expect_next()?;
-                let list = self.parse_list_of_words()?;
+                let list = self.parse_list_of_operands()?;
                 AssemblyLine::Directive(name, list)
             },
             word if word.chars().next().map(is_word).unwrap_or(false) => {
@@ -94,26 +223,6 @@ impl<'input> AssemblyParser<'input> {
         Ok(Some(result))
     }
 
-    fn parse_list_of_words(&mut self) -> Result<Vec<String>, ParserError> {
-        let mut list = vec![];
-
-        // If we're already at the end of the line, then it's an empty list, so return
-        let next = self.lexer.peek();
-        if next.is_none() || next.as_ref().unwrap() == "\n" {
-            return Ok(list);
-        }
-
-        loop {
-            list.push(self.lexer.expect_next()?);
-
-            let next = self.lexer.peek();
-            if next.is_none() || next.as_ref().unwrap() != "," {
-                return Ok(list);
-            }
-            self.lexer.expect_next()?;
-        }
-    }
-
     fn parse_list_of_operands(&mut self) -> Result<Vec<AssemblyOperand>, ParserError> {
         let mut list = vec![];
 
@@ -153,27 +262,82 @@ impl<'input> AssemblyParser<'input> {
                 }
                 Ok(AssemblyOperand::Indirect(list))
             },
-            "+" | "-" => {
+            "+" | "-" if self.lexer.peek().as_deref() == Some("(") => {
                 self.lexer.expect_token("(")?;
                 let list = self.parse_list_of_operands()?;
                 self.lexer.expect_token(")")?;
                 Ok(AssemblyOperand::IndirectPre(token, list))
             },
             "#" => {
-                let string = self.lexer.expect_next()?;
-                let number = parse_any_number(self.lexer.lineno(), &string)?;
-                Ok(AssemblyOperand::Immediate(number))
+                let token = self.lexer.expect_next()?;
+                self.parse_expression_operand(token)
             },
-            _ => {
-                if is_digit(token.chars().next().unwrap()) {
-                    let number = parse_any_number(self.lexer.lineno(), &token)?;
-                    Ok(AssemblyOperand::Immediate(number))
-                } else {
-                    Ok(AssemblyOperand::Label(token))
-                }
+            _ if token.starts_with('"') => Ok(AssemblyOperand::String(token[1..].to_string())),
+            _ => self.parse_expression_operand(token),
+        }
+    }
+
+    /// Parse an expression, which is reduced to a number or a label where possible
+    fn parse_expression_operand(&mut self, token: String) -> Result<AssemblyOperand, ParserError> {
+        let lineno = self.lexer.lineno();
+        let expr = self.parse_unary(token)?;
+        let expr = self.parse_binary(expr, 0)?;
+
+        if let Ok(value) = expr.evaluate(lineno, &mut |_| None) {
+            Ok(AssemblyOperand::Immediate(value))
+        } else if let Expression::Label(name) = expr {
+            Ok(AssemblyOperand::Label(name))
+        } else {
+            Ok(AssemblyOperand::Expression(expr))
+        }
+    }
+
+    fn parse_unary(&mut self, token: String) -> Result<Expression, ParserError> {
+        match token.as_str() {
+            "-" => Ok(Expression::Negate(Box::new(self.parse_next_unary()?))),
+            "~" => Ok(Expression::Not(Box::new(self.parse_next_unary()?))),
+            "+" => self.parse_next_unary(),
+            _ if token.starts_with('\'') => match token[1..].chars().next() {
+                Some(ch) => Ok(Expression::Number(ch as usize)),
+                None => Err(ParserError::new(format!("parse error at line {}: empty character constant", self.lexer.lineno()))),
             },
+            _ if is_digit(token.chars().next().unwrap()) => Ok(Expression::Number(parse_any_number(self.lexer.lineno(), &token)?)),
+            _ if is_word(token.chars().next().unwrap()) => Ok(Expression::Label(token)),
+            _ => Err(ParserError::new(format!(
+                "parse error at line {}: expected a value, found {:?}",
+                self.lexer.lineno(),
+                token
+            ))),
         }
     }
+
+    fn parse_next_unary(&mut self) -> Result<Expression, ParserError> {
+        let token = self.lexer.expect_next()?;
+        self.parse_unary(token)
+    }
+
+    fn parse_binary(&mut self, mut left: Expression, min_precedence: usize) -> Result<Expression, ParserError> {
+        while let Some(op) = self.peek_operator().filter(|op| op.precedence() >= min_precedence) {
+            self.lexer.expect_next()?;
+            // Shifts are written as two tokens, `<<` and `>>`
+            match op {
+                BinaryOperator::ShiftLeft => self.lexer.expect_token("<")?,
+                BinaryOperator::ShiftRight => self.lexer.expect_token(">")?,
+                _ => {},
+            }
+
+            let mut right = self.parse_next_unary()?;
+            while let Some(next) = self.peek_operator().filter(|next| next.precedence() > op.precedence()) {
+                right = self.parse_binary(right, next.precedence())?;
+            }
+            left = Expression::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn peek_operator(&mut self) -> Option<BinaryOperator> {
+        self.lexer.peek().and_then(|token| BinaryOperator::from_token(&token))
+    }
 }
 
 fn parse_any_number(lineno: usize, string: &str) -> Result<usize, ParserError> {
@@ -226,13 +390,29 @@ impl<'input> AssemblyLexer<'input> {
         let ch = self.chars.next()?;
         let mut string = ch.to_string();
 
-        if is_word(ch) {
+        // Strings and character constants are returned with their opening quote, to tell them apart from words
+        if ch == '"' {
+            while let Some(ch) = self.chars.next_if(|ch| *ch != '"' && *ch != '\n') {
+                string.push(self.read_escape(ch));
+            }
+            self.chars.next_if(|ch| *ch == '"');
+        } else if ch == '\'' {
+            if let Some(ch) = self.chars.next_if(|ch| *ch != '\'' && *ch != '\n') {
+                string.push(self.read_escape(ch));
+            }
+            self.chars.next_if(|ch| *ch == '\'');
+        } else if is_word(ch) {
             while let Some(ch) = self.chars.next_if(|ch| is_word(*ch) || *ch == '.') {
                 // Ignore periods in words
                 if ch != '.' {
                     string.push(ch);
                 }
             }
+
+            // Allow a trailing quote for the names of the Z80's shadow registers, such as `af'`
+            if let Some(ch) = self.chars.next_if(|ch| *ch == '\'') {
+                string.push(ch);
+            }
         }
 
         Some(string)
@@ -271,7 +451,7 @@ impl<'input> AssemblyLexer<'input> {
 
     fn eat_whitespace(&mut self) {
         while let Some(ch) = self.chars.peek() {
-            if *ch == '|' {
+            if *ch == '|' || *ch == ';' {
                 self.read_until('\n')
             } else if *ch == '/' {
                 self.chars.next();
@@ -295,6 +475,20 @@ impl<'input> AssemblyLexer<'input> {
         }
     }
 
+    fn read_escape(&mut self, ch: char) -> char {
+        if ch != '\\' {
+            return ch;
+        }
+        match self.chars.next_if(|ch| *ch != '\n') {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some(ch) => ch,
+            None => '\\',
+        }
+    }
+
     fn read_until(&mut self, test: char) {
         while let Some(ch) = self.chars.peek() {
             if *ch == test {
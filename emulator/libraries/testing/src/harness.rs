@@ -0,0 +1,234 @@
+use std::fmt;
+
+use femtos::{Instant, Duration};
+
+use moa_core::{System, Error, EmulatorErrorKind, Address, Addressable, Capabilities};
+use moa_host::{ControllerEvent, KeyEvent, EventSender, Frame};
+
+use crate::host::TestHost;
+
+
+/// How much simulated time to run between checks of the condition, by default
+const DEFAULT_INTERVAL_US: u64 = 1_000;
+
+
+/// What to wait for when running a machine
+pub enum Condition {
+    /// A cpu's program counter reaches the address, which is checked before every instruction
+    PcReaches(Address),
+    /// The memory at the address contains the given bytes
+    MemoryEquals(Address, Vec<u8>),
+    /// A frame is output with the given CRC, as calculated by [`Frame::crc32`]
+    FrameCrc(u32),
+    /// The given number of frames have been output
+    Frames(usize),
+    /// The function returns true, which is checked between each run of simulated time
+    Custom(Box<dyn FnMut(&mut System) -> bool>),
+}
+
+impl fmt::Debug for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::PcReaches(addr) => write!(f, "PcReaches({:#010x})", addr),
+            Condition::MemoryEquals(addr, data) => write!(f, "MemoryEquals({:#010x}, {:02x?})", addr, data),
+            Condition::FrameCrc(crc) => write!(f, "FrameCrc({:#010x})", crc),
+            Condition::Frames(count) => write!(f, "Frames({})", count),
+            Condition::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+/// How a run of the machine ended
+#[derive(Clone, Debug)]
+pub enum Outcome {
+    /// The condition was met
+    Reached,
+    /// The simulated time ran out before the condition was met
+    TimedOut,
+    /// The guest program asked to stop the simulation with the given exit code
+    Exited(i32),
+    /// The simulation stopped with an error, or a breakpoint that wasn't part of the condition
+    Failed(Error),
+}
+
+/// The result of running a machine until a condition
+#[derive(Clone)]
+pub struct TestResult {
+    pub condition: String,
+    pub outcome: Outcome,
+    /// The simulated time when the run ended
+    pub clock: Instant,
+    /// The number of frames that were output during the run
+    pub frames: usize,
+    /// The last frame that was output since the harness was created
+    pub last_frame: Option<Frame>,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        matches!(self.outcome, Outcome::Reached)
+    }
+
+    /// Returns the CRC of the last frame, which can be used as the expected value of a `FrameCrc` condition
+    pub fn last_frame_crc(&self) -> Option<u32> {
+        self.last_frame.as_ref().map(Frame::crc32)
+    }
+}
+
+impl fmt::Display for TestResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.clock.as_duration().as_nanos() as f64 / 1_000_000_000.0;
+        match &self.outcome {
+            Outcome::Reached => write!(f, "{} was reached", self.condition)?,
+            Outcome::TimedOut => write!(f, "timed out waiting for {}", self.condition)?,
+            Outcome::Exited(code) => write!(f, "guest exited with code {} while waiting for {}", code, self.condition)?,
+            Outcome::Failed(err) => write!(f, "error while waiting for {}: {}", self.condition, err)?,
+        }
+        write!(f, " after {:.3}s and {} frames", seconds, self.frames)?;
+        if let Some(crc) = self.last_frame_crc() {
+            write!(f, " (last frame crc {:#010x})", crc)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for TestResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// Runs a machine without a frontend until conditions are met
+pub struct Harness {
+    system: System,
+    host: TestHost,
+    interval: Duration,
+    last_frame: Option<Frame>,
+}
+
+impl Harness {
+    /// Build the machine using a [`TestHost`]
+    pub fn new<F>(build: F) -> Result<Self, Error>
+    where
+        F: FnOnce(&mut TestHost) -> Result<System, Error>,
+    {
+        let mut host = TestHost::default();
+        let system = build(&mut host)?;
+        Ok(Self {
+            system,
+            host,
+            interval: Duration::from_micros(DEFAULT_INTERVAL_US),
+            last_frame: None,
+        })
+    }
+
+    /// Set how much simulated time to run between checks of the conditions, other than `PcReaches`
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn system(&mut self) -> &mut System {
+        &mut self.system
+    }
+
+    pub fn controllers(&self) -> Option<&EventSender<ControllerEvent>> {
+        self.host.controllers.as_ref()
+    }
+
+    pub fn keyboard(&self) -> Option<&EventSender<KeyEvent>> {
+        self.host.keyboard.as_ref()
+    }
+
+    /// Returns the last frame that was output since the harness was created
+    pub fn last_frame(&self) -> Option<&Frame> {
+        self.last_frame.as_ref()
+    }
+
+    /// Run the machine until the condition is met or `timeout` amount of simulated time has passed
+    pub fn run_until(&mut self, mut condition: Condition, timeout: Duration) -> TestResult {
+        let description = format!("{:?}", condition);
+        let deadline = self.system.clock + timeout;
+
+        if let Condition::PcReaches(addr) = condition {
+            self.set_breakpoints(addr, true);
+        }
+
+        let mut frames = 0;
+        let outcome = loop {
+            if self.system.clock >= deadline {
+                break Outcome::TimedOut;
+            }
+
+            let remaining = deadline.duration_since(self.system.clock);
+            let interval = if remaining < self.interval { remaining } else { self.interval };
+            let result = self.system.run_for_duration(interval);
+
+            // Frames are checked first, since the run might have stopped just after the frame being waited for
+            let mut frame_matched = false;
+            while let Some((_, frame)) = self.host.video.as_ref().and_then(|video| video.next()) {
+                frames += 1;
+                if let Condition::FrameCrc(crc) = condition {
+                    frame_matched |= frame.crc32() == crc;
+                }
+                self.last_frame = Some(frame);
+            }
+            if frame_matched {
+                break Outcome::Reached;
+            }
+
+            match result {
+                Ok(()) => {},
+                Err(Error::Breakpoint(info)) => match condition {
+                    Condition::PcReaches(addr) if info.pc == Some(addr) && !info.watchpoint => break Outcome::Reached,
+                    _ => break Outcome::Failed(Error::Breakpoint(info)),
+                },
+                Err(Error::Emulator(EmulatorErrorKind::Exit(code), _)) => break Outcome::Exited(code),
+                Err(err) => break Outcome::Failed(err),
+            }
+
+            let reached = match &mut condition {
+                Condition::PcReaches(_) | Condition::FrameCrc(_) => false,
+                Condition::MemoryEquals(addr, expected) => {
+                    let mut data = vec![0; expected.len()];
+                    if let Err(err) = self.system.get_bus().read(self.system.clock, *addr, &mut data) {
+                        break Outcome::Failed(err);
+                    }
+                    data == *expected
+                },
+                Condition::Frames(count) => frames >= *count,
+                Condition::Custom(func) => func(&mut self.system),
+            };
+            if reached {
+                break Outcome::Reached;
+            }
+        };
+
+        if let Condition::PcReaches(addr) = condition {
+            self.set_breakpoints(addr, false);
+        }
+
+        log::debug!("harness: run ended with {:?} at {:?}", outcome, self.system.clock);
+        TestResult {
+            condition: description,
+            outcome,
+            clock: self.system.clock,
+            frames,
+            last_frame: self.last_frame.clone(),
+        }
+    }
+
+    /// Add or remove the harness's own breakpoint on every cpu.  Breakpoints are counted, so removing it leaves any
+    /// breakpoint that was already set at the same address
+    fn set_breakpoints(&mut self, addr: Address, enable: bool) {
+        for (_, device) in self.system.devices_with(Capabilities::DEBUGGABLE) {
+            if let Some(debuggable) = device.borrow_mut().as_debuggable() {
+                if enable {
+                    debuggable.add_breakpoint(addr);
+                } else {
+                    debuggable.remove_breakpoint(addr);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use moa_core::Error;
+use moa_host::{Host, HostError, Audio, DummyAudio, FrameReceiver, PixelEncoding, ControllerEvent, KeyEvent, MouseEvent, EventSender};
+
+
+/// A host which keeps the machine's video output and input queues so that a test can use them, and discards
+/// the audio
+#[derive(Default)]
+pub struct TestHost {
+    pub video: Option<FrameReceiver>,
+    pub controllers: Option<EventSender<ControllerEvent>>,
+    pub keyboard: Option<EventSender<KeyEvent>>,
+    pub mouse: Option<EventSender<MouseEvent>>,
+}
+
+impl Host for TestHost {
+    type Error = Error;
+
+    fn add_video_source(&mut self, receiver: FrameReceiver) -> Result<(), HostError<Self::Error>> {
+        if self.video.is_some() {
+            return Err(HostError::Specific(Error::new("Only one video source can be registered with the test host")));
+        }
+        receiver.request_encoding(PixelEncoding::RGBA);
+        self.video = Some(receiver);
+        Ok(())
+    }
+
+    fn add_audio_source(&mut self) -> Result<Box<dyn Audio>, HostError<Self::Error>> {
+        Ok(Box::new(DummyAudio()))
+    }
+
+    fn register_controllers(&mut self, sender: EventSender<ControllerEvent>) -> Result<(), HostError<Self::Error>> {
+        self.controllers = Some(sender);
+        Ok(())
+    }
+
+    fn register_keyboard(&mut self, sender: EventSender<KeyEvent>) -> Result<(), HostError<Self::Error>> {
+        self.keyboard = Some(sender);
+        Ok(())
+    }
+
+    fn register_mouse(&mut self, sender: EventSender<MouseEvent>) -> Result<(), HostError<Self::Error>> {
+        self.mouse = Some(sender);
+        Ok(())
+    }
+}
@@ -0,0 +1,17 @@
+//! A harness for running whole machines without a frontend, such as in regression tests
+//!
+//! A machine is built with a [`TestHost`], which keeps the video output and input queues instead of displaying
+//! them, and then a [`Harness`] runs it until a [`Condition`] is met or the simulated time runs out, and returns
+//! a [`TestResult`] describing how the run ended.
+//!
+//! ```ignore
+//! let mut harness = Harness::new(|host| build_genesis(host, options))?;
+//! let result = harness.run_until(Condition::FrameCrc(0x1234abcd), Duration::from_secs(10));
+//! assert!(result.passed(), "{}", result);
+//! ```
+
+mod harness;
+mod host;
+
+pub use crate::harness::{Harness, Condition, Outcome, TestResult};
+pub use crate::host::TestHost;
@@ -0,0 +1,206 @@
+use femtos::{Instant, Duration, Frequency};
+
+use moa_core::{System, Error, Address, Addressable, Capabilities, Steppable, Transmutable, MemoryBlock, Device};
+use moa_host::{Host, Frame, FrameSender, Pixel, PixelEncoding, frame_queue};
+use moa_m68k::{M68k, M68kType};
+use moa_testing::{Harness, Condition, Outcome, TestHost};
+
+const COUNTER_ADDR: Address = 0x1000;
+const EXIT_ADDR: Address = 0x3000;
+const LOOP_ADDR: Address = 0x0402;
+const DONE_ADDR: Address = 0x0410;
+
+/// The reset vectors followed by a program that counts to 0x20 in memory, and then exits with code 3
+#[rustfmt::skip]
+const PROGRAM: &[(Address, &[u16])] = &[
+    (0x0000, &[0x0000, 0x2000, 0x0000, 0x0400]),    // initial stack pointer and pc
+    (0x0400, &[
+        0x7000,                                     // moveq #0, %d0
+        0x5280,                                     // loop: addq.l #1, %d0
+        0x23C0, 0x0000, 0x1000,                     // move.l %d0, $1000
+        0x0C40, 0x0020,                             // cmpi.w #$20, %d0
+        0x66F2,                                     // bne.s loop
+        0x13FC, 0x0003, 0x0000, 0x3000,             // done: move.b #3, $3000
+        0x60FE,                                     // bra.s .
+    ]),
+];
+
+/// A register that stops the simulation with the exit code written to it, on its next step
+#[derive(Default)]
+struct ExitRegister {
+    code: Option<u8>,
+}
+
+impl Addressable for ExitRegister {
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, _clock: Instant, _addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        data[0] = 0;
+        Ok(())
+    }
+
+    fn write(&mut self, _clock: Instant, _addr: Address, data: &[u8]) -> Result<(), Error> {
+        self.code = Some(data[0]);
+        Ok(())
+    }
+}
+
+impl Steppable for ExitRegister {
+    fn step(&mut self, _system: &System) -> Result<Duration, Error> {
+        match self.code {
+            Some(code) => Err(Error::exit(code as i32)),
+            None => Ok(Duration::from_micros(10)),
+        }
+    }
+}
+
+impl Transmutable for ExitRegister {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+}
+
+/// A video device that outputs a frame every millisecond, filled with a shade that's the number of the frame
+struct Screen {
+    sender: FrameSender,
+    count: u8,
+}
+
+impl Steppable for Screen {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        let mut frame = Frame::new(4, 4, self.sender.encoding());
+        frame.clear(Pixel::Rgb(self.count, self.count, self.count));
+        self.sender.add(system.clock, frame);
+        self.count += 1;
+        Ok(Duration::from_millis(1))
+    }
+}
+
+impl Transmutable for Screen {
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+}
+
+fn build_system(host: &mut TestHost, screen: bool) -> Result<System, Error> {
+    let mut system = System::default();
+
+    let mut ram = MemoryBlock::new(vec![0; 0x2000]);
+    for (addr, words) in PROGRAM {
+        for (i, word) in words.iter().enumerate() {
+            ram.write(Instant::START, addr + (i * 2) as Address, &word.to_be_bytes())?;
+        }
+    }
+    system.add_addressable_device(0x0000, Device::new(ram))?;
+
+    // The program would exit long before a few frames are output, so it loops at the end instead
+    if screen {
+        system.add_addressable_device(EXIT_ADDR, Device::new(MemoryBlock::new(vec![0; 1])))?;
+
+        let (sender, receiver) = frame_queue(4, 4);
+        host.add_video_source(receiver)?;
+        system.add_device(
+            "screen",
+            Device::new(Screen {
+                sender,
+                count: 0,
+            }),
+        )?;
+    } else {
+        system.add_addressable_device(EXIT_ADDR, Device::new(ExitRegister::default()))?;
+    }
+
+    let cpu = M68k::from_type(M68kType::MC68000, Frequency::from_mhz(8));
+    system.add_interruptable_device("cpu", Device::new(cpu))?;
+    Ok(system)
+}
+
+fn harness(screen: bool) -> Harness {
+    Harness::new(|host| build_system(host, screen)).unwrap()
+}
+
+fn read_counter(harness: &mut Harness) -> u32 {
+    let system = harness.system();
+    let mut data = [0; 4];
+    system.get_bus().read(system.clock, COUNTER_ADDR, &mut data).unwrap();
+    u32::from_be_bytes(data)
+}
+
+#[test]
+fn pc_reaches_stops_before_the_instruction() {
+    let mut harness = harness(false);
+    let result = harness.run_until(Condition::PcReaches(DONE_ADDR), Duration::from_millis(10));
+    assert!(result.passed(), "{}", result);
+    assert_eq!(read_counter(&mut harness), 0x20);
+
+    // The harness's breakpoint is removed afterwards, so the program runs on to the exit
+    let result = harness.run_until(Condition::Frames(1), Duration::from_millis(10));
+    assert!(matches!(result.outcome, Outcome::Exited(3)), "{}", result);
+}
+
+#[test]
+fn pc_reaches_keeps_the_breakpoints_already_set() {
+    let mut harness = harness(false);
+    for (_, device) in harness.system().devices_with(Capabilities::DEBUGGABLE) {
+        device.borrow_mut().as_debuggable().unwrap().add_breakpoint(LOOP_ADDR);
+    }
+
+    // The first time the loop is reached, the harness's breakpoint and the one already set are both there
+    let result = harness.run_until(Condition::PcReaches(LOOP_ADDR), Duration::from_millis(10));
+    assert!(result.passed(), "{}", result);
+
+    let result = harness.run_until(Condition::PcReaches(DONE_ADDR), Duration::from_millis(10));
+    match result.outcome {
+        Outcome::Failed(Error::Breakpoint(info)) => assert_eq!(info.pc, Some(LOOP_ADDR)),
+        _ => panic!("expected to stop at the breakpoint already set, but {}", result),
+    }
+}
+
+#[test]
+fn memory_equals_checks_between_intervals() {
+    let mut harness = harness(false).with_interval(Duration::from_micros(1));
+    let result = harness.run_until(Condition::MemoryEquals(COUNTER_ADDR, vec![0, 0, 0, 0x10]), Duration::from_millis(10));
+    assert!(result.passed(), "{}", result);
+    assert_eq!(read_counter(&mut harness), 0x10);
+}
+
+#[test]
+fn frames_counts_the_frames_output() {
+    // The checks are made in between the frames, which are output every millisecond
+    let mut harness = harness(true).with_interval(Duration::from_micros(300));
+    let result = harness.run_until(Condition::Frames(3), Duration::from_millis(10));
+    assert!(result.passed(), "{}", result);
+    assert_eq!(result.frames, 3);
+
+    let mut expected = Frame::new(4, 4, PixelEncoding::RGBA);
+    expected.clear(Pixel::Rgb(2, 2, 2));
+    assert_eq!(result.last_frame_crc(), Some(expected.crc32()));
+
+    expected.clear(Pixel::Rgb(4, 4, 4));
+    let result = harness.run_until(Condition::FrameCrc(expected.crc32()), Duration::from_millis(10));
+    assert!(result.passed(), "{}", result);
+    assert_eq!(result.frames, 2);
+}
+
+#[test]
+fn timeout_ends_the_run() {
+    let mut harness = harness(false);
+    let result = harness.run_until(Condition::MemoryEquals(COUNTER_ADDR, vec![0xFF; 4]), Duration::from_micros(20));
+    assert!(matches!(result.outcome, Outcome::TimedOut), "{}", result);
+    assert!(!result.passed());
+    assert!(result.clock >= Instant::START + Duration::from_micros(20));
+}
+
+#[test]
+fn exit_ends_the_run_with_the_code() {
+    let mut harness = harness(false);
+    let result = harness.run_until(Condition::Custom(Box::new(|_| false)), Duration::from_millis(10));
+    assert!(matches!(result.outcome, Outcome::Exited(3)), "{}", result);
+    assert!(!result.passed());
+}
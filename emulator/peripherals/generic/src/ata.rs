@@ -1,7 +1,9 @@
 use std::fs;
+use std::io::{Seek, SeekFrom, Write};
+use std::collections::HashMap;
 use femtos::Instant;
 
-use moa_core::{Error, Address, Addressable, Transmutable};
+use moa_core::{Error, Address, Addressable, Transmutable, strict};
 
 #[rustfmt::skip]
 mod reg {
@@ -21,41 +23,408 @@ mod reg {
 
 #[rustfmt::skip]
 mod cmd {
+    pub(super) const RECALIBRATE: u8           = 0x10;
     pub(super) const READ_SECTORS: u8          = 0x20;
+    pub(super) const READ_SECTORS_NO_RETRY: u8 = 0x21;
     pub(super) const WRITE_SECTORS: u8         = 0x30;
+    pub(super) const WRITE_SECTORS_NO_RETRY: u8 = 0x31;
+    pub(super) const READ_VERIFY: u8           = 0x40;
+    pub(super) const DIAGNOSTIC: u8            = 0x90;
+    pub(super) const INIT_PARAMETERS: u8       = 0x91;
+    pub(super) const STANDBY_IMMEDIATE: u8     = 0xE0;
+    pub(super) const IDLE_IMMEDIATE: u8        = 0xE1;
+    pub(super) const CHECK_POWER_MODE: u8      = 0xE5;
+    pub(super) const FLUSH_CACHE: u8           = 0xE7;
     pub(super) const IDENTIFY: u8              = 0xEC;
     pub(super) const SET_FEATURE: u8           = 0xEF;
 }
 
-#[allow(dead_code)]
-const ATA_ST_BUSY: u8 = 0x80;
-#[allow(dead_code)]
-const ATA_ST_DATA_READY: u8 = 0x08;
-#[allow(dead_code)]
-const ATA_ST_ERROR: u8 = 0x01;
+#[rustfmt::skip]
+mod status {
+    pub(super) const READY: u8                 = 0x40;
+    pub(super) const SEEK_COMPLETE: u8         = 0x10;
+    pub(super) const DATA_REQUEST: u8          = 0x08;
+    pub(super) const ERROR: u8                 = 0x01;
+}
+
+#[rustfmt::skip]
+mod error {
+    pub(super) const ID_NOT_FOUND: u8          = 0x10;
+    pub(super) const ABORTED: u8               = 0x04;
+    /// The value of the error register after a diagnostic, which means there was no error
+    pub(super) const NO_ERROR_DETECTED: u8     = 0x01;
+}
+
+/// The bit of the drive/head register that selects LBA addressing instead of CHS
+const DRIVE_HEAD_LBA: u8 = 0x40;
+/// The bit of the drive/head register that selects the slave drive, which isn't present
+const DRIVE_HEAD_SLAVE: u8 = 0x10;
+
+/// The geometry reported for CHS addressing
+const ATA_HEADS: u32 = 16;
+const ATA_SECTORS_PER_TRACK: u32 = 63;
 
 const ATA_SECTOR_SIZE: u32 = 512;
 
 const DEV_NAME: &str = "ata";
 
+/// A file that stores the sectors written to the disk, so that the base image isn't modified.  It contains a record
+/// for each sector, made of the sector number as a little endian u32 followed by the sector's data
+struct AtaOverlay {
+    filename: String,
+    file: fs::File,
+    /// The offset of each sector's record in the file
+    records: HashMap<u32, u64>,
+    end: u64,
+}
+
+const OVERLAY_RECORD_SIZE: u64 = 4 + ATA_SECTOR_SIZE as u64;
+
+impl AtaOverlay {
+    /// Open the overlay file, creating it if it doesn't exist, and apply its sectors to the disk contents
+    fn open(filename: &str, contents: &mut [u8]) -> Result<Self, Error> {
+        let existing = fs::read(filename).unwrap_or_default();
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(filename)
+            .map_err(|err| Error::new(format!("{}: error opening {}: {}", DEV_NAME, filename, err)))?;
+
+        let mut records = HashMap::new();
+        let mut end = 0;
+        for record in existing.chunks_exact(OVERLAY_RECORD_SIZE as usize) {
+            let sector = u32::from_le_bytes([record[0], record[1], record[2], record[3]]);
+            let offset = (sector * ATA_SECTOR_SIZE) as usize;
+            if offset + ATA_SECTOR_SIZE as usize <= contents.len() {
+                contents[offset..offset + ATA_SECTOR_SIZE as usize].copy_from_slice(&record[4..]);
+            }
+            records.insert(sector, end);
+            end += OVERLAY_RECORD_SIZE;
+        }
+
+        Ok(Self {
+            filename: filename.to_string(),
+            file,
+            records,
+            end,
+        })
+    }
+
+    fn write_sector(&mut self, sector: u32, data: &[u8]) -> Result<(), Error> {
+        let offset = match self.records.get(&sector) {
+            Some(offset) => *offset,
+            None => {
+                let offset = self.end;
+                self.records.insert(sector, offset);
+                self.end += OVERLAY_RECORD_SIZE;
+                offset
+            },
+        };
+
+        self.file
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| self.file.write_all(&sector.to_le_bytes()))
+            .and_then(|_| self.file.write_all(data))
+            .map_err(|err| Error::new(format!("{}: error writing to {}: {}", DEV_NAME, self.filename, err)))
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum Transfer {
+    #[default]
+    None,
+    Read,
+    Write,
+    Identify,
+}
+
 #[derive(Default)]
 pub struct AtaDevice {
-    selected_sector: u32,
-    selected_count: u32,
-    last_error: u8,
+    features: u8,
+    sector_count: u8,
+    sector_num: u8,
+    cyl_low: u8,
+    cyl_high: u8,
+    drive_head: u8,
+    status: u8,
+    error: u8,
     contents: Vec<u8>,
+
+    transfer: Transfer,
+    /// The sector being transferred through the data register, and the number of sectors after it
+    transfer_sector: u32,
+    transfer_remaining: u32,
+    buffer: Vec<u8>,
+    buffer_pos: usize,
+    overlay: Option<AtaOverlay>,
 }
 
 impl AtaDevice {
+    /// Load the disk image from the given file.  Writes only change the contents in memory unless an overlay is used
     pub fn load(&mut self, filename: &str) -> Result<(), Error> {
         match fs::read(filename) {
             Ok(contents) => {
                 self.contents = contents;
+                self.status = status::READY | status::SEEK_COMPLETE;
                 Ok(())
             },
             Err(_) => Err(Error::new(format!("Error reading contents of {}", filename))),
         }
     }
+
+    /// Use a copy-on-write overlay file, which stores the written sectors instead of modifying the disk image.  The
+    /// sectors already in the overlay are applied to the disk, so this must be called after loading the image
+    pub fn load_overlay(&mut self, filename: &str) -> Result<(), Error> {
+        self.overlay = Some(AtaOverlay::open(filename, &mut self.contents)?);
+        Ok(())
+    }
+
+    fn total_sectors(&self) -> u32 {
+        (self.contents.len() / ATA_SECTOR_SIZE as usize).min(0x0FFF_FFFF) as u32
+    }
+
+    fn cylinders(&self) -> u32 {
+        (self.total_sectors() / (ATA_HEADS * ATA_SECTORS_PER_TRACK)).min(16383)
+    }
+
+    fn slave_selected(&self) -> bool {
+        (self.drive_head & DRIVE_HEAD_SLAVE) != 0
+    }
+
+    /// Returns the selected sector, from either the 28-bit LBA or the cylinder, head, and sector registers
+    fn selected_sector(&self) -> Option<u32> {
+        if (self.drive_head & DRIVE_HEAD_LBA) != 0 {
+            Some(
+                (((self.drive_head & 0x0F) as u32) << 24)
+                    | ((self.cyl_high as u32) << 16)
+                    | ((self.cyl_low as u32) << 8)
+                    | self.sector_num as u32,
+            )
+        } else {
+            let cylinder = ((self.cyl_high as u32) << 8) | self.cyl_low as u32;
+            let head = (self.drive_head & 0x0F) as u32;
+            let sector = self.sector_num as u32;
+            if sector == 0 || sector > ATA_SECTORS_PER_TRACK {
+                return None;
+            }
+            Some((cylinder * ATA_HEADS + head) * ATA_SECTORS_PER_TRACK + sector - 1)
+        }
+    }
+
+    /// Update the address registers to the given sector, which is the last sector transferred when a command ends
+    fn set_selected_sector(&mut self, sector: u32) {
+        if (self.drive_head & DRIVE_HEAD_LBA) != 0 {
+            self.sector_num = sector as u8;
+            self.cyl_low = (sector >> 8) as u8;
+            self.cyl_high = (sector >> 16) as u8;
+            self.drive_head = (self.drive_head & 0xF0) | ((sector >> 24) as u8 & 0x0F);
+        } else {
+            let cylinder = sector / (ATA_HEADS * ATA_SECTORS_PER_TRACK);
+            let head = (sector / ATA_SECTORS_PER_TRACK) % ATA_HEADS;
+            self.sector_num = (sector % ATA_SECTORS_PER_TRACK + 1) as u8;
+            self.cyl_low = cylinder as u8;
+            self.cyl_high = (cylinder >> 8) as u8;
+            self.drive_head = (self.drive_head & 0xF0) | head as u8;
+        }
+    }
+
+    fn complete(&mut self) {
+        self.transfer = Transfer::None;
+        self.status = status::READY | status::SEEK_COMPLETE;
+        self.error = 0;
+    }
+
+    fn abort(&mut self, error: u8) {
+        self.transfer = Transfer::None;
+        self.status = status::READY | status::SEEK_COMPLETE | status::ERROR;
+        self.error = error;
+    }
+
+    /// Check the range of sectors for a command, and return the first sector and the count if they're on the disk
+    fn sector_range(&mut self) -> Option<(u32, u32)> {
+        let count = if self.sector_count == 0 {
+            256
+        } else {
+            self.sector_count as u32
+        };
+        match self.selected_sector() {
+            Some(sector) if sector + count <= self.total_sectors() => Some((sector, count)),
+            _ => {
+                log::warn!(target: DEV_NAME, "sectors {:?} + {} are outside the disk", self.selected_sector(), count);
+                self.abort(error::ID_NOT_FOUND);
+                None
+            },
+        }
+    }
+
+    fn start_transfer(&mut self, transfer: Transfer, sector: u32, count: u32) {
+        self.transfer = transfer;
+        self.transfer_sector = sector;
+        self.transfer_remaining = count - 1;
+        self.buffer_pos = 0;
+        self.status = status::READY | status::SEEK_COMPLETE | status::DATA_REQUEST;
+        self.error = 0;
+
+        match transfer {
+            Transfer::Read => self.load_sector(),
+            Transfer::Identify => self.buffer = self.identify(),
+            _ => self.buffer = vec![0; ATA_SECTOR_SIZE as usize],
+        }
+    }
+
+    fn load_sector(&mut self) {
+        let offset = (self.transfer_sector * ATA_SECTOR_SIZE) as usize;
+        self.buffer = self.contents[offset..offset + ATA_SECTOR_SIZE as usize].to_vec();
+    }
+
+    fn store_sector(&mut self) -> Result<(), Error> {
+        let offset = (self.transfer_sector * ATA_SECTOR_SIZE) as usize;
+        self.contents[offset..offset + ATA_SECTOR_SIZE as usize].copy_from_slice(&self.buffer);
+        if let Some(overlay) = self.overlay.as_mut() {
+            overlay.write_sector(self.transfer_sector, &self.buffer)?;
+        }
+        Ok(())
+    }
+
+    /// Move on to the next sector after the buffer has been completely transferred, or end the command
+    fn next_sector(&mut self) {
+        self.set_selected_sector(self.transfer_sector);
+        if self.transfer_remaining == 0 || self.transfer == Transfer::Identify {
+            self.complete();
+            return;
+        }
+
+        self.transfer_remaining -= 1;
+        self.transfer_sector += 1;
+        self.buffer_pos = 0;
+        if self.transfer == Transfer::Read {
+            self.load_sector();
+        }
+    }
+
+    fn read_data(&mut self, data: &mut [u8]) {
+        if !matches!(self.transfer, Transfer::Read | Transfer::Identify) {
+            data.fill(0);
+            return;
+        }
+
+        for byte in data.iter_mut() {
+            *byte = self.buffer[self.buffer_pos];
+            self.buffer_pos += 1;
+        }
+        if self.buffer_pos >= self.buffer.len() {
+            self.next_sector();
+        }
+    }
+
+    fn write_data(&mut self, data: &[u8]) -> Result<(), Error> {
+        if self.transfer != Transfer::Write {
+            return Ok(());
+        }
+
+        for byte in data {
+            self.buffer[self.buffer_pos] = *byte;
+            self.buffer_pos += 1;
+        }
+        if self.buffer_pos >= self.buffer.len() {
+            self.store_sector()?;
+            self.next_sector();
+        }
+        Ok(())
+    }
+
+    /// Build the 256 words of the IDENTIFY DEVICE data, in the little endian byte order that they're transferred in
+    fn identify(&self) -> Vec<u8> {
+        let mut words = [0u16; 256];
+        let total_sectors = self.total_sectors();
+        let cylinders = self.cylinders();
+
+        // Fixed disk, with the default CHS geometry
+        words[0] = 0x0040;
+        words[1] = cylinders as u16;
+        words[3] = ATA_HEADS as u16;
+        words[6] = ATA_SECTORS_PER_TRACK as u16;
+        set_identify_string(&mut words[10..20], "MOA00000000000000001");
+        set_identify_string(&mut words[23..27], "1.0");
+        set_identify_string(&mut words[27..47], "MOA EMULATED ATA DISK");
+        // LBA is supported, and the words for the current geometry are valid
+        words[49] = 0x0200;
+        words[53] = 0x0001;
+        words[54] = cylinders as u16;
+        words[55] = ATA_HEADS as u16;
+        words[56] = ATA_SECTORS_PER_TRACK as u16;
+        let chs_sectors = cylinders * ATA_HEADS * ATA_SECTORS_PER_TRACK;
+        words[57] = chs_sectors as u16;
+        words[58] = (chs_sectors >> 16) as u16;
+        words[60] = total_sectors as u16;
+        words[61] = (total_sectors >> 16) as u16;
+        // Supports ATA-1 to ATA-4
+        words[80] = 0x001E;
+
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    fn run_command(&mut self, command: u8) {
+        if self.slave_selected() {
+            return;
+        }
+
+        match command {
+            cmd::READ_SECTORS | cmd::READ_SECTORS_NO_RETRY => {
+                if let Some((sector, count)) = self.sector_range() {
+                    log::debug!(target: DEV_NAME, "reading {} sectors from {:x}", count, sector);
+                    self.start_transfer(Transfer::Read, sector, count);
+                }
+            },
+            cmd::WRITE_SECTORS | cmd::WRITE_SECTORS_NO_RETRY => {
+                if let Some((sector, count)) = self.sector_range() {
+                    log::debug!(target: DEV_NAME, "writing {} sectors to {:x}", count, sector);
+                    self.start_transfer(Transfer::Write, sector, count);
+                }
+            },
+            cmd::READ_VERIFY => {
+                if self.sector_range().is_some() {
+                    self.complete();
+                }
+            },
+            cmd::IDENTIFY => {
+                self.start_transfer(Transfer::Identify, 0, 1);
+            },
+            cmd::DIAGNOSTIC => {
+                self.complete();
+                self.error = error::NO_ERROR_DETECTED;
+            },
+            cmd::CHECK_POWER_MODE => {
+                // The drive is always active
+                self.sector_count = 0xFF;
+                self.complete();
+            },
+            cmd::RECALIBRATE..=0x1F
+            | cmd::INIT_PARAMETERS
+            | cmd::STANDBY_IMMEDIATE
+            | cmd::IDLE_IMMEDIATE
+            | cmd::FLUSH_CACHE
+            | cmd::SET_FEATURE => {
+                log::debug!(target: DEV_NAME, "accepting command {:x} with features {:x}", command, self.features);
+                self.complete();
+            },
+            _ => {
+                strict::expected(DEV_NAME, format!("unrecognized command {:x}", command));
+                self.abort(error::ABORTED);
+            },
+        }
+    }
+}
+
+/// Store an ATA string, which is padded with spaces and has the first character of each pair in the upper byte
+fn set_identify_string(words: &mut [u16], value: &str) {
+    let mut bytes = value.bytes().chain(std::iter::repeat(b' '));
+    for word in words.iter_mut() {
+        let high = bytes.next().unwrap_or(b' ');
+        let low = bytes.next().unwrap_or(b' ');
+        *word = ((high as u16) << 8) | low as u16;
+    }
 }
 
 impl Addressable for AtaDevice {
@@ -64,34 +433,43 @@ impl Addressable for AtaDevice {
     }
 
     fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        // There is no slave drive, so its registers read as 0
+        if self.slave_selected() && addr != reg::DRIVE_HEAD {
+            data.fill(0);
+            return Ok(());
+        }
+
         match addr {
             reg::DATA_WORD => {
-                self.selected_count -= 2;
-                let offset = ((self.selected_sector * ATA_SECTOR_SIZE) + (ATA_SECTOR_SIZE - 1 - self.selected_count)) as usize;
-                data[0] = self.contents[offset];
-                data[1] = self.contents[offset + 1];
-                if self.selected_count == 0 {
-                    self.selected_sector = 0;
-                    self.selected_count = 0;
-                }
+                let len = data.len().min(2);
+                self.read_data(&mut data[..len]);
             },
             reg::DATA_BYTE => {
-                self.selected_count -= 1;
-                let offset = ((self.selected_sector * ATA_SECTOR_SIZE) + (ATA_SECTOR_SIZE - 1 - self.selected_count)) as usize;
-                data[0] = self.contents[offset];
-                if self.selected_count == 0 {
-                    self.selected_sector = 0;
-                    self.selected_count = 0;
-                }
+                self.read_data(&mut data[..1]);
             },
             reg::STATUS => {
-                data[0] = ATA_ST_DATA_READY;
+                data[0] = self.status;
             },
             reg::ERROR => {
-                data[0] = self.last_error;
+                data[0] = self.error;
+            },
+            reg::SECTOR_COUNT => {
+                data[0] = self.sector_count;
+            },
+            reg::SECTOR_NUM => {
+                data[0] = self.sector_num;
+            },
+            reg::CYL_LOW => {
+                data[0] = self.cyl_low;
+            },
+            reg::CYL_HIGH => {
+                data[0] = self.cyl_high;
+            },
+            reg::DRIVE_HEAD => {
+                data[0] = self.drive_head | 0xA0;
             },
             _ => {
-                log::debug!("{}: reading from {:0x}", DEV_NAME, addr);
+                strict::expected(DEV_NAME, format!("unhandled read from {:#x}", addr));
             },
         }
 
@@ -99,44 +477,37 @@ impl Addressable for AtaDevice {
     }
 
     fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
-        log::debug!("{}: write to register {:x} with {:x}", DEV_NAME, addr, data[0]);
+        log::debug!(target: DEV_NAME, "write to register {:x} with {:x}", addr, data[0]);
         match addr {
             reg::DRIVE_HEAD => {
-                self.selected_sector |= ((data[0] & 0x1F) as u32) << 24;
+                self.drive_head = data[0];
             },
             reg::CYL_HIGH => {
-                self.selected_sector |= (data[0] as u32) << 16;
+                self.cyl_high = data[0];
             },
             reg::CYL_LOW => {
-                self.selected_sector |= (data[0] as u32) << 8;
+                self.cyl_low = data[0];
             },
             reg::SECTOR_NUM => {
-                self.selected_sector |= data[0] as u32;
+                self.sector_num = data[0];
             },
             reg::SECTOR_COUNT => {
-                self.selected_count = (data[0] as u32) * ATA_SECTOR_SIZE;
-            },
-            reg::COMMAND => match data[0] {
-                cmd::READ_SECTORS => {
-                    log::debug!("{}: reading sector {:x}", DEV_NAME, self.selected_sector);
-                },
-                cmd::WRITE_SECTORS => {
-                    log::debug!("{}: writing sector {:x}", DEV_NAME, self.selected_sector);
-                },
-                cmd::IDENTIFY => {},
-                cmd::SET_FEATURE => {},
-                _ => {
-                    log::debug!("{}: unrecognized command {:x}", DEV_NAME, data[0]);
-                },
+                self.sector_count = data[0];
+            },
+            reg::COMMAND => {
+                self.run_command(data[0]);
             },
             reg::FEATURE => {
-                // TODO implement features
+                self.features = data[0];
+            },
+            reg::DATA_WORD => {
+                self.write_data(&data[..data.len().min(2)])?;
             },
             reg::DATA_BYTE => {
-                // TODO implement writing
+                self.write_data(&data[..1])?;
             },
             _ => {
-                log::debug!("{}: writing {:0x} to {:0x}", DEV_NAME, data[0], addr);
+                strict::expected(DEV_NAME, format!("unhandled write of {:02x?} to {:#x}", data, addr));
             },
         }
         Ok(())
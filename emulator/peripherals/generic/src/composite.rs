@@ -0,0 +1,178 @@
+use femtos::{Instant, Duration};
+
+use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable, Bus, Device, Waker, TimerId, strict};
+use moa_signals::Signal;
+
+
+/// A group of devices at fixed offsets behind one address mapping, such as an I/O board with a UART, timer,
+/// and GPIO chip, which can be assembled once and added to different machines as a single device
+///
+/// Each steppable sub-device is stepped at its own rate, and the interrupt outputs of the sub-devices are
+/// combined into one output, which can also be connected to an interrupt level of the system.
+pub struct CompositeDevice {
+    name: String,
+    size: usize,
+    bus: Bus,
+    steppables: Vec<(Device, Instant)>,
+    next_step: Instant,
+    interrupt_sources: Vec<Signal<bool>>,
+    interrupt_output: Signal<bool>,
+    interrupt: Option<(u8, u8)>,
+}
+
+impl CompositeDevice {
+    pub fn new(name: &str, size: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            size,
+            bus: Bus::default(),
+            steppables: vec![],
+            next_step: Instant::START,
+            interrupt_sources: vec![],
+            interrupt_output: Signal::new(false),
+            interrupt: None,
+        }
+    }
+
+    /// Map a sub-device at the given offset from the start of the composite device
+    pub fn add(&mut self, offset: Address, device: Device) -> Result<(), Error> {
+        let size = device
+            .borrow_mut()
+            .as_addressable()
+            .map(|addressable| addressable.size())
+            .ok_or_else(|| Error::new(format!("{}: sub-device at {:x} is not addressable", self.name, offset)))?;
+        if offset as usize + size > self.size {
+            return Err(Error::new(format!(
+                "{}: sub-device at {:x} with size {:x} doesn't fit in {:x} bytes",
+                self.name, offset, size, self.size
+            )));
+        }
+
+        if device.borrow_mut().as_steppable().is_some() {
+            self.steppables.push((device.clone(), Instant::START));
+        }
+        self.bus.insert(offset, device);
+        Ok(())
+    }
+
+    /// Add a sub-device that isn't mapped to an address, such as a clock generator, which is only stepped
+    pub fn add_unmapped(&mut self, device: Device) -> Result<(), Error> {
+        if device.borrow_mut().as_steppable().is_none() {
+            return Err(Error::new(format!("{}: unmapped sub-devices must be steppable", self.name)));
+        }
+        self.steppables.push((device, Instant::START));
+        Ok(())
+    }
+
+    /// Include an interrupt output of a sub-device in the combined interrupt output
+    pub fn add_interrupt_source(&mut self, source: Signal<bool>) {
+        self.interrupt_sources.push(source);
+    }
+
+    /// Raise an interrupt at the given priority and vector on the system's interrupt controller while any of
+    /// the interrupt sources are active
+    pub fn with_interrupt(mut self, priority: u8, vector: u8) -> Self {
+        self.interrupt = Some((priority, vector));
+        self
+    }
+
+    /// Returns the combined interrupt output, which is active while any of the interrupt sources are active
+    pub fn interrupt_output(&self) -> Signal<bool> {
+        self.interrupt_output.clone()
+    }
+
+    fn update_interrupt(&mut self, system: &System) -> Result<(), Error> {
+        let state = self.interrupt_sources.iter().any(|source| source.get());
+        if state != self.interrupt_output.get() {
+            self.interrupt_output.set(state);
+            if let Some((priority, vector)) = self.interrupt {
+                system.get_interrupt_controller().set(state, priority, vector)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Addressable for CompositeDevice {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        if self.bus.get_device_at(addr, data.len()).is_err() {
+            strict::unhandled_read(&self.name, addr);
+            return Ok(());
+        }
+        self.bus.read(clock, addr, data)
+    }
+
+    fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        if self.bus.get_device_at(addr, data.len()).is_err() {
+            strict::unhandled_write(&self.name, addr, data);
+            return Ok(());
+        }
+        self.bus.write(clock, addr, data)
+    }
+}
+
+impl Steppable for CompositeDevice {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        // If a sub-device woke the composite device before its next scheduled step, then it's not known which one
+        // it was, so all of them are stepped
+        let woken = system.clock < self.next_step;
+        for (device, next) in self.steppables.iter_mut() {
+            if woken || *next <= system.clock {
+                let duration = device.borrow_mut().as_steppable().unwrap().step(system)?;
+                *next = system.clock + duration;
+            }
+        }
+        self.update_interrupt(system)?;
+
+        let duration = self
+            .steppables
+            .iter()
+            .map(|(_, next)| next.duration_since(system.clock))
+            .min()
+            // If none of the sub-devices are steppable, then only the interrupt sources need to be checked
+            .unwrap_or_else(|| Duration::from_millis(1));
+        self.next_step = system.clock + duration;
+        Ok(duration)
+    }
+
+    fn on_error(&mut self, system: &System) {
+        for (device, _) in self.steppables.iter() {
+            device.borrow_mut().as_steppable().unwrap().on_error(system);
+        }
+    }
+
+    fn set_waker(&mut self, waker: Waker) {
+        for (device, _) in self.steppables.iter() {
+            device.borrow_mut().as_steppable().unwrap().set_waker(waker.clone());
+        }
+    }
+
+    // The sub-devices share the composite device's waker, so each one ignores the timers it didn't schedule
+    fn on_timer(&mut self, system: &System, timer: TimerId) -> Result<(), Error> {
+        for (device, _) in self.steppables.iter() {
+            device.borrow_mut().as_steppable().unwrap().on_timer(system, timer)?;
+        }
+        self.update_interrupt(system)
+    }
+}
+
+impl Transmutable for CompositeDevice {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+
+    fn self_check(&mut self, system: &System) -> Result<(), Error> {
+        for block in self.bus.blocks() {
+            block.dev.borrow_mut().self_check(system)?;
+        }
+        Ok(())
+    }
+}
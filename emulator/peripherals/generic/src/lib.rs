@@ -1,2 +1,20 @@
 mod ata;
 pub use crate::ata::AtaDevice;
+
+mod composite;
+pub use crate::composite::CompositeDevice;
+
+mod mk48t08;
+pub use crate::mk48t08::MK48T08;
+
+pub mod nic;
+pub use crate::nic::VirtualNic;
+
+mod sdcard;
+pub use crate::sdcard::SdCard;
+
+pub mod spi;
+pub use crate::spi::{SpiGpio, SpiDevice};
+
+pub mod semihosting;
+pub use crate::semihosting::Semihosting;
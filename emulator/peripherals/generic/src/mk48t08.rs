@@ -0,0 +1,314 @@
+//! Timekeeper NVRAM chip emulation (MK48T02/MK48T08)
+//!
+//! The chip is a block of battery-backed static RAM with a real time clock in the last 8 bytes.  The clock
+//! registers are in BCD, and are copied from the internal counters once a second unless the `READ` or `WRITE`
+//! bits are set in the control register.  The time is written by setting the `WRITE` bit, writing the clock
+//! registers, and then clearing the `WRITE` bit, which loads them into the counters.
+//!
+//! If a file is given, the contents of the RAM, including the clock registers, are saved to it whenever they
+//! change, and when the file is loaded again, the clock is advanced by the time that has passed since it
+//! was last saved, as if the battery had kept the clock running while the emulator wasn't.
+
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use femtos::{Instant, Duration};
+
+use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable, write_atomic};
+
+
+#[rustfmt::skip]
+mod reg {
+    use super::Address;
+    // Offsets from the start of the clock registers, at the end of the RAM
+    pub(super) const CONTROL: Address   = 0x00;
+    pub(super) const SECONDS: Address   = 0x01;
+    pub(super) const MINUTES: Address   = 0x02;
+    pub(super) const HOURS: Address     = 0x03;
+    pub(super) const DAY: Address       = 0x04;
+    pub(super) const DATE: Address      = 0x05;
+    pub(super) const MONTH: Address     = 0x06;
+    pub(super) const YEAR: Address      = 0x07;
+}
+
+#[rustfmt::skip]
+mod bits {
+    pub(super) const CONTROL_WRITE: u8  = 0x80;
+    pub(super) const CONTROL_READ: u8   = 0x40;
+    pub(super) const SECONDS_STOP: u8   = 0x80;
+}
+
+const CLOCK_REGISTERS: usize = 8;
+
+/// The number of seconds between the unix epoch and 2000-01-01, which is when the clock counts from
+const EPOCH_2000: u64 = 946_684_800;
+const SECONDS_PER_DAY: u64 = 86_400;
+/// The clock only has a two digit year, so it wraps around after 100 years (2000-01-01 to 2099-12-31)
+const SECONDS_PER_CENTURY: u64 = 36_525 * SECONDS_PER_DAY;
+
+/// The default day of the week numbering starts from Sunday as 1, which makes 2000-01-01 (a Saturday) day 7
+const DEFAULT_WEEKDAY_OFFSET: u64 = 6;
+
+const DEV_NAME: &str = "mk48t08";
+
+
+pub struct MK48T08 {
+    ram: Vec<u8>,
+    /// The time in seconds since 2000-01-01 00:00:00
+    seconds: u64,
+    /// The day of the week register is set by software, so this is the difference between it and the date
+    weekday_offset: u64,
+    filename: Option<String>,
+    dirty: bool,
+}
+
+impl Default for MK48T08 {
+    fn default() -> Self {
+        Self::with_size(8192)
+    }
+}
+
+impl MK48T08 {
+    /// Create the 2KB version of the chip
+    pub fn mk48t02() -> Self {
+        Self::with_size(2048)
+    }
+
+    fn with_size(size: usize) -> Self {
+        let mut chip = Self {
+            ram: vec![0; size],
+            seconds: host_time(),
+            weekday_offset: DEFAULT_WEEKDAY_OFFSET,
+            filename: None,
+            dirty: false,
+        };
+        chip.update_registers();
+        chip
+    }
+
+    /// Load the contents of the RAM from the given file if it exists, and save them back to it when they change
+    pub fn with_file(mut self, filename: &str) -> Result<Self, Error> {
+        if let Ok(contents) = fs::read(filename) {
+            if contents.len() != self.ram.len() {
+                return Err(Error::new(format!(
+                    "{}: expected {} to be {} bytes, but it's {} bytes",
+                    DEV_NAME,
+                    filename,
+                    self.ram.len(),
+                    contents.len()
+                )));
+            }
+            self.ram = contents;
+            self.load_counters();
+
+            if self.ram[self.clock_base() + reg::SECONDS as usize] & bits::SECONDS_STOP == 0 {
+                let saved = fs::metadata(filename)
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+                    .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                    .map(|elapsed| elapsed.as_secs())
+                    .unwrap_or(0);
+                self.advance(saved);
+            }
+            self.update_registers();
+        }
+        self.filename = Some(filename.to_string());
+        Ok(self)
+    }
+
+    /// Set the time of the clock, in seconds since 2000-01-01 00:00:00, instead of using the time of the host
+    pub fn set_time(&mut self, seconds: u64) {
+        self.seconds = seconds % SECONDS_PER_CENTURY;
+        self.weekday_offset = DEFAULT_WEEKDAY_OFFSET;
+        self.update_registers();
+    }
+
+    fn clock_base(&self) -> usize {
+        self.ram.len() - CLOCK_REGISTERS
+    }
+
+    fn clock_register(&self, offset: Address) -> u8 {
+        self.ram[self.clock_base() + offset as usize]
+    }
+
+    /// Returns the offset needed for the day of the week register to be the given day (1 to 7) on the current date
+    fn weekday_from_date(&self, day: u8) -> u64 {
+        let days = self.seconds / SECONDS_PER_DAY;
+        (7 + (day.clamp(1, 7) as u64 - 1) - days % 7) % 7
+    }
+
+    fn advance(&mut self, seconds: u64) {
+        self.seconds = (self.seconds + seconds) % SECONDS_PER_CENTURY;
+    }
+
+    /// Copy the clock counters into the registers, preserving the control bits in the upper bits of each register
+    fn update_registers(&mut self) {
+        let days = self.seconds / SECONDS_PER_DAY;
+        let time = self.seconds % SECONDS_PER_DAY;
+        let (year, month, date) = date_from_days(days);
+        let day = ((days + self.weekday_offset) % 7 + 1) as u8;
+
+        let base = self.clock_base();
+        let set = |ram: &mut [u8], offset: Address, value: u8, mask: u8| {
+            let index = base + offset as usize;
+            ram[index] = (ram[index] & !mask) | (to_bcd(value) & mask);
+        };
+        set(&mut self.ram, reg::SECONDS, (time % 60) as u8, 0x7F);
+        set(&mut self.ram, reg::MINUTES, (time / 60 % 60) as u8, 0x7F);
+        set(&mut self.ram, reg::HOURS, (time / 3600) as u8, 0x3F);
+        set(&mut self.ram, reg::DAY, day, 0x07);
+        set(&mut self.ram, reg::DATE, date, 0x3F);
+        set(&mut self.ram, reg::MONTH, month, 0x1F);
+        set(&mut self.ram, reg::YEAR, year, 0xFF);
+    }
+
+    /// Load the clock counters from the registers, after they've been written to
+    fn load_counters(&mut self) {
+        let seconds = from_bcd(self.clock_register(reg::SECONDS) & 0x7F) as u64;
+        let minutes = from_bcd(self.clock_register(reg::MINUTES) & 0x7F) as u64;
+        let hours = from_bcd(self.clock_register(reg::HOURS) & 0x3F) as u64;
+        let day = from_bcd(self.clock_register(reg::DAY) & 0x07);
+        let date = from_bcd(self.clock_register(reg::DATE) & 0x3F);
+        let month = from_bcd(self.clock_register(reg::MONTH) & 0x1F);
+        let year = from_bcd(self.clock_register(reg::YEAR));
+
+        let days = days_from_date(year, month, date);
+        self.seconds =
+            (days * SECONDS_PER_DAY + hours.min(23) * 3600 + minutes.min(59) * 60 + seconds.min(59)) % SECONDS_PER_CENTURY;
+        self.weekday_offset = self.weekday_from_date(day);
+    }
+
+    fn save(&mut self) -> Result<(), Error> {
+        if let Some(filename) = self.filename.as_ref() {
+            // The file is replaced atomically, so a crash while saving can't lose the clock and settings
+            write_atomic(filename, &self.ram).map_err(|err| Error::new(format!("{}: {}", DEV_NAME, err)))?;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl Addressable for MK48T08 {
+    fn size(&self) -> usize {
+        self.ram.len()
+    }
+
+    fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        let addr = addr as usize;
+        data.copy_from_slice(&self.ram[addr..addr + data.len()]);
+        Ok(())
+    }
+
+    fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        let base = self.clock_base();
+        for (i, byte) in data.iter().enumerate() {
+            let index = addr as usize + i;
+            let previous = self.ram[index];
+            self.ram[index] = *byte;
+
+            // Clearing the write bit loads the new time into the counters
+            if index == base + reg::CONTROL as usize && previous & bits::CONTROL_WRITE != 0 && *byte & bits::CONTROL_WRITE == 0 {
+                log::debug!(target: DEV_NAME, "setting the clock");
+                self.load_counters();
+            }
+        }
+        self.dirty = true;
+        Ok(())
+    }
+}
+
+impl Steppable for MK48T08 {
+    fn step(&mut self, _system: &System) -> Result<Duration, Error> {
+        if self.clock_register(reg::SECONDS) & bits::SECONDS_STOP == 0 {
+            self.advance(1);
+        }
+
+        if self.clock_register(reg::CONTROL) & (bits::CONTROL_WRITE | bits::CONTROL_READ) == 0 {
+            self.update_registers();
+        }
+
+        if self.dirty {
+            self.save()?;
+        }
+        Ok(Duration::from_micros(1_000_000))
+    }
+}
+
+impl Transmutable for MK48T08 {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+}
+
+impl Drop for MK48T08 {
+    fn drop(&mut self) {
+        if self.dirty {
+            if let Err(err) = self.save() {
+                log::error!("{}", err);
+            }
+        }
+    }
+}
+
+fn host_time() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(EPOCH_2000);
+    now.saturating_sub(EPOCH_2000) % SECONDS_PER_CENTURY
+}
+
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+fn is_leap_year(year: u8) -> bool {
+    // Every 4th year is a leap year between 2000 and 2099
+    year % 4 == 0
+}
+
+fn days_in_month(year: u8, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Returns the number of days since 2000-01-01 of the given date in the 21st century
+fn days_from_date(year: u8, month: u8, date: u8) -> u64 {
+    let year = year.min(99);
+    let month = month.clamp(1, 12);
+    let mut days = (0..year).map(|y| if is_leap_year(y) { 366 } else { 365 }).sum::<u64>();
+    days += (1..month).map(|m| days_in_month(year, m) as u64).sum::<u64>();
+    days + date.clamp(1, days_in_month(year, month)) as u64 - 1
+}
+
+/// Returns the (year, month, date) of the given number of days since 2000-01-01
+fn date_from_days(mut days: u64) -> (u8, u8, u8) {
+    let mut year = 0;
+    loop {
+        let length = if is_leap_year(year) { 366 } else { 365 };
+        if days < length {
+            break;
+        }
+        days -= length;
+        year += 1;
+    }
+
+    let mut month = 1;
+    while days >= days_in_month(year, month) as u64 {
+        days -= days_in_month(year, month) as u64;
+        month += 1;
+    }
+    (year, month, days as u8 + 1)
+}
@@ -0,0 +1,371 @@
+//! A simple memory-mapped network interface, for guest operating systems to write a network driver against
+//!
+//! The device sends and receives raw IPv4 packets, without any link layer header, through the network that the
+//! host gives it.  Received packets are kept in a queue of up to 16 packets, and the packet at the head of the
+//! queue can be read from the receive buffer until the guest moves on to the next one.  A packet is sent by
+//! writing it to the transmit buffer, writing its length to `TX_LENGTH`, and then writing `SEND` to `COMMAND`.
+//!
+//! The registers are 32 bits wide, in the byte order of the cpu, and the buffers are accessed a byte at a time
+//! or in any larger size.
+//!
+//! | Offset | Register   | Description                                                             |
+//! |--------|------------|-------------------------------------------------------------------------|
+//! | 0x00   | ID         | reads as 0x4D4E4943 ("MNIC")                                            |
+//! | 0x04   | CONTROL    | the control bits, which are all 0 after a reset                         |
+//! | 0x08   | STATUS     | the status bits, where writing a 1 to an event bit clears it           |
+//! | 0x0C   | COMMAND    | write a command number to perform it                                    |
+//! | 0x10   | RX_LENGTH  | the length of the packet in the receive buffer, or 0 if there isn't one |
+//! | 0x14   | RX_COUNT   | the number of packets waiting, including the one in the receive buffer  |
+//! | 0x18   | TX_LENGTH  | the length of the packet in the transmit buffer                        |
+//! | 0x1C   | DROPPED    | the number of received packets dropped because the queue was full       |
+//! | 0x20   | VECTOR     | the interrupt vector number, which is the autovector after a reset      |
+//! | 0x24   | MTU        | the largest packet that can be sent or received (1500)                  |
+//! | 0x800  | RX_BUFFER  | the packet at the head of the receive queue (read only)                 |
+//! | 0x1000 | TX_BUFFER  | the packet to send                                                      |
+//!
+//! | Bit | CONTROL          | STATUS                                                      |
+//! |-----|------------------|-------------------------------------------------------------|
+//! | 0   | RX_ENABLE        | RX_READY: there is a packet in the receive buffer           |
+//! | 1   | RX_INT_ENABLE    | TX_DONE: the last packet was sent (event)                   |
+//! | 2   | TX_INT_ENABLE    | TX_ERROR: the last packet's length was invalid (event)      |
+//! | 3   |                  | RX_DROPPED: a received packet was dropped (event)           |
+//! | 4   |                  | LINK: the device is connected to a network                  |
+//!
+//! | Number | Command  | Description                                                              |
+//! |--------|----------|--------------------------------------------------------------------------|
+//! | 1      | SEND     | send the first `TX_LENGTH` bytes of the transmit buffer                  |
+//! | 2      | RX_NEXT  | discard the packet in the receive buffer and move to the next one        |
+//! | 3      | RESET    | clear the receive queue, the control and status bits, and the counters   |
+//!
+//! Packets are only received while RX_ENABLE is set, and are otherwise discarded.  The interrupt is asserted
+//! while RX_READY is set with RX_INT_ENABLE, or TX_DONE or TX_ERROR is set with TX_INT_ENABLE.
+
+use std::collections::VecDeque;
+
+use femtos::{Instant, Duration};
+
+use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable, Waker, strict};
+use moa_host::Network;
+
+
+#[rustfmt::skip]
+mod reg {
+    use super::Address;
+    pub(super) const ID: Address        = 0x00;
+    pub(super) const CONTROL: Address   = 0x04;
+    pub(super) const STATUS: Address    = 0x08;
+    pub(super) const COMMAND: Address   = 0x0C;
+    pub(super) const RX_LENGTH: Address = 0x10;
+    pub(super) const RX_COUNT: Address  = 0x14;
+    pub(super) const TX_LENGTH: Address = 0x18;
+    pub(super) const DROPPED: Address   = 0x1C;
+    pub(super) const VECTOR: Address    = 0x20;
+    pub(super) const MTU: Address       = 0x24;
+    pub(super) const RX_BUFFER: Address = 0x800;
+    pub(super) const TX_BUFFER: Address = 0x1000;
+}
+
+#[rustfmt::skip]
+mod control {
+    pub(super) const RX_ENABLE: u32     = 0x01;
+    pub(super) const RX_INT_ENABLE: u32 = 0x02;
+    pub(super) const TX_INT_ENABLE: u32 = 0x04;
+}
+
+#[rustfmt::skip]
+mod status {
+    pub(super) const RX_READY: u32      = 0x01;
+    pub(super) const TX_DONE: u32       = 0x02;
+    pub(super) const TX_ERROR: u32      = 0x04;
+    pub(super) const RX_DROPPED: u32    = 0x08;
+    pub(super) const LINK: u32          = 0x10;
+    /// The bits that stay set until the guest clears them
+    pub(super) const EVENTS: u32        = TX_DONE | TX_ERROR | RX_DROPPED;
+}
+
+#[rustfmt::skip]
+mod cmd {
+    pub(super) const SEND: u32          = 1;
+    pub(super) const RX_NEXT: u32       = 2;
+    pub(super) const RESET: u32         = 3;
+}
+
+const NIC_ID: u32 = 0x4D4E_4943;
+const NIC_MTU: usize = 1500;
+/// The space reserved for each buffer, which is larger than the MTU
+const BUFFER_SIZE: Address = 0x600;
+const RX_QUEUE_LEN: usize = 16;
+
+/// How often, in microseconds, to check for received packets if the network can't wake the device
+const POLL_INTERVAL_US: u64 = 1000;
+/// How often, in microseconds, to step the device if the network wakes it when a packet arrives
+const IDLE_INTERVAL_US: u64 = 100_000;
+
+const DEV_NAME: &str = "nic";
+
+
+pub struct VirtualNic {
+    big_endian: bool,
+    network: Option<Box<dyn Network>>,
+    wakes_on_receive: bool,
+    interrupt_priority: u8,
+    vector: u8,
+
+    control: u32,
+    events: u32,
+    rx_queue: VecDeque<Vec<u8>>,
+    tx_buffer: Vec<u8>,
+    tx_length: u32,
+    dropped: u32,
+
+    waker: Option<Waker>,
+}
+
+impl VirtualNic {
+    /// Create a new device for a cpu with the given byte order, which determines how register values are accessed
+    pub fn new(big_endian: bool) -> Self {
+        Self {
+            big_endian,
+            network: None,
+            wakes_on_receive: false,
+            interrupt_priority: 3,
+            vector: autovector(3),
+
+            control: 0,
+            events: 0,
+            rx_queue: VecDeque::new(),
+            tx_buffer: vec![0; BUFFER_SIZE as usize],
+            tx_length: 0,
+            dropped: 0,
+
+            waker: None,
+        }
+    }
+
+    /// Connect the device to a network.  Without one, the device has no link, and the packets it sends are lost
+    pub fn with_network(mut self, network: Box<dyn Network>) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Set the interrupt priority level, which also sets the reset value of the vector to that level's autovector
+    pub fn with_interrupt_priority(mut self, priority: u8) -> Self {
+        self.interrupt_priority = priority;
+        self.vector = autovector(priority);
+        self
+    }
+
+    fn status(&self) -> u32 {
+        let mut status = self.events;
+        if !self.rx_queue.is_empty() {
+            status |= status::RX_READY;
+        }
+        if self.network.is_some() {
+            status |= status::LINK;
+        }
+        status
+    }
+
+    fn is_interrupting(&self) -> bool {
+        let status = self.status();
+        ((self.control & control::RX_INT_ENABLE) != 0 && (status & status::RX_READY) != 0)
+            || ((self.control & control::TX_INT_ENABLE) != 0 && (status & (status::TX_DONE | status::TX_ERROR)) != 0)
+    }
+
+    /// Move the packets that have arrived from the network into the receive queue
+    fn receive_packets(&mut self) {
+        let network = match self.network.as_mut() {
+            Some(network) => network,
+            None => return,
+        };
+
+        while let Some(packet) = network.receive() {
+            if (self.control & control::RX_ENABLE) == 0 {
+                continue;
+            }
+            if packet.len() > NIC_MTU || self.rx_queue.len() >= RX_QUEUE_LEN {
+                self.dropped = self.dropped.wrapping_add(1);
+                self.events |= status::RX_DROPPED;
+                continue;
+            }
+            self.rx_queue.push_back(packet);
+        }
+    }
+
+    fn run_command(&mut self, number: u32) {
+        match number {
+            cmd::SEND => {
+                let length = self.tx_length as usize;
+                if length == 0 || length > NIC_MTU {
+                    self.events |= status::TX_ERROR;
+                    return;
+                }
+                if let Some(network) = self.network.as_mut() {
+                    network.send(&self.tx_buffer[..length]);
+                }
+                self.events |= status::TX_DONE;
+            },
+            cmd::RX_NEXT => {
+                self.rx_queue.pop_front();
+            },
+            cmd::RESET => {
+                self.control = 0;
+                self.events = 0;
+                self.rx_queue.clear();
+                self.tx_length = 0;
+                self.dropped = 0;
+                self.vector = autovector(self.interrupt_priority);
+            },
+            _ => log::warn!(target: DEV_NAME, "unknown command {}", number),
+        }
+    }
+
+    fn register_bytes(&self, value: u32) -> [u8; 4] {
+        if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    }
+
+    /// Step the device at the current clock, to update the interrupt after a register access
+    fn wake(&self) {
+        if let Some(waker) = self.waker.as_ref() {
+            waker.wake();
+        }
+    }
+}
+
+/// The 68000's autovector number for the given interrupt priority level
+fn autovector(priority: u8) -> u8 {
+    24 + priority
+}
+
+impl Addressable for VirtualNic {
+    fn size(&self) -> usize {
+        (reg::TX_BUFFER + BUFFER_SIZE) as usize
+    }
+
+    fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        if addr >= reg::TX_BUFFER {
+            let start = (addr - reg::TX_BUFFER) as usize;
+            for (i, byte) in data.iter_mut().enumerate() {
+                *byte = self.tx_buffer.get(start + i).copied().unwrap_or(0);
+            }
+            return Ok(());
+        }
+        if addr >= reg::RX_BUFFER {
+            let start = (addr - reg::RX_BUFFER) as usize;
+            let packet = self.rx_queue.front();
+            for (i, byte) in data.iter_mut().enumerate() {
+                *byte = packet.and_then(|packet| packet.get(start + i)).copied().unwrap_or(0);
+            }
+            return Ok(());
+        }
+
+        let value = match addr & !0x3 {
+            reg::ID => NIC_ID,
+            reg::CONTROL => self.control,
+            reg::STATUS => self.status(),
+            reg::RX_LENGTH => self.rx_queue.front().map(|packet| packet.len() as u32).unwrap_or(0),
+            reg::RX_COUNT => self.rx_queue.len() as u32,
+            reg::TX_LENGTH => self.tx_length,
+            reg::DROPPED => self.dropped,
+            reg::VECTOR => self.vector as u32,
+            reg::MTU => NIC_MTU as u32,
+            _ => 0,
+        };
+
+        let bytes = self.register_bytes(value);
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = bytes.get((addr as usize & 0x3) + i).cloned().unwrap_or(0);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        if addr >= reg::TX_BUFFER {
+            let start = (addr - reg::TX_BUFFER) as usize;
+            for (i, byte) in data.iter().enumerate() {
+                if let Some(target) = self.tx_buffer.get_mut(start + i) {
+                    *target = *byte;
+                }
+            }
+            return Ok(());
+        }
+        if addr >= reg::RX_BUFFER {
+            strict::unhandled(DEV_NAME, format!("write to the receive buffer at {:0x}", addr));
+            return Ok(());
+        }
+
+        let offset = addr as usize & 0x3;
+        let register = addr & !0x3;
+        let previous = match register {
+            // Writing to the start of a register clears it, so that narrower cpus don't leave stale upper bytes
+            _ if offset == 0 => 0,
+            reg::CONTROL => self.control,
+            reg::TX_LENGTH => self.tx_length,
+            reg::VECTOR => self.vector as u32,
+            _ => 0,
+        };
+
+        let mut bytes = self.register_bytes(previous);
+        for (i, byte) in data.iter().enumerate() {
+            if let Some(target) = bytes.get_mut(offset + i) {
+                *target = *byte;
+            }
+        }
+        let value = if self.big_endian {
+            // A narrower write to a big endian register is right aligned, like the m68k's byte and word writes
+            u32::from_be_bytes(bytes) >> (8 * (4 - (offset + data.len()).min(4)))
+        } else {
+            u32::from_le_bytes(bytes)
+        };
+
+        match register {
+            reg::CONTROL => self.control = value,
+            reg::STATUS => self.events &= !(value & status::EVENTS),
+            reg::COMMAND => self.run_command(value),
+            reg::TX_LENGTH => self.tx_length = value,
+            reg::VECTOR => self.vector = value as u8,
+            _ => strict::unhandled(DEV_NAME, format!("unhandled write {:0x} to {:0x}", value, addr)),
+        }
+
+        // Any write can change the interrupt state, which is updated when the device is stepped
+        self.wake();
+        Ok(())
+    }
+}
+
+impl Steppable for VirtualNic {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        self.receive_packets();
+        system
+            .get_interrupt_controller()
+            .set(self.is_interrupting(), self.interrupt_priority, self.vector)?;
+
+        if self.wakes_on_receive {
+            Ok(Duration::from_micros(IDLE_INTERVAL_US))
+        } else {
+            Ok(Duration::from_micros(POLL_INTERVAL_US))
+        }
+    }
+
+    fn set_waker(&mut self, waker: Waker) {
+        if let Some(network) = self.network.as_mut() {
+            let waker = waker.clone();
+            self.wakes_on_receive = network.on_receive(Box::new(move || waker.wake()));
+        }
+        self.waker = Some(waker);
+    }
+}
+
+impl Transmutable for VirtualNic {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+}
@@ -0,0 +1,314 @@
+//! An SD card in SPI mode, backed by a disk image file
+//!
+//! Only the commands needed to initialize the card and read and write single blocks are supported.  The card
+//! always reports itself as a high capacity (SDHC) card, so the block commands take a block number rather
+//! than a byte address.  Writes are saved to the image file immediately if the card was loaded from a file.
+
+use std::fs;
+use std::io::{Seek, SeekFrom, Write};
+use std::collections::VecDeque;
+
+use moa_core::Error;
+
+use crate::spi::SpiDevice;
+
+
+#[rustfmt::skip]
+mod cmd {
+    pub(super) const GO_IDLE_STATE: u8          = 0;
+    pub(super) const SEND_IF_COND: u8           = 8;
+    pub(super) const SEND_CSD: u8               = 9;
+    pub(super) const SEND_CID: u8               = 10;
+    pub(super) const STOP_TRANSMISSION: u8      = 12;
+    pub(super) const SET_BLOCKLEN: u8           = 16;
+    pub(super) const READ_SINGLE_BLOCK: u8      = 17;
+    pub(super) const WRITE_BLOCK: u8            = 24;
+    pub(super) const APP_CMD: u8                = 55;
+    pub(super) const READ_OCR: u8               = 58;
+    pub(super) const SD_SEND_OP_COND: u8        = 41;
+}
+
+#[rustfmt::skip]
+mod r1 {
+    pub(super) const READY: u8                  = 0x00;
+    pub(super) const IDLE: u8                   = 0x01;
+    pub(super) const ILLEGAL_COMMAND: u8        = 0x04;
+    pub(super) const ADDRESS_ERROR: u8          = 0x20;
+    pub(super) const PARAMETER_ERROR: u8        = 0x40;
+}
+
+const START_BLOCK_TOKEN: u8 = 0xFE;
+const DATA_ACCEPTED: u8 = 0x05;
+const DATA_WRITE_ERROR: u8 = 0x0D;
+
+/// The operating conditions register, with the power up complete and high capacity bits set, for 2.7V to 3.6V
+const OCR: u32 = 0xC0FF_8000;
+
+const BLOCK_SIZE: usize = 512;
+
+const DEV_NAME: &str = "sdcard";
+
+
+enum CardState {
+    /// Waiting for the start of a command, or for the card to be selected
+    Idle,
+    Command(Vec<u8>),
+    /// Waiting for the start token of the data for a block write
+    WriteToken(usize),
+    /// Receiving the block data, followed by the 2 bytes of the CRC
+    WriteData(usize, Vec<u8>),
+}
+
+pub struct SdCard {
+    contents: Vec<u8>,
+    filename: Option<String>,
+    selected: bool,
+    initialized: bool,
+    app_command: bool,
+    state: CardState,
+    output: VecDeque<u8>,
+}
+
+impl Default for SdCard {
+    fn default() -> Self {
+        Self {
+            contents: vec![],
+            filename: None,
+            selected: false,
+            initialized: false,
+            app_command: false,
+            state: CardState::Idle,
+            output: VecDeque::new(),
+        }
+    }
+}
+
+impl SdCard {
+    pub fn new(mut contents: Vec<u8>) -> Self {
+        let blocks = contents.len().div_ceil(BLOCK_SIZE);
+        contents.resize(blocks * BLOCK_SIZE, 0);
+        Self {
+            contents,
+            ..Default::default()
+        }
+    }
+
+    /// Load the disk image from the given file, which will be updated when blocks are written
+    pub fn load(&mut self, filename: &str) -> Result<(), Error> {
+        match fs::read(filename) {
+            Ok(contents) => {
+                *self = Self::new(contents);
+                self.filename = Some(filename.to_string());
+                Ok(())
+            },
+            Err(_) => Err(Error::new(format!("Error reading contents of {}", filename))),
+        }
+    }
+
+    fn blocks(&self) -> usize {
+        self.contents.len() / BLOCK_SIZE
+    }
+
+    fn status(&self) -> u8 {
+        if self.initialized { r1::READY } else { r1::IDLE }
+    }
+
+    fn respond(&mut self, data: &[u8]) {
+        // There is always at least one byte of delay before the response
+        self.output.push_back(0xFF);
+        self.output.extend(data);
+    }
+
+    fn respond_with_block(&mut self, status: u8, data: &[u8]) {
+        self.respond(&[status, 0xFF, START_BLOCK_TOKEN]);
+        self.output.extend(data);
+        // The CRC isn't checked in SPI mode, so it's not calculated
+        self.output.extend([0xFF, 0xFF]);
+    }
+
+    fn run_command(&mut self, command: &[u8]) {
+        let index = command[0] & 0x3F;
+        let arg = u32::from_be_bytes([command[1], command[2], command[3], command[4]]);
+        let app_command = self.app_command;
+        self.app_command = false;
+        log::debug!(target: DEV_NAME, "{}{} with argument {:08x}", if app_command { "ACMD" } else { "CMD" }, index, arg);
+
+        match (app_command, index) {
+            (_, cmd::GO_IDLE_STATE) => {
+                self.initialized = false;
+                self.respond(&[r1::IDLE]);
+            },
+            (_, cmd::SEND_IF_COND) => {
+                let status = self.status();
+                self.respond(&[status, 0x00, 0x00, (arg >> 8) as u8 & 0x0F, arg as u8]);
+            },
+            (_, cmd::APP_CMD) => {
+                self.app_command = true;
+                let status = self.status();
+                self.respond(&[status]);
+            },
+            (true, cmd::SD_SEND_OP_COND) => {
+                self.initialized = true;
+                self.respond(&[r1::READY]);
+            },
+            (_, cmd::READ_OCR) => {
+                let status = self.status();
+                let ocr = OCR.to_be_bytes();
+                self.respond(&[status, ocr[0], ocr[1], ocr[2], ocr[3]]);
+            },
+            (_, cmd::SEND_CSD) => {
+                let csd = self.csd();
+                let status = self.status();
+                self.respond_with_block(status, &csd);
+            },
+            (_, cmd::SEND_CID) => {
+                let cid = *b"\x00MOMOA  \x10\x00\x00\x00\x01\x01\x80\x01";
+                let status = self.status();
+                self.respond_with_block(status, &cid);
+            },
+            (_, cmd::STOP_TRANSMISSION) => {
+                let status = self.status();
+                self.respond(&[0xFF, status]);
+            },
+            (_, cmd::SET_BLOCKLEN) => {
+                let status = if arg as usize == BLOCK_SIZE {
+                    self.status()
+                } else {
+                    self.status() | r1::PARAMETER_ERROR
+                };
+                self.respond(&[status]);
+            },
+            (_, cmd::READ_SINGLE_BLOCK) if self.initialized => {
+                let block = arg as usize;
+                if block >= self.blocks() {
+                    self.respond(&[r1::ADDRESS_ERROR]);
+                } else {
+                    let offset = block * BLOCK_SIZE;
+                    let data = self.contents[offset..offset + BLOCK_SIZE].to_vec();
+                    self.respond_with_block(r1::READY, &data);
+                }
+            },
+            (_, cmd::WRITE_BLOCK) if self.initialized => {
+                let block = arg as usize;
+                if block >= self.blocks() {
+                    self.respond(&[r1::ADDRESS_ERROR]);
+                } else {
+                    self.respond(&[r1::READY]);
+                    self.state = CardState::WriteToken(block);
+                }
+            },
+            _ => {
+                log::warn!(target: DEV_NAME, "unsupported command {} with argument {:08x}", index, arg);
+                let status = self.status();
+                self.respond(&[status | r1::ILLEGAL_COMMAND]);
+            },
+        }
+    }
+
+    /// Returns a version 2 (high capacity) card specific data register, which has the size of the card
+    fn csd(&self) -> [u8; 16] {
+        let c_size = (self.blocks() / 1024).saturating_sub(1) as u32;
+        [
+            0x40,
+            0x0E,
+            0x00,
+            0x32,
+            0x5B,
+            0x59,
+            0x00,
+            (c_size >> 16) as u8 & 0x3F,
+            (c_size >> 8) as u8,
+            c_size as u8,
+            0x7F,
+            0x80,
+            0x0A,
+            0x40,
+            0x00,
+            0x01,
+        ]
+    }
+
+    fn write_block(&mut self, block: usize, data: &[u8]) -> Result<(), Error> {
+        let offset = block * BLOCK_SIZE;
+        self.contents[offset..offset + BLOCK_SIZE].copy_from_slice(data);
+
+        if let Some(filename) = self.filename.as_ref() {
+            let mut file = fs::OpenOptions::new()
+                .write(true)
+                .open(filename)
+                .map_err(|err| Error::new(format!("{}: error opening {}: {}", DEV_NAME, filename, err)))?;
+            file.seek(SeekFrom::Start(offset as u64))
+                .and_then(|_| file.write_all(data))
+                .map_err(|err| Error::new(format!("{}: error writing to {}: {}", DEV_NAME, filename, err)))?;
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self, input: u8) {
+        match std::mem::replace(&mut self.state, CardState::Idle) {
+            // Commands start with the bits 01, and anything else (usually 0xFF) is ignored
+            CardState::Idle if input & 0xC0 == 0x40 => {
+                self.state = CardState::Command(vec![input]);
+            },
+            CardState::Idle => {},
+            CardState::Command(mut command) => {
+                command.push(input);
+                if command.len() == 6 {
+                    self.run_command(&command);
+                } else {
+                    self.state = CardState::Command(command);
+                }
+            },
+            CardState::WriteToken(block) if input == START_BLOCK_TOKEN => {
+                self.state = CardState::WriteData(block, Vec::with_capacity(BLOCK_SIZE + 2));
+            },
+            CardState::WriteToken(block) => {
+                self.state = CardState::WriteToken(block);
+            },
+            CardState::WriteData(block, mut data) => {
+                data.push(input);
+                if data.len() < BLOCK_SIZE + 2 {
+                    self.state = CardState::WriteData(block, data);
+                    return;
+                }
+
+                let response = match self.write_block(block, &data[..BLOCK_SIZE]) {
+                    Ok(()) => DATA_ACCEPTED,
+                    Err(err) => {
+                        log::error!("{}", err);
+                        DATA_WRITE_ERROR
+                    },
+                };
+                // The data response is followed by a short busy period
+                self.output.extend([response, 0x00, 0x00]);
+            },
+        }
+    }
+}
+
+impl SpiDevice for SdCard {
+    fn select(&mut self, selected: bool) {
+        self.selected = selected;
+        if !selected {
+            self.output.clear();
+        }
+    }
+
+    fn peek(&self) -> u8 {
+        if self.selected {
+            self.output.front().copied().unwrap_or(0xFF)
+        } else {
+            0xFF
+        }
+    }
+
+    fn transfer(&mut self, input: u8) -> u8 {
+        if !self.selected {
+            return 0xFF;
+        }
+
+        let output = self.output.pop_front().unwrap_or(0xFF);
+        self.receive(input);
+        output
+    }
+}
@@ -0,0 +1,416 @@
+//! Semihosting device for guest programs to communicate with the host
+//!
+//! The device has a set of 32-bit registers, and a guest program makes a call by writing the argument to
+//! the `ARGUMENT` register, and then writing the call number to the `COMMAND` register, which performs the
+//! call immediately.  The result can then be read from the `RESULT` register.  Rather than doing this
+//! directly, guest programs are expected to call the small handlers returned by [`Semihosting::m68k_handlers`],
+//! which the machine places at the trap vectors.
+//!
+//! | Offset | Register   | Description                                                   |
+//! |--------|------------|---------------------------------------------------------------|
+//! | 0x00   | COMMAND    | write the call number to perform the call                     |
+//! | 0x04   | ARGUMENT   | the argument of the call                                      |
+//! | 0x08   | RESULT     | the result of the last call                                   |
+//! | 0x0C   | FAULT_PC   | the pc of an unhandled exception (written by the m68k handler) |
+//! | 0x10   | FAULT      | the m68k format/vector word of an unhandled exception         |
+//!
+//! On the m68k, the call is made with `trap #15` with the call number in `d0`, the argument in `d1`, and
+//! the result returned in `d0`.
+//!
+//! | Number | Call      | Argument              | Result                                          |
+//! |--------|-----------|-----------------------|-------------------------------------------------|
+//! | 0      | EXIT      | exit code             | stops the simulation; 0 means the tests passed  |
+//! | 1      | PUTCHAR   | character             | the character                                   |
+//! | 2      | GETCHAR   |                       | the next character from stdin, or -1 at the end |
+//! | 3      | SBRK      | signed increment      | the previous end of the heap, or -1             |
+//! | 4      | NAME      | character             | append a character to the name of the next file |
+//! | 5      | OPEN      |                       | the handle of the named file, or -1             |
+//! | 6      | READ      | handle                | the next byte of the file, or -1 at the end     |
+//! | 7      | CLOSE     | handle                | 0, or -1 if the handle was invalid              |
+//!
+//! Files can only be opened for reading from inside the sandbox directory given to the device, and names
+//! that are absolute or which contain `..` are rejected.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf, Component};
+
+use femtos::{Instant, Duration};
+
+use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable, strict};
+
+
+#[rustfmt::skip]
+mod reg {
+    use super::Address;
+    pub(super) const COMMAND: Address   = 0x00;
+    pub(super) const ARGUMENT: Address  = 0x04;
+    pub(super) const RESULT: Address    = 0x08;
+    pub(super) const FAULT_PC: Address  = 0x0C;
+    pub(super) const FAULT: Address     = 0x10;
+}
+
+#[rustfmt::skip]
+mod call {
+    pub(super) const EXIT: u32      = 0;
+    pub(super) const PUTCHAR: u32   = 1;
+    pub(super) const GETCHAR: u32   = 2;
+    pub(super) const SBRK: u32      = 3;
+    pub(super) const NAME: u32      = 4;
+    pub(super) const OPEN: u32      = 5;
+    pub(super) const READ: u32      = 6;
+    pub(super) const CLOSE: u32     = 7;
+}
+
+/// The m68k exception vector used for semihosting calls (`trap #15`)
+pub const M68K_TRAP_VECTOR: usize = 32 + 15;
+
+const FAILED: u32 = -1_i32 as u32;
+
+/// How often, in microseconds, to check if the guest program has exited or faulted
+const POLL_INTERVAL_US: u64 = 100;
+
+const DEV_NAME: &str = "semihosting";
+
+
+pub struct Semihosting {
+    big_endian: bool,
+    argument: u32,
+    result: u32,
+    fault_pc: u32,
+    heap: Option<(Address, Address)>,
+    sandbox: Option<PathBuf>,
+    name: String,
+    files: Vec<Option<(Vec<u8>, usize)>>,
+    exit: Option<Error>,
+}
+
+impl Semihosting {
+    /// Create a new device for a cpu with the given byte order, which determines how register values are accessed
+    pub fn new(big_endian: bool) -> Self {
+        Self {
+            big_endian,
+            argument: 0,
+            result: 0,
+            fault_pc: 0,
+            heap: None,
+            sandbox: None,
+            name: String::new(),
+            files: vec![],
+            exit: None,
+        }
+    }
+
+    /// Allow the SBRK call to allocate memory between the given start address and limit
+    pub fn with_heap(mut self, start: Address, limit: Address) -> Self {
+        self.heap = Some((start, limit));
+        self
+    }
+
+    /// Allow the guest program to read files from the given directory
+    pub fn with_sandbox<P: AsRef<Path>>(mut self, directory: P) -> Self {
+        self.sandbox = Some(directory.as_ref().to_path_buf());
+        self
+    }
+
+    /// Returns the m68k code of the exception handlers, given the address the device is mapped to
+    ///
+    /// The first handler is for the `trap #15` vector, and the second, starting at the returned offset,
+    /// is for all other exceptions, which reports the vector and pc before stopping the simulation.  The
+    /// second handler requires a 68010 or later because it uses the vector offset in the exception frame.
+    pub fn m68k_handlers(base: Address) -> (Vec<u8>, usize) {
+        let addr = |offset: Address| ((base + offset) as u32).to_be_bytes();
+
+        let mut code = vec![];
+        code.extend_from_slice(&[0x23, 0xC1]); // move.l  %d1, (ARGUMENT).l
+        code.extend_from_slice(&addr(reg::ARGUMENT));
+        code.extend_from_slice(&[0x23, 0xC0]); // move.l  %d0, (COMMAND).l
+        code.extend_from_slice(&addr(reg::COMMAND));
+        code.extend_from_slice(&[0x20, 0x39]); // move.l  (RESULT).l, %d0
+        code.extend_from_slice(&addr(reg::RESULT));
+        code.extend_from_slice(&[0x4E, 0x73]); // rte
+
+        let fault_offset = code.len();
+        code.extend_from_slice(&[0x23, 0xEF, 0x00, 0x02]); // move.l  (2,%sp), (FAULT_PC).l
+        code.extend_from_slice(&addr(reg::FAULT_PC));
+        code.extend_from_slice(&[0x33, 0xEF, 0x00, 0x06]); // move.w  (6,%sp), (FAULT).l
+        code.extend_from_slice(&addr(reg::FAULT));
+        code.extend_from_slice(&[0x60, 0xFE]); // bra.s  .
+
+        (code, fault_offset)
+    }
+
+    fn run_call(&mut self, number: u32) -> u32 {
+        match number {
+            call::EXIT => {
+                self.exit = Some(Error::exit(self.argument as i32));
+                0
+            },
+            call::PUTCHAR => {
+                let mut stdout = io::stdout();
+                let _ = stdout.write_all(&[self.argument as u8]);
+                let _ = stdout.flush();
+                self.argument
+            },
+            call::GETCHAR => {
+                let mut buffer = [0];
+                match io::stdin().read(&mut buffer) {
+                    Ok(1) => buffer[0] as u32,
+                    _ => FAILED,
+                }
+            },
+            call::SBRK => self.sbrk(self.argument as i32),
+            call::NAME => {
+                self.name.push(self.argument as u8 as char);
+                0
+            },
+            call::OPEN => {
+                let name = std::mem::take(&mut self.name);
+                self.open(&name)
+            },
+            call::READ => match self.files.get_mut(self.argument as usize) {
+                Some(Some((contents, position))) if *position < contents.len() => {
+                    *position += 1;
+                    contents[*position - 1] as u32
+                },
+                _ => FAILED,
+            },
+            call::CLOSE => match self.files.get_mut(self.argument as usize) {
+                Some(file) if file.is_some() => {
+                    *file = None;
+                    0
+                },
+                _ => FAILED,
+            },
+            _ => {
+                log::warn!(target: DEV_NAME, "unknown call {}", number);
+                FAILED
+            },
+        }
+    }
+
+    fn sbrk(&mut self, increment: i32) -> u32 {
+        let (brk, limit) = match self.heap.as_mut() {
+            Some(heap) => heap,
+            None => return FAILED,
+        };
+
+        let next = *brk as i64 + increment as i64;
+        if next < 0 || next as Address > *limit {
+            return FAILED;
+        }
+        let previous = *brk;
+        *brk = next as Address;
+        previous as u32
+    }
+
+    fn open(&mut self, name: &str) -> u32 {
+        let sandbox = match self.sandbox.as_ref() {
+            Some(sandbox) => sandbox,
+            None => {
+                log::warn!(target: DEV_NAME, "attempted to open {:?} but no sandbox directory was given", name);
+                return FAILED;
+            },
+        };
+
+        let path = Path::new(name);
+        if path.components().any(|component| !matches!(component, Component::Normal(_))) {
+            log::warn!(target: DEV_NAME, "refusing to open {:?} because it's outside of the sandbox", name);
+            return FAILED;
+        }
+
+        match fs::read(sandbox.join(path)) {
+            Ok(contents) => {
+                let handle = match self.files.iter().position(|file| file.is_none()) {
+                    Some(handle) => handle,
+                    None => {
+                        self.files.push(None);
+                        self.files.len() - 1
+                    },
+                };
+                self.files[handle] = Some((contents, 0));
+                handle as u32
+            },
+            Err(_) => FAILED,
+        }
+    }
+
+    fn register_bytes(&self, value: u32) -> [u8; 4] {
+        if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    }
+}
+
+impl Addressable for Semihosting {
+    fn size(&self) -> usize {
+        0x20
+    }
+
+    fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        let value = match addr & !0x3 {
+            reg::ARGUMENT => self.argument,
+            reg::RESULT => self.result,
+            reg::FAULT_PC => self.fault_pc,
+            _ => 0,
+        };
+
+        let bytes = self.register_bytes(value);
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = bytes.get((addr as usize & 0x3) + i).cloned().unwrap_or(0);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        let offset = addr as usize & 0x3;
+        let register = addr & !0x3;
+        let previous = match register {
+            // Writing to the start of a register clears it, so that narrower cpus don't leave stale upper bytes
+            _ if offset == 0 => 0,
+            reg::ARGUMENT => self.argument,
+            reg::FAULT_PC => self.fault_pc,
+            _ => 0,
+        };
+
+        let mut bytes = self.register_bytes(previous);
+        for (i, byte) in data.iter().enumerate() {
+            if let Some(target) = bytes.get_mut(offset + i) {
+                *target = *byte;
+            }
+        }
+        let value = if self.big_endian {
+            // A narrower write to a big endian register is right aligned, like the m68k's byte and word writes
+            u32::from_be_bytes(bytes) >> (8 * (4 - (offset + data.len()).min(4)))
+        } else {
+            u32::from_le_bytes(bytes)
+        };
+
+        match register {
+            reg::COMMAND => self.result = self.run_call(value),
+            reg::ARGUMENT => self.argument = value,
+            reg::FAULT_PC => self.fault_pc = value,
+            reg::FAULT => {
+                let vector = (value & 0x0FFF) / 4;
+                self.exit = Some(Error::new(format!("{}: unhandled exception {} at pc {:08x}", DEV_NAME, vector, self.fault_pc)));
+            },
+            _ => strict::unhandled(DEV_NAME, format!("unhandled write {:0x} to {:0x}", value, addr)),
+        }
+        Ok(())
+    }
+}
+
+impl Steppable for Semihosting {
+    fn step(&mut self, _system: &System) -> Result<Duration, Error> {
+        // The exit is reported from here instead of from the write, so that the error isn't absorbed by the cpu
+        match self.exit.take() {
+            Some(err) => Err(err),
+            None => Ok(Duration::from_micros(POLL_INTERVAL_US)),
+        }
+    }
+}
+
+impl Transmutable for Semihosting {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use moa_core::EmulatorErrorKind;
+
+    use super::*;
+
+    /// Make a call the way the m68k handler does, with long word writes to the argument and then the command
+    fn call(device: &mut Semihosting, number: u32, argument: u32) -> u32 {
+        device.write(Instant::START, reg::ARGUMENT, &argument.to_be_bytes()).unwrap();
+        device.write(Instant::START, reg::COMMAND, &number.to_be_bytes()).unwrap();
+        let mut data = [0; 4];
+        device.read(Instant::START, reg::RESULT, &mut data).unwrap();
+        u32::from_be_bytes(data)
+    }
+
+    fn open(device: &mut Semihosting, name: &str) -> u32 {
+        for ch in name.chars() {
+            call(device, call::NAME, ch as u32);
+        }
+        call(device, call::OPEN, 0)
+    }
+
+    fn read_file(device: &mut Semihosting, handle: u32) -> Vec<u8> {
+        let mut contents = vec![];
+        loop {
+            match call(device, call::READ, handle) {
+                FAILED => return contents,
+                byte => contents.push(byte as u8),
+            }
+        }
+    }
+
+    fn sandbox(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("moa-semihosting-{}-{}", name, std::process::id()));
+        fs::create_dir_all(directory.join("inside")).unwrap();
+        fs::write(directory.join("inside").join("data.txt"), b"hello").unwrap();
+        fs::write(directory.join("secret.txt"), b"secret").unwrap();
+        directory
+    }
+
+    #[test]
+    fn putchar_returns_the_character() {
+        let mut device = Semihosting::new(true);
+        assert_eq!(call(&mut device, call::PUTCHAR, b'\n' as u32), b'\n' as u32);
+    }
+
+    #[test]
+    fn files_are_read_from_the_sandbox() {
+        let directory = sandbox("read");
+        let mut device = Semihosting::new(true).with_sandbox(directory.join("inside"));
+
+        let handle = open(&mut device, "data.txt");
+        assert_ne!(handle, FAILED);
+        assert_eq!(read_file(&mut device, handle), b"hello");
+        assert_eq!(call(&mut device, call::CLOSE, handle), 0);
+        assert_eq!(call(&mut device, call::CLOSE, handle), FAILED);
+        assert_eq!(call(&mut device, call::READ, handle), FAILED);
+
+        // The name is cleared by each open, so the next file can be named
+        assert_eq!(open(&mut device, "missing.txt"), FAILED);
+        assert_eq!(open(&mut device, "data.txt"), handle);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn files_outside_of_the_sandbox_cannot_be_opened() {
+        let directory = sandbox("escape");
+        let mut device = Semihosting::new(true).with_sandbox(directory.join("inside"));
+
+        assert_eq!(open(&mut device, "../secret.txt"), FAILED);
+        assert_eq!(open(&mut device, "./../secret.txt"), FAILED);
+        assert_eq!(open(&mut device, directory.join("secret.txt").to_str().unwrap()), FAILED);
+
+        let mut device = Semihosting::new(true);
+        assert_eq!(open(&mut device, "data.txt"), FAILED);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn exit_stops_the_simulation_on_the_next_step() {
+        let system = System::default();
+        let mut device = Semihosting::new(true);
+        assert!(device.step(&system).is_ok());
+
+        call(&mut device, call::EXIT, -2_i32 as u32);
+        match device.step(&system) {
+            Err(Error::Emulator(EmulatorErrorKind::Exit(code), _)) => assert_eq!(code, -2),
+            result => panic!("expected an exit, but got {:?}", result),
+        }
+        assert!(device.step(&system).is_ok());
+    }
+}
@@ -0,0 +1,156 @@
+//! A GPIO port for bit-banging an SPI bus with a single device attached
+//!
+//! The `GPIO` register has the clock (bit 0), data out (bit 1), and active low chip select (bit 2) outputs,
+//! and the data in (bit 7) input.  The bus uses SPI mode 0, where the data is sampled on the rising edge of
+//! the clock, and shifted out on the falling edge.  For speed, a whole byte can also be exchanged at once by
+//! writing it to the `DATA` register, after which the byte received can be read from the same register.
+
+use femtos::Instant;
+
+use moa_core::{Error, Address, Addressable, Transmutable, strict};
+
+
+#[rustfmt::skip]
+mod reg {
+    use super::Address;
+    pub(super) const GPIO: Address      = 0x00;
+    pub(super) const DATA: Address      = 0x01;
+}
+
+#[rustfmt::skip]
+mod bits {
+    pub(super) const SCLK: u8           = 0x01;
+    pub(super) const MOSI: u8           = 0x02;
+    pub(super) const CS: u8             = 0x04;
+    pub(super) const MISO: u8           = 0x80;
+}
+
+const DEV_NAME: &str = "spi";
+
+
+/// A device which can be attached to an SPI bus, and which exchanges a byte for every byte it receives
+pub trait SpiDevice {
+    fn select(&mut self, selected: bool);
+
+    /// Returns the next byte the device will send, which must not depend on the byte it's about to receive
+    fn peek(&self) -> u8;
+
+    /// Receive a byte from the bus, and return the byte that was sent at the same time
+    fn transfer(&mut self, input: u8) -> u8;
+}
+
+pub struct SpiGpio {
+    device: Box<dyn SpiDevice>,
+    outputs: u8,
+    bit_count: u8,
+    input_shift: u8,
+    output_shift: u8,
+    load_output: bool,
+    last_data: u8,
+}
+
+impl SpiGpio {
+    pub fn new<D: SpiDevice + 'static>(device: D) -> Self {
+        Self {
+            device: Box::new(device),
+            outputs: bits::CS,
+            bit_count: 0,
+            input_shift: 0,
+            output_shift: 0xFF,
+            load_output: false,
+            last_data: 0xFF,
+        }
+    }
+
+    fn is_selected(&self) -> bool {
+        self.outputs & bits::CS == 0
+    }
+
+    fn set_outputs(&mut self, value: u8) {
+        let previous = self.outputs;
+        self.outputs = value & (bits::SCLK | bits::MOSI | bits::CS);
+        let changed = previous ^ self.outputs;
+
+        if changed & bits::CS != 0 {
+            self.device.select(self.is_selected());
+            self.bit_count = 0;
+            self.output_shift = self.device.peek();
+            self.load_output = false;
+        }
+
+        if !self.is_selected() || changed & bits::SCLK == 0 {
+            return;
+        }
+
+        if self.outputs & bits::SCLK != 0 {
+            let mosi = (self.outputs & bits::MOSI != 0) as u8;
+            self.input_shift = (self.input_shift << 1) | mosi;
+            self.bit_count += 1;
+            if self.bit_count == 8 {
+                self.last_data = self.device.transfer(self.input_shift);
+                self.bit_count = 0;
+                self.load_output = true;
+            }
+        } else if self.load_output {
+            self.output_shift = self.device.peek();
+            self.load_output = false;
+        } else {
+            self.output_shift <<= 1;
+        }
+    }
+
+    fn exchange_byte(&mut self, value: u8) {
+        if !self.is_selected() {
+            log::debug!(target: DEV_NAME, "ignoring transfer of {:02x} while the device isn't selected", value);
+            self.last_data = 0xFF;
+            return;
+        }
+
+        self.last_data = self.device.transfer(value);
+        self.bit_count = 0;
+        self.output_shift = self.device.peek();
+        self.load_output = false;
+    }
+}
+
+impl Addressable for SpiGpio {
+    fn size(&self) -> usize {
+        2
+    }
+
+    fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = match addr + i as Address {
+                reg::GPIO => {
+                    // The data in line is pulled up when the device isn't driving it
+                    let miso = if !self.is_selected() || self.output_shift & 0x80 != 0 {
+                        bits::MISO
+                    } else {
+                        0
+                    };
+                    self.outputs | miso
+                },
+                reg::DATA => self.last_data,
+                _ => 0,
+            };
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        for (i, byte) in data.iter().enumerate() {
+            match addr + i as Address {
+                reg::GPIO => self.set_outputs(*byte),
+                reg::DATA => self.exchange_byte(*byte),
+                _ => strict::unhandled_write(DEV_NAME, addr + i as Address, &[*byte]),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Transmutable for SpiGpio {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+}
@@ -1,2 +1,2 @@
 mod mos6522;
-pub use crate::mos6522::Mos6522;
+pub use crate::mos6522::{Mos6522, Port};
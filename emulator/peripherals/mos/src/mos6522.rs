@@ -1,6 +1,6 @@
 use femtos::{Instant, Duration};
 
-use moa_core::{Error, System, Address, Addressable, Steppable, Transmutable};
+use moa_core::{Error, System, Address, Addressable, Steppable, Transmutable, strict};
 use moa_signals::{Signal, ObservableSignal, Observable};
 
 #[rustfmt::skip]
@@ -10,6 +10,8 @@ mod reg {
     pub(super) const OUTPUT_A: Address     = 0x01;
     pub(super) const DDR_B: Address        = 0x02;
     pub(super) const DDR_A: Address        = 0x03;
+    pub(super) const SHIFT: Address        = 0x0A;
+    pub(super) const AUX_CTRL: Address     = 0x0B;
     pub(super) const PERIPH_CTRL: Address  = 0x0C;
     pub(super) const INT_FLAGS: Address    = 0x0D;
     pub(super) const INT_ENABLE: Address   = 0x0E;
@@ -19,10 +21,22 @@ mod reg {
 
 const DEV_NAME: &str = "mos6522";
 
+/// The bit of the interrupt flags register that's set when any enabled interrupt is active
+const INT_ANY: u8 = 0x80;
+/// The interrupt flag that's set when 8 bits have been shifted in or out of the shift register
+const INT_SHIFT: u8 = 0x04;
+
+/// The bits of the auxiliary control register that select the shift register's mode
+const ACR_SHIFT_MODE: u8 = 0x1C;
+const ACR_SHIFT_IN_EXTERNAL: u8 = 0x0C;
+const ACR_SHIFT_OUT_EXTERNAL: u8 = 0x1C;
+
 
 pub struct Port {
     pub data: u8,
     pub ddr: u8,
+    /// The levels that other devices drive onto the pins, which are read from the pins that are inputs
+    pub input: u8,
 }
 
 impl Default for Port {
@@ -30,10 +44,18 @@ impl Default for Port {
         Self {
             data: 0xff,
             ddr: 0,
+            input: 0xff,
         }
     }
 }
 
+impl Port {
+    /// Returns the value read from the port, which is the output register for outputs, and the pins for inputs
+    pub fn read(&self) -> u8 {
+        (self.data & self.ddr) | (self.input & !self.ddr)
+    }
+}
+
 
 pub struct Mos6522 {
     pub port_a: ObservableSignal<Port>,
@@ -42,6 +64,10 @@ pub struct Mos6522 {
     pub interrupt: Signal<bool>,
     pub interrupt_flags: u8,
     pub interrupt_enable: u8,
+    pub aux_ctrl: u8,
+    pub shift_register: u8,
+    /// True when a byte has been written to the shift register and not yet shifted out
+    shift_out_pending: bool,
 }
 
 impl Default for Mos6522 {
@@ -53,7 +79,48 @@ impl Default for Mos6522 {
             interrupt: Signal::new(false),
             interrupt_flags: 0,
             interrupt_enable: 0,
+            aux_ctrl: 0,
+            shift_register: 0,
+            shift_out_pending: false,
+        }
+    }
+}
+
+impl Mos6522 {
+    /// Set the given bits of the interrupt flags register, for an event on one of the control lines
+    pub fn set_interrupt_flags(&mut self, flags: u8) {
+        self.interrupt_flags |= flags & 0x7F;
+        self.update_interrupt();
+    }
+
+    /// Returns true if the shift register is waiting for a device to clock the byte written to it out of CB2
+    pub fn is_shifting_out(&self) -> bool {
+        self.shift_out_pending && (self.aux_ctrl & ACR_SHIFT_MODE) == ACR_SHIFT_OUT_EXTERNAL
+    }
+
+    /// Returns true if the shift register is set to shift in a byte clocked by a device on CB1
+    pub fn is_shifting_in(&self) -> bool {
+        (self.aux_ctrl & ACR_SHIFT_MODE) == ACR_SHIFT_IN_EXTERNAL
+    }
+
+    /// Clock the byte written to the shift register out to a device, which completes the shift
+    pub fn shift_out(&mut self) -> Option<u8> {
+        if !self.is_shifting_out() {
+            return None;
         }
+        self.shift_out_pending = false;
+        self.set_interrupt_flags(INT_SHIFT);
+        Some(self.shift_register)
+    }
+
+    /// Clock a byte from a device into the shift register, which completes the shift
+    pub fn shift_in(&mut self, data: u8) {
+        self.shift_register = data;
+        self.set_interrupt_flags(INT_SHIFT);
+    }
+
+    fn update_interrupt(&mut self) {
+        self.interrupt.set((self.interrupt_flags & self.interrupt_enable) != 0);
     }
 }
 
@@ -65,10 +132,10 @@ impl Addressable for Mos6522 {
     fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
         match addr {
             reg::OUTPUT_B => {
-                data[0] = self.port_b.borrow_mut().data;
+                data[0] = self.port_b.borrow_mut().read();
             },
             reg::OUTPUT_A => {
-                data[0] = self.port_a.borrow_mut().data;
+                data[0] = self.port_a.borrow_mut().read();
             },
             reg::DDR_B => {
                 data[0] = self.port_b.borrow_mut().ddr;
@@ -76,22 +143,33 @@ impl Addressable for Mos6522 {
             reg::DDR_A => {
                 data[0] = self.port_a.borrow_mut().ddr;
             },
+            reg::SHIFT => {
+                data[0] = self.shift_register;
+                self.interrupt_flags &= !INT_SHIFT;
+                self.update_interrupt();
+            },
+            reg::AUX_CTRL => {
+                data[0] = self.aux_ctrl;
+            },
             reg::INT_FLAGS => {
                 data[0] = self.interrupt_flags;
+                if self.interrupt.get() {
+                    data[0] |= INT_ANY;
+                }
             },
             reg::INT_ENABLE => {
                 data[0] = self.interrupt_enable | 0x80;
             },
             _ => {
-                log::warn!("{}: !!! unhandled read from {:0x}", DEV_NAME, addr);
+                strict::unhandled_read(DEV_NAME, addr);
             },
         }
-        log::debug!("{}: read from register {:x} of {:?}", DEV_NAME, addr, data);
+        log::debug!(target: DEV_NAME, "read from register {:x} of {:?}", addr, data);
         Ok(())
     }
 
     fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
-        log::debug!("{}: write to register {:x} with {:x}", DEV_NAME, addr, data[0]);
+        log::debug!(target: DEV_NAME, "write to register {:x} with {:x}", addr, data[0]);
         match addr {
             reg::OUTPUT_B => {
                 self.port_b.borrow_mut().data = data[0];
@@ -109,6 +187,14 @@ impl Addressable for Mos6522 {
                 self.port_a.borrow_mut().ddr = data[0];
                 self.port_a.notify();
             },
+            reg::SHIFT => {
+                self.shift_register = data[0];
+                self.shift_out_pending = true;
+                self.interrupt_flags &= !INT_SHIFT;
+            },
+            reg::AUX_CTRL => {
+                self.aux_ctrl = data[0];
+            },
             reg::PERIPH_CTRL => {
                 println!("SET TO {:?}", data[0]);
                 self.peripheral_ctrl = data[0];
@@ -118,9 +204,9 @@ impl Addressable for Mos6522 {
             },
             reg::INT_ENABLE => {
                 if (data[0] & 0x80) == 0 {
-                    self.interrupt_flags &= !data[0];
+                    self.interrupt_enable &= !data[0];
                 } else {
-                    self.interrupt_flags |= data[0];
+                    self.interrupt_enable |= data[0] & 0x7F;
                 }
             },
             reg::OUTPUT_A_NHS => {
@@ -128,9 +214,10 @@ impl Addressable for Mos6522 {
                 self.port_a.notify();
             },
             _ => {
-                log::warn!("{}: !!! unhandled write {:0x} to {:0x}", DEV_NAME, data[0], addr);
+                strict::unhandled_write(DEV_NAME, addr, data);
             },
         }
+        self.update_interrupt();
         Ok(())
     }
 }
@@ -1,2 +1,5 @@
 mod mc68681;
 pub use crate::mc68681::MC68681;
+
+mod mc68901;
+pub use crate::mc68901::{MC68901, MfpUsart};
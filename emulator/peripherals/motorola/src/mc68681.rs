@@ -1,6 +1,9 @@
 use femtos::{Instant, Duration, Frequency};
 
-use moa_core::{System, Error, Address, Steppable, Addressable, Transmutable};
+use moa_core::{
+    System, Error, Address, Steppable, Addressable, Transmutable, Waker, Register, RegisterBank, RegisterMapped, Access, Field,
+    read_registers, write_registers,
+};
 use moa_host::Tty;
 
 
@@ -22,6 +25,8 @@ const REG_ACR_WR: Address = 0x09;
 
 const REG_CTUR_WR: Address = 0x0D;
 const REG_CTLR_WR: Address = 0x0F;
+const REG_CUR_RD: Address = 0x0D;
+const REG_CLR_RD: Address = 0x0F;
 const REG_START_RD: Address = 0x1D;
 const REG_STOP_RD: Address = 0x1F;
 
@@ -55,8 +60,14 @@ const SR_RX_FULL: u8 = 0x02;
 const SR_RX_READY: u8 = 0x01;
 
 
+// Auxiliary Control Register Bits (ACR)
+const ACR_TIMER_MODE: u8 = 0x40;
+const ACR_TIMER_SOURCE: u8 = 0x70;
+const ACR_INPUT_CHANGE_ENABLE: u8 = 0x0F;
+
+
 // Interrupt Status/Mask Bits (ISR/IVR)
-//const ISR_INPUT_CHANGE: u8 = 0x80;
+const ISR_INPUT_CHANGE: u8 = 0x80;
 //const ISR_CH_B_BREAK_CHANGE: u8 = 0x40;
 const ISR_CH_B_RX_READY_FULL: u8 = 0x20;
 const ISR_CH_B_TX_READY: u8 = 0x10;
@@ -68,6 +79,48 @@ const ISR_CH_A_TX_READY: u8 = 0x01;
 
 const DEV_NAME: &str = "mc68681";
 
+/// How often to check for input from a tty that can't wake the device when input arrives
+const POLL_INTERVAL_US: u64 = 100;
+/// How often to step when there is nothing to do, since any input or register access will wake the device
+const IDLE_INTERVAL_US: u64 = 1_000_000;
+
+#[rustfmt::skip]
+const REGISTERS: &[Register<MC68681>] = &[
+    Register::new("MR1A/MR2A", REG_MR1A_MR2A, Access::Write),
+    Register::new("SRA", REG_SRA_RD, Access::Read).on_read(|chip, _, _| chip.port_a.status),
+    Register::new("CSRA", REG_CSRA_WR, Access::Write),
+    Register::new("CRA", REG_CRA_WR, Access::Write).on_write(|chip, _, data| chip.port_command(0, data)),
+    Register::new("RBA", REG_RBA_RD, Access::Read).on_read(|chip, _, _| chip.receive(0)),
+    Register::new("TBA", REG_TBA_WR, Access::Write).on_write(|chip, _, data| chip.transmit(0, data)),
+    Register::new("IPCR", REG_IPCR_RD, Access::Read).on_read(|chip, _, _| chip.read_input_change()),
+    Register::new("ACR", REG_ACR_WR, Access::Write)
+        .with_fields(&[
+            Field::new("brg_set", 0x80),
+            Field::new("timer_source", ACR_TIMER_SOURCE),
+            Field::new("input_change_enable", ACR_INPUT_CHANGE_ENABLE),
+        ])
+        .on_write(MC68681::write_acr),
+    Register::new("ISR", REG_ISR_RD, Access::Read).on_read(|chip, _, _| chip.int_status),
+    Register::new("IMR", REG_IMR_WR, Access::Write),
+    Register::new("CUR", REG_CUR_RD, Access::Read).on_read(|chip, clock, _| chip.read_counter(clock, true)),
+    Register::new("CTUR", REG_CTUR_WR, Access::Write),
+    Register::new("CLR", REG_CLR_RD, Access::Read).on_read(|chip, clock, _| chip.read_counter(clock, false)),
+    Register::new("CTLR", REG_CTLR_WR, Access::Write),
+    Register::new("MR1B/MR2B", REG_MR1B_MR2B, Access::Write),
+    Register::new("SRB", REG_SRB_RD, Access::Read).on_read(|chip, _, _| chip.port_b.status),
+    Register::new("CSRB", REG_CSRB_WR, Access::Write),
+    Register::new("CRB", REG_CRB_WR, Access::Write).on_write(|chip, _, data| chip.port_command(1, data)),
+    Register::new("RBB", REG_RBB_RD, Access::Read).on_read(|chip, _, _| chip.receive(1)),
+    Register::new("TBB", REG_TBB_WR, Access::Write).on_write(|chip, _, data| chip.transmit(1, data)),
+    Register::new("IVR", REG_IVR_WR, Access::Write),
+    Register::new("INPUT", REG_INPUT_RD, Access::Read).on_read(|chip, _, _| chip.input_state),
+    Register::new("OPCR", REG_OPCR_WR, Access::Write),
+    Register::new("START", REG_START_RD, Access::Read).on_read(MC68681::start_counter),
+    Register::new("OUT_SET", REG_OUT_SET, Access::Write).on_write(|chip, _, data| chip.output_state |= data),
+    Register::new("STOP", REG_STOP_RD, Access::Read).on_read(MC68681::stop_counter),
+    Register::new("OUT_RESET", REG_OUT_RESET, Access::Write).on_write(|chip, _, data| chip.output_state &= !data),
+];
+
 #[derive(Default)]
 pub struct MC68681Port {
     tty: Option<Box<dyn Tty>>,
@@ -77,6 +130,8 @@ pub struct MC68681Port {
 
     rx_enabled: bool,
     input: u8,
+
+    wakes_on_input: bool,
 }
 
 impl MC68681Port {
@@ -87,6 +142,18 @@ impl MC68681Port {
         Ok(name)
     }
 
+    fn set_waker(&mut self, waker: &Waker) {
+        if let Some(tty) = self.tty.as_mut() {
+            let waker = waker.clone();
+            self.wakes_on_input = tty.on_input(Box::new(move || waker.wake()));
+        }
+    }
+
+    /// Returns true if the port is waiting for input from a tty that must be checked periodically
+    fn needs_polling(&self) -> bool {
+        self.rx_enabled && (self.status & SR_RX_READY) == 0 && self.tty.is_some() && !self.wakes_on_input
+    }
+
     pub fn send_byte(&mut self, data: u8) {
         self.tty.as_mut().map(|tty| tty.write(data));
         self.set_tx_status(false);
@@ -158,23 +225,24 @@ impl MC68681Port {
 pub struct MC68681 {
     frequency: Frequency,
 
-    acr: u8,
+    registers: RegisterBank<MC68681>,
     pub port_a: MC68681Port,
     pub port_b: MC68681Port,
 
-    int_mask: u8,
     int_status: u8,
-    int_vector: u8,
+    interrupt_priority: u8,
 
-    timer_preload: u16,
     timer_count: u16,
     is_timing: bool,
-    timer_divider: u16,
+    /// The level of the square wave output in timer mode, where a full cycle sets the counter ready interrupt
+    timer_output: bool,
+    last_tick: u64,
 
     input_pin_change: u8,
     input_state: u8,
-    output_conf: u8,
     output_state: u8,
+
+    waker: Option<Waker>,
 }
 
 impl Default for MC68681 {
@@ -182,41 +250,211 @@ impl Default for MC68681 {
         MC68681 {
             frequency: Frequency::from_hz(3_686_400),
 
-            acr: 0,
+            registers: RegisterBank::new(DEV_NAME, 0x20, REGISTERS).with_expected_unhandled(),
             port_a: MC68681Port::default(),
             port_b: MC68681Port::default(),
 
-            int_mask: 0,
             int_status: 0,
-            int_vector: 0,
+            interrupt_priority: 4,
 
-            timer_preload: 0,
             timer_count: 0,
             is_timing: true,
-            timer_divider: 0,
+            timer_output: false,
+            last_tick: 0,
 
             input_pin_change: 0,
             input_state: 0,
-            output_conf: 0,
             output_state: 0,
+
+            waker: None,
         }
     }
 }
 
 impl MC68681 {
+    pub fn with_interrupt_priority(mut self, priority: u8) -> Self {
+        self.interrupt_priority = priority;
+        self
+    }
+
     fn set_interrupt_flag(&mut self, flag: u8, value: bool) {
         self.int_status = (self.int_status & !flag) | (if value { flag } else { 0 });
     }
 
     fn check_interrupt_state(&mut self, system: &System) -> Result<(), Error> {
-        system
-            .get_interrupt_controller()
-            .set((self.int_status & self.int_mask) != 0, 4, self.int_vector)
+        let active = (self.int_status & self.registers.get(REG_IMR_WR)) != 0;
+        let vector = self.registers.get(REG_IVR_WR);
+        system.get_interrupt_controller().set(active, self.interrupt_priority, vector)
+    }
+
+    /// Step the device at the current clock, to update the state after a register access
+    fn wake(&self) {
+        if let Some(waker) = self.waker.as_ref() {
+            waker.wake();
+        }
+    }
+
+    /// The value the counter/timer is loaded with, from the upper and lower preload registers
+    fn timer_preload(&self) -> u16 {
+        ((self.registers.get(REG_CTUR_WR) as u16) << 8) | self.registers.get(REG_CTLR_WR) as u16
+    }
+
+    /// Returns the number of crystal clocks for each count of the counter/timer, or None if its clock source is
+    /// an input pin or transmitter clock, which aren't emulated
+    fn timer_prescaler(&self) -> Option<u64> {
+        match self.registers.get_field(REG_ACR_WR, ACR_TIMER_SOURCE) {
+            0b011 | 0b111 => Some(16),
+            0b110 => Some(1),
+            _ => None,
+        }
+    }
+
+    fn timer_tick(&self, clock: Instant) -> u64 {
+        let clocks = clock.as_duration() / self.frequency.period_duration();
+        clocks / self.timer_prescaler().unwrap_or(1)
+    }
+
+    /// The number of counts until the counter reaches zero, where a count of zero means the full 16-bit range
+    fn timer_remaining(&self) -> u64 {
+        if self.timer_count == 0 {
+            0x10000
+        } else {
+            self.timer_count as u64
+        }
+    }
+
+    fn update_timer(&mut self, clock: Instant) {
+        let tick = self.timer_tick(clock);
+        let counts = tick.saturating_sub(self.last_tick);
+        self.last_tick = tick;
+
+        if !self.is_timing || counts == 0 || self.timer_prescaler().is_none() {
+            return;
+        }
+
+        let remaining = self.timer_remaining();
+        if counts < remaining {
+            self.timer_count = (remaining - counts) as u16;
+            return;
+        }
+
+        if (self.registers.get(REG_ACR_WR) & ACR_TIMER_MODE) == 0 {
+            // In counter mode, the counter ready bit is set when it reaches zero, and it keeps counting down from 0xFFFF
+            self.set_interrupt_flag(ISR_TIMER_CHANGE, true);
+            self.timer_count = (0x10000 - (counts - remaining) % 0x10000) as u16;
+        } else {
+            // In timer mode, the output toggles each time the counter reloads, and the counter ready bit is set
+            // once for each full cycle of the output
+            let preload = self.timer_preload();
+            let period = if preload == 0 { 0x10000 } else { preload as u64 };
+            let reloads = 1 + (counts - remaining) / period;
+            if reloads >= 2 || self.timer_output {
+                self.set_interrupt_flag(ISR_TIMER_CHANGE, true);
+            }
+            self.timer_output ^= (reloads & 0x01) != 0;
+            self.timer_count = (period - (counts - remaining) % period) as u16;
+        }
+    }
+
+    fn port(&mut self, port: usize) -> &mut MC68681Port {
+        if port == 0 { &mut self.port_a } else { &mut self.port_b }
+    }
+
+    fn receive(&mut self, port: usize) -> u8 {
+        let flag = if port == 0 {
+            ISR_CH_A_RX_READY_FULL
+        } else {
+            ISR_CH_B_RX_READY_FULL
+        };
+        let port = self.port(port);
+        let data = port.input;
+        port.set_rx_status(false);
+        self.set_interrupt_flag(flag, false);
+        self.wake();
+        data
+    }
+
+    fn transmit(&mut self, port: usize, data: u8) {
+        let flag = if port == 0 { ISR_CH_A_TX_READY } else { ISR_CH_B_TX_READY };
+        log::debug!(target: DEV_NAME, "port {}: write {:x}", if port == 0 { 'a' } else { 'b' }, data);
+        self.port(port).send_byte(data);
+        self.set_interrupt_flag(flag, false);
+    }
+
+    fn port_command(&mut self, port: usize, data: u8) {
+        let flag = if port == 0 { ISR_CH_A_TX_READY } else { ISR_CH_B_TX_READY };
+        if let Some(value) = self.port(port).handle_command(data) {
+            self.set_interrupt_flag(flag, value);
+        }
+    }
+
+    fn write_acr(&mut self, clock: Instant, data: u8) {
+        // Count up to the current time using the previous clock source before changing it
+        self.update_timer(clock);
+        self.registers.set(REG_ACR_WR, data);
+        self.last_tick = self.timer_tick(clock);
+    }
+
+    fn read_input_change(&mut self) -> u8 {
+        // The upper bits are the current state of IP0 to IP3, and reading clears the change bits
+        let data = ((self.input_state & 0x0F) << 4) | (self.input_pin_change & 0x0F);
+        self.input_pin_change = 0;
+        self.set_interrupt_flag(ISR_INPUT_CHANGE, false);
+        self.wake();
+        data
+    }
+
+    fn read_counter(&mut self, clock: Instant, upper: bool) -> u8 {
+        self.update_timer(clock);
+        if upper {
+            (self.timer_count >> 8) as u8
+        } else {
+            self.timer_count as u8
+        }
+    }
+
+    fn start_counter(&mut self, clock: Instant, _: u8) -> u8 {
+        self.timer_count = self.timer_preload();
+        self.is_timing = true;
+        self.timer_output = false;
+        self.last_tick = self.timer_tick(clock);
+        self.wake();
+        0
+    }
+
+    fn stop_counter(&mut self, clock: Instant, _: u8) -> u8 {
+        if (self.registers.get(REG_ACR_WR) & ACR_TIMER_MODE) == 0 {
+            // Counter Mode
+            self.update_timer(clock);
+            self.is_timing = false;
+            self.timer_count = self.timer_preload();
+        } else {
+            // Timer Mode
+            // Do nothing except reset the ISR bit
+        }
+        self.set_interrupt_flag(ISR_TIMER_CHANGE, false);
+        self.wake();
+        0
+    }
+
+    /// Update the input pins, which sets the change bits for IP0 to IP3, and interrupts if the change is enabled
+    pub fn set_input(&mut self, pins: u8) {
+        let changed = (self.input_state ^ pins) & 0x0F;
+        self.input_state = pins & 0x3F;
+        if changed != 0 {
+            self.input_pin_change = (self.input_pin_change & 0xF0) | changed;
+            if (changed & self.registers.get(REG_ACR_WR) & ACR_INPUT_CHANGE_ENABLE) != 0 {
+                self.set_interrupt_flag(ISR_INPUT_CHANGE, true);
+            }
+            self.wake();
+        }
     }
 }
 
 impl Steppable for MC68681 {
     fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        self.update_timer(system.clock);
+
         if self.port_a.check_rx()? {
             self.set_interrupt_flag(ISR_CH_A_RX_READY_FULL, true);
         }
@@ -225,25 +463,6 @@ impl Steppable for MC68681 {
             self.set_interrupt_flag(ISR_CH_B_RX_READY_FULL, true);
         }
 
-        if self.is_timing {
-            self.timer_divider = self.timer_divider.wrapping_sub(1);
-            if self.timer_divider == 0 {
-                self.timer_divider = 1;
-                self.timer_count = self.timer_count.wrapping_sub(1);
-
-                if self.timer_count == 0 {
-                    self.set_interrupt_flag(ISR_TIMER_CHANGE, true);
-                    if (self.acr & 0x40) == 0 {
-                        self.is_timing = false;
-                    } else {
-                        self.timer_count = self.timer_preload;
-                    }
-                }
-            }
-        }
-
-        self.check_interrupt_state(system)?;
-
         if self.port_a.check_tx() {
             self.set_interrupt_flag(ISR_CH_A_TX_READY, true);
         }
@@ -252,7 +471,30 @@ impl Steppable for MC68681 {
             self.set_interrupt_flag(ISR_CH_B_TX_READY, true);
         }
 
-        Ok(self.frequency.period_duration())
+        self.check_interrupt_state(system)?;
+
+        // Step again when the timer expires, since input and register accesses will wake the device sooner
+        let mut clocks = if let Some(prescaler) = self.timer_prescaler().filter(|_| self.is_timing) {
+            self.timer_remaining() * prescaler
+        } else {
+            self.frequency.as_hz() as u64 * IDLE_INTERVAL_US / 1_000_000
+        };
+        if self.port_a.needs_polling() || self.port_b.needs_polling() {
+            clocks = clocks.min(self.frequency.as_hz() as u64 * POLL_INTERVAL_US / 1_000_000);
+        }
+        Ok(self.frequency.period_duration() * clocks.max(1) as u32)
+    }
+
+    fn set_waker(&mut self, waker: Waker) {
+        self.port_a.set_waker(&waker);
+        self.port_b.set_waker(&waker);
+        self.waker = Some(waker);
+    }
+}
+
+impl RegisterMapped for MC68681 {
+    fn register_bank(&mut self) -> &mut RegisterBank<Self> {
+        &mut self.registers
     }
 }
 
@@ -261,107 +503,14 @@ impl Addressable for MC68681 {
         0x30
     }
 
-    fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
-        match addr {
-            REG_SRA_RD => data[0] = self.port_a.status,
-            REG_RBA_RD => {
-                data[0] = self.port_a.input;
-                self.port_a.set_rx_status(false);
-                self.set_interrupt_flag(ISR_CH_A_RX_READY_FULL, false);
-            },
-            REG_SRB_RD => data[0] = self.port_b.status,
-            REG_RBB_RD => {
-                data[0] = self.port_b.input;
-                self.port_b.set_rx_status(false);
-                self.set_interrupt_flag(ISR_CH_B_RX_READY_FULL, false);
-            },
-            REG_ISR_RD => {
-                data[0] = self.int_status;
-            },
-            REG_IPCR_RD => {
-                data[0] = self.input_pin_change;
-            },
-            REG_INPUT_RD => {
-                data[0] = self.input_state;
-            },
-            REG_START_RD => {
-                self.timer_count = self.timer_preload;
-                self.is_timing = true;
-            },
-            REG_STOP_RD => {
-                if (self.acr & 0x40) == 0 {
-                    // Counter Mode
-                    self.is_timing = false;
-                    self.timer_count = self.timer_preload;
-                } else {
-                    // Timer Mode
-                    // Do nothing except reset the ISR bit
-                }
-                self.set_interrupt_flag(ISR_TIMER_CHANGE, false);
-            },
-            _ => {},
-        }
-
-        if addr != REG_SRA_RD && addr != REG_SRB_RD {
-            log::debug!("{}: read from {:0x} of {:0x}", DEV_NAME, addr, data[0]);
-        }
-
-        Ok(())
+    fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        read_registers(self, clock, addr, data)
     }
 
-    fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
-        log::debug!("{}: writing {:0x} to {:0x}", DEV_NAME, data[0], addr);
-        match addr {
-            REG_MR1A_MR2A | REG_MR1B_MR2B | REG_CSRA_WR | REG_CSRB_WR => {
-                // NOTE we aren't simulating the serial speeds, so we aren't doing anything with these settings atm
-            },
-            REG_ACR_WR => {
-                self.acr = data[0];
-            },
-            REG_TBA_WR => {
-                log::debug!("{}a: write {}", DEV_NAME, data[0] as char);
-                self.port_a.send_byte(data[0]);
-                self.set_interrupt_flag(ISR_CH_A_TX_READY, false);
-            },
-            REG_CRA_WR => {
-                if let Some(value) = self.port_a.handle_command(data[0]) {
-                    self.set_interrupt_flag(ISR_CH_A_TX_READY, value);
-                }
-            },
-            REG_TBB_WR => {
-                log::debug!("{}b: write {:x}", DEV_NAME, data[0]);
-                self.port_b.send_byte(data[0]);
-                self.set_interrupt_flag(ISR_CH_B_TX_READY, false);
-            },
-            REG_CRB_WR => {
-                if let Some(value) = self.port_b.handle_command(data[0]) {
-                    self.set_interrupt_flag(ISR_CH_B_TX_READY, value);
-                }
-            },
-            REG_CTUR_WR => {
-                self.timer_preload = (self.timer_preload & 0x00FF) | ((data[0] as u16) << 8);
-            },
-            REG_CTLR_WR => {
-                self.timer_preload = (self.timer_preload & 0xFF00) | (data[0] as u16);
-            },
-            REG_IMR_WR => {
-                self.int_mask = data[0];
-            },
-            REG_IVR_WR => {
-                self.int_vector = data[0];
-            },
-            REG_OPCR_WR => {
-                self.output_conf = data[0];
-            },
-            REG_OUT_SET => {
-                self.output_state |= data[0];
-            },
-            REG_OUT_RESET => {
-                self.output_state &= !data[0];
-            },
-            _ => {},
-        }
-        Ok(())
+    fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        // Any write can change the interrupt or transmitter state, which is updated when the device is stepped
+        self.wake();
+        write_registers(self, clock, addr, data)
     }
 }
 
@@ -0,0 +1,561 @@
+use femtos::{Instant, Duration, Frequency};
+
+use moa_core::{System, Error, Address, Steppable, Addressable, Transmutable, strict};
+use moa_host::Tty;
+
+
+#[rustfmt::skip]
+mod reg {
+    use super::Address;
+    pub(super) const GPDR: Address  = 0x01;
+    pub(super) const AER: Address   = 0x03;
+    pub(super) const DDR: Address   = 0x05;
+    pub(super) const IERA: Address  = 0x07;
+    pub(super) const IERB: Address  = 0x09;
+    pub(super) const IPRA: Address  = 0x0B;
+    pub(super) const IPRB: Address  = 0x0D;
+    pub(super) const ISRA: Address  = 0x0F;
+    pub(super) const ISRB: Address  = 0x11;
+    pub(super) const IMRA: Address  = 0x13;
+    pub(super) const IMRB: Address  = 0x15;
+    pub(super) const VR: Address    = 0x17;
+    pub(super) const TACR: Address  = 0x19;
+    pub(super) const TBCR: Address  = 0x1B;
+    pub(super) const TCDCR: Address = 0x1D;
+    pub(super) const TADR: Address  = 0x1F;
+    pub(super) const TBDR: Address  = 0x21;
+    pub(super) const TCDR: Address  = 0x23;
+    pub(super) const TDDR: Address  = 0x25;
+    pub(super) const SCR: Address   = 0x27;
+    pub(super) const UCR: Address   = 0x29;
+    pub(super) const RSR: Address   = 0x2B;
+    pub(super) const TSR: Address   = 0x2D;
+    pub(super) const UDR: Address   = 0x2F;
+}
+
+/// The interrupt channels in order of priority, where each channel's number is also its bit in the
+/// combined A/B interrupt registers, and the lower 4 bits of the interrupt vector
+#[rustfmt::skip]
+mod channel {
+    pub(super) const TIMER_D: u8    = 4;
+    pub(super) const TIMER_C: u8    = 5;
+    pub(super) const TIMER_B: u8    = 8;
+    pub(super) const TX_ERROR: u8   = 9;
+    pub(super) const TX_EMPTY: u8   = 10;
+    pub(super) const RX_FULL: u8    = 12;
+    pub(super) const TIMER_A: u8    = 13;
+}
+
+/// The interrupt channels of the general purpose I/O pins 0 to 7
+const GPIP_CHANNELS: [u8; 8] = [0, 1, 2, 3, 6, 7, 14, 15];
+
+const VR_SOFTWARE_EOI: u8 = 0x08;
+
+// Receiver Status Register Bits
+const RSR_BUFFER_FULL: u8 = 0x80;
+const RSR_OVERRUN_ERROR: u8 = 0x40;
+const RSR_RX_ENABLE: u8 = 0x01;
+
+// Transmitter Status Register Bits
+const TSR_BUFFER_EMPTY: u8 = 0x80;
+const TSR_TX_ENABLE: u8 = 0x01;
+
+const TIMER_STOPPED: u8 = 0x00;
+const TIMER_EVENT_COUNT: u8 = 0x08;
+
+/// The GPIP pins that share their interrupt channels with the timer A and B inputs in pulse width mode
+const TIMER_A_GPIP: u8 = 4;
+const TIMER_B_GPIP: u8 = 3;
+
+/// The timer prescaler values for each of the delay mode settings (1 to 7) of the timer control registers, which
+/// are also used by the pulse width mode settings (9 to 15)
+const PRESCALERS: [u32; 8] = [0, 4, 10, 16, 50, 64, 100, 200];
+
+const DEV_NAME: &str = "mc68901";
+
+
+#[derive(Clone, Default)]
+struct MfpTimer {
+    /// The mode of the timer, which is the lower 4 bits of the timer's control register
+    mode: u8,
+    /// The value to reload the counter with when it reaches zero
+    reload: u8,
+    /// The current value of the counter, from 1 to 256
+    count: u16,
+    /// The number of timer clocks left before the counter is next decremented
+    prescale_count: u32,
+    /// True while the timer's input is at its active level, which is when the timer counts in pulse width mode
+    gate: bool,
+}
+
+impl MfpTimer {
+    fn prescaler_setting(&self) -> Option<u32> {
+        match self.mode {
+            1..=7 => Some(PRESCALERS[self.mode as usize]),
+            9..=15 => Some(PRESCALERS[(self.mode - 8) as usize]),
+            _ => None,
+        }
+    }
+
+    /// Returns the prescaler if the timer is currently counting, which is only while the input is active in
+    /// pulse width mode
+    fn prescaler(&self) -> Option<u32> {
+        if self.is_pulse_width() && !self.gate {
+            return None;
+        }
+        self.prescaler_setting()
+    }
+
+    fn is_pulse_width(&self) -> bool {
+        (9..=15).contains(&self.mode)
+    }
+
+    fn reload_count(&self) -> u16 {
+        if self.reload == 0 { 256 } else { self.reload as u16 }
+    }
+
+    fn set_mode(&mut self, mode: u8) {
+        self.mode = mode & 0x0F;
+        if let Some(prescaler) = self.prescaler_setting() {
+            self.prescale_count = prescaler;
+        }
+    }
+
+    fn write_data(&mut self, data: u8) {
+        self.reload = data;
+        if self.mode == TIMER_STOPPED || self.count == 0 {
+            self.count = self.reload_count();
+        }
+    }
+
+    fn read_data(&self) -> u8 {
+        // A count of 256 is stored in the 8-bit register as 0
+        self.count as u8
+    }
+
+    /// Decrement the counter by the given number of ticks, returning the number of times it reached zero
+    fn count_down(&mut self, ticks: u64) -> u64 {
+        if self.count == 0 {
+            self.count = self.reload_count();
+        }
+
+        if ticks < self.count as u64 {
+            self.count -= ticks as u16;
+            return 0;
+        }
+
+        let period = self.reload_count() as u64;
+        let remaining = ticks - self.count as u64;
+        self.count = (period - (remaining % period)) as u16;
+        1 + remaining / period
+    }
+
+    /// Advance the timer by the given number of timer clocks, returning the number of times it timed out
+    fn advance(&mut self, clocks: u64) -> u64 {
+        let prescaler = match self.prescaler() {
+            Some(prescaler) => prescaler as u64,
+            None => return 0,
+        };
+
+        if clocks < self.prescale_count as u64 {
+            self.prescale_count -= clocks as u32;
+            return 0;
+        }
+
+        let clocks = clocks - self.prescale_count as u64;
+        self.prescale_count = (prescaler - (clocks % prescaler)) as u32;
+        self.count_down(1 + clocks / prescaler)
+    }
+
+    /// Returns the number of timer clocks until the timer next times out, if it's running in delay mode
+    fn clocks_until_timeout(&self) -> Option<u64> {
+        let prescaler = self.prescaler()? as u64;
+        let count = if self.count == 0 { self.reload_count() } else { self.count } as u64;
+        Some(self.prescale_count as u64 + (count - 1) * prescaler)
+    }
+
+    /// Count an edge on the timer's input pin, returning true if the timer timed out
+    fn count_event(&mut self) -> bool {
+        self.mode == TIMER_EVENT_COUNT && self.count_down(1) > 0
+    }
+}
+
+
+#[derive(Default)]
+pub struct MfpUsart {
+    tty: Option<Box<dyn Tty>>,
+    control: u8,
+    sync_char: u8,
+    rx_status: u8,
+    tx_status: u8,
+    input: u8,
+}
+
+impl MfpUsart {
+    pub fn connect(&mut self, pty: Box<dyn Tty>) -> Result<String, Error> {
+        let name = pty.device_name();
+        println!("{}: opening pts {}", DEV_NAME, name);
+        self.tty = Some(pty);
+        Ok(name)
+    }
+
+    fn check_rx(&mut self) -> bool {
+        if (self.rx_status & RSR_RX_ENABLE) != 0 && (self.rx_status & RSR_BUFFER_FULL) == 0 {
+            if let Some(input) = self.tty.as_mut().and_then(|tty| tty.read()) {
+                self.input = input;
+                self.rx_status |= RSR_BUFFER_FULL;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn receive_byte(&mut self) -> u8 {
+        self.rx_status &= !(RSR_BUFFER_FULL | RSR_OVERRUN_ERROR);
+        self.input
+    }
+
+    fn send_byte(&mut self, data: u8) -> bool {
+        if (self.tx_status & TSR_TX_ENABLE) == 0 {
+            return false;
+        }
+
+        // Transmission is instantaneous, so the buffer is immediately empty again
+        if let Some(tty) = self.tty.as_mut() {
+            tty.write(data);
+        }
+        self.tx_status |= TSR_BUFFER_EMPTY;
+        true
+    }
+}
+
+
+/// Motorola MC68901 Multi-Function Peripheral, with four timers, eight general purpose I/O pins
+/// which can cause interrupts, a 16 channel vectored interrupt controller, and a USART
+pub struct MC68901 {
+    frequency: Frequency,
+    interrupt_level: u8,
+    last_tick: u64,
+
+    gpio_data: u8,
+    gpio_active_edge: u8,
+    gpio_direction: u8,
+    gpio_input: u8,
+
+    int_enable: u16,
+    int_pending: u16,
+    int_in_service: u16,
+    int_mask: u16,
+    int_vector: u8,
+    asserted: Option<u8>,
+
+    timer_a_input: bool,
+    timer_b_input: bool,
+    timers: [MfpTimer; 4],
+
+    pub usart: MfpUsart,
+}
+
+impl Default for MC68901 {
+    fn default() -> Self {
+        // The Atari ST clocks the timers at 2.4576 MHz and connects the MFP to interrupt level 6
+        Self::new(Frequency::from_hz(2_457_600), 6)
+    }
+}
+
+impl MC68901 {
+    pub fn new(frequency: Frequency, interrupt_level: u8) -> Self {
+        Self {
+            frequency,
+            interrupt_level,
+            last_tick: 0,
+
+            gpio_data: 0,
+            gpio_active_edge: 0,
+            gpio_direction: 0,
+            gpio_input: 0xFF,
+
+            int_enable: 0,
+            int_pending: 0,
+            int_in_service: 0,
+            int_mask: 0,
+            int_vector: 0,
+            asserted: None,
+
+            timer_a_input: false,
+            timer_b_input: false,
+            timers: Default::default(),
+
+            usart: MfpUsart::default(),
+        }
+    }
+
+    /// Set the level of one of the general purpose I/O pins, which will cause an interrupt if the pin
+    /// is an input and the transition matches the active edge for that pin
+    pub fn set_gpio_input(&mut self, pin: u8, level: bool) {
+        let bit = 1 << pin;
+        let previous = (self.gpio_input & bit) != 0;
+        self.gpio_input = (self.gpio_input & !bit) | if level { bit } else { 0 };
+
+        let rising_edge = (self.gpio_active_edge & bit) != 0;
+        if (self.gpio_direction & bit) == 0 && previous != level && level == rising_edge {
+            self.request_interrupt(GPIP_CHANNELS[pin as usize]);
+        }
+    }
+
+    /// Set the level of the timer A input pin (TAI), which is counted in event count mode, and gates the
+    /// timer in pulse width mode
+    pub fn set_timer_a_input(&mut self, clock: Instant, level: bool) {
+        self.update_timers(clock);
+        let previous = self.timer_a_input;
+        self.timer_a_input = level;
+        self.update_timer_input(0, previous, level, TIMER_A_GPIP, channel::TIMER_A);
+    }
+
+    /// Set the level of the timer B input pin (TBI), which is counted in event count mode, and gates the
+    /// timer in pulse width mode
+    pub fn set_timer_b_input(&mut self, clock: Instant, level: bool) {
+        self.update_timers(clock);
+        let previous = self.timer_b_input;
+        self.timer_b_input = level;
+        self.update_timer_input(1, previous, level, TIMER_B_GPIP, channel::TIMER_B);
+    }
+
+    fn update_timer_input(&mut self, timer: usize, previous: bool, level: bool, gpip: u8, timer_channel: u8) {
+        // The active edge of the timer inputs is controlled by the same bits as their GPIP pins
+        let rising_edge = (self.gpio_active_edge & (1 << gpip)) != 0;
+        if self.timers[timer].is_pulse_width() {
+            // The timer counts while the input is at the level opposite to the active edge, and the GPIP channel
+            // interrupts on the active edge that ends the pulse
+            self.timers[timer].gate = level != rising_edge;
+            if Self::is_active_edge(previous, level, rising_edge) {
+                self.request_interrupt(GPIP_CHANNELS[gpip as usize]);
+            }
+        } else if Self::is_active_edge(previous, level, rising_edge) && self.timers[timer].count_event() {
+            self.request_interrupt(timer_channel);
+        }
+    }
+
+    fn is_active_edge(previous: bool, level: bool, rising_edge: bool) -> bool {
+        previous != level && level == rising_edge
+    }
+
+    fn request_interrupt(&mut self, channel: u8) {
+        let bit = 1 << channel;
+        if (self.int_enable & bit) != 0 {
+            self.int_pending |= bit;
+        }
+    }
+
+    /// Returns the highest priority channel that is pending, unmasked, and not blocked by a channel in service
+    fn highest_pending(&self) -> Option<u8> {
+        let pending = self.int_pending & self.int_mask;
+        if pending == 0 {
+            return None;
+        }
+
+        let channel = 15 - pending.leading_zeros() as u8;
+        if self.int_in_service != 0 && channel <= 15 - self.int_in_service.leading_zeros() as u8 {
+            return None;
+        }
+        Some(channel)
+    }
+
+    fn acknowledge(&mut self, channel: u8) {
+        let bit = 1 << channel;
+        self.int_pending &= !bit;
+        if (self.int_vector & VR_SOFTWARE_EOI) != 0 {
+            self.int_in_service |= bit;
+        }
+    }
+
+    fn update_interrupt(&mut self, system: &System) -> Result<(), Error> {
+        let mut controller = system.get_interrupt_controller();
+
+        // If the interrupt that was asserted is no longer pending, then the cpu has acknowledged it
+        if let Some(channel) = self.asserted.take() {
+            if !controller.is_pending(self.interrupt_level) {
+                self.acknowledge(channel);
+            }
+        }
+
+        self.asserted = self.highest_pending();
+        let vector = (self.int_vector & 0xF0) | self.asserted.unwrap_or(0);
+        controller.set(self.asserted.is_some(), self.interrupt_level, vector)
+    }
+
+    fn update_timers(&mut self, clock: Instant) {
+        let tick = clock.as_duration() / self.frequency.period_duration();
+        let clocks = tick.saturating_sub(self.last_tick);
+        self.last_tick = tick;
+
+        const CHANNELS: [u8; 4] = [channel::TIMER_A, channel::TIMER_B, channel::TIMER_C, channel::TIMER_D];
+        for (i, channel) in CHANNELS.iter().enumerate() {
+            if self.timers[i].advance(clocks) > 0 {
+                self.request_interrupt(*channel);
+            }
+        }
+    }
+
+    fn read_gpio(&self) -> u8 {
+        (self.gpio_data & self.gpio_direction) | (self.gpio_input & !self.gpio_direction)
+    }
+
+    fn write_high(value: &mut u16, data: u8) {
+        *value = (*value & 0x00FF) | ((data as u16) << 8);
+    }
+
+    fn write_low(value: &mut u16, data: u8) {
+        *value = (*value & 0xFF00) | (data as u16);
+    }
+}
+
+impl Steppable for MC68901 {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        self.update_timers(system.clock);
+
+        if self.usart.check_rx() {
+            self.request_interrupt(channel::RX_FULL);
+        }
+
+        self.update_interrupt(system)?;
+
+        // Step again when the next timer expires, but often enough to poll the tty and notice acknowledgements
+        let max_clocks = self.frequency.as_hz() as u64 / 250_000;
+        let clocks = self
+            .timers
+            .iter()
+            .filter_map(|timer| timer.clocks_until_timeout())
+            .fold(max_clocks, |min, clocks| min.min(clocks))
+            .max(1);
+        Ok(self.frequency.period_duration() * clocks as u32)
+    }
+}
+
+impl Addressable for MC68901 {
+    fn size(&self) -> usize {
+        0x30
+    }
+
+    fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        self.update_timers(clock);
+
+        data[0] = match addr {
+            reg::GPDR => self.read_gpio(),
+            reg::AER => self.gpio_active_edge,
+            reg::DDR => self.gpio_direction,
+            reg::IERA => (self.int_enable >> 8) as u8,
+            reg::IERB => self.int_enable as u8,
+            reg::IPRA => (self.int_pending >> 8) as u8,
+            reg::IPRB => self.int_pending as u8,
+            reg::ISRA => (self.int_in_service >> 8) as u8,
+            reg::ISRB => self.int_in_service as u8,
+            reg::IMRA => (self.int_mask >> 8) as u8,
+            reg::IMRB => self.int_mask as u8,
+            reg::VR => self.int_vector,
+            reg::TACR => self.timers[0].mode,
+            reg::TBCR => self.timers[1].mode,
+            reg::TCDCR => (self.timers[2].mode << 4) | self.timers[3].mode,
+            reg::TADR => self.timers[0].read_data(),
+            reg::TBDR => self.timers[1].read_data(),
+            reg::TCDR => self.timers[2].read_data(),
+            reg::TDDR => self.timers[3].read_data(),
+            reg::SCR => self.usart.sync_char,
+            reg::UCR => self.usart.control,
+            reg::RSR => self.usart.rx_status,
+            reg::TSR => self.usart.tx_status,
+            reg::UDR => self.usart.receive_byte(),
+            _ => {
+                strict::unhandled_read(DEV_NAME, addr);
+                0
+            },
+        };
+
+        log::debug!(target: DEV_NAME, "read from register {:x} of {:x}", addr, data[0]);
+        Ok(())
+    }
+
+    fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        log::debug!(target: DEV_NAME, "write to register {:x} with {:x}", addr, data[0]);
+        self.update_timers(clock);
+
+        match addr {
+            reg::GPDR => self.gpio_data = data[0],
+            reg::AER => self.gpio_active_edge = data[0],
+            reg::DDR => self.gpio_direction = data[0],
+            reg::IERA | reg::IERB => {
+                if addr == reg::IERA {
+                    Self::write_high(&mut self.int_enable, data[0]);
+                } else {
+                    Self::write_low(&mut self.int_enable, data[0]);
+                }
+                // Disabling a channel also clears its pending bit
+                self.int_pending &= self.int_enable;
+            },
+            // Writing to the pending and in-service registers can only clear bits
+            reg::IPRA => self.int_pending &= ((data[0] as u16) << 8) | 0x00FF,
+            reg::IPRB => self.int_pending &= 0xFF00 | data[0] as u16,
+            reg::ISRA => self.int_in_service &= ((data[0] as u16) << 8) | 0x00FF,
+            reg::ISRB => self.int_in_service &= 0xFF00 | data[0] as u16,
+            reg::IMRA => Self::write_high(&mut self.int_mask, data[0]),
+            reg::IMRB => Self::write_low(&mut self.int_mask, data[0]),
+            reg::VR => {
+                self.int_vector = data[0];
+                if (self.int_vector & VR_SOFTWARE_EOI) == 0 {
+                    self.int_in_service = 0;
+                }
+            },
+            reg::TACR => {
+                self.timers[0].set_mode(data[0]);
+                self.timers[0].gate = self.timer_a_input != ((self.gpio_active_edge & (1 << TIMER_A_GPIP)) != 0);
+            },
+            reg::TBCR => {
+                self.timers[1].set_mode(data[0]);
+                self.timers[1].gate = self.timer_b_input != ((self.gpio_active_edge & (1 << TIMER_B_GPIP)) != 0);
+            },
+            reg::TCDCR => {
+                self.timers[2].set_mode((data[0] >> 4) & 0x07);
+                self.timers[3].set_mode(data[0] & 0x07);
+            },
+            reg::TADR => self.timers[0].write_data(data[0]),
+            reg::TBDR => self.timers[1].write_data(data[0]),
+            reg::TCDR => self.timers[2].write_data(data[0]),
+            reg::TDDR => self.timers[3].write_data(data[0]),
+            reg::SCR => self.usart.sync_char = data[0],
+            reg::UCR => self.usart.control = data[0],
+            reg::RSR => {
+                // The buffer full and error bits are read only
+                self.usart.rx_status = (self.usart.rx_status & 0xF0) | (data[0] & 0x0F);
+            },
+            reg::TSR => {
+                let enabling = (self.usart.tx_status & TSR_TX_ENABLE) == 0 && (data[0] & TSR_TX_ENABLE) != 0;
+                self.usart.tx_status = (self.usart.tx_status & 0xC0) | (data[0] & 0x3F);
+                if enabling {
+                    self.usart.tx_status |= TSR_BUFFER_EMPTY;
+                    self.request_interrupt(channel::TX_EMPTY);
+                }
+            },
+            reg::UDR => {
+                if self.usart.send_byte(data[0]) {
+                    self.request_interrupt(channel::TX_EMPTY);
+                } else {
+                    self.request_interrupt(channel::TX_ERROR);
+                }
+            },
+            _ => {
+                strict::unhandled_write(DEV_NAME, addr, data);
+            },
+        }
+        Ok(())
+    }
+}
+
+impl Transmutable for MC68901 {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+}
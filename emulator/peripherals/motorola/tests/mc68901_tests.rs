@@ -0,0 +1,221 @@
+use femtos::{Instant, Duration, Frequency};
+
+use moa_core::{System, Address, Addressable, Steppable};
+use moa_peripherals_motorola::MC68901;
+
+const GPDR: Address = 0x01;
+const AER: Address = 0x03;
+const DDR: Address = 0x05;
+const IERA: Address = 0x07;
+const IERB: Address = 0x09;
+const IPRA: Address = 0x0B;
+const IPRB: Address = 0x0D;
+const ISRB: Address = 0x11;
+const IMRA: Address = 0x13;
+const IMRB: Address = 0x15;
+const VR: Address = 0x17;
+const TACR: Address = 0x19;
+const TADR: Address = 0x1F;
+
+const LEVEL: u8 = 6;
+
+fn init_mfp() -> MC68901 {
+    MC68901::new(Frequency::from_hz(2_457_600), LEVEL)
+}
+
+/// Returns the time of the given number of timer clocks
+fn clocks(count: u32) -> Duration {
+    Frequency::from_hz(2_457_600).period_duration() * count
+}
+
+fn clock_at(count: u32) -> Instant {
+    Instant::START + clocks(count)
+}
+
+fn write(mfp: &mut MC68901, clock: Instant, addr: Address, value: u8) {
+    mfp.write(clock, addr, &[value]).unwrap();
+}
+
+fn read(mfp: &mut MC68901, clock: Instant, addr: Address) -> u8 {
+    let mut data = [0];
+    mfp.read(clock, addr, &mut data).unwrap();
+    data[0]
+}
+
+/// Pulse a GPIP pin low and back high, which is an active edge for the default AER setting
+fn pulse_gpio(mfp: &mut MC68901, pin: u8) {
+    mfp.set_gpio_input(pin, false);
+    mfp.set_gpio_input(pin, true);
+}
+
+/// Step the MFP, and acknowledge the interrupt it asserts like the cpu would, returning its vector.  The MFP
+/// notices the acknowledgement on the step after
+fn step_and_acknowledge(mfp: &mut MC68901, system: &System) -> Option<u8> {
+    mfp.step(system).unwrap();
+    let mut controller = system.get_interrupt_controller();
+    if controller.is_pending(LEVEL) {
+        Some(controller.acknowledge(LEVEL).unwrap())
+    } else {
+        None
+    }
+}
+
+#[test]
+fn interrupts_are_asserted_in_order_of_priority() {
+    let system = System::default();
+    let mut mfp = init_mfp();
+    write(&mut mfp, Instant::START, VR, 0x40);
+    // GPIP 0 is channel 0, the lowest priority, and GPIP 7 is channel 15, the highest
+    write(&mut mfp, Instant::START, IERA, 0x80);
+    write(&mut mfp, Instant::START, IMRA, 0x80);
+    write(&mut mfp, Instant::START, IERB, 0x01);
+    write(&mut mfp, Instant::START, IMRB, 0x01);
+
+    pulse_gpio(&mut mfp, 0);
+    pulse_gpio(&mut mfp, 7);
+    assert_eq!(read(&mut mfp, Instant::START, IPRA), 0x80);
+    assert_eq!(read(&mut mfp, Instant::START, IPRB), 0x01);
+
+    assert_eq!(step_and_acknowledge(&mut mfp, &system), Some(0x4F));
+    assert_eq!(step_and_acknowledge(&mut mfp, &system), Some(0x40));
+    assert_eq!(step_and_acknowledge(&mut mfp, &system), None);
+    assert_eq!(read(&mut mfp, Instant::START, IPRA), 0x00);
+    assert_eq!(read(&mut mfp, Instant::START, IPRB), 0x00);
+}
+
+#[test]
+fn masked_interrupts_stay_pending() {
+    let system = System::default();
+    let mut mfp = init_mfp();
+    write(&mut mfp, Instant::START, VR, 0x40);
+    write(&mut mfp, Instant::START, IERB, 0x01);
+
+    pulse_gpio(&mut mfp, 0);
+    assert_eq!(step_and_acknowledge(&mut mfp, &system), None);
+    assert_eq!(read(&mut mfp, Instant::START, IPRB), 0x01);
+
+    write(&mut mfp, Instant::START, IMRB, 0x01);
+    assert_eq!(step_and_acknowledge(&mut mfp, &system), Some(0x40));
+}
+
+#[test]
+fn interrupts_in_service_block_lower_priorities() {
+    let system = System::default();
+    let mut mfp = init_mfp();
+    write(&mut mfp, Instant::START, VR, 0x48);
+    write(&mut mfp, Instant::START, IERB, 0x03);
+    write(&mut mfp, Instant::START, IMRB, 0x03);
+
+    pulse_gpio(&mut mfp, 0);
+    assert_eq!(step_and_acknowledge(&mut mfp, &system), Some(0x40));
+    assert_eq!(step_and_acknowledge(&mut mfp, &system), None);
+    assert_eq!(read(&mut mfp, Instant::START, ISRB), 0x01);
+
+    // A higher priority channel can interrupt the one in service
+    pulse_gpio(&mut mfp, 1);
+    assert_eq!(step_and_acknowledge(&mut mfp, &system), Some(0x41));
+    assert_eq!(step_and_acknowledge(&mut mfp, &system), None);
+    assert_eq!(read(&mut mfp, Instant::START, ISRB), 0x03);
+
+    // A lower priority channel waits until the channels in service are cleared
+    pulse_gpio(&mut mfp, 0);
+    assert_eq!(step_and_acknowledge(&mut mfp, &system), None);
+    assert_eq!(read(&mut mfp, Instant::START, IPRB), 0x01);
+
+    write(&mut mfp, Instant::START, ISRB, !0x02);
+    assert_eq!(step_and_acknowledge(&mut mfp, &system), None);
+    write(&mut mfp, Instant::START, ISRB, !0x01);
+    assert_eq!(step_and_acknowledge(&mut mfp, &system), Some(0x40));
+}
+
+#[test]
+fn gpio_interrupts_on_the_active_edge() {
+    let mut mfp = init_mfp();
+    write(&mut mfp, Instant::START, IERB, 0x01);
+
+    // With the AER bit clear, only the falling edge causes an interrupt
+    mfp.set_gpio_input(0, true);
+    assert_eq!(read(&mut mfp, Instant::START, IPRB), 0x00);
+    mfp.set_gpio_input(0, false);
+    assert_eq!(read(&mut mfp, Instant::START, IPRB), 0x01);
+    write(&mut mfp, Instant::START, IPRB, !0x01);
+    mfp.set_gpio_input(0, true);
+    assert_eq!(read(&mut mfp, Instant::START, IPRB), 0x00);
+
+    // With the AER bit set, only the rising edge does
+    write(&mut mfp, Instant::START, AER, 0x01);
+    mfp.set_gpio_input(0, false);
+    assert_eq!(read(&mut mfp, Instant::START, IPRB), 0x00);
+    mfp.set_gpio_input(0, true);
+    assert_eq!(read(&mut mfp, Instant::START, IPRB), 0x01);
+}
+
+#[test]
+fn gpio_outputs_dont_interrupt() {
+    let mut mfp = init_mfp();
+    write(&mut mfp, Instant::START, IERB, 0x01);
+    write(&mut mfp, Instant::START, DDR, 0x01);
+    write(&mut mfp, Instant::START, GPDR, 0x00);
+
+    mfp.set_gpio_input(0, false);
+    assert_eq!(read(&mut mfp, Instant::START, IPRB), 0x00);
+    // The output pin reads back the data written to it, and the input pins read their levels
+    mfp.set_gpio_input(1, false);
+    assert_eq!(read(&mut mfp, Instant::START, GPDR), 0xFC);
+}
+
+#[test]
+fn timer_counts_down_at_the_prescaled_rate() {
+    let mut mfp = init_mfp();
+    write(&mut mfp, Instant::START, IERA, 0x20);
+    write(&mut mfp, Instant::START, TADR, 3);
+    // Delay mode with a prescaler of 4
+    write(&mut mfp, Instant::START, TACR, 0x01);
+
+    assert_eq!(read(&mut mfp, clock_at(3), TADR), 3);
+    assert_eq!(read(&mut mfp, clock_at(4), TADR), 2);
+    assert_eq!(read(&mut mfp, clock_at(11), TADR), 1);
+    assert_eq!(read(&mut mfp, clock_at(11), IPRA), 0x00);
+
+    // The counter is reloaded when it times out
+    assert_eq!(read(&mut mfp, clock_at(12), TADR), 3);
+    assert_eq!(read(&mut mfp, clock_at(12), IPRA), 0x20);
+
+    write(&mut mfp, clock_at(12), IPRA, !0x20);
+    assert_eq!(read(&mut mfp, clock_at(41), TADR), 2);
+    assert_eq!(read(&mut mfp, clock_at(41), IPRA), 0x20);
+
+    // Stopping the timer holds the count
+    write(&mut mfp, clock_at(41), TACR, 0x00);
+    assert_eq!(read(&mut mfp, clock_at(100), TADR), 2);
+}
+
+#[test]
+fn timer_steps_when_it_times_out() {
+    let mut system = System::default();
+    let mut mfp = init_mfp();
+    write(&mut mfp, Instant::START, TADR, 2);
+    write(&mut mfp, Instant::START, TACR, 0x01);
+
+    // The counter is decremented at 4 and times out at 8 clocks
+    system.clock = clock_at(1);
+    assert_eq!(mfp.step(&system).unwrap(), clocks(7));
+}
+
+#[test]
+fn timer_counts_events_on_the_active_edge() {
+    let mut mfp = init_mfp();
+    write(&mut mfp, Instant::START, IERA, 0x20);
+    write(&mut mfp, Instant::START, TADR, 2);
+    // Event count mode, which counts the falling edges of the timer A input with the AER bit clear
+    write(&mut mfp, Instant::START, TACR, 0x08);
+
+    mfp.set_timer_a_input(Instant::START, true);
+    assert_eq!(read(&mut mfp, Instant::START, TADR), 2);
+    mfp.set_timer_a_input(Instant::START, false);
+    assert_eq!(read(&mut mfp, Instant::START, TADR), 1);
+    mfp.set_timer_a_input(Instant::START, true);
+    mfp.set_timer_a_input(Instant::START, false);
+    assert_eq!(read(&mut mfp, Instant::START, TADR), 2);
+    assert_eq!(read(&mut mfp, Instant::START, IPRA), 0x20);
+}
@@ -1,5 +1,11 @@
 mod sn76489;
-pub use crate::sn76489::Sn76489;
+pub use crate::sn76489::{Sn76489, Sn76489Variant};
 
 mod ym2612;
 pub use crate::ym2612::Ym2612;
+
+mod ym2149;
+pub use crate::ym2149::Ym2149;
+
+mod ym3812;
+pub use crate::ym3812::Ym3812;
@@ -1,24 +1,61 @@
+use std::any::Any;
 use femtos::{Instant, Duration, Frequency};
 
-use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable};
+use moa_core::{System, Error, Address, Addressable, Steppable, ParallelSteppable, Snapshotable, Transmutable, Capabilities, strict};
 use moa_host::{Host, HostError, Audio, Sample};
 use moa_audio::SquareWave;
 
 
 const DEV_NAME: &str = "sn76489";
 
+/// The channel number of the noise generator in the stereo register, after the 3 tone generators
+const NOISE_CHANNEL: usize = 3;
+
+/// The versions of the chip, which differ in their noise generator's shift register
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Sn76489Variant {
+    /// The original chip, with a 15-bit shift register that's tapped at bits 0 and 1
+    TexasInstruments,
+    /// The clone in the Master System and Genesis, with a 16-bit shift register that's tapped at bits 0 and 3
+    #[default]
+    Sega,
+    /// The Game Gear's version of the Sega chip, which adds a register at address 1 that enables each channel
+    /// on the left and right outputs
+    GameGear,
+}
+
+impl Sn76489Variant {
+    fn shift_register_width(&self) -> u32 {
+        match self {
+            Sn76489Variant::TexasInstruments => 15,
+            Sn76489Variant::Sega | Sn76489Variant::GameGear => 16,
+        }
+    }
+
+    fn tapped_bits(&self) -> u16 {
+        match self {
+            Sn76489Variant::TexasInstruments => 0x0003,
+            Sn76489Variant::Sega | Sn76489Variant::GameGear => 0x0009,
+        }
+    }
+}
+
 #[derive(Clone)]
 struct ToneGenerator {
     on: bool,
     attenuation: f32,
+    clock_frequency: f32,
+    count: usize,
     wave: SquareWave,
 }
 
 impl ToneGenerator {
-    fn new(sample_rate: usize) -> Self {
+    fn new(sample_rate: usize, clock_frequency: f32) -> Self {
         Self {
             on: false,
             attenuation: 0.0,
+            clock_frequency,
+            count: 0,
             wave: SquareWave::new(600.0, sample_rate),
         }
     }
@@ -30,13 +67,14 @@ impl ToneGenerator {
             self.on = true;
             self.attenuation = (attenuation << 1) as f32;
         }
-        log::info!("set attenuation to {} {}", self.attenuation, self.on);
+        log::info!(target: DEV_NAME, "set attenuation to {} {}", self.attenuation, self.on);
     }
 
     fn set_counter(&mut self, count: usize) {
-        let frequency = 3_579_545.0 / (count as f32 * 32.0);
+        self.count = count;
+        let frequency = self.clock_frequency / (count.max(1) as f32 * 32.0);
         self.wave.set_frequency(frequency);
-        log::info!("set frequency to {}", frequency);
+        log::info!(target: DEV_NAME, "set frequency to {}", frequency);
     }
 
     fn get_sample(&mut self) -> f32 {
@@ -49,18 +87,35 @@ impl ToneGenerator {
 struct NoiseGenerator {
     on: bool,
     attenuation: f32,
+    variant: Sn76489Variant,
+    sample_rate: usize,
+
+    /// True for white noise, which feeds back the parity of the tapped bits, or false for periodic noise, which
+    /// feeds back only the bit that's output
+    white: bool,
+    /// The shift rate, where 3 uses the counter of the third tone generator
+    rate: u8,
+    shift_register: u16,
+    shift_frequency: f32,
+    position: f32,
 }
 
-impl Default for NoiseGenerator {
-    fn default() -> Self {
+impl NoiseGenerator {
+    fn new(sample_rate: usize, variant: Sn76489Variant) -> Self {
         Self {
             on: false,
             attenuation: 0.0,
+            variant,
+            sample_rate,
+
+            white: false,
+            rate: 0,
+            shift_register: 1 << (variant.shift_register_width() - 1),
+            shift_frequency: 0.0,
+            position: 0.0,
         }
     }
-}
 
-impl NoiseGenerator {
     fn set_attenuation(&mut self, attenuation: u8) {
         if attenuation == 0x0F {
             self.on = false;
@@ -68,70 +123,133 @@ impl NoiseGenerator {
             self.on = true;
             self.attenuation = (attenuation << 1) as f32;
         }
-        log::info!("set attenuation to {} {}", self.attenuation, self.on);
+        log::info!(target: DEV_NAME, "set attenuation to {} {}", self.attenuation, self.on);
+    }
+
+    /// Set the noise type and shift rate, which also resets the shift register
+    fn set_control(&mut self, bits: u8) {
+        self.white = bits & 0x04 != 0;
+        self.rate = bits & 0x03;
+        self.shift_register = 1 << (self.variant.shift_register_width() - 1);
+    }
+
+    /// Update the shift frequency, which is the tone generator frequency for a counter of 16, 32, or 64, or the
+    /// frequency of the third tone generator
+    fn update_frequency(&mut self, clock_frequency: f32, tone2_count: usize) {
+        let count = match self.rate {
+            0 => 0x10,
+            1 => 0x20,
+            2 => 0x40,
+            _ => tone2_count.max(1),
+        };
+        self.shift_frequency = clock_frequency / (count as f32 * 32.0);
+        log::debug!(target: DEV_NAME, "set noise shift frequency to {}", self.shift_frequency);
     }
 
-    fn set_control(&mut self, _bits: u8) {
-        //let frequency = 3_579_545.0 / (count as f32 * 32.0);
-        //self.wave.set_frequency(frequency);
-        //log::debug!("set frequency to {}", frequency);
+    fn shift(&mut self) {
+        let feedback = if self.white {
+            (self.shift_register & self.variant.tapped_bits()).count_ones() as u16 & 0x01
+        } else {
+            self.shift_register & 0x01
+        };
+        self.shift_register = (self.shift_register >> 1) | (feedback << (self.variant.shift_register_width() - 1));
     }
 
     fn get_sample(&mut self) -> f32 {
-        // TODO this isn't implemented yet
-        0.0
+        self.position += self.shift_frequency / self.sample_rate as f32;
+        while self.position >= 1.0 {
+            self.shift();
+            self.position -= 1.0;
+        }
+
+        let output = if self.shift_register & 0x01 != 0 { 1.0 } else { -1.0 };
+        output / (self.attenuation + 1.0)
     }
 }
 
 
-
 pub struct Sn76489 {
     first_byte: Option<u8>,
     source: Box<dyn Audio>,
+    clock_frequency: f32,
+    variant: Sn76489Variant,
     tones: Vec<ToneGenerator>,
     noise: NoiseGenerator,
+    /// The enable bits of each channel for the right output in the lower 4 bits, and the left in the upper 4 bits
+    stereo: u8,
 }
 
 impl Sn76489 {
-    pub fn new<H, E>(host: &mut H, _clock_frequency: Frequency) -> Result<Self, HostError<E>>
+    pub fn new<H, E>(host: &mut H, clock_frequency: Frequency) -> Result<Self, HostError<E>>
     where
         H: Host<Error = E>,
     {
         let source = host.add_audio_source()?;
         let sample_rate = source.samples_per_second();
+        let clock_frequency = clock_frequency.as_hz() as f32;
+        let variant = Sn76489Variant::default();
 
         Ok(Self {
             first_byte: None,
             source,
-            tones: vec![ToneGenerator::new(sample_rate); 3],
-            noise: NoiseGenerator::default(),
+            clock_frequency,
+            variant,
+            tones: vec![ToneGenerator::new(sample_rate, clock_frequency); 3],
+            noise: NoiseGenerator::new(sample_rate, variant),
+            stereo: 0xFF,
         })
     }
+
+    pub fn with_variant(mut self, variant: Sn76489Variant) -> Self {
+        self.variant = variant;
+        self.noise = NoiseGenerator::new(self.source.samples_per_second(), variant);
+        self
+    }
+
+    fn update_noise_frequency(&mut self) {
+        self.noise.update_frequency(self.clock_frequency, self.tones[2].count);
+    }
+
+    #[inline]
+    fn pan(&self, channel: usize, sample: f32) -> (f32, f32) {
+        let left = if self.stereo & (0x10 << channel) != 0 { sample } else { 0.0 };
+        let right = if self.stereo & (0x01 << channel) != 0 { sample } else { 0.0 };
+        (left, right)
+    }
 }
 
 impl Steppable for Sn76489 {
     fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        self.step_at(system.clock)
+    }
+}
+
+impl ParallelSteppable for Sn76489 {
+    fn step_at(&mut self, clock: Instant) -> Result<Duration, Error> {
         let rate = self.source.samples_per_second();
         let samples = rate / 1000;
 
         let mut buffer = vec![Sample(0.0, 0.0); samples];
         for buffered_sample in buffer.iter_mut().take(samples) {
-            let mut sample = 0.0;
+            let mut sample = (0.0, 0.0);
 
             for ch in 0..3 {
                 if self.tones[ch].on {
-                    sample += self.tones[ch].get_sample();
+                    let (left, right) = self.pan(ch, self.tones[ch].get_sample());
+                    sample.0 += left;
+                    sample.1 += right;
                 }
             }
 
             if self.noise.on {
-                sample += self.noise.get_sample();
+                let (left, right) = self.pan(NOISE_CHANNEL, self.noise.get_sample());
+                sample.0 += left;
+                sample.1 += right;
             }
 
-            let sample = sample.clamp(-1.0, 1.0);
-            *buffered_sample = Sample(sample, sample);
+            *buffered_sample = Sample(sample.0.clamp(-1.0, 1.0), sample.1.clamp(-1.0, 1.0));
         }
-        self.source.write_samples(system.clock, &buffer);
+        self.source.write_samples(clock, &buffer);
 
         Ok(Duration::from_millis(1)) // Every 1ms of simulated time
     }
@@ -139,17 +257,24 @@ impl Steppable for Sn76489 {
 
 impl Addressable for Sn76489 {
     fn size(&self) -> usize {
-        0x01
+        match self.variant {
+            Sn76489Variant::GameGear => 0x02,
+            _ => 0x01,
+        }
     }
 
     fn read(&mut self, _clock: Instant, _addr: Address, _data: &mut [u8]) -> Result<(), Error> {
-        log::warn!("{}: !!! device can't be read", DEV_NAME);
+        log::warn!(target: DEV_NAME, "!!! device can't be read");
         Ok(())
     }
 
     fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        if addr == 1 && self.variant == Sn76489Variant::GameGear {
+            self.stereo = data[0];
+            return Ok(());
+        }
         if addr != 0 {
-            log::warn!("{}: !!! unhandled write {:0x} to {:0x}", DEV_NAME, data[0], addr);
+            strict::unhandled_write(DEV_NAME, addr, data);
             return Ok(());
         }
 
@@ -160,7 +285,10 @@ impl Addressable for Sn76489 {
                 1 => self.tones[0].set_attenuation(value),
                 3 => self.tones[1].set_attenuation(value),
                 5 => self.tones[2].set_attenuation(value),
-                6 => self.noise.set_control(value),
+                6 => {
+                    self.noise.set_control(value);
+                    self.update_noise_frequency();
+                },
                 7 => self.noise.set_attenuation(value),
                 _ => {
                     self.first_byte = Some(data[0]);
@@ -173,15 +301,35 @@ impl Addressable for Sn76489 {
             match reg {
                 0 => self.tones[0].set_counter(value),
                 2 => self.tones[1].set_counter(value),
-                4 => self.tones[2].set_counter(value),
+                4 => {
+                    self.tones[2].set_counter(value);
+                    self.update_noise_frequency();
+                },
                 _ => {},
             }
         }
-        log::debug!("{}: write to register {:x} with {:x}", DEV_NAME, addr, data[0]);
+        log::debug!(target: DEV_NAME, "write to register {:x} with {:x}", addr, data[0]);
         Ok(())
     }
 }
 
+impl Snapshotable for Sn76489 {
+    fn save_state(&mut self) -> Option<Box<dyn Any>> {
+        Some(Box::new((self.first_byte, self.tones.clone(), self.noise.clone(), self.stereo)))
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        if let Some((first_byte, tones, noise, stereo)) =
+            state.downcast_ref::<(Option<u8>, Vec<ToneGenerator>, NoiseGenerator, u8)>()
+        {
+            self.first_byte = *first_byte;
+            self.tones = tones.clone();
+            self.noise = noise.clone();
+            self.stereo = *stereo;
+        }
+    }
+}
+
 impl Transmutable for Sn76489 {
     fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
         Some(self)
@@ -190,4 +338,12 @@ impl Transmutable for Sn76489 {
     fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
         Some(self)
     }
+
+    fn as_snapshotable(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
+
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities::probe(self) | Capabilities::AUDIO
+    }
 }
@@ -0,0 +1,343 @@
+use femtos::{Instant, Duration, Frequency};
+
+use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable, Capabilities, strict};
+use moa_host::{Host, HostError, Audio, Sample};
+use moa_signals::Signal;
+
+
+const DEV_NAME: &str = "ym2149";
+
+#[rustfmt::skip]
+mod reg {
+    pub(super) const TONE_A_FINE: usize     = 0x00;
+    pub(super) const TONE_C_COARSE: usize   = 0x05;
+    pub(super) const NOISE_PERIOD: usize    = 0x06;
+    pub(super) const MIXER: usize           = 0x07;
+    pub(super) const AMPLITUDE_A: usize     = 0x08;
+    pub(super) const ENVELOPE_FINE: usize   = 0x0B;
+    pub(super) const ENVELOPE_COARSE: usize = 0x0C;
+    pub(super) const ENVELOPE_SHAPE: usize  = 0x0D;
+    pub(super) const PORT_A: usize          = 0x0E;
+    pub(super) const PORT_B: usize          = 0x0F;
+}
+
+/// The mask of each register, since the unused upper bits read back as zero
+const REGISTER_MASKS: [u8; 16] = [0xFF, 0x0F, 0xFF, 0x0F, 0xFF, 0x0F, 0x1F, 0xFF, 0x1F, 0x1F, 0x1F, 0xFF, 0xFF, 0x0F, 0xFF, 0xFF];
+
+/// The bits of the mixer register that make the I/O ports outputs
+const MIXER_PORT_A_OUTPUT: u8 = 0x40;
+const MIXER_PORT_B_OUTPUT: u8 = 0x80;
+
+/// The amplitude register bit that uses the envelope instead of the fixed level
+const AMPLITUDE_USE_ENVELOPE: u8 = 0x10;
+
+#[rustfmt::skip]
+mod shape {
+    pub(super) const HOLD: u8       = 0x01;
+    pub(super) const ALTERNATE: u8  = 0x02;
+    pub(super) const ATTACK: u8     = 0x04;
+    pub(super) const CONTINUE: u8   = 0x08;
+}
+
+/// The output level of each of the 16 amplitudes, which are about 3dB apart
+#[rustfmt::skip]
+const VOLUME_TABLE: [f32; 16] = [
+    0.0,    0.0078, 0.0110, 0.0156, 0.0221, 0.0312, 0.0441, 0.0624,
+    0.0883, 0.1249, 0.1766, 0.2498, 0.3533, 0.4996, 0.7066, 1.0,
+];
+
+/// A counter that toggles its output at a frequency derived from the chip clock and a period register
+#[derive(Clone, Default)]
+struct PeriodCounter {
+    toggle_frequency: f32,
+    position: f32,
+    output: bool,
+}
+
+impl PeriodCounter {
+    fn set_period(&mut self, clock_frequency: f32, divider: f32, period: u16) {
+        self.toggle_frequency = clock_frequency / (divider * period.max(1) as f32);
+    }
+
+    /// Advance by one sample, returning the number of times the output toggled
+    fn advance(&mut self, sample_rate: f32) -> usize {
+        self.position += self.toggle_frequency / sample_rate;
+        let mut toggles = 0;
+        while self.position >= 1.0 {
+            self.position -= 1.0;
+            self.output = !self.output;
+            toggles += 1;
+        }
+        toggles
+    }
+}
+
+#[derive(Default)]
+struct Envelope {
+    counter: PeriodCounter,
+    shape: u8,
+    step: u8,
+    attack: bool,
+    holding: bool,
+}
+
+impl Envelope {
+    fn restart(&mut self, shape: u8) {
+        self.shape = shape;
+        self.step = 0;
+        self.attack = (shape & shape::ATTACK) != 0;
+        self.holding = false;
+        self.counter.position = 0.0;
+    }
+
+    fn level(&self) -> u8 {
+        if self.attack { self.step } else { 15 - self.step }
+    }
+
+    fn advance(&mut self, sample_rate: f32) {
+        let steps = self.counter.advance(sample_rate);
+        for _ in 0..steps {
+            if self.holding {
+                return;
+            }
+
+            if self.step < 15 {
+                self.step += 1;
+                continue;
+            }
+
+            // At the end of each cycle, either hold the final level or start the next cycle
+            if (self.shape & shape::CONTINUE) == 0 {
+                self.attack = false;
+                self.step = 15;
+                self.holding = true;
+            } else if (self.shape & shape::HOLD) != 0 {
+                // The held level is the end of the cycle, or the start of it if the direction alternates
+                if (self.shape & shape::ALTERNATE) != 0 {
+                    self.attack = !self.attack;
+                }
+                self.step = 15;
+                self.holding = true;
+            } else {
+                if (self.shape & shape::ALTERNATE) != 0 {
+                    self.attack = !self.attack;
+                }
+                self.step = 0;
+            }
+        }
+    }
+}
+
+/// Yamaha YM2149 Programmable Sound Generator, which is compatible with the General Instrument AY-3-8910,
+/// with three square wave tone channels, a noise generator, an envelope generator, and two 8-bit I/O ports
+///
+/// The chip is accessed through two addresses, where writing to the first selects a register, reading
+/// from the first reads the selected register, and writing to the second writes the selected register
+pub struct Ym2149 {
+    source: Box<dyn Audio>,
+    clock_frequency: f32,
+    selected: usize,
+    registers: [u8; 16],
+    tones: [PeriodCounter; 3],
+    noise: PeriodCounter,
+    noise_shift_register: u32,
+    envelope: Envelope,
+    port_a_input: u8,
+    port_b_input: u8,
+    port_a: Signal<u8>,
+    port_b: Signal<u8>,
+}
+
+impl Ym2149 {
+    pub fn new<H, E>(host: &mut H, clock_frequency: Frequency) -> Result<Self, HostError<E>>
+    where
+        H: Host<Error = E>,
+    {
+        let source = host.add_audio_source()?;
+
+        Ok(Self {
+            source,
+            clock_frequency: clock_frequency.as_hz() as f32,
+            selected: 0,
+            registers: [0; 16],
+            tones: Default::default(),
+            noise: PeriodCounter::default(),
+            noise_shift_register: 1,
+            envelope: Envelope::default(),
+            port_a_input: 0xFF,
+            port_b_input: 0xFF,
+            port_a: Signal::new(0xFF),
+            port_b: Signal::new(0xFF),
+        })
+    }
+
+    /// Returns the output of I/O port A, which is updated when the port is written while it's an output
+    pub fn port_a(&self) -> Signal<u8> {
+        self.port_a.clone()
+    }
+
+    /// Returns the output of I/O port B, which is updated when the port is written while it's an output
+    pub fn port_b(&self) -> Signal<u8> {
+        self.port_b.clone()
+    }
+
+    /// Set the levels of the pins of I/O port A, which are read while the port is an input
+    pub fn set_port_a_input(&mut self, value: u8) {
+        self.port_a_input = value;
+    }
+
+    /// Set the levels of the pins of I/O port B, which are read while the port is an input
+    pub fn set_port_b_input(&mut self, value: u8) {
+        self.port_b_input = value;
+    }
+
+    fn tone_period(&self, channel: usize) -> u16 {
+        ((self.registers[channel * 2 + 1] as u16) << 8) | self.registers[channel * 2] as u16
+    }
+
+    fn read_register(&self) -> u8 {
+        match self.selected {
+            reg::PORT_A if (self.registers[reg::MIXER] & MIXER_PORT_A_OUTPUT) == 0 => self.port_a_input,
+            reg::PORT_B if (self.registers[reg::MIXER] & MIXER_PORT_B_OUTPUT) == 0 => self.port_b_input,
+            register => self.registers[register],
+        }
+    }
+
+    fn write_register(&mut self, value: u8) {
+        let register = self.selected;
+        self.registers[register] = value & REGISTER_MASKS[register];
+        log::debug!(target: DEV_NAME, "write to register {:x} with {:x}", register, value);
+
+        match register {
+            reg::TONE_A_FINE..=reg::TONE_C_COARSE => {
+                let channel = register / 2;
+                let period = self.tone_period(channel);
+                self.tones[channel].set_period(self.clock_frequency, 8.0, period);
+            },
+            reg::NOISE_PERIOD => {
+                let period = self.registers[reg::NOISE_PERIOD] as u16;
+                // The shift register is clocked once per period, instead of toggling twice like the tones
+                self.noise.set_period(self.clock_frequency, 16.0, period);
+            },
+            reg::MIXER => self.update_ports(),
+            reg::ENVELOPE_FINE | reg::ENVELOPE_COARSE => {
+                let period = ((self.registers[reg::ENVELOPE_COARSE] as u16) << 8) | self.registers[reg::ENVELOPE_FINE] as u16;
+                self.envelope.counter.set_period(self.clock_frequency, 16.0, period);
+            },
+            // Writing the shape register always restarts the envelope, even with the same shape
+            reg::ENVELOPE_SHAPE => self.envelope.restart(self.registers[reg::ENVELOPE_SHAPE]),
+            reg::PORT_A | reg::PORT_B => self.update_ports(),
+            _ => {},
+        }
+    }
+
+    fn update_ports(&mut self) {
+        let mixer = self.registers[reg::MIXER];
+        if (mixer & MIXER_PORT_A_OUTPUT) != 0 && self.port_a.get() != self.registers[reg::PORT_A] {
+            self.port_a.set(self.registers[reg::PORT_A]);
+        }
+        if (mixer & MIXER_PORT_B_OUTPUT) != 0 && self.port_b.get() != self.registers[reg::PORT_B] {
+            self.port_b.set(self.registers[reg::PORT_B]);
+        }
+    }
+
+    fn shift_noise(&mut self) {
+        // The noise generator is a 17-bit shift register tapped at bits 0 and 3
+        let feedback = (self.noise_shift_register ^ (self.noise_shift_register >> 3)) & 0x01;
+        self.noise_shift_register = (self.noise_shift_register >> 1) | (feedback << 16);
+    }
+
+    fn get_sample(&mut self, sample_rate: f32) -> f32 {
+        for tone in self.tones.iter_mut() {
+            tone.advance(sample_rate);
+        }
+        for _ in 0..self.noise.advance(sample_rate) {
+            self.shift_noise();
+        }
+        self.envelope.advance(sample_rate);
+
+        let mixer = self.registers[reg::MIXER];
+        let noise_output = (self.noise_shift_register & 0x01) != 0;
+        let mut sample = 0.0;
+        for channel in 0..3 {
+            let tone_disabled = (mixer & (0x01 << channel)) != 0;
+            let noise_disabled = (mixer & (0x08 << channel)) != 0;
+            let output = (self.tones[channel].output || tone_disabled) && (noise_output || noise_disabled);
+
+            let amplitude = self.registers[reg::AMPLITUDE_A + channel];
+            let level = if (amplitude & AMPLITUDE_USE_ENVELOPE) != 0 {
+                self.envelope.level()
+            } else {
+                amplitude & 0x0F
+            };
+
+            if output {
+                sample += VOLUME_TABLE[level as usize];
+            }
+        }
+
+        // Convert the unipolar output of the three channels into a signed sample
+        (sample / 3.0) * 2.0 - 1.0
+    }
+}
+
+impl Steppable for Ym2149 {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        let rate = self.source.samples_per_second();
+        let samples = rate / 1000;
+
+        let mut buffer = vec![Sample(0.0, 0.0); samples];
+        for buffered_sample in buffer.iter_mut() {
+            let sample = self.get_sample(rate as f32);
+            *buffered_sample = Sample(sample, sample);
+        }
+        self.source.write_samples(system.clock, &buffer);
+
+        Ok(Duration::from_millis(1)) // Every 1ms of simulated time
+    }
+}
+
+impl Addressable for Ym2149 {
+    fn size(&self) -> usize {
+        0x04
+    }
+
+    fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        match addr {
+            0x00 | 0x01 => {
+                for byte in data.iter_mut() {
+                    *byte = self.read_register();
+                }
+            },
+            _ => {
+                strict::unhandled_read(DEV_NAME, addr);
+                data.fill(0xFF);
+            },
+        }
+        log::debug!(target: DEV_NAME, "read from register {:x} of {:?}", addr, data);
+        Ok(())
+    }
+
+    fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        match addr {
+            0x00 | 0x01 => self.selected = (data[0] & 0x0F) as usize,
+            0x02 | 0x03 => self.write_register(data[0]),
+            _ => strict::unhandled_write(DEV_NAME, addr, data),
+        }
+        Ok(())
+    }
+}
+
+impl Transmutable for Ym2149 {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities::probe(self) | Capabilities::AUDIO
+    }
+}
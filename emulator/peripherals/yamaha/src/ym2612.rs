@@ -15,12 +15,13 @@
 //!         <http://gendev.spritesmind.net/forum/viewtopic.php?p=6224#6224>
 
 use std::f32;
+use std::any::Any;
 use std::num::NonZeroU8;
 use std::collections::VecDeque;
 use lazy_static::lazy_static;
 use femtos::{Instant, Duration, Frequency};
 
-use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable};
+use moa_core::{System, Error, Address, Addressable, Steppable, ParallelSteppable, Snapshotable, Transmutable, Capabilities, strict};
 use moa_host::{Host, HostError, Audio, Sample};
 
 
@@ -196,6 +197,21 @@ const MAX_ENVELOPE: u16 = 0xFFC;
 const ENVELOPE_CENTER: u16 = 0x800;
 const MAX_PHASE: u32 = 0x000FFFFF;
 
+#[rustfmt::skip]
+mod ssg_eg {
+    pub(super) const ENABLE: u8     = 0x08;
+    pub(super) const ATTACK: u8     = 0x04;
+    pub(super) const ALTERNATE: u8  = 0x02;
+    pub(super) const HOLD: u8       = 0x01;
+}
+
+/// The number of FM clocks between each step of the LFO counter, for each of the LFO frequency settings
+const LFO_PERIODS: [u16; 8] = [108, 77, 71, 67, 62, 44, 8, 5];
+/// The amount the LFO's amplitude is shifted right by, for each of the channel's AMS settings
+const AMS_SHIFT: [u16; 4] = [8, 3, 1, 0];
+/// The maximum pitch deviation in cents, for each of the channel's FMS settings
+const FMS_DEPTH: [f32; 8] = [0.0, 3.4, 6.7, 10.0, 14.0, 20.0, 40.0, 80.0];
+
 
 type FmClock = u64;
 type EnvelopeClock = u64;
@@ -220,6 +236,10 @@ struct EnvelopeGenerator {
     envelope_state: EnvelopeState,
     envelope: u16,
     last_envelope_clock: EnvelopeClock,
+
+    ssg_eg: u8,
+    /// Whether the SSG-EG output is currently inverted, which is toggled each cycle in alternate mode
+    ssg_inverted: bool,
 }
 
 impl EnvelopeGenerator {
@@ -233,6 +253,9 @@ impl EnvelopeGenerator {
             envelope_state: EnvelopeState::Release,
             envelope: MAX_ENVELOPE,
             last_envelope_clock: 0,
+
+            ssg_eg: 0,
+            ssg_inverted: false,
         }
     }
 
@@ -248,24 +271,77 @@ impl EnvelopeGenerator {
         self.rates[etype as usize] = rate;
     }
 
+    fn set_ssg_eg(&mut self, ssg_eg: u8) {
+        self.ssg_eg = ssg_eg & 0x0F;
+    }
+
+    #[inline]
+    fn is_ssg_enabled(&self) -> bool {
+        self.ssg_eg & ssg_eg::ENABLE != 0
+    }
+
+    /// Returns true if the SSG-EG output is inverted, which only applies outside of the release phase
+    #[inline]
+    fn is_ssg_output_inverted(&self) -> bool {
+        self.is_ssg_enabled() && self.ssg_inverted != (self.ssg_eg & ssg_eg::ATTACK != 0)
+    }
+
     fn get_scaled_rate(&self, etype: EnvelopeState, rate_adjust: usize) -> usize {
         calculate_rate(self.rates[etype as usize], rate_adjust)
     }
 
     fn notify_key_change(&mut self, state: bool, _envelope_clock: EnvelopeClock, rate_adjust: usize) {
         if state {
-            let rate = self.get_scaled_rate(EnvelopeState::Attack, rate_adjust);
-            if rate < 62 {
-                self.envelope_state = EnvelopeState::Attack;
-            } else {
-                self.envelope = 0;
-                self.envelope_state = EnvelopeState::Decay;
-            }
+            self.ssg_inverted = false;
+            self.start_attack(rate_adjust);
         } else {
+            // The release starts from the level that was being output, even if it was inverted
+            if self.envelope_state != EnvelopeState::Release && self.is_ssg_output_inverted() {
+                self.envelope = ENVELOPE_CENTER.wrapping_sub(self.envelope) & MAX_ENVELOPE;
+            }
+            self.ssg_inverted = false;
             self.envelope_state = EnvelopeState::Release;
         }
     }
 
+    fn start_attack(&mut self, rate_adjust: usize) {
+        let rate = self.get_scaled_rate(EnvelopeState::Attack, rate_adjust);
+        if rate < 62 {
+            self.envelope_state = EnvelopeState::Attack;
+        } else {
+            self.envelope = 0;
+            self.envelope_state = EnvelopeState::Decay;
+        }
+    }
+
+    /// Check for the end of an SSG-EG cycle, which occurs when the attenuation reaches the center point.  It
+    /// either holds the level, or restarts the attack, and may invert the output.  Returns true if the phase
+    /// generator should be reset, which happens when repeating without alternating
+    fn update_ssg_eg(&mut self, rate_adjust: usize) -> bool {
+        if !self.is_ssg_enabled() || self.envelope < ENVELOPE_CENTER || self.envelope_state == EnvelopeState::Release {
+            return false;
+        }
+
+        let alternate = self.ssg_eg & ssg_eg::ALTERNATE != 0;
+        if self.ssg_eg & ssg_eg::HOLD != 0 {
+            if alternate {
+                self.ssg_inverted = true;
+            }
+            if self.envelope_state != EnvelopeState::Attack && !self.is_ssg_output_inverted() {
+                self.envelope = MAX_ENVELOPE;
+            }
+            false
+        } else {
+            if alternate {
+                self.ssg_inverted = !self.ssg_inverted;
+            }
+            if self.envelope_state != EnvelopeState::Attack {
+                self.start_attack(rate_adjust);
+            }
+            !alternate
+        }
+    }
+
     fn update_envelope(&mut self, envelope_clock: EnvelopeClock, rate_adjust: usize) {
         if self.envelope_state == EnvelopeState::Decay && self.envelope >= self.sustain_level {
             self.envelope_state = EnvelopeState::Sustain;
@@ -293,6 +369,12 @@ impl EnvelopeGenerator {
                         self.envelope = new_envelope.min(MAX_ENVELOPE);
                     }
                 },
+                EnvelopeState::Decay | EnvelopeState::Sustain if self.is_ssg_enabled() => {
+                    // The SSG-EG envelope changes 4 times as fast, and stops at the center point, where the cycle ends
+                    if self.envelope < ENVELOPE_CENTER {
+                        self.envelope = (self.envelope + (increment << 4)).min(MAX_ENVELOPE);
+                    }
+                },
                 EnvelopeState::Decay | EnvelopeState::Sustain | EnvelopeState::Release => {
                     // Convert it to a fixed point decimal number of 4 bit : 8 bits, which will be the output
                     self.envelope += increment << 2;
@@ -311,7 +393,13 @@ impl EnvelopeGenerator {
             self.update_envelope(envelope_clock, rate_adjust);
             self.last_envelope_clock = envelope_clock;
         }
-        (self.envelope + self.total_level).min(MAX_ENVELOPE)
+
+        let envelope = if self.envelope_state != EnvelopeState::Release && self.is_ssg_output_inverted() {
+            ENVELOPE_CENTER.wrapping_sub(self.envelope) & MAX_ENVELOPE
+        } else {
+            self.envelope
+        };
+        (envelope + self.total_level).min(MAX_ENVELOPE)
     }
 }
 
@@ -342,6 +430,8 @@ struct PhaseGenerator {
 
     counter: u32,
     increment: u32,
+    /// The amount the increment is multiplied by for the LFO's pitch modulation
+    lfo_factor: f32,
 }
 
 impl PhaseGenerator {
@@ -357,6 +447,7 @@ impl PhaseGenerator {
 
             counter: 0,
             increment: 0,
+            lfo_factor: 1.0,
         }
     }
 
@@ -421,7 +512,12 @@ impl PhaseGenerator {
 
     fn update_phase(&mut self, _fm_clock: FmClock) -> i16 {
         let phase = ((self.counter >> 10) & 0x3FF) as i16;
-        self.counter += self.increment;
+        let increment = if self.lfo_factor != 1.0 {
+            (self.increment as f32 * self.lfo_factor) as u32
+        } else {
+            self.increment
+        };
+        self.counter = self.counter.wrapping_add(increment);
         phase
     }
 }
@@ -466,6 +562,9 @@ struct Operator {
     phase: PhaseGenerator,
     envelope: EnvelopeGenerator,
     output: i16,
+    /// Whether the LFO's amplitude modulation applies to this operator
+    am_enabled: bool,
+    lfo_attenuation: u16,
 }
 
 impl Operator {
@@ -475,6 +574,8 @@ impl Operator {
             phase: PhaseGenerator::new(debug_name.clone()),
             envelope: EnvelopeGenerator::new(debug_name),
             output: 0,
+            am_enabled: false,
+            lfo_attenuation: 0,
         }
     }
 
@@ -502,6 +603,14 @@ impl Operator {
         self.phase.set_rate_scaling(rate_scaling)
     }
 
+    fn set_ssg_eg(&mut self, ssg_eg: u8) {
+        self.envelope.set_ssg_eg(ssg_eg)
+    }
+
+    fn set_am_enabled(&mut self, enabled: bool) {
+        self.am_enabled = enabled;
+    }
+
     fn notify_key_change(&mut self, state: bool, envelope_clock: EnvelopeClock) {
         self.envelope
             .notify_key_change(state, envelope_clock, self.phase.get_rate_adjust());
@@ -511,7 +620,15 @@ impl Operator {
     fn get_output(&mut self, modulator: i16, clocks: (FmClock, EnvelopeClock)) -> i16 {
         let (fm_clock, envelope_clock) = clocks;
 
-        let envelope = self.envelope.get_envelope(envelope_clock, self.phase.get_rate_adjust());
+        let rate_adjust = self.phase.get_rate_adjust();
+        if self.envelope.update_ssg_eg(rate_adjust) {
+            self.phase.reset();
+        }
+
+        let mut envelope = self.envelope.get_envelope(envelope_clock, rate_adjust);
+        if self.am_enabled {
+            envelope = (envelope + self.lfo_attenuation).min(MAX_ENVELOPE);
+        }
         let phase = self.phase.update_phase(fm_clock);
 
         let mod_phase = phase + modulator;
@@ -548,6 +665,10 @@ struct Channel {
     operators: Vec<Operator>,
     algorithm: OperatorAlgorithm,
     feedback: u8,
+    /// The amplitude and frequency modulation sensitivity to the LFO
+    ams: u8,
+    fms: u8,
+    muted: bool,
 
     key_state: u8,
     next_key_clock: FmClock,
@@ -565,6 +686,9 @@ impl Channel {
                 .collect(),
             algorithm: OperatorAlgorithm::A0,
             feedback: 0,
+            ams: 0,
+            fms: 0,
+            muted: false,
 
             key_state: 0,
             next_key_clock: 0,
@@ -582,6 +706,25 @@ impl Channel {
         self.feedback = feedback;
     }
 
+    fn set_lfo_sensitivity(&mut self, ams: u8, fms: u8) {
+        self.ams = ams;
+        self.fms = fms;
+    }
+
+    fn apply_lfo(&mut self, lfo: &Lfo) {
+        let attenuation = (lfo.amplitude() >> AMS_SHIFT[self.ams as usize]) << 2;
+        let factor = if self.fms != 0 && lfo.enabled {
+            2.0_f32.powf(FMS_DEPTH[self.fms as usize] * lfo.pitch() / 1200.0)
+        } else {
+            1.0
+        };
+
+        for operator in self.operators.iter_mut() {
+            operator.lfo_attenuation = attenuation;
+            operator.phase.lfo_factor = factor;
+        }
+    }
+
     fn change_key_state(&mut self, fm_clock: FmClock, key: u8) {
         self.next_key_clock = fm_clock;
         self.next_key_state = key;
@@ -613,7 +756,14 @@ impl Channel {
         //let output = sign_extend_u16(output, 14);
 
         let sample = output as f32 / (1 << 13) as f32;
+        self.pan(sample)
+    }
 
+    /// Send the sample to the enabled outputs, unless the channel is muted
+    fn pan(&self, sample: f32) -> (f32, f32) {
+        if self.muted {
+            return (0.0, 0.0);
+        }
         let left = if self.enabled.0 { sample } else { 0.0 };
         let right = if self.enabled.1 { sample } else { 0.0 };
         (left, right)
@@ -674,9 +824,63 @@ impl Channel {
 }
 
 
+#[derive(Clone, Default)]
+struct Lfo {
+    enabled: bool,
+    period: u16,
+    divider: u16,
+    /// The 7-bit position within the LFO's waveform
+    counter: u8,
+}
+
+impl Lfo {
+    fn set_control(&mut self, data: u8) {
+        self.enabled = data & 0x08 != 0;
+        self.period = LFO_PERIODS[(data & 0x07) as usize];
+        if !self.enabled {
+            self.divider = 0;
+            self.counter = 0;
+        }
+    }
+
+    fn step(&mut self) {
+        if self.enabled {
+            self.divider += 1;
+            if self.divider >= self.period {
+                self.divider = 0;
+                self.counter = (self.counter + 1) & 0x7F;
+            }
+        }
+    }
+
+    /// Returns the attenuation for amplitude modulation, which is a triangle wave from 0 to 126
+    fn amplitude(&self) -> u16 {
+        let position = (self.counter & 0x3F) as u16;
+        if self.counter & 0x40 == 0 {
+            position << 1
+        } else {
+            (0x3F - position) << 1
+        }
+    }
+
+    /// Returns the pitch deviation for frequency modulation, which is a triangle wave from -1.0 to 1.0
+    fn pitch(&self) -> f32 {
+        let position = (self.counter & 0x1F) as f32 / 31.0;
+        match self.counter >> 5 {
+            0 => position,
+            1 => 1.0 - position,
+            2 => -position,
+            _ => position - 1.0,
+        }
+    }
+}
+
+/// The DAC, which replaces the output of channel 6 with the last sample written to it
+#[derive(Clone)]
 struct Dac {
     enabled: bool,
     samples: VecDeque<(FmClock, f32)>,
+    current: f32,
 }
 
 impl Default for Dac {
@@ -684,6 +888,7 @@ impl Default for Dac {
         Self {
             enabled: false,
             samples: VecDeque::with_capacity(100),
+            current: 0.0,
         }
     }
 }
@@ -693,15 +898,16 @@ impl Dac {
         self.samples.push_back((clock, sample));
     }
 
-    fn get_sample_after(&mut self, clock: FmClock) -> f32 {
-        if let Some((sample_clock, data)) = self.samples.front().cloned() {
-            if clock > sample_clock {
-                self.samples.pop_front();
-                return data;
+    /// Returns the last sample written at or before the given clock, which is held until the next write
+    fn get_sample_at(&mut self, clock: FmClock) -> f32 {
+        while let Some((sample_clock, data)) = self.samples.front().cloned() {
+            if sample_clock > clock {
+                break;
             }
+            self.current = data;
+            self.samples.pop_front();
         }
-
-        0.0
+        self.current
     }
 }
 
@@ -717,6 +923,7 @@ pub struct Ym2612 {
 
     channels: Vec<Channel>,
     dac: Dac,
+    lfo: Lfo,
 
     // TODO the timer hasn't been implemented yet
     #[allow(dead_code)]
@@ -736,6 +943,23 @@ pub struct Ym2612 {
     registers: Vec<u8>,
 }
 
+/// Everything about the chip that's saved with the state of the system, which is all but its audio output
+#[derive(Clone)]
+struct Ym2612State {
+    selected_reg_0: Option<NonZeroU8>,
+    selected_reg_1: Option<NonZeroU8>,
+    next_fm_clock: FmClock,
+    envelope_clock: EnvelopeClock,
+    channels: Vec<Channel>,
+    dac: Dac,
+    lfo: Lfo,
+    timer_a: u16,
+    timer_a_overflow: bool,
+    timer_b: u8,
+    timer_b_overflow: bool,
+    registers: Vec<u8>,
+}
+
 impl Ym2612 {
     pub fn new<H, E>(host: &mut H, clock_frequency: Frequency) -> Result<Self, HostError<E>>
     where
@@ -756,6 +980,7 @@ impl Ym2612 {
 
             channels: (0..CHANNELS).map(|i| Channel::new(format!("ch {}", i))).collect(),
             dac: Dac::default(),
+            lfo: Lfo::default(),
 
             timer_a_enable: false,
             timer_a: 0,
@@ -774,14 +999,20 @@ impl Ym2612 {
 
 impl Steppable for Ym2612 {
     fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        self.step_at(system.clock)
+    }
+}
+
+impl ParallelSteppable for Ym2612 {
+    fn step_at(&mut self, clock: Instant) -> Result<Duration, Error> {
         let rate = self.source.samples_per_second();
         let samples = rate / 1000;
         let sample_duration = Duration::from_secs(1) / rate as u64;
 
-        let mut sample = 0.0;
+        let mut sample = (0.0, 0.0);
         let mut buffer = vec![Sample(0.0, 0.0); samples];
         for (i, buffered_sample) in buffer.iter_mut().enumerate().take(samples) {
-            let sample_clock = system.clock + (sample_duration * i as u64);
+            let sample_clock = clock + (sample_duration * i as u64);
             let fm_clock = sample_clock.as_duration() / self.fm_clock_period;
 
             // Simulate each clock cycle, even if we skip one due to aliasing from the unequal sampling rate of 53,267 Hz
@@ -790,40 +1021,58 @@ impl Steppable for Ym2612 {
             }
             self.next_fm_clock = fm_clock + 1;
 
-            // The DAC uses an 8000 Hz sample rate, so we don't want to skip clocks
-            if self.dac.enabled {
-                sample += self.dac.get_sample_after(fm_clock);
-            }
-
-            // TODO add stereo output, which is supported by ym2612
-            let sample = sample.clamp(-1.0, 1.0);
-            *buffered_sample = Sample(sample, sample);
+            *buffered_sample = Sample(sample.0.clamp(-1.0, 1.0), sample.1.clamp(-1.0, 1.0));
         }
-        self.source.write_samples(system.clock, &buffer);
+        self.source.write_samples(clock, &buffer);
 
         Ok(Duration::from_millis(1)) // Every 1ms of simulated time
     }
 }
 
 impl Ym2612 {
-    fn get_sample(&mut self, fm_clock: FmClock) -> f32 {
+    fn get_sample(&mut self, fm_clock: FmClock) -> (f32, f32) {
         if fm_clock % 3 == 0 {
             self.envelope_clock += 1;
         }
         let clocks = (fm_clock, self.envelope_clock);
 
-        let mut sample = 0.0;
+        self.lfo.step();
+        for channel in self.channels.iter_mut() {
+            channel.apply_lfo(&self.lfo);
+        }
+
+        let mut sample = (0.0, 0.0);
+        let mut add = |(left, right): (f32, f32)| {
+            sample.0 += left;
+            sample.1 += right;
+        };
 
         for ch in 0..(CHANNELS - 1) {
-            sample += self.channels[ch].get_sample(clocks).0;
+            add(self.channels[ch].get_sample(clocks));
         }
 
-        if !self.dac.enabled {
-            sample += self.channels[CHANNELS - 1].get_sample(clocks).0;
+        // The DAC replaces channel 6's output, but still uses its panning
+        if self.dac.enabled {
+            let dac_sample = self.dac.get_sample_at(fm_clock);
+            add(self.channels[CHANNELS - 1].pan(dac_sample));
+        } else {
+            add(self.channels[CHANNELS - 1].get_sample(clocks));
         }
 
         sample
     }
+
+    /// Mute or unmute one of the six channels, numbered from 0, which is useful for debugging music.  Muting the
+    /// last channel also mutes the DAC
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        if let Some(channel) = self.channels.get_mut(channel) {
+            channel.muted = muted;
+        }
+    }
+
+    pub fn is_channel_muted(&self, channel: usize) -> bool {
+        self.channels.get(channel).map(|channel| channel.muted).unwrap_or(false)
+    }
 }
 
 impl Ym2612 {
@@ -832,7 +1081,7 @@ impl Ym2612 {
         self.registers[bank as usize * 256 + reg as usize] = data;
         println!("set {:x} to {:x}", bank as usize * 256 + reg as usize, data);
 
-        //log::warn!("{}: set reg {}{:x} to {:x}", DEV_NAME, bank, reg, data);
+        //log::warn!(target: DEV_NAME, "set reg {}{:x} to {:x}", bank, reg, data);
         match reg {
             0x24 => {
                 self.timer_a = (self.timer_a & 0x3) | ((data as u16) << 2);
@@ -843,11 +1092,14 @@ impl Ym2612 {
             0x26 => {
                 self.timer_b = data;
             },
+            0x22 => {
+                self.lfo.set_control(data);
+            },
             0x27 => {
                 //if (data >> 5) & 0x1 {
                 //    self.timer_b
                 if data >> 6 == 0x01 {
-                    log::warn!("{}: ch 3 special mode requested, but not implemented", DEV_NAME);
+                    log::warn!(target: DEV_NAME, "ch 3 special mode requested, but not implemented");
                 }
             },
 
@@ -857,7 +1109,7 @@ impl Ym2612 {
                     0..=2 => num,
                     4..=6 => num - 1,
                     _ => {
-                        log::warn!("{}: attempted key on/off to invalid channel {}", DEV_NAME, num);
+                        log::warn!(target: DEV_NAME, "attempted key on/off to invalid channel {}", num);
                         return;
                     },
                 };
@@ -865,11 +1117,12 @@ impl Ym2612 {
             },
 
             0x2a => {
+                let sample = ((data as f32 - 128.0) / 255.0) * 2.0;
                 if self.dac.enabled {
                     let fm_clock = clock.as_duration() / self.fm_clock_period;
-                    for i in 0..3 {
-                        self.dac.add_sample(fm_clock + i, ((data as f32 - 128.0) / 255.0) * 2.0);
-                    }
+                    self.dac.add_sample(fm_clock, sample);
+                } else {
+                    self.dac.current = sample;
                 }
             },
 
@@ -910,6 +1163,7 @@ impl Ym2612 {
 
                 let first_decay_rate = self.registers[0x60 + index] & 0x1F;
                 self.channels[ch].operators[op].set_rate(EnvelopeState::Decay, first_decay_rate);
+                self.channels[ch].operators[op].set_am_enabled(self.registers[0x60 + index] & 0x80 != 0);
             },
 
             reg if is_reg_range(reg, 0x70) => {
@@ -939,6 +1193,11 @@ impl Ym2612 {
                 self.channels[ch].operators[op].set_sustain_level(sustain_level);
             },
 
+            reg if is_reg_range(reg, 0x90) => {
+                let (ch, op) = get_ch_op(bank, reg);
+                self.channels[ch].operators[op].set_ssg_eg(data);
+            },
+
             reg if (0xA0..=0xA2).contains(&reg) => {
                 self.update_fnumber(bank, reg & 0x0F);
             },
@@ -969,12 +1228,12 @@ impl Ym2612 {
 
             reg if (0xB4..=0xB6).contains(&reg) => {
                 let ch = get_ch(bank, reg - 4);
-                // TODO add AMS and FMS (which only apply to the LFO)
                 self.channels[ch].set_enabled(data & 0x80 != 0, data & 0x40 != 0);
+                self.channels[ch].set_lfo_sensitivity((data >> 4) & 0x03, data & 0x07);
             },
 
             _ => {
-                log::warn!("{}: !!! unhandled write to register {:0x} with {:0x}", DEV_NAME, reg, data);
+                strict::unhandled(DEV_NAME, format!("unhandled write to register {:0x} with {:0x}", reg, data));
             },
         }
     }
@@ -1032,15 +1291,15 @@ impl Addressable for Ym2612 {
                 data[0] = ((self.timer_a_overflow as u8) << 1) | (self.timer_b_overflow as u8);
             },
             _ => {
-                log::warn!("{}: !!! unhandled read from {:0x}", DEV_NAME, addr);
+                strict::unhandled_read(DEV_NAME, addr);
             },
         }
-        log::debug!("{}: read from register {:x} of {:?}", DEV_NAME, addr, data);
+        log::debug!(target: DEV_NAME, "read from register {:x} of {:?}", addr, data);
         Ok(())
     }
 
     fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
-        log::debug!("{}: write to register {:x} with {:x}", DEV_NAME, addr, data[0]);
+        log::debug!(target: DEV_NAME, "write to register {:x} with {:x}", addr, data[0]);
         match addr {
             0 => {
                 self.selected_reg_0 = NonZeroU8::new(data[0]);
@@ -1059,13 +1318,50 @@ impl Addressable for Ym2612 {
                 }
             },
             _ => {
-                log::warn!("{}: !!! unhandled write {:0x} to {:0x}", DEV_NAME, data[0], addr);
+                strict::unhandled_write(DEV_NAME, addr, data);
             },
         }
         Ok(())
     }
 }
 
+impl Snapshotable for Ym2612 {
+    fn save_state(&mut self) -> Option<Box<dyn Any>> {
+        Some(Box::new(Ym2612State {
+            selected_reg_0: self.selected_reg_0,
+            selected_reg_1: self.selected_reg_1,
+            next_fm_clock: self.next_fm_clock,
+            envelope_clock: self.envelope_clock,
+            channels: self.channels.clone(),
+            dac: self.dac.clone(),
+            lfo: self.lfo.clone(),
+            timer_a: self.timer_a,
+            timer_a_overflow: self.timer_a_overflow,
+            timer_b: self.timer_b,
+            timer_b_overflow: self.timer_b_overflow,
+            registers: self.registers.clone(),
+        }))
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        if let Some(state) = state.downcast_ref::<Ym2612State>() {
+            let state = state.clone();
+            self.selected_reg_0 = state.selected_reg_0;
+            self.selected_reg_1 = state.selected_reg_1;
+            self.next_fm_clock = state.next_fm_clock;
+            self.envelope_clock = state.envelope_clock;
+            self.channels = state.channels;
+            self.dac = state.dac;
+            self.lfo = state.lfo;
+            self.timer_a = state.timer_a;
+            self.timer_a_overflow = state.timer_a_overflow;
+            self.timer_b = state.timer_b;
+            self.timer_b_overflow = state.timer_b_overflow;
+            self.registers = state.registers;
+        }
+    }
+}
+
 impl Transmutable for Ym2612 {
     fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
         Some(self)
@@ -1074,4 +1370,12 @@ impl Transmutable for Ym2612 {
     fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
         Some(self)
     }
+
+    fn as_snapshotable(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
+
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities::probe(self) | Capabilities::AUDIO
+    }
 }
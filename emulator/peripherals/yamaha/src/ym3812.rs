@@ -0,0 +1,1007 @@
+//! Emulate the YM3812 (OPL2) FM Sound Synthesizer (used by the AdLib and Sound Blaster cards, and many arcade boards)
+//!
+//! The chip has nine channels of two operators each, with four waveforms, vibrato and tremolo, and a rhythm mode
+//! which turns the last three channels into five percussion instruments.  It has a single output at the chip's
+//! clock divided by 72 (49,716 Hz for the usual 3.58 MHz clock), which is sent to both sides of the host's output
+//!
+//! The address port is at offset 0, which reads the status register, and the data port is at offset 1
+//!
+//! Resources:
+//! - Registers: <http://www.shipbrook.net/jeff/sb.html> (Programming the AdLib/Sound Blaster FM Music Chips)
+//! - Overview: <https://www.shikadi.net/moddingwiki/OPL_chip>
+//! - Internal Implementation: <https://github.com/nukeykt/Nuked-OPL3>
+
+use std::f64;
+use lazy_static::lazy_static;
+use femtos::{Instant, Duration, Frequency};
+
+use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable, Capabilities, strict};
+use moa_host::{Host, HostError, Audio, Sample};
+use moa_signals::Signal;
+
+
+/// Table of shift values for each possible rate angle
+///
+/// The value here is used to shift a bit to get the number of samples between each increment
+/// of the envelope attenuation, based on the rate that's currently active
+#[rustfmt::skip]
+const COUNTER_SHIFT_VALUES: &[u16] = &[
+    11, 11, 11, 11,
+    10, 10, 10, 10,
+     9,  9,  9,  9,
+     8,  8,  8,  8,
+     7,  7,  7,  7,
+     6,  6,  6,  6,
+     5,  5,  5,  5,
+     4,  4,  4,  4,
+     3,  3,  3,  3,
+     2,  2,  2,  2,
+     1,  1,  1,  1,
+     0,  0,  0,  0,
+     0,  0,  0,  0,
+     0,  0,  0,  0,
+     0,  0,  0,  0,
+     0,  0,  0,  0,
+];
+
+/// Table of attenuation increments for each possible rate angle, which is the same as the YM2612's
+///
+/// Each of the 64 rate values maps to a sequence of 8 cycles, and the amount to increment the
+/// attenuation at each point in that cycle
+#[rustfmt::skip]
+const RATE_TABLE: &[u16] = &[
+    0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0,
+    0, 1, 0, 1, 0, 1, 0, 1,
+    0, 1, 0, 1, 0, 1, 0, 1,
+    0, 1, 0, 1, 0, 1, 0, 1,
+    0, 1, 0, 1, 0, 1, 0, 1,
+    0, 1, 1, 1, 0, 1, 1, 1,
+    0, 1, 1, 1, 0, 1, 1, 1,
+    0, 1, 0, 1, 0, 1, 0, 1,
+    0, 1, 0, 1, 1, 1, 0, 1,
+    0, 1, 1, 1, 0, 1, 1, 1,
+    0, 1, 1, 1, 1, 1, 1, 1,
+    0, 1, 0, 1, 0, 1, 0, 1,
+    0, 1, 0, 1, 1, 1, 0, 1,
+    0, 1, 1, 1, 0, 1, 1, 1,
+    0, 1, 1, 1, 1, 1, 1, 1,
+    0, 1, 0, 1, 0, 1, 0, 1,
+    0, 1, 0, 1, 1, 1, 0, 1,
+    0, 1, 1, 1, 0, 1, 1, 1,
+    0, 1, 1, 1, 1, 1, 1, 1,
+    0, 1, 0, 1, 0, 1, 0, 1,
+    0, 1, 0, 1, 1, 1, 0, 1,
+    0, 1, 1, 1, 0, 1, 1, 1,
+    0, 1, 1, 1, 1, 1, 1, 1,
+    0, 1, 0, 1, 0, 1, 0, 1,
+    0, 1, 0, 1, 1, 1, 0, 1,
+    0, 1, 1, 1, 0, 1, 1, 1,
+    0, 1, 1, 1, 1, 1, 1, 1,
+    0, 1, 0, 1, 0, 1, 0, 1,
+    0, 1, 0, 1, 1, 1, 0, 1,
+    0, 1, 1, 1, 0, 1, 1, 1,
+    0, 1, 1, 1, 1, 1, 1, 1,
+    0, 1, 0, 1, 0, 1, 0, 1,
+    0, 1, 0, 1, 1, 1, 0, 1,
+    0, 1, 1, 1, 0, 1, 1, 1,
+    0, 1, 1, 1, 1, 1, 1, 1,
+    0, 1, 0, 1, 0, 1, 0, 1,
+    0, 1, 0, 1, 1, 1, 0, 1,
+    0, 1, 1, 1, 0, 1, 1, 1,
+    0, 1, 1, 1, 1, 1, 1, 1,
+    0, 1, 0, 1, 0, 1, 0, 1,
+    0, 1, 0, 1, 1, 1, 0, 1,
+    0, 1, 1, 1, 0, 1, 1, 1,
+    0, 1, 1, 1, 1, 1, 1, 1,
+    0, 1, 0, 1, 0, 1, 0, 1,
+    0, 1, 0, 1, 1, 1, 0, 1,
+    0, 1, 1, 1, 0, 1, 1, 1,
+    0, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 2, 1, 1, 1, 2,
+    1, 2, 1, 2, 1, 2, 1, 2,
+    1, 2, 2, 2, 1, 2, 2, 2,
+    2, 2, 2, 2, 2, 2, 2, 2,
+    2, 2, 2, 4, 2, 2, 2, 4,
+    2, 4, 2, 4, 2, 4, 2, 4,
+    2, 4, 4, 4, 2, 4, 4, 4,
+    4, 4, 4, 4, 4, 4, 4, 4,
+    4, 4, 4, 8, 4, 4, 4, 8,
+    4, 8, 4, 8, 4, 8, 4, 8,
+    4, 8, 8, 8, 4, 8, 8, 8,
+    8, 8, 8, 8, 8, 8, 8, 8,
+    8, 8, 8, 8, 8, 8, 8, 8,
+    8, 8, 8, 8, 8, 8, 8, 8,
+    8, 8, 8, 8, 8, 8, 8, 8,
+];
+
+/// The frequency multiple for each of the MULT settings, doubled so that the first one can be a half
+#[rustfmt::skip]
+const MULTIPLE_X2: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+/// The key scale level attenuation of the highest block, indexed by the upper 4 bits of the fnumber
+#[rustfmt::skip]
+const KEY_SCALE_LEVELS: [i16; 16] = [0, 32, 40, 45, 48, 51, 53, 55, 56, 58, 59, 60, 61, 62, 63, 64];
+
+/// The amount the key scale level is shifted right by for each of the KSL settings (0, 3, 1.5, and 6 dB/octave)
+const KEY_SCALE_SHIFT: [u8; 4] = [8, 1, 2, 0];
+
+const LOG_SIN_TABLE_SIZE: usize = 256;
+const EXP_TABLE_SIZE: usize = 256;
+
+lazy_static! {
+    /// The first quarter of a sine wave, as an attenuation in 4.8 fixed point log2 units
+    static ref LOG_SIN_TABLE: Vec<u16> = (0..LOG_SIN_TABLE_SIZE)
+        .map(|i| {
+            let sine = (((i * 2 + 1) as f64 / (LOG_SIN_TABLE_SIZE * 2) as f64) * f64::consts::PI / 2.0).sin();
+            (-sine.log2() * (1 << 8) as f64).round() as u16
+        })
+        .collect();
+
+    /// The fractional part of the exponent used to convert an attenuation back into a linear output
+    static ref EXP_TABLE: Vec<u16> = (0..EXP_TABLE_SIZE)
+        .map(|i| ((2.0_f64.powf(i as f64 / EXP_TABLE_SIZE as f64) - 1.0) * 1024.0).round() as u16)
+        .collect();
+}
+
+const DEV_NAME: &str = "ym3812";
+
+const CHANNELS: usize = 9;
+
+/// The envelope is a 10-bit attenuation in 0.09375 dB steps, the same as the YM2612's
+const MAX_ENVELOPE: u16 = 0x3FF;
+/// An attenuation which is too large to produce any output
+const SILENT: u32 = 0x1000;
+
+#[rustfmt::skip]
+mod reg {
+    pub(super) const TEST: u8               = 0x01;
+    pub(super) const TIMER_1: u8            = 0x02;
+    pub(super) const TIMER_2: u8            = 0x03;
+    pub(super) const TIMER_CONTROL: u8      = 0x04;
+    pub(super) const KEYBOARD_SPLIT: u8     = 0x08;
+    pub(super) const RHYTHM: u8             = 0xBD;
+}
+
+#[rustfmt::skip]
+mod status {
+    pub(super) const IRQ: u8                = 0x80;
+    pub(super) const TIMER_1: u8            = 0x40;
+    pub(super) const TIMER_2: u8            = 0x20;
+    /// The lower bits of the OPL2's status are always 0b110, which is used to tell it apart from the OPL3
+    pub(super) const OPL2_ID: u8            = 0x06;
+}
+
+#[rustfmt::skip]
+mod timer_control {
+    pub(super) const RESET_FLAGS: u8        = 0x80;
+    pub(super) const MASK_1: u8             = 0x40;
+    pub(super) const MASK_2: u8             = 0x20;
+    pub(super) const START_2: u8            = 0x02;
+    pub(super) const START_1: u8            = 0x01;
+}
+
+#[rustfmt::skip]
+mod rhythm {
+    pub(super) const AM_DEPTH: u8           = 0x80;
+    pub(super) const VIBRATO_DEPTH: u8      = 0x40;
+    pub(super) const ENABLE: u8             = 0x20;
+    pub(super) const BASS_DRUM: u8          = 0x10;
+    pub(super) const SNARE_DRUM: u8         = 0x08;
+    pub(super) const TOM_TOM: u8            = 0x04;
+    pub(super) const CYMBAL: u8             = 0x02;
+    pub(super) const HI_HAT: u8             = 0x01;
+}
+
+/// The bit of register 0x01 that allows the waveform registers to select something other than a sine wave
+const WAVEFORM_SELECT_ENABLE: u8 = 0x20;
+/// The bit of register 0x08 that uses the bit 8 of the fnumber for the keyboard split, instead of bit 9
+const NOTE_SELECT: u8 = 0x40;
+/// The bit of register 0x08 that keys on all the channels when timer 1 overflows
+const CSM_MODE: u8 = 0x80;
+
+/// The bit of registers 0xB0-0xB8 that keys on the channel
+const KEY_ON: u8 = 0x20;
+
+
+type SampleClock = u64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum EnvelopeState {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// The channel's frequency and the chip's modulation, which each operator needs when it's updated
+#[derive(Copy, Clone)]
+struct OperatorInputs {
+    fnumber: u16,
+    block: u8,
+    note_select: bool,
+    vibrato_position: u8,
+    vibrato_deep: bool,
+    tremolo: u16,
+}
+
+impl OperatorInputs {
+    /// Returns the value used to scale the envelope rates, based on which part of the keyboard the note is on
+    fn keycode(&self) -> usize {
+        let split_bit = if self.note_select { 8 } else { 9 };
+        ((self.block as usize) << 1) | ((self.fnumber as usize >> split_bit) & 0x01)
+    }
+
+    /// Returns the fnumber with the vibrato applied, which varies it by up to 14 cents in 8 steps
+    fn vibrato_fnumber(&self) -> u16 {
+        let mut range = ((self.fnumber >> 7) & 0x07) as i16;
+        if self.vibrato_position & 0x03 == 0 {
+            range = 0;
+        } else if self.vibrato_position & 0x01 != 0 {
+            range >>= 1;
+        }
+        if !self.vibrato_deep {
+            range >>= 1;
+        }
+        if self.vibrato_position & 0x04 != 0 {
+            range = -range;
+        }
+        (self.fnumber as i16 + range) as u16 & 0x3FF
+    }
+
+    /// Returns the attenuation for the key scale level, which lowers the volume of higher notes
+    fn key_scale_attenuation(&self, key_scale_level: u8) -> u16 {
+        let level = (KEY_SCALE_LEVELS[(self.fnumber >> 6) as usize] << 2) - ((8 - self.block as i16) << 5);
+        // Convert it from 0.1875 dB steps into the envelope's 0.09375 dB steps
+        ((level.max(0) as u16) >> KEY_SCALE_SHIFT[key_scale_level as usize]) << 1
+    }
+}
+
+#[derive(Clone)]
+struct Operator {
+    #[allow(dead_code)]
+    debug_name: String,
+
+    tremolo_enabled: bool,
+    vibrato_enabled: bool,
+    /// Whether the envelope holds at the sustain level until key off, instead of going straight to release (EGT)
+    sustained: bool,
+    key_scale_rate: bool,
+    multiple: u8,
+    key_scale_level: u8,
+    total_level: u16,
+    attack_rate: u8,
+    decay_rate: u8,
+    sustain_level: u16,
+    release_rate: u8,
+    waveform: u8,
+
+    key: bool,
+    envelope_state: EnvelopeState,
+    envelope: u16,
+    attenuation: u16,
+    counter: u32,
+    phase: u16,
+    output: i16,
+}
+
+impl Operator {
+    fn new(debug_name: String) -> Self {
+        Self {
+            debug_name,
+
+            tremolo_enabled: false,
+            vibrato_enabled: false,
+            sustained: false,
+            key_scale_rate: false,
+            multiple: 0,
+            key_scale_level: 0,
+            total_level: 0,
+            attack_rate: 0,
+            decay_rate: 0,
+            sustain_level: 0,
+            release_rate: 0,
+            waveform: 0,
+
+            key: false,
+            envelope_state: EnvelopeState::Release,
+            envelope: MAX_ENVELOPE,
+            attenuation: MAX_ENVELOPE,
+            counter: 0,
+            phase: 0,
+            output: 0,
+        }
+    }
+
+    fn set_modulation(&mut self, data: u8) {
+        self.tremolo_enabled = data & 0x80 != 0;
+        self.vibrato_enabled = data & 0x40 != 0;
+        self.sustained = data & 0x20 != 0;
+        self.key_scale_rate = data & 0x10 != 0;
+        self.multiple = data & 0x0F;
+    }
+
+    fn set_level(&mut self, data: u8) {
+        self.key_scale_level = data >> 6;
+        // The total level is in 0.75 dB steps, which is 8 of the envelope's steps
+        self.total_level = ((data & 0x3F) as u16) << 3;
+    }
+
+    fn set_attack_and_decay(&mut self, data: u8) {
+        self.attack_rate = data >> 4;
+        self.decay_rate = data & 0x0F;
+    }
+
+    fn set_sustain_and_release(&mut self, data: u8) {
+        // The sustain level is in 3 dB steps, except for the last one which is 93 dB
+        let level = (data >> 4) as u16;
+        self.sustain_level = (if level == 0x0F { 0x1F } else { level }) << 5;
+        self.release_rate = data & 0x0F;
+    }
+
+    fn set_waveform(&mut self, data: u8) {
+        self.waveform = data & 0x03;
+    }
+
+    fn set_key(&mut self, key: bool) {
+        if key == self.key {
+            return;
+        }
+        self.key = key;
+        if key {
+            self.envelope_state = EnvelopeState::Attack;
+            self.counter = 0;
+        } else {
+            self.envelope_state = EnvelopeState::Release;
+        }
+    }
+
+    fn get_rate(&self, inputs: &OperatorInputs) -> usize {
+        let rate = match self.envelope_state {
+            EnvelopeState::Attack => self.attack_rate,
+            EnvelopeState::Decay => self.decay_rate,
+            EnvelopeState::Sustain if self.sustained => 0,
+            EnvelopeState::Sustain | EnvelopeState::Release => self.release_rate,
+        };
+
+        if rate == 0 {
+            0
+        } else {
+            let keycode = inputs.keycode();
+            let rate_adjust = if self.key_scale_rate { keycode } else { keycode >> 2 };
+            (rate as usize * 4 + rate_adjust).min(63)
+        }
+    }
+
+    fn update_envelope(&mut self, clock: SampleClock, inputs: &OperatorInputs) {
+        if self.envelope_state == EnvelopeState::Decay && self.envelope >= self.sustain_level {
+            self.envelope_state = EnvelopeState::Sustain;
+        }
+
+        // The attack is instant at the highest rates
+        let rate = self.get_rate(inputs);
+        if self.envelope_state == EnvelopeState::Attack && rate >= 60 {
+            self.envelope = 0;
+            self.envelope_state = EnvelopeState::Decay;
+            return;
+        }
+
+        let counter_shift = COUNTER_SHIFT_VALUES[rate];
+        if clock % (1 << counter_shift) == 0 {
+            let update_cycle = (clock >> counter_shift) & 0x07;
+            let increment = RATE_TABLE[rate * 8 + update_cycle as usize];
+
+            match self.envelope_state {
+                EnvelopeState::Attack => {
+                    let envelope = self.envelope as i32;
+                    let envelope = envelope + ((!envelope * increment as i32) >> 4);
+                    if envelope <= 0 {
+                        self.envelope = 0;
+                        self.envelope_state = EnvelopeState::Decay;
+                    } else {
+                        self.envelope = envelope as u16;
+                    }
+                },
+                EnvelopeState::Decay | EnvelopeState::Sustain | EnvelopeState::Release => {
+                    self.envelope = (self.envelope + increment).min(MAX_ENVELOPE);
+                },
+            }
+        }
+    }
+
+    /// Advance the envelope and phase by one sample
+    fn update(&mut self, clock: SampleClock, inputs: &OperatorInputs) {
+        self.update_envelope(clock, inputs);
+
+        let mut attenuation = self.envelope + self.total_level + inputs.key_scale_attenuation(self.key_scale_level);
+        if self.tremolo_enabled {
+            attenuation += inputs.tremolo;
+        }
+        self.attenuation = attenuation.min(MAX_ENVELOPE);
+
+        let fnumber = if self.vibrato_enabled {
+            inputs.vibrato_fnumber()
+        } else {
+            inputs.fnumber
+        };
+        let increment = ((((fnumber as u32) << inputs.block) >> 1) * MULTIPLE_X2[self.multiple as usize]) >> 1;
+        self.phase = ((self.counter >> 9) & 0x3FF) as u16;
+        self.counter = self.counter.wrapping_add(increment);
+    }
+
+    /// Returns the output at the given phase, which is usually the operator's own phase, offset by the modulator
+    fn get_output_at(&mut self, phase: u16, modulation: i16, waveform_select: bool) -> i16 {
+        let phase = (phase as i16).wrapping_add(modulation) as u16 & 0x3FF;
+        let waveform = if waveform_select { self.waveform } else { 0 };
+
+        let (sine, negative) = waveform_attenuation(waveform, phase);
+        let output = attenuation_to_output(sine + ((self.attenuation as u32) << 2));
+
+        self.output = if negative { -output } else { output };
+        self.output
+    }
+
+    fn get_output(&mut self, modulation: i16, waveform_select: bool) -> i16 {
+        self.get_output_at(self.phase, modulation, waveform_select)
+    }
+}
+
+/// Returns the log-sin attenuation of the waveform at the given 10-bit phase, and whether the output is negative
+fn waveform_attenuation(waveform: u8, phase: u16) -> (u32, bool) {
+    // The table only has the first quarter of the sine wave, which is mirrored to make the second quarter
+    let quarter = |phase: u16| {
+        let index = if phase & 0x100 != 0 { !phase & 0xFF } else { phase & 0xFF };
+        LOG_SIN_TABLE[index as usize] as u32
+    };
+
+    match waveform {
+        // Sine
+        0 => (quarter(phase), phase & 0x200 != 0),
+        // Half sine, which is silent for the negative half
+        1 if phase & 0x200 != 0 => (SILENT, false),
+        // Half sine and absolute sine
+        1 | 2 => (quarter(phase), false),
+        // Quarter sine, which only outputs the rising quarters
+        _ if phase & 0x100 != 0 => (SILENT, false),
+        _ => (quarter(phase & 0xFF), false),
+    }
+}
+
+/// Convert a 4.8 fixed point attenuation into a 13-bit linear output, without the sign
+fn attenuation_to_output(attenuation: u32) -> i16 {
+    if attenuation >= SILENT {
+        return 0;
+    }
+    let mantissa = (EXP_TABLE[(attenuation as usize & 0xFF) ^ 0xFF] as u32 + 1024) << 1;
+    (mantissa >> (attenuation >> 8)) as i16
+}
+
+
+#[derive(Clone)]
+struct Channel {
+    #[allow(dead_code)]
+    debug_name: String,
+    operators: [Operator; 2],
+    fnumber: u16,
+    block: u8,
+    key: bool,
+    feedback: u8,
+    /// Whether the operators' outputs are added together, instead of the first modulating the second
+    additive: bool,
+    muted: bool,
+
+    op1_output: [i16; 2],
+}
+
+impl Channel {
+    fn new(debug_name: String) -> Self {
+        Self {
+            operators: [Operator::new(format!("{}, op 0", debug_name)), Operator::new(format!("{}, op 1", debug_name))],
+            debug_name,
+            fnumber: 0,
+            block: 0,
+            key: false,
+            feedback: 0,
+            additive: false,
+            muted: false,
+
+            op1_output: [0; 2],
+        }
+    }
+
+    fn get_feedback(&self) -> i16 {
+        if self.feedback != 0 {
+            (self.op1_output[0] + self.op1_output[1]) >> (9 - self.feedback)
+        } else {
+            0
+        }
+    }
+
+    fn save_feedback(&mut self) {
+        self.op1_output[0] = self.op1_output[1];
+        self.op1_output[1] = self.operators[0].output;
+    }
+
+    fn get_output(&mut self, waveform_select: bool) -> i16 {
+        let feedback = self.get_feedback();
+        let output = if self.additive {
+            self.operators[0].get_output(feedback, waveform_select) + self.operators[1].get_output(0, waveform_select)
+        } else {
+            let modulator = self.operators[0].get_output(feedback, waveform_select);
+            self.operators[1].get_output(modulator, waveform_select)
+        };
+        self.save_feedback();
+        output
+    }
+
+    /// Returns the output of the bass drum, which only outputs the second operator, even in additive mode
+    fn get_bass_drum_output(&mut self, waveform_select: bool) -> i16 {
+        let feedback = self.get_feedback();
+        let modulator = self.operators[0].get_output(feedback, waveform_select);
+        let output = if self.additive {
+            self.operators[1].get_output(0, waveform_select)
+        } else {
+            self.operators[1].get_output(modulator, waveform_select)
+        };
+        self.save_feedback();
+        output
+    }
+}
+
+
+/// One of the two timers, which count up from the loaded value to 256 at a fixed rate, and set a status flag when
+/// they overflow
+struct Timer {
+    tick: Duration,
+    load: u8,
+    running: bool,
+    masked: bool,
+    overflowed: bool,
+    next_overflow: Instant,
+}
+
+impl Timer {
+    fn new(tick: Duration) -> Self {
+        Self {
+            tick,
+            load: 0,
+            running: false,
+            masked: false,
+            overflowed: false,
+            next_overflow: Instant::START,
+        }
+    }
+
+    fn period(&self) -> Duration {
+        self.tick * (256 - self.load as u64)
+    }
+
+    fn set_running(&mut self, clock: Instant, running: bool) {
+        // The counter is reloaded when the timer is started
+        if running && !self.running {
+            self.next_overflow = clock + self.period();
+        }
+        self.running = running;
+    }
+
+    /// Count up to the given time, returning true if the timer overflowed
+    fn update(&mut self, clock: Instant) -> bool {
+        let mut overflowed = false;
+        while self.running && clock >= self.next_overflow {
+            self.next_overflow += self.period();
+            overflowed = true;
+        }
+        if overflowed && !self.masked {
+            self.overflowed = true;
+        }
+        overflowed
+    }
+}
+
+
+pub struct Ym3812 {
+    source: Box<dyn Audio>,
+    selected: u8,
+
+    sample_period: Duration,
+    next_sample_clock: SampleClock,
+    sample: f32,
+
+    channels: Vec<Channel>,
+    waveform_select: bool,
+    note_select: bool,
+    rhythm: u8,
+    tremolo_position: u8,
+    vibrato_position: u8,
+    noise: u32,
+
+    timer_1: Timer,
+    timer_2: Timer,
+    irq: Signal<bool>,
+
+    registers: [u8; 256],
+}
+
+impl Ym3812 {
+    pub fn new<H, E>(host: &mut H, clock_frequency: Frequency) -> Result<Self, HostError<E>>
+    where
+        H: Host<Error = E>,
+    {
+        let source = host.add_audio_source()?;
+        let sample_period = (clock_frequency / 72).period_duration();
+        let timer_tick = clock_frequency.period_duration() * 288_u32;
+
+        Ok(Self {
+            source,
+            selected: 0,
+
+            sample_period,
+            next_sample_clock: 0,
+            sample: 0.0,
+
+            channels: (0..CHANNELS).map(|i| Channel::new(format!("ch {}", i))).collect(),
+            waveform_select: false,
+            note_select: false,
+            rhythm: 0,
+            tremolo_position: 0,
+            vibrato_position: 0,
+            noise: 1,
+
+            // Timer 1 counts every 80 us and timer 2 every 320 us, with the usual 3.58 MHz clock
+            timer_1: Timer::new(timer_tick),
+            timer_2: Timer::new(timer_tick * 4_u32),
+            irq: Signal::new(false),
+
+            registers: [0; 256],
+        })
+    }
+
+    /// Returns the interrupt output, which is true while either of the timer flags is set
+    pub fn irq(&self) -> Signal<bool> {
+        self.irq.clone()
+    }
+
+    /// Mute or unmute one of the nine channels, numbered from 0, which is useful for debugging music
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        if let Some(channel) = self.channels.get_mut(channel) {
+            channel.muted = muted;
+        }
+    }
+
+    pub fn is_channel_muted(&self, channel: usize) -> bool {
+        self.channels.get(channel).map(|channel| channel.muted).unwrap_or(false)
+    }
+
+    fn read_status(&self) -> u8 {
+        let mut data = status::OPL2_ID;
+        if self.timer_1.overflowed {
+            data |= status::TIMER_1;
+        }
+        if self.timer_2.overflowed {
+            data |= status::TIMER_2;
+        }
+        if self.timer_1.overflowed || self.timer_2.overflowed {
+            data |= status::IRQ;
+        }
+        data
+    }
+
+    fn update_timers(&mut self, clock: Instant) {
+        if self.timer_1.update(clock) && self.registers[reg::KEYBOARD_SPLIT as usize] & CSM_MODE != 0 {
+            log::warn!(target: DEV_NAME, "csm mode key on requested, but not implemented");
+        }
+        self.timer_2.update(clock);
+
+        let irq = self.timer_1.overflowed || self.timer_2.overflowed;
+        if self.irq.get() != irq {
+            self.irq.set(irq);
+        }
+    }
+}
+
+impl Steppable for Ym3812 {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        self.update_timers(system.clock);
+
+        let rate = self.source.samples_per_second();
+        let samples = rate / 1000;
+        let sample_duration = Duration::from_secs(1) / rate as u64;
+
+        let mut buffer = vec![Sample(0.0, 0.0); samples];
+        for (i, buffered_sample) in buffer.iter_mut().enumerate() {
+            let sample_clock = system.clock + (sample_duration * i as u64);
+            let chip_clock = sample_clock.as_duration() / self.sample_period;
+
+            // Simulate each of the chip's samples, even if some are skipped because of its unequal sampling rate
+            for clock in self.next_sample_clock..=chip_clock {
+                self.sample = self.get_sample(clock);
+            }
+            self.next_sample_clock = chip_clock + 1;
+
+            let sample = self.sample.clamp(-1.0, 1.0);
+            *buffered_sample = Sample(sample, sample);
+        }
+        self.source.write_samples(system.clock, &buffer);
+
+        Ok(Duration::from_millis(1)) // Every 1ms of simulated time
+    }
+}
+
+impl Ym3812 {
+    fn get_sample(&mut self, clock: SampleClock) -> f32 {
+        self.update_modulation(clock);
+
+        let am_deep = self.rhythm & rhythm::AM_DEPTH != 0;
+        let tremolo_position = self.tremolo_position as u16;
+        let tremolo = if tremolo_position < 105 {
+            tremolo_position
+        } else {
+            210 - tremolo_position
+        };
+        // Convert it from 0.1875 dB steps into the envelope's 0.09375 dB steps, with a depth of either 4.8 dB or 1 dB
+        let depth_shift = if am_deep { 2 } else { 4 };
+        let tremolo = (tremolo >> depth_shift) << 1;
+
+        for channel in self.channels.iter_mut() {
+            let inputs = OperatorInputs {
+                fnumber: channel.fnumber,
+                block: channel.block,
+                note_select: self.note_select,
+                vibrato_position: self.vibrato_position,
+                vibrato_deep: self.rhythm & rhythm::VIBRATO_DEPTH != 0,
+                tremolo,
+            };
+            for operator in channel.operators.iter_mut() {
+                operator.update(clock, &inputs);
+            }
+        }
+
+        let rhythm_enabled = self.rhythm & rhythm::ENABLE != 0;
+        let melodic_channels = if rhythm_enabled { 6 } else { CHANNELS };
+
+        let mut output = 0.0;
+        for channel in self.channels[..melodic_channels].iter_mut() {
+            let sample = channel.get_output(self.waveform_select);
+            if !channel.muted {
+                output += sample as f32;
+            }
+        }
+        if rhythm_enabled {
+            output += self.get_rhythm_output();
+        }
+
+        output / (1 << 13) as f32
+    }
+
+    fn update_modulation(&mut self, clock: SampleClock) {
+        // The tremolo is a triangle wave of about 3.7 Hz and the vibrato steps through 8 positions at about 6.1 Hz
+        if clock & 0x3F == 0x3F {
+            self.tremolo_position = (self.tremolo_position + 1) % 210;
+        }
+        if clock & 0x3FF == 0x3FF {
+            self.vibrato_position = (self.vibrato_position + 1) & 0x07;
+        }
+
+        // The noise generator is a 23-bit shift register tapped at bits 0 and 14
+        let feedback = (self.noise ^ (self.noise >> 14)) & 0x01;
+        self.noise = (self.noise >> 1) | (feedback << 22);
+    }
+
+    /// Returns the output of the five percussion instruments, which replace channels 6 to 8 in rhythm mode
+    ///
+    /// The hi-hat, snare drum, and cymbal use phases made from bits of the hi-hat and cymbal operators' phases, and
+    /// the noise generator.  The outputs of the percussion instruments are twice as loud as the other channels
+    fn get_rhythm_output(&mut self) -> f32 {
+        let waveform_select = self.waveform_select;
+        let noise = self.noise & 0x01 != 0;
+
+        let hi_hat_phase = self.channels[7].operators[0].phase;
+        let cymbal_phase = self.channels[8].operators[1].phase;
+        let bit = |phase: u16, bit: u16| (phase >> bit) & 0x01 != 0;
+        let mixed = (bit(hi_hat_phase, 2) ^ bit(hi_hat_phase, 7))
+            | (bit(hi_hat_phase, 3) ^ bit(cymbal_phase, 5))
+            | (bit(cymbal_phase, 3) ^ bit(cymbal_phase, 5));
+
+        let hi_hat = ((mixed as u16) << 9) | if mixed ^ noise { 0xD0 } else { 0x34 };
+        let snare_drum = ((bit(hi_hat_phase, 8) as u16) << 9) | (((bit(hi_hat_phase, 8) ^ noise) as u16) << 8);
+        let cymbal = ((mixed as u16) << 9) | 0x80;
+
+        let bass_drum_output = self.channels[6].get_bass_drum_output(waveform_select);
+        let channel_7 = &mut self.channels[7];
+        let hi_hat_output = channel_7.operators[0].get_output_at(hi_hat, 0, waveform_select);
+        let snare_drum_output = channel_7.operators[1].get_output_at(snare_drum, 0, waveform_select);
+        let channel_8 = &mut self.channels[8];
+        let tom_tom_output = channel_8.operators[0].get_output(0, waveform_select);
+        let cymbal_output = channel_8.operators[1].get_output_at(cymbal, 0, waveform_select);
+
+        let mut output = 0.0;
+        if !self.channels[6].muted {
+            output += bass_drum_output as f32 * 2.0;
+        }
+        if !self.channels[7].muted {
+            output += (hi_hat_output + snare_drum_output) as f32 * 2.0;
+        }
+        if !self.channels[8].muted {
+            output += (tom_tom_output + cymbal_output) as f32 * 2.0;
+        }
+        output
+    }
+}
+
+impl Ym3812 {
+    pub fn set_register(&mut self, clock: Instant, reg: u8, data: u8) {
+        // Keep a copy for debugging purposes, and if the original values are needed
+        self.registers[reg as usize] = data;
+
+        match reg {
+            reg::TEST => {
+                self.waveform_select = data & WAVEFORM_SELECT_ENABLE != 0;
+            },
+            reg::TIMER_1 => {
+                self.timer_1.load = data;
+            },
+            reg::TIMER_2 => {
+                self.timer_2.load = data;
+            },
+            reg::TIMER_CONTROL => {
+                self.update_timers(clock);
+                if data & timer_control::RESET_FLAGS != 0 {
+                    // Resetting the flags ignores the other bits
+                    self.timer_1.overflowed = false;
+                    self.timer_2.overflowed = false;
+                } else {
+                    self.timer_1.masked = data & timer_control::MASK_1 != 0;
+                    self.timer_2.masked = data & timer_control::MASK_2 != 0;
+                    self.timer_1.set_running(clock, data & timer_control::START_1 != 0);
+                    self.timer_2.set_running(clock, data & timer_control::START_2 != 0);
+                }
+                self.update_timers(clock);
+            },
+            reg::KEYBOARD_SPLIT => {
+                self.note_select = data & NOTE_SELECT != 0;
+                if data & CSM_MODE != 0 {
+                    log::warn!(target: DEV_NAME, "csm mode requested, but not implemented");
+                }
+            },
+
+            0x20..=0x35 => {
+                if let Some((ch, op)) = get_ch_op(reg & 0x1F) {
+                    self.channels[ch].operators[op].set_modulation(data);
+                }
+            },
+            0x40..=0x55 => {
+                if let Some((ch, op)) = get_ch_op(reg & 0x1F) {
+                    self.channels[ch].operators[op].set_level(data);
+                }
+            },
+            0x60..=0x75 => {
+                if let Some((ch, op)) = get_ch_op(reg & 0x1F) {
+                    self.channels[ch].operators[op].set_attack_and_decay(data);
+                }
+            },
+            0x80..=0x95 => {
+                if let Some((ch, op)) = get_ch_op(reg & 0x1F) {
+                    self.channels[ch].operators[op].set_sustain_and_release(data);
+                }
+            },
+            0xE0..=0xF5 => {
+                if let Some((ch, op)) = get_ch_op(reg & 0x1F) {
+                    self.channels[ch].operators[op].set_waveform(data);
+                }
+            },
+
+            0xA0..=0xA8 => {
+                let channel = &mut self.channels[(reg & 0x0F) as usize];
+                channel.fnumber = (channel.fnumber & 0x300) | data as u16;
+            },
+            0xB0..=0xB8 => {
+                let channel = &mut self.channels[(reg & 0x0F) as usize];
+                channel.fnumber = (channel.fnumber & 0xFF) | (((data & 0x03) as u16) << 8);
+                channel.block = (data >> 2) & 0x07;
+                channel.key = data & KEY_ON != 0;
+                self.update_key_states();
+            },
+            reg::RHYTHM => {
+                self.rhythm = data;
+                self.update_key_states();
+            },
+            0xC0..=0xC8 => {
+                let channel = &mut self.channels[(reg & 0x0F) as usize];
+                channel.feedback = (data >> 1) & 0x07;
+                channel.additive = data & 0x01 != 0;
+            },
+
+            _ => {
+                strict::unhandled(DEV_NAME, format!("unhandled write to register {:0x} with {:0x}", reg, data));
+            },
+        }
+    }
+
+    /// Key each operator on or off, from either its channel's key on bit or the rhythm register's percussion bits
+    fn update_key_states(&mut self) {
+        let drums = if self.rhythm & rhythm::ENABLE != 0 { self.rhythm } else { 0 };
+        let percussion = [
+            (6, 0, rhythm::BASS_DRUM),
+            (6, 1, rhythm::BASS_DRUM),
+            (7, 0, rhythm::HI_HAT),
+            (7, 1, rhythm::SNARE_DRUM),
+            (8, 0, rhythm::TOM_TOM),
+            (8, 1, rhythm::CYMBAL),
+        ];
+
+        for (ch, channel) in self.channels.iter_mut().enumerate() {
+            for (op, operator) in channel.operators.iter_mut().enumerate() {
+                let drum = percussion
+                    .iter()
+                    .any(|&(drum_ch, drum_op, bit)| drum_ch == ch && drum_op == op && drums & bit != 0);
+                operator.set_key(channel.key || drum);
+            }
+        }
+    }
+}
+
+/// Get the channel and operator to target with the lower 5 bits of an operator register number
+///
+/// The operators are numbered in groups of 6, with a gap of 2 between each group.  Each group is
+/// the first operator of three channels followed by the second operator of the same three channels
+#[inline]
+fn get_ch_op(offset: u8) -> Option<(usize, usize)> {
+    let group = (offset as usize) >> 3;
+    let index = (offset as usize) & 0x07;
+    if group > 2 || index > 5 {
+        return None;
+    }
+    Some((group * 3 + index % 3, index / 3))
+}
+
+impl Addressable for Ym3812 {
+    fn size(&self) -> usize {
+        0x02
+    }
+
+    fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        match addr {
+            0 => {
+                self.update_timers(clock);
+                data[0] = self.read_status();
+            },
+            _ => {
+                strict::unhandled_read(DEV_NAME, addr);
+                data[0] = 0xFF;
+            },
+        }
+        log::debug!(target: DEV_NAME, "read from register {:x} of {:?}", addr, data);
+        Ok(())
+    }
+
+    fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        log::debug!(target: DEV_NAME, "write to register {:x} with {:x}", addr, data[0]);
+        match addr {
+            0 => {
+                self.selected = data[0];
+            },
+            1 => {
+                self.set_register(clock, self.selected, data[0]);
+            },
+            _ => {
+                strict::unhandled_write(DEV_NAME, addr, data);
+            },
+        }
+        Ok(())
+    }
+}
+
+impl Transmutable for Ym3812 {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities::probe(self) | Capabilities::AUDIO
+    }
+}
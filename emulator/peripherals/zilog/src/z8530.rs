@@ -1,33 +1,511 @@
-use femtos::{Instant, Duration};
+use femtos::{Instant, Duration, Frequency};
 
-use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable};
+use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable, Waker, InterruptLine, strict};
+use moa_host::Tty;
+
+
+/// The address bit that selects channel A when set, or channel B when clear
+const ADDR_CHANNEL_A: Address = 0x01;
+/// The address bit that selects the data register when set, or the control registers when clear
+const ADDR_DATA: Address = 0x02;
+
+#[rustfmt::skip]
+mod wr0 {
+    pub(super) const REGISTER: u8           = 0x07;
+    pub(super) const COMMAND: u8            = 0x38;
+    pub(super) const CMD_POINT_HIGH: u8     = 0x08;
+    pub(super) const CMD_RESET_EXT: u8      = 0x10;
+    pub(super) const CMD_RX_NEXT_CHAR: u8   = 0x20;
+    pub(super) const CMD_RESET_TX: u8       = 0x28;
+}
+
+#[rustfmt::skip]
+mod wr1 {
+    pub(super) const EXT_INT_ENABLE: u8     = 0x01;
+    pub(super) const TX_INT_ENABLE: u8      = 0x02;
+    pub(super) const RX_INT_MODE: u8        = 0x18;
+    pub(super) const RX_INT_FIRST: u8       = 0x08;
+    pub(super) const RX_INT_ALL: u8         = 0x10;
+}
+
+#[rustfmt::skip]
+mod wr9 {
+    pub(super) const VECTOR_INCLUDES_STATUS: u8 = 0x01;
+    pub(super) const MASTER_INT_ENABLE: u8      = 0x08;
+    pub(super) const STATUS_HIGH: u8            = 0x10;
+    pub(super) const RESET: u8                  = 0xC0;
+    pub(super) const RESET_CHANNEL_B: u8        = 0x40;
+    pub(super) const RESET_CHANNEL_A: u8        = 0x80;
+}
+
+#[rustfmt::skip]
+mod rr0 {
+    pub(super) const RX_AVAILABLE: u8       = 0x01;
+    pub(super) const TX_EMPTY: u8           = 0x04;
+    pub(super) const DCD: u8                = 0x08;
+    pub(super) const CTS: u8                = 0x20;
+    pub(super) const TX_UNDERRUN: u8        = 0x40;
+}
+
+/// The interrupt pending bits in RR3, for channel B, which are shifted up by 3 for channel A
+#[rustfmt::skip]
+mod ip {
+    pub(super) const EXT: u8                = 0x01;
+    pub(super) const TX: u8                 = 0x02;
+    pub(super) const RX: u8                 = 0x04;
+}
+
+const WR3_RX_ENABLE: u8 = 0x01;
+const WR15_DCD_INT_ENABLE: u8 = 0x08;
+const WR4_STOP_BITS: u8 = 0x0C;
+const WR4_PARITY_ENABLE: u8 = 0x01;
+const WR14_BRG_ENABLE: u8 = 0x01;
 
 const DEV_NAME: &str = "z8530";
 
-#[derive(Default)]
-pub struct Z8530 {}
+/// How often to check for input from a tty that can't wake the device when input arrives
+const POLL_INTERVAL_US: u64 = 100;
+/// How often to step when there is nothing to do, since any input or register access will wake the device
+const IDLE_INTERVAL_US: u64 = 1_000_000;
 
-impl Addressable for Z8530 {
-    fn size(&self) -> usize {
-        0x10
+pub struct Z8530Channel {
+    tty: Option<Box<dyn Tty>>,
+    wakes_on_input: bool,
+
+    pointer: u8,
+    write_regs: [u8; 16],
+
+    rx_data: Option<u8>,
+    /// The earliest time that the next character can be received, based on the baud rate
+    rx_ready_at: Instant,
+    /// True when the first character received will cause an interrupt, in the first character interrupt mode
+    rx_first_armed: bool,
+    /// The time that the last character written will finish sending, during which the buffer isn't empty
+    tx_busy_until: Option<Instant>,
+
+    tx_pending: bool,
+    ext_pending: bool,
+    /// The level of the DCD input, as reported in RR0
+    dcd: bool,
+}
+
+impl Default for Z8530Channel {
+    fn default() -> Self {
+        Self {
+            tty: None,
+            wakes_on_input: false,
+
+            pointer: 0,
+            write_regs: [0; 16],
+
+            rx_data: None,
+            rx_ready_at: Instant::START,
+            rx_first_armed: true,
+            tx_busy_until: None,
+
+            tx_pending: false,
+            ext_pending: false,
+            dcd: true,
+        }
     }
+}
 
-    fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
-        log::warn!("{}: !!! unhandled read from {:0x}", DEV_NAME, addr);
-        log::debug!("{}: read from register {:x} of {:?}", DEV_NAME, addr, data);
-        Ok(())
+impl Z8530Channel {
+    pub fn connect(&mut self, pty: Box<dyn Tty>) -> Result<String, Error> {
+        let name = pty.device_name();
+        println!("{}: opening pts {}", DEV_NAME, name);
+        self.tty = Some(pty);
+        Ok(name)
     }
 
-    fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
-        log::debug!("{}: write to register {:x} with {:x}", DEV_NAME, addr, data[0]);
-        log::warn!("{}: !!! unhandled write {:0x} to {:0x}", DEV_NAME, data[0], addr);
+    /// Set the level of the DCD input, which causes an external/status interrupt when it changes, if enabled
+    pub fn set_dcd(&mut self, level: bool) {
+        if level != self.dcd {
+            self.dcd = level;
+            if (self.write_regs[15] & WR15_DCD_INT_ENABLE) != 0 {
+                self.ext_pending = true;
+            }
+        }
+    }
+
+    fn set_waker(&mut self, waker: &Waker) {
+        if let Some(tty) = self.tty.as_mut() {
+            let waker = waker.clone();
+            self.wakes_on_input = tty.on_input(Box::new(move || waker.wake()));
+        }
+    }
+
+    fn reset(&mut self) {
+        self.pointer = 0;
+        self.write_regs = [0; 16];
+        self.write_regs[4] = 0x04;
+        self.write_regs[11] = 0x08;
+        self.write_regs[15] = 0xF8;
+        self.rx_data = None;
+        self.rx_first_armed = true;
+        self.tx_busy_until = None;
+        self.tx_pending = false;
+        self.ext_pending = false;
+    }
+
+    fn rx_enabled(&self) -> bool {
+        (self.write_regs[3] & WR3_RX_ENABLE) != 0
+    }
+
+    /// Returns true if the port is waiting for input from a tty that must be checked periodically
+    fn needs_polling(&self) -> bool {
+        self.rx_enabled() && self.rx_data.is_none() && self.tty.is_some() && !self.wakes_on_input
+    }
+
+    /// Returns the time to send or receive one character, or None if the baud rate generator isn't running
+    fn character_time(&self, pclk: Frequency) -> Option<Duration> {
+        if (self.write_regs[14] & WR14_BRG_ENABLE) == 0 {
+            return None;
+        }
+
+        let time_constant = u16::from_le_bytes([self.write_regs[12], self.write_regs[13]]) as u64;
+        let multiplier = match self.write_regs[4] >> 6 {
+            0 => 1,
+            1 => 16,
+            2 => 32,
+            _ => 64,
+        };
+        let baud_rate = pclk.as_hz() as u64 / (2 * (time_constant + 2) * multiplier);
+
+        let data_bits = match (self.write_regs[5] >> 5) & 0x03 {
+            0 => 5,
+            1 => 7,
+            2 => 6,
+            _ => 8,
+        };
+        let stop_bits = if (self.write_regs[4] & WR4_STOP_BITS) == 0x04 { 1 } else { 2 };
+        let parity_bits = (self.write_regs[4] & WR4_PARITY_ENABLE) as u64;
+        let bits = 1 + data_bits + stop_bits + parity_bits;
+
+        Some(Duration::from_nanos(bits * 1_000_000_000 / baud_rate.max(1)))
+    }
+
+    fn rx_pending(&self) -> bool {
+        if self.rx_data.is_none() {
+            return false;
+        }
+        match self.write_regs[1] & wr1::RX_INT_MODE {
+            wr1::RX_INT_FIRST => self.rx_first_armed,
+            wr1::RX_INT_ALL => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the interrupt pending bits in the same order as RR3 for channel B
+    fn interrupts_pending(&self) -> u8 {
+        let mut pending = 0;
+        if self.ext_pending && (self.write_regs[1] & wr1::EXT_INT_ENABLE) != 0 {
+            pending |= ip::EXT;
+        }
+        if self.tx_pending && (self.write_regs[1] & wr1::TX_INT_ENABLE) != 0 {
+            pending |= ip::TX;
+        }
+        if self.rx_pending() {
+            pending |= ip::RX;
+        }
+        pending
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = rr0::CTS | rr0::TX_UNDERRUN;
+        if self.dcd {
+            status |= rr0::DCD;
+        }
+        if self.rx_data.is_some() {
+            status |= rr0::RX_AVAILABLE;
+        }
+        if self.tx_busy_until.is_none() {
+            status |= rr0::TX_EMPTY;
+        }
+        status
+    }
+
+    fn update(&mut self, clock: Instant, pclk: Frequency) -> Result<(), Error> {
+        if let Some(busy_until) = self.tx_busy_until {
+            if busy_until <= clock {
+                self.tx_busy_until = None;
+                self.tx_pending = true;
+            }
+        }
+
+        if self.rx_enabled() && self.rx_data.is_none() && self.rx_ready_at <= clock {
+            if let Some(input) = self.tty.as_mut().and_then(|tty| tty.read()) {
+                self.rx_data = Some(input);
+                self.rx_ready_at = clock + self.character_time(pclk).unwrap_or(Duration::ZERO);
+            }
+        }
         Ok(())
     }
+
+    fn read_data(&mut self) -> u8 {
+        let data = self.rx_data.take().unwrap_or(0);
+        if (self.write_regs[1] & wr1::RX_INT_MODE) == wr1::RX_INT_FIRST {
+            self.rx_first_armed = false;
+        }
+        data
+    }
+
+    fn send_byte(&mut self, clock: Instant, pclk: Frequency, data: u8) {
+        self.tty.as_mut().map(|tty| tty.write(data));
+        self.tx_pending = false;
+        self.tx_busy_until = Some(clock + self.character_time(pclk).unwrap_or(Duration::ZERO));
+    }
+
+    fn handle_command(&mut self, data: u8) {
+        match data & wr0::COMMAND {
+            wr0::CMD_RESET_EXT => self.ext_pending = false,
+            wr0::CMD_RX_NEXT_CHAR => self.rx_first_armed = true,
+            wr0::CMD_RESET_TX => self.tx_pending = false,
+            _ => {},
+        }
+    }
+}
+
+pub struct Z8530 {
+    /// The PCLK frequency, which drives the baud rate generators
+    frequency: Frequency,
+    pub port_a: Z8530Channel,
+    pub port_b: Z8530Channel,
+
+    vector: u8,
+    master_control: u8,
+    interrupt_priority: u8,
+    autovector: Option<u8>,
+    interrupt_line: Option<InterruptLine>,
+
+    waker: Option<Waker>,
+}
+
+impl Default for Z8530 {
+    fn default() -> Self {
+        Self::new(Frequency::from_hz(3_686_400))
+    }
+}
+
+impl Z8530 {
+    pub fn new(frequency: Frequency) -> Self {
+        let mut scc = Self {
+            frequency,
+            port_a: Z8530Channel::default(),
+            port_b: Z8530Channel::default(),
+
+            vector: 0,
+            master_control: 0,
+            interrupt_priority: 4,
+            autovector: None,
+            interrupt_line: None,
+
+            waker: None,
+        };
+        scc.port_a.reset();
+        scc.port_b.reset();
+        scc
+    }
+
+    pub fn with_interrupt_priority(mut self, priority: u8) -> Self {
+        self.interrupt_priority = priority;
+        self
+    }
+
+    /// Drive the given interrupt line instead of setting the interrupt on the system's controller directly
+    pub fn with_interrupt_line(mut self, line: InterruptLine) -> Self {
+        self.interrupt_line = Some(line);
+        self
+    }
+
+    /// Use a fixed interrupt number instead of the vector register, for systems that don't read the vector
+    pub fn with_autovector(mut self, number: u8) -> Self {
+        self.autovector = Some(number);
+        self
+    }
+
+    /// Step the device at the current clock, to update the state after a register access
+    fn wake(&self) {
+        if let Some(waker) = self.waker.as_ref() {
+            waker.wake();
+        }
+    }
+
+    /// Returns the interrupt pending bits of both channels, as read from RR3
+    fn interrupts_pending(&self) -> u8 {
+        (self.port_a.interrupts_pending() << 3) | self.port_b.interrupts_pending()
+    }
+
+    /// Returns the vector with the highest priority interrupt encoded into it, as read from RR2 of channel B
+    fn modified_vector(&self) -> u8 {
+        let pending = self.interrupts_pending();
+        let status = if (pending & (ip::RX << 3)) != 0 {
+            0b110
+        } else if (pending & (ip::TX << 3)) != 0 {
+            0b100
+        } else if (pending & (ip::EXT << 3)) != 0 {
+            0b101
+        } else if (pending & ip::RX) != 0 {
+            0b010
+        } else if (pending & ip::TX) != 0 {
+            0b000
+        } else if (pending & ip::EXT) != 0 {
+            0b001
+        } else {
+            0b011
+        };
+
+        if (self.master_control & wr9::STATUS_HIGH) != 0 {
+            let status = ((status & 0x01) << 2) | (status & 0x02) | ((status & 0x04) >> 2);
+            (self.vector & !0x70) | (status << 4)
+        } else {
+            (self.vector & !0x0E) | (status << 1)
+        }
+    }
+
+    fn check_interrupt_state(&mut self, system: &System) -> Result<(), Error> {
+        let active = (self.master_control & wr9::MASTER_INT_ENABLE) != 0 && self.interrupts_pending() != 0;
+        let number = match self.autovector {
+            Some(number) => number,
+            None if (self.master_control & wr9::VECTOR_INCLUDES_STATUS) != 0 => self.modified_vector(),
+            None => self.vector,
+        };
+        match self.interrupt_line.as_ref() {
+            Some(line) => {
+                line.set_vectored(active, number);
+                Ok(())
+            },
+            None => system.get_interrupt_controller().set(active, self.interrupt_priority, number),
+        }
+    }
+
+    fn channel(&mut self, addr: Address) -> &mut Z8530Channel {
+        if (addr & ADDR_CHANNEL_A) != 0 {
+            &mut self.port_a
+        } else {
+            &mut self.port_b
+        }
+    }
+
+    fn read_control(&mut self, addr: Address) -> u8 {
+        let is_channel_a = (addr & ADDR_CHANNEL_A) != 0;
+        let channel = self.channel(addr);
+        let register = channel.pointer;
+        channel.pointer = 0;
+
+        match register & 0x0F {
+            0 | 4 => channel.status(),
+            // All characters have been sent
+            1 | 5 => 0x01,
+            2 | 6 if is_channel_a => self.vector,
+            2 | 6 => self.modified_vector(),
+            3 | 7 if is_channel_a => self.interrupts_pending(),
+            8 => channel.read_data(),
+            12 => channel.write_regs[12],
+            13 | 9 => channel.write_regs[13],
+            15 | 11 => channel.write_regs[15],
+            _ => 0,
+        }
+    }
+
+    fn write_control(&mut self, clock: Instant, addr: Address, data: u8) {
+        let frequency = self.frequency;
+        let channel = self.channel(addr);
+        let register = channel.pointer;
+        channel.pointer = 0;
+
+        match register {
+            0 => {
+                channel.pointer = data & wr0::REGISTER;
+                if (data & wr0::COMMAND) == wr0::CMD_POINT_HIGH {
+                    channel.pointer |= 0x08;
+                } else {
+                    channel.handle_command(data);
+                }
+            },
+            2 => self.vector = data,
+            8 => channel.send_byte(clock, frequency, data),
+            9 => {
+                match data & wr9::RESET {
+                    wr9::RESET_CHANNEL_B => self.port_b.reset(),
+                    wr9::RESET_CHANNEL_A => self.port_a.reset(),
+                    wr9::RESET => {
+                        self.port_a.reset();
+                        self.port_b.reset();
+                    },
+                    _ => {},
+                }
+                self.master_control = data & !wr9::RESET;
+            },
+            _ => channel.write_regs[register as usize] = data,
+        }
+    }
 }
 
 impl Steppable for Z8530 {
-    fn step(&mut self, _system: &System) -> Result<Duration, Error> {
-        Ok(Duration::from_secs(1))
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        self.port_a.update(system.clock, self.frequency)?;
+        self.port_b.update(system.clock, self.frequency)?;
+
+        self.check_interrupt_state(system)?;
+
+        // Step again when a character finishes sending, since input and register accesses will wake the device sooner
+        let mut next_ns = IDLE_INTERVAL_US * 1_000;
+        for channel in [&self.port_a, &self.port_b] {
+            if let Some(busy_until) = channel.tx_busy_until {
+                next_ns = next_ns.min(busy_until.duration_since(system.clock).as_nanos() as u64);
+            }
+            if channel.needs_polling() {
+                let ready_in_ns = if channel.rx_ready_at > system.clock {
+                    channel.rx_ready_at.duration_since(system.clock).as_nanos() as u64
+                } else {
+                    0
+                };
+                next_ns = next_ns.min(ready_in_ns.max(POLL_INTERVAL_US * 1_000));
+            }
+        }
+        Ok(Duration::from_nanos(next_ns.max(1)))
+    }
+
+    fn set_waker(&mut self, waker: Waker) {
+        self.port_a.set_waker(&waker);
+        self.port_b.set_waker(&waker);
+        self.waker = Some(waker);
+    }
+}
+
+impl Addressable for Z8530 {
+    fn size(&self) -> usize {
+        0x04
+    }
+
+    fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        data[0] = if (addr & ADDR_DATA) != 0 {
+            self.channel(addr).read_data()
+        } else {
+            self.read_control(addr)
+        };
+        self.wake();
+        log::debug!(target: DEV_NAME, "read from register {:x} of {:?}", addr, data);
+        Ok(())
+    }
+
+    fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        log::debug!(target: DEV_NAME, "write to register {:x} with {:x}", addr, data[0]);
+        if addr >= 0x04 {
+            strict::unhandled_write(DEV_NAME, addr, data);
+            return Ok(());
+        }
+
+        // Any write can change the interrupt or transmitter state, which is updated when the device is stepped
+        self.wake();
+        if (addr & ADDR_DATA) != 0 {
+            let frequency = self.frequency;
+            self.channel(addr).send_byte(clock, frequency, data[0]);
+        } else {
+            self.write_control(clock, addr, data[0]);
+        }
+        Ok(())
     }
 }
 
@@ -0,0 +1,4 @@
+pub mod peripherals;
+
+mod system;
+pub use crate::system::{AtariStOptions, build_atari_st};
@@ -0,0 +1,526 @@
+use std::fs;
+use femtos::{Instant, Duration};
+
+use moa_core::{Error, Address, Device, strict};
+
+
+const SECTOR_SIZE: usize = 512;
+
+/// The number of tracks that the drive can step to
+const MAX_TRACKS: u8 = 84;
+
+/// The time it takes for the disk to make one revolution at 300 RPM
+const REVOLUTION_NS: u64 = 200_000_000;
+/// The length of the index pulse, which is once per revolution
+const INDEX_PULSE_NS: u64 = 4_000_000;
+/// The motor turns off after 10 revolutions without a command
+const MOTOR_TIMEOUT_NS: u64 = 10 * REVOLUTION_NS;
+
+/// The DMA registers, which are words, where the address registers are only accessed through their low byte
+#[rustfmt::skip]
+mod reg {
+    use super::Address;
+    pub(super) const DATA: Address          = 0x04;
+    pub(super) const MODE_STATUS: Address   = 0x06;
+    pub(super) const ADDRESS_HIGH: Address  = 0x08;
+    pub(super) const ADDRESS_MID: Address   = 0x0A;
+    pub(super) const ADDRESS_LOW: Address   = 0x0C;
+}
+
+/// The bits of the DMA mode register
+#[rustfmt::skip]
+mod mode {
+    pub(super) const FDC_REGISTER: u16  = 0x0006;
+    pub(super) const HDC_SELECT: u16    = 0x0008;
+    pub(super) const SECTOR_COUNT: u16  = 0x0010;
+    pub(super) const WRITE: u16         = 0x0100;
+}
+
+/// The bits of the DMA status register
+#[rustfmt::skip]
+mod dma_status {
+    pub(super) const NO_ERROR: u16          = 0x0001;
+    pub(super) const SECTOR_COUNT: u16      = 0x0002;
+}
+
+/// The registers of the WD1772, selected by the DMA mode register
+#[rustfmt::skip]
+mod fdc_reg {
+    pub(super) const COMMAND_STATUS: u16    = 0x0;
+    pub(super) const TRACK: u16             = 0x2;
+    pub(super) const SECTOR: u16            = 0x4;
+}
+
+#[rustfmt::skip]
+mod status {
+    pub(super) const BUSY: u8               = 0x01;
+    pub(super) const INDEX: u8              = 0x02;
+    pub(super) const TRACK_ZERO: u8         = 0x04;
+    pub(super) const RECORD_NOT_FOUND: u8   = 0x10;
+    pub(super) const SPIN_UP: u8            = 0x20;
+    pub(super) const WRITE_PROTECT: u8      = 0x40;
+    pub(super) const MOTOR_ON: u8           = 0x80;
+}
+
+/// The bits of YM2149 port A that select the side and the drive, which are all active low
+const PORT_A_SIDE: u8 = 0x01;
+const PORT_A_DRIVE_A: u8 = 0x02;
+const PORT_A_DRIVE_B: u8 = 0x04;
+
+const DEV_NAME: &str = "floppy";
+
+/// A disk image in the .ST format, which is the raw sectors in order of track, side, and then sector
+pub struct StDisk {
+    sides: usize,
+    sectors_per_track: usize,
+    data: Vec<u8>,
+}
+
+impl StDisk {
+    pub fn load(filename: &str) -> Result<Self, Error> {
+        let data = fs::read(filename).map_err(|_| Error::new(format!("Error reading contents of {}", filename)))?;
+        Self::from_sectors(data)
+    }
+
+    /// Create a disk from the raw sectors, using the geometry in the boot sector if it's valid, or else guessing
+    /// it from the size of the image
+    pub fn from_sectors(data: Vec<u8>) -> Result<Self, Error> {
+        if data.is_empty() || data.len() % SECTOR_SIZE != 0 {
+            return Err(Error::new(format!("disk image must be a multiple of 512 bytes, but it's {} bytes", data.len())));
+        }
+
+        let total = data.len() / SECTOR_SIZE;
+        let boot_sectors = u16::from_le_bytes([data[0x18], data[0x19]]) as usize;
+        let boot_sides = u16::from_le_bytes([data[0x1A], data[0x1B]]) as usize;
+        let (sides, sectors_per_track) =
+            if (1..=2).contains(&boot_sides) && (9..=11).contains(&boot_sectors) && total % (boot_sides * boot_sectors) == 0 {
+                (boot_sides, boot_sectors)
+            } else {
+                [(2, 9), (1, 9), (2, 10), (1, 10), (2, 11), (1, 11)]
+                    .into_iter()
+                    .find(|(sides, sectors)| {
+                        let tracks = total / (sides * sectors);
+                        total % (sides * sectors) == 0 && (80..=84).contains(&tracks)
+                    })
+                    .ok_or_else(|| Error::new(format!("unable to determine the geometry of a disk with {} sectors", total)))?
+            };
+
+        Ok(Self {
+            sides,
+            sectors_per_track,
+            data,
+        })
+    }
+
+    fn tracks(&self) -> usize {
+        self.data.len() / SECTOR_SIZE / self.sides / self.sectors_per_track
+    }
+
+    /// Returns the offset of the given sector in the image, where sectors are numbered from 1
+    fn offset(&self, track: u8, side: usize, sector: u8) -> Option<usize> {
+        let sector = sector as usize;
+        if track as usize >= self.tracks() || side >= self.sides || sector == 0 || sector > self.sectors_per_track {
+            return None;
+        }
+        let index = ((track as usize * self.sides) + side) * self.sectors_per_track + (sector - 1);
+        Some(index * SECTOR_SIZE)
+    }
+}
+
+#[derive(Default)]
+struct Drive {
+    disk: Option<StDisk>,
+    track: u8,
+}
+
+/// The floppy controller of the Atari ST, which is a WD1772 that's accessed through the DMA chip, which
+/// transfers sectors directly to and from RAM
+///
+/// Sector writes are stored in the loaded image, but aren't saved back to the file
+pub struct FloppyController {
+    ram: Device,
+    drives: [Drive; 2],
+    port_a: u8,
+
+    dma_mode: u16,
+    dma_status: u16,
+    dma_address: u32,
+    sector_count: u16,
+
+    command: u8,
+    status: u8,
+    track: u8,
+    sector: u8,
+    data: u8,
+    step_inward: bool,
+    interrupt: bool,
+    motor_off_at: Instant,
+}
+
+impl FloppyController {
+    pub fn new(ram: Device) -> Self {
+        Self {
+            ram,
+            drives: Default::default(),
+            port_a: 0xFF,
+
+            dma_mode: 0,
+            dma_status: dma_status::NO_ERROR,
+            dma_address: 0,
+            sector_count: 0,
+
+            command: 0,
+            status: 0,
+            track: 0,
+            sector: 1,
+            data: 0,
+            step_inward: true,
+            interrupt: false,
+            motor_off_at: Instant::START,
+        }
+    }
+
+    pub fn insert_disk(&mut self, drive: usize, disk: StDisk) {
+        self.drives[drive].disk = Some(disk);
+    }
+
+    /// Set the output of YM2149 port A, which selects the drive and side
+    pub fn set_port_a(&mut self, value: u8) {
+        self.port_a = value;
+    }
+
+    /// Returns the state of the interrupt output, which is connected to GPIP 5 of the MFP
+    pub fn interrupt(&self) -> bool {
+        self.interrupt
+    }
+
+    fn selected_drive(&mut self) -> Option<&mut Drive> {
+        if (self.port_a & PORT_A_DRIVE_A) == 0 {
+            Some(&mut self.drives[0])
+        } else if (self.port_a & PORT_A_DRIVE_B) == 0 {
+            Some(&mut self.drives[1])
+        } else {
+            None
+        }
+    }
+
+    fn selected_side(&self) -> usize {
+        if (self.port_a & PORT_A_SIDE) == 0 { 1 } else { 0 }
+    }
+
+    pub fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) {
+        let value = match addr & !0x01 {
+            reg::DATA => {
+                if (self.dma_mode & mode::HDC_SELECT) != 0 {
+                    // There's no hard disk connected
+                    0x00FF
+                } else {
+                    self.read_fdc(clock) as u16
+                }
+            },
+            reg::MODE_STATUS => self.dma_status | if self.sector_count != 0 { dma_status::SECTOR_COUNT } else { 0 },
+            reg::ADDRESS_HIGH => (self.dma_address >> 16) as u16 & 0x00FF,
+            reg::ADDRESS_MID => (self.dma_address >> 8) as u16 & 0x00FF,
+            reg::ADDRESS_LOW => self.dma_address as u16 & 0x00FF,
+            _ => {
+                strict::unhandled_read(DEV_NAME, addr);
+                0xFFFF
+            },
+        };
+
+        if data.len() == 2 {
+            data.copy_from_slice(&value.to_be_bytes());
+        } else if (addr & 0x01) == 0 {
+            data[0] = (value >> 8) as u8;
+        } else {
+            data[0] = value as u8;
+        }
+        log::debug!(target: DEV_NAME, "read from register {:x} of {:?}", addr, data);
+    }
+
+    pub fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        log::debug!(target: DEV_NAME, "write to register {:x} with {:?}", addr, data);
+        let value = if data.len() == 2 {
+            u16::from_be_bytes([data[0], data[1]])
+        } else {
+            data[0] as u16
+        };
+
+        match addr & !0x01 {
+            reg::DATA => {
+                if (self.dma_mode & mode::SECTOR_COUNT) != 0 {
+                    self.sector_count = value;
+                } else if (self.dma_mode & mode::HDC_SELECT) == 0 {
+                    self.write_fdc(clock, value as u8)?;
+                }
+            },
+            reg::MODE_STATUS => {
+                // Changing the direction resets the DMA
+                if ((self.dma_mode ^ value) & mode::WRITE) != 0 {
+                    self.dma_status = dma_status::NO_ERROR;
+                    self.sector_count = 0;
+                }
+                self.dma_mode = value;
+            },
+            reg::ADDRESS_HIGH => self.dma_address = (self.dma_address & 0x00FFFF) | ((value as u32 & 0xFF) << 16),
+            reg::ADDRESS_MID => self.dma_address = (self.dma_address & 0xFF00FF) | ((value as u32 & 0xFF) << 8),
+            // The low bit of the address is always zero
+            reg::ADDRESS_LOW => self.dma_address = (self.dma_address & 0xFFFF00) | (value as u32 & 0xFE),
+            _ => strict::unhandled_write(DEV_NAME, addr, data),
+        }
+        Ok(())
+    }
+
+    fn read_fdc(&mut self, clock: Instant) -> u8 {
+        match self.dma_mode & mode::FDC_REGISTER {
+            fdc_reg::COMMAND_STATUS => {
+                // Reading the status clears the interrupt
+                self.interrupt = false;
+                self.current_status(clock)
+            },
+            fdc_reg::TRACK => self.track,
+            fdc_reg::SECTOR => self.sector,
+            // The data register
+            _ => self.data,
+        }
+    }
+
+    fn write_fdc(&mut self, clock: Instant, value: u8) -> Result<(), Error> {
+        match self.dma_mode & mode::FDC_REGISTER {
+            fdc_reg::COMMAND_STATUS => self.execute_command(clock, value)?,
+            fdc_reg::TRACK => self.track = value,
+            fdc_reg::SECTOR => self.sector = value,
+            // The data register
+            _ => self.data = value,
+        }
+        Ok(())
+    }
+
+    fn current_status(&mut self, clock: Instant) -> u8 {
+        let mut status = self.status;
+        if clock < self.motor_off_at {
+            status |= status::MOTOR_ON;
+        }
+
+        // The index pulse and track zero bits are only shown after a type I command
+        if (self.command & 0x80) == 0 {
+            status &= !(status::INDEX | status::TRACK_ZERO);
+            let has_disk = self.selected_drive().map(|drive| drive.disk.is_some()).unwrap_or(false);
+            if has_disk && (clock.as_duration().as_nanos() as u64 % REVOLUTION_NS) < INDEX_PULSE_NS {
+                status |= status::INDEX;
+            }
+            if self.selected_drive().map(|drive| drive.track == 0).unwrap_or(false) {
+                status |= status::TRACK_ZERO;
+            }
+        }
+        status
+    }
+
+    fn execute_command(&mut self, clock: Instant, command: u8) -> Result<(), Error> {
+        log::debug!(target: DEV_NAME, "executing command {:x}", command);
+
+        if (command & 0xF0) == 0xD0 {
+            // Force interrupt terminates the current command, and interrupts immediately if bit 3 is set
+            self.status &= !status::BUSY;
+            self.interrupt = (command & 0x08) != 0;
+            return Ok(());
+        }
+
+        self.command = command;
+        self.interrupt = false;
+        self.motor_off_at = clock + Duration::from_nanos(MOTOR_TIMEOUT_NS);
+
+        // Every command completes immediately, and then causes an interrupt
+        self.status = match command >> 4 {
+            0x0..=0x7 => self.execute_type_one(command),
+            0x8 | 0x9 => self.read_sectors(clock, (command & 0x10) != 0)?,
+            0xA | 0xB => self.write_sectors(clock, (command & 0x10) != 0)?,
+            0xC => self.read_address(clock)?,
+            0xE => {
+                strict::unhandled(DEV_NAME, "read track isn't supported".to_string());
+                status::RECORD_NOT_FOUND
+            },
+            _ => self.write_track(),
+        };
+        self.interrupt = true;
+        Ok(())
+    }
+
+    fn execute_type_one(&mut self, command: u8) -> u8 {
+        let update_track = (command & 0x10) != 0;
+        let steps: i16 = match command >> 4 {
+            // Restore steps out until track zero, and then clears the track register
+            0x0 => -(MAX_TRACKS as i16),
+            0x1 => self.data as i16 - self.track as i16,
+            0x2 | 0x3 => {
+                if self.step_inward {
+                    1
+                } else {
+                    -1
+                }
+            },
+            0x4 | 0x5 => {
+                self.step_inward = true;
+                1
+            },
+            _ => {
+                self.step_inward = false;
+                -1
+            },
+        };
+
+        if let Some(drive) = self.selected_drive() {
+            drive.track = (drive.track as i16 + steps).clamp(0, MAX_TRACKS as i16 - 1) as u8;
+        }
+
+        match command >> 4 {
+            0x0 => self.track = 0,
+            0x1 => self.track = self.data,
+            _ if update_track => self.track = (self.track as i16 + steps).clamp(0, 0xFF) as u8,
+            _ => {},
+        }
+
+        let mut status = status::SPIN_UP;
+        let (has_disk, track) = self
+            .selected_drive()
+            .map(|drive| (drive.disk.is_some(), drive.track))
+            .unwrap_or((false, 0));
+        // Verify checks that the track register matches the track under the head
+        if (command & 0x04) != 0 && (!has_disk || track != self.track) {
+            status |= status::RECORD_NOT_FOUND;
+        }
+        status
+    }
+
+    /// Returns the offset of the given sector on the current track of the selected drive, if it exists
+    fn sector_offset(&mut self, sector: u8) -> Option<usize> {
+        let side = self.selected_side();
+        let track = self.track;
+        let drive = self.selected_drive()?;
+        // The track register must match the track under the head for the sector to be found
+        if drive.track != track {
+            return None;
+        }
+        drive.disk.as_ref()?.offset(track, side, sector)
+    }
+
+    fn read_sectors(&mut self, clock: Instant, multiple: bool) -> Result<u8, Error> {
+        loop {
+            let offset = match self.sector_offset(self.sector) {
+                Some(offset) => offset,
+                None => return Ok(status::RECORD_NOT_FOUND),
+            };
+
+            if self.sector_count > 0 {
+                let drive = self.selected_drive().unwrap();
+                let buffer = drive.disk.as_ref().unwrap().data[offset..offset + SECTOR_SIZE].to_vec();
+                self.transfer_to_ram(clock, &buffer)?;
+            }
+
+            if !multiple {
+                return Ok(0);
+            }
+            self.sector = self.sector.wrapping_add(1);
+            if self.sector_count == 0 {
+                return Ok(0);
+            }
+        }
+    }
+
+    fn write_sectors(&mut self, clock: Instant, multiple: bool) -> Result<u8, Error> {
+        loop {
+            let offset = match self.sector_offset(self.sector) {
+                Some(offset) => offset,
+                None => return Ok(status::RECORD_NOT_FOUND),
+            };
+
+            if self.sector_count > 0 {
+                let buffer = self.transfer_from_ram(clock)?;
+                let drive = self.selected_drive().unwrap();
+                drive.disk.as_mut().unwrap().data[offset..offset + SECTOR_SIZE].copy_from_slice(&buffer);
+            }
+
+            if !multiple {
+                return Ok(0);
+            }
+            self.sector = self.sector.wrapping_add(1);
+            if self.sector_count == 0 {
+                return Ok(0);
+            }
+        }
+    }
+
+    fn read_address(&mut self, clock: Instant) -> Result<u8, Error> {
+        let side = self.selected_side() as u8;
+        let track = match self.selected_drive() {
+            Some(drive) if drive.disk.is_some() => drive.track,
+            _ => return Ok(status::RECORD_NOT_FOUND),
+        };
+
+        // The ID field is the track, side, sector, and size code, followed by the CRC, which isn't calculated.
+        // The track is also copied into the sector register
+        self.sector = track;
+        if self.sector_count > 0 {
+            self.write_ram(clock, &[track, side, 1, 2, 0, 0])?;
+        }
+        Ok(0)
+    }
+
+    fn write_track(&mut self) -> u8 {
+        // Formatting a track fills its sectors with the default value, rather than decoding the raw track data
+        let side = self.selected_side();
+        let track = self.track;
+        let write_protected = match self.selected_drive() {
+            Some(Drive {
+                disk: Some(disk),
+                ..
+            }) => {
+                for sector in 1..=disk.sectors_per_track as u8 {
+                    if let Some(offset) = disk.offset(track, side, sector) {
+                        disk.data[offset..offset + SECTOR_SIZE].fill(0xE5);
+                    }
+                }
+                false
+            },
+            _ => true,
+        };
+
+        if write_protected { status::WRITE_PROTECT } else { 0 }
+    }
+
+    fn transfer_to_ram(&mut self, clock: Instant, buffer: &[u8]) -> Result<(), Error> {
+        if (self.dma_mode & mode::WRITE) != 0 {
+            self.dma_status &= !dma_status::NO_ERROR;
+            return Ok(());
+        }
+        self.write_ram(clock, buffer)?;
+        self.sector_count -= 1;
+        Ok(())
+    }
+
+    fn transfer_from_ram(&mut self, clock: Instant) -> Result<Vec<u8>, Error> {
+        let mut buffer = vec![0; SECTOR_SIZE];
+        if (self.dma_mode & mode::WRITE) == 0 {
+            self.dma_status &= !dma_status::NO_ERROR;
+            return Ok(buffer);
+        }
+        self.ram
+            .borrow_mut()
+            .as_addressable()
+            .ok_or_else(|| Error::new(format!("{}: ram is not addressable", DEV_NAME)))?
+            .read(clock, self.dma_address as Address, &mut buffer)?;
+        self.dma_address += SECTOR_SIZE as u32;
+        self.sector_count -= 1;
+        Ok(buffer)
+    }
+
+    fn write_ram(&mut self, clock: Instant, buffer: &[u8]) -> Result<(), Error> {
+        self.ram
+            .borrow_mut()
+            .as_addressable()
+            .ok_or_else(|| Error::new(format!("{}: ram is not addressable", DEV_NAME)))?
+            .write(clock, self.dma_address as Address, buffer)?;
+        self.dma_address += buffer.len() as u32;
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+use femtos::{Instant, Duration};
+
+use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable, Capabilities, Device, strict};
+use moa_signals::Signal;
+use moa_peripherals_motorola::MC68901;
+
+use crate::peripherals::floppy::{FloppyController, StDisk};
+use crate::peripherals::ikbd::Ikbd;
+use crate::peripherals::shifter::Shifter;
+
+
+/// The start of the I/O area, which the offsets of the devices are relative to
+pub const IO_BASE: Address = 0xFF8000;
+const IO_SIZE: usize = 0x8000;
+
+#[rustfmt::skip]
+mod io {
+    use super::Address;
+    pub(super) const MEMORY_CONFIG: Address = 0x0001;
+    pub(super) const SHIFTER: Address       = 0x0200;
+    pub(super) const SHIFTER_END: Address   = 0x0261;
+    pub(super) const FLOPPY: Address        = 0x0600;
+    pub(super) const FLOPPY_END: Address    = 0x060F;
+    pub(super) const PSG: Address           = 0x0800;
+    pub(super) const PSG_END: Address       = 0x08FF;
+    pub(super) const MFP: Address           = 0x7A00;
+    pub(super) const MFP_END: Address       = 0x7A2F;
+    pub(super) const KEYBOARD: Address      = 0x7C00;
+    pub(super) const KEYBOARD_END: Address  = 0x7C03;
+    pub(super) const MIDI: Address          = 0x7C04;
+    pub(super) const MIDI_END: Address      = 0x7C07;
+}
+
+/// The MFP pins that the other devices are connected to
+const GPIP_KEYBOARD_MIDI: u8 = 4;
+const GPIP_FLOPPY: u8 = 5;
+const GPIP_MONOCHROME: u8 = 7;
+
+/// The status of the MIDI ACIA, which has nothing connected, so it's always ready to transmit
+const MIDI_STATUS: u8 = 0x02;
+
+const DEV_NAME: &str = "glue";
+
+/// The I/O area of the Atari ST, which the GLUE chip decodes into the devices that are accessed by the cpu,
+/// and which connects the interrupt outputs of the floppy controller and keyboard to the MFP
+pub struct Glue {
+    memory_config: u8,
+    shifter: Shifter,
+    floppy: FloppyController,
+    psg: Device,
+    psg_port_a: Signal<u8>,
+    mfp: MC68901,
+    ikbd: Ikbd,
+}
+
+impl Glue {
+    pub fn new(
+        shifter: Shifter,
+        floppy: FloppyController,
+        psg: Device,
+        psg_port_a: Signal<u8>,
+        ikbd: Ikbd,
+        monochrome: bool,
+    ) -> Self {
+        let mut mfp = MC68901::default();
+        // The monitor detect pin is low when a monochrome monitor is connected
+        mfp.set_gpio_input(GPIP_MONOCHROME, !monochrome);
+
+        Self {
+            memory_config: 0,
+            shifter,
+            floppy,
+            psg,
+            psg_port_a,
+            mfp,
+            ikbd,
+        }
+    }
+
+    pub fn insert_disk(&mut self, drive: usize, disk: StDisk) {
+        self.floppy.insert_disk(drive, disk);
+    }
+
+    /// Update the interrupt inputs of the MFP, which are active low
+    fn update_gpio(&mut self) {
+        self.mfp.set_gpio_input(GPIP_KEYBOARD_MIDI, !self.ikbd.interrupt());
+        self.mfp.set_gpio_input(GPIP_FLOPPY, !self.floppy.interrupt());
+    }
+}
+
+impl Addressable for Glue {
+    fn size(&self) -> usize {
+        IO_SIZE
+    }
+
+    fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        // The MFP is connected to the low byte of the data bus, and the ACIAs to the high byte
+        let i = data.len() - 1;
+        match addr {
+            io::MEMORY_CONFIG => data[0] = self.memory_config,
+            io::SHIFTER..=io::SHIFTER_END => self.shifter.read(clock, addr - io::SHIFTER, data),
+            io::FLOPPY..=io::FLOPPY_END => {
+                self.floppy.set_port_a(self.psg_port_a.get());
+                self.floppy.read(clock, addr - io::FLOPPY, data);
+                self.update_gpio();
+            },
+            io::PSG..=io::PSG_END => {
+                // The registers are repeated every 4 bytes
+                self.psg
+                    .borrow_mut()
+                    .as_addressable()
+                    .ok_or_else(|| Error::new(format!("{}: psg is not addressable", DEV_NAME)))?
+                    .read(clock, addr & 0x03, data)?;
+            },
+            io::MFP..=io::MFP_END => {
+                data.fill(0xFF);
+                self.mfp.read(clock, (addr - io::MFP) | 0x01, &mut data[i..])?;
+            },
+            io::KEYBOARD..=io::KEYBOARD_END => {
+                data.fill(0xFF);
+                self.ikbd.read(clock, (addr - io::KEYBOARD) & !0x01, &mut data[..1]);
+                self.update_gpio();
+            },
+            io::MIDI..=io::MIDI_END => {
+                data.fill(0xFF);
+                if ((addr - io::MIDI) & !0x01) == 0 {
+                    data[0] = MIDI_STATUS;
+                }
+            },
+            _ => {
+                // TODO the GLUE causes a bus error for addresses that don't have a device
+                strict::unhandled_read(DEV_NAME, IO_BASE + addr);
+                data.fill(0xFF);
+            },
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        let i = data.len() - 1;
+        match addr {
+            io::MEMORY_CONFIG => {
+                log::debug!(target: DEV_NAME, "memory configuration set to {:x}", data[0]);
+                self.memory_config = data[0];
+            },
+            io::SHIFTER..=io::SHIFTER_END => self.shifter.write(clock, addr - io::SHIFTER, data),
+            io::FLOPPY..=io::FLOPPY_END => {
+                self.floppy.set_port_a(self.psg_port_a.get());
+                self.floppy.write(clock, addr - io::FLOPPY, data)?;
+                self.update_gpio();
+            },
+            io::PSG..=io::PSG_END => {
+                self.psg
+                    .borrow_mut()
+                    .as_addressable()
+                    .ok_or_else(|| Error::new(format!("{}: psg is not addressable", DEV_NAME)))?
+                    .write(clock, addr & 0x03, data)?;
+            },
+            io::MFP..=io::MFP_END => self.mfp.write(clock, (addr - io::MFP) | 0x01, &data[i..])?,
+            io::KEYBOARD..=io::KEYBOARD_END => {
+                self.ikbd.write(clock, (addr - io::KEYBOARD) & !0x01, &data[..1]);
+                self.update_gpio();
+            },
+            io::MIDI..=io::MIDI_END => log::debug!(target: DEV_NAME, "ignoring midi write of {:?}", data),
+            _ => strict::unhandled_write(DEV_NAME, IO_BASE + addr, data),
+        }
+        Ok(())
+    }
+}
+
+impl Steppable for Glue {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        let keyboard_next = self.ikbd.step(system.clock);
+        self.update_gpio();
+        let video_next = self.shifter.step(system, &mut self.mfp)?;
+        let mfp_next = self.mfp.step(system)?;
+        Ok(keyboard_next.min(video_next).min(mfp_next))
+    }
+}
+
+impl Transmutable for Glue {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities::probe(self) | Capabilities::VIDEO
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::VecDeque;
+use femtos::{Instant, Duration};
+
+use moa_core::{Address, strict};
+use moa_host::{self, Host, HostError, Key, KeyEvent, MouseEvent, MouseEventType, MouseButton, EventReceiver};
+
+
+/// The time to send one byte from the keyboard at 7812.5 baud, with a start and stop bit
+const BYTE_PERIOD_NS: u64 = 1_280_000;
+
+#[rustfmt::skip]
+mod reg {
+    use super::Address;
+    pub(super) const CONTROL_STATUS: Address    = 0x00;
+    pub(super) const DATA: Address              = 0x02;
+}
+
+#[rustfmt::skip]
+mod status {
+    pub(super) const RX_FULL: u8    = 0x01;
+    pub(super) const TX_EMPTY: u8   = 0x02;
+    pub(super) const IRQ: u8        = 0x80;
+}
+
+const CONTROL_MASTER_RESET: u8 = 0x03;
+const CONTROL_RX_INTERRUPT: u8 = 0x80;
+
+#[rustfmt::skip]
+mod command {
+    pub(super) const RESET: u8                  = 0x80;
+    pub(super) const SET_RELATIVE_MOUSE: u8     = 0x08;
+    pub(super) const DISABLE_MOUSE: u8          = 0x12;
+    pub(super) const RESUME: u8                 = 0x11;
+    pub(super) const PAUSE: u8                  = 0x13;
+    pub(super) const INTERROGATE_TIME: u8       = 0x1C;
+    pub(super) const SET_TIME: u8               = 0x1B;
+}
+
+const RESET_RESPONSE: u8 = 0xF1;
+const TIME_HEADER: u8 = 0xFC;
+const RELATIVE_MOUSE_HEADER: u8 = 0xF8;
+
+const DEV_NAME: &str = "ikbd";
+
+/// Returns the number of parameter bytes that follow each keyboard command
+fn parameter_count(command: u8) -> usize {
+    match command {
+        0x07 | 0x17 | 0x80 => 1,
+        0x0A | 0x0B | 0x0C | 0x21 | 0x22 => 2,
+        0x20 => 3,
+        0x09 => 4,
+        0x0E => 5,
+        0x19 | 0x1B => 6,
+        _ => 0,
+    }
+}
+
+/// The intelligent keyboard controller of the Atari ST, which sends key and mouse events through an MC6850
+/// ACIA, and accepts commands to configure the mouse and read the clock
+///
+/// The joystick commands are accepted, but no joystick events are sent
+pub struct Ikbd {
+    key_receiver: EventReceiver<KeyEvent>,
+    mouse_receiver: EventReceiver<MouseEvent>,
+
+    control: u8,
+    status: u8,
+    rx_data: u8,
+    next_rx: Instant,
+    queue: VecDeque<u8>,
+
+    command: Vec<u8>,
+    paused: bool,
+    mouse_enabled: bool,
+    mouse_buttons: u8,
+    mouse_position: Option<(u32, u32)>,
+    /// The time of day in BCD, as year, month, day, hour, minute, and second
+    time: [u8; 6],
+}
+
+impl Ikbd {
+    pub fn new<H, E>(host: &mut H) -> Result<Self, HostError<E>>
+    where
+        H: Host<Error = E>,
+    {
+        let (key_sender, key_receiver) = moa_host::event_queue();
+        host.register_keyboard(key_sender)?;
+        let (mouse_sender, mouse_receiver) = moa_host::event_queue();
+        host.register_mouse(mouse_sender)?;
+
+        Ok(Self {
+            key_receiver,
+            mouse_receiver,
+
+            control: 0,
+            status: status::TX_EMPTY,
+            rx_data: 0,
+            next_rx: Instant::START,
+            queue: VecDeque::new(),
+
+            command: vec![],
+            paused: false,
+            mouse_enabled: true,
+            mouse_buttons: 0,
+            mouse_position: None,
+            time: [0; 6],
+        })
+    }
+
+    /// Returns the state of the interrupt output of the ACIA, which is connected to GPIP 4 of the MFP
+    pub fn interrupt(&self) -> bool {
+        (self.status & status::IRQ) != 0
+    }
+
+    pub fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) {
+        data[0] = match addr {
+            reg::CONTROL_STATUS => self.status,
+            reg::DATA => {
+                self.status &= !(status::RX_FULL | status::IRQ);
+                self.rx_data
+            },
+            _ => {
+                strict::unhandled_read(DEV_NAME, addr);
+                0xFF
+            },
+        };
+        log::debug!(target: DEV_NAME, "read from register {:x} of {:x}", addr, data[0]);
+    }
+
+    pub fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) {
+        log::debug!(target: DEV_NAME, "write to register {:x} with {:x}", addr, data[0]);
+        match addr {
+            reg::CONTROL_STATUS => {
+                if (data[0] & CONTROL_MASTER_RESET) == CONTROL_MASTER_RESET {
+                    self.status = status::TX_EMPTY;
+                } else {
+                    self.control = data[0];
+                }
+            },
+            reg::DATA => self.receive_command_byte(data[0]),
+            _ => strict::unhandled_write(DEV_NAME, addr, data),
+        }
+    }
+
+    /// Process the input events and send the next byte to the cpu, returning the time until the next byte
+    pub fn step(&mut self, clock: Instant) -> Duration {
+        while let Some(event) = self.key_receiver.receive() {
+            if let Some(scancode) = key_to_scancode(event.key) {
+                self.queue.push_back(if event.state { scancode } else { scancode | 0x80 });
+            }
+        }
+        while let Some(event) = self.mouse_receiver.receive() {
+            self.update_mouse(event);
+        }
+
+        if self.paused || self.queue.is_empty() || clock < self.next_rx {
+            return Duration::from_nanos(BYTE_PERIOD_NS);
+        }
+
+        // A byte that isn't read before the next one arrives is overwritten
+        self.rx_data = self.queue.pop_front().unwrap();
+        self.status |= status::RX_FULL;
+        if (self.control & CONTROL_RX_INTERRUPT) != 0 {
+            self.status |= status::IRQ;
+        }
+        self.next_rx = clock + Duration::from_nanos(BYTE_PERIOD_NS);
+        Duration::from_nanos(BYTE_PERIOD_NS)
+    }
+
+    fn receive_command_byte(&mut self, byte: u8) {
+        self.command.push(byte);
+        if self.command.len() <= parameter_count(self.command[0]) {
+            return;
+        }
+
+        let command = std::mem::take(&mut self.command);
+        log::debug!(target: DEV_NAME, "received command {:x?}", command);
+        // Any command resumes sending after a pause
+        self.paused = false;
+        match command[0] {
+            command::RESET if command[1] == 0x01 => {
+                self.queue.clear();
+                self.mouse_enabled = true;
+                self.queue.push_back(RESET_RESPONSE);
+            },
+            command::SET_RELATIVE_MOUSE => self.mouse_enabled = true,
+            command::DISABLE_MOUSE => self.mouse_enabled = false,
+            command::PAUSE => self.paused = true,
+            command::RESUME => {},
+            command::SET_TIME => {
+                // A byte that isn't valid BCD leaves that field unchanged
+                for (field, value) in self.time.iter_mut().zip(&command[1..]) {
+                    if (value & 0x0F) <= 9 && (value >> 4) <= 9 {
+                        *field = *value;
+                    }
+                }
+            },
+            command::INTERROGATE_TIME => {
+                self.queue.push_back(TIME_HEADER);
+                self.queue.extend(self.time);
+            },
+            _ => log::debug!(target: DEV_NAME, "ignoring command {:x?}", command),
+        }
+    }
+
+    fn update_mouse(&mut self, event: MouseEvent) {
+        let button = |button| match button {
+            MouseButton::Left => 0x02,
+            MouseButton::Right => 0x01,
+            MouseButton::Middle => 0x00,
+        };
+        match event.etype {
+            MouseEventType::Down(which) => self.mouse_buttons |= button(which),
+            MouseEventType::Up(which) => self.mouse_buttons &= !button(which),
+            MouseEventType::Move => {},
+        }
+
+        let (dx, dy) = match self.mouse_position.replace(event.pos) {
+            Some((x, y)) => (event.pos.0 as i32 - x as i32, event.pos.1 as i32 - y as i32),
+            None => (0, 0),
+        };
+        if !self.mouse_enabled {
+            return;
+        }
+
+        // Large movements are split into multiple packets, since each delta is a signed byte
+        let (mut dx, mut dy) = (dx, dy);
+        loop {
+            let step_x = dx.clamp(-128, 127);
+            let step_y = dy.clamp(-128, 127);
+            self.queue
+                .extend([RELATIVE_MOUSE_HEADER | self.mouse_buttons, step_x as u8, step_y as u8]);
+            dx -= step_x;
+            dy -= step_y;
+            if dx == 0 && dy == 0 {
+                break;
+            }
+        }
+    }
+}
+
+fn key_to_scancode(key: Key) -> Option<u8> {
+    let scancode = match key {
+        Key::Escape => 0x01,
+        Key::Num1 => 0x02,
+        Key::Num2 => 0x03,
+        Key::Num3 => 0x04,
+        Key::Num4 => 0x05,
+        Key::Num5 => 0x06,
+        Key::Num6 => 0x07,
+        Key::Num7 => 0x08,
+        Key::Num8 => 0x09,
+        Key::Num9 => 0x0A,
+        Key::Num0 => 0x0B,
+        Key::Minus => 0x0C,
+        Key::Equals => 0x0D,
+        Key::Backspace => 0x0E,
+        Key::Tab => 0x0F,
+        Key::Q => 0x10,
+        Key::W => 0x11,
+        Key::E => 0x12,
+        Key::R => 0x13,
+        Key::T => 0x14,
+        Key::Y => 0x15,
+        Key::U => 0x16,
+        Key::I => 0x17,
+        Key::O => 0x18,
+        Key::P => 0x19,
+        Key::LeftBracket => 0x1A,
+        Key::RightBracket => 0x1B,
+        Key::Enter => 0x1C,
+        Key::LeftCtrl | Key::RightCtrl => 0x1D,
+        Key::A => 0x1E,
+        Key::S => 0x1F,
+        Key::D => 0x20,
+        Key::F => 0x21,
+        Key::G => 0x22,
+        Key::H => 0x23,
+        Key::J => 0x24,
+        Key::K => 0x25,
+        Key::L => 0x26,
+        Key::Semicolon => 0x27,
+        Key::Apostrophe => 0x28,
+        Key::Backquote => 0x29,
+        Key::LeftShift => 0x2A,
+        Key::Backslash => 0x2B,
+        Key::Z => 0x2C,
+        Key::X => 0x2D,
+        Key::C => 0x2E,
+        Key::V => 0x2F,
+        Key::B => 0x30,
+        Key::N => 0x31,
+        Key::M => 0x32,
+        Key::Comma => 0x33,
+        Key::Period => 0x34,
+        Key::Slash => 0x35,
+        Key::RightShift => 0x36,
+        Key::LeftAlt | Key::RightAlt => 0x38,
+        Key::Space => 0x39,
+        Key::CapsLock => 0x3A,
+        Key::F1 => 0x3B,
+        Key::F2 => 0x3C,
+        Key::F3 => 0x3D,
+        Key::F4 => 0x3E,
+        Key::F5 => 0x3F,
+        Key::F6 => 0x40,
+        Key::F7 => 0x41,
+        Key::F8 => 0x42,
+        Key::F9 => 0x43,
+        Key::F10 => 0x44,
+        Key::Home => 0x47,
+        Key::Up => 0x48,
+        Key::NumPadMinus => 0x4A,
+        Key::Left => 0x4B,
+        Key::Right => 0x4D,
+        Key::NumPadPlus => 0x4E,
+        Key::Down => 0x50,
+        Key::Insert => 0x52,
+        Key::Delete => 0x53,
+        // The Undo and Help keys don't have an equivalent on most keyboards
+        Key::F12 => 0x61,
+        Key::F11 => 0x62,
+        Key::NumPadSlash => 0x65,
+        Key::NumPadAsterisk => 0x66,
+        Key::NumPad7 => 0x67,
+        Key::NumPad8 => 0x68,
+        Key::NumPad9 => 0x69,
+        Key::NumPad4 => 0x6A,
+        Key::NumPad5 => 0x6B,
+        Key::NumPad6 => 0x6C,
+        Key::NumPad1 => 0x6D,
+        Key::NumPad2 => 0x6E,
+        Key::NumPad3 => 0x6F,
+        Key::NumPad0 => 0x70,
+        Key::NumPadDot => 0x71,
+        Key::NumPadEnter => 0x72,
+        _ => return None,
+    };
+    Some(scancode)
+}
@@ -0,0 +1,4 @@
+pub mod floppy;
+pub mod glue;
+pub mod ikbd;
+pub mod shifter;
@@ -0,0 +1,285 @@
+use femtos::{Instant, Duration};
+
+use moa_core::{System, Error, Address, Device, strict};
+use moa_host::{self, Host, HostError, Frame, FrameSender, Pixel};
+use moa_peripherals_motorola::MC68901;
+
+
+/// The size of the output frames, which fits the high resolution mode, with the pixels of the lower
+/// resolutions doubled to fill it
+pub const SCREEN_SIZE: (u32, u32) = (640, 400);
+
+/// The size of the screen memory in every resolution
+const SCREEN_BYTES: usize = 32000;
+
+/// The vertical blank interrupt is level 4 on the cpu, which uses the autovector
+const VBL_INTERRUPT_LEVEL: u8 = 4;
+const VBL_INTERRUPT_VECTOR: u8 = 28;
+
+#[rustfmt::skip]
+mod reg {
+    use super::Address;
+    pub(super) const BASE_HIGH: Address     = 0x01;
+    pub(super) const BASE_MID: Address      = 0x03;
+    pub(super) const COUNTER_HIGH: Address  = 0x05;
+    pub(super) const COUNTER_MID: Address   = 0x07;
+    pub(super) const COUNTER_LOW: Address   = 0x09;
+    pub(super) const SYNC_MODE: Address     = 0x0A;
+    pub(super) const PALETTE: Address       = 0x40;
+    pub(super) const PALETTE_END: Address   = 0x5F;
+    pub(super) const RESOLUTION: Address    = 0x60;
+}
+
+/// The sync mode bit that selects 50Hz instead of 60Hz
+const SYNC_50HZ: u8 = 0x02;
+
+const RESOLUTION_LOW: u8 = 0;
+const RESOLUTION_MEDIUM: u8 = 1;
+const RESOLUTION_HIGH: u8 = 2;
+
+const DEV_NAME: &str = "shifter";
+
+/// The timing of the lines of each video mode
+struct Timing {
+    lines_per_frame: u32,
+    first_visible_line: u32,
+    visible_lines: u32,
+    line_duration: Duration,
+}
+
+/// The Atari ST Shifter, which reads the screen from RAM and converts it to pixels in one of three
+/// resolutions, along with the video timing from the GLUE chip, which causes the vertical blank interrupt
+/// and signals the end of each visible line to timer B of the MFP
+pub struct Shifter {
+    frame_sender: FrameSender,
+    ram: Device,
+    monochrome: bool,
+
+    base: u32,
+    sync_mode: u8,
+    palette: [u16; 16],
+    resolution: u8,
+
+    line: u32,
+    next_line: Instant,
+}
+
+impl Shifter {
+    pub fn new<H, E>(host: &mut H, ram: Device, monochrome: bool) -> Result<Self, HostError<E>>
+    where
+        H: Host<Error = E>,
+    {
+        let (frame_sender, frame_receiver) = moa_host::frame_queue(SCREEN_SIZE.0, SCREEN_SIZE.1);
+        host.add_video_source(frame_receiver)?;
+
+        Ok(Self {
+            frame_sender,
+            ram,
+            monochrome,
+
+            base: 0,
+            sync_mode: 0,
+            palette: [0; 16],
+            resolution: if monochrome { RESOLUTION_HIGH } else { RESOLUTION_LOW },
+
+            line: 0,
+            next_line: Instant::START,
+        })
+    }
+
+    fn timing(&self) -> Timing {
+        if self.monochrome {
+            Timing {
+                lines_per_frame: 501,
+                first_visible_line: 34,
+                visible_lines: 400,
+                line_duration: Duration::from_nanos(28_000),
+            }
+        } else if (self.sync_mode & SYNC_50HZ) != 0 {
+            Timing {
+                lines_per_frame: 313,
+                first_visible_line: 63,
+                visible_lines: 200,
+                line_duration: Duration::from_nanos(64_000),
+            }
+        } else {
+            Timing {
+                lines_per_frame: 263,
+                first_visible_line: 34,
+                visible_lines: 200,
+                line_duration: Duration::from_nanos(63_500),
+            }
+        }
+    }
+
+    /// Returns the address that the shifter is currently reading, which is the base address outside of the
+    /// visible lines
+    fn video_counter(&self) -> u32 {
+        let timing = self.timing();
+        let bytes_per_line = (SCREEN_BYTES as u32) / timing.visible_lines;
+        let line = self.line.saturating_sub(timing.first_visible_line).min(timing.visible_lines);
+        if line == 0 || line == timing.visible_lines {
+            self.base
+        } else {
+            self.base + line * bytes_per_line
+        }
+    }
+
+    pub fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) {
+        for (i, byte) in data.iter_mut().enumerate() {
+            let addr = addr + i as Address;
+            *byte = match addr {
+                reg::BASE_HIGH => (self.base >> 16) as u8,
+                reg::BASE_MID => (self.base >> 8) as u8,
+                reg::COUNTER_HIGH => (self.video_counter() >> 16) as u8,
+                reg::COUNTER_MID => (self.video_counter() >> 8) as u8,
+                reg::COUNTER_LOW => self.video_counter() as u8,
+                reg::SYNC_MODE => self.sync_mode,
+                reg::PALETTE..=reg::PALETTE_END => {
+                    let colour = self.palette[((addr - reg::PALETTE) / 2) as usize];
+                    if (addr & 0x01) == 0 {
+                        (colour >> 8) as u8
+                    } else {
+                        colour as u8
+                    }
+                },
+                reg::RESOLUTION => self.resolution,
+                // The unused bytes of the word registers read as all ones
+                _ if addr < reg::RESOLUTION && (addr & 0x01) == 0 => 0xFF,
+                _ => {
+                    strict::unhandled_read(DEV_NAME, addr);
+                    0xFF
+                },
+            };
+        }
+        log::debug!(target: DEV_NAME, "read from register {:x} of {:?}", addr, data);
+    }
+
+    pub fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) {
+        log::debug!(target: DEV_NAME, "write to register {:x} with {:?}", addr, data);
+        for (i, byte) in data.iter().enumerate() {
+            let addr = addr + i as Address;
+            match addr {
+                reg::BASE_HIGH => self.base = (self.base & 0x00FF00) | ((*byte as u32) << 16),
+                reg::BASE_MID => self.base = (self.base & 0xFF0000) | ((*byte as u32) << 8),
+                reg::SYNC_MODE => self.sync_mode = *byte & 0x03,
+                reg::PALETTE..=reg::PALETTE_END => {
+                    let colour = &mut self.palette[((addr - reg::PALETTE) / 2) as usize];
+                    if (addr & 0x01) == 0 {
+                        *colour = (*colour & 0x00FF) | (((*byte & 0x07) as u16) << 8);
+                    } else {
+                        *colour = (*colour & 0xFF00) | (*byte & 0x77) as u16;
+                    }
+                },
+                // A monochrome monitor can only display the high resolution
+                reg::RESOLUTION if !self.monochrome => self.resolution = *byte & 0x03,
+                reg::RESOLUTION => {},
+                _ if addr < reg::RESOLUTION && (addr & 0x01) == 0 => {},
+                _ => strict::unhandled_write(DEV_NAME, addr, &[*byte]),
+            }
+        }
+    }
+
+    /// Advance the video timing to the given clock, causing the vertical blank interrupt at the start of each
+    /// frame, and the end of each visible line on the timer B input of the MFP
+    pub fn step(&mut self, system: &System, mfp: &mut MC68901) -> Result<Duration, Error> {
+        while self.next_line <= system.clock {
+            let timing = self.timing();
+            let last_visible_line = timing.first_visible_line + timing.visible_lines;
+
+            if self.line == 0 {
+                system
+                    .get_interrupt_controller()
+                    .set(true, VBL_INTERRUPT_LEVEL, VBL_INTERRUPT_VECTOR)?;
+            }
+            if (timing.first_visible_line..last_visible_line).contains(&self.line) {
+                // The display enable signal is high during the line, and the falling edge at the end of it
+                // is counted by timer B
+                mfp.set_timer_b_input(self.next_line, true);
+                mfp.set_timer_b_input(self.next_line, false);
+            }
+            if self.line == last_visible_line {
+                self.render(system.clock)?;
+            }
+
+            self.line = (self.line + 1) % timing.lines_per_frame;
+            self.next_line += timing.line_duration;
+        }
+        Ok(self.next_line.duration_since(system.clock))
+    }
+
+    fn render(&mut self, clock: Instant) -> Result<(), Error> {
+        let mut memory = vec![0; SCREEN_BYTES];
+        self.ram
+            .borrow_mut()
+            .as_addressable()
+            .ok_or_else(|| Error::new(format!("{}: ram is not addressable", DEV_NAME)))?
+            .read(clock, self.base as Address, &mut memory)?;
+
+        let mut frame = Frame::new(SCREEN_SIZE.0, SCREEN_SIZE.1, self.frame_sender.encoding());
+        match self.resolution {
+            RESOLUTION_LOW => self.render_planes(&mut frame, &memory, 4, 320, 200),
+            RESOLUTION_MEDIUM => self.render_planes(&mut frame, &memory, 2, 640, 200),
+            _ => self.render_monochrome(&mut frame, &memory),
+        }
+        self.frame_sender.add(clock, frame);
+        Ok(())
+    }
+
+    /// Render a colour resolution, where each group of 16 pixels is stored as one word per bit plane
+    fn render_planes(&self, frame: &mut Frame, memory: &[u8], planes: usize, width: u32, height: u32) {
+        let scale_x = SCREEN_SIZE.0 / width;
+        let scale_y = SCREEN_SIZE.1 / height;
+        let colours: Vec<Pixel> = self.palette.iter().map(|colour| colour_to_pixel(*colour)).collect();
+
+        for y in 0..height {
+            for group in 0..(width / 16) {
+                let offset = ((y * width / 16 + group) as usize) * planes * 2;
+                let words: Vec<u16> = (0..planes)
+                    .map(|plane| u16::from_be_bytes([memory[offset + plane * 2], memory[offset + plane * 2 + 1]]))
+                    .collect();
+
+                for bit in 0..16 {
+                    let index = words
+                        .iter()
+                        .enumerate()
+                        .fold(0, |index, (plane, word)| index | (((*word >> (15 - bit)) & 0x01) << plane));
+                    let pixel = colours[index as usize];
+                    let x = (group * 16 + bit) * scale_x;
+                    for dy in 0..scale_y {
+                        for dx in 0..scale_x {
+                            frame.set_pixel(x + dx, y * scale_y + dy, pixel);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Render the high resolution, where bit 0 of the first palette entry inverts the display
+    fn render_monochrome(&self, frame: &mut Frame, memory: &[u8]) {
+        let inverted = (self.palette[0] & 0x0001) != 0;
+        for (i, byte) in memory.iter().enumerate() {
+            let y = (i / 80) as u32;
+            let x = ((i % 80) * 8) as u32;
+            for bit in 0..8 {
+                let set = (*byte & (0x80 >> bit)) != 0;
+                let pixel = if set == inverted {
+                    Pixel::Rgb(0, 0, 0)
+                } else {
+                    Pixel::Rgb(0xFF, 0xFF, 0xFF)
+                };
+                frame.set_pixel(x + bit, y, pixel);
+            }
+        }
+    }
+}
+
+/// Convert a palette entry with 3 bits for each of red, green, and blue into a pixel
+fn colour_to_pixel(colour: u16) -> Pixel {
+    let expand = |value: u16| -> u8 {
+        let value = (value & 0x07) as u8;
+        (value << 5) | (value << 2) | (value >> 1)
+    };
+    Pixel::Rgb(expand(colour >> 8), expand(colour >> 4), expand(colour))
+}
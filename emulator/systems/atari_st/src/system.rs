@@ -0,0 +1,102 @@
+use std::fs;
+use femtos::Frequency;
+
+use moa_core::{System, Error, Address, MemoryBlock, AddressRepeater, Device, MachineInfo};
+use moa_host::Host;
+
+use moa_m68k::{M68k, M68kType};
+use moa_peripherals_yamaha::Ym2149;
+
+use crate::peripherals::floppy::{FloppyController, StDisk};
+use crate::peripherals::glue::{Glue, IO_BASE};
+use crate::peripherals::ikbd::Ikbd;
+use crate::peripherals::shifter::{Shifter, SCREEN_SIZE};
+
+
+/// The RAM and its mirrors fill the bottom 4MB of the address space
+const RAM_AREA: Address = 0x400000;
+const CARTRIDGE_BASE: Address = 0xFA0000;
+const CARTRIDGE_SIZE: usize = 0x20000;
+
+/// The offset of the address that TOS expects to be loaded at, in the header at the start of the ROM
+const TOS_HEADER_BASE: usize = 0x08;
+
+
+#[derive(Debug)]
+pub struct AtariStOptions {
+    /// A TOS ROM image, which is loaded at the address in its header
+    pub rom: String,
+    /// The size of RAM in bytes, which is 512K on the 520ST and 1MB on the 1040ST
+    pub ram: usize,
+    /// A disk image to insert into drive A, in the .ST format
+    pub disk: Option<String>,
+    /// Connect a monochrome monitor instead of a colour monitor, which only supports the high resolution
+    pub monochrome: bool,
+}
+
+impl Default for AtariStOptions {
+    fn default() -> Self {
+        Self {
+            rom: "binaries/atarist/tos102.img".to_string(),
+            ram: 0x100000,
+            disk: None,
+            monochrome: false,
+        }
+    }
+}
+
+
+pub fn build_atari_st<H: Host>(host: &mut H, options: AtariStOptions) -> Result<System, Error> {
+    let mut system = System::default();
+    let refresh_rate = if options.monochrome { 71 } else { 50 };
+    system.machine_info = MachineInfo::new("atari-st")
+        .with_video(SCREEN_SIZE.0, SCREEN_SIZE.1, Frequency::from_hz(refresh_rate))
+        .with_rom(&options.rom);
+
+    let contents = fs::read(&options.rom).map_err(|_| Error::new(format!("Error reading contents of {}", options.rom)))?;
+    if contents.len() < TOS_HEADER_BASE + 4 {
+        return Err(Error::new(format!("{} is too small to be a TOS image", options.rom)));
+    }
+    let rom_base = u32::from_be_bytes(contents[TOS_HEADER_BASE..TOS_HEADER_BASE + 4].try_into().unwrap()) as Address;
+    if rom_base != 0xFC0000 && rom_base != 0xE00000 {
+        return Err(Error::new(format!("{} isn't a TOS image, since it would be loaded at {:x}", options.rom, rom_base)));
+    }
+
+    if options.ram == 0 || RAM_AREA as usize % options.ram != 0 {
+        return Err(Error::new(format!("RAM size must divide evenly into 4MB, but it's {:x}", options.ram)));
+    }
+    // The first 8 bytes of the address space are read from the ROM at reset, which are copied into RAM instead
+    let mut ram = vec![0; options.ram];
+    ram[..8].copy_from_slice(&contents[..8]);
+    let ram = Device::new(MemoryBlock::new(ram));
+    // Accesses beyond the installed RAM wrap around, which is how TOS finds the amount of memory
+    system.add_peripheral("ram", 0x000000, Device::new(AddressRepeater::new(ram.clone(), RAM_AREA)))?;
+
+    let mut rom = MemoryBlock::new(contents);
+    rom.read_only();
+    system.add_peripheral("rom", rom_base, Device::new(rom))?;
+
+    let mut cartridge = MemoryBlock::new(vec![0xFF; CARTRIDGE_SIZE]);
+    cartridge.read_only();
+    system.add_peripheral("cartridge", CARTRIDGE_BASE, Device::new(cartridge))?;
+
+    // The PSG is clocked at 2MHz, and port A selects the floppy drive and side
+    let psg = Ym2149::new(host, Frequency::from_hz(2_000_000))?;
+    let psg_port_a = psg.port_a();
+    let psg = Device::new(psg);
+    system.add_device("psg", psg.clone())?;
+
+    let shifter = Shifter::new(host, ram.clone(), options.monochrome)?;
+    let floppy = FloppyController::new(ram);
+    let ikbd = Ikbd::new(host)?;
+    let mut glue = Glue::new(shifter, floppy, psg, psg_port_a, ikbd, options.monochrome);
+    if let Some(filename) = &options.disk {
+        glue.insert_disk(0, StDisk::load(filename)?);
+    }
+    system.add_peripheral("glue", IO_BASE, Device::new(glue))?;
+
+    let cpu = M68k::from_type(M68kType::MC68000, Frequency::from_hz(8_000_000));
+    system.add_interruptable_device("cpu", Device::new(cpu))?;
+
+    Ok(system)
+}
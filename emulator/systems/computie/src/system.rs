@@ -1,16 +1,20 @@
 use femtos::Frequency;
 
-use moa_core::{System, Error, Debuggable, MemoryBlock, Device};
-use moa_host::Host;
+use moa_core::{System, Error, Debuggable, MemoryBlock, MemoryRegion, Device, MachineInfo};
+use moa_host::{Host, HostError, SlipTty};
 
 use moa_m68k::{M68k, M68kType};
 use moa_peripherals_generic::AtaDevice;
 use moa_peripherals_motorola::MC68681;
 
+#[derive(Debug)]
 pub struct ComputieOptions {
     pub rom: String,
     pub ram: usize,
     pub frequency: Frequency,
+    pub disk: String,
+    /// A copy-on-write file to store the sectors written to the disk, instead of only changing them in memory
+    pub disk_overlay: Option<String>,
 }
 
 impl Default for ComputieOptions {
@@ -19,12 +23,15 @@ impl Default for ComputieOptions {
             rom: "binaries/computie/monitor.bin".to_string(),
             ram: 0x10_0000,
             frequency: Frequency::from_hz(10_000_000),
+            disk: "binaries/computie/disk-with-partition-table.img".to_string(),
+            disk_overlay: None,
         }
     }
 }
 
 pub fn build_computie<H: Host>(host: &H, options: ComputieOptions) -> Result<System, Error> {
     let mut system = System::default();
+    system.machine_info = MachineInfo::new("computie").with_rom(&options.rom);
 
     let mut rom = MemoryBlock::new(vec![0; 0x10000]);
     rom.load_at(0x0000, &options.rom)?;
@@ -33,14 +40,30 @@ pub fn build_computie<H: Host>(host: &H, options: ComputieOptions) -> Result<Sys
     let mut ram = MemoryBlock::new(vec![0; options.ram]);
     ram.load_at(0, "binaries/computie/kernel.bin")?;
     system.add_addressable_device(0x00100000, Device::new(ram))?;
+    system.get_bus().add_region(MemoryRegion::new("monitor", 0x00000000, 0x10000));
+    system
+        .get_bus()
+        .add_region(MemoryRegion::new("ram", 0x00100000, options.ram as u64));
 
     let mut ata = AtaDevice::default();
-    ata.load("binaries/computie/disk-with-partition-table.img")?;
+    ata.load(&options.disk)?;
+    if let Some(filename) = &options.disk_overlay {
+        ata.load_overlay(filename)?;
+    }
     system.add_addressable_device(0x00600000, Device::new(ata))?;
 
     let mut serial = MC68681::default();
-    launch_terminal_emulator(serial.port_a.connect(host.add_pty()?)?);
-    launch_slip_connection(serial.port_b.connect(host.add_pty()?)?);
+    launch_terminal_emulator(serial.port_a.connect(host.add_serial_port("port-a")?)?);
+    // The kernel uses port B as a SLIP interface, which the host can give a network to directly
+    match host.add_network("slip") {
+        Ok(network) => {
+            serial.port_b.connect(Box::new(SlipTty::new(network)))?;
+        },
+        Err(HostError::NetworkNotSupported) => {
+            launch_slip_connection(serial.port_b.connect(host.add_serial_port("port-b")?)?);
+        },
+        Err(err) => return Err(err.into()),
+    }
     system.add_addressable_device(0x00700000, Device::new(serial))?;
 
 
@@ -55,6 +78,7 @@ pub fn build_computie<H: Host>(host: &H, options: ComputieOptions) -> Result<Sys
 
 pub fn build_computie_k30<H: Host>(host: &H) -> Result<System, Error> {
     let mut system = System::default();
+    system.machine_info = MachineInfo::new("computie-k30").with_rom("binaries/computie/monitor-68030.bin");
 
     let monitor = MemoryBlock::load("binaries/computie/monitor-68030.bin")?;
     system.add_addressable_device(0x00000000, Device::new(monitor))?;
@@ -68,8 +92,8 @@ pub fn build_computie_k30<H: Host>(host: &H) -> Result<System, Error> {
     system.add_addressable_device(0x00600000, Device::new(ata))?;
 
     let mut serial = MC68681::default();
-    launch_terminal_emulator(serial.port_a.connect(host.add_pty()?)?);
-    //launch_slip_connection(serial.port_b.connect(host.add_pty()?)?);
+    launch_terminal_emulator(serial.port_a.connect(host.add_serial_port("port-a")?)?);
+    //launch_slip_connection(serial.port_b.connect(host.add_serial_port("port-b")?)?);
     system.add_addressable_device(0x00700000, Device::new(serial))?;
 
 
@@ -96,6 +120,12 @@ pub fn launch_terminal_emulator(name: String) {
 pub fn launch_slip_connection(name: String) {
     use std::process::Command;
 
+    // slattach can only use a tty, so any other connection is left for the user to set up
+    if !name.starts_with("/dev/") {
+        log::info!("computie: connect a SLIP interface to {} for the network", name);
+        return;
+    }
+
     Command::new("sudo")
         .args(["slattach", "-s", "38400", "-p", "slip", &name])
         .spawn()
@@ -0,0 +1,4 @@
+pub mod peripherals;
+
+mod system;
+pub use crate::system::{CpmOptions, build_cpm};
@@ -0,0 +1,80 @@
+use femtos::Instant;
+
+use moa_core::{Error, Address, Addressable, Transmutable, strict};
+use moa_host::Tty;
+
+
+const DEV_NAME: &str = "cpm-console";
+
+#[rustfmt::skip]
+mod reg {
+    use super::Address;
+    pub(super) const STATUS: Address    = 0x00;
+    pub(super) const DATA: Address      = 0x01;
+}
+
+const STATUS_READY: u8 = 0xFF;
+const STATUS_EMPTY: u8 = 0x00;
+
+
+/// The console port of the z80pack simulator, which the BIOS polls for input by reading the status port, and
+/// reads or writes one character at a time through the data port
+#[derive(Default)]
+pub struct CpmConsole {
+    tty: Option<Box<dyn Tty>>,
+    input: Option<u8>,
+}
+
+impl CpmConsole {
+    pub fn connect(&mut self, pty: Box<dyn Tty>) -> Result<String, Error> {
+        let name = pty.device_name();
+        println!("{}: opening pts {}", DEV_NAME, name);
+        self.tty = Some(pty);
+        Ok(name)
+    }
+
+    fn poll_input(&mut self) {
+        if self.input.is_none() {
+            self.input = self.tty.as_mut().and_then(|tty| tty.read());
+        }
+    }
+}
+
+impl Addressable for CpmConsole {
+    fn size(&self) -> usize {
+        0x02
+    }
+
+    fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        match addr {
+            reg::STATUS => {
+                self.poll_input();
+                data[0] = if self.input.is_some() { STATUS_READY } else { STATUS_EMPTY };
+            },
+            reg::DATA => {
+                self.poll_input();
+                data[0] = self.input.take().unwrap_or(0);
+            },
+            _ => strict::unhandled_read(DEV_NAME, addr),
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        match addr {
+            reg::DATA => {
+                if let Some(tty) = self.tty.as_mut() {
+                    tty.write(data[0]);
+                }
+            },
+            _ => strict::unhandled_write(DEV_NAME, addr, data),
+        }
+        Ok(())
+    }
+}
+
+impl Transmutable for CpmConsole {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+}
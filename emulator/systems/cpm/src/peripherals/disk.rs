@@ -0,0 +1,261 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write, Seek, SeekFrom};
+
+use femtos::Instant;
+
+use moa_core::{Error, Address, Addressable, Transmutable, Device, strict};
+
+
+const DEV_NAME: &str = "cpm-disk";
+
+#[rustfmt::skip]
+mod reg {
+    use super::Address;
+    pub(super) const DRIVE: Address         = 0x00;
+    pub(super) const TRACK: Address         = 0x01;
+    pub(super) const SECTOR: Address        = 0x02;
+    pub(super) const COMMAND: Address       = 0x03;
+    pub(super) const STATUS: Address        = 0x04;
+    pub(super) const DMA_LOW: Address       = 0x05;
+    pub(super) const DMA_HIGH: Address      = 0x06;
+    pub(super) const SECTOR_HIGH: Address   = 0x07;
+}
+
+#[rustfmt::skip]
+mod cmd {
+    pub(super) const READ: u8       = 0x00;
+    pub(super) const WRITE: u8      = 0x01;
+}
+
+#[rustfmt::skip]
+mod status {
+    pub(super) const OK: u8                 = 0x00;
+    pub(super) const ILLEGAL_DRIVE: u8      = 0x01;
+    pub(super) const ILLEGAL_TRACK: u8      = 0x02;
+    pub(super) const ILLEGAL_SECTOR: u8     = 0x03;
+    pub(super) const SEEK_ERROR: u8         = 0x04;
+    pub(super) const READ_ERROR: u8         = 0x05;
+    pub(super) const WRITE_ERROR: u8        = 0x06;
+    pub(super) const ILLEGAL_COMMAND: u8    = 0x07;
+}
+
+pub const SECTOR_SIZE: usize = 128;
+pub const MAX_DRIVES: usize = 16;
+
+/// The value of the bytes in parts of an image past the end of the file, which is what CP/M uses for empty space
+const EMPTY_BYTE: u8 = 0xE5;
+
+
+/// The number of tracks and sectors of a disk, where sectors are numbered from 1
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DiskGeometry {
+    pub tracks: usize,
+    pub sectors_per_track: usize,
+}
+
+impl DiskGeometry {
+    /// A single sided, single density 8" floppy disk, which is the standard CP/M distribution format
+    pub const FLOPPY_8INCH: DiskGeometry = DiskGeometry {
+        tracks: 77,
+        sectors_per_track: 26,
+    };
+
+    /// The 4MB hard disk of the z80pack simulator
+    pub const HARD_DISK_4MB: DiskGeometry = DiskGeometry {
+        tracks: 255,
+        sectors_per_track: 128,
+    };
+
+    /// Guess the geometry from the size of an image file
+    pub fn from_image_size(size: u64) -> Self {
+        if size <= DiskGeometry::FLOPPY_8INCH.size() as u64 {
+            DiskGeometry::FLOPPY_8INCH
+        } else {
+            DiskGeometry::HARD_DISK_4MB
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.tracks * self.sectors_per_track * SECTOR_SIZE
+    }
+}
+
+struct DiskImage {
+    file: File,
+    geometry: DiskGeometry,
+}
+
+/// The disk controller of the z80pack simulator, which transfers a whole sector at a time between an image file
+/// and memory, at the DMA address set by the BIOS
+pub struct CpmDiskController {
+    memory: Device,
+    drives: Vec<Option<DiskImage>>,
+    drive: u8,
+    track: u8,
+    sector: u16,
+    dma: u16,
+    status: u8,
+}
+
+impl CpmDiskController {
+    /// Create a disk controller that transfers data to and from the given memory device
+    pub fn new(memory: Device) -> Self {
+        Self {
+            memory,
+            drives: (0..MAX_DRIVES).map(|_| None).collect(),
+            drive: 0,
+            track: 0,
+            sector: 1,
+            dma: 0x0080,
+            status: status::OK,
+        }
+    }
+
+    /// Use the given image file for a drive, where drive 0 is A.  The file is opened read-only if it can't be
+    /// written to, in which case writes will fail with an error status
+    pub fn attach(&mut self, drive: usize, filename: &str) -> Result<(), Error> {
+        if drive >= MAX_DRIVES {
+            return Err(Error::new(format!("{}: drive {} is out of range", DEV_NAME, drive)));
+        }
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(filename)
+            .or_else(|_| File::open(filename))
+            .map_err(|err| Error::new(format!("{}: error opening {}: {}", DEV_NAME, filename, err)))?;
+        let size = file
+            .metadata()
+            .map_err(|err| Error::new(format!("{}: error reading {}: {}", DEV_NAME, filename, err)))?
+            .len();
+
+        self.drives[drive] = Some(DiskImage {
+            file,
+            geometry: DiskGeometry::from_image_size(size),
+        });
+        Ok(())
+    }
+
+    /// Load the first sector of drive A into the start of memory, which is how the simulator starts CP/M
+    pub fn load_boot_sector(&mut self, clock: Instant) -> Result<(), Error> {
+        self.drive = 0;
+        self.track = 0;
+        self.sector = 1;
+        self.dma = 0x0000;
+        match self.transfer(clock, cmd::READ) {
+            status::OK => Ok(()),
+            err => Err(Error::new(format!("{}: unable to read the boot sector from drive A: error {}", DEV_NAME, err))),
+        }
+    }
+
+    fn transfer(&mut self, clock: Instant, command: u8) -> u8 {
+        let (drive, track, sector) = (self.drive as usize, self.track as usize, self.sector as usize);
+        let image = match self.drives.get_mut(drive).and_then(|drive| drive.as_mut()) {
+            Some(image) => image,
+            None => return status::ILLEGAL_DRIVE,
+        };
+        if track >= image.geometry.tracks {
+            return status::ILLEGAL_TRACK;
+        }
+        if sector == 0 || sector > image.geometry.sectors_per_track {
+            return status::ILLEGAL_SECTOR;
+        }
+
+        let offset = (track * image.geometry.sectors_per_track + sector - 1) * SECTOR_SIZE;
+        if image.file.seek(SeekFrom::Start(offset as u64)).is_err() {
+            return status::SEEK_ERROR;
+        }
+
+        let mut buffer = [EMPTY_BYTE; SECTOR_SIZE];
+        let mut memory = self.memory.borrow_mut();
+        let memory = memory.as_addressable().unwrap();
+        match command {
+            cmd::READ => {
+                if read_available(&mut image.file, &mut buffer).is_err() {
+                    return status::READ_ERROR;
+                }
+                if write_memory(memory, clock, self.dma, &buffer).is_err() {
+                    return status::READ_ERROR;
+                }
+            },
+            cmd::WRITE => {
+                if read_memory(memory, clock, self.dma, &mut buffer).is_err() {
+                    return status::WRITE_ERROR;
+                }
+                if image.file.write_all(&buffer).is_err() {
+                    return status::WRITE_ERROR;
+                }
+            },
+            _ => return status::ILLEGAL_COMMAND,
+        }
+        log::debug!(target: DEV_NAME, "{} drive {} track {} sector {} at {:04x}", command, drive, track, sector, self.dma);
+        status::OK
+    }
+}
+
+/// Read as much of the buffer as the file contains, leaving the rest unchanged
+fn read_available(file: &mut File, buffer: &mut [u8]) -> Result<(), std::io::Error> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..])? {
+            0 => break,
+            count => filled += count,
+        }
+    }
+    Ok(())
+}
+
+// The DMA address wraps around at the end of the 64K address space
+fn write_memory(memory: &mut dyn Addressable, clock: Instant, dma: u16, data: &[u8]) -> Result<(), Error> {
+    for (i, byte) in data.iter().enumerate() {
+        memory.write_u8(clock, dma.wrapping_add(i as u16) as Address, *byte)?;
+    }
+    Ok(())
+}
+
+fn read_memory(memory: &mut dyn Addressable, clock: Instant, dma: u16, data: &mut [u8]) -> Result<(), Error> {
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte = memory.read_u8(clock, dma.wrapping_add(i as u16) as Address)?;
+    }
+    Ok(())
+}
+
+impl Addressable for CpmDiskController {
+    fn size(&self) -> usize {
+        0x08
+    }
+
+    fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        match addr {
+            reg::DRIVE => data[0] = self.drive,
+            reg::TRACK => data[0] = self.track,
+            reg::SECTOR => data[0] = self.sector as u8,
+            reg::SECTOR_HIGH => data[0] = (self.sector >> 8) as u8,
+            reg::STATUS => data[0] = self.status,
+            reg::DMA_LOW => data[0] = self.dma as u8,
+            reg::DMA_HIGH => data[0] = (self.dma >> 8) as u8,
+            _ => strict::unhandled_read(DEV_NAME, addr),
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        match addr {
+            reg::DRIVE => self.drive = data[0],
+            reg::TRACK => self.track = data[0],
+            reg::SECTOR => self.sector = (self.sector & 0xFF00) | data[0] as u16,
+            reg::SECTOR_HIGH => self.sector = (self.sector & 0x00FF) | ((data[0] as u16) << 8),
+            reg::COMMAND => self.status = self.transfer(clock, data[0]),
+            reg::DMA_LOW => self.dma = (self.dma & 0xFF00) | data[0] as u16,
+            reg::DMA_HIGH => self.dma = (self.dma & 0x00FF) | ((data[0] as u16) << 8),
+            _ => strict::unhandled_write(DEV_NAME, addr, data),
+        }
+        Ok(())
+    }
+}
+
+impl Transmutable for CpmDiskController {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+}
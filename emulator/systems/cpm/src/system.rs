@@ -0,0 +1,82 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use femtos::Frequency;
+
+use moa_core::{System, Error, MemoryBlock, Bus, Address, AddressTranslator, Device, MachineInfo};
+use moa_host::Host;
+
+use moa_z80::{MoaZ80, Z80, Z80Type};
+
+use crate::peripherals::console::CpmConsole;
+use crate::peripherals::disk::{CpmDiskController, MAX_DRIVES};
+
+
+// The I/O ports are the same as the z80pack simulator, so that its CP/M disk images can be booted
+const CONSOLE_PORT: Address = 0x00;
+const DISK_PORT: Address = 0x0A;
+
+
+#[derive(Debug)]
+pub struct CpmOptions {
+    /// The image files to use for drives A, B, and so on, where the first sector of drive A is the boot loader
+    pub disks: Vec<String>,
+    pub frequency: Frequency,
+    /// Emulate an Intel 8080 instead of a Z80, to check that programs don't use any Z80 instructions
+    pub i8080: bool,
+}
+
+impl Default for CpmOptions {
+    fn default() -> Self {
+        Self {
+            disks: vec!["binaries/cpm/drivea.dsk".to_string()],
+            frequency: Frequency::from_hz(4_000_000),
+            i8080: false,
+        }
+    }
+}
+
+
+pub fn build_cpm<H: Host>(host: &H, options: CpmOptions) -> Result<System, Error> {
+    if options.disks.is_empty() || options.disks.len() > MAX_DRIVES {
+        return Err(Error::new(format!("cpm: between 1 and {} disk images must be given", MAX_DRIVES)));
+    }
+
+    let mut system = System::default();
+    system.machine_info = MachineInfo::new("cpm");
+
+    let ram = Device::new(MemoryBlock::new(vec![0; 0x10000]));
+    system.add_addressable_device(0x0000, ram.clone())?;
+
+    let mut console = CpmConsole::default();
+    console.connect(host.add_serial_port("console")?)?;
+    let console = Device::new(console);
+
+    let mut disk = CpmDiskController::new(ram);
+    for (drive, filename) in options.disks.iter().enumerate() {
+        disk.attach(drive, filename)?;
+    }
+    disk.load_boot_sector(system.clock)?;
+    let disk = Device::new(disk);
+
+    // Only the lower 8 bits of the port address are decoded, so the whole port space is repeated over the
+    // 16-bit address that the cpu puts on the bus
+    let mut ports = Bus::default();
+    ports.set_ignore_unmapped(true);
+    ports.insert(CONSOLE_PORT, console.clone());
+    ports.insert(DISK_PORT, disk.clone());
+    let ports = AddressTranslator::new(Device::new(ports), 0x10000, |addr| addr & 0xFF);
+    let io_bus = Rc::new(RefCell::new(Bus::default()));
+    io_bus.borrow_mut().insert(0x0000, Device::new(ports));
+
+    system.add_device("console", console)?;
+    system.add_device("disk", disk)?;
+
+    let cputype = if options.i8080 { Z80Type::I8080 } else { Z80Type::Z80 };
+    let cpu = Z80::from_type(cputype, options.frequency);
+    let cpu = MoaZ80::new(cpu, system.bus.clone()).with_io_bus(io_bus);
+
+    system.add_interruptable_device("cpu", Device::new(cpu))?;
+
+    Ok(system)
+}
@@ -0,0 +1,256 @@
+use std::fs;
+
+use serde::Deserialize;
+
+use moa_core::{Address, Error};
+
+/// The description of a machine, which lists the cpu, memory, and peripherals that make it up
+///
+/// A definition is written as a TOML file, such as:
+///
+/// ```toml
+/// name = "my-68008-board"
+///
+/// [cpu]
+/// type = "68008"
+/// frequency = 8_000_000
+///
+/// [[memory]]
+/// name = "rom"
+/// address = 0x000000
+/// file = "monitor.bin"
+/// size = 0x10000
+/// read_only = true
+///
+/// [[memory]]
+/// name = "ram"
+/// address = 0x100000
+/// size = 0x80000
+///
+/// [[device]]
+/// type = "mc68681"
+/// name = "serial"
+/// address = 0x0F0000
+/// interrupt = 4
+/// pty = ["a"]
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineDefinition {
+    #[serde(default = "name_default")]
+    pub name: String,
+    pub cpu: CpuDefinition,
+    #[serde(default)]
+    pub memory: Vec<MemoryDefinition>,
+    #[serde(rename = "device", default)]
+    pub devices: Vec<DeviceDefinition>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+pub enum CpuKind {
+    #[serde(rename = "68000")]
+    MC68000,
+    #[serde(rename = "68008")]
+    MC68008,
+    #[serde(rename = "68010")]
+    MC68010,
+    #[serde(rename = "68020")]
+    MC68020,
+    #[serde(rename = "68030")]
+    MC68030,
+    #[serde(rename = "z80")]
+    Z80,
+    #[serde(rename = "8080")]
+    I8080,
+}
+
+impl CpuKind {
+    /// Returns the cpu with the given name, as used in the type field of the definition
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "68000" => Some(CpuKind::MC68000),
+            "68008" => Some(CpuKind::MC68008),
+            "68010" => Some(CpuKind::MC68010),
+            "68020" => Some(CpuKind::MC68020),
+            "68030" => Some(CpuKind::MC68030),
+            "z80" => Some(CpuKind::Z80),
+            "8080" => Some(CpuKind::I8080),
+            _ => None,
+        }
+    }
+
+    pub fn is_z80(&self) -> bool {
+        matches!(self, CpuKind::Z80 | CpuKind::I8080)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CpuDefinition {
+    #[serde(rename = "type")]
+    pub kind: CpuKind,
+    /// The clock frequency in Hz
+    pub frequency: u32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryDefinition {
+    /// The name of the region, which defaults to `memory` followed by its index
+    #[serde(default)]
+    pub name: String,
+    pub address: Address,
+    /// The size of the region, which defaults to the size of the file if one is given
+    pub size: Option<usize>,
+    /// The file to load into the start of the region
+    pub file: Option<String>,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceKind {
+    /// Motorola MC68681 dual serial port
+    MC68681,
+    /// Motorola MC68901 multi-function peripheral
+    MC68901,
+    /// Zilog Z8530 serial communications controller
+    Z8530,
+    /// ATA hard disk
+    Ata,
+    /// MK48T08 timekeeper and battery-backed RAM
+    MK48T08,
+    /// MK48T02 timekeeper and battery-backed RAM
+    MK48T02,
+    /// Memory-mapped network interface, connected to the host's network
+    Nic,
+}
+
+impl DeviceKind {
+    /// Returns the device with the given name, as used in the type field of the definition
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mc68681" => Some(DeviceKind::MC68681),
+            "mc68901" => Some(DeviceKind::MC68901),
+            "z8530" => Some(DeviceKind::Z8530),
+            "ata" => Some(DeviceKind::Ata),
+            "mk48t08" => Some(DeviceKind::MK48T08),
+            "mk48t02" => Some(DeviceKind::MK48T02),
+            "nic" => Some(DeviceKind::Nic),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the device has serial ports that can be connected to a pseudoterminal
+    pub fn is_serial(&self) -> bool {
+        matches!(self, DeviceKind::MC68681 | DeviceKind::MC68901 | DeviceKind::Z8530)
+    }
+
+    /// Returns true if the device has an interrupt output that can be connected to an interrupt level
+    pub fn has_interrupt(&self) -> bool {
+        self.is_serial() || *self == DeviceKind::Nic
+    }
+}
+
+/// The address space a device is mapped into, which can only be `Io` for the Z80
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressSpace {
+    #[default]
+    Memory,
+    Io,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SerialPort {
+    A,
+    B,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceDefinition {
+    /// The name of the device, which defaults to `device` followed by its index
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: DeviceKind,
+    pub address: Address,
+    #[serde(default)]
+    pub space: AddressSpace,
+    /// The interrupt priority level that the device's interrupt output is connected to
+    pub interrupt: Option<u8>,
+    /// The frequency in Hz of the device's clock input, if it's different from the chip's usual crystal
+    pub frequency: Option<u32>,
+    /// The disk image or backing file for the device
+    pub file: Option<String>,
+    /// The serial ports to connect to a pseudoterminal
+    #[serde(default)]
+    pub pty: Vec<SerialPort>,
+}
+
+impl MachineDefinition {
+    pub fn load(filename: &str) -> Result<Self, Error> {
+        let contents = fs::read_to_string(filename)
+            .map_err(|err| Error::new(format!("error reading machine definition {}: {}", filename, err)))?;
+        Self::parse(&contents).map_err(|err| Error::new(format!("{}: {}", filename, err)))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        let mut definition: MachineDefinition = toml::from_str(contents).map_err(|err| Error::new(err.to_string()))?;
+        for (i, memory) in definition.memory.iter_mut().enumerate() {
+            if memory.name.is_empty() {
+                memory.name = format!("memory{}", i);
+            }
+        }
+        for (i, device) in definition.devices.iter_mut().enumerate() {
+            if device.name.is_empty() {
+                device.name = format!("device{}", i);
+            }
+        }
+        definition.validate()?;
+        Ok(definition)
+    }
+
+    /// Check the values that can't be checked by their types, and that the devices can be connected to the cpu
+    pub fn validate(&self) -> Result<(), Error> {
+        for memory in &self.memory {
+            if memory.size.is_none() && memory.file.is_none() {
+                return Err(Error::new(format!("memory {}: either a size or a file is required", memory.name)));
+            }
+        }
+
+        for device in &self.devices {
+            let error = |message: &str| Error::new(format!("device {}: {}", device.name, message));
+
+            if device.interrupt.is_some() && !device.kind.has_interrupt() {
+                return Err(error("this type of device doesn't have an interrupt output"));
+            }
+            if !matches!(device.interrupt, None | Some(1..=7)) {
+                return Err(error("the interrupt level must be between 1 and 7"));
+            }
+            if device.interrupt.is_some() && self.cpu.kind.is_z80() {
+                return Err(error("interrupt levels can only be used with the 68000 family of cpus"));
+            }
+            if device.space == AddressSpace::Io && !self.cpu.kind.is_z80() {
+                return Err(error("only the Z80 has an io address space"));
+            }
+            if !device.pty.is_empty() && !device.kind.is_serial() {
+                return Err(error("this type of device doesn't have serial ports"));
+            }
+            if device.kind == DeviceKind::MC68901 && device.pty.contains(&SerialPort::B) {
+                return Err(error("the MC68901 only has one serial port, \"a\""));
+            }
+            if device.kind == DeviceKind::Ata && device.file.is_none() {
+                return Err(error("a disk image file is required"));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn name_default() -> String {
+    "custom".to_string()
+}
@@ -0,0 +1,7 @@
+mod definition;
+mod system;
+
+pub use crate::definition::{
+    MachineDefinition, CpuDefinition, CpuKind, MemoryDefinition, DeviceDefinition, DeviceKind, AddressSpace, SerialPort,
+};
+pub use crate::system::{build_custom, build_from_definition, build_bare, CustomOptions, BareOptions};
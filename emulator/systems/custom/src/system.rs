@@ -0,0 +1,294 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use femtos::Frequency;
+
+use moa_core::{System, Error, Address, Addressable, Bus, MemoryBlock, MemoryRegion, Device, MachineInfo};
+use moa_host::{Host, HostError};
+
+use moa_m68k::{M68k, M68kType};
+use moa_z80::{MoaZ80, Z80, Z80Type};
+use moa_peripherals_generic::{AtaDevice, MK48T08, VirtualNic};
+use moa_peripherals_motorola::{MC68681, MC68901};
+use moa_peripherals_zilog::Z8530;
+
+use crate::definition::{
+    MachineDefinition, CpuDefinition, CpuKind, MemoryDefinition, DeviceKind, DeviceDefinition, AddressSpace, SerialPort,
+};
+
+
+// The same defaults as the MC68901's Default, which are those of the Atari ST
+const MC68901_FREQUENCY: u32 = 2_457_600;
+const MC68901_LEVEL: u8 = 6;
+
+
+#[derive(Debug)]
+pub struct CustomOptions {
+    /// The TOML file which describes the machine
+    pub definition: String,
+}
+
+impl Default for CustomOptions {
+    fn default() -> Self {
+        Self {
+            definition: "binaries/custom/computie.toml".to_string(),
+        }
+    }
+}
+
+pub fn build_custom<H: Host>(host: &H, options: CustomOptions) -> Result<System, Error> {
+    let definition = MachineDefinition::load(&options.definition)?;
+    build_from_definition(host, &definition)
+}
+
+/// The options for a generic board which runs a bare ROM image, without writing a machine definition file
+#[derive(Debug)]
+pub struct BareOptions {
+    pub cpu: CpuKind,
+    /// The cpu frequency, which defaults to 8MHz for the 68000 family and 4MHz for the Z80
+    pub frequency: Option<Frequency>,
+    /// The ROM images to load, and the addresses to load them at
+    pub roms: Vec<(String, Address)>,
+    /// The sizes and addresses of the RAM regions
+    pub ram: Vec<(usize, Address)>,
+    /// The serial chips and their addresses, which have their first port connected to a pseudoterminal
+    pub serial: Vec<(DeviceKind, Address)>,
+}
+
+impl Default for BareOptions {
+    fn default() -> Self {
+        Self {
+            cpu: CpuKind::MC68000,
+            frequency: None,
+            roms: vec![],
+            ram: vec![],
+            serial: vec![],
+        }
+    }
+}
+
+pub fn build_bare<H: Host>(host: &H, options: BareOptions) -> Result<System, Error> {
+    if options.roms.is_empty() {
+        return Err(Error::new("bare: at least one ROM image must be given"));
+    }
+
+    let default_frequency = if options.cpu.is_z80() { 4_000_000 } else { 8_000_000 };
+    let cpu = CpuDefinition {
+        kind: options.cpu,
+        frequency: options
+            .frequency
+            .map(|frequency| frequency.as_hz())
+            .unwrap_or(default_frequency),
+    };
+
+    let roms = options
+        .roms
+        .into_iter()
+        .enumerate()
+        .map(|(i, (file, address))| MemoryDefinition {
+            name: format!("rom{}", i),
+            address,
+            size: None,
+            file: Some(file),
+            read_only: true,
+        });
+    let ram = options
+        .ram
+        .into_iter()
+        .enumerate()
+        .map(|(i, (size, address))| MemoryDefinition {
+            name: format!("ram{}", i),
+            address,
+            size: Some(size),
+            file: None,
+            read_only: false,
+        });
+
+    let devices = options
+        .serial
+        .into_iter()
+        .enumerate()
+        .map(|(i, (kind, address))| {
+            if !kind.is_serial() {
+                return Err(Error::new(format!("bare: {:?} isn't a serial device", kind)));
+            }
+            Ok(DeviceDefinition {
+                name: format!("serial{}", i),
+                kind,
+                address,
+                space: AddressSpace::Memory,
+                interrupt: None,
+                frequency: None,
+                file: None,
+                pty: vec![SerialPort::A],
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let definition = MachineDefinition {
+        name: "bare".to_string(),
+        cpu,
+        memory: roms.chain(ram).collect(),
+        devices,
+    };
+    build_from_definition(host, &definition)
+}
+
+/// Build a system with the cpu, memory, and devices listed in the given definition
+pub fn build_from_definition<H: Host>(host: &H, definition: &MachineDefinition) -> Result<System, Error> {
+    definition.validate()?;
+
+    let mut system = System::default();
+    system.machine_info = MachineInfo::new(&definition.name);
+
+    for memory in &definition.memory {
+        let mut block = match (&memory.file, memory.size) {
+            (Some(file), None) => MemoryBlock::load(file)?,
+            (file, size) => {
+                let mut block = MemoryBlock::new(vec![0; size.unwrap_or(0)]);
+                if let Some(file) = file {
+                    block.load_at(0, file)?;
+                }
+                block
+            },
+        };
+        if memory.read_only {
+            block.read_only();
+        }
+
+        let size = block.size() as u64;
+        system.add_addressable_device(memory.address, Device::new(block))?;
+        system
+            .get_bus()
+            .add_region(MemoryRegion::new(&memory.name, memory.address, size));
+    }
+
+    let io_bus = Rc::new(RefCell::new(Bus::default()));
+    for device in &definition.devices {
+        let peripheral = build_device(host, definition.cpu.kind, device)?;
+        match device.space {
+            AddressSpace::Memory => system.add_peripheral(&device.name, device.address, peripheral)?,
+            AddressSpace::Io => {
+                io_bus.borrow_mut().insert(device.address, peripheral.clone());
+                system.add_device(&device.name, peripheral)?;
+            },
+        }
+    }
+
+    let frequency = Frequency::from_hz(definition.cpu.frequency);
+    let cpu = match definition.cpu.kind {
+        CpuKind::MC68000 => Device::new(M68k::from_type(M68kType::MC68000, frequency)),
+        CpuKind::MC68008 => Device::new(M68k::from_type(M68kType::MC68008, frequency)),
+        CpuKind::MC68010 => Device::new(M68k::from_type(M68kType::MC68010, frequency)),
+        CpuKind::MC68020 => Device::new(M68k::from_type(M68kType::MC68020, frequency)),
+        CpuKind::MC68030 => Device::new(M68k::from_type(M68kType::MC68030, frequency)),
+        CpuKind::Z80 | CpuKind::I8080 => {
+            let cputype = if definition.cpu.kind == CpuKind::I8080 {
+                Z80Type::I8080
+            } else {
+                Z80Type::Z80
+            };
+            let cpu = Z80::from_type(cputype, frequency);
+            Device::new(MoaZ80::new(cpu, system.bus.clone()).with_io_bus(io_bus))
+        },
+    };
+    system.add_interruptable_device("cpu", cpu)?;
+
+    Ok(system)
+}
+
+fn build_device<H: Host>(host: &H, cpu: CpuKind, device: &DeviceDefinition) -> Result<Device, Error> {
+    let peripheral = match device.kind {
+        DeviceKind::MC68681 => {
+            let mut serial = MC68681::default();
+            if let Some(level) = device.interrupt {
+                serial = serial.with_interrupt_priority(level);
+            }
+            for port in &device.pty {
+                let tty = host.add_serial_port(&serial_port_name(device, *port))?;
+                let port = if *port == SerialPort::A {
+                    &mut serial.port_a
+                } else {
+                    &mut serial.port_b
+                };
+                log_pty(device, port.connect(tty)?);
+            }
+            Device::new(serial)
+        },
+        DeviceKind::MC68901 => {
+            let frequency = Frequency::from_hz(device.frequency.unwrap_or(MC68901_FREQUENCY));
+            let mut mfp = MC68901::new(frequency, device.interrupt.unwrap_or(MC68901_LEVEL));
+            if !device.pty.is_empty() {
+                log_pty(device, mfp.usart.connect(host.add_serial_port(&device.name)?)?);
+            }
+            Device::new(mfp)
+        },
+        DeviceKind::Z8530 => {
+            let mut scc = match device.frequency {
+                Some(frequency) => Z8530::new(Frequency::from_hz(frequency)),
+                None => Z8530::default(),
+            };
+            if let Some(level) = device.interrupt {
+                scc = scc.with_interrupt_priority(level);
+            }
+            for port in &device.pty {
+                let tty = host.add_serial_port(&serial_port_name(device, *port))?;
+                let port = if *port == SerialPort::A {
+                    &mut scc.port_a
+                } else {
+                    &mut scc.port_b
+                };
+                log_pty(device, port.connect(tty)?);
+            }
+            Device::new(scc)
+        },
+        DeviceKind::Ata => {
+            let mut ata = AtaDevice::default();
+            if let Some(file) = &device.file {
+                ata.load(file)?;
+            }
+            Device::new(ata)
+        },
+        DeviceKind::MK48T08 | DeviceKind::MK48T02 => {
+            let mut rtc = if device.kind == DeviceKind::MK48T02 {
+                MK48T08::mk48t02()
+            } else {
+                MK48T08::default()
+            };
+            if let Some(file) = &device.file {
+                rtc = rtc.with_file(file)?;
+            }
+            Device::new(rtc)
+        },
+        DeviceKind::Nic => {
+            let mut nic = VirtualNic::new(!cpu.is_z80());
+            if let Some(level) = device.interrupt {
+                nic = nic.with_interrupt_priority(level);
+            }
+            match host.add_network(&device.name) {
+                Ok(network) => {
+                    log::info!("{}: network connected to {}", device.name, network.device_name());
+                    nic = nic.with_network(network);
+                },
+                Err(HostError::NetworkNotSupported) => {
+                    log::warn!("{}: the frontend doesn't support networking, so the device has no link", device.name);
+                },
+                Err(err) => return Err(err.into()),
+            }
+            Device::new(nic)
+        },
+    };
+    Ok(peripheral)
+}
+
+/// The name of one of a device's serial ports, which is used to choose how the frontend connects it
+fn serial_port_name(device: &DeviceDefinition, port: SerialPort) -> String {
+    match port {
+        SerialPort::A => format!("{}-a", device.name),
+        SerialPort::B => format!("{}-b", device.name),
+    }
+}
+
+fn log_pty(device: &DeviceDefinition, name: String) {
+    log::info!("{}: serial port connected to {}", device.name, name);
+}
@@ -1,4 +1,5 @@
 pub mod peripherals;
+pub mod romdb;
 pub mod utils;
 
 mod system;
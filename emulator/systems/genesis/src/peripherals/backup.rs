@@ -0,0 +1,391 @@
+//! Cartridge backup memory, which is either SRAM or a serial EEPROM, declared in the ROM header
+//!
+//! Resources:
+//! - https://plutiedev.com/saving-sram
+//! - https://plutiedev.com/rom-header#extra-memory
+//! - http://gendev.spritesmind.net/forum/viewtopic.php?t=206 (EEPROM games and their mappings)
+
+use std::rc::Rc;
+use std::any::Any;
+use std::cell::Cell;
+use femtos::Instant;
+
+use moa_core::{Error, Address, Addressable, Device, Snapshotable, Transmutable, read_beu32};
+
+const HEADER_BACKUP_MEMORY: usize = 0x1B0;
+
+/// The size of the X24C01 EEPROM, which is the only one supported
+const EEPROM_SIZE: usize = 128;
+
+/// The largest SRAM that's believed from the header, so that a corrupt header doesn't allocate a huge block
+const MAX_SRAM_SIZE: usize = 0x10000;
+
+const DEV_NAME: &str = "backup";
+
+
+/// Which bytes of each word the SRAM is connected to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SramLayout {
+    Odd,
+    Even,
+    Word,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BackupMemory {
+    /// SRAM of the given size in bytes, which is kept with a battery if `battery` is set
+    Sram { size: usize, layout: SramLayout, battery: bool },
+    /// A serial EEPROM with Sega's mapping, where writing to 0x200001 sets SDA in bit 0 and SCL in bit 1, and
+    /// reading it returns SDA in bit 0
+    Eeprom,
+}
+
+impl BackupMemory {
+    /// Returns the backup memory declared by the "RA" entry of the ROM header, if any
+    pub fn from_header(data: &[u8]) -> Option<Self> {
+        let header = data.get(HEADER_BACKUP_MEMORY..HEADER_BACKUP_MEMORY + 12)?;
+        if &header[0..2] != b"RA" {
+            return None;
+        }
+
+        let kind = header[2];
+        let start = read_beu32(&header[4..8]) as usize;
+        let end = read_beu32(&header[8..12]) as usize;
+
+        if kind == 0xE8 && header[3] == 0x40 {
+            return Some(BackupMemory::Eeprom);
+        }
+        if header[3] != 0x20 || (kind & 0xA0) != 0xA0 || end < start {
+            log::warn!(target: DEV_NAME, "unsupported backup memory in header: {:02x?}", header);
+            return None;
+        }
+
+        let (layout, size) = match (kind >> 3) & 0x03 {
+            0b11 => (SramLayout::Odd, (end - start) / 2 + 1),
+            0b10 => (SramLayout::Even, (end - start) / 2 + 1),
+            _ => (SramLayout::Word, end - start + 1),
+        };
+        if size > MAX_SRAM_SIZE {
+            log::warn!(target: DEV_NAME, "backup memory of {} bytes is too large, ignoring it", size);
+            return None;
+        }
+
+        Some(BackupMemory::Sram {
+            size,
+            layout,
+            battery: (kind & 0x40) != 0,
+        })
+    }
+
+    /// Whether the contents should be saved between runs
+    pub fn is_saved(&self) -> bool {
+        match self {
+            BackupMemory::Sram {
+                battery,
+                ..
+            } => *battery,
+            BackupMemory::Eeprom => true,
+        }
+    }
+
+    /// Returns the number of bytes that are stored, which is the size of the save file
+    pub fn size(&self) -> usize {
+        match self {
+            BackupMemory::Sram {
+                size,
+                ..
+            } => *size,
+            BackupMemory::Eeprom => EEPROM_SIZE,
+        }
+    }
+}
+
+
+/// The SRAM as seen by the cpu, which is mirrored throughout its window and only connected to some of the bytes
+/// of each word.  The contents are kept in a separate memory device, without the gaps, which is what gets saved
+pub struct CartridgeSram {
+    memory: Device,
+    capacity: usize,
+    layout: SramLayout,
+    window: usize,
+    write_protect: Rc<Cell<bool>>,
+}
+
+impl CartridgeSram {
+    pub fn new(memory: Device, layout: SramLayout, window: usize) -> Self {
+        let capacity = memory.borrow_mut().as_addressable().unwrap().size();
+        Self {
+            memory,
+            capacity,
+            layout,
+            window,
+            write_protect: Rc::new(Cell::new(false)),
+        }
+    }
+
+    /// Returns the flag that stops writes to the SRAM, which is set by the cartridge's mapper register
+    pub fn write_protect(&self) -> Rc<Cell<bool>> {
+        self.write_protect.clone()
+    }
+
+    /// Returns the address in the memory of the given address in the window, or `None` if it isn't connected
+    fn translate(&self, addr: usize) -> Option<Address> {
+        let index = match self.layout {
+            SramLayout::Odd if (addr & 0x01) != 0 => addr >> 1,
+            SramLayout::Even if (addr & 0x01) == 0 => addr >> 1,
+            SramLayout::Word => addr,
+            _ => return None,
+        };
+        Some((index % self.capacity) as Address)
+    }
+}
+
+impl Addressable for CartridgeSram {
+    fn size(&self) -> usize {
+        self.window
+    }
+
+    fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        let mut memory = self.memory.borrow_mut();
+        let memory = memory.as_addressable().unwrap();
+        for (i, byte) in data.iter_mut().enumerate() {
+            match self.translate(addr as usize + i) {
+                Some(index) => memory.read(clock, index, std::slice::from_mut(byte))?,
+                None => *byte = 0xFF,
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        if self.write_protect.get() {
+            return Ok(());
+        }
+
+        let mut memory = self.memory.borrow_mut();
+        let memory = memory.as_addressable().unwrap();
+        for (i, byte) in data.iter().enumerate() {
+            if let Some(index) = self.translate(addr as usize + i) {
+                memory.write(clock, index, std::slice::from_ref(byte))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// The contents are saved by the memory device, which is added to the system separately
+impl Snapshotable for CartridgeSram {
+    fn save_state(&mut self) -> Option<Box<dyn Any>> {
+        Some(Box::new(self.write_protect.get()))
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        if let Some(write_protect) = state.downcast_ref::<bool>() {
+            self.write_protect.set(*write_protect);
+        }
+    }
+}
+
+impl Transmutable for CartridgeSram {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn as_snapshotable(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum EepromState {
+    Standby,
+    Address,
+    Write,
+    Read,
+}
+
+/// The lines and the progress of the current transfer of an EEPROM's I2C interface
+#[derive(Copy, Clone, Debug)]
+struct I2cState {
+    scl: bool,
+    sda: bool,
+    sda_out: bool,
+    /// Whether SCL has risen since the start condition, so that the falling edge just after it isn't counted
+    clocked: bool,
+    state: EepromState,
+    cycle: u8,
+    shift: u8,
+    address: u8,
+}
+
+impl Default for I2cState {
+    fn default() -> Self {
+        Self {
+            scl: false,
+            sda: true,
+            sda_out: true,
+            clocked: false,
+            state: EepromState::Standby,
+            cycle: 0,
+            shift: 0,
+            address: 0,
+        }
+    }
+}
+
+/// An X24C01 serial EEPROM, which is accessed by toggling the I2C lines through the odd bytes of its window.
+/// The word address and the read/write bit are sent together in the first byte after the start condition, and
+/// the address counter wraps within each page of 4 bytes when writing
+pub struct CartridgeEeprom {
+    memory: Device,
+    window: usize,
+    i2c: I2cState,
+}
+
+impl CartridgeEeprom {
+    pub fn new(memory: Device, window: usize) -> Self {
+        Self {
+            memory,
+            window,
+            i2c: I2cState::default(),
+        }
+    }
+
+    fn read_byte(&mut self, clock: Instant) -> Result<u8, Error> {
+        let mut data = [0];
+        let addr = (self.i2c.address as usize % EEPROM_SIZE) as Address;
+        self.memory
+            .borrow_mut()
+            .as_addressable()
+            .unwrap()
+            .read(clock, addr, &mut data)?;
+        Ok(data[0])
+    }
+
+    fn write_byte(&mut self, clock: Instant, value: u8) -> Result<(), Error> {
+        let addr = (self.i2c.address as usize % EEPROM_SIZE) as Address;
+        self.memory
+            .borrow_mut()
+            .as_addressable()
+            .unwrap()
+            .write(clock, addr, &[value])
+    }
+
+    fn update_lines(&mut self, clock: Instant, scl: bool, sda: bool) -> Result<(), Error> {
+        if self.i2c.scl && scl {
+            // A change of SDA while SCL is high is either a start or a stop condition
+            if self.i2c.sda && !sda {
+                self.i2c.state = EepromState::Address;
+                self.i2c.cycle = 0;
+                self.i2c.shift = 0;
+                self.i2c.sda_out = true;
+                self.i2c.clocked = false;
+            } else if !self.i2c.sda && sda {
+                self.i2c.state = EepromState::Standby;
+                self.i2c.sda_out = true;
+            }
+        } else if !self.i2c.scl && scl {
+            self.i2c.clocked = true;
+            match self.i2c.state {
+                EepromState::Address | EepromState::Write if self.i2c.cycle < 8 => {
+                    self.i2c.shift = (self.i2c.shift << 1) | sda as u8;
+                },
+                // The master doesn't acknowledge the last byte it wants to read
+                EepromState::Read if self.i2c.cycle == 8 && sda => {
+                    self.i2c.state = EepromState::Standby;
+                },
+                _ => {},
+            }
+        } else if self.i2c.scl && !scl && self.i2c.clocked && self.i2c.state != EepromState::Standby {
+            self.i2c.cycle += 1;
+            if self.i2c.cycle == 8 {
+                match self.i2c.state {
+                    EepromState::Address => {
+                        self.i2c.address = self.i2c.shift >> 1;
+                        self.i2c.sda_out = false;
+                    },
+                    EepromState::Write => {
+                        self.write_byte(clock, self.i2c.shift)?;
+                        self.i2c.address = (self.i2c.address & !0x03) | (self.i2c.address.wrapping_add(1) & 0x03);
+                        self.i2c.sda_out = false;
+                    },
+                    EepromState::Read => {
+                        self.i2c.address = self.i2c.address.wrapping_add(1) % EEPROM_SIZE as u8;
+                        self.i2c.sda_out = true;
+                    },
+                    EepromState::Standby => {},
+                }
+            } else if self.i2c.cycle == 9 {
+                // The direction only changes after the acknowledge of the address, which the EEPROM drives
+                if self.i2c.state == EepromState::Address {
+                    self.i2c.state = if (self.i2c.shift & 0x01) != 0 {
+                        EepromState::Read
+                    } else {
+                        EepromState::Write
+                    };
+                }
+                self.i2c.cycle = 0;
+                self.i2c.shift = 0;
+                self.i2c.sda_out = true;
+            }
+
+            if self.i2c.state == EepromState::Read && self.i2c.cycle < 8 {
+                let value = self.read_byte(clock)?;
+                self.i2c.sda_out = (value & (0x80 >> self.i2c.cycle)) != 0;
+            }
+        }
+
+        self.i2c.scl = scl;
+        self.i2c.sda = sda;
+        Ok(())
+    }
+}
+
+impl Addressable for CartridgeEeprom {
+    fn size(&self) -> usize {
+        self.window
+    }
+
+    fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = if ((addr as usize + i) & 0x01) != 0 {
+                self.i2c.sda_out as u8
+            } else {
+                0
+            };
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        for (i, byte) in data.iter().enumerate() {
+            if ((addr as usize + i) & 0x01) != 0 {
+                self.update_lines(clock, (byte & 0x02) != 0, (byte & 0x01) != 0)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Snapshotable for CartridgeEeprom {
+    fn save_state(&mut self) -> Option<Box<dyn Any>> {
+        Some(Box::new(self.i2c))
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        if let Some(i2c) = state.downcast_ref::<I2cState>() {
+            self.i2c = *i2c;
+        }
+    }
+}
+
+impl Transmutable for CartridgeEeprom {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn as_snapshotable(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
+}
@@ -0,0 +1,121 @@
+use femtos::Instant;
+
+use moa_core::{System, Error, Address, Addressable, Inspectable, Transmutable, MemoryBlock};
+
+use crate::romdb::{RomDatabase, RomHashes, RomEntry};
+use crate::peripherals::backup::BackupMemory;
+
+
+const HEADER_OVERSEAS_NAME: std::ops::Range<usize> = 0x150..0x180;
+const HEADER_SERIAL: std::ops::Range<usize> = 0x180..0x18E;
+
+const DEV_NAME: &str = "cartridge";
+
+
+/// The cartridge ROM, along with what's known about it from the ROM database
+pub struct Cartridge {
+    rom: MemoryBlock,
+    hashes: RomHashes,
+    title: String,
+    serial: String,
+    entry: Option<RomEntry>,
+    backup: Option<BackupMemory>,
+}
+
+impl Cartridge {
+    pub fn new(data: Vec<u8>, database: &RomDatabase) -> Self {
+        let hashes = RomHashes::calculate(&data);
+        let title = header_string(&data, HEADER_OVERSEAS_NAME);
+        let serial = header_string(&data, HEADER_SERIAL);
+        let entry = database.identify(&hashes).cloned();
+        let backup = BackupMemory::from_header(&data);
+
+        match &entry {
+            Some(entry) => log::info!(target: DEV_NAME, "identified as {}", entry.name),
+            None => log::info!(target: DEV_NAME, "unknown rom {:?} with crc32 {:08x}, sha1 {}", title, hashes.crc32, hashes.sha1),
+        }
+
+        Self {
+            rom: MemoryBlock::new(data),
+            hashes,
+            title,
+            serial,
+            entry,
+            backup,
+        }
+    }
+
+    /// Returns the SRAM or EEPROM that the ROM header says is on the cartridge, if any
+    pub fn backup_memory(&self) -> Option<BackupMemory> {
+        self.backup
+    }
+
+    pub fn entry(&self) -> Option<&RomEntry> {
+        self.entry.as_ref()
+    }
+}
+
+fn header_string(data: &[u8], range: std::ops::Range<usize>) -> String {
+    data.get(range)
+        .map(|bytes| {
+            String::from_utf8_lossy(bytes)
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" ")
+        })
+        .unwrap_or_default()
+}
+
+impl Addressable for Cartridge {
+    fn size(&self) -> usize {
+        self.rom.size()
+    }
+
+    fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        // Past the end of the ROM is unconnected, which can be visible when it's mapped with backup memory
+        if addr as usize + data.len() <= self.rom.size() {
+            return self.rom.read(clock, addr, data);
+        }
+        for (i, byte) in data.iter_mut().enumerate() {
+            let addr = addr + i as Address;
+            if (addr as usize) < self.rom.size() {
+                self.rom.read(clock, addr, std::slice::from_mut(byte))?;
+            } else {
+                *byte = 0xFF;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        self.rom.write(clock, addr, data)
+    }
+}
+
+impl Inspectable for Cartridge {
+    fn inspect(&mut self, _system: &System, _args: &[&str]) -> Result<(), Error> {
+        println!("Title:  {}", self.title);
+        println!("Serial: {}", self.serial);
+        println!("Size:   {} bytes", self.hashes.size);
+        println!("CRC32:  {:08x}", self.hashes.crc32);
+        println!("SHA1:   {}", self.hashes.sha1);
+        if let Some(backup) = &self.backup {
+            println!("Backup: {:?}", backup);
+        }
+        match &self.entry {
+            Some(entry) => println!("Name:   {}", entry.name),
+            None => println!("Name:   (not in the rom database)"),
+        }
+        Ok(())
+    }
+}
+
+impl Transmutable for Cartridge {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn as_inspectable(&mut self) -> Option<&mut dyn Inspectable> {
+        Some(self)
+    }
+}
@@ -1,8 +1,9 @@
+use std::any::Any;
 use femtos::{Instant, Duration};
 
-use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable};
+use moa_core::{System, Error, Address, Addressable, Steppable, Snapshotable, Transmutable, VideoStandard, strict};
 use moa_host::{self, Host, HostError, ControllerDevice, ControllerInput, ControllerEvent, EventReceiver};
-use moa_signals::{Signal};
+use moa_signals::{Signal, EdgeSignal};
 
 const REG_VERSION: Address = 0x01;
 const REG_DATA1: Address = 0x03;
@@ -18,11 +19,16 @@ const REG_S_CTRL3: Address = 0x1F;
 
 const DEV_NAME: &str = "genesis_controller";
 
+/// The number of times TH goes low in each cycle of reading a 6-button controller, after which it starts over
+const SIX_BUTTON_CYCLE: u8 = 4;
+
 pub struct GenesisControllerPort {
     /// Data contains bits:
-    /// 11 | 10 | 9 |    8 |     7 | 6 | 5 | 4 |     3 |    2 |    1 |  0
-    ///  X |  Y | Z | MODE | START | A | C | B | RIGHT | LEFT | DOWN | UP
+    ///   11 | 10 | 9 | 8 |     7 | 6 | 5 | 4 |     3 |    2 |    1 |  0
+    /// MODE |  X | Y | Z | START | A | C | B | RIGHT | LEFT | DOWN | UP
     buttons: u16,
+    /// Whether a 6-button controller is connected, instead of a 3-button controller
+    six_button: bool,
 
     ctrl: u8,
     outputs: u8,
@@ -35,6 +41,7 @@ impl Default for GenesisControllerPort {
     fn default() -> Self {
         Self {
             buttons: 0xffff,
+            six_button: true,
             ctrl: 0,
             outputs: 0,
             th_count: 0,
@@ -44,34 +51,31 @@ impl Default for GenesisControllerPort {
 }
 
 impl GenesisControllerPort {
+    /// Returns the inputs, which depend on the state of TH, and on a 6-button controller, on the number of times
+    /// TH has gone low since the count was last reset.  The third time TH is low, the directions read as 0 to
+    /// identify the controller, then the extra buttons are read while TH is high, and then they read as 1
     pub fn get_data(&mut self) -> u8 {
         let inputs = self.buttons;
         let th_state = (self.outputs & 0x40) != 0;
-
-        match (th_state, self.th_count) {
-            (true, 0) => self.outputs | ((inputs & 0x003F) as u8),
-            (false, 1) => self.outputs | (((inputs & 0x00C0) >> 2) as u8) | ((inputs & 0x0003) as u8),
-            (true, 1) => self.outputs | ((inputs & 0x003F) as u8),
-            (false, 2) => self.outputs | (((inputs & 0x00C0) >> 2) as u8),
-            (true, 2) => self.outputs | ((inputs & 0x0030) as u8) | (((inputs & 0x0F00) >> 8) as u8),
-            (false, 3) => self.outputs | (((inputs & 0x00C0) >> 2) as u8) | 0x0F,
-            (true, 3) => self.outputs | ((inputs & 0x003F) as u8),
-            (false, 0) => self.outputs | (((inputs & 0x00C0) >> 2) as u8) | ((inputs & 0x0003) as u8),
-            _ => 0,
-        }
+        let count = if self.six_button { self.th_count } else { 0 };
+
+        let data = match (th_state, count) {
+            (true, 3) => (inputs & 0x0030) as u8 | ((inputs & 0x0F00) >> 8) as u8,
+            (true, _) => (inputs & 0x003F) as u8,
+            (false, 3) => ((inputs & 0x00C0) >> 2) as u8,
+            (false, 4) => ((inputs & 0x00C0) >> 2) as u8 | 0x0F,
+            (false, _) => ((inputs & 0x00C0) >> 2) as u8 | (inputs & 0x0003) as u8,
+        };
+        self.outputs | data
     }
 
     pub fn set_data(&mut self, outputs: u8) {
         let prev_th = self.outputs & 0x40;
         self.outputs = outputs;
 
-        if ((outputs & 0x40) ^ prev_th) != 0 && (outputs & 0x40) == 0 {
-            // TH bit was toggled
-
-            self.th_count += 1;
-            if self.th_count > 3 {
-                self.th_count = 0;
-            }
+        if prev_th != 0 && (outputs & 0x40) == 0 {
+            // TH went low, which advances the 6-button controller to the next part of its cycle
+            self.th_count = (self.th_count % SIX_BUTTON_CYCLE) + 1;
         }
     }
 
@@ -83,6 +87,25 @@ impl GenesisControllerPort {
     pub fn reset_count(&mut self) {
         self.th_count = 0;
     }
+
+    /// Connect a 3-button controller instead of a 6-button controller, for software that doesn't work with
+    /// the 6-button controller's extra cycles
+    pub fn set_six_button(&mut self, six_button: bool) {
+        self.six_button = six_button;
+    }
+
+    /// Returns the registers of the port, but not the buttons, which follow the host's inputs rather than the
+    /// machine's state, so that going back to a saved state doesn't lose the inputs received since
+    fn registers(&self) -> [u8; 4] {
+        [self.ctrl, self.outputs, self.th_count, self.s_ctrl]
+    }
+
+    fn set_registers(&mut self, [ctrl, outputs, th_count, s_ctrl]: [u8; 4]) {
+        self.ctrl = ctrl;
+        self.outputs = outputs;
+        self.th_count = th_count;
+        self.s_ctrl = s_ctrl;
+    }
 }
 
 
@@ -92,7 +115,9 @@ pub struct GenesisControllers {
     port_2: GenesisControllerPort,
     expansion: GenesisControllerPort,
     interrupt: Signal<bool>,
+    vblank: Option<EdgeSignal>,
     reset_timer: Duration,
+    video_standard: VideoStandard,
 }
 
 impl GenesisControllers {
@@ -109,7 +134,9 @@ impl GenesisControllers {
             port_2: GenesisControllerPort::default(),
             expansion: GenesisControllerPort::default(),
             interrupt: Signal::new(false),
+            vblank: None,
             reset_timer: Duration::ZERO,
+            video_standard: VideoStandard::Ntsc,
         })
     }
 
@@ -117,6 +144,22 @@ impl GenesisControllers {
         self.interrupt.clone()
     }
 
+    /// Only process the input events from the host when the given vblank signal is raised, instead of on every step
+    pub fn poll_at_vblank(&mut self, vblank: EdgeSignal) {
+        self.vblank = Some(vblank);
+    }
+
+    /// Report the given video standard in the version register, which software checks to adjust its timing
+    pub fn set_video_standard(&mut self, standard: VideoStandard) {
+        self.video_standard = standard;
+    }
+
+    /// Connect 3-button controllers to both ports instead of 6-button controllers
+    pub fn use_three_button(&mut self) {
+        self.port_1.set_six_button(false);
+        self.port_2.set_six_button(false);
+    }
+
     fn process_event(&mut self, event: ControllerEvent) {
         let (mask, state) = match event.input {
             ControllerInput::ButtonA(state) => (0x0040, state),
@@ -127,7 +170,10 @@ impl GenesisControllers {
             ControllerInput::DpadLeft(state) => (0x0004, state),
             ControllerInput::DpadRight(state) => (0x0008, state),
             ControllerInput::Start(state) => (0x0080, state),
-            ControllerInput::Mode(state) => (0x0100, state),
+            ControllerInput::ButtonZ(state) => (0x0100, state),
+            ControllerInput::ButtonY(state) => (0x0200, state),
+            ControllerInput::ButtonX(state) => (0x0400, state),
+            ControllerInput::Mode(state) => (0x0800, state),
             _ => (0x0000, false),
         };
 
@@ -159,8 +205,12 @@ impl Addressable for GenesisControllers {
 
         match addr {
             REG_VERSION => {
-                data[i] = 0xA0;
-            }, // Overseas Version, NTSC, No Expansion
+                // Overseas Version, No Expansion, and the PAL bit
+                data[i] = match self.video_standard {
+                    VideoStandard::Ntsc => 0xA0,
+                    VideoStandard::Pal => 0xE0,
+                };
+            },
             REG_DATA1 => {
                 data[i] = self.port_1.get_data();
             },
@@ -189,17 +239,17 @@ impl Addressable for GenesisControllers {
                 data[i] = self.expansion.s_ctrl | 0x02;
             },
             _ => {
-                log::warn!("{}: !!! unhandled reading from {:0x}", DEV_NAME, addr);
+                strict::unhandled_read(DEV_NAME, addr);
             },
         }
-        log::info!("{}: read from register {:x} the value {:x}", DEV_NAME, addr, data[0]);
+        log::info!(target: DEV_NAME, "read from register {:x} the value {:x}", addr, data[0]);
         Ok(())
     }
 
     fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
         self.reset_timer = Duration::ZERO;
 
-        log::info!("{}: write to register {:x} with {:x}", DEV_NAME, addr, data[0]);
+        log::info!(target: DEV_NAME, "write to register {:x} with {:x}", addr, data[0]);
         match addr {
             REG_DATA1 => {
                 self.port_1.set_data(data[0]);
@@ -229,7 +279,7 @@ impl Addressable for GenesisControllers {
                 self.expansion.s_ctrl = data[0] & 0xF8;
             },
             _ => {
-                log::warn!("{}: !!! unhandled write of {:0x} to {:0x}", DEV_NAME, data[0], addr);
+                strict::unhandled_write(DEV_NAME, addr, data);
             },
         }
         Ok(())
@@ -240,8 +290,11 @@ impl Steppable for GenesisControllers {
     fn step(&mut self, _system: &System) -> Result<Duration, Error> {
         let duration = Duration::from_micros(100); // Update every 100us
 
-        while let Some(event) = self.receiver.receive() {
-            self.process_event(event);
+        let poll = self.vblank.as_mut().map(|vblank| vblank.get()).unwrap_or(true);
+        if poll {
+            while let Some(event) = self.receiver.receive() {
+                self.process_event(event);
+            }
         }
 
         self.reset_timer += duration;
@@ -254,6 +307,22 @@ impl Steppable for GenesisControllers {
     }
 }
 
+impl Snapshotable for GenesisControllers {
+    fn save_state(&mut self) -> Option<Box<dyn Any>> {
+        let ports = [self.port_1.registers(), self.port_2.registers(), self.expansion.registers()];
+        Some(Box::new((ports, self.reset_timer)))
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        if let Some(([port_1, port_2, expansion], reset_timer)) = state.downcast_ref::<([[u8; 4]; 3], Duration)>() {
+            self.port_1.set_registers(*port_1);
+            self.port_2.set_registers(*port_2);
+            self.expansion.set_registers(*expansion);
+            self.reset_timer = *reset_timer;
+        }
+    }
+}
+
 impl Transmutable for GenesisControllers {
     fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
         Some(self)
@@ -262,4 +331,8 @@ impl Transmutable for GenesisControllers {
     fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
         Some(self)
     }
+
+    fn as_snapshotable(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
 }
@@ -1,139 +1,154 @@
-use std::rc::Rc;
-use std::cell::{Cell, RefCell};
+use std::any::Any;
 use femtos::Instant;
 
-use moa_core::{Bus, Error, Address, Addressable, Transmutable};
+use moa_core::{Device, Error, Address, Addressable, Snapshotable, Transmutable, strict};
 use moa_signals::Signal;
 
 const DEV_NAME: &str = "coprocessor";
 
-pub struct CoprocessorCoordinator {
-    bus_request: Signal<bool>,
-    reset: Signal<bool>,
+/// The signals between the 68000 and the Z80, which the 68000 uses to request the Z80's bus, so that it can access
+/// the Z80's RAM and the YM2612, and to hold the Z80 in reset
+#[derive(Clone, Default)]
+pub struct CoprocessorSignals {
+    pub reset: Signal<bool>,
+    pub bus_request: Signal<bool>,
+    pub bus_ack: Signal<bool>,
 }
 
+impl CoprocessorSignals {
+    /// Returns true if the 68000 can access the Z80's bus, either because it was granted, or because the Z80 is held in
+    /// reset and isn't using it
+    pub fn has_bus(&self) -> bool {
+        self.bus_ack.get() || self.reset.get()
+    }
+}
+
+/// The bus request and reset registers at 0xA11100 and 0xA11200
+///
+/// The grant isn't reported until the Z80 has finished its current instruction, and it's never reported while the Z80
+/// is held in reset, so software has to release the reset before it can see the grant
+pub struct CoprocessorCoordinator {
+    signals: CoprocessorSignals,
+}
 
 impl CoprocessorCoordinator {
-    pub fn new(reset: Signal<bool>, bus_request: Signal<bool>) -> Self {
+    pub fn new(signals: CoprocessorSignals) -> Self {
         Self {
-            bus_request,
-            reset,
+            signals,
         }
     }
 }
 
 impl Addressable for CoprocessorCoordinator {
+    // Only the page at 0xA11000 is decoded, so that the cartridge and Sega CD registers above it can be mapped
     fn size(&self) -> usize {
-        0x4000
+        0x1000
     }
 
     fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
         match addr {
             0x100 => {
-                data[0] = if self.bus_request.get() && self.reset.get() {
-                    0x01
-                } else {
-                    0x00
-                };
+                // The bit is clear while the bus is granted to the 68000
+                data[0] = if self.signals.bus_ack.get() { 0x00 } else { 0x01 };
             },
             _ => {
-                log::warn!("{}: !!! unhandled read from {:0x}", DEV_NAME, addr);
+                strict::unhandled_read(DEV_NAME, addr);
             },
         }
-        log::info!("{}: read from register {:x} of {:?}", DEV_NAME, addr, data);
+        log::info!(target: DEV_NAME, "read from register {:x} of {:?}", addr, data);
         Ok(())
     }
 
     fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
-        log::info!("{}: write to register {:x} with {:x}", DEV_NAME, addr, data[0]);
+        log::info!(target: DEV_NAME, "write to register {:x} with {:x}", addr, data[0]);
         match addr {
             0x000 => { /* ROM vs DRAM mode */ },
             0x100 => {
-                self.bus_request.set(data[0] != 0);
+                self.signals.bus_request.set(data[0] != 0);
             },
             0x200 => {
-                self.reset.set(data[0] == 0);
+                self.signals.reset.set(data[0] == 0);
             },
             _ => {
-                log::warn!("{}: !!! unhandled write {:0x} to {:0x}", DEV_NAME, data[0], addr);
+                strict::unhandled_write(DEV_NAME, addr, data);
             },
         }
         Ok(())
     }
 }
 
-impl Transmutable for CoprocessorCoordinator {
-    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
-        Some(self)
-    }
-}
-
-
-type CoprocessorRegister = Rc<Cell<Address>>;
-
-pub struct CoprocessorBankRegister {
-    base: CoprocessorRegister,
-}
-
-impl Addressable for CoprocessorBankRegister {
-    fn size(&self) -> usize {
-        0x01
+// The Z80 only saves its handles to the signals, so their levels are saved here
+impl Snapshotable for CoprocessorCoordinator {
+    fn save_state(&mut self) -> Option<Box<dyn Any>> {
+        let signals = [self.signals.reset.get(), self.signals.bus_request.get(), self.signals.bus_ack.get()];
+        Some(Box::new(signals))
     }
 
-    fn read(&mut self, _clock: Instant, _addr: Address, _data: &mut [u8]) -> Result<(), Error> {
-        Ok(())
-    }
-
-    fn write(&mut self, _clock: Instant, _addr: Address, data: &[u8]) -> Result<(), Error> {
-        let value = ((self.base.get() >> 1) | ((data[0] as Address) << 23)) & 0xFF8000;
-        //let value = ((self.base.get() << 1) | ((data[0] as Address) << 15)) & 0xFF8000;
-        println!("New base is {:x}", value);
-        self.base.set(value);
-        Ok(())
+    fn restore_state(&mut self, state: &dyn Any) {
+        if let Some([reset, bus_request, bus_ack]) = state.downcast_ref::<[bool; 3]>() {
+            self.signals.reset.set(*reset);
+            self.signals.bus_request.set(*bus_request);
+            self.signals.bus_ack.set(*bus_ack);
+        }
     }
 }
 
-impl Transmutable for CoprocessorBankRegister {
+impl Transmutable for CoprocessorCoordinator {
     fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
         Some(self)
     }
+
+    fn as_snapshotable(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
 }
 
 
-pub struct CoprocessorBankArea {
-    base: CoprocessorRegister,
-    bus: Rc<RefCell<Bus>>,
+/// The 68000's view of a device on the Z80's bus, which should only be accessed while the 68000 has the bus.  The
+/// access is still made if it doesn't, since software that gets away with it on hardware relies on the result
+pub struct CoprocessorBusGate {
+    device: Device,
+    size: usize,
+    signals: CoprocessorSignals,
 }
 
-impl CoprocessorBankArea {
-    pub fn new(bus: Rc<RefCell<Bus>>) -> (Self, CoprocessorBankRegister) {
-        let base = Rc::new(Cell::new(0));
-        let register = CoprocessorBankRegister {
-            base: base.clone(),
-        };
-        let bank = Self {
-            base,
-            bus,
-        };
-        (bank, register)
+impl CoprocessorBusGate {
+    pub fn new(device: Device, size: usize, signals: CoprocessorSignals) -> Self {
+        Self {
+            device,
+            size,
+            signals,
+        }
+    }
+
+    fn check_bus(&self, addr: Address) {
+        if !self.signals.has_bus() {
+            log::debug!(target: DEV_NAME, "accessed z80 bus at {:x} without the bus being granted", addr);
+        }
     }
 }
 
-impl Addressable for CoprocessorBankArea {
+impl Addressable for CoprocessorBusGate {
     fn size(&self) -> usize {
-        0x8000
+        self.size
     }
 
     fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
-        self.bus.borrow_mut().read(clock, self.base.get() + addr, data)
+        self.check_bus(addr);
+        self.device.borrow_mut().as_addressable().unwrap().read(clock, addr, data)
     }
 
     fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
-        self.bus.borrow_mut().write(clock, self.base.get() + addr, data)
+        self.check_bus(addr);
+        self.device.borrow_mut().as_addressable().unwrap().write(clock, addr, data)
+    }
+
+    fn is_memory(&self) -> bool {
+        self.device.borrow_mut().as_addressable().unwrap().is_memory()
     }
 }
 
-impl Transmutable for CoprocessorBankArea {
+impl Transmutable for CoprocessorBusGate {
     fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
         Some(self)
     }
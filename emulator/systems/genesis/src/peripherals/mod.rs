@@ -1,3 +1,6 @@
+pub mod backup;
+pub mod cartridge;
 pub mod controllers;
 pub mod coprocessor;
+pub mod segacd;
 pub mod ym7101;
@@ -0,0 +1,265 @@
+use super::cdrom::RAW_SECTOR_SIZE;
+
+const BUFFER_SIZE: usize = 0x4000;
+
+/// The offset of the header in a raw sector, after the sync pattern
+const HEADER_OFFSET: usize = 12;
+
+#[rustfmt::skip]
+mod reg {
+    pub(super) const IFSTAT: u8             = 0x1;
+    pub(super) const DBCL: u8               = 0x2;
+    pub(super) const DBCH: u8               = 0x3;
+    pub(super) const HEAD0: u8              = 0x4;
+    pub(super) const HEAD3: u8              = 0x7;
+    pub(super) const PTL: u8                = 0x8;
+    pub(super) const PTH: u8                = 0x9;
+    pub(super) const WAL: u8                = 0xA;
+    pub(super) const WAH: u8                = 0xB;
+    pub(super) const STAT0: u8              = 0xC;
+    pub(super) const STAT3: u8              = 0xF;
+
+    // The write registers, which are at the same addresses as the read registers
+    pub(super) const SBOUT: u8              = 0x0;
+    pub(super) const IFCTRL: u8             = 0x1;
+    pub(super) const DACL: u8               = 0x4;
+    pub(super) const DACH: u8               = 0x5;
+    pub(super) const DTTRG: u8              = 0x6;
+    pub(super) const DTACK: u8              = 0x7;
+    pub(super) const WAL_WRITE: u8          = 0x8;
+    pub(super) const WAH_WRITE: u8          = 0x9;
+    pub(super) const CTRL0: u8              = 0xA;
+    pub(super) const CTRL1: u8              = 0xB;
+    pub(super) const PTL_WRITE: u8          = 0xC;
+    pub(super) const PTH_WRITE: u8          = 0xD;
+    pub(super) const RESET: u8              = 0xF;
+}
+
+// The bits of IFSTAT, which are all active low
+const IFSTAT_DTEI: u8 = 0x40;
+const IFSTAT_DECI: u8 = 0x20;
+const IFSTAT_DTBSY: u8 = 0x08;
+const IFSTAT_DTEN: u8 = 0x02;
+
+const IFCTRL_DTEIEN: u8 = 0x40;
+const IFCTRL_DECIEN: u8 = 0x20;
+const IFCTRL_DOUTEN: u8 = 0x02;
+
+const CTRL0_DECEN: u8 = 0x80;
+const CTRL0_WRRQ: u8 = 0x04;
+
+const STAT0_CRCOK: u8 = 0x80;
+
+/// The destination of a data transfer out of the CDC's buffer
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CdcDestination {
+    MainCpu,
+    SubCpu,
+    Pcm,
+    PrgRam,
+    WordRam,
+    None,
+}
+
+impl CdcDestination {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0x07 {
+            2 => CdcDestination::MainCpu,
+            3 => CdcDestination::SubCpu,
+            4 => CdcDestination::Pcm,
+            5 => CdcDestination::PrgRam,
+            7 => CdcDestination::WordRam,
+            _ => CdcDestination::None,
+        }
+    }
+}
+
+/// The LC8951 CD-ROM decoder, which stores the sectors from the drive in its buffer, and then transfers the data
+/// to one of the cpus, or by DMA to the PRG-RAM, Word RAM, or PCM RAM.  The sub cpu accesses its registers
+/// indirectly through an address register that increments after each access
+///
+/// The error correction is not emulated, since the images already contain the corrected data
+pub struct Cdc {
+    address: u8,
+    ifstat: u8,
+    ifctrl: u8,
+    ctrl0: u8,
+    dbc: u16,
+    dac: u16,
+    wa: u16,
+    pt: u16,
+    head: [u8; 4],
+    stat: [u8; 4],
+    buffer: Vec<u8>,
+
+    pub destination: CdcDestination,
+    /// The address register used for DMA transfers, in units of 8 bytes
+    pub dma_address: u16,
+    /// The data set ready flag, which is set while there is data for a cpu to read
+    pub data_ready: bool,
+    /// The end of data transfer flag
+    pub transfer_ended: bool,
+}
+
+impl Default for Cdc {
+    fn default() -> Self {
+        Self {
+            address: 0,
+            ifstat: 0xFF,
+            ifctrl: 0,
+            ctrl0: 0,
+            dbc: 0,
+            dac: 0,
+            wa: 0,
+            pt: 0,
+            head: [0; 4],
+            stat: [0; 4],
+            buffer: vec![0; BUFFER_SIZE],
+            destination: CdcDestination::None,
+            dma_address: 0,
+            data_ready: false,
+            transfer_ended: false,
+        }
+    }
+}
+
+impl Cdc {
+    pub fn interrupt(&self) -> bool {
+        ((self.ifctrl & IFCTRL_DECIEN) != 0 && (self.ifstat & IFSTAT_DECI) == 0)
+            || ((self.ifctrl & IFCTRL_DTEIEN) != 0 && (self.ifstat & IFSTAT_DTEI) == 0)
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    pub fn set_address(&mut self, address: u8) {
+        self.address = address & 0x0F;
+    }
+
+    pub fn read_register(&mut self) -> u8 {
+        let value = match self.address {
+            reg::IFSTAT => self.ifstat,
+            reg::DBCL => self.dbc as u8,
+            reg::DBCH => (self.dbc >> 8) as u8,
+            reg::HEAD0..=reg::HEAD3 => self.head[(self.address - reg::HEAD0) as usize],
+            reg::PTL => self.pt as u8,
+            reg::PTH => (self.pt >> 8) as u8,
+            reg::WAL => self.wa as u8,
+            reg::WAH => (self.wa >> 8) as u8,
+            reg::STAT0..=reg::STAT3 => {
+                let value = self.stat[(self.address - reg::STAT0) as usize];
+                if self.address == reg::STAT3 {
+                    // Reading the last status register clears the decoder interrupt
+                    self.ifstat |= IFSTAT_DECI;
+                }
+                value
+            },
+            _ => 0,
+        };
+        self.address = (self.address + 1) & 0x0F;
+        value
+    }
+
+    pub fn write_register(&mut self, value: u8) {
+        match self.address {
+            reg::SBOUT => {},
+            reg::IFCTRL => {
+                self.ifctrl = value;
+                if (value & IFCTRL_DOUTEN) == 0 {
+                    self.stop_transfer();
+                }
+            },
+            reg::DBCL => self.dbc = (self.dbc & 0xFF00) | value as u16,
+            reg::DBCH => self.dbc = (self.dbc & 0x00FF) | ((value as u16 & 0x0F) << 8),
+            reg::DACL => self.dac = (self.dac & 0xFF00) | value as u16,
+            reg::DACH => self.dac = (self.dac & 0x00FF) | ((value as u16) << 8),
+            reg::DTTRG => self.start_transfer(),
+            reg::DTACK => self.ifstat |= IFSTAT_DTEI,
+            reg::WAL_WRITE => self.wa = (self.wa & 0xFF00) | value as u16,
+            reg::WAH_WRITE => self.wa = (self.wa & 0x00FF) | ((value as u16) << 8),
+            reg::CTRL0 => self.ctrl0 = value,
+            reg::CTRL1 => {},
+            reg::PTL_WRITE => self.pt = (self.pt & 0xFF00) | value as u16,
+            reg::PTH_WRITE => self.pt = (self.pt & 0x00FF) | ((value as u16) << 8),
+            reg::RESET => self.reset(),
+            _ => {},
+        }
+        self.address = (self.address + 1) & 0x0F;
+    }
+
+    fn reset(&mut self) {
+        self.ifstat = 0xFF;
+        self.ifctrl = 0;
+        self.ctrl0 = 0;
+        self.stat = [0; 4];
+        self.data_ready = false;
+        self.transfer_ended = false;
+    }
+
+    /// Decode a sector read by the drive, which is written to the buffer if enabled
+    pub fn decode(&mut self, sector: &[u8; RAW_SECTOR_SIZE]) {
+        if (self.ctrl0 & CTRL0_DECEN) == 0 {
+            return;
+        }
+
+        self.head.copy_from_slice(&sector[HEADER_OFFSET..HEADER_OFFSET + 4]);
+        self.stat = [STAT0_CRCOK, 0, 0, 0];
+
+        if (self.ctrl0 & CTRL0_WRRQ) != 0 {
+            // The pointer is set to the header of the new sector, and the write address moves to after it
+            self.pt = self.wa;
+            for (i, byte) in sector[HEADER_OFFSET..].iter().enumerate() {
+                self.buffer[(self.pt as usize + i) % BUFFER_SIZE] = *byte;
+            }
+            self.wa = self.wa.wrapping_add((RAW_SECTOR_SIZE - HEADER_OFFSET) as u16);
+        }
+
+        self.ifstat &= !IFSTAT_DECI;
+    }
+
+    fn start_transfer(&mut self) {
+        if (self.ifctrl & IFCTRL_DOUTEN) == 0 {
+            return;
+        }
+        self.ifstat &= !(IFSTAT_DTBSY | IFSTAT_DTEN);
+        self.transfer_ended = false;
+        self.data_ready = matches!(self.destination, CdcDestination::MainCpu | CdcDestination::SubCpu);
+    }
+
+    fn stop_transfer(&mut self) {
+        self.ifstat |= IFSTAT_DTBSY | IFSTAT_DTEN;
+        self.data_ready = false;
+    }
+
+    pub fn is_transferring(&self) -> bool {
+        (self.ifstat & IFSTAT_DTBSY) == 0
+    }
+
+    /// Transfer the next word out of the buffer, ending the transfer if it was the last one
+    pub fn transfer_word(&mut self) -> u16 {
+        if !self.is_transferring() {
+            return 0;
+        }
+
+        let data = ((self.buffer[self.dac as usize % BUFFER_SIZE] as u16) << 8)
+            | self.buffer[(self.dac as usize + 1) % BUFFER_SIZE] as u16;
+        self.dac = self.dac.wrapping_add(2);
+
+        if self.dbc <= 1 {
+            self.dbc = 0x0FFF;
+            self.stop_transfer();
+            self.transfer_ended = true;
+            self.ifstat &= !IFSTAT_DTEI;
+        } else {
+            self.dbc -= 2;
+        }
+        data
+    }
+
+    pub fn set_mode(&mut self, bits: u8) {
+        self.destination = CdcDestination::from_bits(bits);
+        self.transfer_ended = false;
+        self.data_ready = false;
+    }
+}
@@ -0,0 +1,242 @@
+use moa_core::Error;
+
+use super::cdrom::{CdImage, RAW_SECTOR_SIZE, PREGAP_SECTORS, lba_to_msf, msf_to_lba};
+
+#[rustfmt::skip]
+mod cmd {
+    pub(super) const NOP: u8                = 0x0;
+    pub(super) const STOP: u8               = 0x1;
+    pub(super) const REPORT: u8             = 0x2;
+    pub(super) const READ: u8               = 0x3;
+    pub(super) const SEEK: u8               = 0x4;
+    pub(super) const PAUSE: u8              = 0x6;
+    pub(super) const PLAY: u8               = 0x7;
+    pub(super) const FAST_FORWARD: u8       = 0x8;
+    pub(super) const REWIND: u8             = 0x9;
+    pub(super) const CLOSE_TRAY: u8         = 0xC;
+    pub(super) const OPEN_TRAY: u8          = 0xD;
+}
+
+#[rustfmt::skip]
+mod report {
+    pub(super) const ABSOLUTE_TIME: u8      = 0x0;
+    pub(super) const RELATIVE_TIME: u8      = 0x1;
+    pub(super) const TRACK_NUMBER: u8       = 0x2;
+    pub(super) const LEAD_OUT: u8           = 0x3;
+    pub(super) const TRACK_RANGE: u8        = 0x4;
+    pub(super) const TRACK_START: u8        = 0x5;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum DriveState {
+    Stopped = 0x0,
+    Playing = 0x1,
+    Seeking = 0x2,
+    Paused = 0x4,
+    TrayOpen = 0x5,
+    NoDisc = 0xB,
+    Ended = 0xC,
+}
+
+/// The CD drive, which the sub cpu controls by writing 10 nibble command packets and reading back 10 nibble
+/// status packets, each with a checksum in the last nibble.  The packets are exchanged at the 75Hz sector rate
+/// while the host clock is enabled, which is also when the drive reads the next sector
+///
+/// Audio tracks are played by moving through the sectors at the normal rate, but the audio isn't output
+pub struct CdDrive {
+    disc: Option<CdImage>,
+    state: DriveState,
+    /// The current position of the read head, which is negative in the pregap before the first track
+    lba: i32,
+    /// Whether sectors should be passed to the CDC when read, which is false when only playing audio
+    reading: bool,
+    report: u8,
+    report_track: u8,
+    command: [u8; 10],
+    command_pending: bool,
+    status: [u8; 10],
+}
+
+impl CdDrive {
+    pub fn new(disc: Option<CdImage>) -> Self {
+        let state = if disc.is_some() {
+            DriveState::Stopped
+        } else {
+            DriveState::NoDisc
+        };
+        let mut drive = Self {
+            disc,
+            state,
+            lba: -(PREGAP_SECTORS as i32),
+            reading: false,
+            report: report::ABSOLUTE_TIME,
+            report_track: 0,
+            command: [0; 10],
+            command_pending: false,
+            status: [0; 10],
+        };
+        drive.update_status();
+        drive
+    }
+
+    pub fn read_status(&self, index: usize) -> u8 {
+        self.status[index]
+    }
+
+    pub fn read_command(&self, index: usize) -> u8 {
+        self.command[index]
+    }
+
+    /// Write a nibble of the command packet, which is sent to the drive when the last nibble is written
+    pub fn write_command(&mut self, index: usize, value: u8) {
+        self.command[index] = value & 0x0F;
+        if index == self.command.len() - 1 {
+            self.command_pending = true;
+        }
+    }
+
+    /// Process the pending command and move to the next sector, returning the sector if it should be decoded by
+    /// the CDC.  This is called 75 times a second while the host clock is enabled
+    pub fn update(&mut self) -> Result<Option<[u8; RAW_SECTOR_SIZE]>, Error> {
+        if self.command_pending {
+            self.command_pending = false;
+            self.process_command();
+        }
+
+        let mut sector = None;
+        match self.state {
+            DriveState::Seeking => self.state = DriveState::Paused,
+            DriveState::Playing => {
+                if self.lba >= 0 {
+                    let disc = self.disc.as_mut().unwrap();
+                    if self.lba as u32 >= disc.lead_out() {
+                        self.state = DriveState::Ended;
+                    } else if self.reading && !disc.track_at(self.lba as u32).map(|track| track.is_audio()).unwrap_or(true) {
+                        sector = disc.read_sector(self.lba as u32)?;
+                    }
+                }
+                self.lba += 1;
+            },
+            _ => {},
+        }
+
+        self.update_status();
+        Ok(sector)
+    }
+
+    fn process_command(&mut self) {
+        if !self.is_checksum_valid() {
+            log::warn!("cdd: invalid checksum in command {:?}", self.command);
+            return;
+        }
+
+        match self.command[0] {
+            cmd::NOP => {},
+            cmd::STOP => {
+                self.state = self.state_with_disc(DriveState::Stopped);
+                self.lba = -(PREGAP_SECTORS as i32);
+            },
+            cmd::REPORT => {
+                self.report = self.command[3];
+                self.report_track = self.command[4] * 10 + self.command[5];
+            },
+            cmd::READ | cmd::SEEK => {
+                let lba = msf_to_lba(
+                    (self.command[2] * 10 + self.command[3]) as u32,
+                    (self.command[4] * 10 + self.command[5]) as u32,
+                    (self.command[6] * 10 + self.command[7]) as u32,
+                );
+                self.lba = lba as i32 - PREGAP_SECTORS as i32;
+                self.reading = self.command[0] == cmd::READ;
+                let state = if self.reading {
+                    DriveState::Playing
+                } else {
+                    DriveState::Seeking
+                };
+                self.state = self.state_with_disc(state);
+            },
+            cmd::PAUSE => self.state = self.state_with_disc(DriveState::Paused),
+            cmd::PLAY | cmd::FAST_FORWARD | cmd::REWIND => {
+                self.reading = true;
+                self.state = self.state_with_disc(DriveState::Playing);
+            },
+            cmd::CLOSE_TRAY => self.state = self.state_with_disc(DriveState::Stopped),
+            cmd::OPEN_TRAY => self.state = DriveState::TrayOpen,
+            command => log::warn!("cdd: unsupported command {:x}", command),
+        }
+    }
+
+    fn state_with_disc(&self, state: DriveState) -> DriveState {
+        if self.disc.is_some() { state } else { DriveState::NoDisc }
+    }
+
+    fn is_checksum_valid(&self) -> bool {
+        checksum(&self.command[..9]) == self.command[9]
+    }
+
+    fn update_status(&mut self) {
+        let mut data = [0; 7];
+        if let Some(disc) = self.disc.as_ref() {
+            let lba = self.lba.max(0) as u32;
+            let track = disc.track_at(lba).or_else(|| disc.tracks().last());
+            let is_data = track.map(|track| !track.is_audio()).unwrap_or(false);
+
+            match self.report {
+                report::ABSOLUTE_TIME => {
+                    write_msf(&mut data, (self.lba + PREGAP_SECTORS as i32).max(0) as u32);
+                    data[6] = if is_data { 0x4 } else { 0x0 };
+                },
+                report::RELATIVE_TIME => {
+                    let start = track.map(|track| track.start).unwrap_or(0);
+                    write_msf(&mut data, lba.saturating_sub(start));
+                    data[6] = if is_data { 0x4 } else { 0x0 };
+                },
+                report::TRACK_NUMBER => {
+                    let number = track.map(|track| track.number).unwrap_or(0);
+                    data[0] = number / 10;
+                    data[1] = number % 10;
+                },
+                report::LEAD_OUT => write_msf(&mut data, disc.lead_out() + PREGAP_SECTORS),
+                report::TRACK_RANGE => {
+                    let first = disc.tracks().first().map(|track| track.number).unwrap_or(0);
+                    let last = disc.tracks().last().map(|track| track.number).unwrap_or(0);
+                    data[0] = first / 10;
+                    data[1] = first % 10;
+                    data[2] = last / 10;
+                    data[3] = last % 10;
+                },
+                report::TRACK_START => {
+                    if let Some(track) = disc.track(self.report_track) {
+                        write_msf(&mut data, track.start + PREGAP_SECTORS);
+                        // The data flag is in the upper bit of the frame tens
+                        if !track.is_audio() {
+                            data[4] |= 0x8;
+                        }
+                        data[6] = track.number % 10;
+                    }
+                },
+                kind => log::warn!("cdd: unsupported report type {:x}", kind),
+            }
+        }
+
+        self.status[0] = self.state as u8;
+        self.status[1] = self.report;
+        self.status[2..9].copy_from_slice(&data);
+        self.status[9] = checksum(&self.status[..9]);
+    }
+}
+
+fn write_msf(data: &mut [u8], lba: u32) {
+    let (minutes, seconds, frames) = lba_to_msf(lba);
+    data[0] = (minutes / 10) as u8;
+    data[1] = (minutes % 10) as u8;
+    data[2] = (seconds / 10) as u8;
+    data[3] = (seconds % 10) as u8;
+    data[4] = (frames / 10) as u8;
+    data[5] = (frames % 10) as u8;
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    let sum = data.iter().fold(0u8, |sum, value| sum.wrapping_add(*value));
+    !sum & 0x0F
+}
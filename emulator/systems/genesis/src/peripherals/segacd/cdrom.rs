@@ -0,0 +1,271 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use moa_core::Error;
+
+/// The size of a raw sector, with the sync, header, and error correction codes
+pub const RAW_SECTOR_SIZE: usize = 2352;
+/// The size of the user data in a mode 1 sector
+pub const DATA_SECTOR_SIZE: usize = 2048;
+/// The number of sectors read per second at single speed
+pub const SECTORS_PER_SECOND: u32 = 75;
+/// The two second pregap before the first track, which is included in the absolute times on the disc
+pub const PREGAP_SECTORS: u32 = 150;
+
+const SYNC_PATTERN: [u8; 12] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CdTrackKind {
+    /// Mode 1 data stored as raw 2352 byte sectors
+    Mode1Raw,
+    /// Mode 1 data stored as only the 2048 bytes of user data per sector
+    Mode1,
+    /// Red book audio, stored as raw 2352 byte sectors
+    Audio,
+}
+
+impl CdTrackKind {
+    fn sector_size(self) -> usize {
+        match self {
+            CdTrackKind::Mode1 => DATA_SECTOR_SIZE,
+            CdTrackKind::Mode1Raw | CdTrackKind::Audio => RAW_SECTOR_SIZE,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CdTrack {
+    pub number: u8,
+    pub kind: CdTrackKind,
+    /// The logical block address of the start of the track (index 01), not including the pregap
+    pub start: u32,
+    pub length: u32,
+    file: usize,
+    file_offset: u64,
+}
+
+impl CdTrack {
+    pub fn is_audio(&self) -> bool {
+        self.kind == CdTrackKind::Audio
+    }
+
+    pub fn contains(&self, lba: u32) -> bool {
+        lba >= self.start && lba < self.start + self.length
+    }
+}
+
+/// A CDROM image, which can either be an .iso file with a single data track, or a .cue sheet with one or more
+/// .bin files that contain the raw sectors of the tracks
+pub struct CdImage {
+    files: Vec<File>,
+    tracks: Vec<CdTrack>,
+    sectors: u32,
+}
+
+impl CdImage {
+    pub fn load(filename: &str) -> Result<Self, Error> {
+        let lowercase = filename.to_lowercase();
+        if lowercase.ends_with(".cue") {
+            Self::from_cue(filename)
+        } else if lowercase.ends_with(".iso") {
+            Self::from_single_file(filename, CdTrackKind::Mode1)
+        } else {
+            Self::from_single_file(filename, CdTrackKind::Mode1Raw)
+        }
+    }
+
+    fn from_single_file(filename: &str, kind: CdTrackKind) -> Result<Self, Error> {
+        let file = open_file(filename)?;
+        let length = file_length(&file, filename)?;
+        let sectors = (length / kind.sector_size() as u64) as u32;
+
+        Ok(Self {
+            files: vec![file],
+            tracks: vec![CdTrack {
+                number: 1,
+                kind,
+                start: 0,
+                length: sectors,
+                file: 0,
+                file_offset: 0,
+            }],
+            sectors,
+        })
+    }
+
+    fn from_cue(filename: &str) -> Result<Self, Error> {
+        let contents =
+            std::fs::read_to_string(filename).map_err(|err| Error::new(format!("cdrom: error reading {}: {}", filename, err)))?;
+        let directory = Path::new(filename).parent().unwrap_or_else(|| Path::new(""));
+
+        let mut files = vec![];
+        let mut file_bytes = vec![];
+        let mut tracks: Vec<CdTrack> = vec![];
+        // The lba of the start of the current file, which is the sum of the lengths of the previous files
+        let mut file_start = 0;
+        for line in contents.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.first().map(|word| word.to_uppercase()).as_deref() {
+                Some("FILE") => {
+                    let name = quoted_name(line)
+                        .ok_or_else(|| Error::new(format!("cdrom: invalid FILE line in {}: {}", filename, line)))?;
+                    let path = directory.join(name);
+                    let path = path.to_string_lossy();
+                    let file = open_file(&path)?;
+                    file_bytes.push(file_length(&file, &path)?);
+                    files.push(file);
+                    file_start = files
+                        .len()
+                        .checked_sub(2)
+                        .and_then(|prev| file_end(&tracks, &file_bytes, prev))
+                        .unwrap_or(0);
+                },
+                Some("TRACK") if words.len() >= 3 => {
+                    if files.is_empty() {
+                        return Err(Error::new(format!("cdrom: TRACK before FILE in {}", filename)));
+                    }
+                    let number = words[1]
+                        .parse::<u8>()
+                        .map_err(|_| Error::new(format!("cdrom: invalid track number in {}: {}", filename, line)))?;
+                    let kind = match words[2].to_uppercase().as_str() {
+                        "MODE1/2352" => CdTrackKind::Mode1Raw,
+                        "MODE1/2048" => CdTrackKind::Mode1,
+                        "AUDIO" => CdTrackKind::Audio,
+                        kind => return Err(Error::new(format!("cdrom: unsupported track type {} in {}", kind, filename))),
+                    };
+                    tracks.push(CdTrack {
+                        number,
+                        kind,
+                        start: file_start,
+                        length: 0,
+                        file: files.len() - 1,
+                        file_offset: 0,
+                    });
+                },
+                Some("INDEX") if words.len() >= 3 && words[1] == "01" => {
+                    let track = tracks
+                        .last_mut()
+                        .ok_or_else(|| Error::new(format!("cdrom: INDEX before TRACK in {}", filename)))?;
+                    let offset = parse_msf(words[2])
+                        .ok_or_else(|| Error::new(format!("cdrom: invalid INDEX time in {}: {}", filename, line)))?;
+                    track.start = file_start + offset;
+                    track.file_offset = offset as u64 * track.kind.sector_size() as u64;
+                },
+                _ => {},
+            }
+        }
+
+        if tracks.is_empty() {
+            return Err(Error::new(format!("cdrom: no tracks found in {}", filename)));
+        }
+
+        // Each track ends where the next one in the same file starts, or else at the end of its file
+        for i in 0..tracks.len() {
+            let end = match tracks.get(i + 1) {
+                Some(next) if next.file == tracks[i].file => next.start,
+                _ => file_end(&tracks, &file_bytes, tracks[i].file).unwrap_or(tracks[i].start),
+            };
+            tracks[i].length = end.saturating_sub(tracks[i].start);
+        }
+        let sectors = file_end(&tracks, &file_bytes, files.len() - 1).unwrap_or(0);
+
+        Ok(Self {
+            files,
+            tracks,
+            sectors,
+        })
+    }
+
+    pub fn tracks(&self) -> &[CdTrack] {
+        &self.tracks
+    }
+
+    pub fn track(&self, number: u8) -> Option<&CdTrack> {
+        self.tracks.iter().find(|track| track.number == number)
+    }
+
+    pub fn track_at(&self, lba: u32) -> Option<&CdTrack> {
+        self.tracks.iter().find(|track| track.contains(lba))
+    }
+
+    /// The lba of the lead-out area, after the last sector of the last track
+    pub fn lead_out(&self) -> u32 {
+        self.sectors
+    }
+
+    /// Read the raw 2352 byte sector at the given lba, including a generated sync pattern and header for images
+    /// that only have the user data
+    pub fn read_sector(&mut self, lba: u32) -> Result<Option<[u8; RAW_SECTOR_SIZE]>, Error> {
+        let track = match self.track_at(lba) {
+            Some(track) => track.clone(),
+            None => return Ok(None),
+        };
+
+        let mut sector = [0; RAW_SECTOR_SIZE];
+        let sector_size = track.kind.sector_size();
+        let offset = track.file_offset + (lba - track.start) as u64 * sector_size as u64;
+        let file = &mut self.files[track.file];
+        let buffer = match track.kind {
+            CdTrackKind::Mode1 => {
+                sector[0..12].copy_from_slice(&SYNC_PATTERN);
+                let (minutes, seconds, frames) = lba_to_msf(lba + PREGAP_SECTORS);
+                sector[12] = to_bcd(minutes);
+                sector[13] = to_bcd(seconds);
+                sector[14] = to_bcd(frames);
+                sector[15] = 0x01;
+                &mut sector[16..16 + DATA_SECTOR_SIZE]
+            },
+            CdTrackKind::Mode1Raw | CdTrackKind::Audio => &mut sector[..],
+        };
+
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(buffer))
+            .map_err(|err| Error::new(format!("cdrom: error reading sector {}: {}", lba, err)))?;
+        Ok(Some(sector))
+    }
+}
+
+fn open_file(filename: &str) -> Result<File, Error> {
+    File::open(filename).map_err(|err| Error::new(format!("cdrom: error opening {}: {}", filename, err)))
+}
+
+fn file_length(file: &File, filename: &str) -> Result<u64, Error> {
+    file.metadata()
+        .map(|metadata| metadata.len())
+        .map_err(|err| Error::new(format!("cdrom: error reading {}: {}", filename, err)))
+}
+
+/// The lba of the end of the given file, which is determined by the sector size of its last track
+fn file_end(tracks: &[CdTrack], file_bytes: &[u64], file: usize) -> Option<u32> {
+    let track = tracks.iter().rev().find(|track| track.file == file)?;
+    let track_bytes = file_bytes[file].saturating_sub(track.file_offset);
+    Some(track.start + (track_bytes / track.kind.sector_size() as u64) as u32)
+}
+
+fn quoted_name(line: &str) -> Option<&str> {
+    let start = line.find('"')?;
+    let end = line[start + 1..].find('"')?;
+    Some(&line[start + 1..start + 1 + end])
+}
+
+/// Parse a time in the form mm:ss:ff into a number of sectors
+fn parse_msf(time: &str) -> Option<u32> {
+    let parts: Vec<u32> = time.split(':').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    match parts[..] {
+        [minutes, seconds, frames] => Some(msf_to_lba(minutes, seconds, frames)),
+        _ => None,
+    }
+}
+
+pub fn msf_to_lba(minutes: u32, seconds: u32, frames: u32) -> u32 {
+    (minutes * 60 + seconds) * SECTORS_PER_SECOND + frames
+}
+
+pub fn lba_to_msf(lba: u32) -> (u32, u32, u32) {
+    (lba / SECTORS_PER_SECOND / 60, (lba / SECTORS_PER_SECOND) % 60, lba % SECTORS_PER_SECOND)
+}
+
+pub fn to_bcd(value: u32) -> u8 {
+    (((value / 10) % 10) << 4 | (value % 10)) as u8
+}
@@ -0,0 +1,610 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use femtos::{Instant, Duration};
+
+use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable, strict};
+
+use super::cdc::{Cdc, CdcDestination};
+use super::cdd::CdDrive;
+use super::cdrom::SECTORS_PER_SECOND;
+
+pub const BIOS_SIZE: usize = 0x20000;
+pub const PRG_RAM_SIZE: usize = 0x80000;
+pub const PRG_RAM_BANK_SIZE: usize = 0x20000;
+pub const WORD_RAM_SIZE: usize = 0x40000;
+
+/// The stopwatch and timer count in units of 30.72us
+const TIMER_UNIT_NS: u64 = 30_720;
+
+/// The address in the BIOS of the H-INT vector, which is replaced by the value in the H-INT vector register
+const HINT_VECTOR_ADDR: usize = 0x72;
+
+#[rustfmt::skip]
+mod reg {
+    use super::Address;
+    // The registers accessible by both cpus
+    pub(super) const RESET: Address             = 0x00;
+    pub(super) const MEMORY_MODE: Address       = 0x02;
+    pub(super) const CDC_MODE: Address          = 0x04;
+    pub(super) const HINT_VECTOR: Address       = 0x06;
+    pub(super) const CDC_HOST_DATA: Address     = 0x08;
+    pub(super) const STOPWATCH: Address         = 0x0C;
+    pub(super) const COMM_FLAGS: Address        = 0x0E;
+    pub(super) const COMM_COMMAND: Address      = 0x10;
+    pub(super) const COMM_COMMAND_END: Address  = 0x1F;
+    pub(super) const COMM_STATUS: Address       = 0x20;
+    pub(super) const COMM_STATUS_END: Address   = 0x2F;
+
+    // The registers only accessible by the sub cpu
+    pub(super) const CDC_DATA: Address          = 0x06;
+    pub(super) const DMA_ADDRESS: Address       = 0x0A;
+    pub(super) const TIMER: Address             = 0x30;
+    pub(super) const INTERRUPT_MASK: Address    = 0x32;
+    pub(super) const CDD_CONTROL: Address       = 0x36;
+    pub(super) const CDD_STATUS: Address        = 0x38;
+    pub(super) const CDD_STATUS_END: Address    = 0x41;
+    pub(super) const CDD_COMMAND: Address       = 0x42;
+    pub(super) const CDD_COMMAND_END: Address   = 0x4B;
+}
+
+const MEMORY_MODE_RET: u8 = 0x01;
+const MEMORY_MODE_DMNA: u8 = 0x02;
+const MEMORY_MODE_1M: u8 = 0x04;
+
+const CDD_CONTROL_HOCK: u8 = 0x04;
+
+#[rustfmt::skip]
+mod int {
+    pub(super) const MAIN: u8                   = 2;
+    pub(super) const TIMER: u8                  = 3;
+    pub(super) const CDD: u8                    = 4;
+    pub(super) const CDC: u8                    = 5;
+}
+
+const DEV_NAME: &str = "segacd";
+
+/// The areas of the Sega CD's memory, as seen by each of the cpus
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SegaCdArea {
+    Bios,
+    MainPrgRam,
+    MainWordRam,
+    SubPrgRam,
+    SubWordRam2M,
+    SubWordRam1M,
+}
+
+impl SegaCdArea {
+    pub fn size(self) -> usize {
+        match self {
+            SegaCdArea::Bios => BIOS_SIZE,
+            SegaCdArea::MainPrgRam => PRG_RAM_BANK_SIZE,
+            SegaCdArea::MainWordRam => WORD_RAM_SIZE,
+            SegaCdArea::SubPrgRam => PRG_RAM_SIZE,
+            SegaCdArea::SubWordRam2M => WORD_RAM_SIZE,
+            SegaCdArea::SubWordRam1M => WORD_RAM_SIZE / 2,
+        }
+    }
+}
+
+/// The gate array of the Sega CD, which connects the main cpu to the sub cpu, and has the memory shared between
+/// them, the communication registers, and the interfaces to the CD drive and the CDC
+///
+/// The Word RAM is either given entirely to one cpu at a time (2M mode), or split into two banks, one for each cpu,
+/// which can be swapped (1M mode).  The banks are interleaved by word in the Word RAM, so the same data appears
+/// differently in each mode.  The rotation and scaling graphics, and the dot image conversion of the 1M mode are
+/// not emulated
+pub struct GateArray {
+    bios: Vec<u8>,
+    prg_ram: Vec<u8>,
+    word_ram: Vec<u8>,
+    pub cdc: Cdc,
+    pub cdd: CdDrive,
+
+    /// The sub cpu is held in reset until the main cpu releases it
+    pub sub_reset: bool,
+    /// The sub cpu is halted while the main cpu requests its bus
+    pub sub_bus_request: bool,
+    /// The interrupts for the sub cpu that were raised since the last time they were taken
+    sub_interrupts: u8,
+    /// The main cpu's interrupt to the sub cpu, which stays set until the sub cpu acknowledges it
+    main_interrupt: bool,
+    interrupt_mask: u8,
+    cdc_interrupt: bool,
+
+    prg_bank: u8,
+    write_protect: u8,
+    memory_mode: u8,
+    priority_mode: u8,
+    hint_vector: u16,
+    led: u8,
+
+    host_data: u16,
+    main_flags: u8,
+    sub_flags: u8,
+    command: [u8; 16],
+    status: [u8; 16],
+
+    stopwatch_start: Instant,
+    timer: u8,
+    next_timer: Instant,
+    cdd_control: u8,
+    next_sector: Instant,
+}
+
+impl GateArray {
+    pub fn new(bios: Vec<u8>, cdd: CdDrive) -> Self {
+        let mut bios = bios;
+        bios.resize(BIOS_SIZE, 0xFF);
+
+        Self {
+            bios,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            word_ram: vec![0; WORD_RAM_SIZE],
+            cdc: Cdc::default(),
+            cdd,
+
+            sub_reset: true,
+            sub_bus_request: true,
+            sub_interrupts: 0,
+            main_interrupt: false,
+            interrupt_mask: 0,
+            cdc_interrupt: false,
+
+            prg_bank: 0,
+            write_protect: 0,
+            memory_mode: MEMORY_MODE_RET,
+            priority_mode: 0,
+            hint_vector: 0,
+            led: 0,
+
+            host_data: 0,
+            main_flags: 0,
+            sub_flags: 0,
+            command: [0; 16],
+            status: [0; 16],
+
+            stopwatch_start: Instant::START,
+            timer: 0,
+            next_timer: Instant::START,
+            cdd_control: 0,
+            next_sector: Instant::START,
+        }
+    }
+
+    /// Returns the interrupt levels that were raised for the sub cpu, as a bit mask, and clears them
+    pub fn take_sub_interrupts(&mut self) -> u8 {
+        std::mem::take(&mut self.sub_interrupts)
+    }
+
+    /// Clears the main cpu's interrupt to the sub cpu once it's been acknowledged
+    pub fn set_main_interrupt_pending(&mut self, pending: bool) {
+        self.main_interrupt &= pending;
+    }
+
+    fn raise_sub_interrupt(&mut self, level: u8) {
+        if (self.interrupt_mask & (1 << level)) != 0 {
+            self.sub_interrupts |= 1 << level;
+        }
+    }
+
+    fn update_cdc_interrupt(&mut self) {
+        let interrupt = self.cdc.interrupt();
+        if interrupt && !self.cdc_interrupt {
+            self.raise_sub_interrupt(int::CDC);
+        }
+        self.cdc_interrupt = interrupt;
+    }
+
+    fn is_1m_mode(&self) -> bool {
+        (self.memory_mode & MEMORY_MODE_1M) != 0
+    }
+
+    fn ret(&self) -> bool {
+        (self.memory_mode & MEMORY_MODE_RET) != 0
+    }
+
+    /// Returns the offset into the Word RAM of an address in the given 1M bank, where the banks are interleaved by word
+    fn word_ram_bank_offset(bank: usize, addr: usize) -> usize {
+        (((addr >> 1) << 2) | (bank << 1) | (addr & 0x01)) % WORD_RAM_SIZE
+    }
+
+    fn main_word_ram_offset(&self, addr: usize) -> Option<usize> {
+        if self.is_1m_mode() {
+            // The main cpu has bank 0 when RET is clear, and bank 1 when it's set
+            if addr < WORD_RAM_SIZE / 2 {
+                Some(Self::word_ram_bank_offset(self.ret() as usize, addr))
+            } else {
+                // The dot image conversion of the other bank isn't supported
+                None
+            }
+        } else if self.ret() {
+            Some(addr % WORD_RAM_SIZE)
+        } else {
+            None
+        }
+    }
+
+    fn sub_word_ram_offset(&self, area: SegaCdArea, addr: usize) -> Option<usize> {
+        match area {
+            SegaCdArea::SubWordRam2M if !self.is_1m_mode() && !self.ret() => Some(addr % WORD_RAM_SIZE),
+            SegaCdArea::SubWordRam1M if self.is_1m_mode() => Some(Self::word_ram_bank_offset(!self.ret() as usize, addr)),
+            _ => None,
+        }
+    }
+
+    pub fn read_memory(&mut self, area: SegaCdArea, addr: usize) -> u8 {
+        match area {
+            SegaCdArea::Bios if addr == HINT_VECTOR_ADDR => (self.hint_vector >> 8) as u8,
+            SegaCdArea::Bios if addr == HINT_VECTOR_ADDR + 1 => self.hint_vector as u8,
+            SegaCdArea::Bios => self.bios[addr % BIOS_SIZE],
+            SegaCdArea::MainPrgRam => self.prg_ram[self.prg_bank as usize * PRG_RAM_BANK_SIZE + addr % PRG_RAM_BANK_SIZE],
+            SegaCdArea::MainWordRam => match self.main_word_ram_offset(addr) {
+                Some(offset) => self.word_ram[offset],
+                None => 0,
+            },
+            SegaCdArea::SubPrgRam => self.prg_ram[addr % PRG_RAM_SIZE],
+            SegaCdArea::SubWordRam2M | SegaCdArea::SubWordRam1M => match self.sub_word_ram_offset(area, addr) {
+                Some(offset) => self.word_ram[offset],
+                None => 0,
+            },
+        }
+    }
+
+    pub fn write_memory(&mut self, area: SegaCdArea, addr: usize, data: u8) {
+        match area {
+            SegaCdArea::Bios => {},
+            SegaCdArea::MainPrgRam => {
+                self.prg_ram[self.prg_bank as usize * PRG_RAM_BANK_SIZE + addr % PRG_RAM_BANK_SIZE] = data;
+            },
+            SegaCdArea::MainWordRam => {
+                if let Some(offset) = self.main_word_ram_offset(addr) {
+                    self.word_ram[offset] = data;
+                }
+            },
+            SegaCdArea::SubPrgRam => {
+                // The write protect register protects the start of the PRG-RAM from the sub cpu in 512 byte units
+                if addr >= self.write_protect as usize * 0x200 {
+                    self.prg_ram[addr % PRG_RAM_SIZE] = data;
+                }
+            },
+            SegaCdArea::SubWordRam2M | SegaCdArea::SubWordRam1M => {
+                if let Some(offset) = self.sub_word_ram_offset(area, addr) {
+                    self.word_ram[offset] = data;
+                }
+            },
+        }
+    }
+
+    fn stopwatch(&self, clock: Instant) -> u16 {
+        let elapsed = clock.duration_since(self.stopwatch_start).as_nanos() as u64;
+        ((elapsed / TIMER_UNIT_NS) & 0x0FFF) as u16
+    }
+
+    fn cdc_mode(&self) -> u8 {
+        let mut value = match self.cdc.destination {
+            CdcDestination::MainCpu => 2,
+            CdcDestination::SubCpu => 3,
+            CdcDestination::Pcm => 4,
+            CdcDestination::PrgRam => 5,
+            CdcDestination::WordRam => 7,
+            CdcDestination::None => 0,
+        };
+        if self.cdc.transfer_ended {
+            value |= 0x80;
+        }
+        if self.cdc.data_ready {
+            value |= 0x40;
+        }
+        value
+    }
+
+    fn read_host_data(&mut self, destination: CdcDestination, addr: Address) -> u8 {
+        if addr == reg::CDC_HOST_DATA {
+            if self.cdc.destination == destination && self.cdc.data_ready {
+                self.host_data = self.cdc.transfer_word();
+                if self.cdc.transfer_ended {
+                    self.cdc.data_ready = false;
+                }
+                self.update_cdc_interrupt();
+            }
+            (self.host_data >> 8) as u8
+        } else {
+            self.host_data as u8
+        }
+    }
+
+    /// Transfer the data from the CDC by DMA, which is done all at once when the transfer is started
+    fn run_dma(&mut self) {
+        let destination = self.cdc.destination;
+        if !matches!(destination, CdcDestination::PrgRam | CdcDestination::WordRam | CdcDestination::Pcm) {
+            return;
+        }
+
+        let mut addr = self.cdc.dma_address as usize * 8;
+        while self.cdc.is_transferring() {
+            let data = self.cdc.transfer_word();
+            match destination {
+                CdcDestination::PrgRam => {
+                    self.prg_ram[addr % PRG_RAM_SIZE] = (data >> 8) as u8;
+                    self.prg_ram[(addr + 1) % PRG_RAM_SIZE] = data as u8;
+                },
+                CdcDestination::WordRam => {
+                    let area = if self.is_1m_mode() {
+                        SegaCdArea::SubWordRam1M
+                    } else {
+                        SegaCdArea::SubWordRam2M
+                    };
+                    self.write_memory(area, addr, (data >> 8) as u8);
+                    self.write_memory(area, addr + 1, data as u8);
+                },
+                _ => {},
+            }
+            addr += 2;
+        }
+        self.cdc.dma_address = (addr / 8) as u16;
+        self.update_cdc_interrupt();
+    }
+
+    fn read_main_register(&mut self, clock: Instant, addr: Address) -> u8 {
+        match addr {
+            reg::RESET => self.main_interrupt as u8,
+            0x01 => ((self.sub_bus_request as u8) << 1) | (!self.sub_reset as u8),
+            reg::MEMORY_MODE => self.write_protect,
+            0x03 => (self.prg_bank << 6) | self.memory_mode,
+            reg::CDC_MODE => self.cdc_mode(),
+            reg::HINT_VECTOR => (self.hint_vector >> 8) as u8,
+            0x07 => self.hint_vector as u8,
+            0x08 | 0x09 => self.read_host_data(CdcDestination::MainCpu, addr),
+            reg::STOPWATCH => (self.stopwatch(clock) >> 8) as u8,
+            0x0D => self.stopwatch(clock) as u8,
+            reg::COMM_FLAGS => self.main_flags,
+            0x0F => self.sub_flags,
+            reg::COMM_COMMAND..=reg::COMM_COMMAND_END => self.command[(addr - reg::COMM_COMMAND) as usize],
+            reg::COMM_STATUS..=reg::COMM_STATUS_END => self.status[(addr - reg::COMM_STATUS) as usize],
+            _ => {
+                strict::unhandled_read(DEV_NAME, addr);
+                0
+            },
+        }
+    }
+
+    fn write_main_register(&mut self, _clock: Instant, addr: Address, data: u8) {
+        match addr {
+            reg::RESET => {
+                if (data & 0x01) != 0 {
+                    self.main_interrupt = true;
+                    self.raise_sub_interrupt(int::MAIN);
+                }
+            },
+            0x01 => {
+                self.sub_bus_request = (data & 0x02) != 0;
+                self.sub_reset = (data & 0x01) == 0;
+            },
+            reg::MEMORY_MODE => self.write_protect = data,
+            0x03 => {
+                self.prg_bank = (data >> 6) & 0x03;
+                if (data & MEMORY_MODE_DMNA) != 0 {
+                    if self.is_1m_mode() {
+                        // Request a swap of the banks, which the sub cpu does by writing RET
+                        self.memory_mode |= MEMORY_MODE_DMNA;
+                    } else {
+                        // Give the Word RAM to the sub cpu
+                        self.memory_mode = (self.memory_mode | MEMORY_MODE_DMNA) & !MEMORY_MODE_RET;
+                    }
+                }
+            },
+            reg::HINT_VECTOR => self.hint_vector = (self.hint_vector & 0x00FF) | ((data as u16) << 8),
+            0x07 => self.hint_vector = (self.hint_vector & 0xFF00) | data as u16,
+            reg::COMM_FLAGS => self.main_flags = data,
+            reg::COMM_COMMAND..=reg::COMM_COMMAND_END => self.command[(addr - reg::COMM_COMMAND) as usize] = data,
+            _ => strict::unhandled_write(DEV_NAME, addr, &[data]),
+        }
+    }
+
+    fn read_sub_register(&mut self, clock: Instant, addr: Address) -> u8 {
+        match addr {
+            reg::RESET => self.led,
+            // The reset of the peripherals is always complete
+            0x01 => 0x01,
+            reg::MEMORY_MODE => self.write_protect,
+            0x03 => (self.priority_mode << 3) | self.memory_mode,
+            reg::CDC_MODE => self.cdc_mode(),
+            0x05 => self.cdc.address(),
+            0x07 => {
+                let value = self.cdc.read_register();
+                self.update_cdc_interrupt();
+                value
+            },
+            0x08 | 0x09 => self.read_host_data(CdcDestination::SubCpu, addr),
+            reg::DMA_ADDRESS => (self.cdc.dma_address >> 8) as u8,
+            0x0B => self.cdc.dma_address as u8,
+            reg::STOPWATCH => (self.stopwatch(clock) >> 8) as u8,
+            0x0D => self.stopwatch(clock) as u8,
+            reg::COMM_FLAGS => self.main_flags,
+            0x0F => self.sub_flags,
+            reg::COMM_COMMAND..=reg::COMM_COMMAND_END => self.command[(addr - reg::COMM_COMMAND) as usize],
+            reg::COMM_STATUS..=reg::COMM_STATUS_END => self.status[(addr - reg::COMM_STATUS) as usize],
+            0x31 => self.timer,
+            0x33 => self.interrupt_mask,
+            0x37 => self.cdd_control,
+            reg::CDD_STATUS..=reg::CDD_STATUS_END => self.cdd.read_status((addr - reg::CDD_STATUS) as usize),
+            reg::CDD_COMMAND..=reg::CDD_COMMAND_END => self.cdd.read_command((addr - reg::CDD_COMMAND) as usize),
+            _ => 0,
+        }
+    }
+
+    fn write_sub_register(&mut self, clock: Instant, addr: Address, data: u8) {
+        match addr {
+            reg::RESET => self.led = data,
+            0x01 => {},
+            reg::MEMORY_MODE => {},
+            0x03 => {
+                self.priority_mode = (data >> 3) & 0x03;
+                let mode_1m = data & MEMORY_MODE_1M;
+                if mode_1m != 0 {
+                    // Writing RET swaps the banks, which also completes the main cpu's swap request
+                    self.memory_mode = mode_1m | (data & MEMORY_MODE_RET);
+                } else if (data & MEMORY_MODE_RET) != 0 {
+                    // Give the Word RAM back to the main cpu
+                    self.memory_mode = MEMORY_MODE_RET;
+                } else {
+                    self.memory_mode &= !MEMORY_MODE_1M;
+                }
+            },
+            reg::CDC_MODE => self.cdc.set_mode(data),
+            0x05 => self.cdc.set_address(data),
+            reg::CDC_DATA => {},
+            0x07 => {
+                self.cdc.write_register(data);
+                self.run_dma();
+                self.update_cdc_interrupt();
+            },
+            reg::DMA_ADDRESS => self.cdc.dma_address = (self.cdc.dma_address & 0x00FF) | ((data as u16) << 8),
+            0x0B => self.cdc.dma_address = (self.cdc.dma_address & 0xFF00) | data as u16,
+            reg::STOPWATCH | 0x0D => self.stopwatch_start = clock,
+            0x0F => self.sub_flags = data,
+            reg::COMM_STATUS..=reg::COMM_STATUS_END => self.status[(addr - reg::COMM_STATUS) as usize] = data,
+            reg::TIMER => {},
+            0x31 => {
+                self.timer = data;
+                self.next_timer = clock + self.timer_period();
+            },
+            reg::INTERRUPT_MASK => {},
+            0x33 => self.interrupt_mask = data & 0x7E,
+            reg::CDD_CONTROL => {},
+            0x37 => self.cdd_control = data,
+            reg::CDD_COMMAND..=reg::CDD_COMMAND_END => self.cdd.write_command((addr - reg::CDD_COMMAND) as usize, data),
+            _ => log::debug!(target: DEV_NAME, "ignoring write to register {:x} with {:x}", addr, data),
+        }
+    }
+
+    fn timer_period(&self) -> Duration {
+        Duration::from_nanos(TIMER_UNIT_NS * (self.timer as u64 + 1))
+    }
+
+    fn sector_period() -> Duration {
+        Duration::from_secs(1) / SECTORS_PER_SECOND as u64
+    }
+
+    fn step(&mut self, clock: Instant) -> Result<Duration, Error> {
+        if self.timer != 0 && clock >= self.next_timer {
+            self.raise_sub_interrupt(int::TIMER);
+            self.next_timer = clock + self.timer_period();
+        }
+
+        if clock >= self.next_sector {
+            self.next_sector = clock + Self::sector_period();
+            if (self.cdd_control & CDD_CONTROL_HOCK) != 0 {
+                if let Some(sector) = self.cdd.update()? {
+                    self.cdc.decode(&sector);
+                    self.update_cdc_interrupt();
+                }
+                self.raise_sub_interrupt(int::CDD);
+            }
+        }
+
+        let mut next = self.next_sector;
+        if self.timer != 0 && self.next_timer < next {
+            next = self.next_timer;
+        }
+        Ok(next.duration_since(clock))
+    }
+}
+
+/// The registers of the gate array in the main cpu's address space, which are mapped at 0xA12000
+pub struct MainRegisters {
+    gate_array: Rc<RefCell<GateArray>>,
+}
+
+impl MainRegisters {
+    pub fn new(gate_array: Rc<RefCell<GateArray>>) -> Self {
+        Self {
+            gate_array,
+        }
+    }
+}
+
+impl Addressable for MainRegisters {
+    fn size(&self) -> usize {
+        0x30
+    }
+
+    fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        let mut gate_array = self.gate_array.borrow_mut();
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = gate_array.read_main_register(clock, addr + i as Address);
+        }
+        log::debug!(target: DEV_NAME, "main read from register {:x} of {:?}", addr, data);
+        Ok(())
+    }
+
+    fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        log::debug!(target: DEV_NAME, "main write to register {:x} with {:?}", addr, data);
+        let mut gate_array = self.gate_array.borrow_mut();
+        for (i, byte) in data.iter().enumerate() {
+            gate_array.write_main_register(clock, addr + i as Address, *byte);
+        }
+        Ok(())
+    }
+}
+
+impl Transmutable for MainRegisters {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+}
+
+/// The registers of the gate array in the sub cpu's address space, which are mapped at 0xFF8000, and which
+/// also steps the timer and the CD drive
+pub struct SubRegisters {
+    gate_array: Rc<RefCell<GateArray>>,
+}
+
+impl SubRegisters {
+    pub fn new(gate_array: Rc<RefCell<GateArray>>) -> Self {
+        Self {
+            gate_array,
+        }
+    }
+}
+
+impl Addressable for SubRegisters {
+    fn size(&self) -> usize {
+        0x200
+    }
+
+    fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        let mut gate_array = self.gate_array.borrow_mut();
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = gate_array.read_sub_register(clock, addr + i as Address);
+        }
+        log::debug!(target: DEV_NAME, "sub read from register {:x} of {:?}", addr, data);
+        Ok(())
+    }
+
+    fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        log::debug!(target: DEV_NAME, "sub write to register {:x} with {:?}", addr, data);
+        let mut gate_array = self.gate_array.borrow_mut();
+        for (i, byte) in data.iter().enumerate() {
+            gate_array.write_sub_register(clock, addr + i as Address, *byte);
+        }
+        Ok(())
+    }
+}
+
+impl Steppable for SubRegisters {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        self.gate_array.borrow_mut().step(system.clock)
+    }
+}
+
+impl Transmutable for SubRegisters {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+}
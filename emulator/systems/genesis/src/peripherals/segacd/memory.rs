@@ -0,0 +1,100 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use femtos::Instant;
+
+use moa_core::{Error, Address, Addressable, Transmutable};
+
+use super::gate_array::{GateArray, SegaCdArea};
+
+const BACKUP_RAM_SIZE: usize = 0x2000;
+
+/// A view of one of the areas of memory in the gate array, which depends on the bank and mode registers
+pub struct SegaCdMemory {
+    gate_array: Rc<RefCell<GateArray>>,
+    area: SegaCdArea,
+}
+
+impl SegaCdMemory {
+    pub fn new(gate_array: Rc<RefCell<GateArray>>, area: SegaCdArea) -> Self {
+        Self {
+            gate_array,
+            area,
+        }
+    }
+}
+
+impl Addressable for SegaCdMemory {
+    fn size(&self) -> usize {
+        self.area.size()
+    }
+
+    fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        let mut gate_array = self.gate_array.borrow_mut();
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = gate_array.read_memory(self.area, addr as usize + i);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        let mut gate_array = self.gate_array.borrow_mut();
+        for (i, byte) in data.iter().enumerate() {
+            gate_array.write_memory(self.area, addr as usize + i, *byte);
+        }
+        Ok(())
+    }
+}
+
+impl Transmutable for SegaCdMemory {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+}
+
+/// The battery backed RAM of the sub cpu, which is only connected to the odd bytes of its address range.  The
+/// contents are not saved between runs
+pub struct BackupRam {
+    contents: Vec<u8>,
+}
+
+impl Default for BackupRam {
+    fn default() -> Self {
+        Self {
+            contents: vec![0; BACKUP_RAM_SIZE],
+        }
+    }
+}
+
+impl Addressable for BackupRam {
+    fn size(&self) -> usize {
+        BACKUP_RAM_SIZE * 2
+    }
+
+    fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        for (i, byte) in data.iter_mut().enumerate() {
+            let addr = addr as usize + i;
+            *byte = if (addr & 0x01) != 0 {
+                self.contents[(addr >> 1) % BACKUP_RAM_SIZE]
+            } else {
+                0xFF
+            };
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        for (i, byte) in data.iter().enumerate() {
+            let addr = addr as usize + i;
+            if (addr & 0x01) != 0 {
+                self.contents[(addr >> 1) % BACKUP_RAM_SIZE] = *byte;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Transmutable for BackupRam {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+}
@@ -0,0 +1,6 @@
+pub mod cdc;
+pub mod cdd;
+pub mod cdrom;
+pub mod gate_array;
+pub mod memory;
+pub mod sub_cpu;
@@ -0,0 +1,69 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use femtos::{Instant, Frequency, Duration};
+
+use moa_core::{System, Error, Bus, InterruptController, Steppable, Transmutable};
+use moa_m68k::{M68k, M68kType, M68kState};
+
+use super::gate_array::GateArray;
+
+/// The sub cpu runs at 12.5MHz
+pub const SUB_CPU_FREQUENCY: u32 = 12_500_000;
+
+/// How long to wait before checking again while the sub cpu is held in reset or its bus is requested
+const WAIT_US: u64 = 1;
+
+/// The vector number of the first autovector interrupt
+const AUTOVECTOR_BASE: u8 = 24;
+
+/// The 68000 of the Sega CD, which has its own bus and interrupts, separate from the main cpu's
+pub struct SubCpu {
+    cpu: M68k<Instant>,
+    bus: Rc<RefCell<Bus>>,
+    interrupts: InterruptController,
+    gate_array: Rc<RefCell<GateArray>>,
+}
+
+impl SubCpu {
+    pub fn new(bus: Rc<RefCell<Bus>>, gate_array: Rc<RefCell<GateArray>>) -> Self {
+        Self {
+            cpu: M68k::from_type(M68kType::MC68000, Frequency::from_hz(SUB_CPU_FREQUENCY)),
+            bus,
+            interrupts: InterruptController::default(),
+            gate_array,
+        }
+    }
+}
+
+impl Steppable for SubCpu {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        let pending = {
+            let mut gate_array = self.gate_array.borrow_mut();
+            gate_array.set_main_interrupt_pending(self.interrupts.is_pending(2));
+
+            if gate_array.sub_reset {
+                // The cpu reads its reset vectors from the PRG-RAM when it's released
+                self.cpu.state = M68kState::default();
+                return Ok(Duration::from_micros(WAIT_US));
+            } else if gate_array.sub_bus_request {
+                return Ok(Duration::from_micros(WAIT_US));
+            }
+            gate_array.take_sub_interrupts()
+        };
+
+        for level in 1..=6 {
+            if (pending & (1 << level)) != 0 {
+                self.interrupts.set(true, level, AUTOVECTOR_BASE + level)?;
+            }
+        }
+
+        let mut bus = self.bus.borrow_mut();
+        self.cpu.step_on_bus(system.clock, &mut bus, &mut self.interrupts)
+    }
+}
+
+impl Transmutable for SubCpu {
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+}
@@ -1,11 +1,46 @@
+use std::any::Any;
 use femtos::{Instant, Duration, Frequency};
 
-use moa_core::{System, Error, Address, Addressable, Steppable, Inspectable, Transmutable, Device, read_beu16, dump_slice};
+use moa_core::{
+    System, Error, Address, Addressable, Steppable, Inspectable, Snapshotable, Transmutable, Capabilities, Device, Waker, TimerId,
+    VideoStandard, read_beu16, dump_slice, strict,
+};
 use moa_host::{self, Host, HostError, Pixel, PixelEncoding, Frame, FrameSender};
 use moa_signals::{EdgeSignal, Signal};
+use moa_gfx_helpers::{TilePixel, TileFormat, TileLayout, Flip, CellOrder, composite, sprite_cell, sprites_by_line};
 
 const DEV_NAME: &str = "ym7101";
 
+/// The size of the border around the active display, in pixels, which is drawn when overscan is enabled
+const BORDER_LEFT: usize = 13;
+const BORDER_RIGHT: usize = 14;
+const BORDER_TOP: usize = 11;
+const BORDER_BOTTOM: usize = 8;
+/// The most lines that can be counted in a frame, including the lines in vblank
+const MAX_LINES: usize = 320;
+/// The time from the start of the line that hblank starts, in nanoseconds
+const HBLANK_START_NS: u32 = 61_160;
+
+const PATTERN_LAYOUT: TileLayout = TileLayout::new(
+    TileFormat::Linear {
+        bits_per_pixel: 4,
+    },
+    8,
+    8,
+);
+
+/// The names of the debug views that can be shown by the host, and the size of the frames drawn for them
+const VIEW_PATTERNS: &str = "vdp-patterns";
+const VIEW_PLANE_A: &str = "vdp-plane-a";
+const VIEW_PLANE_B: &str = "vdp-plane-b";
+const VIEW_SPRITES: &str = "vdp-sprites";
+const VIEW_PALETTE: &str = "vdp-palette";
+const PATTERNS_SIZE: (u32, u32) = (64 * 8, 32 * 8);
+const PLANE_SIZE: (u32, u32) = (128 * 8, 128 * 8);
+const SPRITES_SIZE: (u32, u32) = (512, 512);
+const PALETTE_SWATCH: u32 = 16;
+const PALETTE_SIZE: (u32, u32) = (16 * PALETTE_SWATCH, 4 * PALETTE_SWATCH);
+
 #[rustfmt::skip]
 mod reg {
     pub(super) const MODE_SET_1: usize              = 0x00;
@@ -36,7 +71,7 @@ mod reg {
 
 #[rustfmt::skip]
 mod status {
-    //pub(super) const PAL_MODE: u16                = 0x0001;
+    pub(super) const PAL_MODE: u16                  = 0x0001;
     pub(super) const DMA_BUSY: u16                  = 0x0002;
     pub(super) const IN_HBLANK: u16                 = 0x0004;
     pub(super) const IN_VBLANK: u16                 = 0x0008;
@@ -91,6 +126,7 @@ enum Memory {
     Vsram,
 }
 
+#[derive(Clone)]
 struct Ym7101Memory {
     vram: [u8; 0x10000],
     cram: [u8; 128],
@@ -171,8 +207,8 @@ impl Ym7101Memory {
             _ => Memory::Cram,
         };
         log::debug!(
-            "{}: transfer requested of type {:x} ({:?}) to address {:x}",
-            DEV_NAME,
+            target: DEV_NAME,
+            "transfer requested of type {:x} ({:?}) to address {:x}",
             self.transfer_type,
             self.transfer_target,
             self.transfer_dest_addr
@@ -204,8 +240,8 @@ impl Ym7101Memory {
         }
         self.transfer_dest_addr += self.transfer_auto_inc;
         log::debug!(
-            "{}: data port read {} bytes from {:?}:{:x} returning {:x},{:x}",
-            DEV_NAME,
+            target: DEV_NAME,
+            "data port read {} bytes from {:?}:{:x} returning {:x},{:x}",
             data.len(),
             self.transfer_target,
             addr,
@@ -222,8 +258,8 @@ impl Ym7101Memory {
             self.set_dma_mode(DmaType::Fill);
         } else {
             log::debug!(
-                "{}: data port write {} bytes to {:?}:{:x} with {:?}",
-                DEV_NAME,
+                target: DEV_NAME,
+                "data port write {} bytes to {:?}:{:x} with {:?}",
                 data.len(),
                 self.transfer_target,
                 self.transfer_dest_addr,
@@ -249,7 +285,7 @@ impl Ym7101Memory {
             (2, Some(upper)) => self.setup_transfer(upper, read_beu16(data)),
             (4, None) => self.setup_transfer(value, read_beu16(&data[2..])),
             _ => {
-                log::error!("{}: !!! error when writing to control port with {} bytes of {:?}", DEV_NAME, data.len(), data);
+                log::error!(target: DEV_NAME, "!!! error when writing to control port with {} bytes of {:?}", data.len(), data);
             },
         }
         Ok(())
@@ -262,8 +298,8 @@ impl Ym7101Memory {
             match self.transfer_run {
                 DmaType::Memory => {
                     log::debug!(
-                        "{}: starting dma transfer {:x} from Mem:{:x} to {:?}:{:x} ({} bytes)",
-                        DEV_NAME,
+                        target: DEV_NAME,
+                        "starting dma transfer {:x} from Mem:{:x} to {:?}:{:x} ({} bytes)",
                         self.transfer_type,
                         self.transfer_src_addr,
                         self.transfer_target,
@@ -288,8 +324,8 @@ impl Ym7101Memory {
                 },
                 DmaType::Copy => {
                     log::debug!(
-                        "{}: starting dma copy from VRAM:{:x} to VRAM:{:x} ({} bytes)",
-                        DEV_NAME,
+                        target: DEV_NAME,
+                        "starting dma copy from VRAM:{:x} to VRAM:{:x} ({} bytes)",
                         self.transfer_src_addr,
                         self.transfer_dest_addr,
                         self.transfer_remain
@@ -303,8 +339,8 @@ impl Ym7101Memory {
                 },
                 DmaType::Fill => {
                     log::debug!(
-                        "{}: starting dma fill to VRAM:{:x} ({} bytes) with {:x}",
-                        DEV_NAME,
+                        target: DEV_NAME,
+                        "starting dma fill to VRAM:{:x} ({} bytes) with {:x}",
                         self.transfer_dest_addr,
                         self.transfer_remain,
                         self.transfer_fill_word
@@ -316,7 +352,7 @@ impl Ym7101Memory {
                     }
                 },
                 _ => {
-                    log::warn!("{}: !!! error unexpected transfer mode {:x}", DEV_NAME, self.transfer_type);
+                    log::warn!(target: DEV_NAME, "!!! error unexpected transfer mode {:x}", self.transfer_type);
                 },
             }
 
@@ -352,6 +388,50 @@ enum Priority {
 }
 */
 
+/// The timing of the lines and frames in nanoseconds, and the clock that the VDP is stepped at, which depend on
+/// the video standard
+#[derive(Copy, Clone, Debug)]
+struct FrameTiming {
+    standard: VideoStandard,
+    line_ns: u32,
+    frame_ns: u32,
+    step_clock: Frequency,
+}
+
+impl FrameTiming {
+    fn new(standard: VideoStandard) -> Self {
+        let line_ns = standard.line_duration().as_nanos() as u32;
+        match standard {
+            VideoStandard::Ntsc => Self {
+                standard,
+                line_ns,
+                frame_ns: 16_630_000,
+                step_clock: Frequency::from_hz(13_423_294),
+            },
+            VideoStandard::Pal => Self {
+                standard,
+                line_ns,
+                frame_ns: standard.lines_per_frame() * line_ns,
+                step_clock: Frequency::from_hz(13_300_856),
+            },
+        }
+    }
+
+    /// Returns the times in the frame that the active display starts and ends, with the rest of the frame in vblank
+    fn active_display(&self, v_cells: usize) -> (u32, u32) {
+        match self.standard {
+            VideoStandard::Ntsc => (1_205_992, 15_424_008),
+            VideoStandard::Pal => {
+                // The extra lines of a PAL frame are split between the top and bottom borders
+                let lines = (v_cells.max(28) * 8) as u32;
+                let start = (self.standard.lines_per_frame() - lines) / 2 * self.line_ns;
+                (start, start + lines * self.line_ns)
+            },
+        }
+    }
+}
+
+#[derive(Clone)]
 struct Ym7101State {
     status: u16,
     memory: Ym7101Memory,
@@ -363,7 +443,10 @@ struct Ym7101State {
     h_int_lines: u8,
     screen_size: (usize, usize),
     scroll_size: (usize, usize),
-    window_pos: ((usize, usize), (usize, usize)),
+    /// The range of cells covered by the window on lines outside of the window's rows, which are replaced by
+    /// the window on the whole line
+    window_columns: (usize, usize),
+    window_rows: (usize, usize),
     window_values: (u8, u8),
     background: u8,
     scroll_a_addr: usize,
@@ -374,7 +457,14 @@ struct Ym7101State {
 
     sprites: Vec<Sprite>,
     sprites_by_line: Vec<Vec<usize>>,
-
+    /// The horizontal cell mode in effect at the start of each line, since it can be changed mid-frame
+    line_h_cells: Vec<usize>,
+    /// Whether to draw the border, and the background colour at the start of each line, including those in vblank
+    overscan: bool,
+    line_background: Vec<u8>,
+    frame_lines: usize,
+
+    timing: FrameTiming,
     last_clock: Instant,
     p_clock: u32,
     h_clock: u32,
@@ -398,7 +488,8 @@ impl Default for Ym7101State {
             h_int_lines: 0,
             screen_size: (0, 0),
             scroll_size: (0, 0),
-            window_pos: ((0, 0), (0, 0)),
+            window_columns: (0, 0),
+            window_rows: (0, 0),
             window_values: (0, 0),
             background: 0,
             scroll_a_addr: 0,
@@ -409,7 +500,12 @@ impl Default for Ym7101State {
 
             sprites: vec![],
             sprites_by_line: vec![],
+            line_h_cells: vec![0; 30 * 8],
+            overscan: false,
+            line_background: vec![0; MAX_LINES],
+            frame_lines: 0,
 
+            timing: FrameTiming::new(VideoStandard::Ntsc),
             last_clock: Instant::START,
             p_clock: 0,
             h_clock: 0,
@@ -442,25 +538,54 @@ impl Ym7101State {
         let h_cells = if (self.mode_4 & mode4::BF_H_CELL_MODE) == 0 { 32 } else { 40 };
         let v_cells = if (self.mode_2 & mode2::BF_V_CELL_MODE) == 0 { 28 } else { 30 };
         self.screen_size = (h_cells, v_cells);
+        self.update_window_position();
+    }
+
+    fn set_h_cells(&mut self, h_cells: usize) {
+        if self.screen_size.0 != h_cells {
+            self.screen_size.0 = h_cells;
+            self.update_window_position();
+        }
+    }
+
+    fn start_line(&mut self) {
+        if let Some(h_cells) = self.line_h_cells.get_mut(self.current_y as usize) {
+            *h_cells = self.screen_size.0;
+        }
+        if let Some(background) = self.line_background.get_mut(self.current_y as usize) {
+            *background = self.background;
+        }
     }
 
+    /// Update the cells covered by the window, which is given in units of 2 cells horizontally and 1 cell
+    /// vertically, from either the left or right edge, and from either the top or bottom edge
     fn update_window_position(&mut self) {
-        let win_h = ((self.window_values.0 & 0x1F) << 1) as usize;
-        let win_v = (self.window_values.1 & 0x1F) as usize;
+        let win_h = (((self.window_values.0 & 0x1F) as usize) << 1).min(self.screen_size.0);
+        let win_v = ((self.window_values.1 & 0x1F) as usize).min(self.screen_size.1);
         let right = (self.window_values.0 & 0x80) != 0;
         let down = (self.window_values.1 & 0x80) != 0;
 
-        self.window_pos = match (right, down) {
-            (false, false) => ((0, 0), (win_h, win_v)),
-            (true, false) => ((win_h, 0), (self.screen_size.0, win_v)),
-            (false, true) => ((0, win_v), (win_h, self.screen_size.1)),
-            (true, true) => ((win_h, win_v), (self.screen_size.0, self.screen_size.1)),
-        }
+        self.window_columns = if right { (win_h, self.screen_size.0) } else { (0, win_h) };
+        self.window_rows = if down { (win_v, self.screen_size.1) } else { (0, win_v) };
     }
 
+    /// Returns true if the window replaces scroll A at the given pixel
+    #[inline(always)]
+    fn is_inside_window(&self, x: usize, y: usize) -> bool {
+        let (cell_x, cell_y) = (x / 8, y / 8);
+        (cell_y >= self.window_rows.0 && cell_y < self.window_rows.1)
+            || (cell_x >= self.window_columns.0 && cell_x < self.window_columns.1)
+    }
 
-    fn is_inside_window(&mut self, x: usize, y: usize) -> bool {
-        x >= self.window_pos.0.0 && x <= self.window_pos.1.0 && y >= self.window_pos.0.1 && y <= self.window_pos.1.1
+    /// Returns the address of a cell in the window's name table, which isn't scrolled and is 32 cells wide in
+    /// H32 mode, or 64 cells wide in H40 mode, where the lowest bit of the table's address is ignored
+    #[inline(always)]
+    fn get_window_pattern_addr(&self, cell_x: usize, cell_y: usize) -> usize {
+        if self.screen_size.0 == 40 {
+            (self.window_addr & 0xF000) + ((cell_x + (cell_y * 64)) << 1)
+        } else {
+            self.window_addr + ((cell_x + (cell_y * 32)) << 1)
+        }
     }
 
     fn get_palette_colour(&self, palette: u8, colour: u8, mode: ColourMode, encoding: PixelEncoding) -> u32 {
@@ -479,6 +604,49 @@ impl Ym7101State {
         }
     }
 
+    fn get_background_colour(&self, background: u8, encoding: PixelEncoding) -> u32 {
+        self.get_palette_colour((background & 0x30) >> 4, background & 0x0f, ColourMode::Normal, encoding)
+    }
+
+    /// Returns the background colour that was in effect for the given line of a frame with a border
+    fn get_border_line_background(&self, y: usize, height: usize) -> u8 {
+        if y < BORDER_TOP {
+            // The top border is displayed at the end of the previous frame, before the line count is reset
+            let line = (self.frame_lines + y).checked_sub(BORDER_TOP);
+            line.and_then(|line| self.line_background.get(line).cloned())
+                .unwrap_or(self.background)
+        } else if y < BORDER_TOP + height {
+            self.line_background.get(y - BORDER_TOP).cloned().unwrap_or(self.background)
+        } else {
+            // The bottom border hasn't been displayed yet when the frame is drawn at the start of vblank
+            self.background
+        }
+    }
+
+    /// Surround the frame with the border, which is filled with the background colour of each line
+    fn draw_border(&self, frame: &mut Frame) {
+        let (width, height) = (frame.width as usize, frame.height as usize);
+        let full_width = BORDER_LEFT + width + BORDER_RIGHT;
+        let full_height = BORDER_TOP + height + BORDER_BOTTOM;
+
+        let mut bitmap = Vec::with_capacity(full_width * full_height);
+        for y in 0..full_height {
+            let colour = self.get_background_colour(self.get_border_line_background(y, height), frame.encoding);
+            if y < BORDER_TOP || y >= BORDER_TOP + height {
+                bitmap.resize(bitmap.len() + full_width, colour);
+            } else {
+                let line = (y - BORDER_TOP) * width;
+                bitmap.resize(bitmap.len() + BORDER_LEFT, colour);
+                bitmap.extend_from_slice(&frame.bitmap[line..line + width]);
+                bitmap.resize(bitmap.len() + BORDER_RIGHT, colour);
+            }
+        }
+
+        frame.width = full_width as u32;
+        frame.height = full_height as u32;
+        frame.bitmap = bitmap;
+    }
+
     fn get_hscroll(&self, hcell: usize, line: usize) -> (usize, usize) {
         let scroll_addr = match self.mode_3 & mode3::BF_H_SCROLL_MODE {
             0 => self.hscroll_addr,
@@ -514,20 +682,10 @@ impl Ym7101State {
         let max_lines = self.screen_size.1 * 8;
 
         self.sprites.clear();
-        self.sprites_by_line = vec![vec![]; max_lines];
 
         let mut link = 0;
         loop {
             let sprite = Sprite::new(&self.memory.vram[sprite_table + (link * 8)..]);
-
-            let start_y = sprite.pos.1;
-            for y in 0..(sprite.size.1 as i16 * 8) {
-                let pos_y = start_y + y;
-                if pos_y >= 0 && pos_y < max_lines as i16 {
-                    self.sprites_by_line[pos_y as usize].push(self.sprites.len());
-                }
-            }
-
             link = sprite.link as usize;
             self.sprites.push(sprite);
 
@@ -535,78 +693,110 @@ impl Ym7101State {
                 break;
             }
         }
+
+        let lines = self
+            .sprites
+            .iter()
+            .map(|sprite| (sprite.pos.1 as isize, sprite.size.1 as usize * 8));
+        self.sprites_by_line = sprites_by_line(lines, max_lines);
     }
 
-    fn get_pattern_pixel(&self, pattern_word: u16, x: usize, y: usize) -> (u8, u8) {
-        let pattern_addr = (pattern_word & 0x07FF) << 5;
+    fn get_pattern_pixel(&self, pattern_word: u16, x: usize, y: usize) -> TilePixel {
+        let pattern_addr = PATTERN_LAYOUT.tile_addr(0, (pattern_word & 0x07FF) as usize);
         let palette = ((pattern_word & 0x6000) >> 13) as u8;
-        let h_rev = (pattern_word & 0x0800) != 0;
-        let v_rev = (pattern_word & 0x1000) != 0;
-
-        let line = if !v_rev { y } else { 7 - y };
-        let column = if !h_rev { x / 2 } else { 3 - (x / 2) };
+        let priority = (pattern_word & 0x8000) != 0;
+        let flip = Flip::new((pattern_word & 0x0800) != 0, (pattern_word & 0x1000) != 0);
 
-        let offset = pattern_addr as usize + line * 4 + column;
-        let second = x % 2 == 1;
-        if (!h_rev && !second) || (h_rev && second) {
-            (palette, self.memory.vram[offset] >> 4)
-        } else {
-            (palette, self.memory.vram[offset] & 0x0f)
-        }
+        let colour = PATTERN_LAYOUT.pixel(&self.memory.vram, pattern_addr, x, y, flip);
+        TilePixel::new(palette, colour, priority)
     }
 
     fn draw_frame(&mut self, frame: &mut Frame) {
         self.build_sprites_lists();
 
-        for y in 0..(self.screen_size.1 * 8) {
-            self.draw_frame_line(frame, y);
+        // The frame is as wide as the widest line, and any narrower lines are stretched to fill it, like they
+        // would be on a TV, since the pixel clock is slower in H32 mode
+        let h_cells = self.screen_size.0;
+        let height = self.screen_size.1 * 8;
+        let line_widths: Vec<usize> = (0..height)
+            .map(|y| match self.line_h_cells.get(y) {
+                Some(cells) if *cells != 0 => *cells * 8,
+                _ => h_cells * 8,
+            })
+            .collect();
+        let frame_width = line_widths.iter().cloned().max().unwrap_or(h_cells * 8);
+        frame.set_size(frame_width as u32, height as u32);
+
+        let mut line = Frame::new(frame_width as u32, 1, frame.encoding);
+        for (y, width) in line_widths.iter().enumerate() {
+            self.set_h_cells(width / 8);
+            if *width == frame_width {
+                self.draw_frame_line(frame, y, y);
+            } else {
+                self.draw_frame_line(&mut line, y, 0);
+                for x in 0..frame_width {
+                    frame.bitmap[y * frame_width + x] = line.bitmap[x * width / frame_width];
+                }
+            }
+        }
+
+        self.set_h_cells(h_cells);
+        self.line_h_cells.iter_mut().for_each(|cells| *cells = 0);
+
+        if self.overscan {
+            self.draw_border(frame);
+        }
+    }
+
+    /// Draw a frame with only the background colour, which is what is displayed while the display is disabled
+    fn draw_blank_frame(&mut self, frame: &mut Frame) {
+        let width = self.screen_size.0.max(32) * 8;
+        let height = self.screen_size.1.max(28) * 8;
+        frame.set_size(width as u32, height as u32);
+        for y in 0..height {
+            let colour = self.get_background_colour(self.line_background[y], frame.encoding);
+            frame.bitmap[y * width..(y + 1) * width].fill(colour);
         }
+        self.draw_border(frame);
     }
 
-    fn draw_frame_line(&mut self, frame: &mut Frame, y: usize) {
-        let bg_colour = ((self.background & 0x30) >> 4, self.background & 0x0f);
+    fn draw_frame_line(&mut self, frame: &mut Frame, y: usize, frame_y: usize) {
+        let bg_colour = TilePixel::new((self.background & 0x30) >> 4, self.background & 0x0f, false);
+        let shadow_highlight = (self.mode_4 & mode4::BF_SHADOW_HIGHLIGHT) != 0;
 
         let (hscrolling_a, hscrolling_b) = self.get_hscroll(y / 8, y % 8);
         for x in 0..(self.screen_size.0 * 8) {
             let (vscrolling_a, vscrolling_b) = self.get_vscroll(x / 8);
 
-            let (priority_b, pixel_b) = if self.scroll_size != (0, 0) {
+            let pixel_b = if self.scroll_size != (0, 0) {
                 let pixel_b_x = (x - hscrolling_b) % (self.scroll_size.0 * 8);
                 let pixel_b_y = (y + vscrolling_b) % (self.scroll_size.1 * 8);
                 let pattern_b_addr = self.get_pattern_addr(self.scroll_b_addr, pixel_b_x / 8, pixel_b_y / 8);
                 let pattern_b_word = self.memory.read_beu16(Memory::Vram, pattern_b_addr);
-                let priority_b = (pattern_b_word & 0x8000) != 0;
-                let pixel_b = self.get_pattern_pixel(pattern_b_word, pixel_b_x % 8, pixel_b_y % 8);
-                (priority_b, pixel_b)
+                self.get_pattern_pixel(pattern_b_word, pixel_b_x % 8, pixel_b_y % 8)
             } else {
-                (false, (0, 0))
+                TilePixel::default()
             };
 
-            let (mut priority_a, mut pixel_a) = if self.scroll_size != (0, 0) {
+            let mut pixel_a = if self.scroll_size != (0, 0) {
                 let pixel_a_x = (x - hscrolling_a) % (self.scroll_size.0 * 8);
                 let pixel_a_y = (y + vscrolling_a) % (self.scroll_size.1 * 8);
                 let pattern_a_addr = self.get_pattern_addr(self.scroll_a_addr, pixel_a_x / 8, pixel_a_y / 8);
                 let pattern_a_word = self.memory.read_beu16(Memory::Vram, pattern_a_addr);
-                let priority_a = (pattern_a_word & 0x8000) != 0;
-                let pixel_a = self.get_pattern_pixel(pattern_a_word, pixel_a_x % 8, pixel_a_y % 8);
-                (priority_a, pixel_a)
+                self.get_pattern_pixel(pattern_a_word, pixel_a_x % 8, pixel_a_y % 8)
             } else {
-                (false, (0, 0))
+                TilePixel::default()
             };
 
-            if self.window_addr != 0 && self.is_inside_window(x, y) {
-                let pixel_win_x = x - self.window_pos.0.0 * 8;
-                let pixel_win_y = y - self.window_pos.0.1 * 8;
-                let pattern_win_addr = self.get_pattern_addr(self.window_addr, pixel_win_x / 8, pixel_win_y / 8);
+            if self.is_inside_window(x, y) {
+                let pattern_win_addr = self.get_window_pattern_addr(x / 8, y / 8);
                 let pattern_win_word = self.memory.read_beu16(Memory::Vram, pattern_win_addr);
 
                 // Scroll A is not displayed where ever the Window is displayed, so we replace Scroll A's data
-                priority_a = (pattern_win_word & 0x8000) != 0;
-                pixel_a = self.get_pattern_pixel(pattern_win_word, pixel_win_x % 8, pixel_win_y % 8);
-            };
+                pixel_a = self.get_pattern_pixel(pattern_win_word, x % 8, y % 8);
+            }
 
-            let mut pixel_sprite = (0, 0);
-            let mut priority_sprite = false;
+            let mut pixel_sprite = TilePixel::default();
             for sprite_num in self.sprites_by_line[y].iter() {
                 let sprite = &self.sprites[*sprite_num];
                 let offset_x = x as i16 - sprite.pos.0;
@@ -614,42 +804,70 @@ impl Ym7101State {
 
                 if offset_x >= 0 && offset_x < (sprite.size.0 as i16 * 8) {
                     let pattern = sprite.calculate_pattern(offset_x as usize / 8, offset_y as usize / 8);
-                    priority_sprite = (pattern & 0x8000) != 0;
-
                     pixel_sprite = self.get_pattern_pixel(pattern, offset_x as usize % 8, offset_y as usize % 8);
-                    if pixel_sprite.1 != 0 {
+                    if !pixel_sprite.is_transparent() {
                         break;
                     }
                 }
             }
 
-            #[rustfmt::skip]
-            let pixels = match (priority_sprite, priority_a, priority_b) {
-                (false, false, true)  => [ pixel_b,      pixel_sprite, pixel_a,      bg_colour ],
-                (true,  false, true)  => [ pixel_sprite, pixel_b,      pixel_a,      bg_colour ],
-                (false, true,  false) => [ pixel_a,      pixel_sprite, pixel_b,      bg_colour ],
-                (false, true,  true)  => [ pixel_a,      pixel_b,      pixel_sprite, bg_colour ],
-                _                     => [ pixel_sprite, pixel_a,      pixel_b,      bg_colour ],
+            let (pixel, mode) = if shadow_highlight {
+                self.resolve_shadow_highlight(pixel_sprite, pixel_a, pixel_b, bg_colour)
+            } else {
+                let layers = [pixel_sprite, pixel_a, pixel_b];
+                let pixel = composite(&layers).map(|i| layers[i]).unwrap_or(bg_colour);
+                (pixel, ColourMode::Normal)
             };
 
-            for (i, pixel) in pixels.iter().enumerate() {
-                if pixel.1 != 0 || i == pixels.len() - 1 {
-                    let mode = if *pixel == (3, 14) {
-                        ColourMode::Highlight
-                    } else if (!priority_a && !priority_b) || *pixel == (3, 15) {
-                        ColourMode::Shadow
-                    } else {
-                        ColourMode::Normal
-                    };
+            let colour = self.get_palette_colour(pixel.palette, pixel.colour, mode, frame.encoding);
+            frame.set_encoded_pixel(x as u32, frame_y as u32, colour);
+        }
+    }
 
-                    frame.set_encoded_pixel(x as u32, y as u32, self.get_palette_colour(pixel.0, pixel.1, mode, frame.encoding));
-                    break;
-                }
-            }
+    /// Returns the visible pixel, and whether it's shadowed or highlighted.  The pixel is shadowed unless scroll
+    /// A or B has priority, or it's from a sprite with priority.  Sprite pixels using colours 14 and 15 of the
+    /// last palette aren't drawn, and instead highlight or shadow the pixel behind them
+    fn resolve_shadow_highlight(
+        &self,
+        pixel_sprite: TilePixel,
+        pixel_a: TilePixel,
+        pixel_b: TilePixel,
+        bg_colour: TilePixel,
+    ) -> (TilePixel, ColourMode) {
+        let planes_priority = pixel_a.priority || pixel_b.priority;
+        let base_mode = if planes_priority {
+            ColourMode::Normal
+        } else {
+            ColourMode::Shadow
+        };
+
+        let is_operator = pixel_sprite.palette == 3 && (pixel_sprite.colour == 14 || pixel_sprite.colour == 15);
+        if is_operator {
+            let planes = [pixel_a, pixel_b];
+            let behind = composite(&planes).map(|i| planes[i]);
+            let pixel = behind.unwrap_or(bg_colour);
+
+            // The operator only applies if the sprite would have been drawn in front of the scroll planes
+            let covered = behind.map(|pixel| pixel.priority).unwrap_or(false) && !pixel_sprite.priority;
+            let mode = match (covered, pixel_sprite.colour, base_mode) {
+                (true, _, mode) => mode,
+                (false, 14, ColourMode::Shadow) => ColourMode::Normal,
+                (false, 14, _) => ColourMode::Highlight,
+                (false, _, _) => ColourMode::Shadow,
+            };
+            return (pixel, mode);
+        }
+
+        let layers = [pixel_sprite, pixel_a, pixel_b];
+        match composite(&layers) {
+            Some(0) if pixel_sprite.priority => (pixel_sprite, ColourMode::Normal),
+            Some(i) => (layers[i], base_mode),
+            None => (bg_colour, base_mode),
         }
     }
 }
 
+#[derive(Clone)]
 struct Sprite {
     pos: (i16, i16),
     size: (u16, u16),
@@ -680,19 +898,10 @@ impl Sprite {
     }
 
     fn calculate_pattern(&self, cell_x: usize, cell_y: usize) -> u16 {
-        let (h, v) = (
-            if !self.rev.0 {
-                cell_x
-            } else {
-                self.size.0 as usize - 1 - cell_x
-            },
-            if !self.rev.1 {
-                cell_y
-            } else {
-                self.size.1 as usize - 1 - cell_y
-            },
-        );
-        (self.pattern & 0xF800) | ((self.pattern & 0x07FF) + (h as u16 * self.size.1) + v as u16)
+        let size = (self.size.0 as usize, self.size.1 as usize);
+        let flip = Flip::new(self.rev.0, self.rev.1);
+        let cell = sprite_cell(size, (cell_x, cell_y), flip, CellOrder::ColumnMajor) as u16;
+        (self.pattern & 0xF800) | ((self.pattern & 0x07FF) + cell)
     }
 }
 
@@ -706,7 +915,7 @@ impl Steppable for Ym7101 {
             system.get_interrupt_controller().set(true, 2, 26)?;
         }
 
-        let clocks_per_pixel = 63_500 / (self.state.screen_size.0 as u32 * 8 + 88);
+        let clocks_per_pixel = self.state.timing.line_ns / (self.state.screen_size.0 as u32 * 8 + 88);
         self.state.p_clock += diff;
         if self.state.p_clock >= clocks_per_pixel {
             let pixels = self.state.p_clock / clocks_per_pixel;
@@ -715,30 +924,34 @@ impl Steppable for Ym7101 {
         }
 
         self.state.h_clock += diff;
-        if (self.state.status & status::IN_HBLANK) != 0 && self.state.h_clock >= 2_340 && self.state.h_clock <= 61_160 {
+        if (self.state.status & status::IN_HBLANK) != 0 && self.state.h_clock >= 2_340 && self.state.h_clock <= HBLANK_START_NS {
             self.state.status &= !status::IN_HBLANK;
             self.state.current_x = 0;
+            self.state.start_line();
         }
-        if (self.state.status & status::IN_HBLANK) == 0 && self.state.h_clock >= 61_160 {
+        if (self.state.status & status::IN_HBLANK) == 0 && self.state.h_clock >= HBLANK_START_NS {
             self.state.status |= status::IN_HBLANK;
             self.state.current_y += 1;
 
+            // The interrupt itself is raised by the timer scheduled for the same hblank
             self.state.h_scanlines = self.state.h_scanlines.wrapping_sub(1);
             if self.state.hsync_int_enabled() && self.state.h_scanlines == 0 {
                 self.state.h_scanlines = self.state.h_int_lines;
-                system.get_interrupt_controller().set(true, 4, 28)?;
             }
         }
-        if self.state.h_clock > 63_500 {
-            self.state.h_clock -= 63_500;
+        if self.state.h_clock > self.state.timing.line_ns {
+            self.state.h_clock -= self.state.timing.line_ns;
         }
 
+        let (active_start, active_end) = self.state.timing.active_display(self.state.screen_size.1);
         self.state.v_clock += diff;
-        if (self.state.status & status::IN_VBLANK) != 0 && self.state.v_clock >= 1_205_992 && self.state.v_clock <= 15_424_008 {
+        if (self.state.status & status::IN_VBLANK) != 0 && self.state.v_clock >= active_start && self.state.v_clock <= active_end {
             self.state.status &= !status::IN_VBLANK;
+            self.state.frame_lines = self.state.current_y as usize;
             self.state.current_y = 0;
+            self.state.start_line();
         }
-        if (self.state.status & status::IN_VBLANK) == 0 && self.state.v_clock >= 15_424_008 {
+        if (self.state.status & status::IN_VBLANK) == 0 && self.state.v_clock >= active_end {
             self.state.status |= status::IN_VBLANK;
 
             if self.state.vsync_int_enabled() {
@@ -749,13 +962,18 @@ impl Steppable for Ym7101 {
                 let mut frame =
                     Frame::new(self.state.screen_size.0 as u32 * 8, self.state.screen_size.1 as u32 * 8, self.sender.encoding());
                 self.state.draw_frame(&mut frame);
-                self.sender.add(system.clock, frame);
+                self.send_frame(system.clock, frame);
+            } else if self.state.overscan {
+                let mut frame = Frame::new(0, 0, self.sender.encoding());
+                self.state.draw_blank_frame(&mut frame);
+                self.send_frame(system.clock, frame);
             }
+            self.debug_views.draw(system.clock, &self.state);
 
             self.vsync_interrupt.signal();
         }
-        if self.state.v_clock > 16_630_000 {
-            self.state.v_clock -= 16_630_000;
+        if self.state.v_clock > self.state.timing.frame_ns {
+            self.state.v_clock -= self.state.timing.frame_ns;
         }
 
         if (self.state.mode_2 & mode2::BF_DMA_ENABLED) != 0 {
@@ -768,48 +986,211 @@ impl Steppable for Ym7101 {
                 });
         }
 
-        Ok(Frequency::from_hz(13_423_294).period_duration() * 4_u32)
+        Ok(self.state.timing.step_clock.period_duration() * 4_u32)
+    }
+
+    fn set_waker(&mut self, waker: Waker) {
+        self.waker = Some(waker);
+        self.schedule_h_interrupt();
+    }
+
+    fn on_timer(&mut self, system: &System, timer: TimerId) -> Result<(), Error> {
+        if self.h_int_timer == Some(timer) {
+            system.get_interrupt_controller().set(true, 4, 28)?;
+            if let Some(waker) = self.waker.as_ref() {
+                let lines = h_interrupt_lines(self.state.h_int_lines);
+                let delay = lines * self.state.timing.line_ns;
+                self.h_int_timer = Some(waker.schedule(system.clock + Duration::from_nanos(delay as u64)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the number of lines until the interrupt when the horizontal interrupt counter has the given value,
+/// where zero wraps around to count a full 256 lines
+fn h_interrupt_lines(counter: u8) -> u32 {
+    if counter == 0 { 256 } else { counter as u32 }
+}
+
+
+/// The senders for the debug views that the host is showing, which are drawn at the start of each vblank
+#[derive(Default)]
+struct DebugViews {
+    patterns: Option<FrameSender>,
+    plane_a: Option<FrameSender>,
+    plane_b: Option<FrameSender>,
+    sprites: Option<FrameSender>,
+    palette: Option<FrameSender>,
+}
+
+impl DebugViews {
+    fn new<H, E>(host: &mut H) -> Self
+    where
+        H: Host<Error = E>,
+    {
+        let mut add_view = |name: &str, (width, height): (u32, u32)| {
+            let (sender, receiver) = moa_host::frame_queue(width, height);
+            host.add_named_video_source(name, receiver).ok().map(|_| sender)
+        };
+
+        Self {
+            patterns: add_view(VIEW_PATTERNS, PATTERNS_SIZE),
+            plane_a: add_view(VIEW_PLANE_A, PLANE_SIZE),
+            plane_b: add_view(VIEW_PLANE_B, PLANE_SIZE),
+            sprites: add_view(VIEW_SPRITES, SPRITES_SIZE),
+            palette: add_view(VIEW_PALETTE, PALETTE_SIZE),
+        }
+    }
+
+    fn draw(&self, clock: Instant, state: &Ym7101State) {
+        if let Some(sender) = &self.patterns {
+            let mut frame = Frame::new(PATTERNS_SIZE.0, PATTERNS_SIZE.1, sender.encoding());
+            state.draw_patterns(&mut frame, 0);
+            sender.add(clock, frame);
+        }
+        let plane_size = (state.scroll_size.0 as u32 * 8, state.scroll_size.1 as u32 * 8);
+        let planes = [(&self.plane_a, state.scroll_a_addr), (&self.plane_b, state.scroll_b_addr)];
+        for (sender, table_addr) in planes {
+            if let Some(sender) = sender.as_ref().filter(|_| plane_size != (0, 0)) {
+                let mut frame = Frame::new(plane_size.0, plane_size.1, sender.encoding());
+                state.draw_plane(&mut frame, table_addr);
+                sender.add(clock, frame);
+            }
+        }
+        if let Some(sender) = &self.sprites {
+            let mut frame = Frame::new(SPRITES_SIZE.0, SPRITES_SIZE.1, sender.encoding());
+            state.draw_sprites(&mut frame);
+            sender.add(clock, frame);
+        }
+        if let Some(sender) = &self.palette {
+            let mut frame = Frame::new(PALETTE_SIZE.0, PALETTE_SIZE.1, sender.encoding());
+            state.draw_palette(&mut frame);
+            sender.add(clock, frame);
+        }
     }
 }
 
 
 pub struct Ym7101 {
     sender: FrameSender,
+    debug_views: DebugViews,
+    /// The last frame that was sent, which the next frame is compared with to find the area that changed
+    last_frame: Frame,
     state: Ym7101State,
     sn_sound: Device,
 
     pub external_interrupt: Signal<bool>,
     pub vsync_interrupt: EdgeSignal,
+
+    waker: Option<Waker>,
+    h_int_timer: Option<TimerId>,
 }
 
 impl Ym7101 {
-    pub fn new<H, E>(host: &mut H, external_interrupt: Signal<bool>, sn_sound: Device) -> Result<Ym7101, HostError<E>>
+    /// Returns the largest size of the frames that are output, which includes the border if overscan is enabled,
+    /// and the 30 cell high mode if the video standard is PAL
+    pub fn frame_size(standard: VideoStandard, overscan: bool) -> (u32, u32) {
+        let height = match standard {
+            VideoStandard::Ntsc => 224,
+            VideoStandard::Pal => 240,
+        };
+        if overscan {
+            ((BORDER_LEFT + 320 + BORDER_RIGHT) as u32, (BORDER_TOP + height + BORDER_BOTTOM) as u32)
+        } else {
+            (320, height as u32)
+        }
+    }
+
+    /// Create the VDP, which draws the border around the display with the background colour if `overscan` is true
+    pub fn new<H, E>(
+        host: &mut H,
+        external_interrupt: Signal<bool>,
+        sn_sound: Device,
+        standard: VideoStandard,
+        overscan: bool,
+    ) -> Result<Ym7101, HostError<E>>
     where
         H: Host<Error = E>,
     {
-        let (sender, receiver) = moa_host::frame_queue(320, 224);
+        let (width, height) = Self::frame_size(standard, overscan);
+        let (sender, receiver) = moa_host::frame_queue(width, height);
         host.add_video_source(receiver)?;
+        let debug_views = DebugViews::new(host);
 
-        Ok(Ym7101 {
+        let mut vdp = Ym7101 {
             sender,
-            state: Ym7101State::default(),
+            debug_views,
+            last_frame: Frame::default(),
+            state: Ym7101State {
+                overscan,
+                ..Default::default()
+            },
             sn_sound,
             external_interrupt,
             vsync_interrupt: EdgeSignal::default(),
-        })
+            waker: None,
+            h_int_timer: None,
+        };
+        vdp.set_video_standard(standard);
+        Ok(vdp)
+    }
+
+    /// Use the line and frame timing of the given video standard, which is also reported in the status register
+    pub fn set_video_standard(&mut self, standard: VideoStandard) {
+        self.state.timing = FrameTiming::new(standard);
+        self.state.status = match standard {
+            VideoStandard::Ntsc => self.state.status & !status::PAL_MODE,
+            VideoStandard::Pal => self.state.status | status::PAL_MODE,
+        };
+    }
+
+    fn send_frame(&mut self, clock: Instant, mut frame: Frame) {
+        frame.find_changes(&self.last_frame);
+        self.last_frame = frame.clone();
+        self.sender.add(clock, frame);
+    }
+
+    /// Schedule the horizontal interrupt for the hblank where the line counter will next reach zero, or cancel
+    /// it if the interrupt is disabled
+    fn schedule_h_interrupt(&mut self) {
+        let Some(waker) = self.waker.as_ref() else {
+            return;
+        };
+        if let Some(timer) = self.h_int_timer.take() {
+            waker.cancel(timer);
+        }
+        if !self.state.hsync_int_enabled() {
+            return;
+        }
+
+        let line_ns = self.state.timing.line_ns;
+        let in_hblank = (self.state.status & status::IN_HBLANK) != 0;
+        let until_hblank = if in_hblank && self.state.h_clock >= HBLANK_START_NS {
+            (line_ns + HBLANK_START_NS).saturating_sub(self.state.h_clock)
+        } else {
+            HBLANK_START_NS.saturating_sub(self.state.h_clock)
+        };
+        let lines = h_interrupt_lines(self.state.h_scanlines);
+        let delay = until_hblank + (lines - 1) * line_ns;
+        self.h_int_timer = Some(waker.schedule(self.state.last_clock + Duration::from_nanos(delay as u64)));
     }
 
     fn set_register(&mut self, word: u16) {
         let reg = ((word & 0x1F00) >> 8) as usize;
         let data = (word & 0x00FF) as u8;
-        log::debug!("{}: register {:x} set to {:x}", DEV_NAME, reg, data);
+        log::debug!(target: DEV_NAME, "register {:x} set to {:x}", reg, data);
         self.update_register_value(reg, data);
     }
 
     fn update_register_value(&mut self, reg: usize, data: u8) {
         match reg {
             reg::MODE_SET_1 => {
+                let was_enabled = self.state.hsync_int_enabled();
                 self.state.mode_1 = data;
+                if self.state.hsync_int_enabled() != was_enabled {
+                    self.schedule_h_interrupt();
+                }
             },
             reg::MODE_SET_2 => {
                 self.state.mode_2 = data;
@@ -819,7 +1200,7 @@ impl Ym7101 {
                 self.state.scroll_a_addr = (data as usize) << 10;
             },
             reg::WINDOW_ADDR => {
-                self.state.window_addr = (data as usize) << 10;
+                self.state.window_addr = ((data & 0x3E) as usize) << 10;
             },
             reg::SCROLL_B_ADDR => {
                 self.state.scroll_b_addr = (data as usize) << 13;
@@ -908,7 +1289,7 @@ impl Addressable for Ym7101 {
 
             // Read from Control Port
             0x04..=0x07 => {
-                log::debug!("{}: read status byte {:x}", DEV_NAME, self.state.status);
+                log::debug!(target: DEV_NAME, "read status byte {:x}", self.state.status);
                 for item in data {
                     *item = if (addr % 2) == 0 {
                         (self.state.status >> 8) as u8
@@ -928,7 +1309,7 @@ impl Addressable for Ym7101 {
             },
 
             _ => {
-                println!("{}: !!! unhandled read from {:x}", DEV_NAME, addr);
+                strict::unhandled_read(DEV_NAME, addr);
             },
         }
         Ok(())
@@ -941,7 +1322,7 @@ impl Addressable for Ym7101 {
 
             // Write to Control Port
             0x04 | 0x06 => {
-                log::debug!("{}: write {} bytes to port {:x} with data {:?}", DEV_NAME, data.len(), addr, data);
+                log::debug!(target: DEV_NAME, "write {} bytes to port {:x} with data {:?}", data.len(), addr, data);
 
                 let value = read_beu16(data);
                 if (value & 0xC000) == 0x8000 {
@@ -969,7 +1350,7 @@ impl Addressable for Ym7101 {
             },
 
             _ => {
-                log::warn!("{}: !!! unhandled write to {:x} with {:?}", DEV_NAME, addr, data);
+                strict::unhandled_write(DEV_NAME, addr, data);
             },
         }
         Ok(())
@@ -988,6 +1369,27 @@ impl Transmutable for Ym7101 {
     fn as_inspectable(&mut self) -> Option<&mut dyn Inspectable> {
         Some(self)
     }
+
+    fn as_snapshotable(&mut self) -> Option<&mut dyn Snapshotable> {
+        Some(self)
+    }
+
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities::probe(self) | Capabilities::VIDEO
+    }
+}
+
+impl Snapshotable for Ym7101 {
+    fn save_state(&mut self) -> Option<Box<dyn Any>> {
+        Some(Box::new((self.state.clone(), self.h_int_timer)))
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        if let Some((state, h_int_timer)) = state.downcast_ref::<(Ym7101State, Option<TimerId>)>() {
+            self.state = state.clone();
+            self.h_int_timer = *h_int_timer;
+        }
+    }
 }
 
 
@@ -1003,6 +1405,27 @@ impl Inspectable for Ym7101 {
             "vsram" => {
                 self.state.dump_vsram();
             },
+            "palette" | "cram" => {
+                self.state.dump_palette();
+            },
+            "sprites" => {
+                self.state.dump_sprites();
+            },
+            "pattern" => {
+                let pattern = args
+                    .get(1)
+                    .and_then(|arg| usize::from_str_radix(arg.trim_start_matches("0x"), 16).ok())
+                    .ok_or_else(|| Error::new("usage: inspect <vdp> pattern <hex number>"))?;
+                self.state.dump_pattern(pattern);
+            },
+            "plane" => {
+                let table_addr = match args.get(1).copied().unwrap_or("a") {
+                    "a" => self.state.scroll_a_addr,
+                    "b" => self.state.scroll_b_addr,
+                    _ => return Err(Error::new("usage: inspect <vdp> plane [a|b]")),
+                };
+                self.state.dump_plane(table_addr);
+            },
             _ => {},
         }
         Ok(())
@@ -1038,4 +1461,151 @@ impl Ym7101State {
     pub fn dump_vsram(&self) {
         dump_slice(&self.memory.vsram, 80);
     }
+
+    pub fn dump_palette(&self) {
+        for palette in 0..4 {
+            print!("Palette {}:", palette);
+            for colour in 0..16 {
+                print!(" {:03x}", self.memory.read_beu16(Memory::Cram, (palette * 16 + colour) * 2));
+            }
+            println!();
+        }
+    }
+
+    /// Returns the sprites in the order they're linked in the sprite table, along with their index in the table,
+    /// stopping at the most sprites that can be displayed, in case the links form a loop
+    fn sprite_list(&self) -> Vec<(usize, Sprite)> {
+        let mut sprites = vec![];
+        let mut link = 0;
+        while sprites.len() < 80 {
+            let sprite_data = match self.memory.vram.get(self.sprites_addr + (link * 8)..) {
+                Some(sprite_data) if sprite_data.len() >= 8 => sprite_data,
+                _ => break,
+            };
+            let sprite = Sprite::new(sprite_data);
+            let next = sprite.link as usize;
+            sprites.push((link, sprite));
+            if next == 0 {
+                break;
+            }
+            link = next;
+        }
+        sprites
+    }
+
+    pub fn dump_sprites(&self) {
+        for (link, sprite) in self.sprite_list() {
+            println!(
+                "{:2}: pos ({:4}, {:4}) size {}x{} pattern {:#06x} palette {} priority {} flip ({}, {}) link {}",
+                link,
+                sprite.pos.0,
+                sprite.pos.1,
+                sprite.size.0,
+                sprite.size.1,
+                sprite.pattern & 0x07FF,
+                (sprite.pattern & 0x6000) >> 13,
+                (sprite.pattern & 0x8000) != 0,
+                sprite.rev.0,
+                sprite.rev.1,
+                sprite.link
+            );
+        }
+    }
+
+    pub fn dump_pattern(&self, pattern: usize) {
+        println!("Pattern {:#06x}:", pattern & 0x07FF);
+        for y in 0..8 {
+            for x in 0..8 {
+                print!("{:x}", self.get_pattern_pixel((pattern & 0x07FF) as u16, x, y).colour);
+            }
+            println!();
+        }
+    }
+
+    pub fn dump_plane(&self, table_addr: usize) {
+        println!("Name table at {:#06x}, {}x{} cells:", table_addr, self.scroll_size.0, self.scroll_size.1);
+        for cell_y in 0..self.scroll_size.1 {
+            print!("{:3}:", cell_y);
+            for cell_x in 0..self.scroll_size.0 {
+                let addr = self.get_pattern_addr(table_addr, cell_x, cell_y);
+                print!(" {:04x}", self.memory.read_beu16(Memory::Vram, addr));
+            }
+            println!();
+        }
+    }
+
+    /// Draw every pattern in VRAM using the given palette, in a grid of 64 by 32 patterns
+    fn draw_patterns(&self, frame: &mut Frame, palette: u8) {
+        for pattern in 0..(64 * 32) {
+            let pattern_word = ((palette as u16) << 13) | pattern as u16;
+            self.draw_pattern(frame, pattern_word, (pattern % 64) * 8, (pattern / 64) * 8, true);
+        }
+    }
+
+    /// Draw the whole of a scroll plane's name table, without scrolling, so the frame is the size of the plane
+    fn draw_plane(&self, frame: &mut Frame, table_addr: usize) {
+        for cell_y in 0..self.scroll_size.1 {
+            for cell_x in 0..self.scroll_size.0 {
+                let pattern_addr = self.get_pattern_addr(table_addr, cell_x, cell_y);
+                let pattern_word = self.memory.read_beu16(Memory::Vram, pattern_addr);
+                self.draw_pattern(frame, pattern_word, cell_x * 8, cell_y * 8, true);
+            }
+        }
+    }
+
+    /// Draw the sprites in the order of the sprite list on the 512 by 512 pixel sprite plane, with the visible
+    /// area of the screen outlined, and the first sprites drawn on top, like they would be on the screen
+    fn draw_sprites(&self, frame: &mut Frame) {
+        frame.clear(Pixel::Rgb(0x20, 0x20, 0x20));
+
+        for (_, sprite) in self.sprite_list().iter().rev() {
+            // Only the lower 9 bits of the position are used, so the sprite plane wraps around
+            let (pos_x, pos_y) = ((sprite.pos.0 + 128) as usize & 0x1FF, (sprite.pos.1 + 128) as usize & 0x1FF);
+            for cell_y in 0..sprite.size.1 as usize {
+                for cell_x in 0..sprite.size.0 as usize {
+                    let pattern_word = sprite.calculate_pattern(cell_x, cell_y);
+                    self.draw_pattern(frame, pattern_word, pos_x + cell_x * 8, pos_y + cell_y * 8, false);
+                }
+            }
+        }
+
+        let outline = Pixel::Rgb(0xFF, 0xFF, 0xFF);
+        let (width, height) = (self.screen_size.0 as u32 * 8, self.screen_size.1 as u32 * 8);
+        for x in 128..(128 + width) {
+            frame.set_pixel(x, 128, outline);
+            frame.set_pixel(x, 128 + height, outline);
+        }
+        for y in 128..(128 + height) {
+            frame.set_pixel(128, y, outline);
+            frame.set_pixel(128 + width, y, outline);
+        }
+    }
+
+    /// Draw each of the 64 colours in CRAM as a square, with one row for each palette
+    fn draw_palette(&self, frame: &mut Frame) {
+        for palette in 0..4 {
+            for colour in 0..16 {
+                let pixel = self.get_palette_colour(palette, colour, ColourMode::Normal, frame.encoding);
+                for y in 0..PALETTE_SWATCH {
+                    for x in 0..PALETTE_SWATCH {
+                        frame.set_encoded_pixel(colour as u32 * PALETTE_SWATCH + x, palette as u32 * PALETTE_SWATCH + y, pixel);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draw a pattern with its top left corner at the given position, where colour 0 is only drawn if `opaque`
+    fn draw_pattern(&self, frame: &mut Frame, pattern_word: u16, pos_x: usize, pos_y: usize, opaque: bool) {
+        for y in 0..8 {
+            for x in 0..8 {
+                let pixel = self.get_pattern_pixel(pattern_word, x, y);
+                if !opaque && pixel.is_transparent() {
+                    continue;
+                }
+                let colour = self.get_palette_colour(pixel.palette, pixel.colour, ColourMode::Normal, frame.encoding);
+                frame.set_encoded_pixel((pos_x + x) as u32, (pos_y + y) as u32, colour);
+            }
+        }
+    }
 }
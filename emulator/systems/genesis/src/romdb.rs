@@ -0,0 +1,228 @@
+use std::fs;
+
+use sha1::{Sha1, Digest};
+
+use moa_core::Error;
+
+
+/// The checksums of a loaded ROM image
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RomHashes {
+    pub size: usize,
+    pub crc32: u32,
+    pub sha1: String,
+}
+
+impl RomHashes {
+    pub fn calculate(data: &[u8]) -> Self {
+        let mut hasher = Sha1::new();
+        hasher.update(data);
+        let sha1 = hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        Self {
+            size: data.len(),
+            crc32: crc32fast::hash(data),
+            sha1,
+        }
+    }
+}
+
+
+/// A known ROM image, as listed in a DAT file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RomEntry {
+    pub name: String,
+    pub crc32: Option<u32>,
+    pub sha1: Option<String>,
+}
+
+impl RomEntry {
+    fn matches(&self, hashes: &RomHashes) -> bool {
+        match (&self.sha1, self.crc32) {
+            (Some(sha1), _) => *sha1 == hashes.sha1,
+            (None, Some(crc32)) => crc32 == hashes.crc32,
+            (None, None) => false,
+        }
+    }
+}
+
+
+/// A database of known ROM images, loaded from No-Intro style (Logiqx XML) DAT files
+///
+/// Only the `name` attribute of `<game>` elements, and the `crc` and `sha1` attributes of their `<rom>`
+/// elements are used.  Anything else, including elements that are missing those attributes, is ignored.
+#[derive(Clone, Debug, Default)]
+pub struct RomDatabase {
+    entries: Vec<RomEntry>,
+}
+
+impl RomDatabase {
+    pub fn load_file(&mut self, filename: &str) -> Result<(), Error> {
+        let contents = fs::read_to_string(filename).map_err(|_| Error::new(format!("Error reading contents of {}", filename)))?;
+        self.add_dat(&contents);
+        Ok(())
+    }
+
+    pub fn add_dat(&mut self, contents: &str) {
+        let mut game: Option<RomEntry> = None;
+        for tag in contents.split('<').skip(1) {
+            let tag = tag.split('>').next().unwrap_or("");
+            match tag.split_whitespace().next().unwrap_or("").trim_end_matches('/') {
+                "game" | "machine" => {
+                    game = get_attribute(tag, "name").map(|name| RomEntry {
+                        name,
+                        crc32: None,
+                        sha1: None,
+                    });
+                },
+                "rom" => {
+                    if let Some(game) = game.as_ref() {
+                        // A game can have more than one ROM, so each one gets its own entry
+                        self.entries.push(RomEntry {
+                            crc32: get_attribute(tag, "crc").and_then(|crc| u32::from_str_radix(&crc, 16).ok()),
+                            sha1: get_attribute(tag, "sha1").map(|sha1| sha1.to_lowercase()),
+                            ..game.clone()
+                        });
+                    }
+                },
+                "/game" | "/machine" => game = None,
+                _ => {},
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn identify(&self, hashes: &RomHashes) -> Option<&RomEntry> {
+        self.entries.iter().find(|entry| entry.matches(hashes))
+    }
+}
+
+fn get_attribute(tag: &str, name: &str) -> Option<String> {
+    let pattern = format!("{}=\"", name);
+    let mut rest = tag;
+    while let Some(index) = rest.find(&pattern) {
+        // Only match whole attribute names, such as `name` but not `filename`
+        let whole_name = rest[..index].ends_with(char::is_whitespace);
+        rest = &rest[index + pattern.len()..];
+        if whole_name {
+            let end = rest.find('"')?;
+            return Some(unescape(&rest[..end]));
+        }
+    }
+    None
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAT: &str = r#"<?xml version="1.0"?>
+<datafile>
+    <header>
+        <name>Sega - Mega Drive - Genesis</name>
+    </header>
+    <game name="First Game (USA)">
+        <description>First Game (USA)</description>
+        <rom name="First Game (USA).md" size="9" crc="CBF43926" sha1="F7C3BC1D808E04732ADF679965CCC34CA7AE3441"/>
+    </game>
+    <game name="Second &amp; Third (Europe)">
+        <rom name="Second.md" size="4" crc="00000002"/>
+        <rom name="Third.md" size="4" crc="00000003" sha1="0000000000000000000000000000000000000003"/>
+    </game>
+</datafile>
+"#;
+
+    fn hashes(crc32: u32, sha1: &str) -> RomHashes {
+        RomHashes {
+            size: 4,
+            crc32,
+            sha1: sha1.to_string(),
+        }
+    }
+
+    #[test]
+    fn calculates_checksums() {
+        let hashes = RomHashes::calculate(b"123456789");
+        assert_eq!(hashes.size, 9);
+        assert_eq!(hashes.crc32, 0xcbf43926);
+        assert_eq!(hashes.sha1, "f7c3bc1d808e04732adf679965ccc34ca7ae3441");
+    }
+
+    #[test]
+    fn loads_an_entry_for_each_rom() {
+        let mut database = RomDatabase::default();
+        database.add_dat(DAT);
+        assert_eq!(database.len(), 3);
+
+        let entry = database.identify(&RomHashes::calculate(b"123456789")).unwrap();
+        assert_eq!(entry.name, "First Game (USA)");
+        assert_eq!(entry.crc32, Some(0xcbf43926));
+        assert_eq!(entry.sha1.as_deref(), Some("f7c3bc1d808e04732adf679965ccc34ca7ae3441"));
+
+        let entry = database.identify(&hashes(2, "unknown")).unwrap();
+        assert_eq!(entry.name, "Second & Third (Europe)");
+        assert_eq!(entry.sha1, None);
+    }
+
+    #[test]
+    fn matches_the_sha1_before_the_crc() {
+        let mut database = RomDatabase::default();
+        database.add_dat(DAT);
+
+        let sha1 = "0000000000000000000000000000000000000003";
+        assert_eq!(database.identify(&hashes(3, sha1)).unwrap().crc32, Some(3));
+        // The CRC of an entry with a SHA1 isn't enough to match it
+        assert!(database.identify(&hashes(3, "unknown")).is_none());
+        assert!(database.identify(&hashes(4, "unknown")).is_none());
+    }
+
+    #[test]
+    fn reads_machine_elements_and_attributes_on_other_lines() {
+        let mut database = RomDatabase::default();
+        database.add_dat("<machine\n\tname=\"Arcade\"><rom\n  filename=\"x\"\n  name=\"a.bin\"\n  crc=\"0000abcd\"/></machine>");
+        assert_eq!(database.len(), 1);
+        assert_eq!(database.identify(&hashes(0xabcd, "unknown")).unwrap().name, "Arcade");
+    }
+
+    #[test]
+    fn ignores_malformed_entries() {
+        let mut database = RomDatabase::default();
+        database.add_dat(
+            r#"
+            <rom name="outside.md" crc="00000001"/>
+            <game description="no name"><rom name="unnamed.md" crc="00000002"/></game>
+            <game name="Bad Checksums"><rom name="bad.md" crc="xyz"/><rom name="open.md" crc="00000003></game>
+            <game name="Unterminated"
+            "#,
+        );
+
+        // The ROMs with checksums that can't be read are still listed, but never match
+        assert_eq!(database.len(), 2);
+        assert!(database.identify(&hashes(1, "unknown")).is_none());
+        assert!(database.identify(&hashes(2, "unknown")).is_none());
+        assert!(database.identify(&hashes(3, "unknown")).is_none());
+    }
+
+    #[test]
+    fn fails_to_load_a_missing_file() {
+        let mut database = RomDatabase::default();
+        assert!(database.load_file("/nonexistent/moa-test.dat").is_err());
+        assert!(database.is_empty());
+    }
+}
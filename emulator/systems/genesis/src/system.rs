@@ -1,10 +1,15 @@
+use std::fmt;
 use std::mem;
+use std::path::Path;
 use std::rc::Rc;
 use std::cell::RefCell;
 
-use femtos::Frequency;
+use femtos::{Frequency, Duration};
 
-use moa_core::{System, Error, MemoryBlock, Bus, Address, Addressable, Device};
+use moa_core::{
+    System, Error, MemoryBlock, MemoryRegion, Bus, BusPort, BankedRegion, BankSwitch, Address, Addressable, Device, StepPriority,
+    MachineInfo, Interleave, VideoStandard, Transmutable, ParallelSteppable, ThreadedDevice,
+};
 use moa_host::Host;
 
 use moa_m68k::{M68k, M68kType};
@@ -13,14 +18,44 @@ use moa_peripherals_yamaha::Ym2612;
 use moa_peripherals_yamaha::Sn76489;
 
 use crate::utils;
+use crate::romdb::RomDatabase;
+use crate::peripherals::cartridge::Cartridge;
+use crate::peripherals::backup::{BackupMemory, CartridgeSram, CartridgeEeprom};
 use crate::peripherals::ym7101::Ym7101;
 use crate::peripherals::controllers::GenesisControllers;
-use crate::peripherals::coprocessor::{CoprocessorCoordinator, CoprocessorBankArea};
+use crate::peripherals::coprocessor::{CoprocessorCoordinator, CoprocessorBusGate, CoprocessorSignals};
+use crate::peripherals::segacd::cdd::CdDrive;
+use crate::peripherals::segacd::cdrom::CdImage;
+use crate::peripherals::segacd::gate_array::{GateArray, MainRegisters, SubRegisters, SegaCdArea, BIOS_SIZE};
+use crate::peripherals::segacd::memory::{SegaCdMemory, BackupRam};
+use crate::peripherals::segacd::sub_cpu::SubCpu;
 
 
 pub struct SegaGenesisOptions {
     pub rom: String,
     pub rom_data: Option<Vec<u8>>,
+    pub rom_database: Option<String>,
+    /// Only read the controller inputs from the host at the start of each vblank, so that the input latency
+    /// is the same relative to the guest's frames every time
+    pub poll_inputs_at_vblank: bool,
+    /// Connect 3-button controllers instead of 6-button controllers, since some older games misread 6-button ones
+    pub three_button: bool,
+    /// Draw the border around the display in the background colour, which some software changes for effects
+    pub overscan: bool,
+    /// Stall the 68000 for the cycles used by DRAM refresh, which slows it down by about 1.5%, for software with
+    /// cycle-counted loops that drift without it
+    pub refresh_cycles: bool,
+    /// Let each cpu run up to this many microseconds ahead of the other devices before switching, which is faster
+    /// but less accurate than interleaving every instruction
+    pub interleave_us: Option<u64>,
+    /// The BIOS of the Sega CD, which is attached instead of a cartridge when given
+    pub cd_bios: Option<String>,
+    /// The CDROM image to insert into the Sega CD, either a .cue sheet or an .iso file
+    pub cd_image: Option<String>,
+    /// Build a PAL console instead of an NTSC one, which runs at 50 frames per second with slower clocks
+    pub video_standard: VideoStandard,
+    /// Step the sound chips on threads of their own, in parallel with the cpus
+    pub threaded_audio: bool,
 }
 
 impl Default for SegaGenesisOptions {
@@ -28,26 +63,90 @@ impl Default for SegaGenesisOptions {
         Self {
             rom: "".to_string(),
             rom_data: None,
+            rom_database: None,
+            poll_inputs_at_vblank: false,
+            three_button: false,
+            overscan: false,
+            refresh_cycles: false,
+            interleave_us: None,
+            cd_bios: None,
+            cd_image: None,
+            video_standard: VideoStandard::Ntsc,
+            threaded_audio: false,
         }
     }
 }
 
+impl fmt::Debug for SegaGenesisOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SegaGenesisOptions")
+            .field("rom", &self.rom)
+            .field("rom_data", &self.rom_data.as_ref().map(|data| format!("<{} bytes>", data.len())))
+            .field("rom_database", &self.rom_database)
+            .field("poll_inputs_at_vblank", &self.poll_inputs_at_vblank)
+            .field("three_button", &self.three_button)
+            .field("overscan", &self.overscan)
+            .field("refresh_cycles", &self.refresh_cycles)
+            .field("interleave_us", &self.interleave_us)
+            .field("cd_bios", &self.cd_bios)
+            .field("cd_image", &self.cd_image)
+            .field("video_standard", &self.video_standard)
+            .field("threaded_audio", &self.threaded_audio)
+            .finish()
+    }
+}
+
 pub fn build_genesis<H: Host>(host: &mut H, mut options: SegaGenesisOptions) -> Result<System, Error> {
     let mut system = System::default();
+    if let Some(micros) = options.interleave_us {
+        system.set_interleave(Interleave::Slice(Duration::from_micros(micros)));
+    }
+
+    let (width, height) = Ym7101::frame_size(options.video_standard, options.overscan);
+    system.machine_info = MachineInfo::new("genesis")
+        .with_video(width, height, options.video_standard.refresh_rate())
+        .with_video_standard(options.video_standard)
+        .with_aspect_ratio(4.0 / 3.0);
+    let (cpu_clock, coproc_clock) = cpu_clocks(options.video_standard);
 
-    let rom_data = if options.rom_data.is_some() {
-        mem::take(&mut options.rom_data).unwrap()
+    let mut backup_switch = None;
+    let rom_end = if let Some(bios) = options.cd_bios.as_ref() {
+        system.machine_info = system.machine_info.with_rom(bios);
+        add_sega_cd(&mut system, bios, options.cd_image.as_deref())?;
+        BIOS_SIZE
     } else {
-        utils::load_rom_file(&options.rom)?
-    };
+        if options.rom_data.is_none() {
+            system.machine_info = system.machine_info.with_rom(&options.rom);
+        }
+
+        let rom_data = if options.rom_data.is_some() {
+            mem::take(&mut options.rom_data).unwrap()
+        } else {
+            utils::load_rom_file(&options.rom)?
+        };
 
-    let rom = MemoryBlock::new(rom_data);
-    //rom.read_only();
-    let rom_end = rom.size();
-    system.add_addressable_device(0x00000000, Device::new(rom))?;
+        let mut database = RomDatabase::default();
+        if let Some(filename) = options.rom_database.as_ref() {
+            database.load_file(filename)?;
+        }
 
-    let cartridge_nvram = MemoryBlock::new(vec![0; 0x400000 - rom_end]);
-    system.add_addressable_device(rom_end as Address, Device::new(cartridge_nvram))?;
+        let rom = Cartridge::new(rom_data, &database);
+        let rom_end = rom.size();
+        match rom.backup_memory() {
+            Some(backup) => {
+                let cartridge = Device::new(rom);
+                system.add_device("cartridge", cartridge.clone())?;
+                backup_switch = add_backup_memory(&mut system, &options.rom, cartridge, rom_end, backup)?;
+            },
+            None => {
+                system.add_peripheral("cartridge", 0x00000000, Device::new(rom))?;
+
+                let cartridge_nvram = MemoryBlock::new(vec![0; 0x400000 - rom_end]);
+                system.add_addressable_device(rom_end as Address, Device::new(cartridge_nvram))?;
+            },
+        }
+        rom_end
+    };
 
     let ram = MemoryBlock::new(vec![0; 0x00010000]);
     system.add_addressable_device(0x00ff0000, Device::new(ram))?;
@@ -55,9 +154,15 @@ pub fn build_genesis<H: Host>(host: &mut H, mut options: SegaGenesisOptions) ->
 
     // Build the Coprocessor's Bus
     let coproc_ram = Device::new(MemoryBlock::new(vec![0; 0x00002000]));
-    let coproc_ym_sound = Device::new(Ym2612::new(host, Frequency::from_hz(7_670_454))?);
-    let coproc_sn_sound = Device::new(Sn76489::new(host, Frequency::from_hz(3_579_545))?);
-    let (coproc_area, coproc_register) = CoprocessorBankArea::new(system.bus.clone());
+    let coproc_ym_sound = sound_device(Ym2612::new(host, cpu_clock)?, options.threaded_audio);
+    let coproc_sn_sound = sound_device(Sn76489::new(host, coproc_clock)?, options.threaded_audio);
+    // The Z80 sees a 32KB window onto the 68000's bus, whose bank number is shifted in one bit at a time from the MSB
+    let main_bus = Device::new(BusPort::new(0, 24, 8, system.bus.clone()));
+    let coproc_area = BankedRegion::new(1, 0x8000).with_banks(main_bus, 0x200);
+    let coproc_register = coproc_area.register_with(0x01, |switch, _, data| {
+        let bank = (switch.selected(0) >> 1) | (((data & 0x01) as usize) << 8);
+        switch.select(0, bank);
+    });
     let coproc_area = Device::new(coproc_area);
     let coproc_register = Device::new(coproc_register);
 
@@ -68,38 +173,190 @@ pub fn build_genesis<H: Host>(host: &mut H, mut options: SegaGenesisOptions) ->
     coproc_bus.borrow_mut().insert(0x6000, coproc_register.clone());
     coproc_bus.borrow_mut().insert(0x7f11, coproc_sn_sound.clone());
     coproc_bus.borrow_mut().insert(0x8000, coproc_area);
-    let coproc = Z80::from_type(Z80Type::Z80, Frequency::from_hz(3_579_545));
-    let coproc = MoaZ80 {
-        bus: coproc_bus,
-        cpu: coproc,
+    let coproc = Z80::from_type(Z80Type::Z80, coproc_clock);
+    let coproc = MoaZ80::new(coproc, coproc_bus.clone());
+    let mut coproc_signals = CoprocessorSignals {
+        reset: coproc.cpu.signals.reset.clone(),
+        bus_request: coproc.cpu.signals.bus_request.clone(),
+        bus_ack: coproc.cpu.signals.bus_ack.clone(),
     };
-    let mut reset = coproc.cpu.signals.reset.clone();
-    let mut bus_request = coproc.cpu.signals.bus_request.clone();
-    reset.set(true);
-    bus_request.set(true);
+    coproc_signals.reset.set(true);
+    coproc_signals.bus_request.set(true);
     let coproc = Device::new(coproc);
 
-    // Add coprocessor devices to the system bus so the 68000 can access them too
-    system.add_addressable_device(0x00a00000, coproc_ram)?;
-    system.add_addressable_device(0x00a04000, coproc_ym_sound)?;
-    system.add_addressable_device(0x00a06000, coproc_register)?;
+    // Add coprocessor devices to the system bus so the 68000 can access them too, when it has the Z80's bus
+    for (base, device, size) in
+        [(0x00a00000, coproc_ram, 0x2000), (0x00a04000, coproc_ym_sound.clone(), 0x04), (0x00a06000, coproc_register, 0x01)]
+    {
+        let gate = CoprocessorBusGate::new(device, size, coproc_signals.clone());
+        system.add_addressable_device(base, Device::new(gate))?;
+    }
     //system.add_addressable_device(0x00c00010, coproc_sn_sound)?;
+    system.add_bus("coproc", coproc_bus);
+    system.add_device("ym_sound", coproc_ym_sound.clone())?;
     system.add_device("sn_sound", coproc_sn_sound.clone())?;
     system.add_device("coproc", coproc.clone())?;
+    system.set_step_priority(&coproc_ym_sound, StepPriority::AUDIO)?;
+    system.set_step_priority(&coproc_sn_sound, StepPriority::AUDIO)?;
 
 
-    let controllers = GenesisControllers::new(host)?;
+    let mut controllers = GenesisControllers::new(host)?;
     let interrupt = controllers.get_interrupt_signal();
+    let vdp = Ym7101::new(host, interrupt, coproc_sn_sound, options.video_standard, options.overscan)?;
+    if options.poll_inputs_at_vblank {
+        controllers.poll_at_vblank(vdp.vsync_interrupt.clone());
+    }
+    if options.three_button {
+        controllers.use_three_button();
+    }
+    controllers.set_video_standard(options.video_standard);
     system.add_addressable_device(0x00a10000, Device::new(controllers))?;
 
-    let coproc = CoprocessorCoordinator::new(reset, bus_request);
+    let coproc = CoprocessorCoordinator::new(coproc_signals);
     system.add_addressable_device(0x00a11000, Device::new(coproc))?;
 
-    let vdp = Ym7101::new(host, interrupt, coproc_sn_sound)?;
-    system.add_peripheral("vdp", 0x00c00000, Device::new(vdp))?;
+    let vdp = Device::new(vdp);
+    system.add_peripheral("vdp", 0x00c00000, vdp.clone())?;
+    system.set_step_priority(&vdp, StepPriority::VIDEO)?;
 
-    let cpu = M68k::from_type(M68kType::MC68000, Frequency::from_hz(7_670_454));
+    let mut cpu = M68k::from_type(M68kType::MC68000, cpu_clock);
+    if options.refresh_cycles {
+        // The refresh takes about 2 out of every 128 cycles
+        cpu.set_refresh_timing(128, 2);
+    }
+    // The Sega CD swaps its memory in and out of the main cpu's address space without writing to it
+    if options.cd_bios.is_none() {
+        cpu.cache_instructions(&mut system.get_bus());
+        // The backup memory can be switched in place of the second half of a big ROM, which replaces the code there
+        if let Some(switch) = backup_switch.as_ref() {
+            system.get_bus().observe_bank_switches(0x00000000, switch);
+        }
+    }
     system.add_interruptable_device("cpu", Device::new(cpu))?;
 
+    // Name the main areas of the address space for the debugger
+    let rom_name = if options.cd_bios.is_some() { "cd_bios" } else { "cartridge" };
+    for (name, base, size) in [
+        (rom_name, 0x00000000, rom_end as Address),
+        ("coproc_ram", 0x00a00000, 0x2000),
+        ("vdp", 0x00c00000, 0x20),
+        ("work_ram", 0x00ff0000, 0x10000),
+    ] {
+        system.get_bus().add_region(MemoryRegion::new(name, base, size));
+    }
+
     Ok(system)
 }
+
+/// Returns the clocks of the 68000 and the Z80, which are divided by 7 and 15 from a master clock of 15 times the
+/// colour subcarrier on NTSC consoles, and 12 times on PAL consoles
+fn cpu_clocks(standard: VideoStandard) -> (Frequency, Frequency) {
+    match standard {
+        VideoStandard::Ntsc => (Frequency::from_hz(7_670_454), Frequency::from_hz(3_579_545)),
+        VideoStandard::Pal => (Frequency::from_hz(7_600_489), Frequency::from_hz(3_546_895)),
+    }
+}
+
+/// Returns the device for a sound chip, which is stepped on a thread of its own if `threaded` is set
+fn sound_device<T>(chip: T, threaded: bool) -> Device
+where
+    T: Transmutable + ParallelSteppable + 'static,
+{
+    if threaded {
+        Device::new(ThreadedDevice::new(chip))
+    } else {
+        Device::new(chip)
+    }
+}
+
+/// Map the cartridge's SRAM or EEPROM at 0x200000, along with the register at 0xA130F1 that controls it, and save it
+/// to a .sav file next to the ROM if it's battery backed
+///
+/// On cartridges of 2MB or less, the backup memory is always mapped.  On bigger cartridges, it shares the second 2MB
+/// with the ROM, which is visible until bit 0 of the register is set, and the switch between them is returned.  Bit 1
+/// of the register write protects the SRAM
+fn add_backup_memory(
+    system: &mut System,
+    rom: &str,
+    cartridge: Device,
+    rom_end: usize,
+    backup: BackupMemory,
+) -> Result<Option<BankSwitch>, Error> {
+    const WINDOW_SIZE: usize = 0x200000;
+
+    let memory = Device::new(MemoryBlock::new(vec![0; backup.size()]));
+    let (window, write_protect) = match backup {
+        BackupMemory::Sram {
+            layout,
+            ..
+        } => {
+            let sram = CartridgeSram::new(memory.clone(), layout, WINDOW_SIZE);
+            let write_protect = sram.write_protect();
+            (Device::new(sram), Some(write_protect))
+        },
+        BackupMemory::Eeprom => (Device::new(CartridgeEeprom::new(memory.clone(), WINDOW_SIZE)), None),
+    };
+
+    let shared = rom_end > WINDOW_SIZE;
+    let area = BankedRegion::new(2, WINDOW_SIZE)
+        .with_banks(cartridge, 2)
+        .with_device(window.clone());
+    let switch = area.switch();
+    if !shared {
+        switch.select(1, 2);
+    }
+    let register = area.register_with(0x01, move |switch, _, data| {
+        if shared {
+            switch.select(1, if (data & 0x01) != 0 { 2 } else { 1 });
+        }
+        if let Some(write_protect) = write_protect.as_ref() {
+            write_protect.set((data & 0x02) != 0);
+        }
+    });
+    system.add_addressable_device(0x00000000, Device::new(area))?;
+    system.add_addressable_device(0x00a130f1, Device::new(register))?;
+    // The backup memory is only reachable through the banked region, so it's added by name to have its state saved
+    system.add_device("backup", window)?;
+    system.add_device("backup_memory", memory.clone())?;
+
+    if backup.is_saved() {
+        let filename = if rom.is_empty() {
+            "cartridge.sav".to_string()
+        } else {
+            Path::new(rom).with_extension("sav").to_string_lossy().into_owned()
+        };
+        log::info!("cartridge backup memory will be saved to {}", filename);
+        system.add_battery_backed_memory(&filename, memory)?;
+    }
+    Ok(shared.then_some(switch))
+}
+
+/// Attach a Sega CD in place of the cartridge, which has its own 68000 with a separate bus, and shares its memory
+/// with the main cpu through the gate array
+fn add_sega_cd(system: &mut System, bios: &str, cd_image: Option<&str>) -> Result<(), Error> {
+    let bios = utils::load_rom_file(bios)?;
+    let disc = cd_image.map(CdImage::load).transpose()?;
+    let gate_array = Rc::new(RefCell::new(GateArray::new(bios, CdDrive::new(disc))));
+
+    let area = |area| Device::new(SegaCdMemory::new(gate_array.clone(), area));
+    system.add_addressable_device(0x00000000, area(SegaCdArea::Bios))?;
+    system.add_addressable_device(0x00020000, area(SegaCdArea::MainPrgRam))?;
+    system.add_addressable_device(0x00200000, area(SegaCdArea::MainWordRam))?;
+    system.add_addressable_device(0x00a12000, Device::new(MainRegisters::new(gate_array.clone())))?;
+
+    let sub_registers = Device::new(SubRegisters::new(gate_array.clone()));
+    let sub_bus = Rc::new(RefCell::new(Bus::default()));
+    sub_bus.borrow_mut().set_ignore_unmapped(true);
+    sub_bus.borrow_mut().insert(0x000000, area(SegaCdArea::SubPrgRam));
+    sub_bus.borrow_mut().insert(0x080000, area(SegaCdArea::SubWordRam2M));
+    sub_bus.borrow_mut().insert(0x0c0000, area(SegaCdArea::SubWordRam1M));
+    sub_bus.borrow_mut().insert(0xfe0000, Device::new(BackupRam::default()));
+    // TODO the PCM sound chip isn't emulated, so its registers and wave RAM are just memory
+    let pcm = MemoryBlock::new(vec![0; 0x4000]);
+    sub_bus.borrow_mut().insert(0xff0000, Device::new(pcm));
+    sub_bus.borrow_mut().insert(0xff8000, sub_registers.clone());
+
+    system.add_device("segacd", sub_registers)?;
+    system.add_device("segacd_cpu", Device::new(SubCpu::new(sub_bus, gate_array)))?;
+    Ok(())
+}
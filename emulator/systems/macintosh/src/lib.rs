@@ -1,4 +1,4 @@
 pub mod peripherals;
 
 mod system;
-pub use crate::system::{build_macintosh_512k};
+pub use crate::system::{MacintoshOptions, build_macintosh_512k};
@@ -0,0 +1,218 @@
+use std::fs;
+
+use moa_core::Error;
+
+
+/// The number of tracks on each side of a 400K or 800K disk
+pub const FLOPPY_TRACKS: usize = 80;
+
+const SECTOR_SIZE: usize = 512;
+const TAG_SIZE: usize = 12;
+
+const SINGLE_SIDED_SIZE: usize = 409_600;
+const DOUBLE_SIDED_SIZE: usize = 819_200;
+
+/// The header of a DiskCopy 4.2 image, which is followed by the sector data and then the tag data
+const DISKCOPY_HEADER_SIZE: usize = 0x54;
+const DISKCOPY_DATA_SIZE: usize = 0x40;
+const DISKCOPY_TAG_SIZE: usize = 0x44;
+const DISKCOPY_MAGIC: usize = 0x52;
+
+/// The number of self-sync bytes before each address and data field
+const SYNC_BYTES: usize = 6;
+/// The number of self-sync bytes at the end of each track, before it repeats
+const TRACK_GAP: usize = 32;
+
+#[rustfmt::skip]
+const GCR_ENCODE: [u8; 64] = [
+    0x96, 0x97, 0x9A, 0x9B, 0x9D, 0x9E, 0x9F, 0xA6,
+    0xA7, 0xAB, 0xAC, 0xAD, 0xAE, 0xAF, 0xB2, 0xB3,
+    0xB4, 0xB5, 0xB6, 0xB7, 0xB9, 0xBA, 0xBB, 0xBC,
+    0xBD, 0xBE, 0xBF, 0xCB, 0xCD, 0xCE, 0xCF, 0xD3,
+    0xD6, 0xD7, 0xD9, 0xDA, 0xDB, 0xDC, 0xDD, 0xDE,
+    0xDF, 0xE5, 0xE6, 0xE7, 0xE9, 0xEA, 0xEB, 0xEC,
+    0xED, 0xEE, 0xEF, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6,
+    0xF7, 0xF9, 0xFA, 0xFB, 0xFC, 0xFD, 0xFE, 0xFF,
+];
+
+/// Returns the number of sectors per track, which decreases every 16 tracks towards the center of the disk
+pub fn sectors_per_track(track: usize) -> usize {
+    12 - (track / 16)
+}
+
+#[inline]
+fn gcr(value: u8) -> u8 {
+    GCR_ENCODE[(value & 0x3F) as usize]
+}
+
+/// A 400K or 800K disk, stored as the GCR-encoded bytes that the drive reads from each track
+pub struct MacDisk {
+    sides: usize,
+    tracks: Vec<Vec<u8>>,
+}
+
+impl MacDisk {
+    /// Load either a raw sector image or a DiskCopy 4.2 image
+    pub fn load(filename: &str) -> Result<Self, Error> {
+        let contents = fs::read(filename).map_err(|_| Error::new(format!("Error reading contents of {}", filename)))?;
+
+        match contents.len() {
+            SINGLE_SIDED_SIZE | DOUBLE_SIDED_SIZE => Self::from_sectors(&contents, None),
+            _ if contents.len() > DISKCOPY_HEADER_SIZE && contents[DISKCOPY_MAGIC..DISKCOPY_MAGIC + 2] == [0x01, 0x00] => {
+                let data_size = read_beu32(&contents[DISKCOPY_DATA_SIZE..]) as usize;
+                let tag_size = read_beu32(&contents[DISKCOPY_TAG_SIZE..]) as usize;
+                let data_end = DISKCOPY_HEADER_SIZE + data_size;
+                if contents.len() < data_end + tag_size {
+                    return Err(Error::new(format!("DiskCopy image {} is truncated", filename)));
+                }
+                let tags = if tag_size > 0 {
+                    Some(&contents[data_end..data_end + tag_size])
+                } else {
+                    None
+                };
+                Self::from_sectors(&contents[DISKCOPY_HEADER_SIZE..data_end], tags)
+            },
+            size => Err(Error::new(format!("{} is not a 400K or 800K disk image (size {})", filename, size))),
+        }
+    }
+
+    /// Encode the sectors of a disk, in the order of track, side, and then sector, with optional tags for each sector
+    pub fn from_sectors(sectors: &[u8], tags: Option<&[u8]>) -> Result<Self, Error> {
+        let sides = match sectors.len() {
+            SINGLE_SIDED_SIZE => 1,
+            DOUBLE_SIDED_SIZE => 2,
+            size => return Err(Error::new(format!("disk image must be 400K or 800K, but it's {} bytes", size))),
+        };
+
+        let mut tracks = Vec::with_capacity(FLOPPY_TRACKS * sides);
+        let mut sector_index = 0;
+        for track in 0..FLOPPY_TRACKS {
+            for side in 0..sides {
+                let count = sectors_per_track(track);
+                let mut encoded = vec![];
+                for sector in 0..count {
+                    let mut block = [0; TAG_SIZE + SECTOR_SIZE];
+                    if let Some(tags) = tags {
+                        let start = sector_index * TAG_SIZE;
+                        if start + TAG_SIZE <= tags.len() {
+                            block[..TAG_SIZE].copy_from_slice(&tags[start..start + TAG_SIZE]);
+                        }
+                    }
+                    let start = sector_index * SECTOR_SIZE;
+                    block[TAG_SIZE..].copy_from_slice(&sectors[start..start + SECTOR_SIZE]);
+
+                    encode_sector(&mut encoded, track, side, sector, sides, &block);
+                    sector_index += 1;
+                }
+                encoded.extend_from_slice(&[0xFF; TRACK_GAP]);
+                tracks.push(encoded);
+            }
+        }
+
+        Ok(Self {
+            sides,
+            tracks,
+        })
+    }
+
+    pub fn sides(&self) -> usize {
+        self.sides
+    }
+
+    /// Returns the encoded bytes of the given track and side, or None if the disk doesn't have that side
+    pub fn track(&self, track: usize, side: usize) -> Option<&[u8]> {
+        if track >= FLOPPY_TRACKS || side >= self.sides {
+            return None;
+        }
+        Some(&self.tracks[track * self.sides + side])
+    }
+}
+
+fn read_beu32(data: &[u8]) -> u32 {
+    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+fn encode_sector(
+    output: &mut Vec<u8>,
+    track: usize,
+    side: usize,
+    sector: usize,
+    sides: usize,
+    block: &[u8; TAG_SIZE + SECTOR_SIZE],
+) {
+    let track_low = (track & 0x3F) as u8;
+    let sector = sector as u8;
+    let side_track_high = ((side as u8) << 5) | ((track >> 6) as u8 & 0x01);
+    let format = if sides == 2 { 0x22 } else { 0x02 };
+    let checksum = track_low ^ sector ^ side_track_high ^ format;
+
+    output.extend_from_slice(&[0xFF; SYNC_BYTES]);
+    output.extend_from_slice(&[0xD5, 0xAA, 0x96]);
+    output.extend_from_slice(&[gcr(track_low), gcr(sector), gcr(side_track_high), gcr(format), gcr(checksum)]);
+    output.extend_from_slice(&[0xDE, 0xAA, 0xFF]);
+
+    output.extend_from_slice(&[0xFF; SYNC_BYTES]);
+    output.extend_from_slice(&[0xD5, 0xAA, 0xAD]);
+    output.push(gcr(sector));
+    encode_data(output, block);
+    output.extend_from_slice(&[0xDE, 0xAA, 0xFF]);
+}
+
+/// Encode the 524 bytes of tags and data into 699 bytes, followed by 4 bytes of checksum, using the same
+/// running checksum as the Sony driver, where each group of 3 bytes is split into 4 6-bit values
+fn encode_data(output: &mut Vec<u8>, block: &[u8; TAG_SIZE + SECTOR_SIZE]) {
+    let mut b1 = [0u8; 175];
+    let mut b2 = [0u8; 175];
+    let mut b3 = [0u8; 175];
+    let (mut c1, mut c2, mut c3) = (0u32, 0u32, 0u32);
+
+    let mut j = 0;
+    for i in 0..175 {
+        c1 = (c1 & 0xFF) << 1;
+        if c1 & 0x100 != 0 {
+            c1 += 1;
+        }
+
+        let value = block[j] as u32;
+        j += 1;
+        c3 += value;
+        if c1 & 0x100 != 0 {
+            c3 += 1;
+            c1 &= 0xFF;
+        }
+        b1[i] = (value ^ c1) as u8;
+
+        let value = block[j] as u32;
+        j += 1;
+        c2 += value;
+        if c3 > 0xFF {
+            c2 += 1;
+            c3 &= 0xFF;
+        }
+        b2[i] = (value ^ c3) as u8;
+
+        if j == block.len() {
+            break;
+        }
+
+        let value = block[j] as u32;
+        j += 1;
+        c1 += value;
+        if c2 > 0xFF {
+            c1 += 1;
+            c2 &= 0xFF;
+        }
+        b3[i] = (value ^ c2) as u8;
+    }
+
+    for i in 0..175 {
+        let high = ((b1[i] & 0xC0) >> 2) | ((b2[i] & 0xC0) >> 4) | ((b3[i] & 0xC0) >> 6);
+        output.extend_from_slice(&[gcr(high), gcr(b1[i]), gcr(b2[i])]);
+        if i != 174 {
+            output.push(gcr(b3[i]));
+        }
+    }
+
+    let high = ((c1 & 0xC0) >> 6) | ((c2 & 0xC0) >> 4) | ((c3 & 0xC0) >> 2);
+    output.extend_from_slice(&[gcr(high as u8), gcr(c3 as u8), gcr(c2 as u8), gcr(c1 as u8)]);
+}
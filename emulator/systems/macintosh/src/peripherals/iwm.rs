@@ -1,36 +1,172 @@
 use femtos::{Instant, Duration};
 
-use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable};
+use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable, strict};
 
+use crate::peripherals::floppy::{MacDisk, FLOPPY_TRACKS};
 
-//const CA0: u8           = 0x01;
-//const CA1: u8           = 0x02;
-//const CA2: u8           = 0x04;
-//const LSTRB: u8         = 0x08;
+
+const CA0: u8 = 0x01;
+const CA1: u8 = 0x02;
+const CA2: u8 = 0x04;
+const LSTRB: u8 = 0x08;
 const ENABLE: u8 = 0x10;
-//const SELECT: u8        = 0x20;
+const SELECT: u8 = 0x20;
 const Q6: u8 = 0x40;
 const Q7: u8 = 0x80;
 
+/// The mode register bit that clears the data register after it's read
+const MODE_ASYNC: u8 = 0x02;
+
+/// The time for the drive to shift in one byte, which is 8 bit cells of 2us each
+const BYTE_PERIOD_NS: u64 = 16_000;
+/// The time between each edge of the tachometer, which pulses 60 times per revolution at about 500 RPM
+const TACH_PERIOD_NS: u64 = 1_000_000;
+
+/// The drive registers selected by CA2, CA1, CA0, and SEL.  The sense line is active low for most of them
+#[rustfmt::skip]
+mod drive_reg {
+    pub(super) const DIRTN: u8      = 0x0;
+    pub(super) const CSTIN: u8      = 0x1;
+    pub(super) const STEP: u8       = 0x2;
+    pub(super) const WRTPRT: u8     = 0x3;
+    pub(super) const MOTORON: u8    = 0x4;
+    pub(super) const TK0: u8        = 0x5;
+    pub(super) const EJECT: u8      = 0x6;
+    pub(super) const TACH: u8       = 0x7;
+    pub(super) const RDDATA0: u8    = 0x8;
+    pub(super) const RDDATA1: u8    = 0x9;
+    pub(super) const SIDES: u8      = 0xC;
+    pub(super) const DRVIN: u8      = 0xF;
+}
+
 const DEV_NAME: &str = "iwm";
 
+/// The internal Sony drive
+#[derive(Default)]
+struct SonyDrive {
+    disk: Option<MacDisk>,
+    track: usize,
+    /// The direction of the next step, which is towards track 0 when true
+    step_outward: bool,
+    motor_on: bool,
+}
+
+impl SonyDrive {
+    fn sense(&self, register: u8, clock: Instant) -> bool {
+        match register {
+            drive_reg::DIRTN => self.step_outward,
+            drive_reg::CSTIN => self.disk.is_none(),
+            // Steps happen immediately, so a step is never in progress
+            drive_reg::STEP => true,
+            // Writes aren't stored, so the disk is always write protected
+            drive_reg::WRTPRT => false,
+            drive_reg::MOTORON => !self.motor_on,
+            drive_reg::TK0 => self.track != 0,
+            drive_reg::EJECT => false,
+            drive_reg::TACH => (clock.as_duration().as_nanos() as u64 / TACH_PERIOD_NS) & 0x01 != 0,
+            drive_reg::RDDATA0 | drive_reg::RDDATA1 => true,
+            drive_reg::SIDES => self.disk.as_ref().map(|disk| disk.sides() == 2).unwrap_or(false),
+            drive_reg::DRVIN => false,
+            _ => true,
+        }
+    }
+
+    fn control(&mut self, register: u8, value: bool) {
+        match register {
+            drive_reg::DIRTN => self.step_outward = value,
+            drive_reg::STEP if !value => {
+                if self.step_outward {
+                    self.track = self.track.saturating_sub(1);
+                } else {
+                    self.track = (self.track + 1).min(FLOPPY_TRACKS - 1);
+                }
+                log::debug!(target: DEV_NAME, "stepped to track {}", self.track);
+            },
+            drive_reg::MOTORON => self.motor_on = !value,
+            drive_reg::EJECT if value => {
+                log::info!(target: DEV_NAME, "ejecting disk");
+                self.disk = None;
+                self.motor_on = false;
+            },
+            _ => log::debug!(target: DEV_NAME, "ignoring drive control {:x} with {}", register, value),
+        }
+    }
+
+    fn read_byte(&self, side: usize, position: u64) -> u8 {
+        if !self.motor_on {
+            return 0xFF;
+        }
+
+        match self.disk.as_ref().and_then(|disk| disk.track(self.track, side)) {
+            Some(track) => track[(position % track.len() as u64) as usize],
+            None => 0xFF,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct IWM {
     state: u8,
     mode: u8,
-    handshake: u8,
+    /// The SEL line, which comes from the VIA and selects the drive register and the head
+    head_select: bool,
+    drive: SonyDrive,
+    /// The position of the last byte read from the disk, which is only returned once in asynchronous mode
+    last_position: Option<u64>,
 }
 
 impl IWM {
+    pub fn insert_disk(&mut self, disk: MacDisk) {
+        self.drive.disk = Some(disk);
+        self.drive.track = 0;
+    }
+
+    pub fn set_head_select(&mut self, head_select: bool) {
+        self.head_select = head_select;
+    }
+
     pub fn flip_switches(&mut self, addr: Address) {
         let mask = 1 << (addr >> 1);
+        let previous = self.state;
 
         if (addr & 0x01) != 0 {
             self.state |= mask;
         } else {
             self.state &= !mask;
         }
-        log::info!("{}: state is now {:x}", DEV_NAME, self.state);
+        log::debug!(target: DEV_NAME, "state is now {:x}", self.state);
+
+        // The drive control registers are written when LSTRB goes high, with CA2 as the value
+        if (previous & LSTRB) == 0 && (self.state & LSTRB) != 0 && !self.external_selected() {
+            let register = ((self.state & (CA1 | CA0)) << 1) | self.head_select as u8;
+            self.drive.control(register, (self.state & CA2) != 0);
+        }
+    }
+
+    fn external_selected(&self) -> bool {
+        (self.state & SELECT) != 0
+    }
+
+    fn sense(&self, clock: Instant) -> bool {
+        if self.external_selected() {
+            // There's no external drive, which reads as all ones
+            return true;
+        }
+        let register = ((self.state & (CA2 | CA1 | CA0)) << 1) | self.head_select as u8;
+        self.drive.sense(register, clock)
+    }
+
+    fn read_data(&mut self, clock: Instant) -> u8 {
+        if (self.state & ENABLE) == 0 || self.external_selected() {
+            return 0xFF;
+        }
+
+        let position = clock.as_duration().as_nanos() as u64 / BYTE_PERIOD_NS;
+        if (self.mode & MODE_ASYNC) != 0 && self.last_position == Some(position) {
+            return 0x00;
+        }
+        self.last_position = Some(position);
+        self.drive.read_byte(self.head_select as usize, position)
     }
 }
 
@@ -39,7 +175,7 @@ impl Addressable for IWM {
         0x10
     }
 
-    fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+    fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
         self.flip_switches(addr);
 
         if (addr & 0x01) != 0 {
@@ -51,43 +187,43 @@ impl Addressable for IWM {
         match self.state & (Q7 | Q6) {
             0 => {
                 // read data register
-                data[0] = 0xFF;
+                data[i] = self.read_data(clock);
             },
             Q6 => {
                 // read "status" register
-                data[i] = (self.mode & 0x1F) | if (self.state & ENABLE) != 0 { 0x20 } else { 0x00 };
+                data[i] = if self.sense(clock) { 0x80 } else { 0x00 }
+                    | if (self.state & ENABLE) != 0 { 0x20 } else { 0x00 }
+                    | (self.mode & 0x1F);
             },
             Q7 => {
-                // read "write-handshake" register
-                data[i] = 0x3F | self.handshake;
-            },
-            b if b == (Q7 | Q6) => {
-                panic!("");
+                // read "write-handshake" register, which is always ready and never underruns
+                data[i] = 0xFF;
             },
             _ => {
-                log::warn!("{}: !!! unhandled read of {:0x} with state {:x}", DEV_NAME, addr, self.state);
+                strict::unhandled(DEV_NAME, format!("unhandled read of {:0x} with state {:x}", addr, self.state));
             },
         }
-        log::info!("{}: read from register {:x} of {:?}", DEV_NAME, addr, data);
+        log::debug!(target: DEV_NAME, "read from register {:x} of {:?}", addr, data);
         Ok(())
     }
 
     fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
         self.flip_switches(addr);
 
-        log::info!("{}: write to register {:x} with {:x}", DEV_NAME, addr, data[0]);
+        log::debug!(target: DEV_NAME, "write to register {:x} with {:x}", addr, data[0]);
 
         let i = data.len() - 1;
         match self.state & (Q7 | Q6 | ENABLE) {
             b if b == (Q7 | Q6 | ENABLE) => {
-                self.handshake &= !0x80;
+                // TODO writes aren't encoded back into the disk image
+                log::debug!(target: DEV_NAME, "discarding write of {:x} to the disk", data[i]);
             },
             b if b == (Q7 | Q6) => {
                 // write the mode register
                 self.mode = data[i] & 0x1f;
             },
             _ => {
-                log::warn!("{}: !!! unhandled write {:0x} to {:0x}", DEV_NAME, data[0], addr);
+                strict::unhandled_write(DEV_NAME, addr, data);
             },
         }
         Ok(())
@@ -96,7 +232,7 @@ impl Addressable for IWM {
 
 impl Steppable for IWM {
     fn step(&mut self, _system: &System) -> Result<Duration, Error> {
-        // TODO implement
+        // The disk position is calculated from the clock when the data register is read
         Ok(Duration::from_secs(1))
     }
 }
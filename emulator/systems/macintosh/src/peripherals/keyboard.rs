@@ -0,0 +1,171 @@
+use std::collections::VecDeque;
+use femtos::{Instant, Duration};
+
+use moa_host::{self, Host, HostError, Key, KeyEvent, EventReceiver};
+use moa_peripherals_mos::Mos6522;
+
+
+#[rustfmt::skip]
+mod cmd {
+    pub(super) const INQUIRY: u8        = 0x10;
+    pub(super) const INSTANT: u8        = 0x14;
+    pub(super) const MODEL_NUMBER: u8   = 0x16;
+    pub(super) const TEST: u8           = 0x36;
+}
+
+#[rustfmt::skip]
+mod response {
+    /// Sent instead of a key transition when no key was pressed or released
+    pub(super) const NULL: u8           = 0x7B;
+    pub(super) const ACK: u8            = 0x7D;
+    /// The model number of the M0110 without a keypad
+    pub(super) const MODEL_M0110: u8    = 0x03;
+}
+
+/// The bit of a key transition that's set when the key is released
+const KEY_UP: u8 = 0x80;
+
+/// The time for the keyboard to clock a byte in or out of the VIA's shift register
+const BYTE_PERIOD_US: u64 = 2_640;
+/// How long the keyboard waits for a key transition before answering an Inquiry command with a Null
+const INQUIRY_TIMEOUT_US: u64 = 250_000;
+/// How often to check for a command from the cpu
+const POLL_INTERVAL_US: u64 = 500;
+
+const DEV_NAME: &str = "mac-keyboard";
+
+enum KeyboardState {
+    Idle,
+    /// A command is being clocked out of the VIA, which finishes at the given time
+    Receiving(Instant),
+    /// An Inquiry command is waiting for a key transition, until the given time
+    Waiting(Instant),
+    /// A response is being clocked into the VIA, which finishes at the given time
+    Responding(u8, Instant),
+}
+
+/// The keyboard of the Macintosh 128K and 512K (M0110), which is connected to the VIA's shift register
+///
+/// The cpu writes a command to the shift register, which the keyboard clocks out, and then switches the shift
+/// register to input and waits for the keyboard to clock in its response.  Keys are reported as transitions, in
+/// response to the Inquiry and Instant commands.
+pub struct MacKeyboard {
+    receiver: EventReceiver<KeyEvent>,
+    state: KeyboardState,
+    transitions: VecDeque<u8>,
+}
+
+impl MacKeyboard {
+    pub fn new<H, E>(host: &mut H) -> Result<Self, HostError<E>>
+    where
+        H: Host<Error = E>,
+    {
+        let (sender, receiver) = moa_host::event_queue();
+        host.register_keyboard(sender)?;
+
+        Ok(Self {
+            receiver,
+            state: KeyboardState::Idle,
+            transitions: VecDeque::new(),
+        })
+    }
+
+    /// Exchange commands and responses with the VIA, returning the time until the keyboard should be stepped again
+    pub fn step(&mut self, clock: Instant, via: &mut Mos6522) -> Duration {
+        while let Some(event) = self.receiver.receive() {
+            if let Some(keycode) = key_to_keycode(event.key) {
+                let transition = (keycode << 1) | 0x01;
+                self.transitions
+                    .push_back(if event.state { transition } else { transition | KEY_UP });
+            }
+        }
+
+        match self.state {
+            KeyboardState::Idle => {
+                if via.is_shifting_out() {
+                    self.state = KeyboardState::Receiving(clock + Duration::from_micros(BYTE_PERIOD_US));
+                }
+            },
+            KeyboardState::Receiving(done) if clock >= done => match via.shift_out() {
+                Some(command) => self.run_command(clock, command),
+                None => self.state = KeyboardState::Idle,
+            },
+            KeyboardState::Waiting(timeout) => {
+                if let Some(transition) = self.transitions.pop_front() {
+                    self.respond(clock, transition);
+                } else if clock >= timeout {
+                    self.respond(clock, response::NULL);
+                }
+            },
+            // The response can only be clocked in once the cpu has switched the shift register to input
+            KeyboardState::Responding(data, done) if clock >= done && via.is_shifting_in() => {
+                log::debug!(target: DEV_NAME, "sending {:02x}", data);
+                via.shift_in(data);
+                self.state = KeyboardState::Idle;
+            },
+            _ => {},
+        }
+
+        Duration::from_micros(POLL_INTERVAL_US)
+    }
+
+    fn run_command(&mut self, clock: Instant, command: u8) {
+        log::debug!(target: DEV_NAME, "received command {:02x}", command);
+        match command {
+            cmd::INQUIRY => {
+                self.state = KeyboardState::Waiting(clock + Duration::from_micros(INQUIRY_TIMEOUT_US));
+            },
+            cmd::INSTANT => {
+                let transition = self.transitions.pop_front().unwrap_or(response::NULL);
+                self.respond(clock, transition);
+            },
+            cmd::MODEL_NUMBER => {
+                // The keyboard resets itself when asked for its model number
+                self.transitions.clear();
+                self.respond(clock, response::MODEL_M0110);
+            },
+            cmd::TEST => self.respond(clock, response::ACK),
+            _ => {
+                log::warn!(target: DEV_NAME, "unknown command {:02x}", command);
+                self.state = KeyboardState::Idle;
+            },
+        }
+    }
+
+    fn respond(&mut self, clock: Instant, data: u8) {
+        self.state = KeyboardState::Responding(data, clock + Duration::from_micros(BYTE_PERIOD_US));
+    }
+}
+
+/// Returns the key number of the M0110 keyboard, which is sent shifted left by one with the lowest bit set
+///
+/// Alt is the Option key and Ctrl is the Command key.  The M0110 doesn't have arrow, function, or keypad keys
+fn key_to_keycode(key: Key) -> Option<u8> {
+    #[rustfmt::skip]
+    let keycode = match key {
+        Key::A => 0x00,             Key::S => 0x01,             Key::D => 0x02,
+        Key::F => 0x03,             Key::H => 0x04,             Key::G => 0x05,
+        Key::Z => 0x06,             Key::X => 0x07,             Key::C => 0x08,
+        Key::V => 0x09,             Key::B => 0x0B,             Key::Q => 0x0C,
+        Key::W => 0x0D,             Key::E => 0x0E,             Key::R => 0x0F,
+        Key::Y => 0x10,             Key::T => 0x11,             Key::Num1 => 0x12,
+        Key::Num2 => 0x13,          Key::Num3 => 0x14,          Key::Num4 => 0x15,
+        Key::Num6 => 0x16,          Key::Num5 => 0x17,          Key::Equals => 0x18,
+        Key::Num9 => 0x19,          Key::Num7 => 0x1A,          Key::Minus => 0x1B,
+        Key::Num8 => 0x1C,          Key::Num0 => 0x1D,          Key::RightBracket => 0x1E,
+        Key::O => 0x1F,             Key::U => 0x20,             Key::LeftBracket => 0x21,
+        Key::I => 0x22,             Key::P => 0x23,             Key::Enter => 0x24,
+        Key::L => 0x25,             Key::J => 0x26,             Key::Apostrophe => 0x27,
+        Key::K => 0x28,             Key::Semicolon => 0x29,     Key::Backslash => 0x2A,
+        Key::Comma => 0x2B,         Key::Slash => 0x2C,         Key::N => 0x2D,
+        Key::M => 0x2E,             Key::Period => 0x2F,        Key::Tab => 0x30,
+        Key::Space => 0x31,         Key::Backquote => 0x32,     Key::Backspace => 0x33,
+        Key::NumPadEnter => 0x34,
+        Key::LeftCtrl | Key::RightCtrl => 0x37,
+        Key::LeftShift | Key::RightShift => 0x38,
+        Key::CapsLock => 0x39,
+        Key::LeftAlt | Key::RightAlt => 0x3A,
+        _ => return None,
+    };
+    Some(keycode)
+}
@@ -1,31 +1,53 @@
 use std::rc::Rc;
 use std::cell::RefCell;
-use femtos::{Instant, Duration};
+use femtos::{Instant, Duration, Frequency};
 
-use moa_core::{System, Bus, Error, Address, Addressable, AddressRepeater, Steppable, Transmutable, Device};
-use moa_signals::Observable;
+use moa_core::{System, Bus, Error, Address, Addressable, AddressRepeater, Steppable, Transmutable, Device, Waker, InterruptLine};
+use moa_signals::{Observable, ObservableSignal};
 
-use moa_peripherals_mos::Mos6522;
+use moa_peripherals_mos::{Mos6522, Port};
 use moa_peripherals_zilog::Z8530;
+use crate::peripherals::floppy::MacDisk;
 use crate::peripherals::iwm::IWM;
+use crate::peripherals::keyboard::MacKeyboard;
+use crate::peripherals::mouse::MacMouse;
 
 const DEV_NAME: &str = "mac";
 
+/// The bit of VIA port A that's connected to the SEL line of the floppy drive
+const VIA_HEAD_SELECT: u8 = 0x20;
+
+/// The VIA interrupt flag for the CA2 line, which is connected to the one second pulse of the real time clock
+const VIA_INT_ONE_SECOND: u8 = 0x01;
+
 
 pub struct Mainboard {
     lower_bus: Rc<RefCell<Bus>>,
-    scc1: Z8530,
-    scc2: Z8530,
+    scc: Z8530,
     iwm: IWM,
     via: Mos6522,
+    keyboard: MacKeyboard,
+    mouse: MacMouse,
     phase_read: PhaseRead,
+    via_interrupt: InterruptLine,
     last_sec: Instant,
 }
 
 impl Mainboard {
-    pub fn new(ram: Device, rom: Device) -> Result<Self, Error> {
-        let scc1 = Z8530::default();
-        let scc2 = Z8530::default();
+    /// Create the mainboard with the interrupt lines of the VIA and the SCC, which are connected to levels 1 and 2
+    /// of the cpu's interrupt controller, so that an SCC interrupt takes priority over a VIA interrupt
+    pub fn new(
+        ram: Device,
+        rom: Device,
+        via_interrupt: InterruptLine,
+        scc_interrupt: InterruptLine,
+        keyboard: MacKeyboard,
+        mouse: MacMouse,
+    ) -> Result<Self, Error> {
+        // The cpu uses the autovector instead of reading the vector register
+        let scc = Z8530::new(Frequency::from_hz(3_672_000))
+            .with_interrupt_line(scc_interrupt)
+            .with_autovector(26);
         let iwm = IWM::default();
         let via = Mos6522::default();
         let phase_read = PhaseRead::default();
@@ -34,11 +56,13 @@ impl Mainboard {
 
         let mainboard = Self {
             lower_bus: lower_bus.clone(),
-            scc1,
-            scc2,
+            scc,
             iwm,
             via,
+            keyboard,
+            mouse,
             phase_read,
+            via_interrupt,
             last_sec: Instant::START,
         };
 
@@ -77,6 +101,29 @@ impl Mainboard {
 
         Ok(mainboard)
     }
+
+    /// Returns the VIA's ports, which the video and sound circuits use to select their buffers
+    pub fn via_ports(&self) -> (ObservableSignal<Port>, ObservableSignal<Port>) {
+        (self.via.port_a.clone(), self.via.port_b.clone())
+    }
+
+    /// Returns the SCC, whose channel A is the modem port and channel B is the printer port
+    pub fn serial(&mut self) -> &mut Z8530 {
+        &mut self.scc
+    }
+
+    pub fn insert_disk(&mut self, disk: MacDisk) {
+        self.iwm.insert_disk(disk);
+    }
+
+    fn update_via_interrupt(&mut self) {
+        self.via_interrupt.set(self.via.interrupt.get());
+    }
+
+    fn update_head_select(&mut self) {
+        let port_a = self.via.port_a.borrow_mut().data;
+        self.iwm.set_head_select((port_a & VIA_HEAD_SELECT) != 0);
+    }
 }
 
 impl Addressable for Mainboard {
@@ -87,11 +134,10 @@ impl Addressable for Mainboard {
     fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
         if addr < 0x800000 {
             self.lower_bus.borrow_mut().read(clock, addr, data)
-        } else if (0x900000..0xA00000).contains(&addr) {
-            self.scc1.read(clock, (addr >> 9) & 0x0F, data)
-        } else if (0xB00000..0xC00000).contains(&addr) {
-            self.scc2.read(clock, (addr >> 9) & 0x0F, data)
+        } else if (0x900000..0xA00000).contains(&addr) || (0xB00000..0xC00000).contains(&addr) {
+            self.scc.read(clock, (addr >> 1) & 0x03, data)
         } else if (0xD00000..0xE00000).contains(&addr) {
+            self.update_head_select();
             self.iwm.read(clock, (addr >> 9) & 0x0F, data)
         } else if (0xE80000..0xF00000).contains(&addr) {
             self.via.read(clock, (addr >> 9) & 0x0F, data)
@@ -108,14 +154,15 @@ impl Addressable for Mainboard {
     fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
         if addr < 0x800000 {
             self.lower_bus.borrow_mut().write(clock, addr, data)
-        } else if (0x900000..0xA00000).contains(&addr) {
-            self.scc1.write(clock, (addr >> 9) & 0x0F, data)
-        } else if (0xB00000..0xC00000).contains(&addr) {
-            self.scc2.write(clock, (addr >> 9) & 0x0F, data)
+        } else if (0x900000..0xA00000).contains(&addr) || (0xB00000..0xC00000).contains(&addr) {
+            self.scc.write(clock, (addr >> 1) & 0x03, data)
         } else if (0xD00000..0xE00000).contains(&addr) {
+            self.update_head_select();
             self.iwm.write(clock, (addr >> 9) & 0x0F, data)
         } else if (0xE80000..0xF00000).contains(&addr) {
-            self.via.write(clock, (addr >> 9) & 0x0F, data)
+            let result = self.via.write(clock, (addr >> 9) & 0x0F, data);
+            self.update_via_interrupt();
+            result
         } else if (0xF00000..0xF80000).contains(&addr) {
             self.phase_read.write(clock, addr, data)
         } else {
@@ -126,17 +173,23 @@ impl Addressable for Mainboard {
 
 impl Steppable for Mainboard {
     fn step(&mut self, system: &System) -> Result<Duration, Error> {
-        let elapsed = self.via.step(system)?;
+        // The keyboard and mouse are updated first, so that any interrupts they cause are raised in this step
+        let keyboard = self.keyboard.step(system.clock, &mut self.via);
+        let mouse = self.mouse.step(system.clock, &mut self.via, &mut self.scc);
+        let elapsed = self.via.step(system)?.min(self.scc.step(system)?).min(keyboard).min(mouse);
 
         // TODO should this be 1 second, or a multiple of 979_200, which is an 8th of the CPU clock
-        if self.last_sec + Duration::from_secs(1) > system.clock {
+        if system.clock >= self.last_sec + Duration::from_secs(1) {
             self.last_sec += Duration::from_secs(1);
-            //let port_a = self.via.port_a.borrow_mut();
-            // TODO how will the ca1/ca2 cb1/cb2 pins work in the via
-            system.get_interrupt_controller().set(true, 1, 25)?;
+            self.via.set_interrupt_flags(VIA_INT_ONE_SECOND);
         }
+        self.update_via_interrupt();
         Ok(elapsed)
     }
+
+    fn set_waker(&mut self, waker: Waker) {
+        self.scc.set_waker(waker);
+    }
 }
 
 impl Transmutable for Mainboard {
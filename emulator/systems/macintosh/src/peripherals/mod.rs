@@ -1,3 +1,7 @@
+pub mod floppy;
 pub mod iwm;
+pub mod keyboard;
 pub mod mainboard;
+pub mod mouse;
+pub mod sound;
 pub mod video;
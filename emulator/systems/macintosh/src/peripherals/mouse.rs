@@ -0,0 +1,134 @@
+use femtos::{Instant, Duration};
+
+use moa_host::{self, Host, HostError, MouseEvent, MouseEventType, MouseButton, EventReceiver};
+use moa_peripherals_mos::Mos6522;
+use moa_peripherals_zilog::Z8530;
+
+
+/// The bit of VIA port B that's connected to the mouse button, which is 0 while it's pressed
+const VIA_MOUSE_BUTTON: u8 = 0x08;
+/// The bit of VIA port B that's connected to the X2 quadrature signal
+const VIA_MOUSE_X2: u8 = 0x10;
+/// The bit of VIA port B that's connected to the Y2 quadrature signal
+const VIA_MOUSE_Y2: u8 = 0x20;
+
+/// The time between each edge of X1 or Y1, which gives the cpu time to handle each interrupt
+const STEP_INTERVAL_US: u64 = 1_000;
+/// The most movement that's waiting to be sent, so that a large jump doesn't take too long to catch up
+const MAX_PENDING: i32 = 128;
+/// How often to step when the mouse isn't moving
+const IDLE_INTERVAL_US: u64 = 10_000;
+
+
+/// One axis of the mouse, which is a pair of quadrature signals
+#[derive(Default)]
+struct QuadratureAxis {
+    /// The distance that hasn't been sent yet
+    pending: i32,
+    /// The X1 or Y1 signal, which causes an interrupt when it changes
+    phase1: bool,
+    /// The X2 or Y2 signal, which is read by the interrupt handler to find the direction
+    phase2: bool,
+}
+
+impl QuadratureAxis {
+    fn add(&mut self, distance: i32) {
+        self.pending = (self.pending + distance).clamp(-MAX_PENDING, MAX_PENDING);
+    }
+
+    /// Move one step, returning false if there's no movement waiting
+    fn step(&mut self) -> bool {
+        if self.pending == 0 {
+            return false;
+        }
+
+        // The second signal changes while the first is steady, so that after the first signal changes, they're
+        // different when moving right or down, and the same when moving left or up
+        let forward = self.pending > 0;
+        self.phase2 = if forward { self.phase1 } else { !self.phase1 };
+        self.phase1 = !self.phase1;
+        self.pending -= self.pending.signum();
+        true
+    }
+}
+
+/// The mouse of the Macintosh 128K and 512K, whose X1 and Y1 signals are connected to the DCD inputs of the SCC,
+/// and whose X2, Y2, and button signals are connected to VIA port B
+pub struct MacMouse {
+    receiver: EventReceiver<MouseEvent>,
+    position: Option<(u32, u32)>,
+    button: bool,
+    x: QuadratureAxis,
+    y: QuadratureAxis,
+    next_step: Instant,
+}
+
+impl MacMouse {
+    pub fn new<H, E>(host: &mut H) -> Result<Self, HostError<E>>
+    where
+        H: Host<Error = E>,
+    {
+        let (sender, receiver) = moa_host::event_queue();
+        host.register_mouse(sender)?;
+
+        Ok(Self {
+            receiver,
+            position: None,
+            button: false,
+            // The first signals start high, like the SCC's DCD inputs, so there's no interrupt until the mouse moves
+            x: QuadratureAxis {
+                phase1: true,
+                ..Default::default()
+            },
+            y: QuadratureAxis {
+                phase1: true,
+                ..Default::default()
+            },
+            next_step: Instant::START,
+        })
+    }
+
+    /// Update the mouse signals, returning the time until the mouse should be stepped again
+    pub fn step(&mut self, clock: Instant, via: &mut Mos6522, scc: &mut Z8530) -> Duration {
+        while let Some(event) = self.receiver.receive() {
+            match event.etype {
+                MouseEventType::Down(MouseButton::Left) => self.button = true,
+                MouseEventType::Up(MouseButton::Left) => self.button = false,
+                _ => {},
+            }
+            if let Some((x, y)) = self.position.replace(event.pos) {
+                self.x.add(event.pos.0 as i32 - x as i32);
+                self.y.add(event.pos.1 as i32 - y as i32);
+            }
+        }
+
+        let mut moving = false;
+        if clock >= self.next_step {
+            moving = self.x.step() | self.y.step();
+            if moving {
+                self.next_step = clock + Duration::from_micros(STEP_INTERVAL_US);
+            }
+        }
+        scc.port_a.set_dcd(self.x.phase1);
+        scc.port_b.set_dcd(self.y.phase1);
+
+        let mut inputs = 0;
+        if !self.button {
+            inputs |= VIA_MOUSE_BUTTON;
+        }
+        if self.x.phase2 {
+            inputs |= VIA_MOUSE_X2;
+        }
+        if self.y.phase2 {
+            inputs |= VIA_MOUSE_Y2;
+        }
+        let mut port_b = via.port_b.borrow_mut();
+        port_b.input = (port_b.input & !(VIA_MOUSE_BUTTON | VIA_MOUSE_X2 | VIA_MOUSE_Y2)) | inputs;
+
+        if moving || self.x.pending != 0 || self.y.pending != 0 {
+            Duration::from_micros(STEP_INTERVAL_US)
+        } else {
+            Duration::from_micros(IDLE_INTERVAL_US)
+        }
+    }
+}
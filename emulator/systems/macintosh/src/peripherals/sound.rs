@@ -0,0 +1,98 @@
+use femtos::Duration;
+
+use moa_core::{System, Error, Address, Steppable, Transmutable, Capabilities, Device};
+use moa_host::{Host, HostError, Audio, Sample};
+use moa_signals::ObservableSignal;
+use moa_peripherals_mos::Port;
+
+use crate::peripherals::video::RAM_TOP;
+
+
+const SOUND_BASE: u32 = RAM_TOP - 0x0300;
+const SOUND_ALT_BASE: u32 = RAM_TOP - 0x5F00;
+
+/// The number of words in the sound buffer, one of which is read during each horizontal line including blanking
+const SOUND_WORDS: usize = 370;
+const FRAME_PERIOD_US: u64 = 16_600;
+
+/// The bits of VIA port A that set the volume from 0 to 7
+const VIA_VOLUME: u8 = 0x07;
+/// The bit of VIA port A that selects the main sound buffer when set, or the alternate buffer when clear
+const VIA_SOUND_PAGE: u8 = 0x08;
+/// The bit of VIA port B that disables the sound output when set
+const VIA_SOUND_DISABLE: u8 = 0x80;
+
+/// The sound circuit, which plays the upper byte of each word in the sound buffer at the horizontal line rate
+pub struct MacSound {
+    source: Box<dyn Audio>,
+    ram: Device,
+    via_port_a: ObservableSignal<Port>,
+    via_port_b: ObservableSignal<Port>,
+}
+
+impl MacSound {
+    pub fn new<H, E>(
+        host: &mut H,
+        ram: Device,
+        via_port_a: ObservableSignal<Port>,
+        via_port_b: ObservableSignal<Port>,
+    ) -> Result<Self, HostError<E>>
+    where
+        H: Host<Error = E>,
+    {
+        let source = host.add_audio_source()?;
+
+        Ok(Self {
+            source,
+            ram,
+            via_port_a,
+            via_port_b,
+        })
+    }
+}
+
+impl Steppable for MacSound {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        let port_a = self.via_port_a.borrow_mut().data;
+        let enabled = (self.via_port_b.borrow_mut().data & VIA_SOUND_DISABLE) == 0;
+        let base = if (port_a & VIA_SOUND_PAGE) != 0 {
+            SOUND_BASE
+        } else {
+            SOUND_ALT_BASE
+        };
+
+        let mut words = [0; SOUND_WORDS * 2];
+        self.ram
+            .borrow_mut()
+            .as_addressable()
+            .ok_or_else(|| Error::new("mac sound: ram is not addressable"))?
+            .read(system.clock, base as Address, &mut words)?;
+
+        let rate = self.source.samples_per_second();
+        let samples = rate * FRAME_PERIOD_US as usize / 1_000_000;
+        let volume = (port_a & VIA_VOLUME) as f32 / 7.0;
+
+        let mut buffer = vec![Sample(0.0, 0.0); samples];
+        if enabled {
+            for (i, buffered_sample) in buffer.iter_mut().enumerate() {
+                // The sample is the upper byte of each word, and the lower byte controls the disk speed
+                let word = i * SOUND_WORDS / samples;
+                let sample = (words[word * 2] as f32 - 128.0) / 128.0 * volume;
+                *buffered_sample = Sample(sample, sample);
+            }
+        }
+        self.source.write_samples(system.clock, &buffer);
+
+        Ok(Duration::from_micros(FRAME_PERIOD_US))
+    }
+}
+
+impl Transmutable for MacSound {
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities::probe(self) | Capabilities::AUDIO
+    }
+}
@@ -1,18 +1,36 @@
 use femtos::Duration;
 
-use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable};
-use moa_host::{self, Host, HostError, Frame, FrameSender, Pixel};
+use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable, Capabilities, Device};
+use moa_host::{self, Host, HostError, Frame, FrameSender, Pixel, Rect};
+use moa_signals::ObservableSignal;
+use moa_peripherals_mos::Port;
 
 
-const SCRN_BASE: u32 = 0x07A700;
-const SCRN_SIZE: (u32, u32) = (512, 342);
+/// The top of RAM on the 512K, which the screen and sound buffers are placed relative to
+pub const RAM_TOP: u32 = 0x080000;
+
+const SCRN_BASE: u32 = RAM_TOP - 0x5900;
+const SCRN_ALT_BASE: u32 = RAM_TOP - 0xD900;
+pub const SCRN_SIZE: (u32, u32) = (512, 342);
+
+/// The debug view that shows both screen buffers side by side, with the main buffer on the left
+const VIEW_BUFFERS: &str = "mac-screen-buffers";
+
+/// The bit of VIA port A that selects the main screen buffer when set, or the alternate buffer when clear
+const VIA_SCREEN_PAGE: u8 = 0x40;
 
 pub struct MacVideo {
     frame_sender: FrameSender,
+    ram: Device,
+    via_port_a: ObservableSignal<Port>,
+    /// The contents of the screen buffer that were drawn into the frame, so that only changed lines are redrawn
+    last_screen: Vec<u8>,
+    frame: Frame,
+    buffers_sender: Option<FrameSender>,
 }
 
 impl MacVideo {
-    pub fn new<H, E>(host: &mut H) -> Result<Self, HostError<E>>
+    pub fn new<H, E>(host: &mut H, ram: Device, via_port_a: ObservableSignal<Port>) -> Result<Self, HostError<E>>
     where
         H: Host<Error = E>,
     {
@@ -20,10 +38,49 @@ impl MacVideo {
 
         host.add_video_source(frame_receiver)?;
 
+        let (buffers_sender, buffers_receiver) = moa_host::frame_queue(SCRN_SIZE.0 * 2, SCRN_SIZE.1);
+        let buffers_sender = host
+            .add_named_video_source(VIEW_BUFFERS, buffers_receiver)
+            .ok()
+            .map(|_| buffers_sender);
+
         Ok(Self {
             frame_sender,
+            ram,
+            via_port_a,
+            last_screen: vec![],
+            frame: Frame::default(),
+            buffers_sender,
         })
     }
+
+    fn screen_base(&self) -> u32 {
+        if (self.via_port_a.borrow_mut().data & VIA_SCREEN_PAGE) != 0 {
+            SCRN_BASE
+        } else {
+            SCRN_ALT_BASE
+        }
+    }
+
+    fn draw_buffers(&self, system: &System, sender: &FrameSender) -> Result<(), Error> {
+        let mut frame = Frame::new(SCRN_SIZE.0 * 2, SCRN_SIZE.1, sender.encoding());
+        let mut buffer = vec![0; (SCRN_SIZE.0 / 8 * SCRN_SIZE.1) as usize];
+        for (i, base) in [SCRN_BASE, SCRN_ALT_BASE].into_iter().enumerate() {
+            self.ram
+                .borrow_mut()
+                .as_addressable()
+                .ok_or_else(|| Error::new("mac video: ram is not addressable"))?
+                .read(system.clock, base as Address, &mut buffer)?;
+
+            for (j, word) in buffer.chunks_exact(2).enumerate() {
+                let (x, y) = ((j as u32 * 16) % SCRN_SIZE.0, (j as u32 * 16) / SCRN_SIZE.0);
+                let word = u16::from_be_bytes([word[0], word[1]]);
+                frame.blit(i as u32 * SCRN_SIZE.0 + x, y, BitIter::new(word), 16, 1);
+            }
+        }
+        sender.add(system.clock, frame);
+        Ok(())
+    }
 }
 
 pub struct BitIter {
@@ -61,16 +118,43 @@ impl Iterator for BitIter {
 
 impl Steppable for MacVideo {
     fn step(&mut self, system: &System) -> Result<Duration, Error> {
-        let mut memory = system.get_bus();
-        let mut frame = Frame::new(SCRN_SIZE.0, SCRN_SIZE.1, self.frame_sender.encoding());
+        // The video circuit reads directly from RAM, regardless of where the overlay has mapped it
+        let bytes_per_line = SCRN_SIZE.0 / 8;
+        let mut buffer = vec![0; (bytes_per_line * SCRN_SIZE.1) as usize];
+        let base = self.screen_base();
+        self.ram
+            .borrow_mut()
+            .as_addressable()
+            .ok_or_else(|| Error::new("mac video: ram is not addressable"))?
+            .read(system.clock, base as Address, &mut buffer)?;
+
+        let encoding = self.frame_sender.encoding();
+        if self.frame.encoding != encoding || self.last_screen.len() != buffer.len() {
+            self.frame = Frame::new(SCRN_SIZE.0, SCRN_SIZE.1, encoding);
+            self.last_screen = vec![0; buffer.len()];
+        } else {
+            self.frame.dirty = Some(Rect::default());
+        }
+
+        let redraw_all = self.frame.dirty.is_none();
         for y in 0..SCRN_SIZE.1 {
+            let line = (y * bytes_per_line) as usize..((y + 1) * bytes_per_line) as usize;
+            if !redraw_all && buffer[line.clone()] == self.last_screen[line.clone()] {
+                continue;
+            }
             for x in 0..(SCRN_SIZE.0 / 16) {
-                let word = memory.read_beu16(system.clock, (SCRN_BASE + (x * 2) + (y * (SCRN_SIZE.0 / 8))) as Address)?;
-                frame.blit(x * 16, y, BitIter::new(word), 16, 1);
+                let i = line.start + (x * 2) as usize;
+                let word = u16::from_be_bytes([buffer[i], buffer[i + 1]]);
+                self.frame.blit(x * 16, y, BitIter::new(word), 16, 1);
             }
+            self.frame.mark_dirty(Rect::new(0, y, SCRN_SIZE.0, 1));
         }
 
-        self.frame_sender.add(system.clock, frame);
+        self.last_screen = buffer;
+        self.frame_sender.add(system.clock, self.frame.clone());
+        if let Some(sender) = &self.buffers_sender {
+            self.draw_buffers(system, sender)?;
+        }
         Ok(Duration::from_micros(16_600))
     }
 }
@@ -79,4 +163,8 @@ impl Transmutable for MacVideo {
     fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
         Some(self)
     }
+
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities::probe(self) | Capabilities::VIDEO
+    }
 }
@@ -1,16 +1,44 @@
 use femtos::Frequency;
 
-use moa_core::{System, Error, MemoryBlock, Debuggable, Device};
+use moa_core::{System, Error, MemoryBlock, Debuggable, Device, MachineInfo};
 use moa_host::Host;
 
 use moa_m68k::{M68k, M68kType};
 
-use crate::peripherals::video::MacVideo;
+use crate::peripherals::video::{MacVideo, SCRN_SIZE, RAM_TOP};
+use crate::peripherals::sound::MacSound;
 use crate::peripherals::mainboard::Mainboard;
+use crate::peripherals::floppy::MacDisk;
+use crate::peripherals::keyboard::MacKeyboard;
+use crate::peripherals::mouse::MacMouse;
+
+
+#[derive(Debug)]
+pub struct MacintoshOptions {
+    pub rom: String,
+    /// A 400K or 800K disk image to insert into the internal drive, either as raw sectors or in DiskCopy 4.2 format
+    pub disk: Option<String>,
+    /// Connect the modem and printer ports to ptys
+    pub serial: bool,
+}
+
+impl Default for MacintoshOptions {
+    fn default() -> Self {
+        Self {
+            rom: "binaries/macintosh/Macintosh 512k.rom".to_string(),
+            disk: None,
+            serial: false,
+        }
+    }
+}
 
 
-pub fn build_macintosh_512k<H: Host>(host: &mut H) -> Result<System, Error> {
+pub fn build_macintosh_512k<H: Host>(host: &mut H, options: MacintoshOptions) -> Result<System, Error> {
     let mut system = System::default();
+    // The pixels are square, so the aspect ratio is the same as the video size
+    system.machine_info = MachineInfo::new("macintosh")
+        .with_video(SCRN_SIZE.0, SCRN_SIZE.1, Frequency::from_hz(60))
+        .with_rom(&options.rom);
 
     /*
     use crate::peripherals::mos6522::Mos6522;
@@ -59,16 +87,33 @@ pub fn build_macintosh_512k<H: Host>(host: &mut H) -> Result<System, Error> {
     system.add_addressable_device(0x00EFE000, wrap_transmutable(adapter))?;
     */
 
-    let ram = MemoryBlock::new(vec![0; 0x00080000]);
-    let mut rom = MemoryBlock::load("binaries/macintosh/Macintosh 512k.rom")?;
+    let ram = Device::new(MemoryBlock::new(vec![0; RAM_TOP as usize]));
+    let mut rom = MemoryBlock::load(&options.rom)?;
     rom.read_only();
 
-    let video = MacVideo::new(host)?;
-    system.add_device("video", Device::new(video)).unwrap();
-
-    let mainboard = Mainboard::new(Device::new(ram), Device::new(rom))?;
+    let (via_interrupt, scc_interrupt) = {
+        let mut interrupts = system.get_interrupt_controller();
+        (interrupts.connect(1), interrupts.connect(2))
+    };
+    let keyboard = MacKeyboard::new(host)?;
+    let mouse = MacMouse::new(host)?;
+    let mut mainboard = Mainboard::new(ram.clone(), Device::new(rom), via_interrupt, scc_interrupt, keyboard, mouse)?;
+    if let Some(filename) = &options.disk {
+        mainboard.insert_disk(MacDisk::load(filename)?);
+    }
+    if options.serial {
+        mainboard.serial().port_a.connect(host.add_serial_port("modem")?)?;
+        mainboard.serial().port_b.connect(host.add_serial_port("printer")?)?;
+    }
+    let (via_port_a, via_port_b) = mainboard.via_ports();
     system.add_addressable_device(0x00000000, Device::new(mainboard))?;
 
+    // The video and sound circuits read their buffers from RAM, selected by the VIA
+    let video = MacVideo::new(host, ram.clone(), via_port_a.clone())?;
+    system.add_device("video", Device::new(video)).unwrap();
+    let sound = MacSound::new(host, ram, via_port_a, via_port_b)?;
+    system.add_device("sound", Device::new(sound))?;
+
 
     let mut cpu = M68k::from_type(M68kType::MC68000, Frequency::from_hz(7_833_600));
 
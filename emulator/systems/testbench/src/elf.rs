@@ -0,0 +1,96 @@
+use std::fs;
+
+use moa_core::{Error, Address};
+
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS32: u8 = 1;
+const ELFDATA2MSB: u8 = 2;
+const EM_68K: u16 = 4;
+const PT_LOAD: u32 = 1;
+
+
+/// A segment of the program which must be loaded into memory before running it
+#[derive(Clone, Debug)]
+pub struct ElfSegment {
+    pub addr: Address,
+    pub data: Vec<u8>,
+    /// The size of the segment in memory, which includes the zero-filled area after the data (eg. `.bss`)
+    pub mem_size: usize,
+}
+
+/// A 32-bit big endian m68k ELF executable
+#[derive(Clone, Debug)]
+pub struct ElfImage {
+    pub entry: Address,
+    pub segments: Vec<ElfSegment>,
+}
+
+impl ElfImage {
+    pub fn load(filename: &str) -> Result<Self, Error> {
+        let contents = fs::read(filename).map_err(|_| Error::new(format!("Error reading contents of {}", filename)))?;
+        Self::parse(&contents).map_err(|err| Error::new(format!("{}: {}", filename, err)))
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < 52 || &data[0..4] != ELF_MAGIC {
+            return Err(Error::new("not an ELF file"));
+        }
+        if data[4] != ELFCLASS32 || data[5] != ELFDATA2MSB || read_u16(data, 18)? != EM_68K {
+            return Err(Error::new("not a 32-bit big endian m68k ELF file"));
+        }
+
+        let entry = read_u32(data, 24)? as Address;
+        let phoff = read_u32(data, 28)? as usize;
+        let phentsize = read_u16(data, 42)? as usize;
+        let phnum = read_u16(data, 44)? as usize;
+
+        let mut segments = vec![];
+        for i in 0..phnum {
+            let header = phoff + i * phentsize;
+            if read_u32(data, header)? != PT_LOAD {
+                continue;
+            }
+
+            let offset = read_u32(data, header + 4)? as usize;
+            let addr = read_u32(data, header + 12)? as Address;
+            let file_size = read_u32(data, header + 16)? as usize;
+            let mem_size = read_u32(data, header + 20)? as usize;
+
+            let contents = data
+                .get(offset..offset + file_size)
+                .ok_or_else(|| Error::new(format!("segment {} extends past the end of the file", i)))?;
+            segments.push(ElfSegment {
+                addr,
+                data: contents.to_vec(),
+                mem_size: mem_size.max(file_size),
+            });
+        }
+
+        Ok(Self {
+            entry,
+            segments,
+        })
+    }
+
+    /// Returns the address just past the end of the highest segment, which is where the heap can start
+    pub fn end(&self) -> Address {
+        self.segments
+            .iter()
+            .map(|segment| segment.addr + segment.mem_size as Address)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, Error> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| Error::new("unexpected end of file"))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, Error> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or_else(|| Error::new("unexpected end of file"))
+}
@@ -0,0 +1,5 @@
+mod elf;
+mod system;
+
+pub use crate::elf::{ElfImage, ElfSegment};
+pub use crate::system::{build_testbench, TestbenchOptions};
@@ -0,0 +1,91 @@
+use femtos::{Instant, Frequency};
+
+use moa_core::{System, Error, MemoryBlock, Address, Addressable, Device, MachineInfo};
+use moa_host::Host;
+
+use moa_m68k::{M68k, M68kType};
+use moa_peripherals_generic::Semihosting;
+use moa_peripherals_generic::semihosting::M68K_TRAP_VECTOR;
+
+use crate::elf::ElfImage;
+
+
+/// Where the exception handlers are placed, just after the vector table
+const HANDLERS_ADDR: Address = 0x400;
+const SEMIHOSTING_ADDR: Address = 0x00ff_0000;
+
+#[derive(Debug)]
+pub struct TestbenchOptions {
+    pub elf: String,
+    pub ram: usize,
+    pub stack_size: usize,
+    pub frequency: Frequency,
+    pub sandbox: Option<String>,
+}
+
+impl Default for TestbenchOptions {
+    fn default() -> Self {
+        Self {
+            elf: "".to_string(),
+            ram: 0x00f0_0000,
+            stack_size: 0x1_0000,
+            frequency: Frequency::from_hz(10_000_000),
+            sandbox: None,
+        }
+    }
+}
+
+/// Build a bare 68010 machine which runs a statically linked ELF program with the stack at the top of RAM,
+/// the heap between the end of the program and the stack, and `trap #15` semihosting calls for I/O and exiting
+pub fn build_testbench<H: Host>(_host: &H, options: TestbenchOptions) -> Result<System, Error> {
+    let mut system = System::default();
+    system.machine_info = MachineInfo::new("testbench");
+
+    let image = ElfImage::load(&options.elf)?;
+    let ram_end = options.ram as Address;
+    let (handlers, fault_offset) = Semihosting::m68k_handlers(SEMIHOSTING_ADDR);
+
+    let mut ram = MemoryBlock::new(vec![0; options.ram]);
+    for segment in image.segments.iter() {
+        if segment.addr < HANDLERS_ADDR + handlers.len() as Address || segment.addr + segment.mem_size as Address > ram_end {
+            return Err(Error::new(format!(
+                "{}: segment at {:08x} with size {:x} is outside of the available RAM ({:08x} to {:08x})",
+                options.elf,
+                segment.addr,
+                segment.mem_size,
+                HANDLERS_ADDR + handlers.len() as Address,
+                ram_end
+            )));
+        }
+        ram.write(Instant::START, segment.addr, &segment.data)?;
+    }
+
+    // Set up the vector table with the initial stack and entry point, and the exception handlers
+    ram.write(Instant::START, HANDLERS_ADDR, &handlers)?;
+    ram.write(Instant::START, 0, &(ram_end as u32).to_be_bytes())?;
+    ram.write(Instant::START, 4, &(image.entry as u32).to_be_bytes())?;
+    for vector in 2..256 {
+        let handler = if vector == M68K_TRAP_VECTOR {
+            HANDLERS_ADDR
+        } else {
+            HANDLERS_ADDR + fault_offset as Address
+        };
+        ram.write(Instant::START, vector as Address * 4, &(handler as u32).to_be_bytes())?;
+    }
+    system.add_addressable_device(0x0000_0000, Device::new(ram))?;
+
+    // The heap can grow up to the bottom of the stack, starting from a long word aligned address after the program
+    let heap_start = (image.end() + 3) & !3;
+    let heap_limit = ram_end.saturating_sub(options.stack_size as Address);
+    let mut semihosting = Semihosting::new(true).with_heap(heap_start, heap_limit);
+    if let Some(directory) = options.sandbox.as_ref() {
+        semihosting = semihosting.with_sandbox(directory);
+    }
+    system.add_peripheral("semihosting", SEMIHOSTING_ADDR, Device::new(semihosting))?;
+
+    let mut cpu = M68k::from_type(M68kType::MC68010, options.frequency);
+    cpu.cache_instructions(&mut system.get_bus());
+    system.add_interruptable_device("cpu", Device::new(cpu))?;
+
+    Ok(system)
+}
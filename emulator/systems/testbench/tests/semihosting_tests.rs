@@ -0,0 +1,153 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use femtos::Duration;
+
+use moa_systems_testbench::{build_testbench, TestbenchOptions};
+use moa_testing::{Harness, Condition, Outcome};
+
+const ENTRY: u32 = 0x1000;
+const PROGRAM_OFFSET: usize = 0x100;
+
+/// Prints a character, and then exits with the result of printing it
+#[rustfmt::skip]
+const PUTCHAR_PROGRAM: &[u16] = &[
+    0x7241,                                         // moveq #'A', %d1
+    0x7001,                                         // moveq #PUTCHAR, %d0
+    0x4E4F,                                         // trap #15
+    0x2200,                                         // move.l %d0, %d1
+    0x7000,                                         // moveq #EXIT, %d0
+    0x4E4F,                                         // trap #15
+    0x60FE,                                         // bra.s .
+];
+
+/// Opens the file named at NAME_OFFSET, and then exits with its first byte, or -1 if it couldn't be read
+#[rustfmt::skip]
+const READ_PROGRAM: &[u16] = &[
+    0x41F9, 0x0000, 0x1100,                         // lea NAME, %a0
+    0x7200,                                         // moveq #0, %d1
+    0x1218,                                         // name: move.b (%a0)+, %d1
+    0x6706,                                         // beq.s open
+    0x7004,                                         // moveq #NAME, %d0
+    0x4E4F,                                         // trap #15
+    0x60F6,                                         // bra.s name
+    0x7005,                                         // open: moveq #OPEN, %d0
+    0x4E4F,                                         // trap #15
+    0x2200,                                         // move.l %d0, %d1
+    0x7006,                                         // moveq #READ, %d0
+    0x4E4F,                                         // trap #15
+    0x2200,                                         // move.l %d0, %d1
+    0x7000,                                         // moveq #EXIT, %d0
+    0x4E4F,                                         // trap #15
+    0x60FE,                                         // bra.s .
+];
+const NAME_OFFSET: usize = 0x100;
+
+/// Runs an illegal instruction, which the fault handler reports
+#[rustfmt::skip]
+const FAULT_PROGRAM: &[u16] = &[
+    0x4E71,                                         // nop
+    0x4AFC,                                         // illegal
+];
+
+/// Build an ELF file with one segment at ENTRY that contains the program
+fn build_elf(program: &[u8]) -> Vec<u8> {
+    let mut elf = vec![0; PROGRAM_OFFSET];
+    elf[0..4].copy_from_slice(b"\x7fELF");
+    elf[4] = 1; // 32-bit
+    elf[5] = 2; // big endian
+    elf[6] = 1; // version
+    elf[16..18].copy_from_slice(&2_u16.to_be_bytes()); // executable
+    elf[18..20].copy_from_slice(&4_u16.to_be_bytes()); // m68k
+    elf[24..28].copy_from_slice(&ENTRY.to_be_bytes());
+    elf[28..32].copy_from_slice(&52_u32.to_be_bytes()); // program headers just after the file header
+    elf[42..44].copy_from_slice(&32_u16.to_be_bytes());
+    elf[44..46].copy_from_slice(&1_u16.to_be_bytes());
+
+    let header = 52;
+    let size = program.len() as u32;
+    for (offset, value) in [(0, 1), (4, PROGRAM_OFFSET as u32), (8, ENTRY), (12, ENTRY), (16, size), (20, size)] {
+        elf[header + offset..header + offset + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    elf.extend_from_slice(program);
+    elf
+}
+
+fn program_bytes(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_be_bytes()).collect()
+}
+
+fn read_program(name: &str) -> Vec<u8> {
+    let mut program = program_bytes(READ_PROGRAM);
+    program.resize(NAME_OFFSET, 0);
+    program.extend_from_slice(name.as_bytes());
+    program.push(0);
+    program
+}
+
+/// A directory for the test's files, with a sandbox directory inside of it
+fn test_directory(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("moa-testbench-{}-{}", name, std::process::id()));
+    fs::create_dir_all(directory.join("sandbox")).unwrap();
+    fs::write(directory.join("sandbox").join("data.txt"), b"hello").unwrap();
+    fs::write(directory.join("secret.txt"), b"secret").unwrap();
+    directory
+}
+
+fn run(directory: &Path, program: &[u8]) -> Outcome {
+    let filename = directory.join("program.elf");
+    fs::write(&filename, build_elf(program)).unwrap();
+
+    let options = TestbenchOptions {
+        elf: filename.to_str().unwrap().to_string(),
+        ram: 0x10000,
+        stack_size: 0x1000,
+        sandbox: Some(directory.join("sandbox").to_str().unwrap().to_string()),
+        ..Default::default()
+    };
+    let mut harness = Harness::new(|host| build_testbench(host, options)).unwrap();
+    harness
+        .run_until(Condition::Custom(Box::new(|_| false)), Duration::from_millis(10))
+        .outcome
+}
+
+#[test]
+fn putchar_returns_the_character() {
+    let directory = test_directory("putchar");
+    let outcome = run(&directory, &program_bytes(PUTCHAR_PROGRAM));
+    fs::remove_dir_all(directory).unwrap();
+    assert!(matches!(outcome, Outcome::Exited(0x41)), "{:?}", outcome);
+}
+
+#[test]
+fn files_are_read_from_the_sandbox() {
+    let directory = test_directory("read");
+    let outcome = run(&directory, &read_program("data.txt"));
+    fs::remove_dir_all(directory).unwrap();
+    assert!(matches!(outcome, Outcome::Exited(0x68)), "{:?}", outcome);
+}
+
+#[test]
+fn files_outside_of_the_sandbox_cannot_be_read() {
+    let directory = test_directory("escape");
+    let outcomes = [
+        run(&directory, &read_program("../secret.txt")),
+        run(&directory, &read_program(directory.join("secret.txt").to_str().unwrap())),
+    ];
+    fs::remove_dir_all(directory).unwrap();
+    for outcome in outcomes {
+        assert!(matches!(outcome, Outcome::Exited(-1)), "{:?}", outcome);
+    }
+}
+
+#[test]
+fn unhandled_exceptions_stop_the_simulation() {
+    let directory = test_directory("fault");
+    let outcome = run(&directory, &program_bytes(FAULT_PROGRAM));
+    fs::remove_dir_all(directory).unwrap();
+    match outcome {
+        Outcome::Failed(err) => assert!(err.msg().contains("unhandled exception 4"), "{}", err),
+        _ => panic!("expected the exception to be reported, but {:?}", outcome),
+    }
+}
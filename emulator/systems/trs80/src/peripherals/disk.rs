@@ -0,0 +1,304 @@
+use std::fs;
+use std::path::Path;
+
+use moa_core::Error;
+
+
+/// The data address mark of a normal sector, where the others give a different record type
+pub const DATA_MARK_NORMAL: u8 = 0xFB;
+
+/// The JV1 format has 10 single density sectors of 256 bytes on each track, with the directory on track 17
+const JV1_SECTORS_PER_TRACK: usize = 10;
+const JV1_SECTOR_SIZE: usize = 256;
+const JV1_DIRECTORY_TRACK: u8 = 17;
+/// The data mark that TRSDOS uses for the directory sectors, which isn't stored in a JV1 image
+const JV1_DIRECTORY_MARK: u8 = 0xFA;
+
+/// The JV3 format starts with a table of sector headers, followed by a write protect byte and then the sectors
+const JV3_ENTRIES: usize = 2901;
+const JV3_HEADER_SIZE: usize = JV3_ENTRIES * 3 + 1;
+const JV3_FREE: u8 = 0xFF;
+
+#[rustfmt::skip]
+mod jv3_flags {
+    pub(super) const DOUBLE_DENSITY: u8     = 0x80;
+    pub(super) const DATA_MARK: u8          = 0x60;
+    pub(super) const SIDE: u8               = 0x10;
+    pub(super) const SIZE: u8               = 0x03;
+}
+
+/// The DMK format starts with a 16 byte header, followed by the raw data of each track, which begins with a
+/// table of pointers to the ID address marks in the track
+const DMK_HEADER_SIZE: usize = 16;
+const DMK_IDAM_TABLE_SIZE: usize = 128;
+const DMK_WRITE_PROTECTED: u8 = 0xFF;
+
+#[rustfmt::skip]
+mod dmk_flags {
+    pub(super) const SINGLE_SIDED: u8       = 0x10;
+    pub(super) const SINGLE_DENSITY: u8     = 0x40;
+    pub(super) const IGNORE_DENSITY: u8     = 0x80;
+    pub(super) const IDAM_DOUBLE_DENSITY: u16 = 0x8000;
+    pub(super) const IDAM_OFFSET: u16       = 0x3FFF;
+}
+
+/// The address mark at the start of a sector's ID field
+const ID_ADDRESS_MARK: u8 = 0xFE;
+/// The number of bytes after the ID field that the data address mark is searched for in
+const DATA_MARK_SEARCH: usize = 43;
+
+/// One sector of a disk, along with the ID field that identifies it, which doesn't have to match its position
+#[derive(Clone, Debug)]
+pub struct DiskSector {
+    pub track: u8,
+    pub side: u8,
+    pub sector: u8,
+    pub size_code: u8,
+    pub data_mark: u8,
+    pub data: Vec<u8>,
+}
+
+impl DiskSector {
+    /// Returns the size of the sector's data from the size code in its ID field
+    pub fn size_from_code(size_code: u8) -> usize {
+        128 << (size_code & 0x03)
+    }
+}
+
+/// A disk image for the TRS-80, which can be loaded from the JV1, JV3, or DMK formats, and which keeps the
+/// sectors of each track in the order they pass under the head
+///
+/// Sector writes and formatted tracks are stored in the loaded image, but aren't saved back to the file
+pub struct Trs80Disk {
+    /// The sectors of each side of each track, indexed by track * 2 + side
+    tracks: Vec<Vec<DiskSector>>,
+    write_protected: bool,
+}
+
+impl Trs80Disk {
+    /// Load a disk image, using the extension of the filename to choose the format, or guessing it from the
+    /// contents if the extension isn't known
+    pub fn load(filename: &str) -> Result<Self, Error> {
+        let data = fs::read(filename).map_err(|_| Error::new(format!("Error reading contents of {}", filename)))?;
+        let extension = Path::new(filename)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        match extension.as_str() {
+            "jv1" => Self::from_jv1(&data),
+            "jv3" => Self::from_jv3(&data),
+            "dmk" => Self::from_dmk(&data),
+            _ if Self::is_dmk(&data) => Self::from_dmk(&data),
+            _ if !data.is_empty() && data.len() % (JV1_SECTORS_PER_TRACK * JV1_SECTOR_SIZE) == 0 => Self::from_jv1(&data),
+            _ => Self::from_jv3(&data),
+        }
+    }
+
+    fn new(write_protected: bool) -> Self {
+        Self {
+            tracks: vec![],
+            write_protected,
+        }
+    }
+
+    fn add_sector(&mut self, track: u8, side: u8, sector: DiskSector) {
+        let index = track as usize * 2 + (side & 0x01) as usize;
+        if self.tracks.len() <= index {
+            self.tracks.resize(index + 1, vec![]);
+        }
+        self.tracks[index].push(sector);
+    }
+
+    /// Load a JV1 image, which is the 256 byte sectors of each track in order, for a single sided disk
+    pub fn from_jv1(data: &[u8]) -> Result<Self, Error> {
+        if data.is_empty() || data.len() % JV1_SECTOR_SIZE != 0 {
+            return Err(Error::new(format!("JV1 image must be a multiple of 256 bytes, but it's {} bytes", data.len())));
+        }
+
+        let mut disk = Self::new(false);
+        for (i, contents) in data.chunks(JV1_SECTOR_SIZE).enumerate() {
+            let track = (i / JV1_SECTORS_PER_TRACK) as u8;
+            let data_mark = if track == JV1_DIRECTORY_TRACK {
+                JV1_DIRECTORY_MARK
+            } else {
+                DATA_MARK_NORMAL
+            };
+            let sector = DiskSector {
+                track,
+                side: 0,
+                sector: (i % JV1_SECTORS_PER_TRACK) as u8,
+                size_code: 1,
+                data_mark,
+                data: contents.to_vec(),
+            };
+            disk.add_sector(track, 0, sector);
+        }
+        Ok(disk)
+    }
+
+    /// Load a JV3 image, which has one or more blocks of a header table followed by the sectors it describes
+    pub fn from_jv3(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < JV3_HEADER_SIZE {
+            return Err(Error::new(format!("JV3 image must be at least {} bytes, but it's {}", JV3_HEADER_SIZE, data.len())));
+        }
+
+        let mut disk = Self::new(data[JV3_HEADER_SIZE - 1] == 0x00);
+        let mut offset = 0;
+        while offset + JV3_HEADER_SIZE <= data.len() {
+            let header = &data[offset..offset + JV3_HEADER_SIZE - 1];
+            offset += JV3_HEADER_SIZE;
+
+            let mut any_used = false;
+            for entry in header.chunks(3) {
+                let (track, sector, flags) = (entry[0], entry[1], entry[2]);
+                if track == JV3_FREE {
+                    continue;
+                }
+                any_used = true;
+
+                // The size codes are stored with the lowest bit inverted, so that 0 is a 256 byte sector
+                let size_code = (flags & jv3_flags::SIZE) ^ 0x01;
+                let size = DiskSector::size_from_code(size_code);
+                if offset + size > data.len() {
+                    return Err(Error::new(format!("JV3 image is truncated in track {} sector {}", track, sector)));
+                }
+
+                let data_mark = match ((flags & jv3_flags::DATA_MARK) >> 5, (flags & jv3_flags::DOUBLE_DENSITY) != 0) {
+                    (0, _) => DATA_MARK_NORMAL,
+                    (1, true) => 0xF8,
+                    (1, false) => 0xFA,
+                    (2, _) => 0xF9,
+                    (_, _) => 0xF8,
+                };
+                let side = if (flags & jv3_flags::SIDE) != 0 { 1 } else { 0 };
+                disk.add_sector(track, side, DiskSector {
+                    track,
+                    side,
+                    sector,
+                    size_code,
+                    data_mark,
+                    data: data[offset..offset + size].to_vec(),
+                });
+                offset += size;
+            }
+
+            if !any_used {
+                break;
+            }
+        }
+        Ok(disk)
+    }
+
+    fn is_dmk(data: &[u8]) -> bool {
+        if data.len() < DMK_HEADER_SIZE || (data[0] != 0x00 && data[0] != DMK_WRITE_PROTECTED) {
+            return false;
+        }
+        let tracks = data[1] as usize;
+        let track_length = u16::from_le_bytes([data[2], data[3]]) as usize;
+        let sides = if (data[4] & dmk_flags::SINGLE_SIDED) != 0 { 1 } else { 2 };
+        track_length > DMK_IDAM_TABLE_SIZE && data.len() == DMK_HEADER_SIZE + tracks * sides * track_length
+    }
+
+    /// Load a DMK image, which stores the raw bytes of each track, and decode the sectors that are found in it
+    pub fn from_dmk(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < DMK_HEADER_SIZE {
+            return Err(Error::new(format!("DMK image must be at least {} bytes, but it's {}", DMK_HEADER_SIZE, data.len())));
+        }
+
+        let tracks = data[1] as usize;
+        let track_length = u16::from_le_bytes([data[2], data[3]]) as usize;
+        let flags = data[4];
+        let sides = if (flags & dmk_flags::SINGLE_SIDED) != 0 { 1 } else { 2 };
+        if track_length <= DMK_IDAM_TABLE_SIZE || data.len() < DMK_HEADER_SIZE + tracks * sides * track_length {
+            return Err(Error::new(format!("DMK image is truncated, or has an invalid track length of {}", track_length)));
+        }
+        // Single density bytes are written twice, unless the image only has single density, or ignores it
+        let single_density_step = if (flags & (dmk_flags::SINGLE_DENSITY | dmk_flags::IGNORE_DENSITY)) != 0 {
+            1
+        } else {
+            2
+        };
+
+        let mut disk = Self::new(data[0] == DMK_WRITE_PROTECTED);
+        for track in 0..tracks {
+            for side in 0..sides {
+                let start = DMK_HEADER_SIZE + (track * sides + side) * track_length;
+                let raw = &data[start..start + track_length];
+                for pointer in raw[..DMK_IDAM_TABLE_SIZE].chunks(2) {
+                    let pointer = u16::from_le_bytes([pointer[0], pointer[1]]);
+                    if pointer == 0 {
+                        break;
+                    }
+                    let step = if (pointer & dmk_flags::IDAM_DOUBLE_DENSITY) != 0 {
+                        1
+                    } else {
+                        single_density_step
+                    };
+                    let offset = (pointer & dmk_flags::IDAM_OFFSET) as usize;
+                    match decode_sector(raw, offset, step) {
+                        Some(sector) => disk.add_sector(track as u8, side as u8, sector),
+                        None => log::warn!("trs80: ignoring damaged sector in track {} side {} of DMK image", track, side),
+                    }
+                }
+            }
+        }
+        Ok(disk)
+    }
+
+    pub fn is_write_protected(&self) -> bool {
+        self.write_protected
+    }
+
+    /// Returns the sectors on the given side of a track, in the order they pass under the head
+    pub fn track(&self, track: u8, side: u8) -> &[DiskSector] {
+        self.tracks
+            .get(track as usize * 2 + (side & 0x01) as usize)
+            .map(|sectors| sectors.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Returns the sector on the given side of a physical track which has the given track and sector in its ID
+    pub fn find_sector(&mut self, track: u8, side: u8, id_track: u8, id_sector: u8) -> Option<&mut DiskSector> {
+        self.tracks
+            .get_mut(track as usize * 2 + (side & 0x01) as usize)?
+            .iter_mut()
+            .find(|sector| sector.track == id_track && sector.sector == id_sector)
+    }
+
+    /// Replace the sectors on the given side of a track, such as after it's been formatted
+    pub fn replace_track(&mut self, track: u8, side: u8, sectors: Vec<DiskSector>) {
+        let index = track as usize * 2 + (side & 0x01) as usize;
+        if self.tracks.len() <= index {
+            self.tracks.resize(index + 1, vec![]);
+        }
+        self.tracks[index] = sectors;
+    }
+}
+
+/// Decode the sector whose ID address mark is at the given offset of the raw track data, where each byte is
+/// repeated `step` times
+pub fn decode_sector(raw: &[u8], offset: usize, step: usize) -> Option<DiskSector> {
+    let byte = |i: usize| raw.get(offset + i * step).copied();
+    if byte(0)? != ID_ADDRESS_MARK {
+        return None;
+    }
+    let (track, side, sector, size_code) = (byte(1)?, byte(2)?, byte(3)?, byte(4)?);
+
+    // Skip the CRC of the ID field, and then search the gap for the data address mark
+    let data_mark_index = (7..7 + DATA_MARK_SEARCH).find(|i| matches!(byte(*i), Some(0xF8..=0xFB)))?;
+    let data_mark = byte(data_mark_index)?;
+    let size = DiskSector::size_from_code(size_code);
+    let data = (0..size)
+        .map(|i| byte(data_mark_index + 1 + i))
+        .collect::<Option<Vec<u8>>>()?;
+
+    Some(DiskSector {
+        track,
+        side,
+        sector,
+        size_code,
+        data_mark,
+        data,
+    })
+}
@@ -0,0 +1,151 @@
+use femtos::{Instant, Duration};
+
+use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable, strict};
+use moa_signals::Signal;
+
+use super::disk::Trs80Disk;
+use super::wd1771::{Wd1771, MAX_DRIVES};
+
+
+/// The real time clock interrupts the cpu 40 times a second
+const CLOCK_PERIOD_NS: u64 = 25_000_000;
+
+#[rustfmt::skip]
+mod reg {
+    use super::Address;
+    /// Reading returns the interrupt latch, and writing sets the drive select latch
+    pub(super) const LATCH: Address             = 0x00;
+    pub(super) const LATCH_END: Address         = 0x03;
+    pub(super) const CASSETTE: Address          = 0x04;
+    pub(super) const CASSETTE_END: Address      = 0x07;
+    pub(super) const PRINTER: Address           = 0x08;
+    pub(super) const PRINTER_END: Address       = 0x0B;
+    pub(super) const FDC: Address               = 0x0C;
+    pub(super) const FDC_END: Address           = 0x0F;
+}
+
+/// The bits of the interrupt latch, which show what caused the interrupt
+const LATCH_CLOCK: u8 = 0x80;
+const LATCH_DISK: u8 = 0x40;
+
+/// The bit of the drive select latch that selects the second side, used by some double sided drive adapters
+const SELECT_SIDE: u8 = 0x10;
+
+/// The status of the printer port, which has nothing connected, so it's selected and not busy
+const PRINTER_STATUS: u8 = 0x30;
+
+const DEV_NAME: &str = "expansion";
+
+/// The Model I Expansion Interface, which is mapped at 0x37E0 and has the floppy disk controller, the drive
+/// select latch, the printer port, and the real time clock
+///
+/// The interrupt from the disk controller and the clock are both connected to the maskable interrupt of the
+/// cpu (which uses mode 1 on the Model I), and reading the interrupt latch shows which one caused it.  The
+/// non-maskable interrupt is only used by the reset button on the Model I
+pub struct ExpansionInterface {
+    fdc: Wd1771,
+    interrupt: Signal<bool>,
+    clock_interrupt: bool,
+    next_clock: Instant,
+}
+
+impl ExpansionInterface {
+    pub fn new(interrupt: Signal<bool>) -> Self {
+        Self {
+            fdc: Wd1771::default(),
+            interrupt,
+            clock_interrupt: false,
+            next_clock: Instant::START,
+        }
+    }
+
+    pub fn insert_disk(&mut self, drive: usize, disk: Trs80Disk) -> Result<(), Error> {
+        if drive >= MAX_DRIVES {
+            return Err(Error::new(format!("{}: only {} drives can be connected", DEV_NAME, MAX_DRIVES)));
+        }
+        self.fdc.insert_disk(drive, disk);
+        Ok(())
+    }
+
+    fn update_interrupt(&mut self) {
+        self.interrupt.set(self.clock_interrupt || self.fdc.interrupt());
+    }
+}
+
+impl Addressable for ExpansionInterface {
+    fn size(&self) -> usize {
+        0x10
+    }
+
+    fn read(&mut self, clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        data[0] = match addr {
+            reg::LATCH..=reg::LATCH_END => {
+                self.fdc.update(clock);
+                let mut latch = 0;
+                if self.clock_interrupt {
+                    latch |= LATCH_CLOCK;
+                }
+                if self.fdc.interrupt() {
+                    latch |= LATCH_DISK;
+                }
+                // Reading the latch clears the clock interrupt
+                self.clock_interrupt = false;
+                latch
+            },
+            reg::PRINTER..=reg::PRINTER_END => PRINTER_STATUS,
+            reg::FDC..=reg::FDC_END => self.fdc.read(clock, addr - reg::FDC),
+            _ => {
+                strict::unhandled_read(DEV_NAME, addr);
+                0xFF
+            },
+        };
+        self.update_interrupt();
+        log::debug!(target: DEV_NAME, "read from register {:x} of {:x}", addr, data[0]);
+        Ok(())
+    }
+
+    fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        log::debug!(target: DEV_NAME, "write to register {:x} with {:x}", addr, data[0]);
+        match addr {
+            reg::LATCH..=reg::LATCH_END => {
+                let drive = (0..MAX_DRIVES).find(|drive| (data[0] & (1 << drive)) != 0);
+                let side = if (data[0] & SELECT_SIDE) != 0 { 1 } else { 0 };
+                self.fdc.select(drive, side);
+            },
+            reg::CASSETTE..=reg::CASSETTE_END => log::debug!(target: DEV_NAME, "ignoring cassette select of {:x}", data[0]),
+            reg::PRINTER..=reg::PRINTER_END => log::debug!(target: DEV_NAME, "ignoring printer output of {:x}", data[0]),
+            reg::FDC..=reg::FDC_END => self.fdc.write(clock, addr - reg::FDC, data[0]),
+            _ => strict::unhandled_write(DEV_NAME, addr, data),
+        }
+        self.update_interrupt();
+        Ok(())
+    }
+}
+
+impl Steppable for ExpansionInterface {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        if system.clock >= self.next_clock {
+            self.clock_interrupt = true;
+            self.next_clock = self.next_clock + Duration::from_nanos(CLOCK_PERIOD_NS);
+        }
+        self.fdc.update(system.clock);
+        self.update_interrupt();
+
+        let next = self.next_clock.duration_since(system.clock);
+        if self.fdc.is_busy() {
+            Ok(next.min(Duration::from_millis(1)))
+        } else {
+            Ok(next)
+        }
+    }
+}
+
+impl Transmutable for ExpansionInterface {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+}
@@ -1,4 +1,7 @@
+pub mod disk;
+pub mod expansion;
 pub mod model1;
+pub mod wd1771;
 #[rustfmt::skip]
 pub mod keymap;
 #[rustfmt::skip]
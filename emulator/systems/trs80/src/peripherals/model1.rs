@@ -1,6 +1,6 @@
 use femtos::{Instant, Duration};
 
-use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable};
+use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable, Capabilities, strict};
 use moa_host::{self, Host, HostError, Frame, FrameSender, KeyEvent, EventReceiver};
 
 use super::keymap;
@@ -8,7 +8,7 @@ use super::charset::CharacterGenerator;
 
 
 const DEV_NAME: &str = "model1";
-const SCREEN_SIZE: (u32, u32) = (384, 128);
+pub const SCREEN_SIZE: (u32, u32) = (384, 128);
 
 
 pub struct Model1Keyboard {
@@ -33,12 +33,12 @@ impl Model1Keyboard {
 
 impl Addressable for Model1Keyboard {
     fn size(&self) -> usize {
-        0x420
+        0x400
     }
 
     fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
-        if (0x20..=0xA0).contains(&addr) {
-            let offset = addr - 0x20;
+        if addr <= 0x80 {
+            let offset = addr;
             data[0] = 0;
             if (offset & 0x01) != 0 {
                 data[0] |= self.keyboard_mem[0];
@@ -66,14 +66,14 @@ impl Addressable for Model1Keyboard {
             }
             //info!("{}: read from keyboard {:x} of {:?}", DEV_NAME, addr, data);
         } else {
-            log::warn!("{}: !!! unhandled read from {:0x}", DEV_NAME, addr);
+            strict::unhandled_read(DEV_NAME, addr);
         }
-        log::debug!("{}: read from register {:x} of {:?}", DEV_NAME, addr, data);
+        log::debug!(target: DEV_NAME, "read from register {:x} of {:?}", addr, data);
         Ok(())
     }
 
     fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
-        log::warn!("{}: !!! unhandled write {:0x} to {:0x}", DEV_NAME, data[0], addr);
+        strict::unhandled_write(DEV_NAME, addr, data);
         Ok(())
     }
 }
@@ -142,12 +142,12 @@ impl Addressable for Model1Video {
 
     fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
         data[0] = self.video_mem[addr as usize];
-        log::debug!("{}: read from register {:x} of {:?}", DEV_NAME, addr, data);
+        log::debug!(target: DEV_NAME, "read from register {:x} of {:?}", addr, data);
         Ok(())
     }
 
     fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
-        log::debug!("{}: write to register {:x} with {:x}", DEV_NAME, addr, data[0]);
+        log::debug!(target: DEV_NAME, "write to register {:x} with {:x}", addr, data[0]);
         self.video_mem[addr as usize] = data[0];
         Ok(())
     }
@@ -161,4 +161,8 @@ impl Transmutable for Model1Video {
     fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
         Some(self)
     }
+
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities::probe(self) | Capabilities::VIDEO
+    }
 }
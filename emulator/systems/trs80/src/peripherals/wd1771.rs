@@ -0,0 +1,524 @@
+use femtos::{Instant, Duration};
+
+use moa_core::{Address, strict};
+
+use super::disk::{Trs80Disk, decode_sector};
+
+
+/// The number of drives that the expansion interface can select
+pub const MAX_DRIVES: usize = 4;
+
+/// The number of tracks that the drive can step to
+const MAX_TRACKS: u8 = 80;
+
+/// The time it takes for the disk to make one revolution at 300 RPM
+const REVOLUTION_NS: u64 = 200_000_000;
+/// The length of the index pulse, which is once per revolution
+const INDEX_PULSE_NS: u64 = 4_000_000;
+/// The time it takes to search for a sector before giving up, which is 4 revolutions
+const SEARCH_TIMEOUT_NS: u64 = 4 * REVOLUTION_NS;
+/// The time between each step of the head, selected by the rate bits of the type I commands
+const STEP_RATES_NS: [u64; 4] = [6_000_000, 6_000_000, 10_000_000, 20_000_000];
+/// The number of bytes in one revolution of a single density track, which is what write track expects
+const WRITE_TRACK_LENGTH: usize = 3125;
+
+#[rustfmt::skip]
+mod reg {
+    use super::Address;
+    pub(super) const COMMAND_STATUS: Address    = 0x0;
+    pub(super) const TRACK: Address             = 0x1;
+    pub(super) const SECTOR: Address            = 0x2;
+    pub(super) const DATA: Address              = 0x3;
+}
+
+#[rustfmt::skip]
+mod status {
+    pub(super) const BUSY: u8               = 0x01;
+    pub(super) const INDEX: u8              = 0x02;
+    pub(super) const DATA_REQUEST: u8       = 0x02;
+    pub(super) const TRACK_ZERO: u8         = 0x04;
+    pub(super) const RECORD_NOT_FOUND: u8   = 0x10;
+    pub(super) const SEEK_ERROR: u8         = 0x10;
+    pub(super) const HEAD_LOADED: u8        = 0x20;
+    pub(super) const RECORD_TYPE: u8        = 0x60;
+    pub(super) const WRITE_PROTECT: u8      = 0x40;
+    pub(super) const NOT_READY: u8          = 0x80;
+}
+
+#[rustfmt::skip]
+mod flags {
+    pub(super) const UPDATE_TRACK: u8   = 0x10;
+    pub(super) const VERIFY: u8         = 0x04;
+    pub(super) const STEP_RATE: u8      = 0x03;
+    pub(super) const MULTIPLE: u8       = 0x10;
+    pub(super) const DATA_MARK: u8      = 0x03;
+    pub(super) const IMMEDIATE: u8      = 0x08;
+}
+
+const DEV_NAME: &str = "wd1771";
+
+/// The data transfer of the command that's in progress, if any
+enum Transfer {
+    None,
+    Read(Vec<u8>),
+    Write { data_mark: u8, buffer: Vec<u8> },
+    WriteTrack(Vec<u8>),
+}
+
+#[derive(Default)]
+struct Drive {
+    disk: Option<Trs80Disk>,
+    track: u8,
+}
+
+/// The Western Digital FD1771 single density floppy disk controller, which transfers each byte through the data
+/// register as the cpu polls the data request bit, and raises its interrupt output when a command completes
+pub struct Wd1771 {
+    drives: [Drive; MAX_DRIVES],
+    selected: Option<usize>,
+    side: u8,
+
+    command: u8,
+    status: u8,
+    track: u8,
+    sector: u8,
+    data: u8,
+    step_inward: bool,
+    interrupt: bool,
+    transfer: Transfer,
+    /// The time when the command in progress finishes, and the status it ends with
+    completion: Option<(Instant, u8)>,
+}
+
+impl Default for Wd1771 {
+    fn default() -> Self {
+        Self {
+            drives: Default::default(),
+            selected: None,
+            side: 0,
+
+            command: 0,
+            status: 0,
+            track: 0,
+            sector: 0,
+            data: 0,
+            step_inward: true,
+            interrupt: false,
+            transfer: Transfer::None,
+            completion: None,
+        }
+    }
+}
+
+impl Wd1771 {
+    pub fn insert_disk(&mut self, drive: usize, disk: Trs80Disk) {
+        self.drives[drive].disk = Some(disk);
+    }
+
+    /// Select the drive and side from the drive select latch, where the lowest set bit selects the drive
+    pub fn select(&mut self, drive: Option<usize>, side: u8) {
+        self.selected = drive.filter(|drive| *drive < MAX_DRIVES);
+        self.side = side;
+    }
+
+    /// Returns the state of the interrupt request output, which goes high when a command completes
+    pub fn interrupt(&self) -> bool {
+        self.interrupt
+    }
+
+    /// Returns true while a command is waiting to complete, so the controller needs to be updated
+    pub fn is_busy(&self) -> bool {
+        self.completion.is_some()
+    }
+
+    fn selected_drive(&mut self) -> Option<&mut Drive> {
+        self.selected.map(|drive| &mut self.drives[drive])
+    }
+
+    fn selected_disk(&mut self) -> Option<&mut Trs80Disk> {
+        self.selected_drive().and_then(|drive| drive.disk.as_mut())
+    }
+
+    /// Finish the command in progress if its time has passed
+    pub fn update(&mut self, clock: Instant) {
+        if let Some((time, status)) = self.completion {
+            if clock >= time {
+                self.finish(status);
+            }
+        }
+    }
+
+    fn finish(&mut self, status: u8) {
+        self.completion = None;
+        self.transfer = Transfer::None;
+        self.status = status;
+        self.interrupt = true;
+    }
+
+    pub fn read(&mut self, clock: Instant, addr: Address) -> u8 {
+        self.update(clock);
+        let value = match addr {
+            reg::COMMAND_STATUS => {
+                // Reading the status clears the interrupt
+                self.interrupt = false;
+                self.current_status(clock)
+            },
+            reg::TRACK => self.track,
+            reg::SECTOR => self.sector,
+            reg::DATA => {
+                self.read_data();
+                self.data
+            },
+            _ => {
+                strict::unhandled_read(DEV_NAME, addr);
+                0xFF
+            },
+        };
+        log::debug!(target: DEV_NAME, "read from register {:x} of {:x}", addr, value);
+        value
+    }
+
+    pub fn write(&mut self, clock: Instant, addr: Address, value: u8) {
+        log::debug!(target: DEV_NAME, "write to register {:x} with {:x}", addr, value);
+        self.update(clock);
+        match addr {
+            reg::COMMAND_STATUS => self.execute_command(clock, value),
+            reg::TRACK => self.track = value,
+            reg::SECTOR => self.sector = value,
+            reg::DATA => {
+                self.data = value;
+                self.write_data(clock);
+            },
+            _ => strict::unhandled_write(DEV_NAME, addr, &[value]),
+        }
+    }
+
+    fn current_status(&mut self, clock: Instant) -> u8 {
+        let mut status = self.status;
+        let (has_disk, write_protected, track) = self
+            .selected_drive()
+            .map(|drive| {
+                let write_protected = drive.disk.as_ref().map(|disk| disk.is_write_protected()).unwrap_or(false);
+                (drive.disk.is_some(), write_protected, drive.track)
+            })
+            .unwrap_or((false, false, 0));
+        if !has_disk {
+            status |= status::NOT_READY;
+        }
+
+        // The index pulse, track zero, and write protect bits are only shown after a type I command, or after
+        // force interrupt
+        if (self.command & 0x80) == 0 || (self.command & 0xF0) == 0xD0 {
+            status &= !(status::INDEX | status::TRACK_ZERO | status::WRITE_PROTECT);
+            if has_disk && (clock.as_duration().as_nanos() as u64 % REVOLUTION_NS) < INDEX_PULSE_NS {
+                status |= status::INDEX;
+            }
+            if track == 0 {
+                status |= status::TRACK_ZERO;
+            }
+            if write_protected {
+                status |= status::WRITE_PROTECT;
+            }
+        }
+        status
+    }
+
+    fn execute_command(&mut self, clock: Instant, command: u8) {
+        log::debug!(target: DEV_NAME, "executing command {:x}", command);
+
+        if (command & 0xF0) == 0xD0 {
+            // Force interrupt terminates the current command, and interrupts immediately if bit 3 is set
+            self.completion = None;
+            self.transfer = Transfer::None;
+            self.command = command;
+            self.status &= !(status::BUSY | status::DATA_REQUEST);
+            self.interrupt = (command & flags::IMMEDIATE) != 0;
+            return;
+        }
+
+        // A new command can't be started until the current one finishes
+        if (self.status & status::BUSY) != 0 {
+            log::warn!(target: DEV_NAME, "ignoring command {:x} while busy", command);
+            return;
+        }
+
+        self.command = command;
+        self.interrupt = false;
+        self.status = status::BUSY;
+        if self.selected_disk().is_none() {
+            self.finish(status::NOT_READY);
+            return;
+        }
+
+        match command >> 4 {
+            0x0..=0x7 => self.execute_type_one(clock, command),
+            0x8 | 0x9 => self.read_sector(clock),
+            0xA | 0xB => self.write_sector(clock, command),
+            0xC => self.read_address(clock),
+            0xE => {
+                strict::unhandled(DEV_NAME, "read track isn't supported".to_string());
+                self.completion = Some((clock + Duration::from_nanos(REVOLUTION_NS), status::RECORD_NOT_FOUND));
+            },
+            _ => self.write_track(clock),
+        }
+    }
+
+    fn execute_type_one(&mut self, clock: Instant, command: u8) {
+        let steps: i16 = match command >> 4 {
+            // Restore steps out until track zero, and then clears the track register
+            0x0 => -(self.selected_drive().map(|drive| drive.track).unwrap_or(0) as i16),
+            0x1 => self.data as i16 - self.track as i16,
+            0x2 | 0x3 => {
+                if self.step_inward {
+                    1
+                } else {
+                    -1
+                }
+            },
+            0x4 | 0x5 => {
+                self.step_inward = true;
+                1
+            },
+            _ => {
+                self.step_inward = false;
+                -1
+            },
+        };
+
+        if let Some(drive) = self.selected_drive() {
+            drive.track = (drive.track as i16 + steps).clamp(0, MAX_TRACKS as i16 - 1) as u8;
+        }
+
+        match command >> 4 {
+            0x0 => self.track = 0,
+            0x1 => self.track = self.data,
+            _ if (command & flags::UPDATE_TRACK) != 0 => self.track = (self.track as i16 + steps).clamp(0, 0xFF) as u8,
+            _ => {},
+        }
+
+        let mut status = status::HEAD_LOADED;
+        if (command & flags::VERIFY) != 0 {
+            // Verify checks that the track register matches the ID of a sector under the head
+            let (track, side, id_track) = (self.physical_track(), self.side, self.track);
+            let found = self
+                .selected_disk()
+                .map(|disk| disk.track(track, side).iter().any(|sector| sector.track == id_track))
+                .unwrap_or(false);
+            if !found {
+                status |= status::SEEK_ERROR;
+            }
+        }
+
+        let step_time = STEP_RATES_NS[(command & flags::STEP_RATE) as usize] * steps.unsigned_abs() as u64;
+        self.completion = Some((clock + Duration::from_nanos(step_time), status));
+    }
+
+    fn physical_track(&mut self) -> u8 {
+        self.selected_drive().map(|drive| drive.track).unwrap_or(0)
+    }
+
+    /// Start reading the sector in the sector register, whose ID must match the track register
+    fn read_sector(&mut self, clock: Instant) {
+        let (track, side, id_track, id_sector) = (self.physical_track(), self.side, self.track, self.sector);
+        let sector = self
+            .selected_disk()
+            .and_then(|disk| disk.find_sector(track, side, id_track, id_sector))
+            .map(|sector| (sector.data.clone(), sector.data_mark));
+
+        match sector {
+            Some((data, data_mark)) => {
+                self.status = status::BUSY | status::DATA_REQUEST | record_type(data_mark);
+                self.transfer = Transfer::Read(data);
+            },
+            None => self.completion = Some((clock + Duration::from_nanos(SEARCH_TIMEOUT_NS), status::RECORD_NOT_FOUND)),
+        }
+    }
+
+    fn write_sector(&mut self, clock: Instant, command: u8) {
+        if self.selected_disk().map(|disk| disk.is_write_protected()).unwrap_or(true) {
+            self.finish(status::WRITE_PROTECT);
+            return;
+        }
+
+        let (track, side, id_track, id_sector) = (self.physical_track(), self.side, self.track, self.sector);
+        let exists = self
+            .selected_disk()
+            .and_then(|disk| disk.find_sector(track, side, id_track, id_sector))
+            .is_some();
+        if !exists {
+            self.completion = Some((clock + Duration::from_nanos(SEARCH_TIMEOUT_NS), status::RECORD_NOT_FOUND));
+            return;
+        }
+
+        self.status = status::BUSY | status::DATA_REQUEST;
+        self.transfer = Transfer::Write {
+            data_mark: 0xFB - (command & flags::DATA_MARK),
+            buffer: vec![],
+        };
+    }
+
+    /// Start reading the ID field of the next sector that passes under the head
+    fn read_address(&mut self, clock: Instant) {
+        let (track, side) = (self.physical_track(), self.side);
+        let sectors = self
+            .selected_disk()
+            .map(|disk| disk.track(track, side).to_vec())
+            .unwrap_or_default();
+        if sectors.is_empty() {
+            self.completion = Some((clock + Duration::from_nanos(SEARCH_TIMEOUT_NS), status::RECORD_NOT_FOUND));
+            return;
+        }
+
+        // Use the rotation of the disk to choose which sector is next
+        let position = (clock.as_duration().as_nanos() as u64 % REVOLUTION_NS) * sectors.len() as u64 / REVOLUTION_NS;
+        let sector = &sectors[position as usize];
+        // The track number in the ID is also copied into the sector register
+        self.sector = sector.track;
+        self.status = status::BUSY | status::DATA_REQUEST;
+        self.transfer = Transfer::Read(vec![sector.track, sector.side, sector.sector, sector.size_code, 0x00, 0x00]);
+    }
+
+    fn write_track(&mut self, _clock: Instant) {
+        if self.selected_disk().map(|disk| disk.is_write_protected()).unwrap_or(true) {
+            self.finish(status::WRITE_PROTECT);
+            return;
+        }
+        self.status = status::BUSY | status::DATA_REQUEST;
+        self.transfer = Transfer::WriteTrack(vec![]);
+    }
+
+    /// Move the next byte of the transfer into the data register
+    fn read_data(&mut self) {
+        if let Transfer::Read(buffer) = &mut self.transfer {
+            if buffer.is_empty() {
+                return;
+            }
+            self.data = buffer.remove(0);
+            if buffer.is_empty() {
+                self.next_sector();
+            }
+        }
+    }
+
+    /// Finish reading a sector, and continue with the next one if the multiple sector flag is set
+    fn next_sector(&mut self) {
+        let last_record_type = self.status & status::RECORD_TYPE;
+        if (self.command >> 5) == 0x4 && (self.command & flags::MULTIPLE) != 0 {
+            self.sector = self.sector.wrapping_add(1);
+            let (track, side, id_track, id_sector) = (self.physical_track(), self.side, self.track, self.sector);
+            let next = self
+                .selected_disk()
+                .and_then(|disk| disk.find_sector(track, side, id_track, id_sector))
+                .map(|sector| (sector.data.clone(), sector.data_mark));
+            if let Some((data, data_mark)) = next {
+                self.status = status::BUSY | status::DATA_REQUEST | record_type(data_mark);
+                self.transfer = Transfer::Read(data);
+                return;
+            }
+            // Multiple sector commands end when there's no next sector
+            self.finish(last_record_type | status::RECORD_NOT_FOUND);
+            return;
+        }
+        self.finish(last_record_type);
+    }
+
+    /// Add the data register to the transfer, and write the sector or track when it's complete
+    fn write_data(&mut self, clock: Instant) {
+        let data = self.data;
+        match &mut self.transfer {
+            Transfer::Write {
+                buffer,
+                ..
+            } => buffer.push(data),
+            Transfer::WriteTrack(buffer) => buffer.push(data),
+            _ => return,
+        }
+
+        match std::mem::replace(&mut self.transfer, Transfer::None) {
+            Transfer::Write {
+                data_mark,
+                buffer,
+            } => self.write_buffer(clock, data_mark, buffer),
+            Transfer::WriteTrack(buffer) if buffer.len() >= WRITE_TRACK_LENGTH => {
+                self.format_track(&buffer);
+                self.finish(0);
+            },
+            transfer => self.transfer = transfer,
+        }
+    }
+
+    /// Write the buffered data to the sector once all of it has been transferred, and continue with the next
+    /// sector if the multiple sector flag is set
+    fn write_buffer(&mut self, clock: Instant, data_mark: u8, buffer: Vec<u8>) {
+        let (track, side, id_track, id_sector) = (self.physical_track(), self.side, self.track, self.sector);
+        let size = self
+            .selected_disk()
+            .and_then(|disk| disk.find_sector(track, side, id_track, id_sector))
+            .map(|sector| sector.data.len());
+        match size {
+            Some(size) if buffer.len() < size => {
+                self.transfer = Transfer::Write {
+                    data_mark,
+                    buffer,
+                };
+                return;
+            },
+            Some(_) => {},
+            None => {
+                self.finish(status::RECORD_NOT_FOUND);
+                return;
+            },
+        }
+
+        if let Some(sector) = self
+            .selected_disk()
+            .and_then(|disk| disk.find_sector(track, side, id_track, id_sector))
+        {
+            sector.data.copy_from_slice(&buffer);
+            sector.data_mark = data_mark;
+        }
+
+        if (self.command & flags::MULTIPLE) != 0 {
+            self.sector = self.sector.wrapping_add(1);
+            self.write_sector(clock, self.command);
+        } else {
+            self.finish(0);
+        }
+    }
+
+    /// Decode the sectors from the raw track written by the write track command, where 0xF7 writes the two
+    /// bytes of the CRC, and replace the current track with them
+    fn format_track(&mut self, buffer: &[u8]) {
+        let mut raw = Vec::with_capacity(buffer.len() * 2);
+        for byte in buffer {
+            if *byte == 0xF7 {
+                raw.extend([0x00, 0x00]);
+            } else {
+                raw.push(*byte);
+            }
+        }
+
+        let mut sectors = vec![];
+        let mut i = 0;
+        while i < raw.len() {
+            match decode_sector(&raw, i, 1) {
+                Some(sector) => {
+                    i += sector.data.len() + 7;
+                    sectors.push(sector);
+                },
+                None => i += 1,
+            }
+        }
+
+        let (track, side) = (self.physical_track(), self.side);
+        log::info!(target: DEV_NAME, "formatted track {} side {} with {} sectors", track, side, sectors.len());
+        if let Some(disk) = self.selected_disk() {
+            disk.replace_track(track, side, sectors);
+        }
+    }
+}
+
+/// Returns the record type bits of the status for a sector's data address mark
+fn record_type(data_mark: u8) -> u8 {
+    ((0xFB - data_mark.clamp(0xF8, 0xFB)) << 5) & status::RECORD_TYPE
+}
@@ -1,17 +1,23 @@
 use femtos::Frequency;
 
-use moa_core::{System, Error, MemoryBlock, Device};
+use moa_core::{System, Error, MemoryBlock, Device, MachineInfo};
 use moa_host::Host;
 
 use moa_z80::{MoaZ80, Z80, Z80Type};
 
-use crate::peripherals::model1::{Model1Keyboard, Model1Video};
+use crate::peripherals::disk::Trs80Disk;
+use crate::peripherals::expansion::ExpansionInterface;
+use crate::peripherals::model1::{Model1Keyboard, Model1Video, SCREEN_SIZE};
 
 
+#[derive(Debug)]
 pub struct Trs80Options {
     pub rom: String,
     pub memory: u16,
     pub frequency: Frequency,
+    /// Disk images for drives 0 to 3, in the JV1, JV3, or DMK formats, which connect the Expansion Interface
+    /// so that the ROM boots from drive 0
+    pub disks: Vec<String>,
 }
 
 impl Default for Trs80Options {
@@ -20,6 +26,7 @@ impl Default for Trs80Options {
             rom: "binaries/trs80/level2.rom".to_string(),
             memory: 0xC000,
             frequency: Frequency::from_hz(1_774_000),
+            disks: vec![],
         }
     }
 }
@@ -27,6 +34,10 @@ impl Default for Trs80Options {
 
 pub fn build_trs80<H: Host>(host: &mut H, options: Trs80Options) -> Result<System, Error> {
     let mut system = System::default();
+    system.machine_info = MachineInfo::new("trs80")
+        .with_video(SCREEN_SIZE.0, SCREEN_SIZE.1, Frequency::from_hz(60))
+        .with_aspect_ratio(4.0 / 3.0)
+        .with_rom(&options.rom);
 
     let mut rom = MemoryBlock::new(vec![0; 0x3000]);
     //rom.load_at(0x0000, "binaries/trs80/level1.rom")?;
@@ -39,16 +50,23 @@ pub fn build_trs80<H: Host>(host: &mut H, options: Trs80Options) -> Result<Syste
     system.add_addressable_device(0x4000, Device::new(ram))?;
 
     let keyboard = Model1Keyboard::new(host)?;
-    system.add_addressable_device(0x37E0, Device::new(keyboard)).unwrap();
+    system.add_addressable_device(0x3800, Device::new(keyboard)).unwrap();
     let video = Model1Video::new(host)?;
-    system.add_addressable_device(0x37E0 + 0x420, Device::new(video)).unwrap();
+    system.add_addressable_device(0x3C00, Device::new(video)).unwrap();
 
     // TODO the ioport needs to be hooked up
     let cpu = Z80::from_type(Z80Type::Z80, options.frequency);
-    let cpu = MoaZ80 {
-        bus: system.bus.clone(),
-        cpu,
-    };
+
+    // Without the Expansion Interface, the ROM finds no disk controller and starts BASIC
+    if !options.disks.is_empty() {
+        let mut expansion = ExpansionInterface::new(cpu.signals.interrupt.clone());
+        for (drive, filename) in options.disks.iter().enumerate() {
+            expansion.insert_disk(drive, Trs80Disk::load(filename)?)?;
+        }
+        system.add_peripheral("expansion", 0x37E0, Device::new(expansion))?;
+    }
+
+    let cpu = MoaZ80::new(cpu, system.bus.clone());
 
     system.add_interruptable_device("cpu", Device::new(cpu))?;
 
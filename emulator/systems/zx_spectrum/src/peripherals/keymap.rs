@@ -0,0 +1,72 @@
+use moa_host::Key;
+
+/// The row and column of the caps shift and symbol shift keys in the keyboard matrix
+pub const CAPS_SHIFT: (usize, u8) = (0, 0);
+pub const SYMBOL_SHIFT: (usize, u8) = (7, 1);
+
+/// Returns the positions in the keyboard matrix that are pressed by the given host key, as a half-row and a
+/// bit in that half-row.  The keys that the Spectrum doesn't have are mapped to a shifted combination,
+/// such as backspace, which is caps shift and 0
+pub fn key_to_matrix(key: Key) -> &'static [(usize, u8)] {
+    match key {
+        Key::LeftShift | Key::RightShift => &[CAPS_SHIFT],
+        Key::Z => &[(0, 1)],
+        Key::X => &[(0, 2)],
+        Key::C => &[(0, 3)],
+        Key::V => &[(0, 4)],
+        Key::A => &[(1, 0)],
+        Key::S => &[(1, 1)],
+        Key::D => &[(1, 2)],
+        Key::F => &[(1, 3)],
+        Key::G => &[(1, 4)],
+        Key::Q => &[(2, 0)],
+        Key::W => &[(2, 1)],
+        Key::E => &[(2, 2)],
+        Key::R => &[(2, 3)],
+        Key::T => &[(2, 4)],
+        Key::Num1 => &[(3, 0)],
+        Key::Num2 => &[(3, 1)],
+        Key::Num3 => &[(3, 2)],
+        Key::Num4 => &[(3, 3)],
+        Key::Num5 => &[(3, 4)],
+        Key::Num0 => &[(4, 0)],
+        Key::Num9 => &[(4, 1)],
+        Key::Num8 => &[(4, 2)],
+        Key::Num7 => &[(4, 3)],
+        Key::Num6 => &[(4, 4)],
+        Key::P => &[(5, 0)],
+        Key::O => &[(5, 1)],
+        Key::I => &[(5, 2)],
+        Key::U => &[(5, 3)],
+        Key::Y => &[(5, 4)],
+        Key::Enter | Key::NumPadEnter => &[(6, 0)],
+        Key::L => &[(6, 1)],
+        Key::K => &[(6, 2)],
+        Key::J => &[(6, 3)],
+        Key::H => &[(6, 4)],
+        Key::Space => &[(7, 0)],
+        Key::LeftCtrl | Key::RightCtrl | Key::LeftAlt | Key::RightAlt => &[SYMBOL_SHIFT],
+        Key::M => &[(7, 2)],
+        Key::N => &[(7, 3)],
+        Key::B => &[(7, 4)],
+
+        Key::Backspace => &[CAPS_SHIFT, (4, 0)],
+        Key::Escape => &[CAPS_SHIFT, (7, 0)],
+        Key::CapsLock => &[CAPS_SHIFT, (3, 1)],
+        Key::Left => &[CAPS_SHIFT, (3, 4)],
+        Key::Down => &[CAPS_SHIFT, (4, 4)],
+        Key::Up => &[CAPS_SHIFT, (4, 3)],
+        Key::Right => &[CAPS_SHIFT, (4, 2)],
+        Key::Comma => &[SYMBOL_SHIFT, (7, 3)],
+        Key::Period => &[SYMBOL_SHIFT, (7, 2)],
+        Key::Semicolon => &[SYMBOL_SHIFT, (5, 1)],
+        Key::Apostrophe => &[SYMBOL_SHIFT, (5, 0)],
+        Key::Slash => &[SYMBOL_SHIFT, (0, 4)],
+        Key::Minus | Key::NumPadMinus => &[SYMBOL_SHIFT, (6, 3)],
+        Key::Equals => &[SYMBOL_SHIFT, (6, 1)],
+        Key::NumPadPlus => &[SYMBOL_SHIFT, (6, 2)],
+        Key::NumPadAsterisk => &[SYMBOL_SHIFT, (7, 4)],
+        Key::NumPadSlash => &[SYMBOL_SHIFT, (0, 4)],
+        _ => &[],
+    }
+}
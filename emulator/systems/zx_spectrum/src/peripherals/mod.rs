@@ -0,0 +1,4 @@
+pub mod rom;
+pub mod ula;
+#[rustfmt::skip]
+pub mod keymap;
@@ -0,0 +1,41 @@
+use femtos::Instant;
+
+use moa_core::{Error, Address, Addressable, Transmutable};
+
+
+/// The 16K ROM, where writes are ignored instead of causing an error, since the Spectrum software expects
+/// them to have no effect
+pub struct Rom {
+    contents: Vec<u8>,
+}
+
+impl Rom {
+    pub fn new(contents: Vec<u8>) -> Self {
+        Self {
+            contents,
+        }
+    }
+}
+
+impl Addressable for Rom {
+    fn size(&self) -> usize {
+        self.contents.len()
+    }
+
+    fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        let addr = addr as usize;
+        data.copy_from_slice(&self.contents[addr..addr + data.len()]);
+        Ok(())
+    }
+
+    fn write(&mut self, _clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        log::trace!("rom: ignoring write to {:x} with {:?}", addr, data);
+        Ok(())
+    }
+}
+
+impl Transmutable for Rom {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+}
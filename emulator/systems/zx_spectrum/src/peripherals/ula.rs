@@ -0,0 +1,343 @@
+use femtos::{Instant, Duration, Frequency};
+
+use moa_core::{System, Error, Address, Addressable, Steppable, Transmutable, Capabilities, Device};
+use moa_host::{self, Host, HostError, Frame, FrameSender, Pixel, Audio, Sample, KeyEvent, EventReceiver};
+use moa_signals::Signal;
+use moa_z80::{Z80BusCycle, Z80BusCycleKind};
+
+use super::keymap;
+
+
+/// The size of the output frames, which is the 256x192 display surrounded by a 32 pixel border
+pub const SCREEN_SIZE: (u32, u32) = (DISPLAY_SIZE.0 + BORDER * 2, DISPLAY_SIZE.1 + BORDER * 2);
+const DISPLAY_SIZE: (u32, u32) = (256, 192);
+const BORDER: u32 = 32;
+
+#[rustfmt::skip]
+mod timing {
+    /// The number of clocks (T states) in each line and frame of the 48K
+    pub(super) const CLOCKS_PER_LINE: u64      = 224;
+    pub(super) const LINES_PER_FRAME: u64      = 312;
+    pub(super) const CLOCKS_PER_FRAME: u64     = CLOCKS_PER_LINE * LINES_PER_FRAME;
+    /// The line that the first line of the display is drawn on
+    pub(super) const FIRST_DISPLAY_LINE: u64   = 64;
+    /// The clock at which the ULA starts fetching the first line of the display, which is when contention starts
+    pub(super) const CONTENTION_START: u64     = 14335;
+    /// The number of clocks in each line where the ULA fetches the display
+    pub(super) const DISPLAY_CLOCKS: u64       = 128;
+    /// The number of clocks that the interrupt output stays asserted for at the start of each frame
+    pub(super) const INTERRUPT_CLOCKS: u64     = 32;
+}
+
+/// The number of clocks a bus cycle is delayed by when it starts at each clock of the ULA's 8 clock fetch
+const CONTENTION_PATTERN: [u16; 8] = [6, 5, 4, 3, 2, 1, 0, 0];
+
+/// The flash attribute swaps the ink and paper every 16 frames
+const FLASH_FRAMES: u32 = 16;
+
+#[rustfmt::skip]
+mod port {
+    pub(super) const BORDER_MASK: u8   = 0x07;
+    pub(super) const MIC: u8           = 0x08;
+    pub(super) const EAR: u8           = 0x10;
+}
+
+/// The offsets of the display and attributes in RAM, which starts at 0x4000
+const DISPLAY_BASE: usize = 0x0000;
+const ATTRIBUTES_BASE: usize = 0x1800;
+const SCREEN_MEMORY: usize = 0x1B00;
+
+const DEV_NAME: &str = "ula";
+
+/// Returns the number of clocks that the ULA delays a bus cycle of the cpu by, which happens when the cpu
+/// accesses the lower 16K of RAM, or an even numbered port, while the ULA is reading the display.  The clock
+/// is counted from the start of the first frame
+pub fn contention_delay(clock: u64, cycle: &Z80BusCycle) -> u16 {
+    let contended_memory = (0x4000..0x8000).contains(&cycle.addr);
+    let contended = match cycle.kind {
+        Z80BusCycleKind::IoRead | Z80BusCycleKind::IoWrite => contended_memory || (cycle.addr & 0x01) == 0,
+        _ => contended_memory,
+    };
+    if !contended {
+        return 0;
+    }
+
+    let clock = (clock % timing::CLOCKS_PER_FRAME).wrapping_sub(timing::CONTENTION_START);
+    let line = clock / timing::CLOCKS_PER_LINE;
+    let position = clock % timing::CLOCKS_PER_LINE;
+    if line >= DISPLAY_SIZE.1 as u64 || position >= timing::DISPLAY_CLOCKS {
+        return 0;
+    }
+    CONTENTION_PATTERN[(position % 8) as usize]
+}
+
+/// The ULA of the ZX Spectrum 48K, which draws the display from RAM, interrupts the cpu at the start of
+/// each frame, and decodes the port with the keyboard, border colour, and beeper on it, which is
+/// selected by any even port address
+pub struct Ula {
+    frame_sender: FrameSender,
+    key_receiver: EventReceiver<KeyEvent>,
+    source: Box<dyn Audio>,
+    ram: Device,
+    interrupt: Signal<bool>,
+    period: Duration,
+
+    /// The number of host keys that are holding down each key in the matrix, since some host keys press
+    /// the same shift key
+    keys: [[u8; 5]; 8],
+    output: u8,
+
+    frame_start: Instant,
+    frame_count: u32,
+    /// The border colour at the start of the frame, and the clock within the frame of each change after it
+    border_start: u8,
+    border_changes: Vec<(u64, u8)>,
+
+    last_audio: Instant,
+    /// The level of the beeper at the start of the audio period, and the time of each change after it
+    beeper_start: bool,
+    beeper_changes: Vec<(Instant, bool)>,
+}
+
+impl Ula {
+    pub fn new<H, E>(host: &mut H, ram: Device, interrupt: Signal<bool>, frequency: Frequency) -> Result<Self, HostError<E>>
+    where
+        H: Host<Error = E>,
+    {
+        let (frame_sender, frame_receiver) = moa_host::frame_queue(SCREEN_SIZE.0, SCREEN_SIZE.1);
+        host.add_video_source(frame_receiver)?;
+        let (key_sender, key_receiver) = moa_host::event_queue();
+        host.register_keyboard(key_sender)?;
+        let source = host.add_audio_source()?;
+
+        Ok(Self {
+            frame_sender,
+            key_receiver,
+            source,
+            ram,
+            interrupt,
+            period: frequency.period_duration(),
+
+            keys: [[0; 5]; 8],
+            output: 0,
+
+            frame_start: Instant::START,
+            frame_count: 0,
+            border_start: 0,
+            border_changes: vec![],
+
+            last_audio: Instant::START,
+            beeper_start: false,
+            beeper_changes: vec![],
+        })
+    }
+
+    fn frame_duration(&self) -> Duration {
+        self.period * timing::CLOCKS_PER_FRAME as u32
+    }
+
+    /// Returns the keyboard half-rows selected by the upper byte of the port address, where a pressed key and
+    /// a selected row are both low
+    fn read_keyboard(&self, port: u16) -> u8 {
+        let mut data = 0x1F;
+        for (row, keys) in self.keys.iter().enumerate() {
+            if (port & (0x100 << row)) == 0 {
+                for (bit, count) in keys.iter().enumerate() {
+                    if *count > 0 {
+                        data &= !(1 << bit);
+                    }
+                }
+            }
+        }
+        data
+    }
+
+    fn update_keyboard(&mut self) {
+        while let Some(event) = self.key_receiver.receive() {
+            for (row, bit) in keymap::key_to_matrix(event.key) {
+                let count = &mut self.keys[*row][*bit as usize];
+                *count = if event.state {
+                    count.saturating_add(1)
+                } else {
+                    count.saturating_sub(1)
+                };
+            }
+        }
+    }
+
+    fn write_port(&mut self, clock: Instant, data: u8) {
+        let changed = self.output ^ data;
+        self.output = data;
+
+        if (changed & port::BORDER_MASK) != 0 {
+            let position = clock.duration_since(self.frame_start) / self.period;
+            self.border_changes.push((position, data & port::BORDER_MASK));
+        }
+        if (changed & port::EAR) != 0 {
+            self.beeper_changes.push((clock, (data & port::EAR) != 0));
+        }
+    }
+
+    /// Write the beeper samples since the last time, using the level of the beeper at the time of each sample
+    fn write_audio(&mut self, clock: Instant) {
+        let sample_duration = Duration::from_secs(1) / self.source.samples_per_second() as u64;
+        let samples = clock.duration_since(self.last_audio) / sample_duration;
+
+        let mut level = self.beeper_start;
+        let mut changes = self.beeper_changes.iter().peekable();
+        let mut buffer = vec![Sample(0.0, 0.0); samples as usize];
+        for (i, buffered_sample) in buffer.iter_mut().enumerate() {
+            let time = self.last_audio + sample_duration * i as u32;
+            while let Some((_, next_level)) = changes.next_if(|(change, _)| *change <= time) {
+                level = *next_level;
+            }
+            let sample = if level { 0.25 } else { -0.25 };
+            *buffered_sample = Sample(sample, sample);
+        }
+        self.source.write_samples(self.last_audio, &buffer);
+
+        // The changes after the last sample are kept for the next period
+        self.last_audio = self.last_audio + sample_duration * samples as u32;
+        let last_audio = self.last_audio;
+        self.beeper_start = level;
+        self.beeper_changes.retain(|(change, _)| *change > last_audio);
+    }
+
+    fn render(&mut self, clock: Instant) -> Result<(), Error> {
+        let mut memory = vec![0; SCREEN_MEMORY];
+        self.ram
+            .borrow_mut()
+            .as_addressable()
+            .ok_or_else(|| Error::new(format!("{}: ram is not addressable", DEV_NAME)))?
+            .read(clock, 0, &mut memory)?;
+
+        let mut frame = Frame::new(SCREEN_SIZE.0, SCREEN_SIZE.1, self.frame_sender.encoding());
+
+        // The border colour is taken from the start of each line, so changes in the middle of a line are
+        // drawn on the next line
+        let mut border = self.border_start;
+        let mut changes = self.border_changes.iter().peekable();
+        let first_line = timing::FIRST_DISPLAY_LINE - BORDER as u64;
+        for y in 0..SCREEN_SIZE.1 {
+            let line_start = (first_line + y as u64) * timing::CLOCKS_PER_LINE;
+            while let Some((_, colour)) = changes.next_if(|(position, _)| *position <= line_start) {
+                border = *colour;
+            }
+            for x in 0..SCREEN_SIZE.0 {
+                frame.set_pixel(x, y, colour_to_pixel(border, false));
+            }
+        }
+
+        let flash = (self.frame_count / FLASH_FRAMES) % 2 != 0;
+        for y in 0..DISPLAY_SIZE.1 {
+            for column in 0..(DISPLAY_SIZE.0 / 8) {
+                let (pixels, attribute) = display_bytes(&memory, y, column);
+                let inverted = flash && (attribute & 0x80) != 0;
+                let bright = (attribute & 0x40) != 0;
+                let ink = colour_to_pixel(attribute & 0x07, bright);
+                let paper = colour_to_pixel((attribute >> 3) & 0x07, bright);
+
+                for bit in 0..8 {
+                    let set = (pixels & (0x80 >> bit)) != 0;
+                    let pixel = if set != inverted { ink } else { paper };
+                    frame.set_pixel(BORDER + column * 8 + bit, BORDER + y, pixel);
+                }
+            }
+        }
+        self.frame_sender.add(clock, frame);
+        Ok(())
+    }
+}
+
+/// Returns the byte of pixels and the attribute byte for the given line and column of the display, where the
+/// lines are interleaved so that each third of the screen stores the first line of each character first
+fn display_bytes(memory: &[u8], y: u32, column: u32) -> (u8, u8) {
+    let y = y as usize;
+    let column = column as usize;
+    let offset = ((y & 0xC0) << 5) | ((y & 0x07) << 8) | ((y & 0x38) << 2) | column;
+    let attribute = (y / 8) * 32 + column;
+    (memory[DISPLAY_BASE + offset], memory[ATTRIBUTES_BASE + attribute])
+}
+
+/// Convert a colour number, with green, red, and blue in bits 2 to 0, into a pixel
+fn colour_to_pixel(colour: u8, bright: bool) -> Pixel {
+    let level = if bright { 0xFF } else { 0xD7 };
+    let component = |bit: u8| if (colour & bit) != 0 { level } else { 0 };
+    Pixel::Rgb(component(0x02), component(0x04), component(0x01))
+}
+
+impl Addressable for Ula {
+    fn size(&self) -> usize {
+        0x10000
+    }
+
+    fn read(&mut self, _clock: Instant, addr: Address, data: &mut [u8]) -> Result<(), Error> {
+        // Odd ports aren't decoded by the ULA, and read the bus while it's floating
+        data[0] = if (addr & 0x01) == 0 {
+            self.update_keyboard();
+            // Bits 5 and 7 are unused, and bit 6 is the EAR input, which has nothing connected
+            0xA0 | self.read_keyboard(addr as u16)
+        } else {
+            0xFF
+        };
+        log::trace!(target: DEV_NAME, "read from port {:x} of {:x}", addr, data[0]);
+        Ok(())
+    }
+
+    fn write(&mut self, clock: Instant, addr: Address, data: &[u8]) -> Result<(), Error> {
+        log::trace!(target: DEV_NAME, "write to port {:x} with {:x}", addr, data[0]);
+        if (addr & 0x01) == 0 {
+            if ((self.output ^ data[0]) & port::MIC) != 0 {
+                log::trace!(target: DEV_NAME, "mic output set to {}", (data[0] & port::MIC) != 0);
+            }
+            self.write_port(clock, data[0]);
+        }
+        Ok(())
+    }
+}
+
+impl Steppable for Ula {
+    fn step(&mut self, system: &System) -> Result<Duration, Error> {
+        self.update_keyboard();
+
+        let next_frame = self.frame_start + self.frame_duration();
+        if system.clock >= next_frame {
+            self.render(system.clock)?;
+            self.frame_start = next_frame;
+            self.frame_count = self.frame_count.wrapping_add(1);
+            self.border_start = self.output & port::BORDER_MASK;
+            self.border_changes.clear();
+            self.interrupt.set(true);
+        }
+
+        let interrupt_end = self.frame_start + self.period * timing::INTERRUPT_CLOCKS as u32;
+        if self.interrupt.get() && system.clock >= interrupt_end {
+            self.interrupt.set(false);
+        }
+
+        let next_audio = self.last_audio + Duration::from_millis(1);
+        if system.clock >= next_audio {
+            self.write_audio(system.clock);
+        }
+
+        let mut next = (self.frame_start + self.frame_duration()).min(self.last_audio + Duration::from_millis(1));
+        if self.interrupt.get() {
+            next = next.min(interrupt_end);
+        }
+        Ok(next.duration_since(system.clock))
+    }
+}
+
+impl Transmutable for Ula {
+    fn as_addressable(&mut self) -> Option<&mut dyn Addressable> {
+        Some(self)
+    }
+
+    fn as_steppable(&mut self) -> Option<&mut dyn Steppable> {
+        Some(self)
+    }
+
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities::probe(self) | Capabilities::VIDEO | Capabilities::AUDIO
+    }
+}
@@ -0,0 +1,72 @@
+use std::fs;
+use std::rc::Rc;
+use std::cell::RefCell;
+use femtos::Frequency;
+
+use moa_core::{System, Error, Address, Bus, MemoryBlock, Device, MachineInfo};
+use moa_host::Host;
+
+use moa_z80::{MoaZ80, Z80, Z80Type};
+
+use crate::peripherals::rom::Rom;
+use crate::peripherals::ula::{self, Ula, SCREEN_SIZE};
+
+
+const ROM_SIZE: usize = 0x4000;
+const RAM_BASE: Address = 0x4000;
+const RAM_SIZE: usize = 0xC000;
+
+
+#[derive(Debug)]
+pub struct ZxSpectrumOptions {
+    /// The 16K ROM with the BASIC interpreter
+    pub rom: String,
+    pub frequency: Frequency,
+    /// Delay the cpu when it accesses the memory or ports shared with the ULA while the display is being drawn
+    pub contended_memory: bool,
+}
+
+impl Default for ZxSpectrumOptions {
+    fn default() -> Self {
+        Self {
+            rom: "binaries/zxspectrum/48.rom".to_string(),
+            frequency: Frequency::from_hz(3_500_000),
+            contended_memory: true,
+        }
+    }
+}
+
+
+pub fn build_zx_spectrum<H: Host>(host: &mut H, options: ZxSpectrumOptions) -> Result<System, Error> {
+    let mut system = System::default();
+    system.machine_info = MachineInfo::new("zx-spectrum")
+        .with_video(SCREEN_SIZE.0, SCREEN_SIZE.1, Frequency::from_hz(50))
+        .with_aspect_ratio(4.0 / 3.0)
+        .with_rom(&options.rom);
+
+    let contents = fs::read(&options.rom).map_err(|_| Error::new(format!("Error reading contents of {}", options.rom)))?;
+    if contents.len() != ROM_SIZE {
+        return Err(Error::new(format!("{} must be 16K, but it's {} bytes", options.rom, contents.len())));
+    }
+    system.add_addressable_device(0x0000, Device::new(Rom::new(contents)))?;
+
+    let ram = Device::new(MemoryBlock::new(vec![0; RAM_SIZE]));
+    system.add_addressable_device(RAM_BASE, ram.clone())?;
+
+    let cpu = Z80::from_type(Z80Type::Z80, options.frequency);
+    let ula = Ula::new(host, ram, cpu.signals.interrupt.clone(), options.frequency)?;
+    let ula = Device::new(ula);
+
+    // The ULA decodes every even port, so it's given the whole port space
+    let io_bus = Rc::new(RefCell::new(Bus::default()));
+    io_bus.borrow_mut().insert(0x0000, ula.clone());
+    system.add_device("ula", ula)?;
+
+    let mut cpu = MoaZ80::new(cpu, system.bus.clone()).with_io_bus(io_bus);
+    if options.contended_memory {
+        cpu = cpu.with_contention(ula::contention_delay);
+    }
+    system.add_interruptable_device("cpu", Device::new(cpu))?;
+
+    Ok(system)
+}
@@ -14,7 +14,7 @@ use femtos::{Instant, Frequency};
 use emulator_hal::{Step, BusAccess};
 use emulator_hal_memory::MemoryBlock;
 
-use moa_z80::{Z80, Z80Type, Z80Port, InterruptMode, Flags, Status};
+use moa_z80::{Z80, Z80Type, Z80Port, Z80BusCycle, InterruptMode, Flags, Status};
 
 #[derive(Clone, Debug)]
 enum Error {
@@ -45,6 +45,9 @@ struct Args {
     /// Check instruction timings
     #[clap(short = 't', long)]
     check_timings: bool,
+    /// Check the order, addresses, and data of each instruction's bus transactions
+    #[clap(short = 'c', long)]
+    check_cycles: bool,
     /// Don't check I/O instructions
     #[clap(short = 'i', long)]
     no_check_io: bool,
@@ -91,6 +94,10 @@ struct TestState {
 #[derive(Debug, Deserialize)]
 struct TestCycle(u16, Option<u8>, String);
 
+/// A bus transaction, as (is_write, is_io, address, data), which is asserted over one or more of the
+/// test's cycles
+type BusTransaction = (bool, bool, u16, Option<u8>);
+
 #[derive(Debug, Deserialize)]
 struct TestPort {
     addr: u16,
@@ -147,6 +154,14 @@ impl TestCase {
         for port in self.ports.iter() {
             println!("{:04x} {:02x} {}", port.addr, port.value, port.atype);
         }
+
+        println!("cycles: ");
+        for TestCycle(addr, data, pins) in self.cycles.iter() {
+            match data {
+                Some(data) => println!("{:04x} {:02x} {}", addr, data, pins),
+                None => println!("{:04x} -- {}", addr, pins),
+            }
+        }
     }
 }
 
@@ -328,6 +343,72 @@ fn step_cpu_and_assert(
         }
     }
 
+    if args.check_cycles {
+        assert_bus_cycles(&cpu.previous_cycle.bus_cycles, &case.cycles)?;
+    }
+
+    Ok(())
+}
+
+/// Get the bus transactions from the test's cycles, which list the pins for each clock cycle as a string of
+/// the form "rwmi", with a '-' for each inactive pin.  The refresh cycles of an opcode fetch assert /MREQ
+/// without /RD, so they are skipped
+fn expected_bus_transactions(cycles: &[TestCycle]) -> Vec<BusTransaction> {
+    let mut transactions: Vec<BusTransaction> = vec![];
+    let mut previous_active = false;
+    for TestCycle(addr, data, pins) in cycles.iter() {
+        let is_read = pins.contains('r');
+        let is_write = pins.contains('w');
+        let is_io = pins.contains('i');
+        let active = (is_read || is_write) && (is_io || pins.contains('m'));
+
+        if active {
+            match transactions.last_mut() {
+                // The strobes are held for more than one cycle, so this continues the previous transaction
+                Some(last) if previous_active && last.0 == is_write && last.1 == is_io && last.2 == *addr => {
+                    if data.is_some() {
+                        last.3 = *data;
+                    }
+                },
+                _ => transactions.push((is_write, is_io, *addr, *data)),
+            }
+        }
+        previous_active = active;
+    }
+    transactions
+}
+
+fn assert_bus_cycles(actual: &[Z80BusCycle], cycles: &[TestCycle]) -> Result<(), Error> {
+    let expected = expected_bus_transactions(cycles);
+
+    for (i, (cycle, transaction)) in actual.iter().zip(expected.iter()).enumerate() {
+        let (is_write, is_io, addr, data) = *transaction;
+        let matches = cycle.kind.is_write() == is_write
+            && cycle.kind.is_io() == is_io
+            && cycle.addr == addr
+            && data.map(|data| data == cycle.data).unwrap_or(true);
+
+        if !matches {
+            return Err(Error::Assertion(format!(
+                "bus cycle {}: expected {} {} at {:#06x} with {:02x?}, but was {:?}",
+                i,
+                if is_write { "write" } else { "read" },
+                if is_io { "io" } else { "memory" },
+                addr,
+                data,
+                cycle
+            )));
+        }
+    }
+
+    if actual.len() != expected.len() {
+        return Err(Error::Assertion(format!(
+            "expected instruction to have {} bus cycles, but had {}",
+            expected.len(),
+            actual.len()
+        )));
+    }
+
     Ok(())
 }
 
@@ -349,6 +430,10 @@ fn run_test(case: &TestCase, args: &Args) -> Result<(), Error> {
                     initial_cpu.dump_state(&mut writer, Instant::START, &mut bus).unwrap();
                     cpu.dump_state(&mut writer, Instant::START, &mut bus).unwrap();
                     println!("{}", writer);
+                    println!("bus cycles: ");
+                    for cycle in cpu.previous_cycle.bus_cycles.iter() {
+                        println!("{:04x} {:02x} {:?}", cycle.addr, cycle.data, cycle.kind);
+                    }
                 }
                 println!("FAILED: {:?}", err);
             }
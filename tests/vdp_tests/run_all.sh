@@ -0,0 +1,6 @@
+#!/bin/bash
+LOCATION=$(dirname ${BASH_SOURCE[0]})
+{
+    cd $LOCATION
+    cargo run --release -- --rom "../240pSuite/240pSuite.bin" --script "scripts/240p.txt" --references "references.txt" "$@"
+}
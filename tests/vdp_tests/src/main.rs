@@ -0,0 +1,342 @@
+const DEFAULT_ROM: &str = "tests/240pSuite/240pSuite.bin";
+const DEFAULT_SCRIPT: &str = "tests/vdp_tests/scripts/240p.txt";
+const DEFAULT_REFERENCES: &str = "tests/vdp_tests/references.txt";
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use clap::Parser;
+use femtos::Duration;
+
+use moa_core::{System, Error, MachineInfo};
+use moa_host::{
+    Host, HostError, Audio, DummyAudio, Frame, FrameReceiver, PixelEncoding, ControllerDevice, ControllerEvent, ControllerInput,
+    EventSender,
+};
+use moa_systems_genesis::{build_genesis, SegaGenesisOptions};
+
+/// The number of frames to hold a button down for when using the `tap` command
+const TAP_FRAMES: usize = 4;
+
+#[derive(Parser)]
+struct Args {
+    /// Only run the captures that start with the given name
+    filter: Option<String>,
+    /// ROM file of the 240p Test Suite
+    #[clap(long, default_value = DEFAULT_ROM)]
+    rom: String,
+    /// Script of controller inputs and captures to run
+    #[clap(long, default_value = DEFAULT_SCRIPT)]
+    script: String,
+    /// File containing the CRCs of the reference frames
+    #[clap(long, default_value = DEFAULT_REFERENCES)]
+    references: String,
+    /// Write the CRCs of the captured frames to the references file instead of comparing them
+    #[clap(short, long)]
+    update: bool,
+    /// Write the captured frames of failed comparisons next to the references file for inspection
+    #[clap(short, long)]
+    debug: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+    if let Err(err) = run_script(&args) {
+        println!("error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+
+#[derive(Default)]
+struct TestHost {
+    video: Option<FrameReceiver>,
+    controllers: Option<EventSender<ControllerEvent>>,
+}
+
+impl Host for TestHost {
+    type Error = Error;
+
+    fn add_video_source(&mut self, receiver: FrameReceiver) -> Result<(), HostError<Self::Error>> {
+        receiver.request_encoding(PixelEncoding::RGBA);
+        self.video = Some(receiver);
+        Ok(())
+    }
+
+    fn add_audio_source(&mut self) -> Result<Box<dyn Audio>, HostError<Self::Error>> {
+        Ok(Box::new(DummyAudio()))
+    }
+
+    fn register_controllers(&mut self, sender: EventSender<ControllerEvent>) -> Result<(), HostError<Self::Error>> {
+        self.controllers = Some(sender);
+        Ok(())
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Command {
+    Wait(usize),
+    Press(String),
+    Release(String),
+    Tap(String),
+    Capture(String),
+}
+
+fn parse_script(contents: &str) -> Result<Vec<Command>, Error> {
+    let mut commands = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        if words.len() != 2 {
+            return Err(Error::new(format!("line {}: expected a command and one argument", i + 1)));
+        }
+
+        let command = match words[0] {
+            "wait" => Command::Wait(
+                words[1]
+                    .parse()
+                    .map_err(|_| Error::new(format!("line {}: invalid number of frames", i + 1)))?,
+            ),
+            "press" => Command::Press(words[1].to_string()),
+            "release" => Command::Release(words[1].to_string()),
+            "tap" => Command::Tap(words[1].to_string()),
+            "capture" => Command::Capture(words[1].to_string()),
+            _ => return Err(Error::new(format!("line {}: unknown command {}", i + 1, words[0]))),
+        };
+        commands.push(command);
+    }
+    Ok(commands)
+}
+
+fn parse_button(name: &str, state: bool) -> Result<ControllerInput, Error> {
+    match name {
+        "up" => Ok(ControllerInput::DpadUp(state)),
+        "down" => Ok(ControllerInput::DpadDown(state)),
+        "left" => Ok(ControllerInput::DpadLeft(state)),
+        "right" => Ok(ControllerInput::DpadRight(state)),
+        "a" => Ok(ControllerInput::ButtonA(state)),
+        "b" => Ok(ControllerInput::ButtonB(state)),
+        "c" => Ok(ControllerInput::ButtonC(state)),
+        "x" => Ok(ControllerInput::ButtonX(state)),
+        "y" => Ok(ControllerInput::ButtonY(state)),
+        "z" => Ok(ControllerInput::ButtonZ(state)),
+        "start" => Ok(ControllerInput::Start(state)),
+        "mode" => Ok(ControllerInput::Mode(state)),
+        _ => Err(Error::new(format!("unknown button {}", name))),
+    }
+}
+
+
+/// Parse the references file, which has the name of each capture followed by the CRC of its frame in hex
+fn parse_references(contents: &str) -> Result<Vec<(String, u32)>, Error> {
+    let mut references = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => {},
+            [name, crc] => {
+                let crc = u32::from_str_radix(crc, 16).map_err(|_| Error::new(format!("line {}: invalid crc {}", i + 1, crc)))?;
+                references.push((name.to_string(), crc));
+            },
+            _ => return Err(Error::new(format!("line {}: expected a capture name and a crc", i + 1))),
+        }
+    }
+    Ok(references)
+}
+
+fn write_references(path: &str, references: &[(String, u32)]) -> Result<(), Error> {
+    let mut contents =
+        String::from("# The CRC32 of the frame at each capture in the VDP test script, which is generated with --update\n");
+    for (name, crc) in references.iter() {
+        contents.push_str(&format!("{} {:08x}\n", name, crc));
+    }
+    fs::write(path, contents).map_err(|_| Error::new(format!("Error writing {}", path)))
+}
+
+/// Returns the time between the frames of the machine, based on its video standard
+fn frame_duration(machine_info: &MachineInfo) -> Result<Duration, Error> {
+    let standard = machine_info
+        .video_standard
+        .ok_or_else(|| Error::new("the genesis machine doesn't have a video standard"))?;
+    Ok(standard.line_duration() * standard.lines_per_frame())
+}
+
+
+struct TestRunner<'a> {
+    args: &'a Args,
+    system: System,
+    host: TestHost,
+    frame_duration: Duration,
+    references: Vec<(String, u32)>,
+    last_frame: Option<Frame>,
+    passed: usize,
+    failed: usize,
+}
+
+impl<'a> TestRunner<'a> {
+    fn new(args: &'a Args, references: Vec<(String, u32)>) -> Result<Self, Error> {
+        let mut host = TestHost::default();
+        let options = SegaGenesisOptions {
+            rom: args.rom.clone(),
+            ..Default::default()
+        };
+        let system = build_genesis(&mut host, options)?;
+
+        if host.video.is_none() || host.controllers.is_none() {
+            return Err(Error::new("the genesis machine didn't register a video source and controllers"));
+        }
+
+        Ok(Self {
+            args,
+            frame_duration: frame_duration(&system.machine_info)?,
+            system,
+            host,
+            references,
+            last_frame: None,
+            passed: 0,
+            failed: 0,
+        })
+    }
+
+    fn run_frames(&mut self, frames: usize) -> Result<(), Error> {
+        for _ in 0..frames {
+            self.system.run_for_duration(self.frame_duration)?;
+            if let Some((_, frame)) = self.host.video.as_ref().unwrap().latest() {
+                self.last_frame = Some(frame);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_button(&mut self, name: &str, state: bool) -> Result<(), Error> {
+        let input = parse_button(name, state)?;
+        self.host
+            .controllers
+            .as_ref()
+            .unwrap()
+            .send(ControllerEvent::new(ControllerDevice::A, input));
+        Ok(())
+    }
+
+    fn run_command(&mut self, command: &Command) -> Result<(), Error> {
+        match command {
+            Command::Wait(frames) => self.run_frames(*frames)?,
+            Command::Press(button) => self.set_button(button, true)?,
+            Command::Release(button) => self.set_button(button, false)?,
+            Command::Tap(button) => {
+                self.set_button(button, true)?;
+                self.run_frames(TAP_FRAMES)?;
+                self.set_button(button, false)?;
+                self.run_frames(TAP_FRAMES)?;
+            },
+            Command::Capture(name) => self.capture(name)?,
+        }
+        Ok(())
+    }
+
+    fn capture(&mut self, name: &str) -> Result<(), Error> {
+        if let Some(filter) = &self.args.filter {
+            if !name.starts_with(filter) {
+                return Ok(());
+            }
+        }
+
+        let frame = self
+            .last_frame
+            .as_ref()
+            .ok_or_else(|| Error::new(format!("{}: no frame has been drawn yet", name)))?;
+        let crc = frame.crc32();
+
+        if self.args.update {
+            match self.references.iter_mut().find(|(reference, _)| reference == name) {
+                Some((_, expected)) => *expected = crc,
+                None => self.references.push((name.to_string(), crc)),
+            }
+            println!("{}: updated", name);
+            return Ok(());
+        }
+
+        // A capture without a reference can't pass, so the run is stopped rather than carrying on without it
+        let expected = self
+            .references
+            .iter()
+            .find(|(reference, _)| reference == name)
+            .map(|(_, crc)| *crc)
+            .ok_or_else(|| {
+                Error::new(format!(
+                    "{}: missing reference in {}, which can be created with --update",
+                    name, self.args.references
+                ))
+            })?;
+
+        if crc == expected {
+            self.passed += 1;
+            println!("{}: passed", name);
+            return Ok(());
+        }
+
+        self.failed += 1;
+        println!("{}: FAILED: frame crc {:08x} doesn't match the reference {:08x}", name, crc, expected);
+        if self.args.debug {
+            write_ppm(&failed_path(&self.args.references, name), frame)?;
+        }
+        Ok(())
+    }
+}
+
+fn run_script(args: &Args) -> Result<(), Error> {
+    let contents =
+        fs::read_to_string(&args.script).map_err(|_| Error::new(format!("Error reading contents of {}", args.script)))?;
+    let commands = parse_script(&contents)?;
+
+    let references = match fs::read_to_string(&args.references) {
+        Ok(contents) => parse_references(&contents)?,
+        Err(_) if args.update => vec![],
+        Err(_) => {
+            return Err(Error::new(format!(
+                "missing references file {}, which can be created with --update",
+                args.references
+            )));
+        },
+    };
+
+    let start = SystemTime::now();
+    let mut runner = TestRunner::new(args, references)?;
+    for command in commands.iter() {
+        runner.run_command(command)?;
+    }
+    if args.update {
+        write_references(&args.references, &runner.references)?;
+    }
+    let elapsed_secs = start.elapsed().unwrap().as_secs();
+
+    println!();
+    println!("passed: {}, failed: {}", runner.passed, runner.failed);
+    println!("completed in {}m {}s", elapsed_secs / 60, elapsed_secs % 60);
+
+    if runner.failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Returns the path of the image of a failed capture, which is written in the same directory as the references file
+fn failed_path(references: &str, name: &str) -> PathBuf {
+    Path::new(references).with_file_name(format!("{}.failed.ppm", name))
+}
+
+/// Write a frame as a binary PPM image, which can be viewed with most image viewers
+fn write_ppm(path: &Path, frame: &Frame) -> Result<(), Error> {
+    let mut data = Vec::with_capacity(frame.bitmap.len() * 3 + 20);
+    data.extend_from_slice(format!("P6\n{} {}\n255\n", frame.width, frame.height).as_bytes());
+    for pixel in frame.bitmap.iter() {
+        data.extend_from_slice(&[(pixel >> 24) as u8, (pixel >> 16) as u8, (pixel >> 8) as u8]);
+    }
+    fs::write(path, data).map_err(|_| Error::new(format!("Error writing {}", path.display())))
+}